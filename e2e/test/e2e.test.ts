@@ -4,6 +4,7 @@ import fs from 'fs';
 import { fileURLToPath } from 'url';
 import WebSocket from 'ws';
 import os from 'os';
+import crypto from 'crypto';
 
 // Monkey-patch os.networkInterfaces to handle Android permission issues
 const originalNetworkInterfaces = os.networkInterfaces;
@@ -21,10 +22,10 @@ const ROOT_DIR = path.resolve(__dirname, '../..');
 const FRONTEND_DIR = path.join(ROOT_DIR, 'frontend');
 const SERVER_URL = 'http://127.0.0.1:8080';
 const WS_URL = 'ws://127.0.0.1:8080/ws';
-const CONFIG_FILE = path.join(os.homedir(), '.runotepad', 'config.json');
-
-// Auth token (read from config after server starts)
-let authToken: string = '';
+// Auth token handed to the server on start; the config only stores its hash
+const authToken: string = crypto.randomBytes(16).toString('hex');
+// Fresh config per run; workspaces default to a directory next to it
+const CONFIG_FILE = path.join(fs.mkdtempSync(path.join(os.tmpdir(), 'runotepad-config-')), 'config.json');
 
 // Test result tracking
 interface TestResult {
@@ -72,19 +73,60 @@ function execCommand(command: string, cwd: string): void {
   execSync(command, { cwd, stdio: 'inherit' });
 }
 
-function readAuthToken(): string {
-  try {
-    const configData = fs.readFileSync(CONFIG_FILE, 'utf-8');
-    const config = JSON.parse(configData);
-    return config.token || '';
-  } catch (err) {
-    logError(`Failed to read auth token: ${err}`);
-    return '';
+function getAuthenticatedWsUrl(): string {
+  return `${WS_URL}?token=${encodeURIComponent(authToken)}`;
+}
+
+async function api(method: string, apiPath: string, body?: unknown): Promise<Response> {
+  return fetch(`${SERVER_URL}${apiPath}`, {
+    method,
+    headers: {
+      'Content-Type': 'application/json',
+      'Authorization': `Bearer ${authToken}`,
+    },
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+}
+
+// Check a failed response's status and its `{"error": {code, message, details}}` body
+async function expectError(response: Response, status: number, code: string): Promise<{ details: any }> {
+  const body = await response.json();
+  if (response.status !== status || body.error?.code !== code || typeof body.error?.message !== 'string') {
+    throw new Error(`Expected ${status} ${code}, got ${response.status} ${JSON.stringify(body)}`);
   }
+  return body.error;
 }
 
-function getAuthenticatedWsUrl(): string {
-  return `${WS_URL}?token=${encodeURIComponent(authToken)}`;
+// Poll a background job until it leaves the queued/running states
+async function waitForJob(id: string): Promise<any> {
+  for (let attempt = 0; attempt < 600; attempt++) {
+    const response = await api('GET', `/api/jobs/${id}`);
+    if (!response.ok) {
+      throw new Error(`Job ${id}: HTTP ${response.status}`);
+    }
+    const job = await response.json();
+    if (job.state !== 'queued' && job.state !== 'running') {
+      return job;
+    }
+    await new Promise((resolve) => setTimeout(resolve, 100));
+  }
+  throw new Error(`Job ${id} did not finish`);
+}
+
+// Create a bare git repository with a couple of runbooks to clone from
+function createFixtureRepo(): string {
+  const dir = fs.mkdtempSync(path.join(os.tmpdir(), 'runotepad-e2e-'));
+  const src = path.join(dir, 'src');
+  const bare = path.join(dir, 'fixture.git');
+  fs.mkdirSync(path.join(src, 'docs'), { recursive: true });
+  fs.writeFileSync(path.join(src, 'README.md'), '# Fixture\n');
+  fs.writeFileSync(path.join(src, 'docs', 'alerts.md'), '# Alerts\n\nHighCPU fires above 90%\n');
+  const git = (cmd: string, cwd: string) => execSync(`git ${cmd}`, { cwd, stdio: 'ignore' });
+  git('init -b main', src);
+  git('-c user.email=e2e@example.com -c user.name=e2e add -A', src);
+  git('-c user.email=e2e@example.com -c user.name=e2e commit -m init', src);
+  git(`clone --bare ${src} ${bare}`, dir);
+  return bare;
 }
 
 // Step 1: Build Frontend (typecheck + build)
@@ -129,6 +171,12 @@ async function startServer(): Promise<void> {
     log('Starting server...');
     serverProcess = spawn('cargo', ['run'], {
       cwd: ROOT_DIR,
+      env: {
+        ...process.env,
+        RUNOTEPAD_TOKEN: authToken,
+        RUNOTEPAD_CONFIG_FILE: CONFIG_FILE,
+        RUNOTEPAD_IMPORT_ROOT: os.tmpdir(),
+      },
       stdio: ['ignore', 'pipe', 'pipe'],
     });
 
@@ -151,14 +199,6 @@ async function startServer(): Promise<void> {
     // Wait for server to respond to HTTP requests
     await waitForServer(SERVER_URL, 30000);
     log('Server is responding to requests');
-
-    // Read auth token from config
-    authToken = readAuthToken();
-    if (authToken) {
-      log(`Auth token loaded: ${authToken.substring(0, 8)}...`);
-    } else {
-      throw new Error('Failed to load auth token from config');
-    }
   });
 }
 
@@ -257,6 +297,113 @@ async function runHttpTests(): Promise<void> {
       throw new Error('Expected array of workspaces');
     }
   });
+
+  // Test: Every /api route goes through the auth middleware, including ones
+  // that never checked the token themselves
+  await runTest('Auth middleware covers all API routes', async () => {
+    const logBody = JSON.stringify({ level: 'info', message: 'e2e auth check' });
+    const headers = { 'Content-Type': 'application/json' };
+
+    const unauthenticated = await fetch(`${SERVER_URL}/api/console`, { method: 'POST', headers, body: logBody });
+    if (unauthenticated.status !== 401) {
+      throw new Error(`Expected 401 for /api/console without token, got ${unauthenticated.status}`);
+    }
+    const authenticated = await api('POST', '/api/console', { level: 'info', message: 'e2e auth check' });
+    if (authenticated.status !== 200) {
+      throw new Error(`Expected 200 for /api/console with token, got ${authenticated.status}`);
+    }
+
+    const unknown = await fetch(`${SERVER_URL}/api/not-a-route`);
+    if (unknown.status !== 401) {
+      throw new Error(`Expected 401 for unknown API route, got ${unknown.status}`);
+    }
+  });
+
+  // Test: Named tokens can be minted, used and revoked
+  await runTest('Named token lifecycle', async () => {
+    const created = await api('POST', '/api/auth/tokens', { name: 'e2e' });
+    if (created.status !== 201) {
+      throw new Error(`Expected 201, got ${created.status}`);
+    }
+    const { token } = await created.json();
+
+    const withNew = await fetch(`${SERVER_URL}/api/workspaces?token=${token}`);
+    if (!withNew.ok) {
+      throw new Error(`New token rejected: HTTP ${withNew.status}`);
+    }
+
+    const listed = await (await api('GET', '/api/auth/tokens')).json();
+    const entry = listed.find((t: { name: string }) => t.name === 'e2e');
+    if (!entry || 'token' in entry || !entry.last_used) {
+      throw new Error(`Unexpected token listing: ${JSON.stringify(listed)}`);
+    }
+
+    const duplicate = await api('POST', '/api/auth/tokens', { name: 'e2e' });
+    if (duplicate.status !== 409) {
+      throw new Error(`Expected 409 for duplicate name, got ${duplicate.status}`);
+    }
+
+    const revoked = await api('DELETE', '/api/auth/tokens/e2e');
+    if (!revoked.ok) {
+      throw new Error(`Revoke failed: HTTP ${revoked.status}`);
+    }
+    const afterRevoke = await fetch(`${SERVER_URL}/api/workspaces?token=${token}`);
+    if (afterRevoke.status !== 401) {
+      throw new Error(`Expected 401 after revoke, got ${afterRevoke.status}`);
+    }
+  });
+
+  // Test: Read-scoped tokens can read but not change anything
+  await runTest('Read-only token scope', async () => {
+    const created = await (await api('POST', '/api/auth/tokens', { name: 'e2e-read', scope: 'read' })).json();
+    if (created.scope !== 'read') {
+      throw new Error(`Expected read scope, got ${created.scope}`);
+    }
+    const headers = { 'Authorization': `Bearer ${created.token}`, 'Content-Type': 'application/json' };
+
+    const read = await fetch(`${SERVER_URL}/api/workspaces`, { headers });
+    if (read.status !== 200) {
+      throw new Error(`Expected 200 for GET with read token, got ${read.status}`);
+    }
+    const write = await fetch(`${SERVER_URL}/api/auth/tokens`, {
+      method: 'POST',
+      headers,
+      body: JSON.stringify({ name: 'e2e-escalate' }),
+    });
+    if (write.status !== 403) {
+      throw new Error(`Expected 403 for POST with read token, got ${write.status}`);
+    }
+    const terminal = await fetch(`${SERVER_URL}/ws?token=${created.token}`);
+    if (terminal.status !== 403) {
+      throw new Error(`Expected 403 for /ws with read token, got ${terminal.status}`);
+    }
+
+    await api('DELETE', '/api/auth/tokens/e2e-read');
+  });
+
+  // Test: Rotating a token invalidates the old secret
+  await runTest('Token rotation', async () => {
+    const created = await (await api('POST', '/api/auth/tokens', { name: 'e2e-rotate' })).json();
+    const rotated = await fetch(`${SERVER_URL}/api/auth/rotate`, {
+      method: 'POST',
+      headers: { 'Authorization': `Bearer ${created.token}` },
+    });
+    if (!rotated.ok) {
+      throw new Error(`HTTP ${rotated.status}: ${rotated.statusText}`);
+    }
+    const { name, token } = await rotated.json();
+    if (name !== 'e2e-rotate' || token === created.token) {
+      throw new Error('Rotation should return a new secret for the calling token');
+    }
+
+    const oldStatus = (await fetch(`${SERVER_URL}/api/workspaces?token=${created.token}`)).status;
+    const newStatus = (await fetch(`${SERVER_URL}/api/workspaces?token=${token}`)).status;
+    if (oldStatus !== 401 || newStatus !== 200) {
+      throw new Error(`Expected old=401 new=200, got old=${oldStatus} new=${newStatus}`);
+    }
+
+    await api('DELETE', '/api/auth/tokens/e2e-rotate');
+  });
 }
 
 async function runWebSocketTests(): Promise<void> {
@@ -425,6 +572,634 @@ async function runWebSocketTests(): Promise<void> {
       });
     });
   });
+
+  // Test: sessions carry a label and the runbook they were opened for
+  await runTest('Terminal sessions are labelled', async () => {
+    const ws = new WebSocket(getAuthenticatedWsUrl());
+    const messages: any[] = [];
+    // The next message of `type`, failing on an error message
+    const next = async (type: string): Promise<any> => {
+      for (let attempt = 0; attempt < 200; attempt++) {
+        const index = messages.findIndex((msg) => msg.type === type || msg.type === 'error');
+        if (index !== -1) {
+          const [msg] = messages.splice(index, 1);
+          if (msg.type === 'error') throw new Error(`PTY error: ${msg.message}`);
+          return msg;
+        }
+        await new Promise((resolve) => setTimeout(resolve, 50));
+      }
+      throw new Error(`No ${type} message`);
+    };
+    ws.on('message', (data) => messages.push(JSON.parse(data.toString())));
+    await new Promise((resolve) => ws.on('open', resolve));
+
+    try {
+      ws.send(JSON.stringify({ type: 'create', label: ' prod-db\u0007 shell ', runbook_path: 'ops/db.md' }));
+      const created = await next('created');
+      if (created.label !== 'prod-db shell' || created.runbook_path !== 'ops/db.md') {
+        throw new Error(`Unexpected created message: ${JSON.stringify(created)}`);
+      }
+      let listed = (await (await api('GET', '/api/sessions')).json()).sessions;
+      let mine = listed.find((s: any) => s.session_id === created.session_id);
+      if (mine?.label !== 'prod-db shell' || mine.runbook_path !== 'ops/db.md' || !mine.opened_at) {
+        throw new Error(`Unexpected session list: ${JSON.stringify(listed)}`);
+      }
+
+      ws.send(JSON.stringify({ type: 'relabel', session_id: created.session_id, label: 'x'.repeat(100) }));
+      const relabeled = await next('relabeled');
+      if (relabeled.label !== 'x'.repeat(64)) {
+        throw new Error(`Label not capped: ${JSON.stringify(relabeled)}`);
+      }
+      listed = (await (await api('GET', '/api/sessions')).json()).sessions;
+      mine = listed.find((s: any) => s.session_id === created.session_id);
+      if (mine?.label !== 'x'.repeat(64)) {
+        throw new Error(`Relabel not listed: ${JSON.stringify(listed)}`);
+      }
+      ws.send(JSON.stringify({ type: 'close', session_id: created.session_id }));
+      await next('closed');
+    } finally {
+      ws.close();
+    }
+  });
+}
+
+async function runFileTests(): Promise<void> {
+  const workspace = `e2e-${Date.now()}`;
+  const fileUrl = (p: string) =>
+    `/api/workspaces/${workspace}/branches/main/file?path=${encodeURIComponent(p)}`;
+
+  await runTest('Workspace and worktree setup', async () => {
+    const repoUrl = createFixtureRepo();
+    let response = await api('POST', '/api/workspaces', {
+      name: workspace,
+      repo_url: repoUrl,
+      base_branch: 'main',
+    });
+    if (response.status !== 202) {
+      throw new Error(`Workspace create: HTTP ${response.status}`);
+    }
+    const job = await waitForJob((await response.json()).id);
+    if (job.state !== 'succeeded' || job.kind !== 'clone_workspace' || job.result?.name !== workspace) {
+      throw new Error(`Clone job: ${JSON.stringify(job)}`);
+    }
+    response = await api('POST', `/api/workspaces/${workspace}/branches`, { branch_name: 'main' });
+    if (response.status !== 201) {
+      throw new Error(`Worktree create: HTTP ${response.status}`);
+    }
+  });
+
+  await runTest('Terminals opened for a workspace get its environment', async () => {
+    const update = await api('PUT', `/api/workspaces/${workspace}/env`, {
+      env: { E2E_STAGE: 'staging' },
+      secret_env: { E2E_SECRET: 's3cret' },
+    });
+    if (update.status !== 200) {
+      throw new Error(`Env update: HTTP ${update.status}`);
+    }
+    const listed = await (await api('GET', `/api/workspaces/${workspace}/env`)).json();
+    if (JSON.stringify(listed.secret_env) !== '["E2E_SECRET"]' || JSON.stringify(listed).includes('s3cret')) {
+      throw new Error(`Unexpected env listing: ${JSON.stringify(listed)}`);
+    }
+
+    const ws = new WebSocket(getAuthenticatedWsUrl());
+    let output = '';
+    let sessionId: string | undefined;
+    ws.on('message', (data) => {
+      const msg = JSON.parse(data.toString());
+      if (msg.type === 'created') sessionId = msg.session_id;
+      if (msg.type === 'output') output += msg.data;
+      if (msg.type === 'error') output += `ERROR ${msg.message}`;
+    });
+    await new Promise((resolve) => ws.on('open', resolve));
+    try {
+      ws.send(JSON.stringify({ type: 'create', workspace, branch: 'main' }));
+      for (let attempt = 0; attempt < 100 && !sessionId; attempt++) {
+        await new Promise((resolve) => setTimeout(resolve, 50));
+      }
+      if (!sessionId) throw new Error(`No session: ${output}`);
+      ws.send(JSON.stringify({ type: 'input', session_id: sessionId, data: 'echo "[$E2E_STAGE/$E2E_SECRET] $PWD"\n' }));
+      for (let attempt = 0; attempt < 100 && !output.includes('[staging/s3cret]'); attempt++) {
+        await new Promise((resolve) => setTimeout(resolve, 50));
+      }
+      if (!output.includes(`[staging/s3cret] `) || !output.includes(`worktrees/main`)) {
+        throw new Error(`Environment not set: ${output}`);
+      }
+      ws.send(JSON.stringify({ type: 'close', session_id: sessionId }));
+    } finally {
+      ws.close();
+    }
+  });
+
+  await runTest('Workspace details', async () => {
+    const response = await api('GET', `/api/workspaces/${workspace}`);
+    if (!response.ok) {
+      throw new Error(`HTTP ${response.status}`);
+    }
+    const detail = await response.json();
+    if (detail.worktree_count !== 1 || !detail.healthy || detail.status !== 'ok' || !detail.head_commit?.sha) {
+      throw new Error(`Unexpected details: ${JSON.stringify(detail)}`);
+    }
+    if (!(detail.disk_usage.bytes > 0)) {
+      throw new Error('Disk usage should be non-zero');
+    }
+
+    const missing = await api('GET', '/api/workspaces/does-not-exist');
+    if (missing.status !== 404) {
+      throw new Error(`Expected 404 for unknown workspace, got ${missing.status}`);
+    }
+  });
+
+  await runTest('Workspace status check', async () => {
+    const response = await api('POST', `/api/workspaces/${workspace}/verify`);
+    if (!response.ok) {
+      throw new Error(`HTTP ${response.status}`);
+    }
+    const result = await response.json();
+    if (result.status !== 'ok') {
+      throw new Error(`Expected status ok, got ${JSON.stringify(result)}`);
+    }
+  });
+
+  await runTest('Event stream reports job updates', async () => {
+    const controller = new AbortController();
+    const stream = await fetch(`${SERVER_URL}/api/events?workspace=${workspace}`, {
+      headers: { 'Authorization': `Bearer ${authToken}` },
+      signal: controller.signal,
+    });
+    if (!stream.ok || stream.headers.get('content-type') !== 'text/event-stream') {
+      throw new Error(`Event stream: HTTP ${stream.status} ${stream.headers.get('content-type')}`);
+    }
+    try {
+      const started = await api('POST', `/api/workspaces/${workspace}/fetch`);
+      if (started.status !== 202) {
+        throw new Error(`Fetch: HTTP ${started.status}`);
+      }
+      const job = await started.json();
+
+      const reader = stream.body!.getReader();
+      const decoder = new TextDecoder();
+      const seen: any[] = [];
+      let buffer = '';
+      const deadline = Date.now() + 10000;
+      while (!seen.some((e) => e.type === 'job' && e.job.id === job.id && e.job.state === 'succeeded')) {
+        if (Date.now() > deadline) {
+          throw new Error(`No finished job event, got ${JSON.stringify(seen)}`);
+        }
+        const { value, done } = await reader.read();
+        if (done) {
+          throw new Error('Event stream ended');
+        }
+        buffer += decoder.decode(value, { stream: true });
+        let end;
+        while ((end = buffer.indexOf('\n\n')) >= 0) {
+          const frame = buffer.slice(0, end);
+          buffer = buffer.slice(end + 2);
+          if (frame.startsWith('data: ')) {
+            seen.push(JSON.parse(frame.slice('data: '.length)));
+          }
+        }
+      }
+      if (!seen.some((e) => e.type === 'git' && e.operation === 'fetch')) {
+        throw new Error(`No fetch event, got ${JSON.stringify(seen)}`);
+      }
+    } finally {
+      controller.abort();
+    }
+  });
+
+  await runTest('Update workspace repository URL', async () => {
+    const unreachable = await api('PATCH', `/api/workspaces/${workspace}`, {
+      repo_url: '/nonexistent/repo.git',
+    });
+    if (unreachable.status !== 400) {
+      throw new Error(`Expected 400 for unreachable URL, got ${unreachable.status}`);
+    }
+
+    const movedUrl = createFixtureRepo();
+    const response = await api('PATCH', `/api/workspaces/${workspace}`, { repo_url: movedUrl });
+    if (!response.ok) {
+      throw new Error(`HTTP ${response.status}: ${await response.text()}`);
+    }
+    const workspaces = await (await api('GET', '/api/workspaces')).json();
+    const updated = workspaces.find((w: { name: string }) => w.name === workspace);
+    if (updated?.repo_url !== movedUrl) {
+      throw new Error(`repo_url not updated: ${JSON.stringify(updated)}`);
+    }
+  });
+
+  await runTest('Rename workspace', async () => {
+    const invalid = await api('POST', `/api/workspaces/${workspace}/rename`, { new_name: '../escape' });
+    if (invalid.status !== 400) {
+      throw new Error(`Expected 400 for invalid name, got ${invalid.status}`);
+    }
+
+    const renamed = `${workspace}-renamed`;
+    let response = await api('POST', `/api/workspaces/${workspace}/rename`, { new_name: renamed });
+    if (!response.ok) {
+      throw new Error(`Rename: HTTP ${response.status}: ${await response.text()}`);
+    }
+    const read = await api('GET', `/api/workspaces/${renamed}/branches/main/file?path=README.md`);
+    if (!read.ok) {
+      throw new Error(`Read after rename: HTTP ${read.status}`);
+    }
+
+    response = await api('POST', `/api/workspaces/${renamed}/rename`, { new_name: workspace });
+    if (!response.ok) {
+      throw new Error(`Rename back: HTTP ${response.status}`);
+    }
+  });
+
+  await runTest('Import local repository', async () => {
+    const outside = await api('POST', '/api/workspaces', {
+      name: `${workspace}-outside`,
+      local_path: '/',
+      base_branch: 'main',
+    });
+    if (outside.status !== 403) {
+      throw new Error(`Expected 403 outside the import root, got ${outside.status}`);
+    }
+
+    const localPath = createFixtureRepo();
+    const imported = `${workspace}-imported`;
+    let response = await api('POST', '/api/workspaces?sync=true', {
+      name: imported,
+      local_path: localPath,
+      base_branch: 'main',
+    });
+    if (response.status !== 201) {
+      throw new Error(`Import: HTTP ${response.status}: ${await response.text()}`);
+    }
+    response = await api('POST', `/api/workspaces/${imported}/branches`, { branch_name: 'main' });
+    if (response.status !== 201) {
+      throw new Error(`Worktree on imported workspace: HTTP ${response.status}`);
+    }
+    await api('DELETE', `/api/workspaces/${imported}`);
+  });
+
+  await runTest('Concurrent workspace creates', async () => {
+    const repoUrl = createFixtureRepo();
+    const name = `${workspace}-dup`;
+    const responses = await Promise.all(
+      [0, 1].map(() => api('POST', '/api/workspaces?sync=true', { name, repo_url: repoUrl, base_branch: 'main' }))
+    );
+    const statuses = responses.map((r) => r.status).sort();
+    if (statuses[0] !== 201 || statuses[1] !== 409) {
+      throw new Error(`Expected one 201 and one 409, got ${statuses.join(', ')}`);
+    }
+    await api('DELETE', `/api/workspaces/${name}`);
+  });
+
+  await runTest('Concurrent config writes keep every change', async () => {
+    const repoUrl = createFixtureRepo();
+    const names = Array.from({ length: 8 }, (_, i) => `${workspace}-hammer-${i}`);
+    await Promise.all(
+      names.map((name) => api('POST', '/api/workspaces?sync=true', { name, repo_url: repoUrl, base_branch: 'main' }))
+    );
+    const removed = names.slice(0, 4);
+    await Promise.all(removed.map((name) => api('DELETE', `/api/workspaces/${name}`)));
+
+    const saved = JSON.parse(fs.readFileSync(CONFIG_FILE, 'utf-8'));
+    const onDisk = Object.keys(saved.workspaces).filter((n) => n.startsWith(`${workspace}-hammer-`)).sort();
+    const expected = names.slice(4).sort();
+    if (JSON.stringify(onDisk) !== JSON.stringify(expected)) {
+      throw new Error(`Config has ${JSON.stringify(onDisk)}, expected ${JSON.stringify(expected)}`);
+    }
+    await Promise.all(expected.map((name) => api('DELETE', `/api/workspaces/${name}`)));
+  });
+
+  await runTest('Search returns matches in tree order', async () => {
+    let response = await api('PUT', fileUrl('docs/disk.md'), { content: '# Disk\n\nDiskFull pages\nhighcpu too\n' });
+    if (!response.ok) {
+      throw new Error(`Save: HTTP ${response.status}`);
+    }
+    const listing = await (await api('GET', `/api/workspaces/${workspace}/branches`)).json();
+    const main = listing.find((b: { name: string }) => b.name === 'main');
+    fs.writeFileSync(path.join(main.worktree_path, 'docs', 'blob.md'), 'HighCPU\0binary');
+
+    const search = async (query: string) => {
+      const res = await api('GET', `/api/workspaces/${workspace}/branches/main/search?${query}`);
+      if (res.status !== 200) {
+        throw new Error(`Search ${query}: HTTP ${res.status}`);
+      }
+      return res.json();
+    };
+    const found = (data: any) =>
+      data.matches.map((m: any) => `${m.path}:${m.line_number}:${m.match_start}-${m.match_end}:${m.line}`);
+
+    let data = await search('q=highcpu');
+    const expected = [
+      'docs/alerts.md:3:0-7:HighCPU fires above 90%',
+      'docs/disk.md:4:0-7:highcpu too',
+    ];
+    if (JSON.stringify(found(data)) !== JSON.stringify(expected) || data.truncated !== false) {
+      throw new Error(`Unexpected matches: ${JSON.stringify(data)}`);
+    }
+
+    data = await search('q=highcpu&case_sensitive=true');
+    if (JSON.stringify(found(data)) !== JSON.stringify(['docs/disk.md:4:0-7:highcpu too'])) {
+      throw new Error(`Case-sensitive search: ${JSON.stringify(data)}`);
+    }
+
+    data = await search('q=fires%7Cpages&regex=true');
+    if (JSON.stringify(data.matches.map((m: any) => m.line)) !== '["HighCPU fires above 90%","DiskFull pages"]') {
+      throw new Error(`Regex search: ${JSON.stringify(data)}`);
+    }
+
+    // Outside regex mode the query is taken literally
+    data = await search('q=fires%7Cpages');
+    if (data.matches.length !== 0) {
+      throw new Error(`Literal search matched: ${JSON.stringify(data)}`);
+    }
+
+    data = await search('q=highcpu&limit=1');
+    if (data.matches.length !== 1 || data.truncated !== true) {
+      throw new Error(`Limited search: ${JSON.stringify(data)}`);
+    }
+  });
+
+  await runTest('Search rejects invalid regexes', async () => {
+    const response = await api('GET', `/api/workspaces/${workspace}/branches/main/search?q=%28unclosed&regex=true`);
+    if (response.status !== 400) {
+      throw new Error(`Expected 400 for an invalid regex, got ${response.status}`);
+    }
+  });
+
+  await runTest('Failed save leaves original file intact', async () => {
+    let response = await api('PUT', fileUrl('docs/alerts.md'), { content: 'original\n' });
+    if (!response.ok) {
+      throw new Error(`Initial save: HTTP ${response.status}`);
+    }
+
+    // A file where the new file's directory should be fails the save for
+    // every user, root included
+    response = await api('PUT', fileUrl('docs/alerts.md/nested.md'), { content: 'clobbered\n' });
+    if (response.ok) {
+      throw new Error('Save below a file should fail');
+    }
+
+    const data = await (await api('GET', fileUrl('docs/alerts.md'))).json();
+    if (data.content !== 'original\n') {
+      throw new Error(`Original content lost: ${JSON.stringify(data.content)}`);
+    }
+  });
+
+  await runTest('Path traversal attempts are rejected', async () => {
+    // [raw query value, expected status for read, create, write and delete]
+    const cases: Array<[string, number, number, number, number]> = [
+      ['../outside.md', 400, 400, 400, 400],
+      ['docs/../../outside.md', 400, 400, 400, 400],
+      ['docs/%2e%2e/%2e%2e/outside.md', 400, 400, 400, 400],
+      ['..%5C..%5Coutside.md', 400, 400, 400, 400],
+      ['notes%00.md', 400, 400, 400, 400],
+      ['notes..md', 404, 201, 200, 200],
+    ];
+    const url = (p: string) => `/api/workspaces/${workspace}/branches/main/file?path=${p}`;
+
+    // Files an escaping path would land on, planted so a delete that got
+    // out would show up too
+    const listing = await (await api('GET', `/api/workspaces/${workspace}/branches`)).json();
+    const main = listing.find((b: { name: string }) => b.name === 'main');
+    const decoys = [
+      path.join(main.worktree_path, '..', 'outside.md'),
+      path.join(main.worktree_path, '..', '..', 'outside.md'),
+    ];
+    const outside = (dir: string) => fs.readdirSync(dir).sort().join('\n');
+    for (const decoy of decoys) {
+      fs.writeFileSync(decoy, 'decoy\n');
+    }
+    const before = decoys.map((decoy) => outside(path.dirname(decoy)));
+
+    try {
+      for (const [p, readStatus, createStatus, writeStatus, deleteStatus] of cases) {
+        const steps: Array<[string, number, unknown]> = [
+          ['GET', readStatus, undefined],
+          ['POST', createStatus, { content: 'x' }],
+          ['PUT', writeStatus, { content: 'x' }],
+          ['DELETE', deleteStatus, undefined],
+        ];
+        for (const [method, expected, body] of steps) {
+          const response = await api(method, url(p), body);
+          if (response.status !== expected) {
+            throw new Error(`${method} ${p}: expected ${expected}, got ${response.status}`);
+          }
+        }
+      }
+
+      decoys.forEach((decoy, i) => {
+        if (fs.readFileSync(decoy, 'utf-8') !== 'decoy\n') {
+          throw new Error(`${decoy} was written through the API`);
+        }
+        if (outside(path.dirname(decoy)) !== before[i]) {
+          throw new Error(`Files outside the workspace changed in ${path.dirname(decoy)}`);
+        }
+      });
+    } finally {
+      for (const decoy of decoys) {
+        fs.rmSync(decoy, { force: true });
+      }
+    }
+
+    // Absolute paths are resolved inside the worktree, never against the host root
+    const response = await api('GET', url('/etc/passwd'));
+    const data = await response.json();
+    if (response.ok || String(data.content ?? '').includes('root:')) {
+      throw new Error('Absolute path escaped the worktree');
+    }
+  });
+
+  await runTest('Invalid workspace and branch names are rejected', async () => {
+    const badWorkspace = await api('POST', '/api/workspaces', {
+      name: '../../etc',
+      repo_url: createFixtureRepo(),
+      base_branch: 'main',
+    });
+    if (badWorkspace.status !== 400) {
+      throw new Error(`Expected 400 for traversal workspace name, got ${badWorkspace.status}`);
+    }
+
+    for (const branchName of ['--help', 'a..b', 'x.lock', 'has space']) {
+      const response = await api('POST', `/api/workspaces/${workspace}/branches`, {
+        branch_name: branchName,
+      });
+      if (response.status !== 400) {
+        throw new Error(`Expected 400 for branch '${branchName}', got ${response.status}`);
+      }
+    }
+  });
+
+  await runTest('Malformed and oversized bodies get JSON errors', async () => {
+    const missing = await api('POST', `/api/workspaces/${workspace}/branches`, { branch: 'x' });
+    const missingError = await expectError(missing, 400, 'invalid_body');
+    if (missingError.details?.field !== 'branch_name') {
+      throw new Error(`Missing field not named: ${JSON.stringify(missingError)}`);
+    }
+
+    const broken = await fetch(`${SERVER_URL}/api/workspaces/${workspace}/branches`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json', 'Authorization': `Bearer ${authToken}` },
+      body: '{"branch_name":',
+    });
+    await expectError(broken, 400, 'invalid_body');
+
+    const oversized = await api('POST', `/api/workspaces/${workspace}/branches`, {
+      branch_name: 'x'.repeat(512 * 1024),
+    });
+    await expectError(oversized, 413, 'body_too_large');
+  });
+
+  await runTest('Errors carry stable codes', async () => {
+    await expectError(await fetch(`${SERVER_URL}/api/workspaces`), 401, 'auth_required');
+    await expectError(await fetch(`${SERVER_URL}/api/workspaces?token=wrong`), 401, 'invalid_token');
+    await expectError(await api('GET', '/api/workspaces/no-such-workspace'), 404, 'workspace_not_found');
+    await expectError(
+      await api('GET', `/api/workspaces/${workspace}/branches/no-such-branch/files`),
+      404,
+      'worktree_not_found',
+    );
+    await expectError(await api('GET', fileUrl('docs/missing.md')), 404, 'path_not_found');
+    await expectError(await api('GET', fileUrl('../outside.md')), 400, 'invalid_path');
+    await expectError(
+      await api('POST', `/api/workspaces/${workspace}/branches`, { branch_name: 'a..b' }),
+      400,
+      'invalid_branch_name',
+    );
+    await expectError(
+      await api('POST', '/api/workspaces', { name: workspace, repo_url: createFixtureRepo(), base_branch: 'main' }),
+      409,
+      'workspace_exists',
+    );
+    await expectError(await api('DELETE', '/api/auth/tokens/no-such-token'), 404, 'token_not_found');
+
+    const gitFailure = await expectError(
+      await api('POST', `/api/workspaces/${workspace}/branches`, { branch_name: 'e2e-bad-base', from_branch: 'no-such-base' }),
+      500,
+      'git_failed',
+    );
+    if (typeof gitFailure.details?.stderr !== 'string') {
+      throw new Error(`git stderr missing from details: ${JSON.stringify(gitFailure)}`);
+    }
+  });
+
+  await runTest('API is served under /api/v1 with deprecated aliases', async () => {
+    const version = await fetch(`${SERVER_URL}/api/version`);
+    const info = await version.json();
+    if (version.status !== 200 || !info.api_versions?.includes('v1')) {
+      throw new Error(`Version: HTTP ${version.status} ${JSON.stringify(info)}`);
+    }
+
+    const current = await api('GET', '/api/v1/workspaces');
+    const legacy = await api('GET', '/api/workspaces');
+    if (current.status !== 200 || legacy.status !== 200) {
+      throw new Error(`Expected 200 from both prefixes, got ${current.status} and ${legacy.status}`);
+    }
+    if (current.headers.get('deprecation') !== null || legacy.headers.get('deprecation') !== 'true') {
+      throw new Error('Only the unversioned path should carry a Deprecation header');
+    }
+    if (JSON.stringify(await current.json()) !== JSON.stringify(await legacy.json())) {
+      throw new Error('Both prefixes should return the same body');
+    }
+  });
+
+  await runTest('Runbook block runs in a terminal session', async () => {
+    const runbook = '# Smoke\n\n```bash {name=marker}\necho "E2E_BLOCK_$((6 * 7))"\n```\n';
+    const saved = await api('PUT', fileUrl('runbook.md'), { content: runbook });
+    if (!saved.ok) {
+      throw new Error(`Save runbook: HTTP ${saved.status}`);
+    }
+    const runUrl = `/api/v1/workspaces/${workspace}/branches/main/blocks/run`;
+
+    await new Promise<void>((resolve, reject) => {
+      const ws = new WebSocket(getAuthenticatedWsUrl());
+      const timeout = setTimeout(() => {
+        ws.close();
+        reject(new Error('Block output never reached the terminal'));
+      }, 15000);
+      const fail = (err: Error) => {
+        clearTimeout(timeout);
+        ws.close();
+        reject(err);
+      };
+
+      ws.on('open', () => ws.send(JSON.stringify({ type: 'create' })));
+      ws.on('message', async (data) => {
+        const msg = JSON.parse(data.toString());
+        if (msg.type === 'created') {
+          const response = await api('POST', runUrl, {
+            path: 'runbook.md',
+            block_index: 0,
+            session_id: msg.session_id,
+          });
+          const run = await response.json();
+          if (response.status !== 200 || run.session_id !== msg.session_id || !run.file_hash) {
+            fail(new Error(`Run in session: HTTP ${response.status} ${JSON.stringify(run)}`));
+          }
+        } else if (msg.type === 'output' && msg.data.includes('E2E_BLOCK_42')) {
+          clearTimeout(timeout);
+          ws.send(JSON.stringify({ type: 'close', session_id: msg.session_id }));
+          ws.close();
+          resolve();
+        }
+      });
+      ws.on('error', (err) => fail(new Error(`WebSocket error: ${err.message}`)));
+    });
+
+    const response = await api('POST', runUrl, { path: 'runbook.md', block_index: 0 });
+    const run = await response.json();
+    if (response.status !== 200 || run.exit_code !== 0 || run.stdout !== 'E2E_BLOCK_42\n') {
+      throw new Error(`Run in worktree: HTTP ${response.status} ${JSON.stringify(run)}`);
+    }
+  });
+
+  await runTest('Responses carry a request ID', async () => {
+    const generated = await api('GET', '/api/workspaces');
+    if (!generated.headers.get('x-request-id')) {
+      throw new Error('X-Request-Id missing from response');
+    }
+
+    const echoed = await fetch(`${SERVER_URL}/api/workspaces/no-such-workspace`, {
+      headers: { 'Authorization': `Bearer ${authToken}`, 'X-Request-Id': 'e2e-request-1' },
+    });
+    if (echoed.headers.get('x-request-id') !== 'e2e-request-1') {
+      throw new Error(`Client request ID not echoed: ${echoed.headers.get('x-request-id')}`);
+    }
+
+    const unauthenticated = await fetch(`${SERVER_URL}/api/workspaces`);
+    if (!unauthenticated.headers.get('x-request-id')) {
+      throw new Error('X-Request-Id missing from rejected request');
+    }
+  });
+
+  await runTest('Workspace cleanup', async () => {
+    const response = await api('DELETE', `/api/workspaces/${workspace}`);
+    if (!response.ok) {
+      throw new Error(`HTTP ${response.status}`);
+    }
+    const summary = await response.json();
+    if (!summary.removed_worktrees?.includes('main')) {
+      throw new Error(`Worktree not reported as removed: ${JSON.stringify(summary)}`);
+    }
+  });
+
+  // Test: Mutations are in the audit log, without file contents
+  await runTest('Audit log records mutations', async () => {
+    const response = await api('GET', '/api/audit?limit=1000');
+    if (!response.ok) {
+      throw new Error(`HTTP ${response.status}`);
+    }
+    const entries = await response.json();
+    const actions = entries
+      .filter((e: { summary: { workspace?: string } }) => e.summary.workspace === workspace)
+      .map((e: { action: string }) => e.action);
+    for (const expected of ['workspace.create', 'branch.create', 'file.save', 'workspace.delete']) {
+      if (!actions.includes(expected)) {
+        throw new Error(`Missing ${expected} in audit log: ${JSON.stringify(actions)}`);
+      }
+    }
+    const save = entries.find((e: { action: string }) => e.action === 'file.save');
+    if ('content' in save.summary) {
+      throw new Error('Audit log must not contain file contents');
+    }
+  });
 }
 
 // Main test runner
@@ -457,6 +1232,10 @@ async function main(): Promise<void> {
     log('Running WebSocket tests...');
     await runWebSocketTests();
 
+    // Run workspace file tests
+    log('Running file tests...');
+    await runFileTests();
+
   } finally {
     // Cleanup
     stopServer();
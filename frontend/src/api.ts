@@ -1,6 +1,30 @@
 import { logger } from './logger';
 import { authManager } from './auth';
-import type { Workspace, Branch, FileEntry } from './types';
+import type {
+  Workspace,
+  Branch,
+  BranchStatus,
+  FileEntry,
+  RestoredFile,
+  TrashItem,
+  CommitResult,
+  Job,
+  Runbook,
+  BlockRun,
+  Execution,
+  Checklist,
+  LintResults,
+  Outline,
+  Approval,
+  Capabilities,
+} from './types';
+
+// Only used when the event stream can't be opened
+const JOB_POLL_INTERVAL_MS = 1000;
+
+function isRunning(job: Job): boolean {
+  return job.state === 'queued' || job.state === 'running';
+}
 
 class ApiClient {
   private baseUrl: string;
@@ -33,22 +57,99 @@ class ApiClient {
     const response = await fetch(url, options);
 
     if (!response.ok) {
-      const error = await response.json().catch(() => ({ error: response.statusText }));
-      logger.error(`API error: ${error.error || response.statusText}`);
-      throw new Error(error.error || response.statusText);
+      const body = await response.json().catch(() => ({}));
+      const message = body.error?.message || response.statusText;
+      logger.error(`API error: ${body.error?.code ?? response.status} ${message}`);
+      throw new Error(message);
     }
 
     return response.json();
   }
 
+  private getJob(id: string): Promise<Job> {
+    return this.request<Job>('GET', `/api/v1/jobs/${encodeURIComponent(id)}`);
+  }
+
+  private async pollJob(job: Job): Promise<Job> {
+    while (isRunning(job)) {
+      await new Promise((resolve) => setTimeout(resolve, JOB_POLL_INTERVAL_MS));
+      job = await this.getJob(job.id);
+    }
+    return job;
+  }
+
+  // Follow a job's events on /api/v1/events until it finishes, falling back to
+  // polling if the stream fails
+  private waitForJob(job: Job): Promise<Job> {
+    return new Promise((resolve, reject) => {
+      const params = new URLSearchParams({ workspace: job.workspace });
+      const token = authManager.getToken();
+      if (token) {
+        params.set('token', token);
+      }
+      const source = new EventSource(`${this.baseUrl}/api/v1/events?${params}`);
+      let settled = false;
+      const settle = (finished: Job) => {
+        if (!settled) {
+          settled = true;
+          source.close();
+          resolve(finished);
+        }
+      };
+
+      // The job may have finished before the stream was open
+      source.onopen = () => {
+        this.getJob(job.id)
+          .then((latest) => {
+            if (!isRunning(latest)) {
+              settle(latest);
+            }
+          })
+          .catch(() => {});
+      };
+      source.onmessage = (message) => {
+        const event = JSON.parse(message.data);
+        if (event.type === 'job' && event.job.id === job.id && !isRunning(event.job)) {
+          settle(event.job);
+        }
+      };
+      source.onerror = () => {
+        if (settled) {
+          return;
+        }
+        logger.warn('Event stream failed, polling the job instead');
+        settled = true;
+        source.close();
+        this.pollJob(job).then(resolve, reject);
+      };
+    });
+  }
+
+  // Start a background job and wait for it, resolving with its result
+  private async runJob<T>(method: string, path: string, body?: unknown): Promise<T> {
+    let job = await this.request<Job>(method, path, body);
+    if (isRunning(job)) {
+      job = await this.waitForJob(job);
+    }
+    if (job.state !== 'succeeded') {
+      logger.error(`Job ${job.kind} ${job.state}: ${job.error?.code} ${job.error?.message}`);
+      throw new Error(job.error?.message ?? `Job ${job.state}`);
+    }
+    return job.result as T;
+  }
+
   // Auth
   async checkAuth(): Promise<{ valid: boolean }> {
-    return this.request('GET', '/api/auth/check');
+    return this.request('GET', '/api/v1/auth/check');
+  }
+
+  async getCapabilities(): Promise<Capabilities> {
+    return this.request('GET', '/api/v1/capabilities');
   }
 
   // Workspaces
   async listWorkspaces(): Promise<Workspace[]> {
-    return this.request('GET', '/api/workspaces');
+    return this.request('GET', '/api/v1/workspaces');
   }
 
   async createWorkspace(
@@ -56,7 +157,7 @@ class ApiClient {
     repoUrl: string,
     baseBranch: string
   ): Promise<Workspace> {
-    return this.request('POST', '/api/workspaces', {
+    return this.runJob('POST', '/api/v1/workspaces', {
       name,
       repo_url: repoUrl,
       base_branch: baseBranch,
@@ -64,12 +165,12 @@ class ApiClient {
   }
 
   async deleteWorkspace(name: string): Promise<void> {
-    return this.request('DELETE', `/api/workspaces/${encodeURIComponent(name)}`);
+    return this.request('DELETE', `/api/v1/workspaces/${encodeURIComponent(name)}`);
   }
 
   // Branches
   async listBranches(workspace: string): Promise<Branch[]> {
-    return this.request('GET', `/api/workspaces/${encodeURIComponent(workspace)}/branches`);
+    return this.request('GET', `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches`);
   }
 
   async createBranch(
@@ -77,16 +178,23 @@ class ApiClient {
     branchName: string,
     fromBranch?: string
   ): Promise<void> {
-    return this.request('POST', `/api/workspaces/${encodeURIComponent(workspace)}/branches`, {
+    return this.request('POST', `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches`, {
       branch_name: branchName,
       from_branch: fromBranch,
     });
   }
 
+  async branchStatus(workspace: string, branch: string): Promise<BranchStatus> {
+    return this.request(
+      'GET',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/status`
+    );
+  }
+
   async deleteBranch(workspace: string, branch: string): Promise<void> {
     return this.request(
       'DELETE',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}`
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}`
     );
   }
 
@@ -94,7 +202,7 @@ class ApiClient {
   async listFiles(workspace: string, branch: string): Promise<FileEntry[]> {
     return this.request(
       'GET',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/files`
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/files`
     );
   }
 
@@ -102,13 +210,128 @@ class ApiClient {
     workspace: string,
     branch: string,
     path: string
-  ): Promise<{ path: string; content: string }> {
+  ): Promise<{ path: string; content: string; has_draft: boolean; draft_saved_at: string | null }> {
+    return this.request(
+      'GET',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file?path=${encodeURIComponent(path)}`
+    );
+  }
+
+  // Autosaved drafts, kept by the server outside the worktree
+  async getDraft(workspace: string, branch: string, path: string): Promise<{ path: string; content: string; saved_at: string }> {
+    return this.request('GET', this.draftUrl(workspace, branch, path));
+  }
+
+  async saveDraft(workspace: string, branch: string, path: string, content: string): Promise<{ saved_at: string }> {
+    return this.request('PUT', this.draftUrl(workspace, branch, path), { content });
+  }
+
+  async discardDraft(workspace: string, branch: string, path: string): Promise<void> {
+    await this.request('DELETE', this.draftUrl(workspace, branch, path));
+  }
+
+  private draftUrl(workspace: string, branch: string, path: string): string {
+    return `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file/draft?path=${encodeURIComponent(path)}`;
+  }
+
+  // With content, the outline of that unsaved text instead of the file
+  async getOutline(workspace: string, branch: string, path: string, content?: string): Promise<Outline> {
+    const url = `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file/outline`;
+    if (content !== undefined) {
+      return this.request('POST', url, { path, content });
+    }
+    return this.request('GET', `${url}?path=${encodeURIComponent(path)}`);
+  }
+
+  async listBlocks(workspace: string, branch: string, path: string): Promise<Runbook> {
+    return this.request(
+      'GET',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/blocks?path=${encodeURIComponent(path)}`
+    );
+  }
+
+  async runBlock(
+    workspace: string,
+    branch: string,
+    path: string,
+    blockIndex: number,
+    options: {
+      sessionId?: string;
+      recordOutput?: boolean;
+      variables?: Record<string, string | number | boolean>;
+      approvalToken?: string;
+      dryRun?: boolean;
+    } = {}
+  ): Promise<BlockRun> {
+    return this.request(
+      'POST',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/blocks/run`,
+      {
+        path,
+        block_index: blockIndex,
+        session_id: options.sessionId,
+        record_output: options.recordOutput ?? false,
+        variables: options.variables ?? {},
+        approval_token: options.approvalToken,
+        dry_run: options.dryRun ?? false,
+      }
+    );
+  }
+
+  // Approve another token's run of a requires_approval block, as reviewed
+  async createApproval(
+    workspace: string,
+    branch: string,
+    path: string,
+    blockIndex: number,
+    blockHash: string
+  ): Promise<Approval> {
+    return this.request('POST', '/api/v1/approvals', {
+      workspace,
+      branch,
+      path,
+      block_index: blockIndex,
+      block_hash: blockHash,
+    });
+  }
+
+  async getChecklist(workspace: string, branch: string, path: string): Promise<Checklist> {
     return this.request(
       'GET',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file?path=${encodeURIComponent(path)}`
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/checklist?path=${encodeURIComponent(path)}`
     );
   }
 
+  // fileHash is the checklist's file_hash; a stale one gets 409 file_changed
+  async toggleStep(
+    workspace: string,
+    branch: string,
+    path: string,
+    step: number,
+    checked: boolean,
+    fileHash: string
+  ): Promise<Checklist> {
+    return this.request(
+      'PUT',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/checklist?path=${encodeURIComponent(path)}`,
+      { step, checked, file_hash: fileHash }
+    );
+  }
+
+  // Without a path every runbook of the worktree is checked
+  async lint(workspace: string, branch: string, path?: string): Promise<LintResults> {
+    const query = path ? `?path=${encodeURIComponent(path)}` : '';
+    return this.request(
+      'GET',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/lint${query}`
+    );
+  }
+
+  async listExecutions(workspace: string, path?: string): Promise<Execution[]> {
+    const query = path ? `?path=${encodeURIComponent(path)}` : '';
+    return this.request('GET', `/api/v1/workspaces/${encodeURIComponent(workspace)}/executions${query}`);
+  }
+
   async saveFile(
     workspace: string,
     branch: string,
@@ -117,43 +340,109 @@ class ApiClient {
   ): Promise<void> {
     return this.request(
       'PUT',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file?path=${encodeURIComponent(path)}`,
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file?path=${encodeURIComponent(path)}`,
       { content }
     );
   }
 
+  // Save only the changes made to the content read at baseHash
+  async patchFile(
+    workspace: string,
+    branch: string,
+    path: string,
+    baseHash: string,
+    edits: { start_byte: number; end_byte: number; replacement: string }[]
+  ): Promise<{ path: string; file_hash: string; bytes: number }> {
+    return this.request(
+      'PATCH',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file?path=${encodeURIComponent(path)}`,
+      { base_hash: baseHash, edits }
+    );
+  }
+
+  async restoreFile(
+    workspace: string,
+    branch: string,
+    path: string,
+    options: { ref?: string; overwrite?: boolean } = {}
+  ): Promise<RestoredFile> {
+    return this.request(
+      'POST',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file/restore`,
+      { path, ...options }
+    );
+  }
+
+  // Moves the file to the worktree's trash unless permanent
+  async deleteFile(
+    workspace: string,
+    branch: string,
+    path: string,
+    permanent = false
+  ): Promise<{ path: string; permanent: boolean; trash: TrashItem | null }> {
+    return this.request(
+      'DELETE',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/file?path=${encodeURIComponent(path)}&permanent=${permanent}`
+    );
+  }
+
+  async listTrash(workspace: string, branch: string): Promise<TrashItem[]> {
+    return this.request(
+      'GET',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/trash`
+    );
+  }
+
+  async restoreFromTrash(workspace: string, branch: string, id: string): Promise<TrashItem> {
+    return this.request(
+      'POST',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/trash/restore`,
+      { id }
+    );
+  }
+
+  // Without an id the whole trash is purged
+  async purgeTrash(workspace: string, branch: string, id?: string): Promise<{ purged: string[] }> {
+    return this.request(
+      'POST',
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/trash/purge`,
+      { id }
+    );
+  }
+
   // Git operations
   async commit(
     workspace: string,
     branch: string,
     message: string,
-    files: string[]
-  ): Promise<{ commit_id: string }> {
+    files: string[],
+    all = false
+  ): Promise<CommitResult> {
     return this.request(
       'POST',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/commit`,
-      { message, files }
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/commit`,
+      { message, files, all }
     );
   }
 
   async push(workspace: string, branch: string): Promise<void> {
-    return this.request(
+    return this.runJob(
       'POST',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/push`
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/push`
     );
   }
 
   async pull(workspace: string, branch: string): Promise<void> {
-    return this.request(
+    return this.runJob(
       'POST',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/pull`
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/pull`
     );
   }
 
   async rebase(workspace: string, branch: string): Promise<void> {
-    return this.request(
+    return this.runJob(
       'POST',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/rebase`
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/rebase`
     );
   }
 
@@ -164,7 +453,7 @@ class ApiClient {
   ): Promise<void> {
     return this.request(
       'POST',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/checkout`,
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/checkout`,
       { new_base_branch: newBaseBranch }
     );
   }
@@ -176,7 +465,7 @@ class ApiClient {
   ): Promise<void> {
     return this.request(
       'POST',
-      `/api/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/rename`,
+      `/api/v1/workspaces/${encodeURIComponent(workspace)}/branches/${encodeURIComponent(branch)}/rename`,
       { new_name: newName }
     );
   }
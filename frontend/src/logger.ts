@@ -1,3 +1,6 @@
+// authManager is only read when logs are flushed, after both modules have loaded
+import { authManager } from './auth';
+
 export enum LogLevel {
   TRACE = 0,
   DEBUG = 1,
@@ -35,16 +38,17 @@ function flushLogs(): void {
 
   const logsToSend = pendingLogs.splice(0, pendingLogs.length);
 
-  // Send each log (could batch into array endpoint if needed)
-  for (const log of logsToSend) {
-    fetch('/api/console', {
-      method: 'POST',
-      headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify(log),
-    }).catch(() => {
-      // Silently ignore fetch errors to avoid infinite loops
-    });
-  }
+  // The endpoint takes an array, so the batch goes in one request
+  fetch('/api/v1/console', {
+    method: 'POST',
+    headers: {
+      'Content-Type': 'application/json',
+      ...authManager.getAuthHeader(),
+    },
+    body: JSON.stringify(logsToSend),
+  }).catch(() => {
+    // Silently ignore fetch errors to avoid infinite loops
+  });
 }
 
 function formatArgs(args: unknown[]): string {
@@ -45,6 +45,10 @@ class TerminalManager {
         break;
       }
 
+      case 'relabeled':
+        logger.info(`Session ${msg.session_id} relabeled: ${msg.label ?? '(none)'}`);
+        break;
+
       case 'closed':
         logger.info(`Session closed: ${msg.session_id}`);
         this.terminals.delete(msg.session_id);
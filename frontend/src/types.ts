@@ -1,16 +1,25 @@
 // WebSocket message types (client -> server)
 export type WsClientMessage =
-  | { type: 'create'; id: string }
+  | { type: 'create'; id: string; label?: string; runbook_path?: string; workspace?: string; branch?: string }
   | { type: 'input'; session_id: string; data: string }
   | { type: 'resize'; session_id: string; cols: number; rows: number }
-  | { type: 'close'; session_id: string };
+  | { type: 'close'; session_id: string }
+  | { type: 'relabel'; session_id: string; label: string | null };
 
 // WebSocket message types (server -> client)
 export type WsServerMessage =
-  | { type: 'created'; session_id: string }
+  | { type: 'created'; session_id: string; label: string | null; runbook_path: string | null }
+  | { type: 'relabeled'; session_id: string; label: string | null }
   | { type: 'output'; session_id: string; data: string }
   | { type: 'closed'; session_id: string }
-  | { type: 'error'; message: string };
+  | { type: 'error'; message: string }
+  | {
+      type: 'file_changed';
+      workspace: string;
+      branch: string;
+      path: string;
+      kind: 'created' | 'modified' | 'deleted';
+    };
 
 // Terminal session data
 export interface TerminalSession {
@@ -27,10 +36,76 @@ export interface Workspace {
   created_at: string;
 }
 
+// Background git operation (clone, fetch, pull, push, rebase)
+export type JobState = 'queued' | 'running' | 'succeeded' | 'failed' | 'cancelled';
+
+export interface GitProgress {
+  phase: string | null;
+  percent: number | null;
+  current: number | null;
+  total: number | null;
+  throughput: string | null;
+  line: string;
+}
+
+export interface Job {
+  id: string;
+  kind: 'clone_workspace' | 'fetch' | 'pull' | 'push' | 'rebase' | 'verify' | 'prune' | 'cleanup_merged';
+  workspace: string;
+  branch: string | null;
+  state: JobState;
+  progress: string | null;
+  // progress taken apart when it is git's; only line when git's wording isn't recognized
+  git_progress: GitProgress | null;
+  result: unknown;
+  error: { code: string; message: string; details: unknown } | null;
+  // Set for jobs queued by POST /api/admin/workspaces/bulk
+  batch?: string;
+}
+
 export interface Branch {
   name: string;
   is_worktree: boolean;
   worktree_path: string | null;
+  // What the worktree really has checked out; null when detached
+  checked_out_branch: string | null;
+  head_commit: string | null;
+  dirty: boolean | null;
+  // Relative to the workspace's base branch
+  ahead: number | null;
+  behind: number | null;
+  // The stats above took too long and are null
+  stats_timeout: boolean;
+  // Stale locks or an interrupted rebase/merge left in the worktree
+  needs_attention: Attention[];
+  // Pinned to a tag or commit, named detached:<ref>; can't be committed to
+  detached: boolean;
+}
+
+export interface Attention {
+  // rebase_interrupted, merge_interrupted, cherry_pick_interrupted,
+  // revert_interrupted, lock_held or stale_lock
+  condition: string;
+  message: string;
+}
+
+export interface SubmoduleStatus {
+  path: string;
+  // Checked out, or recorded when not initialized
+  commit: string;
+  initialized: boolean;
+  commit_mismatch: boolean;
+  conflicted: boolean;
+  dirty: boolean;
+}
+
+export interface BranchStatus {
+  branch: string;
+  checked_out_branch: string | null;
+  head_commit: string | null;
+  // status is the two-letter code of git status --porcelain
+  changes: { status: string; path: string }[];
+  submodules: SubmoduleStatus[];
 }
 
 export interface FileEntry {
@@ -38,6 +113,222 @@ export interface FileEntry {
   path: string;
   is_dir: boolean;
   children?: FileEntry[];
+  frontmatter?: Record<string, unknown>;
+  frontmatter_warnings?: string[];
+}
+
+export interface RestoredFile {
+  path: string;
+  commit: { sha: string; subject: string; author: string; date: string };
+  // Whether the file was gone at the requested revision
+  from_deleted: boolean;
+  bytes: number;
+}
+
+export interface TrashItem {
+  id: string;
+  // Where the file was
+  path: string;
+  deleted_at: string;
+  size: number;
+}
+
+export interface OutlineHeading {
+  level: number;
+  title: string;
+  anchor: string;
+  line: number;
+  children: OutlineHeading[];
+}
+
+// section is the index of the heading a block or task is under, in
+// document order
+export interface Outline {
+  path: string | null;
+  headings: OutlineHeading[];
+  code_blocks: {
+    index: number;
+    language: string | null;
+    start_line: number;
+    end_line: number;
+    section: number | null;
+  }[];
+  tasks: { index: number; checked: boolean; line: number; section: number | null }[];
+}
+
+export interface LintProblem {
+  path: string;
+  line: number;
+  rule: 'broken_link' | 'broken_anchor' | 'missing_image' | 'empty_code_block';
+  message: string;
+}
+
+export interface LintResults {
+  problems: LintProblem[];
+  files_checked: number;
+  truncated: boolean;
+}
+
+export interface CommitResult {
+  commit_id: string;
+  // What the commit includes; status is A, M, D or T
+  files: { status: string; path: string }[];
+}
+
+export interface Heading {
+  level: number;
+  title: string;
+  line: number;
+}
+
+// A fenced code block of a markdown file, run as a runbook step
+export interface CodeBlock {
+  index: number;
+  language: string | null;
+  attributes: Record<string, string>;
+  info: string;
+  code: string;
+  start_line: number;
+  end_line: number;
+  section: number | null;
+  variables: string[];
+  warnings?: string[];
+}
+
+// Result of running a block; the output fields are set when it ran on the
+// server rather than in a terminal session
+export interface BlockRun {
+  execution_id: string;
+  path: string;
+  block_index: number;
+  language: string | null;
+  start_line: number;
+  file_hash: string;
+  block_hash: string;
+  session_id?: string;
+  session_label?: string;
+  approved_by?: string;
+  exit_code?: number | null;
+  stdout?: string;
+  stderr?: string;
+  truncated?: boolean;
+  timed_out?: boolean;
+  duration_ms?: number;
+  recorded_output: boolean;
+  // Set for dry runs, which only resolve the block
+  dry_run?: boolean;
+  command?: string;
+  shell?: string;
+  worktree_path?: string | null;
+  timeout_secs?: number | null;
+}
+
+// An entry of a workspace's execution history: a block run or a checklist
+// tick
+interface ExecutionBase {
+  id: string;
+  timestamp: string;
+  token: string | null;
+  branch: string;
+  path: string;
+  file_hash: string;
+}
+
+export type Execution =
+  | (ExecutionBase & {
+      kind: 'block_run';
+      block_index: number;
+      block_hash: string;
+      session_id: string | null;
+      session_label?: string;
+      approved_by?: string;
+      schedule_id?: string;
+      exit_code: number | null;
+      timed_out: boolean;
+      duration_ms: number | null;
+    })
+  | (ExecutionBase & {
+      kind: 'dry_run';
+      block_index: number;
+      block_hash: string;
+      session_id: string | null;
+      session_label?: string;
+      approved_by?: string;
+    })
+  | (ExecutionBase & {
+      kind: 'checklist_toggle';
+      step_index: number;
+      checked: boolean;
+      text: string;
+    });
+
+// Permission to run a requires_approval block once, granted by another token
+export interface Approval {
+  approval_token: string;
+  approver: string;
+  workspace: string;
+  branch: string;
+  path: string;
+  block_index: number;
+  block_hash: string;
+  created_at: string;
+  expires_at: string;
+}
+
+// A task list item of a markdown file
+export interface Step {
+  index: number;
+  checked: boolean;
+  text: string;
+  line: number;
+  section: number | null;
+}
+
+export interface Checklist {
+  path: string;
+  file_hash: string;
+  headings: Heading[];
+  steps: Step[];
+}
+
+export interface Variable {
+  name: string;
+  type: 'string' | 'number' | 'boolean';
+  default: string | null;
+  description?: string;
+}
+
+export interface Runbook {
+  path: string;
+  headings: Heading[];
+  blocks: CodeBlock[];
+  variables: Variable[];
+  variable_warnings?: string[];
+}
+
+// What the server offers; hide the controls of features set to false
+export interface Capabilities {
+  version: string;
+  api_versions: string[];
+  current_api_version: string;
+  read_only: boolean;
+  features: Record<string, boolean>;
+  limits: {
+    max_read_bytes: number;
+    max_save_bytes: number;
+    max_request_bytes: number;
+    max_recorded_output_bytes: number;
+    max_sessions: number | null;
+    session_idle_timeout_secs: number | null;
+    session_scrollback_bytes: number;
+  };
+  websocket: {
+    path: string;
+    subprotocol: string;
+    token_subprotocol_prefix: string;
+    query_token: boolean;
+    client_messages: string[];
+  };
 }
 
 // Route params
@@ -22,12 +22,16 @@ class WebSocketConnection {
 
   connect(): void {
     const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-    const tokenParam = authManager.getTokenParam();
-    const wsUrl = `${protocol}//${window.location.host}/ws${tokenParam ? '?' + tokenParam : ''}`;
-    logger.info(`Connecting to WebSocket: ${wsUrl.replace(/token=[^&]+/, 'token=***')}`);
+    const wsUrl = `${protocol}//${window.location.host}/ws`;
+    logger.info(`Connecting to WebSocket: ${wsUrl}`);
+
+    // The token goes in a subprotocol rather than the URL, where proxies
+    // and access logs would keep it; the server accepts plain 'runotepad'
+    const token = authManager.getToken();
+    const protocols = token ? ['runotepad', `runotepad-token.${token}`] : [];
 
     try {
-      this.ws = new WebSocket(wsUrl);
+      this.ws = new WebSocket(wsUrl, protocols);
     } catch (e) {
       logger.error('Failed to create WebSocket:', e);
       this.updateStatus(false, `WebSocket Error: ${(e as Error).message}`);
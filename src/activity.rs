@@ -0,0 +1,370 @@
+//! Activity feed of a branch: its commits, the file saves and deletes the
+//! audit log recorded in its worktree, and its execution history entries,
+//! newest first in one list. Each source is read lazily from the newest
+//! entry back (`git log` through a pipe, the JSONL files backwards) and
+//! the three are merged as they are read, so a page costs about what it
+//! shows rather than the size of the history.
+//!
+//! Items are ordered by (timestamp, source, id) and a page's `next_cursor`
+//! is the last item's key, so pages stay stable while new activity comes
+//! in. A source whose timestamps go back in time (clock skew between
+//! commits, audit entries queued out of order) is ordered as if each item
+//! were no newer than the one before it.
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use crate::audit::AuditLog;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::git_ops;
+use crate::history;
+use crate::logging;
+use crate::workspace;
+
+const DEFAULT_ACTIVITY_LIMIT: usize = 100;
+const MAX_ACTIVITY_LIMIT: usize = 1000;
+
+/// Where an item comes from; part of the ordering key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Source {
+    Audit,
+    Commit,
+    Execution,
+}
+
+impl Source {
+    fn name(self) -> &'static str {
+        match self {
+            Source::Audit => "audit",
+            Source::Commit => "commit",
+            Source::Execution => "execution",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [Source::Audit, Source::Commit, Source::Execution]
+            .into_iter()
+            .find(|source| source.name() == name)
+    }
+}
+
+/// Position of an item in the feed, which runs from the greatest key down
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    timestamp: DateTime<Utc>,
+    source: Source,
+    id: String,
+}
+
+impl Key {
+    /// `<timestamp>_<source>_<id>`, as `before` takes it
+    fn cursor(&self) -> String {
+        format!(
+            "{}_{}_{}",
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true),
+            self.source.name(),
+            self.id
+        )
+    }
+
+    fn parse(cursor: &str) -> Option<Self> {
+        let mut parts = cursor.splitn(3, '_');
+        let timestamp = DateTime::parse_from_rfc3339(parts.next()?).ok()?.with_timezone(&Utc);
+        let source = Source::parse(parts.next()?)?;
+        let id = parts.next().filter(|id| !id.is_empty())?.to_string();
+        Some(Self { timestamp, source, id })
+    }
+}
+
+struct Item {
+    key: Key,
+    body: serde_json::Value,
+}
+
+type Entries = Box<dyn Iterator<Item = Result<Item, ApiError>>>;
+
+/// The items of a source that are newest first by their own timestamps,
+/// with each key's timestamp held to at most the previous one's and items
+/// of equal key timestamps ordered by id, so the keys only go down
+struct Monotonic {
+    inner: Entries,
+    newest: Option<DateTime<Utc>>,
+    /// Read ahead: the first item past the current run of equal timestamps
+    next: Option<Item>,
+    /// The current run, in reverse order of emission
+    run: Vec<Item>,
+}
+
+impl Monotonic {
+    fn new(inner: Entries) -> Self {
+        Self {
+            inner,
+            newest: None,
+            next: None,
+            run: Vec::new(),
+        }
+    }
+
+    fn pull(&mut self) -> Option<Result<Item, ApiError>> {
+        let mut item = match self.next.take() {
+            Some(item) => item,
+            None => match self.inner.next()? {
+                Ok(item) => item,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        if let Some(newest) = self.newest {
+            item.key.timestamp = item.key.timestamp.min(newest);
+        }
+        self.newest = Some(item.key.timestamp);
+        Some(Ok(item))
+    }
+}
+
+impl Iterator for Monotonic {
+    type Item = Result<Item, ApiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.run.pop() {
+            return Some(Ok(item));
+        }
+        let first = match self.pull()? {
+            Ok(item) => item,
+            Err(e) => return Some(Err(e)),
+        };
+        let timestamp = first.key.timestamp;
+        self.run.push(first);
+        while let Some(item) = self.pull() {
+            match item {
+                Ok(item) if item.key.timestamp == timestamp => self.run.push(item),
+                Ok(item) => {
+                    self.next = Some(item);
+                    break;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        // Popped from the end: smallest id last
+        self.run.sort_by(|a, b| a.key.id.cmp(&b.key.id));
+        self.run.pop().map(Ok)
+    }
+}
+
+/// A source's next item, ordered by key for the merge
+struct Head {
+    item: Item,
+    source: usize,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.item.key == other.item.key
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.item.key.cmp(&other.item.key)
+    }
+}
+
+/// Merge sources whose keys go down into one feed whose keys go down,
+/// holding one item per source
+struct Merge {
+    sources: Vec<Monotonic>,
+    heads: BinaryHeap<Head>,
+    before: Option<Key>,
+    started: bool,
+}
+
+impl Merge {
+    fn new(sources: Vec<Entries>, before: Option<Key>) -> Self {
+        Self {
+            sources: sources.into_iter().map(Monotonic::new).collect(),
+            heads: BinaryHeap::new(),
+            before,
+            started: false,
+        }
+    }
+
+    /// Put the next item of `source` that is past the cursor in the heap
+    fn advance(&mut self, source: usize) -> Result<(), ApiError> {
+        for item in self.sources[source].by_ref() {
+            let item = item?;
+            if self.before.as_ref().is_some_and(|before| item.key >= *before) {
+                continue;
+            }
+            self.heads.push(Head { item, source });
+            break;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<Item, ApiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for source in 0..self.sources.len() {
+                if let Err(e) = self.advance(source) {
+                    return Some(Err(e));
+                }
+            }
+        }
+        let Head { item, source } = self.heads.pop()?;
+        if let Err(e) = self.advance(source) {
+            return Some(Err(e));
+        }
+        Some(Ok(item))
+    }
+}
+
+/// `value` with the item's `type` and its own `timestamp`
+fn body(kind: &str, timestamp: DateTime<Utc>, value: impl serde::Serialize) -> serde_json::Value {
+    let mut body = serde_json::to_value(value).unwrap_or_default();
+    if let Some(object) = body.as_object_mut() {
+        object.insert("type".to_string(), kind.into());
+        object.insert("timestamp".to_string(), serde_json::json!(timestamp));
+    }
+    body
+}
+
+fn commits(worktree_path: &std::path::Path) -> Result<Entries, ApiError> {
+    let log = git_ops::log_stream(worktree_path, "HEAD").map_err(|e| e.context("Failed to read the commit log"))?;
+    Ok(Box::new(log.map(|commit| {
+        let commit = commit.map_err(|e| e.context("Failed to read the commit log"))?;
+        Ok(Item {
+            key: Key {
+                timestamp: commit.committed_at,
+                source: Source::Commit,
+                id: commit.sha.clone(),
+            },
+            body: body("commit", commit.committed_at, &commit),
+        })
+    })))
+}
+
+fn file_changes(audit: &AuditLog, workspace: String, branch: String) -> Result<Entries, ApiError> {
+    let entries = audit.newest_first().map_err(|e| ApiError::io("Failed to read audit log", e))?;
+    Ok(Box::new(entries.filter_map(move |entry| {
+        let (offset, entry) = match entry {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(ApiError::io("Failed to read audit log", e))),
+        };
+        let kind = match entry.action.as_str() {
+            "file.save" => "file_save",
+            "file.create" => "file_create",
+            "file.delete" => "file_delete",
+            _ => return None,
+        };
+        if entry.summary["workspace"] != workspace.as_str() || entry.summary["branch"] != branch.as_str() {
+            return None;
+        }
+        Some(Ok(Item {
+            key: Key {
+                timestamp: entry.timestamp,
+                source: Source::Audit,
+                id: offset.to_string(),
+            },
+            body: body(
+                kind,
+                entry.timestamp,
+                serde_json::json!({
+                    "token": entry.token,
+                    "path": entry.summary["path"],
+                    "bytes": entry.summary.get("bytes")
+                }),
+            ),
+        }))
+    })))
+}
+
+fn executions(workspace_path: &std::path::Path, branch: String) -> Result<Entries, ApiError> {
+    let entries = history::newest_first(workspace_path)
+        .map_err(|e| ApiError::io("Failed to read execution history", e))?;
+    Ok(Box::new(entries.filter_map(move |execution| {
+        let execution = match execution {
+            Ok(execution) => execution,
+            Err(e) => return Some(Err(ApiError::io("Failed to read execution history", e))),
+        };
+        if execution.branch != branch {
+            return None;
+        }
+        Some(Ok(Item {
+            key: Key {
+                timestamp: execution.timestamp,
+                source: Source::Execution,
+                id: execution.id.clone(),
+            },
+            body: body("execution", execution.timestamp, &execution),
+        }))
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    pub limit: Option<usize>,
+    /// `next_cursor` of the previous page
+    pub before: Option<String>,
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/activity - Commits, file
+/// saves and deletes, and executions of a branch, newest first
+pub async fn branch_activity(
+    config: web::Data<Arc<ConfigManager>>,
+    audit: web::Data<Arc<AuditLog>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ActivityQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let limit = query.limit.unwrap_or(DEFAULT_ACTIVITY_LIMIT).clamp(1, MAX_ACTIVITY_LIMIT);
+    let before = match query.before.as_deref() {
+        Some(cursor) => Some(Key::parse(cursor).ok_or_else(|| {
+            ApiError::validation("invalid_cursor", format!("Invalid activity cursor '{}'", cursor))
+        })?),
+        None => None,
+    };
+
+    let workspace_path = config.workspace_path(&workspace);
+    let audit = audit.get_ref().clone();
+    let body = logging::block(move || -> Result<serde_json::Value, ApiError> {
+        let sources = vec![
+            commits(&worktree_path)?,
+            file_changes(&audit, workspace, branch.clone())?,
+            executions(&workspace_path, branch)?,
+        ];
+        let mut feed = Merge::new(sources, before).peekable();
+        let mut items = Vec::new();
+        let mut last = None;
+        while items.len() < limit {
+            let Some(item) = feed.next() else { break };
+            let Item { key, body: mut entry } = item?;
+            entry["cursor"] = key.cursor().into();
+            items.push(entry);
+            last = Some(key);
+        }
+        let more = feed.peek().is_some();
+        Ok(serde_json::json!({
+            "items": items,
+            "next_cursor": last.filter(|_| more).map(|key| key.cursor())
+        }))
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(body))
+}
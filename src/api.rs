@@ -0,0 +1,223 @@
+//! The HTTP API's routes, mounted under `/api/v1` and, for clients written
+//! before versioning, under the deprecated unversioned `/api` prefix. Both
+//! mounts are registered by `routes`, so they always share handlers.
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue, LINK};
+use actix_web::{web, HttpResponse};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::borrow::Cow;
+
+use crate::{
+    activity, approvals, audit, auth, branch_search, bulk, bundle, capabilities, checklist, conflicts, console, drafts, events, export, extract, gitignore, history, jobs,
+    lint, notifications, remotes, render, repair, runbook, scheduler, server_log, sessions, trash, workspace, workspace_env,
+};
+
+/// Prefix of the current API version
+pub const V1_PREFIX: &str = "/api/v1";
+
+/// Prefix of the unversioned aliases
+pub const LEGACY_PREFIX: &str = "/api";
+
+/// API versions this server answers, oldest first
+pub const SUPPORTED_VERSIONS: &[&str] = &["v1"];
+
+/// `path` with a version prefix replaced by the unversioned one, so path
+/// rules (auth exemptions, scopes) are written once for both mounts
+pub fn unversioned_path(path: &str) -> Cow<'_, str> {
+    match path.strip_prefix(V1_PREFIX) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => Cow::Owned(format!("{}{}", LEGACY_PREFIX, rest)),
+        _ => Cow::Borrowed(path),
+    }
+}
+
+/// Every API route, relative to the prefix it is mounted under
+pub fn routes(max_save_bytes: usize) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.route("/version", web::get().to(version))
+            .route("/capabilities", web::get().to(capabilities::get_capabilities))
+            // Console log forwarding
+            .route("/console", web::post().to(console::console_log_handler))
+            .route("/console", web::get().to(console::list_console_entries))
+            .route("/console", web::delete().to(console::clear_console_entries))
+            // Auth endpoints
+            .route("/auth/check", web::get().to(auth::auth_check_handler))
+            .route("/auth/rotate", web::post().to(auth::rotate_token))
+            .route("/auth/tokens", web::get().to(auth::list_tokens))
+            .route("/auth/tokens", web::post().to(auth::create_token))
+            .route("/auth/tokens/{name}", web::patch().to(auth::update_token))
+            .route("/auth/tokens/{name}", web::delete().to(auth::revoke_token))
+            .route("/audit", web::get().to(audit::list_audit_entries))
+            .route("/admin/logs", web::get().to(server_log::list_logs))
+            .route("/admin/logs/stream", web::get().to(server_log::stream_logs))
+            .route("/admin/export", web::get().to(bundle::export_bundle))
+            .route("/admin/workspaces/bulk", web::post().to(bulk::start_bulk))
+            .route("/admin/workspaces/bulk/{batch_id}", web::get().to(bulk::get_bulk))
+            // Server-sent events
+            .route("/events", web::get().to(events::stream_events))
+            // Background jobs
+            .route("/jobs", web::get().to(jobs::list_jobs))
+            .route("/jobs/{id}", web::get().to(jobs::get_job))
+            .route("/jobs/{id}/cancel", web::post().to(jobs::cancel_job))
+            .route("/sessions", web::get().to(sessions::list_sessions))
+            .route("/sessions/{id}", web::delete().to(sessions::terminate_session))
+            .route("/sessions/{id}/buffer", web::get().to(sessions::read_buffer))
+            .route("/sessions/{id}/buffer/search", web::get().to(sessions::search_buffer))
+            .route("/approvals", web::post().to(approvals::create_approval))
+            .route("/notifications/status", web::get().to(notifications::notifications_status))
+            // Scheduled runs
+            .route("/schedules", web::get().to(scheduler::list_schedules))
+            .route("/schedules", web::post().to(scheduler::create_schedule))
+            .route("/schedules/{id}", web::delete().to(scheduler::delete_schedule))
+            .route("/schedules/{id}/run", web::post().to(scheduler::run_schedule))
+            .route("/executions/{id}", web::get().to(history::get_execution))
+            // Workspace endpoints
+            .route("/workspaces", web::get().to(workspace::list_workspaces))
+            .route("/workspaces", web::post().to(workspace::create_workspace))
+            .route("/workspaces/{name}", web::get().to(workspace::get_workspace))
+            .route("/workspaces/{name}", web::patch().to(workspace::update_workspace))
+            .route("/workspaces/{name}/rename", web::post().to(workspace::rename_workspace))
+            .route("/workspaces/{name}/verify", web::post().to(workspace::verify_workspace))
+            .route("/workspaces/{name}/fetch", web::post().to(workspace::fetch_workspace))
+            .route("/workspaces/{name}/repair", web::post().to(repair::repair_workspace))
+            .route("/workspaces/{name}/search", web::get().to(branch_search::search_branches))
+            .route("/workspaces/{name}/executions", web::get().to(history::list_executions))
+            .route("/workspaces/{name}/env", web::get().to(workspace_env::get_env))
+            .route("/workspaces/{name}/env", web::put().to(workspace_env::update_env))
+            .route("/workspaces/{name}/remotes", web::get().to(remotes::list_remotes))
+            .route("/workspaces/{name}/remotes", web::post().to(remotes::add_remote))
+            .route("/workspaces/{name}/remotes/{remote}", web::delete().to(remotes::remove_remote))
+            .route("/workspaces/{name}", web::delete().to(workspace::delete_workspace))
+            // Branch endpoints
+            .route("/workspaces/{name}/branches", web::get().to(workspace::list_branches))
+            .route("/workspaces/{name}/branches", web::post().to(workspace::create_branch))
+            .route("/workspaces/{name}/branches/{branch}", web::delete().to(workspace::delete_branch))
+            // File endpoints
+            .route("/workspaces/{name}/branches/{branch}/status", web::get().to(workspace::branch_status))
+            .route("/workspaces/{name}/branches/{branch}/files", web::get().to(workspace::list_files))
+            .service(
+                web::resource("/workspaces/{name}/branches/{branch}/file")
+                    .app_data(extract::json_config(max_save_bytes))
+                    .app_data(extract::payload_config(max_save_bytes))
+                    .route(web::get().to(workspace::read_file))
+                    .route(web::put().to(workspace::save_file))
+                    .route(web::post().to(workspace::create_file))
+                    .route(web::patch().to(workspace::patch_file))
+                    .route(web::delete().to(workspace::delete_file)),
+            )
+            .service(
+                web::resource("/workspaces/{name}/branches/{branch}/file/draft")
+                    .app_data(extract::json_config(max_save_bytes))
+                    .route(web::get().to(drafts::get_draft))
+                    .route(web::put().to(drafts::save_draft))
+                    .route(web::delete().to(drafts::discard_draft)),
+            )
+            .service(
+                web::resource("/workspaces/{name}/branches/{branch}/file/raw")
+                    .route(web::get().to(workspace::read_raw_file))
+                    .route(web::put().to(workspace::save_raw_file)),
+            )
+            .service(
+                web::resource("/workspaces/{name}/branches/{branch}/file/outline")
+                    .app_data(extract::json_config(max_save_bytes))
+                    .route(web::get().to(workspace::file_outline))
+                    .route(web::post().to(workspace::content_outline)),
+            )
+            .service(
+                web::resource("/workspaces/{name}/branches/{branch}/file/render")
+                    .app_data(extract::json_config(max_save_bytes))
+                    .route(web::get().to(render::render_file))
+                    .route(web::post().to(render::render_content)),
+            )
+            .route("/workspaces/{name}/branches/{branch}/file/diff", web::get().to(workspace::file_diff))
+            .route("/workspaces/{name}/branches/{branch}/file/restore", web::post().to(workspace::restore_file))
+            .route("/workspaces/{name}/branches/{branch}/trash", web::get().to(trash::list_trash))
+            .route("/workspaces/{name}/branches/{branch}/trash/restore", web::post().to(trash::restore_item))
+            .route("/workspaces/{name}/branches/{branch}/trash/purge", web::post().to(trash::purge_trash))
+            .route("/workspaces/{name}/branches/{branch}/blocks", web::get().to(workspace::list_blocks))
+            .route("/workspaces/{name}/branches/{branch}/blocks/run", web::post().to(runbook::run_block))
+            .route("/workspaces/{name}/branches/{branch}/export", web::get().to(export::export_runbook))
+            .route("/workspaces/{name}/branches/{branch}/checklist", web::get().to(checklist::get_checklist))
+            .route("/workspaces/{name}/branches/{branch}/checklist", web::put().to(checklist::toggle_step))
+            .route("/workspaces/{name}/branches/{branch}/lint", web::get().to(lint::lint))
+            .route("/workspaces/{name}/branches/{branch}/activity", web::get().to(activity::branch_activity))
+            .route("/workspaces/{name}/branches/{branch}/search", web::get().to(workspace::search_files))
+            .route("/workspaces/{name}/branches/{branch}/archive", web::get().to(workspace::download_archive))
+            // Git operation endpoints
+            .route("/workspaces/{name}/branches/{branch}/commit", web::post().to(workspace::commit_files))
+            .route("/workspaces/{name}/branches/{branch}/commit/template", web::get().to(workspace::commit_template))
+            .route("/workspaces/{name}/branches/{branch}/push", web::post().to(workspace::push_branch))
+            .route("/workspaces/{name}/branches/{branch}/pull", web::post().to(workspace::pull_branch))
+            .route("/workspaces/{name}/branches/{branch}/rebase", web::post().to(workspace::rebase_branch))
+            .service(
+                web::resource("/workspaces/{name}/branches/{branch}/ignore")
+                    .route(web::get().to(gitignore::get_ignore))
+                    .route(web::put().to(gitignore::update_ignore)),
+            )
+            .route("/workspaces/{name}/branches/{branch}/conflicts", web::get().to(conflicts::list_conflicts))
+            .route("/workspaces/{name}/branches/{branch}/conflicts/file", web::get().to(conflicts::get_conflict_file))
+            .route("/workspaces/{name}/branches/{branch}/conflicts/resolve", web::post().to(conflicts::resolve_conflict))
+            .route("/workspaces/{name}/branches/{branch}/checkout", web::post().to(workspace::change_base_branch))
+            .route("/workspaces/{name}/branches/{branch}/rename", web::post().to(workspace::rename_branch));
+    }
+}
+
+/// GET /api/version - Server version and the API versions it supports
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "api_versions": SUPPORTED_VERSIONS,
+        "current_api_version": SUPPORTED_VERSIONS[SUPPORTED_VERSIONS.len() - 1]
+    }))
+}
+
+/// Middleware for the unversioned mount: marks every response with a
+/// `Deprecation` header and links to the same route under `/api/v1`
+pub struct Deprecated;
+
+impl<S, B> Transform<S, ServiceRequest> for Deprecated
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = DeprecatedMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DeprecatedMiddleware { service }))
+    }
+}
+
+pub struct DeprecatedMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for DeprecatedMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let rest = req.path().strip_prefix(LEGACY_PREFIX).unwrap_or_default();
+        let successor = format!("<{}{}>; rel=\"successor-version\"", V1_PREFIX, rest);
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let headers = res.headers_mut();
+            headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+            if let Ok(link) = HeaderValue::from_str(&successor) {
+                headers.insert(LINK, link);
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,214 @@
+//! Approvals for dangerous runbook steps. A code block with the
+//! `requires_approval` attribute only runs with an approval token minted for
+//! that exact code by a different token than the one running it. Approvals
+//! are kept in memory, expire after `approval_ttl_secs` and are used up by
+//! the run they allow.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth;
+use crate::blocks::{self, CodeBlock};
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::workspace;
+use crate::AppState;
+
+/// Block attribute that puts a block behind an approval
+pub const REQUIRES_APPROVAL: &str = "requires_approval";
+
+/// Whether `block` may only run with an approval
+pub fn required(block: &CodeBlock) -> bool {
+    block
+        .attributes
+        .get(REQUIRES_APPROVAL)
+        .is_some_and(|value| value != "false")
+}
+
+/// Who approved running which block
+#[derive(Debug, Clone, Serialize)]
+pub struct Approval {
+    pub approval_token: String,
+    pub approver: String,
+    pub workspace: String,
+    pub branch: String,
+    pub path: String,
+    pub block_index: usize,
+    /// SHA-256 of the approved code; an edited block needs a new approval
+    pub block_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The block a run is for, to match against an approval
+pub struct ApprovalRequest<'a> {
+    pub workspace: &'a str,
+    pub branch: &'a str,
+    pub path: &'a str,
+    pub block_index: usize,
+    pub block_hash: &'a str,
+    /// Token running the block
+    pub runner: Option<&'a str>,
+}
+
+impl ApprovalRequest<'_> {
+    /// 403 telling the client to get an approval for this block
+    fn refused(&self, reason: &str, message: impl Into<String>) -> ApiError {
+        ApiError::forbidden("approval_required", message).with_details(serde_json::json!({
+            "reason": reason,
+            "workspace": self.workspace,
+            "branch": self.branch,
+            "path": self.path,
+            "block_index": self.block_index,
+            "block_hash": self.block_hash,
+        }))
+    }
+}
+
+/// Approvals not yet used or expired, by token
+pub struct Approvals {
+    approvals: Mutex<HashMap<String, Approval>>,
+}
+
+impl Approvals {
+    pub fn new() -> Self {
+        Self {
+            approvals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn grant(&self, approval: Approval) {
+        let mut approvals = self.approvals.lock().unwrap();
+        let now = Utc::now();
+        approvals.retain(|_, approval| approval.expires_at > now);
+        approvals.insert(approval.approval_token.clone(), approval);
+    }
+
+    /// Use up the approval `token` for the run described by `request`; 403
+    /// `approval_required` when there is no token or it doesn't allow the run
+    pub fn take(&self, token: Option<&str>, request: &ApprovalRequest) -> Result<Approval, ApiError> {
+        self.verify(token, request, true)
+    }
+
+    /// `take` without using the approval up, for dry runs
+    pub fn check(&self, token: Option<&str>, request: &ApprovalRequest) -> Result<Approval, ApiError> {
+        self.verify(token, request, false)
+    }
+
+    fn verify(&self, token: Option<&str>, request: &ApprovalRequest, consume: bool) -> Result<Approval, ApiError> {
+        let Some(token) = token else {
+            let message = format!("Block {} of '{}' requires an approval", request.block_index, request.path);
+            return Err(request.refused("missing", message));
+        };
+        let unknown = || request.refused("unknown", "Approval token is unknown, expired or already used");
+        let mut approvals = self.approvals.lock().unwrap();
+        let Some(approval) = approvals.get(token) else {
+            return Err(unknown());
+        };
+        if approval.expires_at <= Utc::now() {
+            approvals.remove(token);
+            return Err(unknown());
+        }
+        let matches = approval.workspace == request.workspace
+            && approval.branch == request.branch
+            && approval.path == request.path
+            && approval.block_index == request.block_index
+            && approval.block_hash == request.block_hash;
+        if !matches {
+            return Err(request.refused("mismatch", "Approval token was granted for a different block"));
+        }
+        if request.runner == Some(approval.approver.as_str()) {
+            return Err(request.refused("self_approved", "A block can't be run with an approval its runner granted"));
+        }
+        if !consume {
+            return Ok(approval.clone());
+        }
+        Ok(approvals.remove(token).unwrap())
+    }
+}
+
+impl Default for Approvals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApprovalRequest {
+    pub workspace: String,
+    pub branch: String,
+    pub path: String,
+    pub block_index: usize,
+    /// `block_hash` of the block as the approver reviewed it
+    pub block_hash: String,
+}
+
+/// POST /api/approvals - Approve running a block once, for another token
+pub async fn create_approval(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+    body: Json<CreateApprovalRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    auth::require_workspace_access(&req, &body.workspace)?;
+    let worktree_path = workspace::require_worktree(&config, &body.workspace, &body.branch)?;
+    let content = workspace::read_text_file(&config, &worktree_path, &body.path)?;
+    let runbook = blocks::parse(&content);
+    let Some(block) = runbook.blocks.get(body.block_index) else {
+        return Err(ApiError::not_found(
+            "block_not_found",
+            format!("'{}' has no code block {}", body.path, body.block_index),
+        )
+        .with_details(serde_json::json!({ "block_count": runbook.blocks.len() })));
+    };
+    let block_hash = blocks::content_hash(&block.code);
+    if block_hash != body.block_hash {
+        return Err(ApiError::conflict(
+            "block_changed",
+            format!("Block {} of '{}' changed since it was reviewed", body.block_index, body.path),
+        )
+        .with_details(serde_json::json!({ "block_hash": block_hash })));
+    }
+    let Some(approver) = audit::Actor::from_request(&req).token else {
+        return Err(ApiError::forbidden("approval_required", "Approvals must be granted by a named token"));
+    };
+
+    let now = Utc::now();
+    let ttl = chrono::Duration::from_std(config.get_approval_ttl()).unwrap_or(chrono::Duration::MAX);
+    let approval = Approval {
+        approval_token: Uuid::new_v4().to_string(),
+        approver,
+        workspace: body.workspace,
+        branch: body.branch,
+        path: body.path,
+        block_index: body.block_index,
+        block_hash,
+        created_at: now,
+        expires_at: now.checked_add_signed(ttl).unwrap_or(DateTime::<Utc>::MAX_UTC),
+    };
+    log::info!(
+        "{} approved block {} of {} in {}/{}",
+        approval.approver, approval.block_index, approval.path, approval.workspace, approval.branch
+    );
+    audit::record(
+        &req,
+        "approval.create",
+        serde_json::json!({
+            "workspace": approval.workspace,
+            "branch": approval.branch,
+            "path": approval.path,
+            "block_index": approval.block_index,
+            "block_hash": approval.block_hash,
+            "expires_at": approval.expires_at,
+        }),
+    );
+    state.approvals.grant(approval.clone());
+    Ok(HttpResponse::Created().json(approval))
+}
@@ -0,0 +1,138 @@
+//! The web UI's files: served from a directory on disk or, in builds with
+//! the `embed-assets` feature, from copies compiled into the binary so it
+//! runs from anywhere.
+
+use actix_files::Files;
+use actix_web::web;
+use std::path::{Path, PathBuf};
+
+/// Where the web UI is served from
+#[derive(Debug, Clone)]
+pub enum Source {
+    Directory(PathBuf),
+    #[cfg(feature = "embed-assets")]
+    Embedded,
+}
+
+impl Source {
+    /// The configured directory if there is one, else the embedded files if
+    /// built in, else the first candidate directory holding an `index.html`
+    pub fn resolve(configured: Option<&Path>) -> Self {
+        if let Some(dir) = configured {
+            return Source::Directory(dir.to_path_buf());
+        }
+        #[cfg(feature = "embed-assets")]
+        {
+            Source::Embedded
+        }
+        #[cfg(not(feature = "embed-assets"))]
+        {
+            let candidates = default_dirs();
+            let found = candidates
+                .iter()
+                .find(|dir| dir.join("index.html").is_file())
+                .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.clone()));
+            // Report the first candidate if none has the UI
+            Source::Directory(found.unwrap_or_else(|| candidates[0].clone()))
+        }
+    }
+
+    /// Log where the UI is served from, or a prominent error if it can't be
+    pub fn check(&self) {
+        match self {
+            Source::Directory(dir) if !dir.join("index.html").is_file() => {
+                let problem = if dir.is_dir() { "has no index.html" } else { "does not exist" };
+                log::error!("===========================================");
+                log::error!("  Web UI not found: {:?} {}", dir, problem);
+                log::error!("  Every page will be a 404; pass --static-dir");
+                log::error!("===========================================");
+            }
+            Source::Directory(dir) => log::info!("Serving web UI from {:?}", dir),
+            #[cfg(feature = "embed-assets")]
+            Source::Embedded => log::info!("Serving web UI embedded in the binary"),
+        }
+    }
+}
+
+/// `static/` next to the executable, in the project a `cargo run`
+/// executable (`target/<profile>/runotepad`) was built in, and in the
+/// working directory
+#[cfg(not(feature = "embed-assets"))]
+fn default_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(exe_dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+        dirs.push(exe_dir.join("static"));
+        if let Some(project) = exe_dir.parent().and_then(Path::parent) {
+            dirs.push(project.join("static"));
+        }
+    }
+    dirs.push(PathBuf::from("static"));
+    dirs
+}
+
+/// Whether a file name carries a content hash (`bundle.3f9a1c2e.js`), so
+/// its content never changes and it can be cached for good
+pub fn is_hashed_name(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let mut parts: Vec<&str> = name.split(['.', '-']).collect();
+    // The extension never counts
+    parts.pop();
+    parts
+        .iter()
+        .skip(1)
+        .any(|part| part.len() >= 8 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Register the catch-all route serving the UI; must come after every
+/// other route
+pub fn configure(source: &Source) -> impl FnOnce(&mut web::ServiceConfig) {
+    let source = source.clone();
+    move |cfg| match source {
+        Source::Directory(dir) => {
+            cfg.service(Files::new("/", dir).index_file("index.html"));
+        }
+        #[cfg(feature = "embed-assets")]
+        Source::Embedded => {
+            cfg.route("/{path:.*}", web::get().to(embedded::serve));
+        }
+    }
+}
+
+#[cfg(feature = "embed-assets")]
+mod embedded {
+    use actix_web::http::header;
+    use actix_web::{HttpRequest, HttpResponse};
+
+    #[derive(rust_embed::RustEmbed)]
+    #[folder = "static/"]
+    struct Assets;
+
+    /// GET /{path} - A file of the embedded UI, with an ETag from its hash
+    pub async fn serve(req: HttpRequest) -> HttpResponse {
+        let path = req.match_info().query("path");
+        let path = if path.is_empty() || path.ends_with('/') {
+            format!("{}index.html", path)
+        } else {
+            path.to_string()
+        };
+        let Some(file) = Assets::get(&path) else {
+            return HttpResponse::NotFound().finish();
+        };
+
+        let hash: String = file.metadata.sha256_hash().iter().map(|b| format!("{:02x}", b)).collect();
+        let etag = crate::caching::etag(&hash);
+        let not_modified = crate::caching::is_fresh(&req, &etag);
+
+        let mut resp = if not_modified {
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+        resp.insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, crate::caching::ui_cache_control(&path)));
+        if not_modified {
+            return resp.body(actix_web::body::None::new());
+        }
+        resp.content_type(file.metadata.mimetype()).body(file.data.into_owned())
+    }
+}
@@ -0,0 +1,231 @@
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::TokenIdentity;
+use crate::errors::ApiError;
+use crate::file_ops;
+use crate::logging;
+
+/// How often the writer thread syncs the log to disk while entries arrive
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 1000;
+
+/// One line of the audit log. `summary` describes the request (names,
+/// paths, messages) and never carries file contents.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub token: Option<String>,
+    pub ip: Option<String>,
+    pub action: String,
+    pub route: String,
+    pub summary: serde_json::Value,
+}
+
+/// Append-only JSONL audit log. Entries are handed to a writer thread so
+/// request handlers never wait on the disk; the thread syncs at most once
+/// per `SYNC_INTERVAL`.
+pub struct AuditLog {
+    path: PathBuf,
+    sender: Mutex<mpsc::Sender<AuditEntry>>,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || write_entries(file, receiver));
+
+        Ok(Self {
+            path,
+            sender: Mutex::new(sender),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        if self.sender.lock().unwrap().send(entry).is_err() {
+            log::error!("Audit log writer has stopped; entry dropped");
+        }
+    }
+
+    pub fn record_as(&self, actor: &Actor, action: &str, route: String, summary: serde_json::Value) {
+        self.record(AuditEntry {
+            timestamp: Utc::now(),
+            token: actor.token.clone(),
+            ip: actor.ip.clone(),
+            action: action.to_string(),
+            route,
+            summary,
+        });
+    }
+
+    /// Entries strictly after `since` (oldest first, up to `limit`), or the
+    /// latest `limit` entries when `since` is not given
+    pub fn read(&self, since: Option<DateTime<Utc>>, limit: usize) -> std::io::Result<Vec<AuditEntry>> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = VecDeque::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            // A line still being appended can't be parsed yet; skip it
+            let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+                continue;
+            };
+            match since {
+                Some(since) if entry.timestamp <= since => continue,
+                Some(_) => {
+                    entries.push_back(entry);
+                    if entries.len() == limit {
+                        break;
+                    }
+                }
+                None => {
+                    entries.push_back(entry);
+                    if entries.len() > limit {
+                        entries.pop_front();
+                    }
+                }
+            }
+        }
+        Ok(entries.into())
+    }
+
+    /// Entries from the newest back, each with its offset in the log (which
+    /// is only appended to, so offsets identify entries), read lazily
+    pub fn newest_first(&self) -> std::io::Result<impl Iterator<Item = std::io::Result<(u64, AuditEntry)>>> {
+        let lines = match file_ops::ReverseLines::open(&self.path) {
+            Ok(lines) => Some(lines),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(lines.into_iter().flatten().filter_map(|line| match line {
+            // A line still being appended can't be parsed yet; skip it
+            Ok((offset, line)) => serde_json::from_str(&line).ok().map(|entry| Ok((offset, entry))),
+            Err(e) => Some(Err(e)),
+        }))
+    }
+}
+
+fn write_entries(mut file: File, receiver: mpsc::Receiver<AuditEntry>) {
+    let mut last_sync = Instant::now();
+    let mut unsynced = false;
+    loop {
+        match receiver.recv_timeout(SYNC_INTERVAL) {
+            Ok(entry) => {
+                let mut line = match serde_json::to_string(&entry) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        log::error!("Failed to serialize audit entry: {}", e);
+                        continue;
+                    }
+                };
+                line.push('\n');
+                // One write per line so readers never see half an entry
+                if let Err(e) = file.write_all(line.as_bytes()) {
+                    log::error!("Failed to write audit log: {}", e);
+                }
+                unsynced = true;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        if unsynced && last_sync.elapsed() >= SYNC_INTERVAL {
+            if let Err(e) = file.sync_data() {
+                log::error!("Failed to sync audit log: {}", e);
+            }
+            unsynced = false;
+            last_sync = Instant::now();
+        }
+    }
+    let _ = file.sync_data();
+}
+
+/// Who is acting, captured from the request so it can outlive it (the
+/// WebSocket handler records after the upgrade)
+#[derive(Debug, Clone)]
+pub struct Actor {
+    pub token: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl Actor {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self {
+            token: req.extensions().get::<TokenIdentity>().map(|t| t.name.clone()),
+            ip: req.peer_addr().map(|a| a.ip().to_string()),
+        }
+    }
+}
+
+/// Record a mutating operation performed by `req`
+pub fn record(req: &HttpRequest, action: &str, summary: serde_json::Value) {
+    Recorder::from_request(req).record(action, summary);
+}
+
+/// `record` for operations that complete after their request, such as
+/// background jobs: captures the actor and route up front
+#[derive(Clone)]
+pub struct Recorder {
+    audit: Option<Arc<AuditLog>>,
+    actor: Actor,
+    route: String,
+}
+
+impl Recorder {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self {
+            audit: req.app_data::<web::Data<Arc<AuditLog>>>().map(|a| a.get_ref().clone()),
+            actor: Actor::from_request(req),
+            route: format!("{} {}", req.method(), req.path()),
+        }
+    }
+
+    pub fn record(&self, action: &str, summary: serde_json::Value) {
+        match &self.audit {
+            Some(audit) => audit.record_as(&self.actor, action, self.route.clone(), summary),
+            None => log::error!("Audit log is not registered; {} not recorded", action),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub limit: Option<usize>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// GET /api/audit - Page through the audit log
+pub async fn list_audit_entries(
+    audit: web::Data<Arc<AuditLog>>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT).clamp(1, MAX_AUDIT_LIMIT);
+    let since = query.since;
+
+    let audit = audit.get_ref().clone();
+    let entries = logging::block(move || audit.read(since, limit))
+        .await?
+        .map_err(|e| ApiError::io("Failed to read audit log", e))?;
+    Ok(HttpResponse::Ok().json(entries))
+}
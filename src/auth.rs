@@ -1,64 +1,206 @@
-use actix_web::{dev::ServiceRequest, HttpRequest, HttpResponse};
-use std::sync::Arc;
-
-use crate::config::ConfigManager;
-
-/// Extract token from HttpRequest (query param or Authorization header)
-pub fn extract_token_from_request(req: &HttpRequest) -> Option<String> {
-    // Try query parameter first: ?token=xxx
-    if let Some(token) = req.query_string().split('&').find_map(|pair| {
-        let mut parts = pair.splitn(2, '=');
-        let key = parts.next()?;
-        let value = parts.next()?;
-        if key == "token" {
-            Some(value.to_string())
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api;
+use crate::audit;
+use crate::blocks;
+use crate::config::{self, ConfigManager, TokenIdentity, TokenScope};
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::unix_socket;
+use crate::AppState;
+
+/// Prefix of the WebSocket subprotocol a token can be offered as
+pub const TOKEN_PROTOCOL_PREFIX: &str = "runotepad-token.";
+/// Subprotocol the WebSocket handshake accepts
+pub const WS_PROTOCOL: &str = "runotepad";
+
+/// Failed attempts allowed from one address within `AUTH_FAILURE_WINDOW`
+const MAX_AUTH_FAILURES: u32 = 10;
+const AUTH_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// Prune expired entries once the table grows past this many addresses
+const AUTH_LIMITER_PRUNE_AT: usize = 1024;
+
+struct FailureWindow {
+    count: u32,
+    started: Instant,
+}
+
+/// Tracks failed token checks per source address so brute-forcing gets
+/// throttled. Windows are fixed and expire on their own.
+pub struct AuthLimiter {
+    failures: Mutex<HashMap<IpAddr, FailureWindow>>,
+}
+
+impl AuthLimiter {
+    pub fn new() -> Self {
+        Self {
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Time left until `ip` may try again, if it is currently locked out
+    pub fn retry_after(&self, ip: IpAddr) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let window = failures.get(&ip)?;
+        let elapsed = window.started.elapsed();
+        if window.count >= MAX_AUTH_FAILURES && elapsed < AUTH_FAILURE_WINDOW {
+            Some(AUTH_FAILURE_WINDOW - elapsed)
         } else {
             None
         }
-    }) {
-        return Some(token);
     }
 
-    // Try Authorization header: Bearer xxx
-    if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                return Some(token.to_string());
-            }
+    pub fn record_failure(&self, ip: IpAddr) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() > AUTH_LIMITER_PRUNE_AT {
+            failures.retain(|_, w| w.started.elapsed() < AUTH_FAILURE_WINDOW);
+        }
+
+        let window = failures.entry(ip).or_insert(FailureWindow {
+            count: 0,
+            started: Instant::now(),
+        });
+        if window.started.elapsed() >= AUTH_FAILURE_WINDOW {
+            window.count = 0;
+            window.started = Instant::now();
+        }
+        window.count += 1;
+        if window.count == MAX_AUTH_FAILURES {
+            log::warn!(
+                "Too many failed authentication attempts from {}, blocking for {}s",
+                ip,
+                AUTH_FAILURE_WINDOW.as_secs()
+            );
         }
     }
+}
 
-    None
+impl Default for AuthLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn limiter_and_ip(req: &HttpRequest) -> Option<(web::Data<Arc<AppState>>, IpAddr)> {
+    let state = req.app_data::<web::Data<Arc<AppState>>>()?.clone();
+    let ip = unix_socket::peer_ip(req)?;
+    Some((state, ip))
+}
+
+/// 429 if the caller's address is locked out after repeated failures
+pub fn check_rate_limit(req: &HttpRequest) -> Result<(), ApiError> {
+    let Some((state, ip)) = limiter_and_ip(req) else {
+        return Ok(());
+    };
+    match state.auth_limiter.retry_after(ip) {
+        Some(wait) => Err(ApiError::too_many_requests("Too many failed authentication attempts", wait)),
+        None => Ok(()),
+    }
 }
 
-/// Check auth from HttpRequest - returns Ok(()) if valid, Err(HttpResponse) if not
-pub fn check_auth(req: &HttpRequest, config: &Arc<ConfigManager>) -> Result<(), HttpResponse> {
-    match extract_token_from_request(req) {
-        Some(token) if config.verify_token(&token) => Ok(()),
-        Some(_) => Err(HttpResponse::Unauthorized().json(serde_json::json!({
-            "error": "Invalid token"
-        }))),
-        None => Err(HttpResponse::Unauthorized().json(serde_json::json!({
-            "error": "Authentication required",
-            "hint": "Provide token via ?token=xxx or Authorization: Bearer xxx"
-        }))),
+/// Note a rejected token from the caller's address
+pub fn record_auth_failure(req: &HttpRequest) {
+    if let Some((state, ip)) = limiter_and_ip(req) {
+        state.auth_limiter.record_failure(ip);
     }
 }
 
-/// Extract token from ServiceRequest (query param or Authorization header)
-pub fn extract_token(req: &ServiceRequest) -> Option<String> {
-    // Try query parameter first: ?token=xxx
-    if let Some(token) = req.query_string().split('&').find_map(|pair| {
-        let mut parts = pair.splitn(2, '=');
-        let key = parts.next()?;
-        let value = parts.next()?;
+/// Check a token presented with `req`, applying the failure rate limit;
+/// returns the token's identity, or `None` if it isn't valid
+pub fn verify_with_limit(
+    req: &HttpRequest,
+    config: &ConfigManager,
+    token: &str,
+) -> Result<Option<TokenIdentity>, ApiError> {
+    check_rate_limit(req)?;
+    let identity = config.verify_token(token);
+    if identity.is_none() {
+        record_auth_failure(req);
+    }
+    Ok(identity)
+}
+
+/// Token offered as a `runotepad-token.<token>` WebSocket subprotocol, the
+/// one credential a browser can send with a WebSocket connect other than
+/// the URL
+fn token_from_protocols(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|protocol| protocol.trim().strip_prefix(TOKEN_PROTOCOL_PREFIX))
+        .map(str::to_string)
+}
+
+/// Whether the client offered the `runotepad` subprotocol, or a token as
+/// one, and so expects the handshake to accept `runotepad`
+pub fn offers_ws_protocol(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|protocol| protocol == WS_PROTOCOL || protocol.starts_with(TOKEN_PROTOCOL_PREFIX))
+}
+
+/// `headers` for logging, with credentials left out
+pub fn redacted_headers(headers: &header::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = value.to_str().unwrap_or("<binary>");
+            let value = if name == header::AUTHORIZATION || name == header::COOKIE {
+                "<redacted>".to_string()
+            } else if name == header::SEC_WEBSOCKET_PROTOCOL {
+                value
+                    .split(',')
+                    .map(|protocol| match protocol.trim().strip_prefix(TOKEN_PROTOCOL_PREFIX) {
+                        Some(_) => format!("{}<redacted>", TOKEN_PROTOCOL_PREFIX),
+                        None => protocol.trim().to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            } else {
+                value.to_string()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Extract the token of a request: on `/ws` from a `runotepad-token.`
+/// subprotocol first, then the `?token=` query parameter (unless
+/// `ws_query_token` is off, for `/ws`), then an `Authorization: Bearer` header
+pub fn extract_token_from_request(req: &HttpRequest, config: &ConfigManager) -> Option<String> {
+    let websocket = api::unversioned_path(req.path()) == "/ws";
+    if websocket {
+        if let Some(token) = token_from_protocols(req) {
+            return Some(token);
+        }
+    }
+
+    // Then the query parameter: ?token=xxx
+    let query = req.query_string().split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
         if key == "token" {
             Some(value.to_string())
         } else {
             None
         }
-    }) {
-        return Some(token);
+    });
+    if let Some(token) = query {
+        if !websocket || config.ws_query_token() {
+            return Some(token);
+        }
+        log::warn!("Ignoring ?token= on /ws; ws_query_token is off");
     }
 
     // Try Authorization header: Bearer xxx
@@ -73,19 +215,30 @@ pub fn extract_token(req: &ServiceRequest) -> Option<String> {
     None
 }
 
-/// Check if a path requires authentication
-pub fn requires_auth(path: &str) -> bool {
-    // API endpoints require auth (except auth check)
+/// Check if a path requires authentication. This is the only list of
+/// exemptions; `RequireAuth` enforces it for every route.
+pub fn requires_auth(method: &Method, path: &str, config: &ConfigManager) -> bool {
+    let path = &*api::unversioned_path(path);
+
+    // API endpoints require auth (except auth check and version)
     if path.starts_with("/api/") {
         // Allow unauthenticated access to auth check endpoint
-        if path == "/api/auth/check" {
+        if path == "/api/auth/check" || path == "/api/version" {
+            return false;
+        }
+        // Browser log forwarding can be opened up for local development;
+        // reading the forwarded entries back can't
+        if path == "/api/console" && method == Method::POST && config.allow_unauthenticated_console() {
+            return false;
+        }
+        if path == "/api/capabilities" && config.allow_unauthenticated_capabilities() {
             return false;
         }
         return true;
     }
 
-    // WebSocket requires auth
-    if path == "/ws" {
+    // WebSocket and the raw worktree files require auth
+    if path == "/ws" || path.starts_with("/raw/") {
         return true;
     }
 
@@ -93,32 +246,224 @@ pub fn requires_auth(path: &str) -> bool {
     false
 }
 
-/// Verify token and return error response if invalid
+/// Scope a token needs for a request. Reads are open to `read` tokens;
+/// anything that changes state, and terminals over `/ws`, need `read_write`.
+/// `None` if the path needs no authentication at all.
+pub fn required_scope(method: &Method, path: &str, config: &ConfigManager) -> Option<TokenScope> {
+    if !requires_auth(method, path, config) {
+        return None;
+    }
+
+    // Browser log forwarding doesn't change anything
+    if path == "/api/console" && method == Method::POST {
+        return Some(TokenScope::Read);
+    }
+
+    // The audit log, the browser console entries and the server log reveal
+    // who did what, so they are kept from read-only tokens
+    if path == "/api/audit" || path == "/api/console" || path.starts_with("/api/admin/") {
+        return Some(TokenScope::ReadWrite);
+    }
+
+    // Terminal output is for those who may open terminals
+    if path.starts_with("/api/sessions/") && (path.ends_with("/buffer") || path.ends_with("/buffer/search")) {
+        return Some(TokenScope::ReadWrite);
+    }
+
+    if path != "/ws" && (method == Method::GET || method == Method::HEAD) {
+        Some(TokenScope::Read)
+    } else {
+        Some(TokenScope::ReadWrite)
+    }
+}
+
+/// Requests refused in read-only mode. `/ws` is a GET and stays open; its
+/// handler refuses terminals instead.
+fn changes_state(method: &Method, path: &str) -> bool {
+    method != Method::GET && method != Method::HEAD && !(method == Method::POST && path == "/api/console")
+}
+
+/// Verify token and return an error if it is invalid or lacks the scope the
+/// route needs. Returns the token's identity, or `None` for exempt paths.
 pub fn verify_request(
     req: &ServiceRequest,
-    config: &Arc<ConfigManager>,
-) -> Result<(), HttpResponse> {
-    let path = req.path();
+    config: &ConfigManager,
+) -> Result<Option<TokenIdentity>, ApiError> {
+    let path = &*api::unversioned_path(req.path());
 
-    if !requires_auth(path) {
-        return Ok(());
-    }
+    let Some(required) = required_scope(req.method(), path, config) else {
+        return Ok(None);
+    };
 
-    match extract_token(req) {
-        Some(token) if config.verify_token(&token) => Ok(()),
+    let Some(token) = extract_token_from_request(req.request(), config) else {
+        log::warn!("Missing token for path: {}", req.path());
+        let hint = if path == "/ws" {
+            "Provide token via a runotepad-token.xxx subprotocol, ?token=xxx query param or Authorization: Bearer xxx header"
+        } else {
+            "Provide token via ?token=xxx query param or Authorization: Bearer xxx header"
+        };
+        return Err(ApiError::unauthorized("auth_required", "Authentication required")
+            .with_details(serde_json::json!({ "hint": hint })));
+    };
+
+    match verify_with_limit(req.request(), config, &token)? {
+        Some(_) if config.read_only() && changes_state(req.method(), path) => {
+            log::warn!("Refused {} {} in read-only mode", req.method(), req.path());
+            Err(ApiError::forbidden("read_only_mode", "Server is in read-only mode"))
+        }
+        Some(identity) if identity.scope.allows(required) => Ok(Some(identity)),
         Some(_) => {
-            log::warn!("Invalid token for path: {}", path);
-            Err(HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid token"
-            })))
+            log::warn!("Insufficient token scope for {} {}", req.method(), req.path());
+            Err(ApiError::forbidden(
+                "insufficient_scope",
+                "Token scope does not allow this operation",
+            )
+            .with_details(serde_json::json!({ "required_scope": required })))
         }
         None => {
-            log::warn!("Missing token for path: {}", path);
-            Err(HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authentication required",
-                "hint": "Provide token via ?token=xxx query param or Authorization: Bearer xxx header"
-            })))
+            log::warn!("Invalid token for path: {}", req.path());
+            Err(invalid_token())
+        }
+    }
+}
+
+/// Workspace a request path belongs to: `/api/workspaces/{name}/...` and
+/// `/raw/{name}/...`
+fn workspace_of_path(path: &str) -> Option<String> {
+    let rest = path.strip_prefix("/api/workspaces/").or_else(|| path.strip_prefix("/raw/"))?;
+    let name = rest.split('/').next().filter(|name| !name.is_empty())?;
+    Some(blocks::percent_decode(name))
+}
+
+/// Paths that reach past single workspaces: token management, the audit
+/// log, the browser console entries and the admin endpoints
+fn manages_server(method: &Method, path: &str) -> bool {
+    path.starts_with("/api/auth/tokens")
+        || path == "/api/audit"
+        || (path == "/api/console" && method != Method::POST)
+        || path.starts_with("/api/admin/")
+}
+
+pub fn workspace_forbidden(workspace: &str) -> ApiError {
+    ApiError::forbidden(
+        "workspace_forbidden",
+        format!("Token does not have access to workspace '{}'", workspace),
+    )
+    .with_details(serde_json::json!({ "workspace": workspace }))
+}
+
+/// Whether the token of `req` may use `workspace`; requests without one
+/// only reach paths that need none
+pub fn may_use_workspace(req: &HttpRequest, workspace: &str) -> bool {
+    req.extensions()
+        .get::<TokenIdentity>()
+        .is_none_or(|identity| identity.allows_workspace(workspace))
+}
+
+/// Whether the token of `req` is limited to some workspaces
+pub fn is_restricted(req: &HttpRequest) -> bool {
+    req.extensions().get::<TokenIdentity>().is_some_and(TokenIdentity::is_restricted)
+}
+
+/// 403 `workspace_forbidden` unless the token of `req` may use
+/// `workspace`. Denials go to the audit log.
+pub fn require_workspace_access(req: &HttpRequest, workspace: &str) -> Result<(), ApiError> {
+    if may_use_workspace(req, workspace) {
+        return Ok(());
+    }
+    log::warn!("Token has no access to workspace {:?}: {} {}", workspace, req.method(), req.path());
+    audit::record(req, "workspace.denied", serde_json::json!({ "workspace": workspace }));
+    Err(workspace_forbidden(workspace))
+}
+
+/// 403 `workspace_forbidden` if the token of `req` is limited to some
+/// workspaces, since `what` reaches past them. Denials go to the audit log.
+pub fn require_unrestricted(req: &HttpRequest, what: &str) -> Result<(), ApiError> {
+    if !is_restricted(req) {
+        return Ok(());
+    }
+    log::warn!("Token limited to workspaces can't {}: {} {}", what, req.method(), req.path());
+    audit::record(req, "workspace.denied", serde_json::json!({ "workspace": null, "refused": what }));
+    Err(ApiError::forbidden(
+        "workspace_forbidden",
+        format!("Token is limited to some workspaces and can't {}", what),
+    ))
+}
+
+/// The workspace limits of the token of `req`: paths of other workspaces
+/// are refused, and so is managing the server unless the token may use
+/// every workspace (it could otherwise mint itself a token without limits)
+fn check_workspace_access(req: &HttpRequest) -> Result<(), ApiError> {
+    let path = &*api::unversioned_path(req.path());
+    if let Some(workspace) = workspace_of_path(path) {
+        return require_workspace_access(req, &workspace);
+    }
+    if manages_server(req.method(), path) {
+        return require_unrestricted(req, "manage the server");
+    }
+    Ok(())
+}
+
+/// Middleware rejecting requests that `verify_request` doesn't accept, so
+/// handlers never need to check the token themselves
+pub struct RequireAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequireAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireAuthMiddleware { service }))
+    }
+}
+
+pub struct RequireAuthMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let denied = match req.app_data::<web::Data<Arc<ConfigManager>>>() {
+            Some(config) => match verify_request(&req, config) {
+                Ok(identity) => {
+                    // Handlers that care (`/ws`, the audit log) read it back out
+                    if let Some(identity) = identity {
+                        req.extensions_mut().insert(identity);
+                    }
+                    check_workspace_access(req.request()).err().map(|e| e.error_response())
+                }
+                Err(e) => Some(e.error_response()),
+            },
+            None => {
+                log::error!("RequireAuth registered without a ConfigManager");
+                Some(ApiError::internal("Authentication is not configured").error_response())
+            }
+        };
+
+        if let Some(resp) = denied {
+            let res = req.into_response(resp).map_into_right_body();
+            return Box::pin(async { Ok(res) });
         }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
     }
 }
 
@@ -131,9 +476,7 @@ pub async fn auth_check_handler(
         .query_string()
         .split('&')
         .find_map(|pair| {
-            let mut parts = pair.splitn(2, '=');
-            let key = parts.next()?;
-            let value = parts.next()?;
+            let (key, value) = pair.split_once('=')?;
             if key == "token" {
                 Some(value.to_string())
             } else {
@@ -148,18 +491,222 @@ pub async fn auth_check_handler(
                 .map(|s| s.to_string())
         });
 
-    match token {
-        Some(t) if config.verify_token(&t) => HttpResponse::Ok().json(serde_json::json!({
-            "valid": true,
-            "message": "Token is valid"
-        })),
-        Some(_) => HttpResponse::Unauthorized().json(serde_json::json!({
-            "valid": false,
-            "error": "Invalid token"
-        })),
-        None => HttpResponse::BadRequest().json(serde_json::json!({
-            "valid": false,
-            "error": "No token provided"
-        })),
+    let identity = match &token {
+        Some(t) => match verify_with_limit(&req, &config, t) {
+            Ok(identity) => identity,
+            Err(e) => return e.error_response(),
+        },
+        None => None,
+    };
+
+    let error = match (token, identity) {
+        (Some(_), Some(identity)) => {
+            return HttpResponse::Ok().json(serde_json::json!({
+                "valid": true,
+                "message": "Token is valid",
+                "scope": identity.scope,
+                "workspaces": identity.workspaces,
+                "read_only": config.read_only()
+            }));
+        }
+        (Some(_), None) => invalid_token(),
+        (None, _) => ApiError::validation("no_token", "No token provided"),
+    };
+
+    // The usual error body, plus the verdict this endpoint exists to give
+    let mut body = error.body();
+    body["valid"] = serde_json::Value::Bool(false);
+    HttpResponse::build(error.status_code()).json(body)
+}
+
+fn invalid_token() -> ApiError {
+    ApiError::unauthorized("invalid_token", "Invalid token")
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scope: TokenScope,
+    /// Workspaces the token may use; empty or left out for all
+    #[serde(default)]
+    pub workspaces: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateTokenRequest {
+    pub workspaces: Vec<String>,
+}
+
+/// A workspace allowlist validated and without duplicates. The workspaces
+/// need not exist yet, so a token can be handed out before its workspace is
+/// created.
+fn clean_workspace_list(workspaces: &[String]) -> Result<Vec<String>, ApiError> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for name in workspaces {
+        config::validate_workspace_name(name).map_err(|rule| {
+            ApiError::validation("invalid_workspace_name", format!("Workspace '{}': {}", name, rule))
+        })?;
+        if !cleaned.contains(name) {
+            cleaned.push(name.clone());
+        }
+    }
+    Ok(cleaned)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RotateTokenQuery {
+    pub name: Option<String>,
+}
+
+/// Token names end up in URLs, so keep them to a simple charset
+fn is_valid_token_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// GET /api/auth/tokens - List token metadata (never the secrets)
+pub async fn list_tokens(
+    config: actix_web::web::Data<Arc<ConfigManager>>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(config.list_tokens())
+}
+
+/// POST /api/auth/tokens - Mint a named token; the secret is only returned here
+pub async fn create_token(
+    req: HttpRequest,
+    config: actix_web::web::Data<Arc<ConfigManager>>,
+    body: Json<CreateTokenRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let name = body.name.trim();
+    if !is_valid_token_name(name) {
+        return Err(ApiError::validation(
+            "invalid_token_name",
+            "Token name must be 1-64 characters of letters, digits, '-', '_' or '.'",
+        ));
+    }
+
+    let workspaces = clean_workspace_list(&body.workspaces)?;
+
+    match config.create_token(name, body.scope, workspaces) {
+        Ok(Some((info, secret))) => {
+            log::info!("Created API token {:?} ({:?})", info.name, info.scope);
+            audit::record(
+                &req,
+                "token.create",
+                serde_json::json!({ "name": info.name, "scope": info.scope, "workspaces": info.workspaces }),
+            );
+            Ok(HttpResponse::Created().json(serde_json::json!({
+                "name": info.name,
+                "token": secret,
+                "scope": info.scope,
+                "workspaces": info.workspaces,
+                "created_at": info.created_at,
+            })))
+        }
+        Ok(None) => Err(ApiError::conflict(
+            "token_exists",
+            format!("Token '{}' already exists", name),
+        )),
+        Err(e) => Err(ApiError::internal(format!("Failed to save token: {}", e))),
+    }
+}
+
+fn token_not_found(name: &str) -> ApiError {
+    ApiError::not_found("token_not_found", format!("Token '{}' not found", name))
+}
+
+/// PATCH /api/auth/tokens/{name} - Replace the workspaces a token is
+/// limited to (empty for all)
+pub async fn update_token(
+    req: HttpRequest,
+    config: actix_web::web::Data<Arc<ConfigManager>>,
+    path: actix_web::web::Path<String>,
+    body: Json<UpdateTokenRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let workspaces = clean_workspace_list(&body.workspaces)?;
+
+    match config.set_token_workspaces(&name, workspaces) {
+        Ok(Some(info)) => {
+            log::info!("Limited API token {:?} to workspaces {:?}", info.name, info.workspaces);
+            audit::record(
+                &req,
+                "token.update",
+                serde_json::json!({ "name": info.name, "workspaces": info.workspaces }),
+            );
+            Ok(HttpResponse::Ok().json(info))
+        }
+        Ok(None) => Err(token_not_found(&name)),
+        Err(e) => Err(ApiError::internal(format!("Failed to save config: {}", e))),
+    }
+}
+
+/// DELETE /api/auth/tokens/{name} - Revoke a token
+pub async fn revoke_token(
+    req: HttpRequest,
+    config: actix_web::web::Data<Arc<ConfigManager>>,
+    path: actix_web::web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let tokens = config.list_tokens();
+    if !tokens.iter().any(|t| t.name == name) {
+        return Err(token_not_found(&name));
+    }
+    if tokens.len() == 1 {
+        return Err(ApiError::conflict(
+            "last_token",
+            "Cannot revoke the last remaining token",
+        ));
+    }
+
+    match config.revoke_token(&name) {
+        Ok(true) => {
+            log::info!("Revoked API token {:?}", name);
+            audit::record(&req, "token.revoke", serde_json::json!({ "name": name }));
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "message": format!("Token '{}' revoked", name)
+            })))
+        }
+        Ok(false) => Err(token_not_found(&name)),
+        Err(e) => Err(ApiError::internal(format!("Failed to save config: {}", e))),
+    }
+}
+
+/// POST /api/auth/rotate - Replace a token's secret (the caller's own token
+/// unless `?name=` is given) and return the new value once
+pub async fn rotate_token(
+    req: HttpRequest,
+    config: actix_web::web::Data<Arc<ConfigManager>>,
+    query: actix_web::web::Query<RotateTokenQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let own = extract_token_from_request(&req, &config).and_then(|t| config.token_name(&t));
+    let name = match &query.name {
+        Some(name) => name.clone(),
+        None => own.clone().ok_or_else(invalid_token)?,
+    };
+    if own.as_deref() != Some(name.as_str()) {
+        require_unrestricted(&req, "rotate other tokens")?;
+    }
+
+    // Existing WebSocket connections were authenticated at upgrade time and
+    // stay open; only new requests need the new secret
+    match config.rotate_token(&name) {
+        Ok(Some((info, secret))) => {
+            log::info!("Rotated API token {:?}", info.name);
+            audit::record(&req, "token.rotate", serde_json::json!({ "name": info.name }));
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "name": info.name,
+                "token": secret,
+                "scope": info.scope,
+                "workspaces": info.workspaces,
+                "created_at": info.created_at,
+            })))
+        }
+        Ok(None) => Err(token_not_found(&name)),
+        Err(e) => Err(ApiError::internal(format!("Failed to save config: {}", e))),
     }
 }
@@ -0,0 +1,492 @@
+//! Runbook steps: the fenced code blocks and task list items of a markdown
+//! file, in order, with the file's headings as an outline around them.
+//! Problems with a single block (an unclosed fence, a garbled attribute
+//! list) are reported on that block instead of failing the whole file.
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use crate::variables;
+
+/// A fenced code block, e.g. ```` ```bash {name=restart-api timeout=60} ````
+#[derive(Debug, Serialize)]
+pub struct CodeBlock {
+    pub index: usize,
+    /// First word of the info string
+    pub language: Option<String>,
+    /// `key=value` pairs from the `{...}` part of the info string; bare keys
+    /// are `"true"`
+    pub attributes: BTreeMap<String, String>,
+    /// The info string as written
+    pub info: String,
+    pub code: String,
+    /// 1-based lines of the opening fence and the closing fence (or the
+    /// block's last line when the fence is never closed)
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Index into `Runbook::headings` of the section the block is in
+    pub section: Option<usize>,
+    /// Names of the `{{name}}` variables the code refers to
+    pub variables: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Heading {
+    pub level: u8,
+    pub title: String,
+    pub line: usize,
+}
+
+/// A task list item, `- [ ] Drain the node` or `- [x] ...`
+#[derive(Debug, Serialize)]
+pub struct Task {
+    pub index: usize,
+    pub checked: bool,
+    pub text: String,
+    pub line: usize,
+    /// Index into `Runbook::headings` of the section the task is in
+    pub section: Option<usize>,
+    /// Byte offset of the character between the checkbox's brackets
+    #[serde(skip)]
+    pub mark_offset: usize,
+}
+
+/// A link or image, with its destination as written
+#[derive(Debug)]
+pub struct Link {
+    pub image: bool,
+    pub dest: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Runbook {
+    pub headings: Vec<Heading>,
+    pub blocks: Vec<CodeBlock>,
+    pub tasks: Vec<Task>,
+    #[serde(skip)]
+    pub links: Vec<Link>,
+    /// YAML of the leading `---` block, if there is one
+    #[serde(skip)]
+    pub front_matter: Option<String>,
+}
+
+/// Byte offsets where each line starts, to turn offsets into line numbers
+struct Lines(Vec<usize>);
+
+impl Lines {
+    fn new(content: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self(starts)
+    }
+
+    /// 1-based line holding byte `offset`
+    fn line_of(&self, offset: usize) -> usize {
+        self.0.partition_point(|&start| start <= offset)
+    }
+
+    /// First and last line of `range`
+    fn span(&self, range: &Range<usize>) -> (usize, usize) {
+        let last = if range.end > range.start { range.end - 1 } else { range.start };
+        (self.line_of(range.start), self.line_of(last))
+    }
+}
+
+pub fn parse(content: &str) -> Runbook {
+    let lines = Lines::new(content);
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut blocks = Vec::new();
+
+    // Text of the heading or fenced block being read, if any
+    let mut heading: Option<(u8, usize, String)> = None;
+    let mut block: Option<(String, Range<usize>, String)> = None;
+    let mut front_matter: Option<String> = None;
+    let mut in_front_matter = false;
+    let mut tasks: Vec<Task> = Vec::new();
+    // Index into `tasks` of the item whose text is being read
+    let mut task: Option<usize> = None;
+    let mut links = Vec::new();
+
+    let options = Options::ENABLE_YAML_STYLE_METADATA_BLOCKS | Options::ENABLE_TASKLISTS;
+    for (event, range) in Parser::new_ext(content, options).into_offset_iter() {
+        match event {
+            Event::TaskListMarker(checked) => {
+                let bracket = content[range.clone()].find('[').unwrap_or(0);
+                task = Some(tasks.len());
+                tasks.push(Task {
+                    index: tasks.len(),
+                    checked,
+                    text: String::new(),
+                    line: lines.line_of(range.start),
+                    section: headings.len().checked_sub(1),
+                    mark_offset: range.start + bracket + 1,
+                });
+            }
+            // A nested list ends the text of the item holding it
+            Event::Start(Tag::List(_)) | Event::End(TagEnd::Item) => {
+                if let Some(index) = task.take() {
+                    let text = tasks[index].text.trim().to_string();
+                    tasks[index].text = text;
+                }
+            }
+            Event::Start(Tag::MetadataBlock(_)) => in_front_matter = true,
+            Event::End(TagEnd::MetadataBlock(_)) => in_front_matter = false,
+            Event::Text(text) if in_front_matter => {
+                front_matter.get_or_insert_with(String::new).push_str(&text);
+            }
+            Event::Start(Tag::Link { ref dest_url, .. }) | Event::Start(Tag::Image { ref dest_url, .. }) => {
+                links.push(Link {
+                    image: matches!(event, Event::Start(Tag::Image { .. })),
+                    dest: dest_url.to_string(),
+                    line: lines.line_of(range.start),
+                });
+            }
+            Event::Start(Tag::Heading { level, .. }) => {
+                heading = Some((level as u8, lines.line_of(range.start), String::new()));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, line, title)) = heading.take() {
+                    headings.push(Heading {
+                        level,
+                        title: title.trim().to_string(),
+                        line,
+                    });
+                }
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                block = Some((info.to_string(), range, String::new()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                // Indented blocks never start one, so they are skipped
+                if let Some((info, range, code)) = block.take() {
+                    let (start_line, end_line) = lines.span(&range);
+                    let mut warnings = Vec::new();
+                    if !fence_is_closed(&content[range.clone()]) {
+                        warnings.push("Code fence is never closed; the block runs to the end of its section".to_string());
+                    }
+                    let (language, attributes) = parse_info(&info, &mut warnings);
+                    blocks.push(CodeBlock {
+                        index: blocks.len(),
+                        language,
+                        attributes,
+                        info,
+                        variables: variables::references(&code),
+                        code,
+                        start_line,
+                        end_line,
+                        section: headings.len().checked_sub(1),
+                        warnings,
+                    });
+                }
+            }
+            Event::Text(text) | Event::Code(text) | Event::InlineHtml(text) => {
+                if let Some((_, _, code)) = &mut block {
+                    code.push_str(&text);
+                } else if let Some((_, _, title)) = &mut heading {
+                    title.push_str(&text);
+                } else if let Some(index) = task {
+                    tasks[index].text.push_str(&text);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                if let Some((_, _, title)) = &mut heading {
+                    title.push(' ');
+                } else if let Some(index) = task {
+                    tasks[index].text.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Runbook {
+        headings,
+        blocks,
+        tasks,
+        links,
+        front_matter,
+    }
+}
+
+/// Anchor of a heading as GitHub makes it: lowercased, punctuation dropped
+/// and spaces turned into dashes
+fn slug(title: &str) -> String {
+    title
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Anchor of each of `headings`, in order; repeated titles get `-1`, `-2`,
+/// ... appended
+pub fn heading_anchors(headings: &[Heading]) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    headings
+        .iter()
+        .map(|heading| {
+            let slug = slug(&heading.title);
+            let count = seen.entry(slug.clone()).or_insert(0);
+            let anchor = match *count {
+                0 => slug,
+                n => format!("{}-{}", slug, n),
+            };
+            *count += 1;
+            anchor
+        })
+        .collect()
+}
+
+/// Whether link or image `dest` is a URL (remote, `mailto:`, `data:`)
+/// rather than a file in the worktree
+pub fn is_url(dest: &str) -> bool {
+    dest.contains(':') || dest.starts_with("//")
+}
+
+/// `text` with `%XX` escapes decoded, or as it is if they don't make UTF-8
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| text.to_string())
+}
+
+/// Worktree-relative path of link or image `dest` in the runbook at
+/// `runbook_path` (without its `?query` or `#anchor`), or None if it leaves
+/// the worktree. A leading `/` starts from the worktree root.
+pub fn link_path(runbook_path: &str, dest: &str) -> Option<String> {
+    let dest = dest.split(['?', '#']).next().unwrap_or_default();
+    let mut path = match dest.strip_prefix('/') {
+        Some(_) => PathBuf::new(),
+        None => Path::new(runbook_path).parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    for component in Path::new(dest).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::ParentDir => {
+                if !path.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    Some(path.to_string_lossy().into_owned())
+}
+
+/// Language tag of the blocks holding a recorded run's output
+pub const OUTPUT_LANGUAGE: &str = "output";
+
+/// `content` with `output` in an `output` block right after code block
+/// `index`, replacing the one a previous run left there. None if there is
+/// no such block.
+pub fn record_output(content: &str, index: usize, output: &str) -> Option<String> {
+    let runbook = parse(content);
+    let block = runbook.blocks.get(index)?;
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+
+    // Only blank lines may separate a block from its output
+    let previous = runbook.blocks.get(index + 1).filter(|next| {
+        next.language.as_deref() == Some(OUTPUT_LANGUAGE)
+            && lines[block.end_line..next.start_line - 1].iter().all(|line| line.trim().is_empty())
+    });
+    let (keep_until, resume_at) = match previous {
+        Some(next) => (next.start_line - 1, next.end_line),
+        None => (block.end_line, block.end_line),
+    };
+
+    let fence = fence_for(output);
+
+    let mut result: String = lines[..keep_until].concat();
+    if !result.ends_with('\n') {
+        result.push('\n');
+    }
+    if previous.is_none() {
+        result.push('\n');
+    }
+    result.push_str(&format!("{}{}\n{}", fence, OUTPUT_LANGUAGE, output));
+    if !output.is_empty() && !output.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(&fence);
+    result.push('\n');
+    result.push_str(&lines[resume_at..].concat());
+    Some(result)
+}
+
+/// A fence of backticks that no backtick run in `text` can close
+pub fn fence_for(text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat((longest_run + 1).max(3))
+}
+
+/// `content` with `section`, which starts with a heading, appended after a
+/// blank line. None if the end of `content` would swallow it: an unclosed
+/// fence or HTML block that the heading would land in.
+pub fn append_section(content: &str, section: &str) -> Option<String> {
+    let mut result = content.to_string();
+    if !result.is_empty() {
+        if !result.ends_with('\n') {
+            result.push('\n');
+        }
+        result.push('\n');
+    }
+    let heading_line = result.matches('\n').count() + 1;
+    result.push_str(section);
+    if !result.ends_with('\n') {
+        result.push('\n');
+    }
+    let runbook = parse(&result);
+    runbook.headings.iter().any(|heading| heading.line == heading_line).then_some(result)
+}
+
+/// Hex SHA-256 of a file's or a block's text, identifying the revision that
+/// was run
+pub fn content_hash(content: impl AsRef<[u8]>) -> String {
+    let mut hasher = ContentHasher::new();
+    hasher.update(content);
+    hasher.finish()
+}
+
+/// `content_hash` of content read in pieces
+pub fn reader_hash(mut reader: impl std::io::Read) -> std::io::Result<String> {
+    let mut hasher = ContentHasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(hasher.finish()),
+            n => hasher.update(&buf[..n]),
+        }
+    }
+}
+
+/// `content_hash` of content that arrives in pieces
+#[derive(Default)]
+pub struct ContentHasher(Sha256);
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, content: impl AsRef<[u8]>) {
+        self.0.update(content.as_ref());
+    }
+
+    pub fn finish(self) -> String {
+        self.0.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Whether a fenced block's source ends with a closing fence at least as
+/// long as its opening one
+fn fence_is_closed(source: &str) -> bool {
+    let mut source_lines = source.lines();
+    let opening = source_lines.next().unwrap_or_default().trim_start();
+    let Some(fence_char) = opening.chars().next() else {
+        return false;
+    };
+    let fence_len = opening.chars().take_while(|&c| c == fence_char).count();
+    source_lines.next_back().is_some_and(|last| {
+        let last = last.trim();
+        last.len() >= fence_len && last.chars().all(|c| c == fence_char)
+    })
+}
+
+/// Split an info string such as `bash {name=restart-api timeout=60}` into
+/// its language and attributes. Malformed attributes are skipped with a
+/// warning.
+fn parse_info(info: &str, warnings: &mut Vec<String>) -> (Option<String>, BTreeMap<String, String>) {
+    let info = info.trim();
+    let (head, attrs) = match info.find('{') {
+        Some(open) => (&info[..open], Some(&info[open + 1..])),
+        None => (info, None),
+    };
+    let language = head.split_whitespace().next().map(str::to_string);
+
+    let mut attributes = BTreeMap::new();
+    let Some(attrs) = attrs else {
+        return (language, attributes);
+    };
+    let attrs = match attrs.rfind('}') {
+        Some(close) => &attrs[..close],
+        None => {
+            warnings.push("Attribute list is missing its closing '}'".to_string());
+            attrs
+        }
+    };
+
+    for token in split_attributes(attrs, warnings) {
+        match token.split_once('=') {
+            Some((key, _)) if key.trim().is_empty() => {
+                warnings.push(format!("Attribute '{}' has no name", token));
+            }
+            Some((key, value)) => {
+                attributes.insert(key.trim().to_string(), unquote(value.trim()).to_string());
+            }
+            None => {
+                attributes.insert(token, "true".to_string());
+            }
+        }
+    }
+    (language, attributes)
+}
+
+/// Split on whitespace and commas outside double quotes
+fn split_attributes(attrs: &str, warnings: &mut Vec<String>) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in attrs.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if !quoted && (c.is_whitespace() || c == ',') => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        warnings.push("Attribute value is missing its closing quote".to_string());
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.strip_prefix('"').unwrap_or(value);
+    value.strip_suffix('"').unwrap_or(value)
+}
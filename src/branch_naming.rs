@@ -0,0 +1,138 @@
+//! Branch naming policy of a workspace, so the branches pushed to a shared
+//! remote follow its convention (say `runbook/<user>/<slug>`).
+//! `branch_name_pattern` is a regex that whole branch names created or
+//! renamed through the API must match. `branch_name_template` generates
+//! names for `create_branch` requests that give `auto_name` instead of a
+//! name, from these placeholders:
+//!
+//! - `{user}`: the name of the request's token
+//! - `{slug}`: the `slug` of the request
+//! - `{date}`: today's date (UTC) as `YYYY-MM-DD`
+//!
+//! Token names and slugs are lowercased and anything but letters, digits,
+//! `.`, `_` and `-` becomes `-`, so they can't add path levels of their own.
+
+use chrono::Utc;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{validate_branch_name, WorkspaceConfig};
+use crate::errors::ApiError;
+
+const PLACEHOLDERS: [&str; 3] = ["user", "slug", "date"];
+
+#[derive(Debug, Deserialize)]
+pub struct AutoName {
+    pub slug: String,
+}
+
+/// A workspace's policy, as the detail endpoint shows it
+#[derive(Debug, Serialize)]
+pub struct BranchNaming {
+    pub pattern: Option<String>,
+    pub template: Option<String>,
+}
+
+impl BranchNaming {
+    pub fn of(ws_config: &WorkspaceConfig) -> Self {
+        Self {
+            pattern: ws_config.branch_name_pattern.clone(),
+            template: ws_config.branch_name_template.clone(),
+        }
+    }
+}
+
+/// `pattern` anchored to match whole names
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// 400 `invalid_branch_pattern` unless `pattern` is a valid regex
+pub fn check_pattern(pattern: &str) -> Result<(), ApiError> {
+    compile(pattern).map(|_| ()).map_err(|e| {
+        ApiError::validation("invalid_branch_pattern", format!("Invalid branch name pattern: {}", e))
+    })
+}
+
+/// Error message naming what is wrong with `template` unless its only
+/// `{...}` placeholders are `known` ones
+pub fn check_placeholders(template: &str, known: &[&str]) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err("Unclosed '{' in template".to_string());
+        };
+        let name = &rest[start + 1..start + len];
+        if !known.contains(&name) {
+            let known: Vec<String> = known.iter().map(|name| format!("{{{}}}", name)).collect();
+            return Err(format!("Unknown placeholder '{{{}}}' in template; use {}", name, known.join(", ")));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+/// 400 `invalid_branch_template` unless `template` only uses known
+/// placeholders and includes `{slug}`, which tells generated names apart
+pub fn check_template(template: &str) -> Result<(), ApiError> {
+    let invalid = |message: String| ApiError::validation("invalid_branch_template", message);
+    check_placeholders(template, &PLACEHOLDERS).map_err(invalid)?;
+    if !template.contains("{slug}") {
+        return Err(invalid("Branch name template must include {slug}".to_string()));
+    }
+    Ok(())
+}
+
+/// 400 `branch_name_policy` (with the `pattern`) if the workspace has a
+/// pattern and `name` doesn't match it
+pub fn require_allowed(ws_config: &WorkspaceConfig, name: &str) -> Result<(), ApiError> {
+    let Some(pattern) = &ws_config.branch_name_pattern else {
+        return Ok(());
+    };
+    let regex = compile(pattern).map_err(|e| {
+        log::error!("Invalid branch name pattern '{}' in the config: {}", pattern, e);
+        ApiError::internal("The workspace's branch name pattern is invalid")
+    })?;
+    if !regex.is_match(name) {
+        return Err(ApiError::validation(
+            "branch_name_policy",
+            format!("Branch name '{}' doesn't match the workspace's pattern '{}'", name, pattern),
+        )
+        .with_details(serde_json::json!({ "pattern": pattern })));
+    }
+    Ok(())
+}
+
+/// `value` made safe to fill in as one level of a branch name
+fn path_segment(value: &str) -> String {
+    let mut segment = String::new();
+    for c in value.trim().chars().flat_map(char::to_lowercase) {
+        let c = if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '-' };
+        if !(c == '-' && segment.ends_with('-')) {
+            segment.push(c);
+        }
+    }
+    segment.trim_matches(|c| c == '-' || c == '.').to_string()
+}
+
+/// Generate a branch name from the workspace's template for `user` (the
+/// token name); 400 `no_branch_template` if the workspace has none,
+/// `invalid_slug` if nothing of the slug is left to use
+pub fn generate(ws_config: &WorkspaceConfig, user: &str, auto_name: &AutoName) -> Result<String, ApiError> {
+    let Some(template) = &ws_config.branch_name_template else {
+        return Err(ApiError::validation(
+            "no_branch_template",
+            "The workspace has no branch name template; give a branch_name",
+        ));
+    };
+    let slug = path_segment(&auto_name.slug);
+    if slug.is_empty() {
+        return Err(ApiError::validation("invalid_slug", "The slug has no letters or digits"));
+    }
+    let name = template
+        .replace("{user}", &path_segment(user))
+        .replace("{slug}", &slug)
+        .replace("{date}", &Utc::now().format("%Y-%m-%d").to_string());
+    validate_branch_name(&name).map_err(|message| ApiError::validation("invalid_branch_name", message))?;
+    Ok(name)
+}
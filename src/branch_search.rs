@@ -0,0 +1,156 @@
+//! Search of the runbooks of every branch of a workspace at once, for "which
+//! branch has the updated failover runbook?". Each branch's committed
+//! markdown files are read with `git grep` in the bare repository, so
+//! branches without a worktree are searched as well; uncommitted changes in
+//! worktrees aren't, the per-branch search endpoint covers those.
+
+use actix_web::{web, HttpResponse};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::file_ops;
+use crate::git_ops;
+use crate::logging;
+use crate::workspace;
+
+const DEFAULT_SEARCH_LIMIT: usize = 200;
+const MAX_SEARCH_LIMIT: usize = 5000;
+const DEFAULT_PER_BRANCH_LIMIT: usize = 50;
+/// Branches searched at once
+const SEARCH_WORKERS: usize = 4;
+/// Longest snippet returned of a matching line, in characters
+const MAX_SNIPPET_CHARS: usize = 300;
+
+#[derive(Debug, Deserialize)]
+pub struct BranchSearchQuery {
+    pub q: String,
+    /// Take `q` as a POSIX extended regex rather than a literal string
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Matches returned in all
+    pub limit: Option<usize>,
+    /// Matches returned per branch
+    pub per_branch: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BranchMatch {
+    pub branch: String,
+    pub path: String,
+    pub line: usize,
+    pub snippet: String,
+}
+
+/// A branch that couldn't be searched
+#[derive(Debug, Serialize)]
+pub struct SearchWarning {
+    pub branch: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BranchSearchResults {
+    pub matches: Vec<BranchMatch>,
+    pub branches_searched: usize,
+    /// More matches were found than `limit`
+    pub truncated: bool,
+    /// Branches with more matches than `per_branch`
+    pub truncated_branches: Vec<String>,
+    pub warnings: Vec<SearchWarning>,
+}
+
+fn snippet(text: &str) -> String {
+    let text = text.trim_end_matches('\r');
+    match text.char_indices().nth(MAX_SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// GET /api/workspaces/{name}/search - Search the committed markdown files
+/// of every branch
+pub async fn search_branches(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+    query: web::Query<BranchSearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let workspace = path.into_inner();
+    let query = query.into_inner();
+    workspace::require_workspace(&config, &workspace)?;
+
+    if query.q.is_empty() {
+        return Err(ApiError::validation("empty_query", "Search query must not be empty"));
+    }
+    if query.regex {
+        // Checked like the per-branch search's patterns; one git reads
+        // differently fails in git grep and comes back as warnings
+        file_ops::build_search_pattern(&query.q, true, query.case_sensitive)
+            .map_err(|e| ApiError::validation("invalid_regex", format!("Invalid regex: {}", e)))?;
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT);
+    let per_branch = query.per_branch.unwrap_or(DEFAULT_PER_BRANCH_LIMIT).min(limit);
+
+    workspace::require_usable_repo(&config, &workspace).await?;
+    let repo_path = config.repo_path(&workspace);
+    let branches = git_ops::list_branches(&repo_path).map_err(|e| e.context("Failed to list branches"))?;
+
+    // In branch order, whichever finishes first
+    let searches: Vec<_> = futures::stream::iter(branches)
+        .map(|branch| {
+            let repo_path = repo_path.clone();
+            let q = query.q.clone();
+            let (literal, case_sensitive) = (!query.regex, query.case_sensitive);
+            async move {
+                let rev = format!("refs/heads/{}", branch);
+                let found =
+                    logging::block(move || git_ops::grep_rev(&repo_path, &rev, &q, literal, case_sensitive, per_branch))
+                        .await;
+                (branch, found)
+            }
+        })
+        .buffered(SEARCH_WORKERS)
+        .collect()
+        .await;
+
+    let mut results = BranchSearchResults {
+        matches: Vec::new(),
+        branches_searched: searches.len(),
+        truncated: false,
+        truncated_branches: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for (branch, found) in searches {
+        let (found, more) = match found? {
+            Ok(found) => found,
+            Err(e) => {
+                log::warn!("Failed to search branch '{}' of {}: {}", branch, workspace, e);
+                results.warnings.push(SearchWarning {
+                    branch,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        if more {
+            results.truncated_branches.push(branch.clone());
+        }
+        for found in found {
+            if results.matches.len() == limit {
+                results.truncated = true;
+                break;
+            }
+            results.matches.push(BranchMatch {
+                branch: branch.clone(),
+                path: found.path,
+                line: found.line,
+                snippet: snippet(&found.text),
+            });
+        }
+    }
+    Ok(HttpResponse::Ok().json(results))
+}
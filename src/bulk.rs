@@ -0,0 +1,287 @@
+//! One operation over many workspaces, for admins looking after a fleet of
+//! them: `POST /api/admin/workspaces/bulk` queues a job per workspace as one
+//! batch and answers with the job IDs; `GET .../bulk/{batch_id}` tells how
+//! far the batch got. A workspace whose job fails doesn't stop the others,
+//! and only `concurrency` jobs of a batch run at once, leaving workers for
+//! everything else.
+//!
+//! - `fetch`: what `POST /api/workspaces/{name}/fetch` does
+//! - `verify`: recheck the workspace's status and disk space; fails unless
+//!   the status is `ok`
+//! - `prune`: forget worktrees whose directory is gone and remote-tracking
+//!   branches the remotes no longer have
+//! - `cleanup_merged`: remove the worktrees and branches already merged into
+//!   the base branch, leaving the protected ones, those with uncommitted
+//!   changes or an open terminal, and those without commits of their own
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::api;
+use crate::audit;
+use crate::config::{sanitize_branch_name, ConfigManager, WorkspaceStatus};
+use crate::drafts;
+use crate::errors::ApiError;
+use crate::events::{Event, EventBus};
+use crate::extract::Json;
+use crate::git_ops;
+use crate::jobs::{Job, JobKind};
+use crate::quota;
+use crate::workspace;
+use crate::AppState;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkOperation {
+    Fetch,
+    Verify,
+    Prune,
+    CleanupMerged,
+}
+
+impl BulkOperation {
+    fn kind(self) -> JobKind {
+        match self {
+            BulkOperation::Fetch => JobKind::Fetch,
+            BulkOperation::Verify => JobKind::Verify,
+            BulkOperation::Prune => JobKind::Prune,
+            BulkOperation::CleanupMerged => JobKind::CleanupMerged,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkRequest {
+    pub operation: BulkOperation,
+    /// Workspace names; `*` stands for every workspace
+    pub workspaces: Vec<String>,
+    /// Jobs of the batch run at once [default: one less than `job_workers`,
+    /// at least 1]
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+/// The configured workspaces `patterns` name, sorted, or 404 naming one
+/// that doesn't exist
+fn matching_workspaces(config: &ConfigManager, patterns: &[String]) -> Result<Vec<String>, ApiError> {
+    let known = config.get_workspaces();
+    let mut names = BTreeSet::new();
+    for pattern in patterns {
+        if pattern == "*" {
+            names.extend(known.keys().cloned());
+        } else if known.contains_key(pattern) {
+            names.insert(pattern.clone());
+        } else {
+            return Err(ApiError::not_found(
+                "workspace_not_found",
+                format!("Workspace '{}' not found", pattern),
+            ));
+        }
+    }
+    Ok(names.into_iter().collect())
+}
+
+fn verify_job(config: Arc<ConfigManager>, name: String) -> Job {
+    Job::new(JobKind::Verify, name.clone(), None, move |_| {
+        quota::rescan(&config, &name);
+        let status = config.verify_workspace(&name);
+        if status != WorkspaceStatus::Ok {
+            return Err(ApiError::conflict(
+                "workspace_unhealthy",
+                format!("Workspace '{}': {}", name, status.describe()),
+            )
+            .with_details(serde_json::json!({ "status": status })));
+        }
+        Ok(serde_json::json!({
+            "status": status,
+            "message": status.describe(),
+            "quota": quota::usage(&config, &name)
+        }))
+    })
+}
+
+fn prune_job(config: Arc<ConfigManager>, state: &AppState, name: String) -> Job {
+    let listings = state.listings.clone();
+    Job::new(JobKind::Prune, name.clone(), None, move |job| {
+        workspace::check_usable_repo(&config, &name)?;
+        let repo_path = config.repo_path(&name);
+        let remotes = git_ops::list_remotes(&repo_path).map_err(|e| e.context("Failed to list remotes"))?;
+        job.progress("Pruning worktrees and remote-tracking branches");
+        let pruned = git_ops::prune(&repo_path, &remotes);
+        config.invalidate_workspace_status(&name);
+        listings.invalidate_workspace(&name);
+        pruned.map_err(|e| e.context("Failed to prune"))?;
+
+        Ok(serde_json::json!({
+            "message": "Prune completed successfully",
+            "remotes": remotes.iter().map(|remote| &remote.name).collect::<Vec<_>>()
+        }))
+    })
+}
+
+fn cleanup_merged_job(
+    config: Arc<ConfigManager>,
+    state: &AppState,
+    events: EventBus,
+    name: String,
+    session_dirs: Vec<PathBuf>,
+    audit: audit::Recorder,
+) -> Job {
+    let listings = state.listings.clone();
+    let watcher = state.watcher.clone();
+    let locks = state.worktree_locks.clone();
+    Job::new(JobKind::CleanupMerged, name.clone(), None, move |job| {
+        let ws_config = workspace::require_workspace(&config, &name)?;
+        workspace::check_usable_repo(&config, &name)?;
+        let repo_path = config.repo_path(&name);
+        let base = &ws_config.base_branch;
+        let base_commit = git_ops::resolve_commit(&repo_path, base).ok_or_else(|| {
+            ApiError::conflict("base_branch_missing", format!("Base branch '{}' doesn't exist", base))
+        })?;
+        let branches = git_ops::list_branches(&repo_path).map_err(|e| e.context("Failed to list branches"))?;
+        let worktrees = git_ops::list_worktrees(&repo_path).map_err(|e| e.context("Failed to list worktrees"))?;
+
+        let mut removed = Vec::new();
+        let mut skipped = Vec::new();
+        for branch in branches.iter().filter(|branch| *branch != base && !ws_config.is_protected(branch)) {
+            let Some(tip) = git_ops::resolve_commit(&repo_path, branch) else {
+                continue;
+            };
+            // A branch just created from the base has nothing merged yet
+            if tip == base_commit || !git_ops::is_ancestor(&repo_path, &tip, &base_commit)? {
+                continue;
+            }
+            let worktree_path = config.worktree_path(&name, branch);
+            let has_worktree = worktrees.contains(&sanitize_branch_name(branch));
+            if has_worktree {
+                let lock = locks.try_acquire(&worktree_path, "cleanup_merged");
+                let reason = if session_dirs.iter().any(|dir| dir.starts_with(&worktree_path)) {
+                    Some("terminal_open")
+                } else if lock.is_none() {
+                    Some("worktree_locked")
+                } else if git_ops::has_uncommitted_changes(&worktree_path).unwrap_or(true) {
+                    Some("uncommitted_changes")
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    skipped.push(serde_json::json!({ "branch": branch, "reason": reason }));
+                    continue;
+                }
+                job.progress(format!("Removing the worktree of '{}'", branch));
+                watcher.unwatch(&worktree_path);
+                let worktree_removed =
+                    git_ops::remove_worktree(&repo_path, &worktree_path, &sanitize_branch_name(branch));
+                listings.invalidate_worktree(&name, branch);
+                worktree_removed.map_err(|e| e.context("Failed to remove worktree"))?;
+                drafts::discard_branch(&config, &name, branch);
+            }
+            git_ops::delete_branch(&repo_path, branch).map_err(|e| e.context("Failed to delete branch"))?;
+
+            events.publish(Event::BranchDeleted {
+                workspace: name.clone(),
+                branch: branch.clone(),
+            });
+            audit.record(
+                "branch.delete",
+                serde_json::json!({ "workspace": name, "branch": branch, "merged_into": base, "worktree": has_worktree }),
+            );
+            removed.push(branch.clone());
+        }
+        config.invalidate_workspace_status(&name);
+        listings.invalidate_workspace(&name);
+
+        Ok(serde_json::json!({
+            "message": format!("{} merged branch(es) removed", removed.len()),
+            "removed": removed,
+            "skipped": skipped
+        }))
+    })
+}
+
+/// POST /api/admin/workspaces/bulk - Queue one job of an operation per
+/// matching workspace, as a batch
+pub async fn start_bulk(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
+    body: Json<BulkRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let BulkRequest {
+        operation,
+        workspaces,
+        concurrency,
+    } = body.into_inner();
+    let names = matching_workspaces(&config, &workspaces)?;
+    if names.is_empty() {
+        return Err(ApiError::validation("no_workspaces", "No workspace matches the request"));
+    }
+    let workers = config.get_job_workers().max(1);
+    let concurrency = concurrency.unwrap_or(workers - 1).clamp(1, workers);
+
+    let session_dirs: Vec<PathBuf> = match operation {
+        BulkOperation::CleanupMerged => state
+            .session_infos()
+            .await
+            .into_iter()
+            .filter_map(|session| session.cwd.map(PathBuf::from))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let recorder = audit::Recorder::from_request(&req);
+    let jobs: Vec<Job> = names
+        .iter()
+        .map(|name| {
+            let config = config.get_ref().clone();
+            let name = name.clone();
+            match operation {
+                BulkOperation::Fetch => {
+                    workspace::fetch_job(&config, &state, events.get_ref().clone(), name, None, recorder.clone())
+                }
+                BulkOperation::Verify => verify_job(config, name),
+                BulkOperation::Prune => prune_job(config, &state, name),
+                BulkOperation::CleanupMerged => cleanup_merged_job(
+                    config,
+                    &state,
+                    events.get_ref().clone(),
+                    name,
+                    session_dirs.clone(),
+                    recorder.clone(),
+                ),
+            }
+        })
+        .collect();
+
+    let batch = state.jobs.enqueue_batch(operation.kind(), jobs, concurrency, recorder);
+    audit::record(
+        &req,
+        "workspace.bulk",
+        serde_json::json!({
+            "batch": batch.batch_id,
+            "operation": batch.operation,
+            "workspaces": names,
+            "concurrency": batch.concurrency
+        }),
+    );
+    Ok(HttpResponse::Accepted()
+        .insert_header((
+            "Location",
+            format!("{}/admin/workspaces/bulk/{}", api::V1_PREFIX, batch.batch_id),
+        ))
+        .json(batch))
+}
+
+/// GET /api/admin/workspaces/bulk/{batch_id} - A batch, with how many of its
+/// jobs are in each state and their records
+pub async fn get_bulk(state: web::Data<Arc<AppState>>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let batch = state
+        .jobs
+        .batch(&id)
+        .ok_or_else(|| ApiError::not_found("batch_not_found", format!("Batch '{}' not found", id)))?;
+    Ok(HttpResponse::Ok().json(batch))
+}
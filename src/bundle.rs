@@ -0,0 +1,337 @@
+//! Moving a server to another host: `GET /api/admin/export` bundles the
+//! config with its workspace registry, and `runotepad import` turns a bundle
+//! back into a config file and, if asked, clones the workspaces again.
+//! Importing is only offered on the command line, so nobody can replace a
+//! server's tokens and workspaces over HTTP.
+//!
+//! Secrets leave a bundle unless it is asked for with them. Token hashes
+//! and webhook secrets then travel as they are, and workspace secrets
+//! travel opened, since the key they are sealed with stays on the old host;
+//! the import seals them again with the new host's key.
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::audit;
+use crate::config::{self, Config, ConfigManager, ConfigOverrides, GIT_PASSWORD_SECRET};
+use crate::errors::ApiError;
+use crate::git_ops;
+use crate::secrets::SecretKey;
+use crate::workspace_env::secret_key_error;
+
+/// `format` of every bundle
+pub const BUNDLE_FORMAT: &str = "runotepad-bundle";
+
+/// Layout of bundles this version writes; newer ones are refused
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct Bundle {
+    pub format: &'static str,
+    pub bundle_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub server_version: &'static str,
+    /// Token hashes, webhook secrets and opened workspace secrets are in
+    /// `config`
+    pub includes_secrets: bool,
+    /// What was left out, e.g. `tokens.default` or
+    /// `workspaces.ops.secret_env.DB_PASSWORD`
+    pub redacted: Vec<String>,
+    pub config: Config,
+}
+
+/// A copy of `config` with its secrets left out (and listed), or kept and
+/// the sealed ones opened with `key`
+pub fn export(config: Config, include_secrets: bool, key: Option<&SecretKey>) -> Result<Bundle, String> {
+    let mut config = config;
+    let mut redacted = Vec::new();
+    if include_secrets {
+        for (name, workspace) in config.workspaces.iter_mut() {
+            if workspace.secret_env.is_empty() && workspace.git_password.is_none() {
+                continue;
+            }
+            let key = key.ok_or_else(|| format!("Secrets of workspace '{}' can't be opened without the secret key", name))?;
+            for (var, value) in workspace.secret_env.iter_mut() {
+                *value = key
+                    .open(var, value)
+                    .map_err(|e| format!("Secret '{}' of workspace '{}': {}", var, name, e))?;
+            }
+            if let Some(password) = workspace.git_password.as_mut() {
+                *password = key
+                    .open(GIT_PASSWORD_SECRET, password)
+                    .map_err(|e| format!("Git password of workspace '{}': {}", name, e))?;
+            }
+        }
+    } else {
+        redacted.extend(config.tokens.drain(..).map(|token| format!("tokens.{}", token.name)));
+        for webhook in config.notifications.webhooks.iter_mut() {
+            if webhook.secret.take().is_some() {
+                redacted.push(format!("notifications.webhooks.{}.secret", webhook.name));
+            }
+        }
+        for (name, workspace) in config.workspaces.iter_mut() {
+            for var in std::mem::take(&mut workspace.secret_env).into_keys() {
+                redacted.push(format!("workspaces.{}.secret_env.{}", name, var));
+            }
+            if workspace.git_password.take().is_some() {
+                redacted.push(format!("workspaces.{}.git_password", name));
+            }
+        }
+        redacted.sort();
+    }
+    Ok(Bundle {
+        format: BUNDLE_FORMAT,
+        bundle_version: BUNDLE_VERSION,
+        exported_at: Utc::now(),
+        server_version: env!("CARGO_PKG_VERSION"),
+        includes_secrets: include_secrets,
+        redacted,
+        config,
+    })
+}
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub include_secrets: bool,
+}
+
+/// GET /api/admin/export - The config and workspace registry as a bundle
+/// for `runotepad import`; secrets only with `?include_secrets=true`
+pub async fn export_bundle(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let snapshot = config.snapshot();
+    let needs_key = query.include_secrets
+        && snapshot
+            .workspaces
+            .values()
+            .any(|workspace| !workspace.secret_env.is_empty() || workspace.git_password.is_some());
+    let key = if needs_key {
+        Some(SecretKey::load(config.secret_key_path()).map_err(|e| secret_key_error(&config, e))?)
+    } else {
+        None
+    };
+    let bundle = export(snapshot, query.include_secrets, key.as_ref()).map_err(ApiError::internal)?;
+
+    audit::record(
+        &req,
+        "config.export",
+        serde_json::json!({
+            "include_secrets": query.include_secrets,
+            "workspaces": bundle.config.workspaces.len()
+        }),
+    );
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"runotepad-{}.json\"",
+                bundle.exported_at.format("%Y%m%d-%H%M%S")
+            ),
+        ))
+        .json(bundle))
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportOptions {
+    /// Replace an existing config file (kept as `.bak`)
+    pub force: bool,
+    /// Clone every workspace once the config is written
+    pub clone: bool,
+}
+
+/// How an import went; the config is written whenever there is one
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub config_path: PathBuf,
+    pub workspaces: usize,
+    pub cloned: Vec<String>,
+    /// Already on disk, so left as they are
+    pub skipped: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+/// The config of the bundle in `content`, after checking it is one this
+/// version reads and that its workspaces are valid
+pub fn read_bundle(content: &str, source: &Path) -> Result<(Config, bool), Box<dyn std::error::Error>> {
+    let mut value: serde_json::Value =
+        serde_json::from_str(content).map_err(|e| format!("{} is not JSON: {}", source.display(), e))?;
+    if value.get("format").and_then(|f| f.as_str()) != Some(BUNDLE_FORMAT) {
+        return Err(format!("{} is not a runotepad bundle", source.display()).into());
+    }
+    let version = value.get("bundle_version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version > u64::from(BUNDLE_VERSION) {
+        return Err(format!(
+            "{} is a version {} bundle, written by a newer runotepad; this one reads up to version {}",
+            source.display(),
+            version,
+            BUNDLE_VERSION
+        )
+        .into());
+    }
+    let includes_secrets = value.get("includes_secrets").and_then(|v| v.as_bool()).unwrap_or(false);
+    let config = config::config_from_value(value["config"].take(), source)?;
+
+    for (name, workspace) in &config.workspaces {
+        config::validate_workspace_name(name).map_err(|e| format!("Workspace '{}': {}", name, e))?;
+        config::validate_branch_name(&workspace.base_branch)
+            .map_err(|e| format!("Base branch of workspace '{}': {}", name, e))?;
+        for remote in [&workspace.push_remote, &workspace.upstream_remote].into_iter().flatten() {
+            config::validate_remote_name(remote).map_err(|e| format!("Remote of workspace '{}': {}", name, e))?;
+        }
+    }
+    Ok((config, includes_secrets))
+}
+
+/// Write the config of the bundle at `file` for a server started with
+/// `overrides`, then clone its workspaces if `options` say so. `progress`
+/// gets a line per step.
+pub fn import(
+    file: &Path,
+    overrides: ConfigOverrides,
+    options: ImportOptions,
+    progress: &mut dyn FnMut(String),
+) -> Result<ImportReport, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(file).map_err(|e| format!("Can't read {}: {}", file.display(), e))?;
+    let (mut bundled, includes_secrets) = read_bundle(&content, file)?;
+    let redacted: Vec<String> = serde_json::from_str::<serde_json::Value>(&content)?["redacted"]
+        .as_array()
+        .map(|items| items.iter().filter_map(|item| item.as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+
+    let config_path = overrides.config_file.clone().unwrap_or_else(config::default_config_path);
+    if config_path.exists() && !options.force {
+        return Err(format!("{} already exists; pass --force to replace it", config_path.display()).into());
+    }
+
+    // Opened secrets are sealed again once the new key is known; a bundle
+    // without secrets may still carry sealed ones, which no key here opens
+    let mut opened: BTreeMap<String, (BTreeMap<String, String>, Option<String>)> = BTreeMap::new();
+    for (name, workspace) in bundled.workspaces.iter_mut() {
+        let secret_env = std::mem::take(&mut workspace.secret_env);
+        let git_password = workspace.git_password.take();
+        if includes_secrets && (!secret_env.is_empty() || git_password.is_some()) {
+            opened.insert(name.clone(), (secret_env, git_password));
+        }
+    }
+    bundled.tokens.retain(|token| !token.hash.is_empty());
+    let replaced = config_path.exists();
+    config::write_config(&config_path, &bundled)?;
+
+    // A config that doesn't load leaves what was there before
+    let manager = match ConfigManager::new(overrides) {
+        Ok(manager) => manager,
+        Err(e) => {
+            let _ = if replaced {
+                fs::rename(config::backup_path(&config_path), &config_path)
+            } else {
+                fs::remove_file(&config_path)
+            };
+            return Err(format!("The bundle's config doesn't load: {}", e).into());
+        }
+    };
+    progress(format!("Wrote {}", config_path.display()));
+    if let Some(token) = manager.take_generated_token() {
+        progress(format!("The bundle has no tokens; generated access token (shown only once): {}", token));
+    }
+
+    if !opened.is_empty() {
+        let key = SecretKey::load_or_create(manager.secret_key_path())
+            .map_err(|e| format!("Secret key file {}: {}", manager.secret_key_path().display(), e))?;
+        for (name, (secret_env, git_password)) in opened {
+            let env = manager.get_workspace(&name).map(|workspace| workspace.env).unwrap_or_default();
+            let sealed = secret_env
+                .into_iter()
+                .map(|(var, value)| {
+                    let sealed = key.seal(&var, &value);
+                    (var, sealed)
+                })
+                .collect();
+            manager.set_workspace_env(&name, env, sealed)?;
+            if let Some(password) = git_password {
+                let sealed = key.seal(GIT_PASSWORD_SECRET, &password);
+                manager.set_workspace_git_identity(&name, None, None, None, Some(sealed))?;
+            }
+        }
+        progress(format!("Sealed workspace secrets with {}", manager.secret_key_path().display()));
+    }
+
+    for item in &redacted {
+        progress(format!("Not in the bundle, set it again: {}", item));
+    }
+    let snapshot = manager.snapshot();
+    let paths = [
+        ("tls_cert", &snapshot.tls_cert),
+        ("tls_key", &snapshot.tls_key),
+        ("import_root", &snapshot.import_root),
+        ("static_dir", &snapshot.static_dir),
+    ];
+    for (setting, path) in paths {
+        if let Some(path) = path.as_ref().filter(|path| !path.exists()) {
+            progress(format!("{} {} does not exist on this host", setting, path.display()));
+        }
+    }
+
+    let mut names: Vec<String> = snapshot.workspaces.keys().cloned().collect();
+    names.sort();
+    let mut report = ImportReport {
+        config_path,
+        workspaces: names.len(),
+        ..ImportReport::default()
+    };
+    if !options.clone {
+        return Ok(report);
+    }
+    for (i, name) in names.iter().enumerate() {
+        let step = format!("[{}/{}] {}", i + 1, names.len(), name);
+        if manager.repo_path(name).exists() {
+            progress(format!("{}: already cloned, skipped", step));
+            report.skipped.push(name.clone());
+            continue;
+        }
+        let url = &snapshot.workspaces[name].repo_url;
+        progress(format!("{}: cloning {}", step, url));
+        match clone_workspace(&manager, name, url) {
+            Ok(()) => {
+                progress(format!("{}: done", step));
+                report.cloned.push(name.clone());
+            }
+            Err(e) => {
+                let _ = fs::remove_dir_all(manager.workspace_path(name));
+                manager.invalidate_workspace_status(name);
+                progress(format!("{}: failed: {}", step, e));
+                report.failed.push(name.clone());
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The steps of creating a workspace, for one already configured
+fn clone_workspace(config: &ConfigManager, name: &str, url: &str) -> Result<(), String> {
+    let repo_path = config.repo_path(name);
+    config.begin_clone(name).map_err(|e| e.to_string())?;
+    git_ops::clone_repo(url, &repo_path).map_err(|e| e.to_string())?;
+    fs::create_dir_all(config.worktrees_path(name)).map_err(|e| e.to_string())?;
+    if git_ops::repo_uses_lfs(&repo_path) {
+        if git_ops::lfs_available() {
+            if let Err(e) = git_ops::lfs_install(&repo_path) {
+                log::warn!("Failed to set up Git LFS for workspace {}: {}", name, e);
+            }
+        } else {
+            log::warn!("Workspace {} uses Git LFS but git-lfs is not installed", name);
+        }
+    }
+    config.scope_git(name);
+    config.finish_clone(name);
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! HTTP caching: ETags that let clients polling a file get `304 Not
+//! Modified` instead of its content again (and saving one check it is still
+//! the revision they read), and `Cache-Control` for the web UI's files, whose
+//! hashed bundles never change while `index.html` does.
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::HttpRequest;
+use futures::future::{ready, LocalBoxFuture, Ready};
+
+use crate::assets;
+
+/// Strong ETag for a content hash
+pub fn etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Whether `If-None-Match` of `req` names `etag`, so the client's copy is
+/// current
+pub fn is_fresh(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|tag| tag.trim())
+                // Weak comparison, as If-None-Match calls for
+                .map(|tag| tag.strip_prefix("W/").unwrap_or(tag))
+                .any(|tag| tag == etag || tag == "*")
+        })
+}
+
+/// Whether `If-Match` of `req`, if it has one, holds for a resource whose
+/// current ETag is `etag` (None when it doesn't exist)
+pub fn if_match(req: &HttpRequest, etag: Option<&str>) -> bool {
+    let Some(header) = req.headers().get(header::IF_MATCH) else {
+        return true;
+    };
+    let Some(etag) = etag else {
+        return false;
+    };
+    header.to_str().is_ok_and(|v| {
+        // Strong comparison: a weak tag never matches
+        v.split(',').map(|tag| tag.trim()).any(|tag| tag == etag || tag == "*")
+    })
+}
+
+/// `Cache-Control` for a file of the web UI
+pub fn ui_cache_control(path: &str) -> &'static str {
+    if assets::is_hashed_name(path) {
+        "public, max-age=31536000, immutable"
+    } else {
+        // Unhashed names (index.html, bundle.js) change in place
+        "no-cache"
+    }
+}
+
+/// Middleware adding `Cache-Control` to web UI responses that don't set it
+pub struct UiCacheControl;
+
+impl<S, B> Transform<S, ServiceRequest> for UiCacheControl
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = UiCacheControlMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(UiCacheControlMiddleware { service }))
+    }
+}
+
+pub struct UiCacheControlMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for UiCacheControlMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let path = req.path();
+        let cache_control = (path != "/ws" && path != "/api" && !path.starts_with("/api/") && !path.starts_with("/raw/"))
+            .then(|| ui_cache_control(path));
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let status = res.status();
+            if let Some(cache_control) = cache_control {
+                if (status.is_success() || status == actix_web::http::StatusCode::NOT_MODIFIED)
+                    && !res.headers().contains_key(header::CACHE_CONTROL)
+                {
+                    res.headers_mut()
+                        .insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+                }
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,253 @@
+//! What this server build and its config support, for the frontend to hide
+//! the controls of features it can't use instead of showing errors.
+//! Optional features are listed once, in `FEATURES`, each with the route it
+//! is used through, so the flags can't drift from what is mounted: the API
+//! tests call every listed route.
+
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::api;
+use crate::auth;
+use crate::config::ConfigManager;
+use crate::WsMessage;
+
+/// An optional feature, and whether this server offers it
+pub struct Feature {
+    pub name: &'static str,
+    /// `METHOD /path` the feature is used through, with `{...}` for path
+    /// parameters; None for what has no route of its own
+    pub route: Option<&'static str>,
+    enabled: fn(&ConfigManager) -> bool,
+}
+
+fn always(_: &ConfigManager) -> bool {
+    true
+}
+
+fn writable(config: &ConfigManager) -> bool {
+    !config.read_only()
+}
+
+pub const FEATURES: &[Feature] = &[
+    Feature {
+        name: "terminals",
+        route: Some("GET /ws"),
+        enabled: writable,
+    },
+    Feature {
+        name: "transcripts",
+        route: Some("GET /ws"),
+        enabled: writable,
+    },
+    Feature {
+        name: "session_scrollback",
+        route: Some("GET /api/v1/sessions/{id}/buffer"),
+        enabled: |config| !config.read_only() && config.get_session_scrollback_bytes() > 0,
+    },
+    Feature {
+        name: "block_runs",
+        route: Some("POST /api/v1/workspaces/{name}/branches/{branch}/blocks/run"),
+        enabled: writable,
+    },
+    Feature {
+        name: "recorded_output",
+        route: Some("POST /api/v1/workspaces/{name}/branches/{branch}/blocks/run"),
+        enabled: writable,
+    },
+    Feature {
+        name: "approvals",
+        route: Some("POST /api/v1/approvals"),
+        enabled: writable,
+    },
+    Feature {
+        name: "execution_history",
+        route: Some("GET /api/v1/workspaces/{name}/executions"),
+        enabled: always,
+    },
+    Feature {
+        name: "checklists",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/checklist"),
+        enabled: always,
+    },
+    Feature {
+        name: "schedules",
+        route: Some("GET /api/v1/schedules"),
+        enabled: writable,
+    },
+    Feature {
+        name: "notifications",
+        route: Some("GET /api/v1/notifications/status"),
+        enabled: |config| !config.get_notifications().webhooks.is_empty(),
+    },
+    Feature {
+        name: "search",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/search"),
+        enabled: always,
+    },
+    Feature {
+        name: "branch_search",
+        route: Some("GET /api/v1/workspaces/{name}/search"),
+        enabled: always,
+    },
+    Feature {
+        name: "render",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/file/render"),
+        enabled: always,
+    },
+    Feature {
+        name: "lint",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/lint"),
+        enabled: always,
+    },
+    Feature {
+        name: "raw_files",
+        route: Some("GET /raw/{workspace}/{branch}/{path}"),
+        enabled: always,
+    },
+    Feature {
+        name: "html_export",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/export"),
+        enabled: always,
+    },
+    Feature {
+        name: "archives",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/archive"),
+        enabled: always,
+    },
+    Feature {
+        name: "file_patches",
+        route: Some("PATCH /api/v1/workspaces/{name}/branches/{branch}/file"),
+        enabled: writable,
+    },
+    Feature {
+        name: "drafts",
+        route: Some("PUT /api/v1/workspaces/{name}/branches/{branch}/file/draft"),
+        enabled: writable,
+    },
+    Feature {
+        name: "trash",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/trash"),
+        enabled: always,
+    },
+    Feature {
+        name: "conflict_resolution",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/conflicts"),
+        enabled: writable,
+    },
+    Feature {
+        name: "detached_worktrees",
+        route: Some("POST /api/v1/workspaces/{name}/branches"),
+        enabled: writable,
+    },
+    Feature {
+        name: "activity",
+        route: Some("GET /api/v1/workspaces/{name}/branches/{branch}/activity"),
+        enabled: always,
+    },
+    Feature {
+        name: "events",
+        route: Some("GET /api/v1/events"),
+        enabled: always,
+    },
+    Feature {
+        name: "bulk_operations",
+        route: Some("POST /api/v1/admin/workspaces/bulk"),
+        enabled: writable,
+    },
+    Feature {
+        name: "config_export",
+        route: Some("GET /api/v1/admin/export"),
+        enabled: always,
+    },
+    Feature {
+        name: "server_logs",
+        route: Some("GET /api/v1/admin/logs"),
+        enabled: always,
+    },
+    Feature {
+        name: "embedded_assets",
+        route: None,
+        enabled: |_| cfg!(feature = "embed-assets"),
+    },
+    Feature {
+        name: "compression",
+        route: None,
+        enabled: |config| config.get_compress_responses(),
+    },
+];
+
+#[derive(Debug, Serialize)]
+pub struct Limits {
+    /// Largest file opened as text
+    pub max_read_bytes: u64,
+    /// Largest file saved
+    pub max_save_bytes: usize,
+    /// Largest JSON request body
+    pub max_request_bytes: usize,
+    /// Output kept of a block run recorded into its runbook
+    pub max_recorded_output_bytes: usize,
+    /// Terminal sessions open at once; None for no limit
+    pub max_sessions: Option<usize>,
+    /// Time an idle terminal session is kept; None as it isn't closed
+    pub session_idle_timeout_secs: Option<u64>,
+    /// Latest output kept of each terminal session; 0 for none
+    pub session_scrollback_bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebSocketProtocol {
+    pub path: &'static str,
+    /// Subprotocol the handshake accepts
+    pub subprotocol: &'static str,
+    /// Prefix of the subprotocol a token can be offered as
+    pub token_subprotocol_prefix: &'static str,
+    /// Whether `?token=` is accepted on `/ws`
+    pub query_token: bool,
+    /// `type`s of the messages a client may send
+    pub client_messages: &'static [&'static str],
+}
+
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub version: &'static str,
+    pub api_versions: &'static [&'static str],
+    pub current_api_version: &'static str,
+    pub read_only: bool,
+    pub features: BTreeMap<&'static str, bool>,
+    pub limits: Limits,
+    pub websocket: WebSocketProtocol,
+}
+
+pub fn capabilities(config: &ConfigManager) -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        api_versions: api::SUPPORTED_VERSIONS,
+        current_api_version: api::SUPPORTED_VERSIONS[api::SUPPORTED_VERSIONS.len() - 1],
+        read_only: config.read_only(),
+        features: FEATURES.iter().map(|feature| (feature.name, (feature.enabled)(config))).collect(),
+        limits: Limits {
+            max_read_bytes: config.get_max_read_bytes(),
+            max_save_bytes: config.get_max_save_bytes(),
+            max_request_bytes: config.get_max_request_bytes(),
+            max_recorded_output_bytes: config.get_max_recorded_output_bytes(),
+            max_sessions: None,
+            session_idle_timeout_secs: None,
+            session_scrollback_bytes: config.get_session_scrollback_bytes(),
+        },
+        websocket: WebSocketProtocol {
+            path: "/ws",
+            subprotocol: auth::WS_PROTOCOL,
+            token_subprotocol_prefix: auth::TOKEN_PROTOCOL_PREFIX,
+            query_token: config.ws_query_token(),
+            client_messages: WsMessage::KINDS,
+        },
+    }
+}
+
+/// GET /api/capabilities - Server version, optional features and limits
+pub async fn get_capabilities(config: web::Data<Arc<ConfigManager>>) -> HttpResponse {
+    HttpResponse::Ok().json(capabilities(&config))
+}
@@ -0,0 +1,146 @@
+//! Runbook checklists: the task list items (`- [ ]` / `- [x]`) of a markdown
+//! file as steps that responders tick off during an incident. Ticking one
+//! rewrites its checkbox in the file, so the state is versioned with the
+//! document, and records who did it in the execution history. A toggle names
+//! the file revision (`file_hash`) it was made against and is refused if the
+//! file has changed since, so two responders can't undo each other's ticks.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::blocks;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::file_ops;
+use crate::history;
+use crate::workspace::{self, FileQuery};
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct ToggleStepRequest {
+    /// Index of the step in the checklist
+    pub step: usize,
+    pub checked: bool,
+    /// `file_hash` of the checklist the toggle was made against
+    pub file_hash: String,
+}
+
+fn checklist_body(path: &str, content: &str) -> serde_json::Value {
+    let runbook = blocks::parse(content);
+    serde_json::json!({
+        "path": path,
+        "file_hash": blocks::content_hash(content),
+        "headings": runbook.headings,
+        "steps": runbook.tasks
+    })
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/checklist?path=x - Task list
+/// items of a markdown file as steps, with its headings as an outline
+pub async fn get_checklist(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    let content = workspace::read_text_file(&config, &worktree_path, &query.path)?;
+    Ok(HttpResponse::Ok().json(checklist_body(&query.path, &content)))
+}
+
+/// Rewrite one step's checkbox, if the file is still at `expected_hash`.
+/// Returns the new content and the step's text.
+fn toggle(
+    config: &ConfigManager,
+    worktree_path: &Path,
+    path: &str,
+    toggle: &ToggleStepRequest,
+) -> Result<(String, String), ApiError> {
+    let content = workspace::read_text_file(config, worktree_path, path)?;
+    let current_hash = blocks::content_hash(&content);
+    if current_hash != toggle.file_hash {
+        return Err(ApiError::conflict(
+            "file_changed",
+            format!("'{}' changed since the checklist was read", path),
+        )
+        .with_details(serde_json::json!({ "file_hash": current_hash })));
+    }
+
+    let runbook = blocks::parse(&content);
+    let Some(task) = runbook.tasks.get(toggle.step) else {
+        return Err(ApiError::not_found(
+            "step_not_found",
+            format!("'{}' has no checklist step {}", path, toggle.step),
+        )
+        .with_details(serde_json::json!({ "step_count": runbook.tasks.len() })));
+    };
+
+    let mark = if toggle.checked { "x" } else { " " };
+    let mut updated = content.clone();
+    updated.replace_range(task.mark_offset..task.mark_offset + 1, mark);
+    file_ops::write_file(worktree_path, path, &updated).map_err(|e| ApiError::io("Failed to save file", e))?;
+    Ok((updated, task.text.clone()))
+}
+
+/// PUT /api/workspaces/{name}/branches/{branch}/checklist?path=x - Tick or
+/// untick a step
+pub async fn toggle_step(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+    body: Json<ToggleStepRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    // Check and write under one lock, or two toggles against the same
+    // revision could both pass the check
+    let (updated, text) = {
+        let _guard = state.file_write_lock.lock().unwrap();
+        toggle(&config, &worktree_path, &query.path, &body)?
+    };
+    log::info!(
+        "Step {} of {} {}",
+        body.step,
+        query.path,
+        if body.checked { "ticked" } else { "unticked" }
+    );
+
+    let token = audit::Actor::from_request(&req).token;
+    let execution = history::Execution {
+        id: Uuid::new_v4().to_string(),
+        timestamp: Utc::now(),
+        token,
+        branch: branch.clone(),
+        path: query.path.clone(),
+        file_hash: body.file_hash.clone(),
+        kind: history::ExecutionKind::ChecklistToggle {
+            step_index: body.step,
+            checked: body.checked,
+            text,
+        },
+    };
+    history::record(&state, &config, &workspace, execution, None).await;
+    audit::record(
+        &req,
+        "checklist.toggle",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "path": query.path,
+            "step": body.step,
+            "checked": body.checked
+        }),
+    );
+
+    Ok(HttpResponse::Ok().json(checklist_body(&query.path, &updated)))
+}
@@ -0,0 +1,132 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::logging::LogFormat;
+
+/// Interactive runbook server with embedded terminal sessions
+///
+/// Options can also be given through environment variables; command line
+/// arguments take precedence over the environment, which takes precedence
+/// over values in the config file.
+#[derive(Debug, Parser)]
+#[command(name = "runotepad", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Address to listen on [default: 0.0.0.0]
+    #[arg(long, env = "RUNOTEPAD_BIND")]
+    pub bind: Option<String>,
+
+    /// Port to listen on [default: 8080]
+    #[arg(long, env = "RUNOTEPAD_PORT")]
+    pub port: Option<u16>,
+
+    /// Also listen on this Unix domain socket
+    #[arg(long, env = "RUNOTEPAD_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Octal permissions of the Unix socket [default: 660]
+    #[arg(long, env = "RUNOTEPAD_UNIX_SOCKET_MODE", requires = "unix_socket")]
+    pub unix_socket_mode: Option<String>,
+
+    /// Listen on the Unix socket only, without a TCP listener
+    #[arg(long, env = "RUNOTEPAD_NO_TCP")]
+    pub no_tcp: bool,
+
+    /// HTTP worker threads [default: one per CPU]
+    #[arg(long, env = "RUNOTEPAD_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// Seconds an idle connection is kept open; 0 disables keep-alive
+    /// [default: 5]
+    #[arg(long, env = "RUNOTEPAD_KEEP_ALIVE_SECS")]
+    pub keep_alive_secs: Option<u64>,
+
+    /// Seconds a client has to send a request's headers [default: 5]
+    #[arg(long, env = "RUNOTEPAD_CLIENT_REQUEST_TIMEOUT_SECS")]
+    pub client_request_timeout_secs: Option<u64>,
+
+    /// Concurrent connections each worker accepts [default: 25000]
+    #[arg(long, env = "RUNOTEPAD_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+
+    /// Directory holding cloned workspaces
+    /// [default: workspaces/ next to the config file]
+    #[arg(long, env = "RUNOTEPAD_WORKSPACE_DIR")]
+    pub workspace_dir: Option<PathBuf>,
+
+    /// Path to the config file [default: ~/.runotepad/config.json]
+    #[arg(long, env = "RUNOTEPAD_CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+
+    /// Access token, replacing the one stored in the config file
+    #[arg(long, env = "RUNOTEPAD_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// Append-only audit log of mutating operations
+    /// [default: audit.jsonl next to the config file]
+    #[arg(long, env = "RUNOTEPAD_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+
+    /// File the key sealing workspace secrets is derived from; created on
+    /// first use [default: secret.key next to the config file]
+    #[arg(long, env = "RUNOTEPAD_SECRET_KEY_FILE")]
+    pub secret_key_file: Option<PathBuf>,
+
+    /// Directory below which existing repositories may be imported as
+    /// workspaces [default: imports disabled]
+    #[arg(long, env = "RUNOTEPAD_IMPORT_ROOT")]
+    pub import_root: Option<PathBuf>,
+
+    /// Directory holding the web UI (index.html and bundles)
+    /// [default: static/ next to the executable or in the current directory]
+    #[arg(long, env = "RUNOTEPAD_STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+
+    /// Serve reads only: refuse every change and every terminal
+    #[arg(long, env = "RUNOTEPAD_READ_ONLY")]
+    pub read_only: bool,
+
+    /// Generate a new secret for the `default` token, print it and exit
+    #[arg(long, conflicts_with = "token")]
+    pub rotate_token: bool,
+
+    /// PEM certificate chain; enables HTTPS together with --tls-key
+    #[arg(long, env = "RUNOTEPAD_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "RUNOTEPAD_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Log filter, e.g. `info` or `runotepad=debug,actix_web=info`
+    /// [default: RUST_LOG, then debug]
+    #[arg(long, env = "RUNOTEPAD_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Log line format; `json` writes one object per line with the request
+    /// or terminal session ID as separate fields
+    #[arg(long, env = "RUNOTEPAD_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Check git, the config, the token, the workspaces, the listeners and
+    /// the TLS files, print a table of the results and exit nonzero if any
+    /// check fails
+    Doctor,
+    /// Write the config from a bundle of `GET /api/admin/export`, sealing
+    /// its secrets (if it has them) with this host's key
+    Import {
+        /// Bundle to import
+        file: PathBuf,
+        /// Then clone every workspace, one after the other
+        #[arg(long)]
+        clone: bool,
+        /// Replace an existing config file, keeping it as config.json.bak
+        #[arg(long)]
+        force: bool,
+    },
+}
@@ -0,0 +1,107 @@
+//! Commit message policy of a workspace, for repositories whose changelog
+//! tooling expects a convention such as conventional commits with a ticket
+//! reference. `commit_message_pattern` is a regex that commit messages made
+//! through the API must contain a match of (anchor it with `^`/`$` to match
+//! the whole message; `(?m)` makes those match at line ends).
+//! `commit_message_template` prefills the UI's commit form, with these
+//! placeholders:
+//!
+//! - `{branch}`: the branch being committed to
+//! - `{files}`: the changed files, e.g. `a.md, b.md and 3 more`
+//!
+//! With `commit_message_advisory` a message that doesn't match is committed
+//! anyway and the response carries a `warning` instead.
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::branch_naming;
+use crate::config::WorkspaceConfig;
+use crate::errors::ApiError;
+
+const PLACEHOLDERS: [&str; 2] = ["branch", "files"];
+
+/// Files named in `{files}` before the rest are only counted
+const LISTED_FILES: usize = 3;
+
+/// A workspace's policy, as the detail endpoint shows it
+#[derive(Debug, Serialize)]
+pub struct CommitMessagePolicy {
+    pub pattern: Option<String>,
+    pub template: Option<String>,
+    pub advisory: bool,
+}
+
+impl CommitMessagePolicy {
+    pub fn of(ws_config: &WorkspaceConfig) -> Self {
+        Self {
+            pattern: ws_config.commit_message_pattern.clone(),
+            template: ws_config.commit_message_template.clone(),
+            advisory: ws_config.commit_message_advisory,
+        }
+    }
+}
+
+/// 400 `invalid_commit_pattern` unless `pattern` is a valid regex
+pub fn check_pattern(pattern: &str) -> Result<(), ApiError> {
+    Regex::new(pattern).map(|_| ()).map_err(|e| {
+        ApiError::validation("invalid_commit_pattern", format!("Invalid commit message pattern: {}", e))
+    })
+}
+
+/// 400 `invalid_commit_template` unless `template` only uses known
+/// placeholders
+pub fn check_template(template: &str) -> Result<(), ApiError> {
+    branch_naming::check_placeholders(template, &PLACEHOLDERS)
+        .map_err(|message| ApiError::validation("invalid_commit_template", message))
+}
+
+/// `files` as `a.md, b.md and 3 more`
+fn files_summary(files: &[String]) -> String {
+    match files {
+        [] => String::new(),
+        [only] => only.clone(),
+        _ if files.len() <= LISTED_FILES => {
+            let (last, rest) = files.split_last().unwrap();
+            format!("{} and {}", rest.join(", "), last)
+        }
+        _ => format!("{} and {} more", files[..LISTED_FILES].join(", "), files.len() - LISTED_FILES),
+    }
+}
+
+/// The workspace's template filled in for a commit of `files` to `branch`,
+/// if it has one
+pub fn fill_template(ws_config: &WorkspaceConfig, branch: &str, files: &[String]) -> Option<String> {
+    let template = ws_config.commit_message_template.as_ref()?;
+    Some(template.replace("{branch}", branch).replace("{files}", &files_summary(files)))
+}
+
+/// Check `message` against the workspace's pattern. A message that
+/// doesn't match is 400 `commit_message_policy`, with the `pattern` and
+/// the filled-in template as an `example`; in an advisory workspace it is
+/// let through and the warning returned instead.
+pub fn check(
+    ws_config: &WorkspaceConfig,
+    message: &str,
+    branch: &str,
+    files: &[String],
+) -> Result<Option<String>, ApiError> {
+    let Some(pattern) = &ws_config.commit_message_pattern else {
+        return Ok(None);
+    };
+    let regex = Regex::new(pattern).map_err(|e| {
+        log::error!("Invalid commit message pattern '{}' in the config: {}", pattern, e);
+        ApiError::internal("The workspace's commit message pattern is invalid")
+    })?;
+    if regex.is_match(message) {
+        return Ok(None);
+    }
+    let warning = format!("Commit message doesn't match the workspace's pattern '{}'", pattern);
+    if ws_config.commit_message_advisory {
+        return Ok(Some(warning));
+    }
+    Err(ApiError::validation("commit_message_policy", warning).with_details(serde_json::json!({
+        "pattern": pattern,
+        "example": fill_template(ws_config, branch, files)
+    })))
+}
@@ -1,115 +1,1635 @@
 use chrono::{DateTime, Utc};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::file_ops;
+use crate::git_ops;
+use crate::git_scope::{self, Credential};
+use crate::secrets::SecretKey;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkspaceConfig {
     pub repo_url: String,
     pub base_branch: String,
     pub created_at: DateTime<Utc>,
+    /// Environment variables of terminals and runs in the workspace
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Like `env`, but the values are sealed with the server's secret key
+    /// (see `secrets`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secret_env: BTreeMap<String, String>,
+    /// Disk space (in bytes) the workspace may take, instead of
+    /// `max_workspace_size_bytes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_bytes: Option<u64>,
+    /// Remote pushes go to [default: origin]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_remote: Option<String>,
+    /// Remote pulls and rebases take the base branch from [default: origin]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_remote: Option<String>,
+    /// Branches the API won't commit to, push, rename or delete without an
+    /// override [default: the base branch]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protected_branches: Option<Vec<String>>,
+    /// Regex branch names created or renamed through the API must match
+    /// (see `branch_naming`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_name_pattern: Option<String>,
+    /// Template of generated branch names, e.g. `runbook/{user}/{slug}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_name_template: Option<String>,
+    /// Regex commit messages made through the API must contain a match of
+    /// (see `commit_message`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_message_pattern: Option<String>,
+    /// Template the UI prefills commit messages with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_message_template: Option<String>,
+    /// Commit messages that don't match the pattern only get a warning
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub commit_message_advisory: bool,
+    /// Key commits are signed with: the path of an SSH key, or the ID of a
+    /// GPG key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key_path: Option<String>,
+    /// `ssh` or `gpg` [default: gpg]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_format: Option<git_ops::SigningFormat>,
+    /// Author and committer name of commits [default: the server user's]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_user_name: Option<String>,
+    /// Author and committer email of commits [default: the server user's]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_user_email: Option<String>,
+    /// User git gives remotes asking for credentials (see `git_scope`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_username: Option<String>,
+    /// Password or token for `git_username`, sealed with the server's secret
+    /// key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_password: Option<String>,
+    /// Fields this version doesn't know, kept so saving doesn't drop them
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl WorkspaceConfig {
+    pub fn push_remote(&self) -> &str {
+        self.push_remote.as_deref().unwrap_or(DEFAULT_REMOTE)
+    }
+
+    pub fn upstream_remote(&self) -> &str {
+        self.upstream_remote.as_deref().unwrap_or(DEFAULT_REMOTE)
+    }
+
+    pub fn protected_branches(&self) -> Vec<String> {
+        match &self.protected_branches {
+            Some(branches) => branches.clone(),
+            None => vec![self.base_branch.clone()],
+        }
+    }
+
+    /// How the workspace's commits are signed, if they are
+    pub fn signing(&self) -> Option<git_ops::Signing> {
+        Some(git_ops::Signing {
+            format: self.signing_format.unwrap_or_default(),
+            key: self.signing_key_path.clone()?,
+        })
+    }
+
+    pub fn is_protected(&self, branch: &str) -> bool {
+        match &self.protected_branches {
+            Some(branches) => branches.iter().any(|protected| protected == branch),
+            None => self.base_branch == branch,
+        }
+    }
+}
+
+/// Remote a workspace is cloned from, and pushed to and pulled from unless
+/// it names others
+pub const DEFAULT_REMOTE: &str = "origin";
+
+/// Disk space a workspace was found to take
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WorkspaceUsage {
+    pub bytes: u64,
+    pub scanned_at: DateTime<Utc>,
+}
+
+/// Whether a configured workspace is usable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceStatus {
+    Ok,
+    /// The initial clone is still running
+    Cloning,
+    /// Listed in the config but its directory is gone, e.g. a workspace
+    /// directory under /tmp cleared by a reboot
+    MissingOnDisk,
+    /// The workspace directory exists but the repository inside it doesn't
+    RepoMissing,
+    /// Git doesn't recognize the repository, or its clone was interrupted
+    RepoCorrupt,
+    /// Worktrees on disk and those registered with git don't match
+    WorktreesInconsistent,
+    /// The repository stores files in Git LFS but git-lfs isn't installed,
+    /// so worktrees hold pointer files instead of their content
+    LfsUnavailable,
+    /// Commits are to be signed, but the key or the program signing with it
+    /// is missing
+    SigningUnavailable,
+}
+
+impl WorkspaceStatus {
+    /// Whether git operations on the repository itself can be attempted
+    pub fn repo_usable(self) -> bool {
+        matches!(
+            self,
+            Self::Ok | Self::WorktreesInconsistent | Self::LfsUnavailable | Self::SigningUnavailable
+        )
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Ok => "workspace is healthy",
+            Self::Cloning => "repository is still being cloned",
+            Self::MissingOnDisk => "workspace directory is missing on disk",
+            Self::RepoMissing => "repository directory is missing",
+            Self::RepoCorrupt => "repository is corrupt or its clone was interrupted",
+            Self::WorktreesInconsistent => "worktrees on disk don't match those registered with git",
+            Self::LfsUnavailable => "repository uses Git LFS but git-lfs is not installed, so LFS files are only pointers",
+            Self::SigningUnavailable => "commits are to be signed but the signing key or program is missing",
+        }
+    }
+}
+
+/// File in a workspace directory while its clone runs, holding the server's
+/// process ID so a clone cut short by a restart can be told apart
+const CLONING_MARKER: &str = ".cloning";
+
+/// Name workspace `git_password` values are sealed under
+pub const GIT_PASSWORD_SECRET: &str = "git_password";
+/// How long a computed workspace status is reused
+const STATUS_TTL: Duration = Duration::from_secs(30);
+
+/// What a token may do: `read` covers every GET, `read_write` everything
+/// including terminals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    Read,
+    #[default]
+    ReadWrite,
+}
+
+impl TokenScope {
+    pub fn allows(self, required: TokenScope) -> bool {
+        self == TokenScope::ReadWrite || required == TokenScope::Read
+    }
+}
+
+/// The token a request authenticated with
+#[derive(Debug, Clone)]
+pub struct TokenIdentity {
+    pub name: String,
+    pub scope: TokenScope,
+    /// Workspaces the token is limited to; empty for all
+    pub workspaces: Vec<String>,
+}
+
+impl TokenIdentity {
+    /// Whether the token is limited to some workspaces
+    pub fn is_restricted(&self) -> bool {
+        !self.workspaces.is_empty()
+    }
+
+    pub fn allows_workspace(&self, workspace: &str) -> bool {
+        !self.is_restricted() || self.workspaces.iter().any(|allowed| allowed == workspace)
+    }
+}
+
+/// A named API token. Only a salted hash of the secret is stored; the
+/// cleartext is handed out once when the token is created or rotated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    /// `sha256$<salt>$<digest>`, both hex encoded
+    #[serde(default)]
+    pub hash: String,
+    /// Plaintext secret written by older versions; hashed on load
+    #[serde(default, skip_serializing)]
+    pub token: Option<String>,
+    /// Tokens from before scopes existed keep full access
+    #[serde(default)]
+    pub scope: TokenScope,
+    /// Workspaces the token may use; empty for all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub workspaces: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ApiToken {
+    fn new(name: &str, secret: &str, scope: TokenScope) -> Self {
+        Self {
+            name: name.to_string(),
+            hash: hash_token(secret),
+            token: None,
+            scope,
+            workspaces: Vec::new(),
+            created_at: Utc::now(),
+            last_used: None,
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    fn matches(&self, secret: &str) -> bool {
+        verify_token_hash(&self.hash, secret)
+    }
+}
+
+/// Where and how events are sent as webhooks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Seconds a delivery attempt may take [default: 10]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// Attempts per event before it is counted as failed [default: 3]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+}
+
+impl NotificationsConfig {
+    fn is_empty(&self) -> bool {
+        self.webhooks.is_empty() && self.timeout_secs.is_none() && self.max_attempts.is_none()
+    }
+}
+
+/// Seconds each kind of background job may run; also the limit of the
+/// same operation run within a request with `?sync=true`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobTimeoutsConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clone_workspace: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebase: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prune: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleanup_merged: Option<u64>,
+}
+
+impl JobTimeoutsConfig {
+    fn is_empty(&self) -> bool {
+        self.clone_workspace.is_none()
+            && self.fetch.is_none()
+            && self.pull.is_none()
+            && self.push.is_none()
+            && self.rebase.is_none()
+            && self.verify.is_none()
+            && self.prune.is_none()
+            && self.cleanup_merged.is_none()
+    }
+}
+
+/// Requests per minute each token (or, without one, each address) may make;
+/// 0 turns a limit off
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitsConfig {
+    /// Cheap reads, such as reading a file [default: 1200]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reads_per_minute: Option<u32>,
+    /// Reads that walk or diff a worktree: file listings, searches, diffs,
+    /// status, lint, export and archives [default: 300]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expensive_reads_per_minute: Option<u32>,
+    /// Anything but GET and HEAD [default: 600]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutations_per_minute: Option<u32>,
+}
+
+impl RateLimitsConfig {
+    fn is_empty(&self) -> bool {
+        self.reads_per_minute.is_none()
+            && self.expensive_reads_per_minute.is_none()
+            && self.mutations_per_minute.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Shown in the delivery status instead of the URL, which may hold a
+    /// secret of its own
+    pub name: String,
+    pub url: String,
+    /// Key of the `X-Runotepad-Signature` HMAC; unsigned without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Event names sent [default: all]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+    /// Only events about these workspaces [default: any]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub workspaces: Vec<String>,
+}
+
+/// A runbook run on a cron schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    pub id: String,
+    pub workspace: String,
+    pub branch: String,
+    pub path: String,
+    pub blocks: BlockSelection,
+    /// Five-field cron expression, in UTC
+    pub cron: String,
+    /// Values of the blocks' `{{name}}` variables
+    #[serde(default)]
+    pub variables: serde_json::Map<String, serde_json::Value>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Why the scheduler turned the schedule off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Token that created the schedule; its runs are recorded as this token's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+}
+
+/// Which blocks of a file a schedule runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BlockSelection {
+    /// `"all"`: every runnable block, in order
+    All(AllBlocks),
+    Indices(Vec<usize>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllBlocks {
+    All,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Token metadata that is safe to return from the API
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenInfo {
+    pub name: String,
+    pub scope: TokenScope,
+    pub workspaces: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl From<&ApiToken> for TokenInfo {
+    fn from(token: &ApiToken) -> Self {
+        Self {
+            name: token.name.clone(),
+            scope: token.scope,
+            workspaces: token.workspaces.clone(),
+            created_at: token.created_at,
+            last_used: token.last_used,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub token: String,
+    /// Schema version; files from before versioning count as 0
+    #[serde(default)]
+    pub version: u32,
+    /// Legacy single token; migrated into `tokens` on load and never written back
+    #[serde(default, skip_serializing)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
     #[serde(default)]
     pub workspaces: HashMap<String, WorkspaceConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+    /// Unix domain socket to listen on, besides or instead of TCP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<PathBuf>,
+    /// Octal permissions the socket is created with [default: 660]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_socket_mode: Option<String>,
+    /// Listen on `unix_socket` only
+    #[serde(default)]
+    pub disable_tcp: bool,
+    /// HTTP worker threads [default: one per CPU]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workers: Option<usize>,
+    /// Seconds an idle connection is kept open; 0 closes connections after
+    /// each response [default: 5]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive_secs: Option<u64>,
+    /// Seconds a client has to send a request's headers [default: 5]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_request_timeout_secs: Option<u64>,
+    /// Concurrent connections each worker accepts [default: 25000]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+    /// JSONL audit log of mutating operations [default: audit.jsonl next to
+    /// the config file]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<PathBuf>,
+    /// File the key sealing workspace `secret_env` values is derived from
+    /// [default: secret.key next to the config file]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_key_file: Option<PathBuf>,
+    /// Directory below which existing repositories may be imported as
+    /// workspaces; imports are refused when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import_root: Option<PathBuf>,
+    /// Directory the web UI is served from [default: found next to the
+    /// executable, see `assets::Source::resolve`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_dir: Option<PathBuf>,
+    /// Refuse every change: only reads are served and no terminals open
+    #[serde(default)]
+    pub read_only: bool,
+    /// Accept `/api/console` without a token; for local development only
+    #[serde(default)]
+    pub allow_unauthenticated_console: bool,
+    /// Answer `/api/capabilities` without a token, so a login page can
+    /// adapt before anyone signs in
+    #[serde(default)]
+    pub allow_unauthenticated_capabilities: bool,
+    /// Accept `?token=` on `/ws`; browsers can offer it as a subprotocol
+    /// instead, which keeps it out of URLs
+    #[serde(default = "default_true")]
+    pub ws_query_token: bool,
+    /// Browser console entries kept in memory for `GET /api/console`
+    #[serde(default = "default_console_buffer_entries")]
+    pub console_buffer_entries: usize,
+    /// Least severe server log level kept for `GET /api/admin/logs`
+    #[serde(default = "default_log_buffer_level")]
+    pub log_buffer_level: String,
+    /// Server log records kept in memory for `GET /api/admin/logs`
+    #[serde(default = "default_log_buffer_entries")]
+    pub log_buffer_entries: usize,
+    /// Largest file (in bytes) the text file API will return
+    #[serde(default = "default_max_read_bytes")]
+    pub max_read_bytes: u64,
+    /// Largest request body (in bytes) accepted by the JSON API
+    #[serde(default = "default_max_request_bytes")]
+    pub max_request_bytes: usize,
+    /// Largest request body (in bytes) accepted when saving a file; JSON
+    /// escaping makes this larger than the file itself
+    #[serde(default = "default_max_save_bytes")]
+    pub max_save_bytes: usize,
+    /// Background jobs (clone, fetch, pull, push, rebase) run at once
+    #[serde(default = "default_job_workers")]
+    pub job_workers: usize,
+    /// Seconds any background job may run before its git command is
+    /// killed, for operations without their own `job_timeouts` entry
+    /// [default: per operation, see `JobKind::default_timeout`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_timeout_secs: Option<u64>,
+    /// Seconds each kind of job may run, overriding `job_timeout_secs`
+    #[serde(default, skip_serializing_if = "JobTimeoutsConfig::is_empty")]
+    pub job_timeouts: JobTimeoutsConfig,
+    /// Worktrees whose git stats a branch listing collects at once
+    #[serde(default = "default_branch_stats_workers")]
+    pub branch_stats_workers: usize,
+    /// Seconds a branch listing waits for one worktree's git stats before
+    /// listing it without them
+    #[serde(default = "default_branch_stats_timeout_secs")]
+    pub branch_stats_timeout_secs: u64,
+    /// Seconds a git lock file must have been left for before the cleanup
+    /// at startup or `POST /api/workspaces/{name}/repair` removes it (and
+    /// then only if no process holds it)
+    #[serde(default = "default_stale_lock_secs")]
+    pub stale_lock_secs: u64,
+    /// Seconds a git operation waits for another one changing the same
+    /// worktree to finish before giving up with 423
+    #[serde(default = "default_worktree_lock_timeout_secs")]
+    pub worktree_lock_timeout_secs: u64,
+    /// Abort rebases, merges, cherry-picks and reverts found half done at
+    /// startup, rather than reporting the branch as needing attention
+    #[serde(default)]
+    pub abort_interrupted_operations: bool,
+    /// Output (in bytes) of a run kept when it is recorded into its runbook
+    #[serde(default = "default_max_recorded_output_bytes")]
+    pub max_recorded_output_bytes: usize,
+    /// Latest output (in bytes) of each terminal session kept for the
+    /// buffer endpoints; 0 keeps none
+    #[serde(default)]
+    pub session_scrollback_bytes: usize,
+    /// Block runs kept in each workspace's execution history
+    #[serde(default = "default_execution_history_max_entries")]
+    pub execution_history_max_entries: usize,
+    /// Days block runs are kept in the execution history [default: no limit]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution_history_max_age_days: Option<u64>,
+    /// Days a file deleted through the API stays in its worktree's trash
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Days an autosaved draft is kept after its last save
+    #[serde(default = "default_draft_retention_days")]
+    pub draft_retention_days: u64,
+    /// Seconds file trees and branch lists are cached for; 0 turns the
+    /// cache off
+    #[serde(default = "default_listing_cache_ttl_secs")]
+    pub listing_cache_ttl_secs: u64,
+    /// Disk space (in bytes) each workspace may take; workspaces can
+    /// override it [default: no limit]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_workspace_size_bytes: Option<u64>,
+    /// Seconds between scans of the disk space taken by workspaces with a
+    /// quota
+    #[serde(default = "default_quota_scan_interval_secs")]
+    pub quota_scan_interval_secs: u64,
+    /// Compress responses for clients that accept it
+    #[serde(default = "default_true")]
+    pub compress_responses: bool,
+    /// Seconds an approval to run a `requires_approval` block stays valid
+    #[serde(default = "default_approval_ttl_secs")]
+    pub approval_ttl_secs: u64,
+    /// Webhooks notified of events
+    #[serde(default, skip_serializing_if = "NotificationsConfig::is_empty")]
+    pub notifications: NotificationsConfig,
+    /// Request budgets of the API
+    #[serde(default, skip_serializing_if = "RateLimitsConfig::is_empty")]
+    pub rate_limits: RateLimitsConfig,
+    /// Runbooks run on a schedule
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<ScheduleConfig>,
+    /// Fields this version doesn't know, kept so saving doesn't drop them
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            token: generate_token(),
+            version: CONFIG_VERSION,
+            token: None,
+            tokens: Vec::new(),
             workspaces: HashMap::new(),
+            workspace_dir: None,
+            bind: None,
+            port: None,
+            tls_cert: None,
+            tls_key: None,
+            unix_socket: None,
+            unix_socket_mode: None,
+            disable_tcp: false,
+            workers: None,
+            keep_alive_secs: None,
+            client_request_timeout_secs: None,
+            max_connections: None,
+            audit_log: None,
+            secret_key_file: None,
+            import_root: None,
+            static_dir: None,
+            read_only: false,
+            allow_unauthenticated_console: false,
+            allow_unauthenticated_capabilities: false,
+            ws_query_token: true,
+            console_buffer_entries: default_console_buffer_entries(),
+            log_buffer_level: default_log_buffer_level(),
+            log_buffer_entries: default_log_buffer_entries(),
+            max_read_bytes: default_max_read_bytes(),
+            max_request_bytes: default_max_request_bytes(),
+            max_save_bytes: default_max_save_bytes(),
+            job_workers: default_job_workers(),
+            job_timeout_secs: None,
+            job_timeouts: JobTimeoutsConfig::default(),
+            branch_stats_workers: default_branch_stats_workers(),
+            branch_stats_timeout_secs: default_branch_stats_timeout_secs(),
+            stale_lock_secs: default_stale_lock_secs(),
+            worktree_lock_timeout_secs: default_worktree_lock_timeout_secs(),
+            abort_interrupted_operations: false,
+            max_recorded_output_bytes: default_max_recorded_output_bytes(),
+            session_scrollback_bytes: 0,
+            execution_history_max_entries: default_execution_history_max_entries(),
+            execution_history_max_age_days: None,
+            trash_retention_days: default_trash_retention_days(),
+            draft_retention_days: default_draft_retention_days(),
+            listing_cache_ttl_secs: default_listing_cache_ttl_secs(),
+            max_workspace_size_bytes: None,
+            quota_scan_interval_secs: default_quota_scan_interval_secs(),
+            compress_responses: true,
+            approval_ttl_secs: default_approval_ttl_secs(),
+            notifications: NotificationsConfig::default(),
+            rate_limits: RateLimitsConfig::default(),
+            schedules: Vec::new(),
+            extra: serde_json::Map::new(),
+        }
+    }
+}
+
+fn default_max_read_bytes() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_max_request_bytes() -> usize {
+    256 * 1024
+}
+
+fn default_max_save_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_job_workers() -> usize {
+    2
+}
+
+fn default_branch_stats_workers() -> usize {
+    8
+}
+
+fn default_branch_stats_timeout_secs() -> u64 {
+    5
+}
+
+fn default_stale_lock_secs() -> u64 {
+    60
+}
+
+fn default_worktree_lock_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_recorded_output_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_execution_history_max_entries() -> usize {
+    1000
+}
+
+fn default_console_buffer_entries() -> usize {
+    1000
+}
+
+fn default_log_buffer_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_buffer_entries() -> usize {
+    1000
+}
+
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
+fn default_draft_retention_days() -> u64 {
+    7
+}
+
+fn default_listing_cache_ttl_secs() -> u64 {
+    10
+}
+
+fn default_quota_scan_interval_secs() -> u64 {
+    300
+}
+
+fn default_approval_ttl_secs() -> u64 {
+    15 * 60
+}
+
+/// Settings supplied on the command line or through the environment, which
+/// take precedence over the config file
+#[derive(Debug, Default, Clone)]
+pub struct ConfigOverrides {
+    pub config_file: Option<PathBuf>,
+    pub workspace_dir: Option<PathBuf>,
+    pub token: Option<String>,
+    pub bind: Option<String>,
+    pub port: Option<u16>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_mode: Option<String>,
+    /// Only ever turns the TCP listener off
+    pub disable_tcp: bool,
+    pub workers: Option<usize>,
+    pub keep_alive_secs: Option<u64>,
+    pub client_request_timeout_secs: Option<u64>,
+    pub max_connections: Option<usize>,
+    pub audit_log: Option<PathBuf>,
+    pub secret_key_file: Option<PathBuf>,
+    pub import_root: Option<PathBuf>,
+    pub static_dir: Option<PathBuf>,
+    /// Only ever turns read-only mode on; the config file can't be overruled
+    /// into accepting changes
+    pub read_only: bool,
+}
+
+/// Settings of the HTTP server itself, applied at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerTuning {
+    pub workers: usize,
+    /// None when connections aren't kept alive
+    pub keep_alive: Option<Duration>,
+    pub client_request_timeout: Duration,
+    /// Per worker
+    pub max_connections: usize,
+}
+
+pub struct ConfigManager {
+    config: RwLock<Config>,
+    config_path: PathBuf,
+    workspace_dir: PathBuf,
+    /// None when the server listens on the Unix socket only
+    bind_address: Option<String>,
+    tls: Option<(PathBuf, PathBuf)>,
+    unix_socket: Option<(PathBuf, u32)>,
+    server_tuning: ServerTuning,
+    audit_log_path: PathBuf,
+    secret_key_path: PathBuf,
+    import_root: Option<PathBuf>,
+    static_dir: Option<PathBuf>,
+    read_only: bool,
+    /// Cleartext of a token generated during startup, shown to the user once
+    generated_token: std::sync::Mutex<Option<String>>,
+    status_cache: std::sync::Mutex<HashMap<String, (Instant, WorkspaceStatus)>>,
+    /// Disk space of each workspace as last scanned
+    usage_cache: std::sync::Mutex<HashMap<String, WorkspaceUsage>>,
+    /// Held while serializing and writing, so concurrent saves land in order
+    save_lock: std::sync::Mutex<()>,
+}
+
+impl ConfigManager {
+    pub fn new(overrides: ConfigOverrides) -> Result<Self, Box<dyn std::error::Error>> {
+        let config_path = overrides.config_file.unwrap_or_else(default_config_path);
+
+        // Ensure config directory exists
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Load or create config
+        let mut dirty = false;
+        let mut config: Config = if config_path.exists() {
+            match read_config(&config_path) {
+                Ok(config) => config,
+                // A backup from before an upgrade would lose the newer data
+                Err(e) if e.is::<UnsupportedVersion>() => return Err(e),
+                Err(e) => {
+                    let backup = backup_path(&config_path);
+                    let config = read_config(&backup).map_err(|_| e.to_string())?;
+                    log::warn!(
+                        "Config file {:?} is unreadable ({}); using the backup {:?}",
+                        config_path,
+                        e,
+                        backup
+                    );
+                    dirty = true;
+                    config
+                }
+            }
+        } else {
+            let config = Config::default();
+            let content = serde_json::to_string_pretty(&config)?;
+            file_ops::atomic_write(&config_path, content.as_bytes())?;
+            log::info!("Created new config file at {:?}", config_path);
+            config
+        };
+
+        dirty |= migrate(&mut config);
+
+        let mut generated_token = None;
+        if config.tokens.is_empty() && overrides.token.is_none() {
+            let secret = generate_token();
+            set_default_token(&mut config, &secret);
+            generated_token = Some(secret);
+            dirty = true;
+        }
+
+        // Persist an explicitly provided token so it survives restarts without the flag
+        if let Some(token) = &overrides.token {
+            dirty |= set_default_token(&mut config, token);
+        }
+
+        let workspace_dir = overrides
+            .workspace_dir
+            .or_else(|| config.workspace_dir.clone())
+            .unwrap_or_else(|| config_path.with_file_name("workspaces"));
+        fs::create_dir_all(&workspace_dir)?;
+
+        let bind = overrides
+            .bind
+            .or_else(|| config.bind.clone())
+            .unwrap_or_else(|| DEFAULT_BIND.to_string());
+        let port = overrides.port.or(config.port).unwrap_or(DEFAULT_PORT);
+        let bind_address = if bind.contains(':') && !bind.starts_with('[') {
+            // Bare IPv6 address
+            format!("[{}]:{}", bind, port)
+        } else {
+            format!("{}:{}", bind, port)
+        };
+
+        let unix_socket = match overrides.unix_socket.or_else(|| config.unix_socket.clone()) {
+            Some(path) => {
+                let mode = overrides
+                    .unix_socket_mode
+                    .or_else(|| config.unix_socket_mode.clone())
+                    .unwrap_or_else(|| DEFAULT_UNIX_SOCKET_MODE.to_string());
+                let mode = u32::from_str_radix(&mode, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or_else(|| format!("Invalid unix socket mode '{}': expected octal like 660", mode))?;
+                Some((path, mode))
+            }
+            None => None,
+        };
+        let bind_address = if overrides.disable_tcp || config.disable_tcp {
+            if unix_socket.is_none() {
+                return Err("TCP can only be disabled when a unix socket is set".into());
+            }
+            None
+        } else {
+            Some(bind_address)
+        };
+
+        let server_tuning = ServerTuning {
+            workers: match overrides.workers.or(config.workers) {
+                Some(0) => return Err("workers must be at least 1".into()),
+                Some(workers) => workers,
+                None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            },
+            keep_alive: match overrides.keep_alive_secs.or(config.keep_alive_secs) {
+                Some(0) => None,
+                secs => Some(Duration::from_secs(secs.unwrap_or(DEFAULT_KEEP_ALIVE_SECS))),
+            },
+            client_request_timeout: match overrides
+                .client_request_timeout_secs
+                .or(config.client_request_timeout_secs)
+            {
+                // actix reads 0 as no timeout, which lets idle clients hold
+                // connections forever
+                Some(0) => return Err("client_request_timeout_secs must be at least 1".into()),
+                secs => Duration::from_secs(secs.unwrap_or(DEFAULT_CLIENT_REQUEST_TIMEOUT_SECS)),
+            },
+            max_connections: match overrides.max_connections.or(config.max_connections) {
+                Some(0) => return Err("max_connections must be at least 1".into()),
+                max => max.unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            },
+        };
+
+        // Certificate and key come as a pair from the same source
+        let tls = match (overrides.tls_cert, overrides.tls_key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => match (&config.tls_cert, &config.tls_key) {
+                (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+                (None, None) => None,
+                _ => return Err("Both tls_cert and tls_key must be set in the config file".into()),
+            },
+            _ => return Err("Both --tls-cert and --tls-key must be given".into()),
+        };
+
+        let audit_log_path = overrides
+            .audit_log
+            .or_else(|| config.audit_log.clone())
+            .unwrap_or_else(|| config_path.with_file_name("audit.jsonl"));
+        let secret_key_path = overrides
+            .secret_key_file
+            .or_else(|| config.secret_key_file.clone())
+            .unwrap_or_else(|| config_path.with_file_name("secret.key"));
+
+        let import_root = overrides.import_root.or_else(|| config.import_root.clone());
+        let static_dir = overrides.static_dir.or_else(|| config.static_dir.clone());
+        let read_only = overrides.read_only || config.read_only;
+
+        let manager = Self {
+            config: RwLock::new(config),
+            config_path,
+            workspace_dir,
+            bind_address,
+            tls,
+            unix_socket,
+            server_tuning,
+            audit_log_path,
+            secret_key_path,
+            import_root,
+            static_dir,
+            read_only,
+            generated_token: std::sync::Mutex::new(generated_token),
+            status_cache: std::sync::Mutex::new(HashMap::new()),
+            usage_cache: std::sync::Mutex::new(HashMap::new()),
+            save_lock: std::sync::Mutex::new(()),
+        };
+
+        if dirty {
+            manager.save()?;
+        }
+
+        for name in manager.get_workspaces().into_keys() {
+            if manager.workspace_path(&name).is_dir() {
+                manager.scope_git(&name);
+            }
+        }
+
+        Ok(manager)
+    }
+
+    /// Certificate and key paths when TLS is enabled
+    pub fn tls_paths(&self) -> Option<(&PathBuf, &PathBuf)> {
+        self.tls.as_ref().map(|(cert, key)| (cert, key))
+    }
+
+    /// Copy of the whole config as it would be saved
+    pub fn snapshot(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn audit_log_path(&self) -> &PathBuf {
+        &self.audit_log_path
+    }
+
+    /// File the key sealing workspace secrets is derived from
+    pub fn secret_key_path(&self) -> &PathBuf {
+        &self.secret_key_path
+    }
+
+    /// Directory local repository imports are confined to, if enabled
+    pub fn import_root(&self) -> Option<&PathBuf> {
+        self.import_root.as_ref()
+    }
+
+    /// Directory to serve the web UI from, if one was configured
+    pub fn static_dir(&self) -> Option<&PathBuf> {
+        self.static_dir.as_ref()
+    }
+
+    /// Whether the server refuses all changes
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Resolved `host:port` the server should listen on, if it listens on
+    /// TCP
+    pub fn bind_address(&self) -> Option<&str> {
+        self.bind_address.as_deref()
+    }
+
+    pub fn server_tuning(&self) -> ServerTuning {
+        self.server_tuning
+    }
+
+    /// Unix socket path and the permissions to create it with
+    pub fn unix_socket(&self) -> Option<(&PathBuf, u32)> {
+        self.unix_socket.as_ref().map(|(path, mode)| (path, *mode))
+    }
+
+    /// Cleartext of the token generated on this start, if any. Only the
+    /// first call returns it; afterwards just the hash exists.
+    pub fn take_generated_token(&self) -> Option<String> {
+        self.generated_token.lock().unwrap().take()
+    }
+
+    /// Check a presented token against every named token and record its
+    /// use; returns which token it is if valid
+    pub fn verify_token(&self, token: &str) -> Option<TokenIdentity> {
+        let now = Utc::now();
+        let (stale, identity) = {
+            let mut config = self.config.write().unwrap();
+            let entry = config.tokens.iter_mut().find(|t| t.matches(token))?;
+            // Only hit the disk when the recorded time is noticeably out of date
+            let stale = entry
+                .last_used
+                .is_none_or(|t| now - t > chrono::Duration::seconds(LAST_USED_PERSIST_SECS));
+            entry.last_used = Some(now);
+            let identity = TokenIdentity {
+                name: entry.name.clone(),
+                scope: entry.scope,
+                workspaces: entry.workspaces.clone(),
+            };
+            (stale, identity)
+        };
+        if stale {
+            if let Err(e) = self.save() {
+                log::warn!("Failed to persist token usage: {}", e);
+            }
+        }
+        Some(identity)
+    }
+
+    /// Name of the token with this secret, if any
+    pub fn token_name(&self, token: &str) -> Option<String> {
+        self.config
+            .read()
+            .unwrap()
+            .tokens
+            .iter()
+            .find(|t| t.matches(token))
+            .map(|t| t.name.clone())
+    }
+
+    /// Replace a token's secret with a freshly generated one, returning the
+    /// new cleartext; `None` if no token has that name
+    pub fn rotate_token(
+        &self,
+        name: &str,
+    ) -> Result<Option<(TokenInfo, String)>, Box<dyn std::error::Error>> {
+        let secret = generate_token();
+        let info = {
+            let mut config = self.config.write().unwrap();
+            let Some(entry) = config.tokens.iter_mut().find(|t| t.name == name) else {
+                return Ok(None);
+            };
+            set_secret(entry, &secret);
+            TokenInfo::from(&*entry)
+        };
+        self.save()?;
+        Ok(Some((info, secret)))
+    }
+
+    /// Rotate the `default` token, recreating it if it was revoked
+    pub fn rotate_default_token(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let secret = generate_token();
+        set_default_token(&mut self.config.write().unwrap(), &secret);
+        self.save()?;
+        Ok(secret)
+    }
+
+    pub fn list_tokens(&self) -> Vec<TokenInfo> {
+        self.config.read().unwrap().tokens.iter().map(TokenInfo::from).collect()
+    }
+
+    /// Mint a new token, limited to `workspaces` unless that is empty,
+    /// returning its cleartext; `None` if the name is already taken
+    pub fn create_token(
+        &self,
+        name: &str,
+        scope: TokenScope,
+        workspaces: Vec<String>,
+    ) -> Result<Option<(TokenInfo, String)>, Box<dyn std::error::Error>> {
+        let secret = generate_token();
+        let info = {
+            let mut config = self.config.write().unwrap();
+            if config.tokens.iter().any(|t| t.name == name) {
+                return Ok(None);
+            }
+            let mut token = ApiToken::new(name, &secret, scope);
+            token.workspaces = workspaces;
+            let info = TokenInfo::from(&token);
+            config.tokens.push(token);
+            info
+        };
+        self.save()?;
+        Ok(Some((info, secret)))
+    }
+
+    /// Replace the workspaces a token is limited to (empty for all);
+    /// `None` if no token has that name
+    pub fn set_token_workspaces(
+        &self,
+        name: &str,
+        workspaces: Vec<String>,
+    ) -> Result<Option<TokenInfo>, Box<dyn std::error::Error>> {
+        let info = {
+            let mut config = self.config.write().unwrap();
+            let Some(entry) = config.tokens.iter_mut().find(|t| t.name == name) else {
+                return Ok(None);
+            };
+            entry.workspaces = workspaces;
+            TokenInfo::from(&*entry)
+        };
+        self.save()?;
+        Ok(Some(info))
+    }
+
+    pub fn revoke_token(&self, name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let removed = {
+            let mut config = self.config.write().unwrap();
+            let before = config.tokens.len();
+            config.tokens.retain(|t| t.name != name);
+            config.tokens.len() != before
+        };
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    pub fn allow_unauthenticated_console(&self) -> bool {
+        self.config.read().unwrap().allow_unauthenticated_console
+    }
+
+    pub fn allow_unauthenticated_capabilities(&self) -> bool {
+        self.config.read().unwrap().allow_unauthenticated_capabilities
+    }
+
+    pub fn ws_query_token(&self) -> bool {
+        self.config.read().unwrap().ws_query_token
+    }
+
+    pub fn get_console_buffer_entries(&self) -> usize {
+        self.config.read().unwrap().console_buffer_entries
+    }
+
+    /// `log_buffer_level` parsed; `info` if it isn't a level
+    pub fn get_log_buffer_level(&self) -> log::LevelFilter {
+        let level = self.config.read().unwrap().log_buffer_level.clone();
+        level.parse().unwrap_or_else(|_| {
+            log::warn!("Invalid log_buffer_level {:?}, using info", level);
+            log::LevelFilter::Info
+        })
+    }
+
+    pub fn get_log_buffer_entries(&self) -> usize {
+        self.config.read().unwrap().log_buffer_entries
+    }
+
+    pub fn get_max_read_bytes(&self) -> u64 {
+        self.config.read().unwrap().max_read_bytes
+    }
+
+    pub fn get_max_request_bytes(&self) -> usize {
+        self.config.read().unwrap().max_request_bytes
+    }
+
+    pub fn get_max_save_bytes(&self) -> usize {
+        self.config.read().unwrap().max_save_bytes
+    }
+
+    /// Read once at startup; changes need a restart
+    pub fn get_job_workers(&self) -> usize {
+        self.config.read().unwrap().job_workers.max(1)
+    }
+
+    pub fn get_job_timeout(&self) -> Option<Duration> {
+        self.config.read().unwrap().job_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn get_job_timeouts(&self) -> JobTimeoutsConfig {
+        self.config.read().unwrap().job_timeouts.clone()
+    }
+
+    pub fn get_branch_stats_workers(&self) -> usize {
+        self.config.read().unwrap().branch_stats_workers.max(1)
+    }
+
+    pub fn get_branch_stats_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.read().unwrap().branch_stats_timeout_secs)
+    }
+
+    pub fn get_stale_lock_age(&self) -> Duration {
+        Duration::from_secs(self.config.read().unwrap().stale_lock_secs)
+    }
+
+    pub fn get_worktree_lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.read().unwrap().worktree_lock_timeout_secs)
+    }
+
+    pub fn abort_interrupted_operations(&self) -> bool {
+        self.config.read().unwrap().abort_interrupted_operations
+    }
+
+    pub fn get_rate_limits(&self) -> RateLimitsConfig {
+        self.config.read().unwrap().rate_limits.clone()
+    }
+
+    pub fn get_notifications(&self) -> NotificationsConfig {
+        self.config.read().unwrap().notifications.clone()
+    }
+
+    pub fn get_approval_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.read().unwrap().approval_ttl_secs)
+    }
+
+    pub fn get_max_recorded_output_bytes(&self) -> usize {
+        self.config.read().unwrap().max_recorded_output_bytes
+    }
+
+    pub fn get_session_scrollback_bytes(&self) -> usize {
+        self.config.read().unwrap().session_scrollback_bytes
+    }
+
+    pub fn get_execution_history_max_entries(&self) -> usize {
+        self.config.read().unwrap().execution_history_max_entries
+    }
+
+    pub fn get_execution_history_max_age_days(&self) -> Option<u64> {
+        self.config.read().unwrap().execution_history_max_age_days
+    }
+
+    pub fn get_trash_retention_days(&self) -> u64 {
+        self.config.read().unwrap().trash_retention_days
+    }
+
+    pub fn get_draft_retention(&self) -> Duration {
+        Duration::from_secs(self.config.read().unwrap().draft_retention_days * 24 * 60 * 60)
+    }
+
+    pub fn get_listing_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.read().unwrap().listing_cache_ttl_secs)
+    }
+
+    pub fn get_compress_responses(&self) -> bool {
+        self.config.read().unwrap().compress_responses
+    }
+
+    pub fn get_quota_scan_interval(&self) -> Duration {
+        Duration::from_secs(self.config.read().unwrap().quota_scan_interval_secs.max(1))
+    }
+
+    /// Disk space (in bytes) a workspace may take, if it is limited
+    pub fn workspace_quota(&self, name: &str) -> Option<u64> {
+        let config = self.config.read().unwrap();
+        config
+            .workspaces
+            .get(name)
+            .and_then(|ws| ws.max_size_bytes)
+            .or(config.max_workspace_size_bytes)
+    }
+
+    pub fn get_workspace_dir(&self) -> &PathBuf {
+        &self.workspace_dir
+    }
+
+    pub fn get_workspaces(&self) -> HashMap<String, WorkspaceConfig> {
+        self.config.read().unwrap().workspaces.clone()
+    }
+
+    pub fn get_workspace(&self, name: &str) -> Option<WorkspaceConfig> {
+        self.config.read().unwrap().workspaces.get(name).cloned()
+    }
+
+    pub fn add_workspace(
+        &self,
+        name: String,
+        repo_url: String,
+        base_branch: String,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.workspaces.insert(
+                name.clone(),
+                WorkspaceConfig {
+                    repo_url,
+                    base_branch,
+                    created_at: Utc::now(),
+                    env: BTreeMap::new(),
+                    secret_env: BTreeMap::new(),
+                    max_size_bytes: None,
+                    push_remote: None,
+                    upstream_remote: None,
+                    protected_branches: None,
+                    branch_name_pattern: None,
+                    branch_name_template: None,
+                    commit_message_pattern: None,
+                    commit_message_template: None,
+                    commit_message_advisory: false,
+                    signing_key_path: None,
+                    signing_format: None,
+                    git_user_name: None,
+                    git_user_email: None,
+                    git_username: None,
+                    git_password: None,
+                    extra: serde_json::Map::new(),
+                },
+            );
+        }
+        self.save()?;
+        self.scope_git(&name);
+        Ok(())
+    }
+
+    pub fn remove_workspace(&self, name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let removed = {
+            let mut config = self.config.write().unwrap();
+            config.workspaces.remove(name).is_some()
+        };
+        if removed {
+            git_scope::unregister(&self.workspace_path(name));
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Replace a workspace's environment; `secret_env` values must already be
+    /// sealed. False if the workspace is missing.
+    pub fn set_workspace_env(
+        &self,
+        name: &str,
+        env: BTreeMap<String, String>,
+        secret_env: BTreeMap<String, String>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        {
+            let mut config = self.config.write().unwrap();
+            let Some(workspace) = config.workspaces.get_mut(name) else {
+                return Ok(false);
+            };
+            workspace.env = env;
+            workspace.secret_env = secret_env;
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Move a workspace entry to a new name; false if `old` is missing or
+    /// `new` is taken
+    pub fn rename_workspace(&self, old: &str, new: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let renamed = {
+            let mut config = self.config.write().unwrap();
+            if config.workspaces.contains_key(new) {
+                false
+            } else if let Some(workspace) = config.workspaces.remove(old) {
+                config.workspaces.insert(new.to_string(), workspace);
+                // Tokens limited to the workspace keep it under its new name
+                for allowed in config.tokens.iter_mut().flat_map(|t| t.workspaces.iter_mut()) {
+                    if allowed == old {
+                        *allowed = new.to_string();
+                    }
+                }
+                true
+            } else {
+                false
+            }
+        };
+        if renamed {
+            git_scope::unregister(&self.workspace_path(old));
+            self.save()?;
+        }
+        Ok(renamed)
+    }
+
+    pub fn update_workspace_base_branch(
+        &self,
+        name: &str,
+        base_branch: String,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let updated = {
+            let mut config = self.config.write().unwrap();
+            if let Some(workspace) = config.workspaces.get_mut(name) {
+                workspace.base_branch = base_branch;
+                true
+            } else {
+                false
+            }
+        };
+        if updated {
+            self.save()?;
         }
+        Ok(updated)
     }
-}
-
-pub struct ConfigManager {
-    config: RwLock<Config>,
-    config_path: PathBuf,
-    workspace_dir: PathBuf,
-}
-
-impl ConfigManager {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_path = get_config_path();
-        let workspace_dir = get_workspace_dir();
 
-        // Ensure directories exist
-        if let Some(parent) = config_path.parent() {
-            fs::create_dir_all(parent)?;
+    /// Set the remotes a workspace pushes to and pulls from; None leaves a
+    /// preference unchanged. False if the workspace is missing.
+    pub fn set_workspace_remotes(
+        &self,
+        name: &str,
+        push_remote: Option<String>,
+        upstream_remote: Option<String>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        // The default isn't written out
+        let preference = |remote: String| (remote != DEFAULT_REMOTE).then_some(remote);
+        {
+            let mut config = self.config.write().unwrap();
+            let Some(workspace) = config.workspaces.get_mut(name) else {
+                return Ok(false);
+            };
+            if let Some(remote) = push_remote {
+                workspace.push_remote = preference(remote);
+            }
+            if let Some(remote) = upstream_remote {
+                workspace.upstream_remote = preference(remote);
+            }
         }
-        fs::create_dir_all(&workspace_dir)?;
-
-        // Load or create config
-        let config = if config_path.exists() {
-            let content = fs::read_to_string(&config_path)?;
-            serde_json::from_str(&content)?
-        } else {
-            let config = Config::default();
-            let content = serde_json::to_string_pretty(&config)?;
-            fs::write(&config_path, content)?;
-            log::info!("Created new config file at {:?}", config_path);
-            log::info!("Access token: {}", config.token);
-            config
-        };
+        self.save()?;
+        Ok(true)
+    }
 
-        Ok(Self {
-            config: RwLock::new(config),
-            config_path,
-            workspace_dir,
-        })
+    /// Replace the branches protected in a workspace. False if the
+    /// workspace is missing.
+    pub fn set_workspace_protected_branches(
+        &self,
+        name: &str,
+        branches: Vec<String>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        {
+            let mut config = self.config.write().unwrap();
+            let Some(workspace) = config.workspaces.get_mut(name) else {
+                return Ok(false);
+            };
+            workspace.protected_branches = Some(branches);
+        }
+        self.save()?;
+        Ok(true)
     }
 
-    pub fn get_token(&self) -> String {
-        self.config.read().unwrap().token.clone()
+    /// Set a workspace's branch name pattern and template; None leaves one
+    /// unchanged and an empty string removes it. False if the workspace is
+    /// missing.
+    pub fn set_workspace_branch_naming(
+        &self,
+        name: &str,
+        pattern: Option<String>,
+        template: Option<String>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let setting = |value: String| (!value.is_empty()).then_some(value);
+        {
+            let mut config = self.config.write().unwrap();
+            let Some(workspace) = config.workspaces.get_mut(name) else {
+                return Ok(false);
+            };
+            if let Some(pattern) = pattern {
+                workspace.branch_name_pattern = setting(pattern);
+            }
+            if let Some(template) = template {
+                workspace.branch_name_template = setting(template);
+            }
+        }
+        self.save()?;
+        Ok(true)
     }
 
-    pub fn verify_token(&self, token: &str) -> bool {
-        self.config.read().unwrap().token == token
+    /// Set a workspace's commit message pattern, template and whether the
+    /// pattern is only advisory; None leaves a setting unchanged and an
+    /// empty string removes the pattern or template. False if the workspace
+    /// is missing.
+    pub fn set_workspace_commit_messages(
+        &self,
+        name: &str,
+        pattern: Option<String>,
+        template: Option<String>,
+        advisory: Option<bool>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let setting = |value: String| (!value.is_empty()).then_some(value);
+        {
+            let mut config = self.config.write().unwrap();
+            let Some(workspace) = config.workspaces.get_mut(name) else {
+                return Ok(false);
+            };
+            if let Some(pattern) = pattern {
+                workspace.commit_message_pattern = setting(pattern);
+            }
+            if let Some(template) = template {
+                workspace.commit_message_template = setting(template);
+            }
+            if let Some(advisory) = advisory {
+                workspace.commit_message_advisory = advisory;
+            }
+        }
+        self.save()?;
+        Ok(true)
     }
 
-    pub fn get_workspace_dir(&self) -> &PathBuf {
-        &self.workspace_dir
+    /// Set the key a workspace's commits are signed with and its format;
+    /// None leaves a setting unchanged and an empty key path stops signing.
+    /// False if the workspace is missing.
+    pub fn set_workspace_signing(
+        &self,
+        name: &str,
+        key_path: Option<String>,
+        format: Option<git_ops::SigningFormat>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        {
+            let mut config = self.config.write().unwrap();
+            let Some(workspace) = config.workspaces.get_mut(name) else {
+                return Ok(false);
+            };
+            if let Some(key_path) = key_path {
+                workspace.signing_key_path = (!key_path.is_empty()).then_some(key_path);
+            }
+            if let Some(format) = format {
+                workspace.signing_format = Some(format);
+            }
+        }
+        self.save()?;
+        self.scope_git(name);
+        self.invalidate_workspace_status(name);
+        Ok(true)
     }
 
-    pub fn get_workspaces(&self) -> HashMap<String, WorkspaceConfig> {
-        self.config.read().unwrap().workspaces.clone()
+    /// Set the identity a workspace's commits are made with and the
+    /// credential given to its remotes; `password` must already be sealed.
+    /// None leaves a setting unchanged and an empty string removes it. False
+    /// if the workspace is missing.
+    pub fn set_workspace_git_identity(
+        &self,
+        name: &str,
+        user_name: Option<String>,
+        user_email: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        {
+            let mut config = self.config.write().unwrap();
+            let Some(workspace) = config.workspaces.get_mut(name) else {
+                return Ok(false);
+            };
+            let setting = |value: String| (!value.is_empty()).then_some(value);
+            if let Some(user_name) = user_name {
+                workspace.git_user_name = setting(user_name);
+            }
+            if let Some(user_email) = user_email {
+                workspace.git_user_email = setting(user_email);
+            }
+            if let Some(username) = username {
+                workspace.git_username = setting(username);
+            }
+            if let Some(password) = password {
+                workspace.git_password = setting(password);
+            }
+        }
+        self.save()?;
+        self.scope_git(name);
+        Ok(true)
     }
 
-    pub fn get_workspace(&self, name: &str) -> Option<WorkspaceConfig> {
-        self.config.read().unwrap().workspaces.get(name).cloned()
+    /// Regenerate the git config of a workspace from its settings and run
+    /// its git commands with it (see `git_scope`). Failures are logged: git
+    /// still runs, only without the workspace's settings.
+    pub fn scope_git(&self, name: &str) {
+        let Some(workspace) = self.get_workspace(name) else {
+            return;
+        };
+        let credential = workspace.git_password.as_ref().and_then(|sealed| {
+            let opened = SecretKey::load(&self.secret_key_path).and_then(|key| key.open(GIT_PASSWORD_SECRET, sealed));
+            match opened {
+                Ok(password) => Some(Credential {
+                    username: workspace.git_username.clone().unwrap_or_default(),
+                    password,
+                }),
+                Err(e) => {
+                    log::error!("Git password of workspace '{}' can't be opened: {}", name, e);
+                    None
+                }
+            }
+        });
+        if let Err(e) = git_scope::register(&self.workspace_path(name), name, &workspace, credential) {
+            log::error!("Failed to write the git config of workspace '{}': {}", name, e);
+        }
     }
 
-    pub fn add_workspace(
+    pub fn update_workspace_repo_url(
         &self,
-        name: String,
+        name: &str,
         repo_url: String,
-        base_branch: String,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let updated = {
             let mut config = self.config.write().unwrap();
-            config.workspaces.insert(
-                name,
-                WorkspaceConfig {
-                    repo_url,
-                    base_branch,
-                    created_at: Utc::now(),
-                },
-            );
+            if let Some(workspace) = config.workspaces.get_mut(name) {
+                workspace.repo_url = repo_url;
+                true
+            } else {
+                false
+            }
+        };
+        if updated {
+            self.save()?;
         }
+        Ok(updated)
+    }
+
+    pub fn get_schedules(&self) -> Vec<ScheduleConfig> {
+        self.config.read().unwrap().schedules.clone()
+    }
+
+    pub fn add_schedule(&self, schedule: ScheduleConfig) -> Result<(), Box<dyn std::error::Error>> {
+        self.config.write().unwrap().schedules.push(schedule);
         self.save()
     }
 
-    pub fn remove_workspace(&self, name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    pub fn remove_schedule(&self, id: &str) -> Result<bool, Box<dyn std::error::Error>> {
         let removed = {
             let mut config = self.config.write().unwrap();
-            config.workspaces.remove(name).is_some()
+            let before = config.schedules.len();
+            config.schedules.retain(|s| s.id != id);
+            config.schedules.len() != before
         };
         if removed {
             self.save()?;
@@ -117,15 +1637,13 @@ impl ConfigManager {
         Ok(removed)
     }
 
-    pub fn update_workspace_base_branch(
-        &self,
-        name: &str,
-        base_branch: String,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
+    /// Turn a schedule off, recording why; false if it is missing
+    pub fn disable_schedule(&self, id: &str, reason: String) -> Result<bool, Box<dyn std::error::Error>> {
         let updated = {
             let mut config = self.config.write().unwrap();
-            if let Some(workspace) = config.workspaces.get_mut(name) {
-                workspace.base_branch = base_branch;
+            if let Some(schedule) = config.schedules.iter_mut().find(|s| s.id == id) {
+                schedule.enabled = false;
+                schedule.disabled_reason = Some(reason);
                 true
             } else {
                 false
@@ -137,13 +1655,187 @@ impl ConfigManager {
         Ok(updated)
     }
 
+    /// Write the config atomically, keeping the previous version as `.bak`.
+    /// Every mutation calls this after releasing the write lock, and each
+    /// save serializes the current state, so the last save holds all changes
     fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let config = self.config.read().unwrap();
-        let content = serde_json::to_string_pretty(&*config)?;
-        fs::write(&self.config_path, content)?;
+        let _saving = self.save_lock.lock().unwrap();
+        let content = {
+            let config = self.config.read().unwrap();
+            serde_json::to_string_pretty(&*config)?
+        };
+
+        if self.config_path.exists() {
+            if let Err(e) = fs::copy(&self.config_path, backup_path(&self.config_path)) {
+                log::warn!("Failed to back up config file {:?}: {}", self.config_path, e);
+            }
+        }
+        file_ops::atomic_write(&self.config_path, content.as_bytes())?;
+        Ok(())
+    }
+
+    /// Status of a workspace, recomputed once the cached value is older than
+    /// `STATUS_TTL`
+    pub fn workspace_status(&self, name: &str) -> WorkspaceStatus {
+        if let Some((checked, status)) = self.status_cache.lock().unwrap().get(name) {
+            if checked.elapsed() < STATUS_TTL {
+                return *status;
+            }
+        }
+        self.verify_workspace(name)
+    }
+
+    /// Recompute a workspace's status now, bypassing the cache
+    pub fn verify_workspace(&self, name: &str) -> WorkspaceStatus {
+        let status = self.check_workspace(name);
+        self.status_cache
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (Instant::now(), status));
+        status
+    }
+
+    /// Forget a cached status after the workspace changed on disk
+    pub fn invalidate_workspace_status(&self, name: &str) {
+        self.status_cache.lock().unwrap().remove(name);
+    }
+
+    /// Disk space of a workspace as last scanned
+    pub fn workspace_usage(&self, name: &str) -> Option<WorkspaceUsage> {
+        self.usage_cache.lock().unwrap().get(name).copied()
+    }
+
+    /// Scan the disk space a workspace takes now; slow on big workspaces
+    pub fn measure_workspace(&self, name: &str) -> WorkspaceUsage {
+        let usage = WorkspaceUsage {
+            bytes: file_ops::disk_usage(&self.workspace_path(name), usize::MAX).bytes,
+            scanned_at: Utc::now(),
+        };
+        self.usage_cache.lock().unwrap().insert(name.to_string(), usage);
+        usage
+    }
+
+    /// Count `bytes` written to a workspace until its next scan
+    pub fn add_workspace_usage(&self, name: &str, bytes: u64) {
+        if let Some(usage) = self.usage_cache.lock().unwrap().get_mut(name) {
+            usage.bytes += bytes;
+        }
+    }
+
+    /// Forget a workspace's scanned disk space, so the next check scans it
+    pub fn invalidate_workspace_usage(&self, name: &str) {
+        self.usage_cache.lock().unwrap().remove(name);
+    }
+
+    fn check_workspace(&self, name: &str) -> WorkspaceStatus {
+        let workspace_path = self.workspace_path(name);
+        if !workspace_path.is_dir() {
+            return WorkspaceStatus::MissingOnDisk;
+        }
+
+        if let Ok(owner) = fs::read_to_string(workspace_path.join(CLONING_MARKER)) {
+            return if owner.trim() == std::process::id().to_string() {
+                WorkspaceStatus::Cloning
+            } else {
+                WorkspaceStatus::RepoCorrupt
+            };
+        }
+
+        let repo_path = self.repo_path(name);
+        if !repo_path.is_dir() {
+            return WorkspaceStatus::RepoMissing;
+        }
+        if !git_ops::is_repo_healthy(&repo_path) {
+            return WorkspaceStatus::RepoCorrupt;
+        }
+
+        let registered = match git_ops::registered_worktrees(&repo_path) {
+            Ok(paths) => paths,
+            Err(_) => return WorkspaceStatus::RepoCorrupt,
+        };
+        let mut registered: Vec<PathBuf> = registered
+            .iter()
+            .map(|p| p.canonicalize().unwrap_or_else(|_| p.clone()))
+            .collect();
+        let mut on_disk: Vec<PathBuf> = fs::read_dir(self.worktrees_path(name))
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .filter(|p| p.join(".git").exists())
+                    .filter_map(|p| p.canonicalize().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        registered.sort();
+        on_disk.sort();
+        if registered != on_disk {
+            return WorkspaceStatus::WorktreesInconsistent;
+        }
+
+        let uses_lfs = git_ops::repo_uses_lfs(&repo_path) || on_disk.iter().any(|p| git_ops::worktree_uses_lfs(p));
+        if uses_lfs && !git_ops::lfs_available() {
+            return WorkspaceStatus::LfsUnavailable;
+        }
+
+        let signing = self.get_workspace(name).and_then(|workspace| workspace.signing());
+        if let Some(Err(reason)) = signing.map(|signing| signing.check()) {
+            log::warn!("Workspace '{}' can't sign commits: {}", name, reason);
+            return WorkspaceStatus::SigningUnavailable;
+        }
+
+        WorkspaceStatus::Ok
+    }
+
+    /// Mark a workspace as cloning until `finish_clone`
+    pub fn begin_clone(&self, name: &str) -> std::io::Result<()> {
+        let workspace_path = self.workspace_path(name);
+        fs::create_dir_all(&workspace_path)?;
+        fs::write(workspace_path.join(CLONING_MARKER), std::process::id().to_string())?;
+        self.invalidate_workspace_status(name);
         Ok(())
     }
 
+    pub fn finish_clone(&self, name: &str) {
+        let _ = fs::remove_file(self.workspace_path(name).join(CLONING_MARKER));
+        self.invalidate_workspace_status(name);
+    }
+
+    /// Warn about configured workspaces that aren't usable; returns their
+    /// names
+    pub fn check_workspaces(&self) -> Vec<String> {
+        let mut broken: Vec<(String, WorkspaceStatus)> = self
+            .get_workspaces()
+            .into_keys()
+            .map(|name| {
+                let status = self.verify_workspace(&name);
+                (name, status)
+            })
+            .filter(|(_, status)| *status != WorkspaceStatus::Ok)
+            .collect();
+        broken.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (name, status) in &broken {
+            let legacy = Path::new(LEGACY_WORKSPACE_DIR).join(name);
+            if *status == WorkspaceStatus::MissingOnDisk
+                && self.workspace_dir != Path::new(LEGACY_WORKSPACE_DIR)
+                && legacy.is_dir()
+            {
+                log::warn!(
+                    "Workspace '{}' is missing from {:?} but exists in the old default {:?}; \
+                     set RUNOTEPAD_WORKSPACE_DIR={} to keep using it",
+                    name,
+                    self.workspace_dir,
+                    LEGACY_WORKSPACE_DIR,
+                    LEGACY_WORKSPACE_DIR
+                );
+            } else {
+                log::warn!("Workspace '{}' at {:?}: {}", name, self.workspace_path(name), status.describe());
+            }
+        }
+        broken.into_iter().map(|(name, _)| name).collect()
+    }
+
     pub fn workspace_path(&self, name: &str) -> PathBuf {
         self.workspace_dir.join(name)
     }
@@ -161,38 +1853,316 @@ impl ConfigManager {
     }
 }
 
-fn get_config_path() -> PathBuf {
-    if let Ok(path) = std::env::var("RUNOTEPAD_CONFIG_FILE") {
-        return PathBuf::from(path);
-    }
+/// Name of the token created on first start and replaced by `--token`
+pub const DEFAULT_TOKEN_NAME: &str = "default";
+/// How stale a token's recorded `last_used` may get before it is written to disk
+const LAST_USED_PERSIST_SECS: i64 = 60;
+
+const DEFAULT_BIND: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_UNIX_SOCKET_MODE: &str = "660";
+// actix's defaults
+const DEFAULT_KEEP_ALIVE_SECS: u64 = 5;
+const DEFAULT_CLIENT_REQUEST_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_MAX_CONNECTIONS: usize = 25_000;
 
+pub fn default_config_path() -> PathBuf {
     dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join(".runotepad")
         .join("config.json")
 }
 
-fn get_workspace_dir() -> PathBuf {
-    if let Ok(path) = std::env::var("RUNOTEPAD_WORKSPACE_DIR") {
-        return PathBuf::from(path);
+/// Current config schema version; bump it together with a new step in
+/// `migrate`
+pub const CONFIG_VERSION: u32 = 3;
+
+/// A config file written by a newer runotepad than this one
+#[derive(Debug)]
+struct UnsupportedVersion {
+    path: PathBuf,
+    found: u32,
+}
+
+impl std::fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Config file {:?} has version {}, but this runotepad only supports up to version {}; \
+             upgrade runotepad to use it",
+            self.path, self.found, CONFIG_VERSION
+        )
     }
+}
 
-    PathBuf::from("/tmp/runbookws")
+impl std::error::Error for UnsupportedVersion {}
+
+fn read_config(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)?;
+    config_from_value(serde_json::from_str(&content)?, path)
 }
 
-fn generate_token() -> String {
-    // Check environment variable first
-    if let Ok(token) = std::env::var("RUNOTEPAD_TOKEN") {
-        return token;
+/// A config as read from `source`, unmigrated; refused if a newer runotepad
+/// wrote it. The version is checked first: a newer layout may not parse as
+/// this one.
+pub fn config_from_value(value: serde_json::Value, source: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+    let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version > u64::from(CONFIG_VERSION) {
+        return Err(Box::new(UnsupportedVersion {
+            path: source.to_path_buf(),
+            found: u32::try_from(version).unwrap_or(u32::MAX),
+        }));
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Write `config` to `path` atomically, keeping a file already there as
+/// `.bak`
+pub fn write_config(path: &Path, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if path.exists() {
+        fs::copy(path, backup_path(path))?;
+    }
+    let content = serde_json::to_string_pretty(config)?;
+    file_ops::atomic_write(path, content.as_bytes())?;
+    Ok(())
+}
+
+/// Upgrade `config` one schema version at a time; returns whether anything
+/// changed
+fn migrate(config: &mut Config) -> bool {
+    let from = config.version;
+    while config.version < CONFIG_VERSION {
+        match config.version {
+            0 => migrate_v0_single_token(config),
+            1 => migrate_v1_hash_secrets(config),
+            2 => migrate_v2_job_timeouts(config),
+            _ => unreachable!("no migration from config version {}", config.version),
+        }
+        config.version += 1;
+    }
+    if config.version != from {
+        log::info!("Migrated config from version {} to {}", from, config.version);
+    }
+    config.version != from
+}
+
+/// v0 -> v1: fold the single `token` field into the named token list
+fn migrate_v0_single_token(config: &mut Config) {
+    if let Some(legacy) = config.token.take() {
+        if !config.tokens.iter().any(|t| t.matches(&legacy)) {
+            set_default_token(config, &legacy);
+        }
+        log::info!("Migrated legacy token to named token {:?}", DEFAULT_TOKEN_NAME);
+    }
+}
+
+/// v1 -> v2: replace plaintext token secrets with salted hashes
+fn migrate_v1_hash_secrets(config: &mut Config) {
+    for token in config.tokens.iter_mut() {
+        if let Some(secret) = token.token.take() {
+            token.hash = hash_token(&secret);
+            log::info!("Hashed plaintext secret of token {:?}", token.name);
+        }
+    }
+}
+
+/// `job_timeout_secs` every job had before timeouts were set per operation
+const LEGACY_JOB_TIMEOUT_SECS: u64 = 30 * 60;
+
+/// v2 -> v3: drop the job timeout saved only because it was the default, so
+/// the per-operation defaults apply
+fn migrate_v2_job_timeouts(config: &mut Config) {
+    if config.job_timeout_secs == Some(LEGACY_JOB_TIMEOUT_SECS) {
+        config.job_timeout_secs = None;
+        log::info!("Dropped the default job_timeout_secs in favor of per-operation timeouts");
+    }
+}
+
+/// `config.json` -> `config.json.bak`
+pub fn backup_path(config_path: &Path) -> PathBuf {
+    let mut name = config_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    config_path.with_file_name(name)
+}
+
+/// Where workspaces lived before they defaulted to the config directory
+const LEGACY_WORKSPACE_DIR: &str = "/tmp/runbookws";
+
+/// Set the secret of the `default` token, creating it if needed; returns
+/// whether anything changed
+fn set_default_token(config: &mut Config, secret: &str) -> bool {
+    match config.tokens.iter_mut().find(|t| t.name == DEFAULT_TOKEN_NAME) {
+        Some(existing) if existing.matches(secret) => false,
+        Some(existing) => {
+            set_secret(existing, secret);
+            true
+        }
+        None => {
+            config.tokens.push(ApiToken::new(DEFAULT_TOKEN_NAME, secret, TokenScope::ReadWrite));
+            true
+        }
     }
+}
+
+/// A new secret is effectively a new token, so its history starts over
+fn set_secret(token: &mut ApiToken, secret: &str) {
+    token.hash = hash_token(secret);
+    token.created_at = Utc::now();
+    token.last_used = None;
+}
 
+fn generate_token() -> String {
     // Generate a random 32-character hex token
+    random_hex(16)
+}
+
+fn random_hex(len: usize) -> String {
     let mut rng = rand::thread_rng();
-    let bytes: Vec<u8> = (0..16).map(|_| rng.gen()).collect();
+    let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+    to_hex(&bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
+fn salted_digest(salt: &str, secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(secret.as_bytes());
+    to_hex(&hasher.finalize())
+}
+
+/// Salted SHA-256 of a token secret. Secrets are 128 random bits, so a
+/// fast hash is enough and keeps per-request verification cheap.
+fn hash_token(secret: &str) -> String {
+    let salt = random_hex(16);
+    format!("sha256${}${}", salt, salted_digest(&salt, secret))
+}
+
+fn verify_token_hash(stored: &str, secret: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some("sha256"), Some(salt), Some(digest), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    // Digests are fixed length, so only the content comparison could leak timing
+    salted_digest(salt, secret).as_bytes().ct_eq(digest.as_bytes()).into()
+}
+
 /// Sanitize branch name for use as directory name
 pub fn sanitize_branch_name(name: &str) -> String {
-    name.replace('/', "_").replace('\\', "_")
+    name.replace(['/', '\\'], "_")
+}
+
+/// Worktrees pinned to a tag or commit go by `detached:<ref>` where others
+/// go by their branch; branch names can't contain ':', so the two never
+/// collide
+const DETACHED_PREFIX: &str = "detached:";
+
+/// Name, and directory name, of the worktree pinned to `rev`
+pub fn detached_worktree_name(rev: &str) -> String {
+    format!("{}{}", DETACHED_PREFIX, sanitize_branch_name(rev))
+}
+
+/// Whether a worktree name is that of a worktree pinned to a tag or commit
+pub fn is_detached_worktree(name: &str) -> bool {
+    name.starts_with(DETACHED_PREFIX)
+}
+
+/// Workspace names become directory names, so they are kept to a plain slug
+pub fn validate_workspace_name(name: &str) -> Result<(), String> {
+    let invalid = |reason: &str| Err(format!("Invalid workspace name '{}': {}", name, reason));
+
+    if name.is_empty() {
+        return invalid("must not be empty");
+    }
+    if name.len() > 64 {
+        return invalid("must be at most 64 characters");
+    }
+    if name.starts_with(['.', '-']) {
+        return invalid("must not start with '.' or '-'");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return invalid(&format!(
+            "contains {:?}; use letters, digits, '-', '_' and '.'",
+            c
+        ));
+    }
+    Ok(())
+}
+
+/// Remote names are a subset of what git accepts, so one can't be read as
+/// an option or contain a refspec
+pub fn validate_remote_name(name: &str) -> Result<(), String> {
+    let invalid = |reason: &str| Err(format!("Invalid remote name '{}': {}", name, reason));
+
+    if name.is_empty() {
+        return invalid("must not be empty");
+    }
+    if name.len() > 64 {
+        return invalid("must be at most 64 characters");
+    }
+    if name.starts_with(['.', '-']) {
+        return invalid("must not start with '.' or '-'");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return invalid(&format!(
+            "contains {:?}; use letters, digits, '-', '_' and '.'",
+            c
+        ));
+    }
+    Ok(())
+}
+
+/// The rules of `git check-ref-format --branch`, plus no leading '-' so a
+/// name can never be read as an option
+pub fn validate_branch_name(name: &str) -> Result<(), String> {
+    let invalid = |reason: &str| Err(format!("Invalid branch name '{}': {}", name, reason));
+
+    if name.is_empty() {
+        return invalid("must not be empty");
+    }
+    if name.starts_with('-') {
+        return invalid("must not start with '-'");
+    }
+    if name == "HEAD" || name == "@" {
+        return invalid("is reserved");
+    }
+    if let Some(c) = name.chars().find(|c| {
+        c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+    }) {
+        return invalid(&format!("must not contain {:?}", c));
+    }
+    if name.contains("..") {
+        return invalid("must not contain '..'");
+    }
+    if name.contains("@{") {
+        return invalid("must not contain '@{'");
+    }
+    if name.ends_with('.') {
+        return invalid("must not end with '.'");
+    }
+    for component in name.split('/') {
+        if component.is_empty() {
+            return invalid("must not have empty path components");
+        }
+        if component.starts_with('.') {
+            return invalid("path components must not start with '.'");
+        }
+        if component.ends_with(".lock") {
+            return invalid("path components must not end with '.lock'");
+        }
+    }
+    Ok(())
 }
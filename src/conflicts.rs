@@ -0,0 +1,222 @@
+//! Resolving merge conflicts from the web editor. When a rebase (or a
+//! merge, cherry-pick or revert run in a terminal) stops on conflicts, the
+//! worktree keeps the unmerged paths in its index. These endpoints list
+//! them, give the editor each side of a conflicted file, and take the
+//! resolved content back; once nothing is left unmerged the operation can
+//! be finished, e.g. by committing.
+//!
+//! Files that aren't text in every version are reported as `binary` and
+//! can't be resolved here; they need a terminal.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::audit;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::file_ops;
+use crate::git_ops::{self, Conflict};
+use crate::logging;
+use crate::quota;
+use crate::workspace;
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct ConflictQuery {
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveRequest {
+    pub path: String,
+    /// The file as it should be committed, without conflict markers
+    pub content: String,
+}
+
+/// One version of a conflicted file
+struct Versions {
+    base: Option<Vec<u8>>,
+    ours: Option<Vec<u8>>,
+    theirs: Option<Vec<u8>>,
+    /// The worktree's file, with conflict markers where git left them
+    working: Option<Vec<u8>>,
+}
+
+impl Versions {
+    fn read(worktree_path: &Path, path: &str) -> Result<Self, ApiError> {
+        let stage = |stage: &str| {
+            git_ops::show_file_at(worktree_path, stage, path)
+                .map_err(|e| ApiError::from(e.context("Failed to read conflicted file")))
+        };
+        Ok(Self {
+            base: stage(":1")?,
+            ours: stage(":2")?,
+            theirs: stage(":3")?,
+            working: std::fs::read(worktree_path.join(path)).ok(),
+        })
+    }
+
+    fn all(&self) -> [&Option<Vec<u8>>; 4] {
+        [&self.base, &self.ours, &self.theirs, &self.working]
+    }
+
+    /// Whether some version isn't text the editor can show
+    fn binary(&self) -> bool {
+        self.all()
+            .into_iter()
+            .flatten()
+            .any(|bytes| bytes.contains(&0) || std::str::from_utf8(bytes).is_err())
+    }
+}
+
+fn text(bytes: Option<Vec<u8>>) -> Option<String> {
+    bytes.and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+/// `path` relative to the worktree root, as git names it
+fn worktree_relative(worktree_path: &Path, path: &str) -> Result<String, ApiError> {
+    let full_path = file_ops::safe_join(worktree_path, path).map_err(|e| ApiError::io("Invalid path", e))?;
+    let rel_path = full_path.strip_prefix(worktree_path).unwrap_or(&full_path);
+    Ok(rel_path.to_string_lossy().into_owned())
+}
+
+/// 404 `conflict_not_found` unless `path` is conflicted in the worktree
+fn require_conflict(worktree_path: &Path, path: &str) -> Result<Conflict, ApiError> {
+    git_ops::list_conflicts(worktree_path)
+        .map_err(|e| e.context("Failed to list conflicts"))?
+        .into_iter()
+        .find(|conflict| conflict.path == path)
+        .ok_or_else(|| ApiError::not_found("conflict_not_found", format!("'{}' has no conflict", path)))
+}
+
+fn binary_conflict(path: &str) -> ApiError {
+    ApiError::conflict(
+        "binary_conflict",
+        format!("'{}' is binary; resolve its conflict in a terminal", path),
+    )
+    .with_details(serde_json::json!({ "path": path }))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/conflicts - Conflicted
+/// paths of the worktree and the operation that stopped on them
+pub async fn list_conflicts(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    let body = logging::block(move || -> Result<serde_json::Value, ApiError> {
+        let conflicts = git_ops::list_conflicts(&worktree_path).map_err(|e| e.context("Failed to list conflicts"))?;
+        let mut entries = Vec::with_capacity(conflicts.len());
+        for conflict in conflicts {
+            let binary = Versions::read(&worktree_path, &conflict.path)?.binary();
+            entries.push(serde_json::json!({
+                "path": conflict.path,
+                "stages": conflict.stages,
+                "binary": binary
+            }));
+        }
+        Ok(serde_json::json!({
+            "operation": git_ops::pending_operation(&worktree_path),
+            "conflicts": entries
+        }))
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(body))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/conflicts/file?path=x -
+/// The base, ours and theirs versions of a conflicted file and the
+/// worktree's copy with conflict markers
+pub async fn get_conflict_file(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ConflictQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let file_path = worktree_relative(&worktree_path, &query.path)?;
+
+    let body = logging::block(move || -> Result<serde_json::Value, ApiError> {
+        let conflict = require_conflict(&worktree_path, &file_path)?;
+        let versions = Versions::read(&worktree_path, &file_path)?;
+        if versions.binary() {
+            return Err(binary_conflict(&file_path));
+        }
+        Ok(serde_json::json!({
+            "path": file_path,
+            "stages": conflict.stages,
+            "base": text(versions.base),
+            "ours": text(versions.ours),
+            "theirs": text(versions.theirs),
+            "working": text(versions.working)
+        }))
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(body))
+}
+
+/// POST /api/workspaces/{name}/branches/{branch}/conflicts/resolve - Write
+/// the resolved content of a conflicted file and stage it
+pub async fn resolve_conflict(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<(String, String)>,
+    body: Json<ResolveRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let file_path = worktree_relative(&worktree_path, &body.path)?;
+    quota::require_space(&config, &workspace)?;
+    let _lock = workspace::lock_worktree(&config, &state, &worktree_path, "resolve_conflict").await?;
+
+    let body = body.into_inner();
+    let bytes = body.content.len() as u64;
+    let listings = state.listings.clone();
+    let (operation, remaining) = {
+        let workspace = workspace.clone();
+        let branch = branch.clone();
+        let file_path = file_path.clone();
+        logging::block(move || -> Result<_, ApiError> {
+            require_conflict(&worktree_path, &file_path)?;
+            if Versions::read(&worktree_path, &file_path)?.binary() {
+                return Err(binary_conflict(&file_path));
+            }
+            let written = file_ops::write_file(&worktree_path, &file_path, &body.content)
+                .map_err(|e| ApiError::io("Failed to save file", e))
+                .and_then(|()| {
+                    git_ops::stage_files(&worktree_path, std::slice::from_ref(&file_path))
+                        .map_err(|e| ApiError::from(e.context("Failed to stage file")))
+                });
+            listings.invalidate_worktree(&workspace, &branch);
+            written?;
+            let remaining: Vec<String> = git_ops::list_conflicts(&worktree_path)
+                .map_err(|e| e.context("Failed to list conflicts"))?
+                .into_iter()
+                .map(|conflict| conflict.path)
+                .collect();
+            Ok((git_ops::pending_operation(&worktree_path), remaining))
+        })
+        .await??
+    };
+    config.add_workspace_usage(&workspace, bytes);
+
+    audit::record(
+        &req,
+        "conflict.resolve",
+        serde_json::json!({ "workspace": workspace, "branch": branch, "path": file_path }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": format!("Conflict in '{}' resolved", file_path),
+        "path": file_path,
+        "operation": operation,
+        "remaining": remaining,
+        "all_resolved": remaining.is_empty()
+    })))
+}
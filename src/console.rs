@@ -0,0 +1,311 @@
+//! Browser console forwarding: the frontend sends its console messages to
+//! `POST /api/console`, one at a time or in batches. They go to the server
+//! log and into a bounded in-memory buffer that `GET /api/console` queries,
+//! so a user's frontend problem can be looked at without grepping the log.
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audit;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::logging;
+use crate::unix_socket;
+use crate::AppState;
+
+/// Messages accepted from one address per `CONSOLE_WINDOW`
+const CONSOLE_MAX_MESSAGES: u32 = 100;
+const CONSOLE_WINDOW: Duration = Duration::from_secs(10);
+/// Longer messages are cut off before they reach the log
+const MAX_CONSOLE_MESSAGE_CHARS: usize = 4096;
+const MAX_CONSOLE_TIMESTAMP_CHARS: usize = 64;
+const MAX_CONSOLE_LEVEL_CHARS: usize = 16;
+const MAX_USER_AGENT_CHARS: usize = 256;
+const CONSOLE_LIMITER_PRUNE_AT: usize = 1024;
+
+const DEFAULT_CONSOLE_LIMIT: usize = 100;
+
+/// Levels in increasing severity; others count as `trace`
+const CONSOLE_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
+
+fn severity(level: &str) -> usize {
+    CONSOLE_LEVELS.iter().position(|known| *known == level).unwrap_or(0)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsoleLogRequest {
+    level: String,
+    message: String,
+    timestamp: Option<String>,
+}
+
+/// One message, or a batch the frontend buffered
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ConsoleLogBody {
+    Batch(Vec<ConsoleLogRequest>),
+    One(ConsoleLogRequest),
+}
+
+/// A forwarded browser console message
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsoleEntry {
+    /// When the server received it
+    pub timestamp: DateTime<Utc>,
+    pub level: String,
+    pub message: String,
+    /// Whether the message was cut off at `MAX_CONSOLE_MESSAGE_CHARS`
+    pub truncated: bool,
+    /// The browser's time of the message, as it sent it
+    pub client_timestamp: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// The latest forwarded messages, oldest first
+pub struct ConsoleBuffer {
+    capacity: usize,
+    entries: Mutex<VecDeque<ConsoleEntry>>,
+}
+
+impl ConsoleBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn push(&self, entry: ConsoleEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Remove every entry; returns how many there were
+    fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+
+    /// Entries at `min_level` or above, paged like the audit log: strictly
+    /// after `since` (oldest first, up to `limit`), or the latest `limit`
+    fn query(&self, min_level: usize, since: Option<DateTime<Utc>>, limit: usize) -> Vec<ConsoleEntry> {
+        let entries = self.entries.lock().unwrap();
+        let matching = entries.iter().filter(|entry| severity(&entry.level) >= min_level);
+        match since {
+            Some(since) => matching
+                .filter(|entry| entry.timestamp > since)
+                .take(limit)
+                .cloned()
+                .collect(),
+            None => {
+                let mut latest: Vec<ConsoleEntry> = matching.rev().take(limit).cloned().collect();
+                latest.reverse();
+                latest
+            }
+        }
+    }
+}
+
+struct ConsoleWindow {
+    accepted: u32,
+    dropped: u64,
+    started: Instant,
+}
+
+/// Per-address budget for forwarded browser logs. Messages over the budget
+/// are dropped and the number dropped is reported once the window closes.
+pub struct ConsoleLimiter {
+    windows: Mutex<HashMap<IpAddr, ConsoleWindow>>,
+}
+
+impl ConsoleLimiter {
+    pub fn new() -> Self {
+        Self {
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a message from `ip` may be logged right now
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > CONSOLE_LIMITER_PRUNE_AT {
+            windows.retain(|_, w| w.started.elapsed() < CONSOLE_WINDOW);
+        }
+
+        let window = windows.entry(ip).or_insert(ConsoleWindow {
+            accepted: 0,
+            dropped: 0,
+            started: Instant::now(),
+        });
+        if window.started.elapsed() >= CONSOLE_WINDOW {
+            if window.dropped > 0 {
+                log::warn!(
+                    "Dropped {} browser console messages from {} over the rate limit",
+                    window.dropped,
+                    ip
+                );
+            }
+            *window = ConsoleWindow {
+                accepted: 0,
+                dropped: 0,
+                started: Instant::now(),
+            };
+        }
+
+        if window.accepted < CONSOLE_MAX_MESSAGES {
+            window.accepted += 1;
+            true
+        } else {
+            if window.dropped == 0 {
+                log::warn!("Rate limiting browser console messages from {}", ip);
+            }
+            window.dropped += 1;
+            false
+        }
+    }
+}
+
+impl Default for ConsoleLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `input` cut to `max_chars`, with a marker saying how much was cut; and
+/// whether anything was
+fn truncate(input: &str, max_chars: usize) -> (String, bool) {
+    let mut chars = input.chars();
+    let mut out: String = chars.by_ref().take(max_chars).collect();
+    let rest = chars.count();
+    if rest > 0 {
+        out.push_str(&format!("... ({} more chars)", rest));
+    }
+    (out, rest > 0)
+}
+
+/// Make a browser-supplied string safe to put on a single log line: control
+/// characters (including newlines) are escaped and the length is capped
+fn sanitize(input: &str, max_chars: usize) -> String {
+    let (truncated, _) = truncate(input, max_chars);
+    let mut out = String::with_capacity(truncated.len());
+    for c in truncated.chars() {
+        if c.is_control() {
+            out.extend(c.escape_default());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// POST /api/console - Forward browser console messages (one, or an array)
+/// to the server log and the console buffer
+pub async fn console_log_handler(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    body: Json<ConsoleLogBody>,
+) -> Result<HttpResponse, ApiError> {
+    let messages = match body.into_inner() {
+        ConsoleLogBody::Batch(messages) => messages,
+        ConsoleLogBody::One(message) => vec![message],
+    };
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| sanitize(value, MAX_USER_AGENT_CHARS));
+    let ip = unix_socket::peer_ip(&req);
+
+    let mut accepted = 0;
+    for message in &messages {
+        if let Some(ip) = ip {
+            if !state.console_limiter.allow(ip) {
+                continue;
+            }
+        }
+        accepted += 1;
+
+        let level = sanitize(&message.level, MAX_CONSOLE_LEVEL_CHARS);
+        let msg = sanitize(&message.message, MAX_CONSOLE_MESSAGE_CHARS);
+        let ts = sanitize(message.timestamp.as_deref().unwrap_or(""), MAX_CONSOLE_TIMESTAMP_CHARS);
+        let log_level = match level.as_str() {
+            "error" => log::Level::Error,
+            "warn" => log::Level::Warn,
+            "info" => log::Level::Info,
+            "debug" => log::Level::Debug,
+            _ => log::Level::Trace,
+        };
+        // The frontend logs terminal traffic, so this stays out of the admin log
+        logging::log_uncaptured(log_level, module_path!(), format_args!("[BROWSER {}] {}", ts, msg));
+
+        let (text, truncated) = truncate(&message.message, MAX_CONSOLE_MESSAGE_CHARS);
+        state.console_log.push(ConsoleEntry {
+            timestamp: Utc::now(),
+            level,
+            message: text,
+            truncated,
+            client_timestamp: message.timestamp.as_ref().map(|_| ts),
+            user_agent: user_agent.clone(),
+            ip: ip.map(|ip| ip.to_string()),
+        });
+    }
+
+    if accepted == 0 && !messages.is_empty() {
+        return Err(ApiError::too_many_requests("Too many console messages", CONSOLE_WINDOW));
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "accepted": accepted,
+        "dropped": messages.len() - accepted,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsoleQuery {
+    /// Only entries at this level or more severe
+    pub level: Option<String>,
+    pub limit: Option<usize>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// GET /api/console - Query the buffered browser console entries
+pub async fn list_console_entries(
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<ConsoleQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let min_level = match query.level.as_deref() {
+        None => 0,
+        Some(level) if CONSOLE_LEVELS.contains(&level) => severity(level),
+        Some(level) => {
+            return Err(ApiError::validation(
+                "invalid_query",
+                format!("Unknown level '{}'", level),
+            )
+            .with_details(serde_json::json!({ "allowed": CONSOLE_LEVELS })));
+        }
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_CONSOLE_LIMIT).max(1);
+    Ok(HttpResponse::Ok().json(state.console_log.query(min_level, query.since, limit)))
+}
+
+/// DELETE /api/console - Clear the console buffer
+pub async fn clear_console_entries(req: HttpRequest, state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let cleared = state.console_log.clear();
+    log::info!("Cleared {} browser console entries", cleared);
+    audit::record(&req, "console.clear", serde_json::json!({ "cleared": cleared }));
+    HttpResponse::Ok().json(serde_json::json!({ "cleared": cleared }))
+}
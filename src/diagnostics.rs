@@ -0,0 +1,306 @@
+//! Checks of the environment the server runs in: git, the config, the token,
+//! the workspace directory and each workspace, the listeners and the TLS
+//! files. `runotepad doctor` runs them all and prints a table; `/readyz`
+//! runs those that make sense on a running server, so a probe and an
+//! operator see the same verdicts.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::assets;
+use crate::auth;
+use crate::config::{ConfigManager, ConfigOverrides, TokenScope, WorkspaceStatus};
+use crate::logging;
+use crate::tls;
+
+/// Oldest git with every command the server runs (`worktree repair`)
+pub const MIN_GIT_VERSION: (u32, u32) = (2, 29);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Pass => "PASS",
+            Level::Warn => "WARN",
+            Level::Fail => "FAIL",
+        }
+    }
+}
+
+/// Verdict of one check
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub level: Level,
+    pub message: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, level: Level, message: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            level,
+            message: message.into(),
+        }
+    }
+}
+
+/// `(major, minor)` of `git version 2.43.0` and the like
+fn parse_git_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.trim().strip_prefix("git version ")?;
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// Git is installed and new enough
+pub fn check_git() -> Check {
+    let output = match Command::new("git").arg("--version").output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            return Check::new("git", Level::Fail, format!("git --version failed: {}", output.status));
+        }
+        Err(e) => return Check::new("git", Level::Fail, format!("git can't be run: {}", e)),
+    };
+    let reported = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    match parse_git_version(&reported) {
+        Some(version) if version >= MIN_GIT_VERSION => Check::new("git", Level::Pass, reported),
+        Some(_) => Check::new(
+            "git",
+            Level::Warn,
+            format!(
+                "{}; {}.{} or newer is needed for everything to work",
+                reported, MIN_GIT_VERSION.0, MIN_GIT_VERSION.1
+            ),
+        ),
+        None => Check::new("git", Level::Warn, format!("Unrecognized version '{}'", reported)),
+    }
+}
+
+/// The config loads; the manager to run the remaining checks with, if so
+pub fn check_config(overrides: ConfigOverrides) -> (Check, Option<ConfigManager>) {
+    match ConfigManager::new(overrides) {
+        Ok(config) => (Check::new("config", Level::Pass, "Loaded"), Some(config)),
+        Err(e) => (Check::new("config", Level::Fail, e.to_string()), None),
+    }
+}
+
+/// Some token can reach the API. Loading a config without one generates
+/// one that nobody has seen.
+pub fn check_token(config: &ConfigManager) -> Check {
+    if config.take_generated_token().is_some() {
+        return Check::new(
+            "token",
+            Level::Warn,
+            "No token was configured, so one was generated; run with --rotate-token to get one to use",
+        );
+    }
+    let tokens = config.list_tokens();
+    if tokens.is_empty() {
+        return Check::new("token", Level::Fail, "No token is configured");
+    }
+    let writers = tokens.iter().filter(|token| token.scope.allows(TokenScope::ReadWrite)).count();
+    if writers == 0 {
+        return Check::new("token", Level::Warn, format!("{} token(s), all read-only", tokens.len()));
+    }
+    Check::new("token", Level::Pass, format!("{} token(s)", tokens.len()))
+}
+
+/// The workspace directory exists and is writable, and holds exactly the
+/// configured workspaces
+pub fn check_workspace_dir(config: &ConfigManager) -> Check {
+    let dir = config.get_workspace_dir();
+    if !dir.is_dir() {
+        return Check::new("workspace_dir", Level::Fail, format!("{} does not exist", dir.display()));
+    }
+    let probe = dir.join(format!(".runotepad-probe-{}", std::process::id()));
+    if let Err(e) = fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe)) {
+        return Check::new("workspace_dir", Level::Fail, format!("{} is not writable: {}", dir.display(), e));
+    }
+
+    let workspaces = config.get_workspaces();
+    let mut missing: Vec<&String> = workspaces.keys().filter(|name| !dir.join(name).is_dir()).collect();
+    missing.sort();
+    let mut unknown: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with('.') && !workspaces.contains_key(name))
+                .collect()
+        })
+        .unwrap_or_default();
+    unknown.sort();
+
+    let mut problems = Vec::new();
+    if !missing.is_empty() {
+        let names: Vec<&str> = missing.iter().map(|name| name.as_str()).collect();
+        problems.push(format!("configured workspaces missing from it: {}", names.join(", ")));
+    }
+    if !unknown.is_empty() {
+        problems.push(format!("directories no workspace is configured for: {}", unknown.join(", ")));
+    }
+    if problems.is_empty() {
+        return Check::new("workspace_dir", Level::Pass, dir.display().to_string());
+    }
+    Check::new(
+        "workspace_dir",
+        Level::Warn,
+        format!("{}: {}", dir.display(), problems.join("; ")),
+    )
+}
+
+/// Each workspace's repository and worktrees, as its status reports them
+pub fn check_workspaces(config: &ConfigManager) -> Vec<Check> {
+    let mut names: Vec<String> = config.get_workspaces().into_keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| {
+            let status = config.workspace_status(&name);
+            let level = match status {
+                WorkspaceStatus::Ok => Level::Pass,
+                WorkspaceStatus::Cloning => Level::Warn,
+                status if status.repo_usable() => Level::Warn,
+                _ => Level::Fail,
+            };
+            Check::new(format!("workspace {}", name), level, status.describe())
+        })
+        .collect()
+}
+
+/// The TLS certificate and key load and match, when TLS is configured
+pub fn check_tls(config: &ConfigManager) -> Option<Check> {
+    let (cert, key) = config.tls_paths()?;
+    Some(match tls::load_server_config(cert, key) {
+        Ok(_) => Check::new("tls", Level::Pass, format!("{} and {}", cert.display(), key.display())),
+        Err(e) => Check::new("tls", Level::Fail, e),
+    })
+}
+
+/// The web UI is there to serve
+pub fn check_static(config: &ConfigManager) -> Check {
+    match assets::Source::resolve(config.static_dir().map(|dir| dir.as_path())) {
+        assets::Source::Directory(dir) if !dir.join("index.html").is_file() => Check::new(
+            "static",
+            Level::Warn,
+            format!("No index.html in {}; every page will be a 404", dir.display()),
+        ),
+        assets::Source::Directory(dir) => Check::new("static", Level::Pass, dir.display().to_string()),
+        #[cfg(feature = "embed-assets")]
+        assets::Source::Embedded => Check::new("static", Level::Pass, "Embedded in the binary"),
+    }
+}
+
+/// The TCP address and the unix socket can be listened on. Only meaningful
+/// before the server starts, which then holds them.
+pub fn check_listeners(config: &ConfigManager) -> Vec<Check> {
+    let mut checks = Vec::new();
+    if let Some(address) = config.bind_address() {
+        checks.push(match std::net::TcpListener::bind(address) {
+            Ok(_) => Check::new("listen", Level::Pass, address),
+            Err(e) => Check::new("listen", Level::Fail, format!("Can't listen on {}: {}", address, e)),
+        });
+    }
+    if let Some((path, _)) = config.unix_socket() {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        checks.push(if dir.is_dir() {
+            Check::new("unix_socket", Level::Pass, path.display().to_string())
+        } else {
+            Check::new("unix_socket", Level::Fail, format!("{} does not exist", dir.display()))
+        });
+    }
+    checks
+}
+
+/// The checks that hold for a running server
+pub fn server_checks(config: &ConfigManager) -> Vec<Check> {
+    let mut checks = vec![check_git(), check_token(config), check_workspace_dir(config)];
+    checks.extend(check_workspaces(config));
+    checks.extend(check_tls(config));
+    checks.push(check_static(config));
+    checks
+}
+
+/// Every check, for a server about to start with `overrides`
+pub fn doctor_checks(overrides: ConfigOverrides) -> Vec<Check> {
+    let (config_check, config) = check_config(overrides);
+    let mut checks = vec![config_check];
+    match config {
+        Some(config) => {
+            checks.extend(server_checks(&config));
+            checks.extend(check_listeners(&config));
+        }
+        None => checks.push(check_git()),
+    }
+    checks
+}
+
+/// `checks` as a table with a summary line
+pub fn render_table(checks: &[Check]) -> String {
+    let width = checks.iter().map(|check| check.name.len()).max().unwrap_or(0);
+    let mut table = String::new();
+    for check in checks {
+        table.push_str(&format!(
+            "{}  {:width$}  {}\n",
+            check.level.label(),
+            check.name,
+            check.message,
+            width = width
+        ));
+    }
+    let count = |level| checks.iter().filter(|check| check.level == level).count();
+    table.push_str(&format!(
+        "\n{} passed, {} warning(s), {} failed\n",
+        count(Level::Pass),
+        count(Level::Warn),
+        count(Level::Fail)
+    ));
+    table
+}
+
+/// Worst level among `checks`
+pub fn verdict(checks: &[Check]) -> Level {
+    checks.iter().map(|check| check.level).max().unwrap_or(Level::Pass)
+}
+
+/// GET /readyz - 200 when no check fails, 503 otherwise. Counts only; the
+/// checks themselves, which name workspaces and paths, come with a
+/// read_write token.
+pub async fn readyz(req: HttpRequest, config: web::Data<Arc<ConfigManager>>) -> HttpResponse {
+    let checked = config.get_ref().clone();
+    let checks = match logging::block(move || server_checks(&checked)).await {
+        Ok(checks) => checks,
+        Err(e) => vec![Check::new("checks", Level::Fail, e.to_string())],
+    };
+    let count = |level| checks.iter().filter(|check| check.level == level).count();
+    let ready = verdict(&checks) != Level::Fail;
+    let mut body = serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "passed": count(Level::Pass),
+        "warnings": count(Level::Warn),
+        "failed": count(Level::Fail),
+    });
+    let trusted = auth::extract_token_from_request(&req, &config)
+        .and_then(|token| auth::verify_with_limit(&req, &config, &token).ok().flatten())
+        .is_some_and(|identity| identity.scope.allows(TokenScope::ReadWrite));
+    if trusted {
+        body["checks"] = serde_json::json!(checks);
+    }
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
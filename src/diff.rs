@@ -0,0 +1,103 @@
+use serde::Serialize;
+use similar::{DiffTag, TextDiff};
+
+/// Lines of context around each hunk in the unified diff
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileDiffStatus {
+    Unchanged,
+    Modified,
+    Added,
+    Deleted,
+}
+
+/// A changed region, 1-based like unified diff headers. A zero line count
+/// means the change is a pure insertion or deletion at that position.
+#[derive(Debug, Clone, Serialize)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileDiff {
+    pub path: String,
+    pub status: FileDiffStatus,
+    pub binary: bool,
+    pub unified: String,
+    pub hunks: Vec<Hunk>,
+}
+
+/// Diff the committed version of a file against the working copy. `None`
+/// means the file doesn't exist on that side.
+pub fn diff_file(path: &str, old: Option<&[u8]>, new: Option<&[u8]>) -> FileDiff {
+    let status = match (old, new) {
+        (None, Some(_)) => FileDiffStatus::Added,
+        (Some(_), None) => FileDiffStatus::Deleted,
+        (Some(a), Some(b)) if a == b => FileDiffStatus::Unchanged,
+        _ => FileDiffStatus::Modified,
+    };
+
+    let old_text = old.map(std::str::from_utf8);
+    let new_text = new.map(std::str::from_utf8);
+    let is_binary = |side: &Option<Result<&str, std::str::Utf8Error>>| {
+        matches!(side, Some(Err(_))) || matches!(side, Some(Ok(t)) if t.contains('\0'))
+    };
+
+    if is_binary(&old_text) || is_binary(&new_text) {
+        return FileDiff {
+            path: path.to_string(),
+            status,
+            binary: true,
+            unified: String::new(),
+            hunks: Vec::new(),
+        };
+    }
+
+    let old_text = old_text.and_then(Result::ok).unwrap_or("");
+    let new_text = new_text.and_then(Result::ok).unwrap_or("");
+    let diff = TextDiff::from_lines(old_text, new_text);
+
+    let old_header = if old.is_some() { format!("a/{}", path) } else { "/dev/null".to_string() };
+    let new_header = if new.is_some() { format!("b/{}", path) } else { "/dev/null".to_string() };
+    let unified = if status == FileDiffStatus::Unchanged {
+        String::new()
+    } else {
+        diff.unified_diff()
+            .context_radius(CONTEXT_LINES)
+            .header(&old_header, &new_header)
+            .to_string()
+    };
+
+    // Zero-context grouping gives exact changed ranges for the editor gutter;
+    // adjacent delete/insert ops within a group are reported as one hunk
+    let hunks = diff
+        .grouped_ops(0)
+        .into_iter()
+        .filter_map(|group| {
+            let changes: Vec<_> = group.iter().filter(|op| op.tag() != DiffTag::Equal).collect();
+            let first = changes.first()?;
+            let last = changes.last()?;
+            let old_start = first.old_range().start;
+            let new_start = first.new_range().start;
+            Some(Hunk {
+                old_start: old_start + 1,
+                old_lines: last.old_range().end - old_start,
+                new_start: new_start + 1,
+                new_lines: last.new_range().end - new_start,
+            })
+        })
+        .collect();
+
+    FileDiff {
+        path: path.to_string(),
+        status,
+        binary: false,
+        unified,
+        hunks,
+    }
+}
@@ -0,0 +1,215 @@
+//! Autosaved drafts of files being edited, so edits survive a browser or
+//! laptop dying before they were saved. The draft of a file is kept outside
+//! its worktree, in `<workspace>/.runotepad-drafts/<branch>/<SHA-256 of the
+//! path>`, so git, file lists, searches and archives never see it. Saving
+//! the file leaves the draft alone; the editor discards it once the file is
+//! saved. Drafts untouched for `draft_retention_days` are purged in the
+//! background, and with their worktree.
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::blocks;
+use crate::config::{sanitize_branch_name, ConfigManager};
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::file_ops;
+use crate::logging;
+use crate::quota;
+use crate::workspace::{self, FileQuery, SaveFileRequest};
+
+pub const DRAFTS_DIR: &str = ".runotepad-drafts";
+
+/// How often old drafts are looked for
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// When a draft was last saved, as the file read shows it
+#[derive(Debug, Clone, Serialize)]
+pub struct DraftInfo {
+    pub saved_at: DateTime<Utc>,
+    pub bytes: u64,
+}
+
+fn drafts_root(config: &ConfigManager, workspace: &str) -> PathBuf {
+    config.workspace_path(workspace).join(DRAFTS_DIR)
+}
+
+fn branch_drafts(config: &ConfigManager, workspace: &str, branch: &str) -> PathBuf {
+    drafts_root(config, workspace).join(sanitize_branch_name(branch))
+}
+
+/// Where the draft of `file_path` is kept, with the path checked like a
+/// file read's and normalized, so `./a.md` and `a.md` share a draft
+fn draft_path(
+    config: &ConfigManager,
+    workspace: &str,
+    branch: &str,
+    worktree_path: &Path,
+    file_path: &str,
+) -> Result<PathBuf, ApiError> {
+    let full_path = file_ops::safe_join(worktree_path, file_path).map_err(|e| ApiError::io("Invalid path", e))?;
+    let relative = full_path.strip_prefix(worktree_path).unwrap_or(&full_path);
+    Ok(branch_drafts(config, workspace, branch).join(blocks::content_hash(relative.to_string_lossy().as_bytes())))
+}
+
+fn info(metadata: &fs::Metadata) -> DraftInfo {
+    DraftInfo {
+        saved_at: metadata.modified().map(DateTime::from).unwrap_or_else(|_| Utc::now()),
+        bytes: metadata.len(),
+    }
+}
+
+/// The draft of a file, if it has one
+pub fn find(
+    config: &ConfigManager,
+    workspace: &str,
+    branch: &str,
+    worktree_path: &Path,
+    file_path: &str,
+) -> Option<DraftInfo> {
+    let path = draft_path(config, workspace, branch, worktree_path, file_path).ok()?;
+    fs::metadata(path).ok().map(|metadata| info(&metadata))
+}
+
+/// Drop every draft of a branch, whose worktree is going away
+pub fn discard_branch(config: &ConfigManager, workspace: &str, branch: &str) {
+    let dir = branch_drafts(config, workspace, branch);
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        if e.kind() != io::ErrorKind::NotFound {
+            log::warn!("Failed to remove the drafts in {:?}: {}", dir, e);
+        }
+    }
+}
+
+/// Remove the drafts of `workspace` not saved for `max_age`, and the
+/// branch directories left empty; how many were removed
+pub fn purge(config: &ConfigManager, workspace: &str, max_age: Duration) -> usize {
+    let Ok(branches) = fs::read_dir(drafts_root(config, workspace)) else {
+        return 0;
+    };
+    let now = SystemTime::now();
+    let mut purged = 0;
+    for branch_dir in branches.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        let Ok(drafts) = fs::read_dir(&branch_dir) else {
+            continue;
+        };
+        for draft in drafts.filter_map(|entry| entry.ok()) {
+            let expired = draft
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > max_age);
+            if expired && fs::remove_file(draft.path()).is_ok() {
+                purged += 1;
+            }
+        }
+        // Only succeeds once the directory is empty
+        let _ = fs::remove_dir(&branch_dir);
+    }
+    purged
+}
+
+/// Purge old drafts every `PURGE_INTERVAL` for as long as the server runs
+pub fn start(config: Arc<ConfigManager>) {
+    actix_rt::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let purge_config = config.clone();
+            let _ = logging::block(move || {
+                let max_age = purge_config.get_draft_retention();
+                for name in purge_config.get_workspaces().into_keys() {
+                    let purged = purge(&purge_config, &name, max_age);
+                    if purged > 0 {
+                        log::info!("Purged {} old draft(s) of workspace '{}'", purged, name);
+                    }
+                }
+            })
+            .await;
+        }
+    });
+}
+
+fn draft_not_found(file_path: &str) -> ApiError {
+    ApiError::not_found("draft_not_found", format!("No draft of '{}'", file_path))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/file/draft?path=x - The
+/// autosaved draft of a file
+pub async fn get_draft(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let draft = draft_path(&config, &workspace, &branch, &worktree_path, &query.path)?;
+
+    let (content, metadata) = match fs::read(&draft).and_then(|content| Ok((content, fs::metadata(&draft)?))) {
+        Ok(read) => read,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(draft_not_found(&query.path)),
+        Err(e) => return Err(ApiError::io("Failed to read draft", e)),
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": query.path,
+        "content": String::from_utf8_lossy(&content),
+        "saved_at": info(&metadata).saved_at
+    })))
+}
+
+/// PUT /api/workspaces/{name}/branches/{branch}/file/draft?path=x - Save a
+/// file's draft, replacing the one before
+pub async fn save_draft(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+    body: Json<SaveFileRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let draft = draft_path(&config, &workspace, &branch, &worktree_path, &query.path)?;
+    quota::require_space(&config, &workspace)?;
+
+    // Written aside and renamed over the last draft, so a crash mid-write
+    // leaves that one
+    let partial = draft.with_extension("partial");
+    fs::create_dir_all(draft.parent().unwrap_or(&draft))
+        .and_then(|()| fs::write(&partial, &body.content))
+        .and_then(|()| fs::rename(&partial, &draft))
+        .map_err(|e| ApiError::io("Failed to save draft", e))?;
+    config.add_workspace_usage(&workspace, body.content.len() as u64);
+
+    let metadata = fs::metadata(&draft).map_err(|e| ApiError::io("Failed to save draft", e))?;
+    let info = info(&metadata);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": query.path,
+        "saved_at": info.saved_at,
+        "bytes": info.bytes
+    })))
+}
+
+/// DELETE /api/workspaces/{name}/branches/{branch}/file/draft?path=x -
+/// Discard a file's draft
+pub async fn discard_draft(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let draft = draft_path(&config, &workspace, &branch, &worktree_path, &query.path)?;
+
+    match fs::remove_file(&draft) {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "message": format!("Draft of '{}' discarded", query.path),
+            "path": query.path
+        }))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(draft_not_found(&query.path)),
+        Err(e) => Err(ApiError::io("Failed to discard draft", e)),
+    }
+}
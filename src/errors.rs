@@ -0,0 +1,307 @@
+//! Errors returned by the API handlers. Every failure has the same body:
+//!
+//! ```json
+//! {"error": {"code": "workspace_not_found", "message": "Workspace 'docs' not found", "details": null}}
+//! ```
+//!
+//! `code` is stable and meant for programs; `message` is for people and may
+//! change wording. `details` is an object with extra context for some codes,
+//! otherwise null.
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::git_ops::GitError;
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// 400: the request is malformed or names something invalid
+    Validation(ErrorBody),
+    /// 401: no token, or one that isn't valid
+    Unauthorized(ErrorBody),
+    /// 403: the caller is known but the operation isn't allowed
+    Forbidden(ErrorBody),
+    NotFound(ErrorBody),
+    /// 409: clashes with existing state or an operation in progress
+    Conflict(ErrorBody),
+    /// 412: an `If-Match` condition doesn't hold
+    PreconditionFailed(ErrorBody),
+    /// 423: what the operation needs is held by another one
+    Locked(ErrorBody),
+    PayloadTooLarge(ErrorBody),
+    UnsupportedMediaType(ErrorBody),
+    /// 422: the request is well-formed but the repository refused it
+    UnprocessableEntity(ErrorBody),
+    /// 429, with the time until the caller may try again
+    TooManyRequests(ErrorBody, Duration),
+    /// 504: an operation ran past its time limit and was stopped
+    Timeout(ErrorBody),
+    /// 507: the operation would take disk space the server won't give
+    InsufficientStorage(ErrorBody),
+    /// 500: a git command failed; its stderr goes into `details`
+    GitFailure(GitError),
+    /// A filesystem operation failed; the status follows the error kind
+    Io(String, std::io::Error),
+    /// 500 for anything else
+    Internal(ErrorBody),
+}
+
+#[derive(Debug)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<Value>,
+}
+
+impl ErrorBody {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+}
+
+impl ApiError {
+    pub fn validation(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Validation(ErrorBody::new(code, message))
+    }
+
+    pub fn unauthorized(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Unauthorized(ErrorBody::new(code, message))
+    }
+
+    pub fn forbidden(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Forbidden(ErrorBody::new(code, message))
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::NotFound(ErrorBody::new(code, message))
+    }
+
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Conflict(ErrorBody::new(code, message))
+    }
+
+    pub fn precondition_failed(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::PreconditionFailed(ErrorBody::new(code, message))
+    }
+
+    pub fn locked(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Locked(ErrorBody::new(code, message))
+    }
+
+    pub fn payload_too_large(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::PayloadTooLarge(ErrorBody::new(code, message))
+    }
+
+    pub fn unsupported_media_type(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::UnsupportedMediaType(ErrorBody::new(code, message))
+    }
+
+    pub fn unprocessable(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::UnprocessableEntity(ErrorBody::new(code, message))
+    }
+
+    pub fn too_many_requests(message: impl Into<String>, retry_after: Duration) -> Self {
+        let mut body = ErrorBody::new("rate_limited", message);
+        body.details = Some(serde_json::json!({ "retry_after_secs": retry_after.as_secs() + 1 }));
+        ApiError::TooManyRequests(body, retry_after)
+    }
+
+    pub fn timeout(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Timeout(ErrorBody::new(code, message))
+    }
+
+    pub fn insufficient_storage(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::InsufficientStorage(ErrorBody::new(code, message))
+    }
+
+    pub fn io(context: impl Into<String>, error: std::io::Error) -> Self {
+        ApiError::Io(context.into(), error)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        ApiError::Internal(ErrorBody::new("internal_error", message))
+    }
+
+    /// 500 with a code of its own, for failures the server's operator has
+    /// to fix
+    pub fn server(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Internal(ErrorBody::new(code, message))
+    }
+
+    pub fn workspace_not_found(name: &str) -> Self {
+        Self::not_found("workspace_not_found", format!("Workspace '{}' not found", name))
+    }
+
+    pub fn worktree_not_found(branch: &str) -> Self {
+        Self::not_found("worktree_not_found", format!("Worktree '{}' not found", branch))
+    }
+
+    /// Attach structured details. Git and I/O errors fill in their own and
+    /// are returned unchanged.
+    pub fn with_details(mut self, details: Value) -> Self {
+        if let Some(body) = self.body_mut() {
+            body.details = Some(details);
+        }
+        self
+    }
+
+    fn body_mut(&mut self) -> Option<&mut ErrorBody> {
+        match self {
+            ApiError::Validation(body)
+            | ApiError::Unauthorized(body)
+            | ApiError::Forbidden(body)
+            | ApiError::NotFound(body)
+            | ApiError::Conflict(body)
+            | ApiError::PreconditionFailed(body)
+            | ApiError::Locked(body)
+            | ApiError::PayloadTooLarge(body)
+            | ApiError::UnsupportedMediaType(body)
+            | ApiError::UnprocessableEntity(body)
+            | ApiError::TooManyRequests(body, _)
+            | ApiError::Timeout(body)
+            | ApiError::InsufficientStorage(body)
+            | ApiError::Internal(body) => Some(body),
+            ApiError::GitFailure(_) | ApiError::Io(..) => None,
+        }
+    }
+
+    /// Machine-readable code sent as `error.code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(body)
+            | ApiError::Unauthorized(body)
+            | ApiError::Forbidden(body)
+            | ApiError::NotFound(body)
+            | ApiError::Conflict(body)
+            | ApiError::PreconditionFailed(body)
+            | ApiError::Locked(body)
+            | ApiError::PayloadTooLarge(body)
+            | ApiError::UnsupportedMediaType(body)
+            | ApiError::UnprocessableEntity(body)
+            | ApiError::TooManyRequests(body, _)
+            | ApiError::Timeout(body)
+            | ApiError::InsufficientStorage(body)
+            | ApiError::Internal(body) => body.code,
+            ApiError::GitFailure(_) => "git_failed",
+            ApiError::Io(_, e) => match e.kind() {
+                std::io::ErrorKind::NotFound => "path_not_found",
+                std::io::ErrorKind::InvalidInput => "invalid_path",
+                std::io::ErrorKind::AlreadyExists => "path_exists",
+                _ => "io_error",
+            },
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::GitFailure(e) => e.stderr.as_ref().map(|stderr| serde_json::json!({ "stderr": stderr })),
+            ApiError::Io(..) => None,
+            ApiError::Validation(body)
+            | ApiError::Unauthorized(body)
+            | ApiError::Forbidden(body)
+            | ApiError::NotFound(body)
+            | ApiError::Conflict(body)
+            | ApiError::PreconditionFailed(body)
+            | ApiError::Locked(body)
+            | ApiError::PayloadTooLarge(body)
+            | ApiError::UnsupportedMediaType(body)
+            | ApiError::UnprocessableEntity(body)
+            | ApiError::TooManyRequests(body, _)
+            | ApiError::Timeout(body)
+            | ApiError::InsufficientStorage(body)
+            | ApiError::Internal(body) => body.details.clone(),
+        }
+    }
+
+    /// The `{"error": {...}}` response body
+    pub fn body(&self) -> Value {
+        serde_json::json!({
+            "error": {
+                "code": self.code(),
+                "message": self.to_string(),
+                "details": self.details()
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Validation(body)
+            | ApiError::Unauthorized(body)
+            | ApiError::Forbidden(body)
+            | ApiError::NotFound(body)
+            | ApiError::Conflict(body)
+            | ApiError::PreconditionFailed(body)
+            | ApiError::Locked(body)
+            | ApiError::PayloadTooLarge(body)
+            | ApiError::UnsupportedMediaType(body)
+            | ApiError::UnprocessableEntity(body)
+            | ApiError::TooManyRequests(body, _)
+            | ApiError::Timeout(body)
+            | ApiError::InsufficientStorage(body)
+            | ApiError::Internal(body) => write!(f, "{}", body.message),
+            ApiError::GitFailure(e) => write!(f, "{}", e),
+            ApiError::Io(context, e) => write!(f, "{}: {}", context, e),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::Locked(_) => StatusCode::LOCKED,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::Io(_, e) => match e.kind() {
+                std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                std::io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+                std::io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            ApiError::GitFailure(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            log::error!("{}", self);
+        }
+
+        let mut response = HttpResponse::build(status);
+        if let ApiError::TooManyRequests(_, retry_after) = self {
+            response.insert_header(("Retry-After", (retry_after.as_secs() + 1).to_string()));
+        }
+        response.json(self.body())
+    }
+}
+
+impl From<GitError> for ApiError {
+    fn from(e: GitError) -> Self {
+        ApiError::GitFailure(e)
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ApiError {
+    fn from(e: actix_web::error::BlockingError) -> Self {
+        ApiError::internal(format!("Background task failed: {}", e))
+    }
+}
@@ -0,0 +1,209 @@
+//! Server-sent events: job updates, file changes and workspace/branch
+//! lifecycle changes, streamed to clients from `GET /api/events` instead of
+//! being polled for. Everything goes through one broadcast channel; a client
+//! that falls too far behind is disconnected (and told so) rather than
+//! buffered for.
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::auth;
+use crate::config::TokenIdentity;
+use crate::errors::ApiError;
+use crate::jobs::JobInfo;
+use crate::watcher::FileChangeEvent;
+use crate::AppState;
+
+/// Events buffered per subscriber before it counts as lagging
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Comment lines sent on an idle stream so proxies don't time it out
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A job was queued, changed state or reported progress
+    Job { job: JobInfo },
+    FileChanged(FileChangeEvent),
+    WorkspaceCreated { workspace: String },
+    /// Repository URL or base branch changed
+    WorkspaceUpdated { workspace: String },
+    WorkspaceRenamed { workspace: String, new_name: String },
+    WorkspaceDeleted { workspace: String },
+    BranchCreated { workspace: String, branch: String },
+    BranchDeleted { workspace: String, branch: String },
+    BranchRenamed { workspace: String, branch: String, new_name: String },
+    /// A git operation that changed the repository or a branch finished
+    Git {
+        workspace: String,
+        branch: Option<String>,
+        operation: &'static str,
+        commit_id: Option<String>,
+    },
+    /// A runbook block started running, in the worktree or a terminal
+    /// session
+    BlockExecuted {
+        workspace: String,
+        branch: String,
+        path: String,
+        block_index: usize,
+        execution_id: String,
+        token: Option<String>,
+        session_id: Option<String>,
+    },
+    /// A terminal session was opened over the WebSocket
+    SessionOpened {
+        session_id: String,
+        label: Option<String>,
+        token: Option<String>,
+    },
+    /// A scheduled run stopped at a block that failed or couldn't run
+    ScheduleFailed {
+        schedule_id: String,
+        workspace: String,
+        branch: String,
+        path: String,
+        /// None when the run failed before any block
+        block_index: Option<usize>,
+        execution_id: Option<String>,
+        exit_code: Option<i32>,
+        error: Option<String>,
+    },
+}
+
+impl Event {
+    /// Whether the event is about workspace `name` (under either name, for
+    /// a rename)
+    pub fn concerns(&self, name: &str) -> bool {
+        match self {
+            Event::Job { job } => job.workspace == name,
+            Event::FileChanged(change) => change.workspace == name,
+            Event::WorkspaceRenamed { workspace, new_name } => workspace == name || new_name == name,
+            Event::WorkspaceCreated { workspace }
+            | Event::WorkspaceUpdated { workspace }
+            | Event::WorkspaceDeleted { workspace }
+            | Event::BranchCreated { workspace, .. }
+            | Event::BranchDeleted { workspace, .. }
+            | Event::BranchRenamed { workspace, .. }
+            | Event::Git { workspace, .. }
+            | Event::BlockExecuted { workspace, .. }
+            | Event::ScheduleFailed { workspace, .. } => workspace == name,
+            Event::SessionOpened { .. } => false,
+        }
+    }
+
+    /// Whether `identity` may see the event: a token limited to some
+    /// workspaces only sees those about one of them
+    pub fn visible_to(&self, identity: &TokenIdentity) -> bool {
+        !identity.is_restricted() || identity.workspaces.iter().any(|name| self.concerns(name))
+    }
+}
+
+/// The channel events are published into
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // No subscribers is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Only events about this workspace
+    pub workspace: Option<String>,
+}
+
+fn frame(data: &serde_json::Value) -> web::Bytes {
+    web::Bytes::from(format!("data: {}\n\n", data))
+}
+
+/// GET /api/events - Stream events as `text/event-stream`; only those
+/// about workspaces the token may use
+pub async fn stream_events(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    struct Stream {
+        events: broadcast::Receiver<Event>,
+        heartbeat: tokio::time::Interval,
+        workspace: Option<String>,
+        identity: Option<TokenIdentity>,
+    }
+
+    let workspace = query.into_inner().workspace;
+    if let Some(workspace) = &workspace {
+        auth::require_workspace_access(&req, workspace)?;
+    }
+    let start = tokio::time::Instant::now() + HEARTBEAT_INTERVAL;
+    let stream = Stream {
+        events: state.events.subscribe(),
+        heartbeat: tokio::time::interval_at(start, HEARTBEAT_INTERVAL),
+        workspace,
+        identity: req.extensions().get::<TokenIdentity>().cloned(),
+    };
+    log::debug!("Event stream opened");
+
+    // The state is None once the stream has ended
+    let body = futures::stream::unfold(Some(stream), |stream| async move {
+        let mut stream = stream?;
+        loop {
+            tokio::select! {
+                _ = stream.heartbeat.tick() => {
+                    let beat = web::Bytes::from_static(b": heartbeat\n\n");
+                    return Some((Ok::<_, std::io::Error>(beat), Some(stream)));
+                }
+                received = stream.events.recv() => match received {
+                    Ok(event) => {
+                        if stream.workspace.as_deref().is_some_and(|name| !event.concerns(name))
+                            || stream.identity.as_ref().is_some_and(|identity| !event.visible_to(identity))
+                        {
+                            continue;
+                        }
+                        let data = serde_json::to_value(&event).unwrap_or_default();
+                        return Some((Ok(frame(&data)), Some(stream)));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        log::warn!("Event stream fell {} events behind, disconnecting it", missed);
+                        let data = serde_json::json!({ "type": "lagged", "missed": missed });
+                        return Some((Ok(frame(&data)), None));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // Keep nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        // Compression would hold events back until the encoder's buffer fills
+        .insert_header(actix_web::http::header::ContentEncoding::Identity)
+        .streaming(body))
+}
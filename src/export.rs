@@ -0,0 +1,241 @@
+//! Runbook export: a markdown file rendered to one self-contained HTML page,
+//! for attaching an executed runbook (with its recorded `output` blocks) to
+//! a postmortem. Images in the worktree are inlined as data URIs; the front
+//! matter becomes a metadata header and a footer names the commit exported.
+
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use pulldown_cmark::{CowStr, Event, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::blocks;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::file_ops;
+use crate::git_ops;
+use crate::logging;
+use crate::render;
+use crate::workspace;
+
+/// Image types inlined, by file extension
+const IMAGE_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+];
+
+const STYLE: &str = "\
+body{font-family:-apple-system,BlinkMacSystemFont,'Segoe UI',Helvetica,Arial,sans-serif;\
+max-width:60rem;margin:2rem auto;padding:0 1rem;line-height:1.5;color:#1f2328}\
+pre{background:#f6f8fa;padding:.75rem;overflow-x:auto;border-radius:6px}\
+pre code.language-output{display:block;border-left:3px solid #8250df;padding-left:.75rem}\
+table{border-collapse:collapse}td,th{border:1px solid #d0d7de;padding:.25rem .5rem}\
+img{max-width:100%}\
+.metadata{background:#f6f8fa;padding:.5rem 1rem;border-radius:6px}\
+.metadata dt{font-weight:600}.metadata dd{margin:0 0 .5rem 1rem}\
+.missing-image{display:inline-block;border:1px dashed #cf222e;color:#cf222e;padding:.25rem .5rem}\
+footer{margin-top:3rem;color:#656d76;font-size:.875rem;border-top:1px solid #d0d7de;padding-top:.5rem}";
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub path: String,
+    /// Only `html` so far
+    pub format: Option<String>,
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Image `src` of the runbook at `runbook_path` as a data URI, or why it
+/// can't be inlined
+fn inline_image(worktree_path: &Path, runbook_path: &str, src: &str, max_bytes: u64) -> Result<String, String> {
+    let path = blocks::link_path(runbook_path, src).ok_or_else(|| "outside the worktree".to_string())?;
+    let extension = Path::new(&path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let Some((_, mime)) = IMAGE_TYPES.iter().find(|(known, _)| *known == extension) else {
+        return Err("unsupported image type".to_string());
+    };
+    let full_path = file_ops::safe_join(worktree_path, &path).map_err(|e| e.to_string())?;
+    let size = std::fs::metadata(&full_path).map_err(|_| "not found".to_string())?.len();
+    if size > max_bytes {
+        return Err(format!("larger than {} bytes", max_bytes));
+    }
+    if file_ops::lfs_pointer(&full_path).is_some() {
+        return Err("Git LFS content not downloaded".to_string());
+    }
+    let data = std::fs::read(&full_path).map_err(|e| e.to_string())?;
+    Ok(format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(data)))
+}
+
+/// The markdown of `content` as HTML, with worktree images inlined and
+/// missing ones replaced by a placeholder
+fn render_body(content: &str, runbook_path: &str, worktree_path: &Path, max_image_bytes: u64) -> String {
+    let mut events = Vec::new();
+    // Set while skipping the alt text of an image replaced by a placeholder
+    let mut in_missing_image = false;
+    for event in Parser::new_ext(content, render::markdown_options()) {
+        match event {
+            Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                if blocks::is_url(&dest_url) {
+                    events.push(Event::Start(Tag::Image { link_type, dest_url, title, id }));
+                    continue;
+                }
+                match inline_image(worktree_path, runbook_path, &dest_url, max_image_bytes) {
+                    Ok(data_uri) => events.push(Event::Start(Tag::Image {
+                        link_type,
+                        dest_url: CowStr::from(data_uri),
+                        title,
+                        id,
+                    })),
+                    Err(reason) => {
+                        log::debug!("Not inlining image {} in export: {}", dest_url, reason);
+                        events.push(Event::InlineHtml(CowStr::from(format!(
+                            "<span class=\"missing-image\">Image {} not included: {}</span>",
+                            escape(&dest_url),
+                            escape(&reason)
+                        ))));
+                        in_missing_image = true;
+                    }
+                }
+            }
+            Event::End(TagEnd::Image) if in_missing_image => in_missing_image = false,
+            _ if in_missing_image => {}
+            event => events.push(event),
+        }
+    }
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    html
+}
+
+/// Front matter as a definition list
+fn render_metadata(front_matter: &serde_json::Map<String, serde_json::Value>) -> String {
+    let mut html = String::from("<dl class=\"metadata\">\n");
+    for (key, value) in front_matter {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        html.push_str(&format!("<dt>{}</dt><dd>{}</dd>\n", escape(key), escape(&value)));
+    }
+    html.push_str("</dl>\n");
+    html
+}
+
+/// The export of the runbook `path` with `content`
+fn render(
+    worktree_path: &Path,
+    workspace: &str,
+    branch: &str,
+    path: &str,
+    content: &str,
+    max_image_bytes: u64,
+) -> String {
+    let runbook = blocks::parse(content);
+    let front_matter = runbook
+        .front_matter
+        .as_deref()
+        .and_then(|text| serde_yaml::from_str::<serde_json::Value>(text).ok())
+        .and_then(|value| match value {
+            serde_json::Value::Object(map) => Some(map),
+            _ => None,
+        });
+    let title = front_matter
+        .as_ref()
+        .and_then(|map| map.get("title"))
+        .and_then(|title| title.as_str())
+        .map(str::to_string)
+        .or_else(|| runbook.headings.first().map(|heading| heading.title.clone()))
+        .unwrap_or_else(|| path.to_string());
+
+    let commit = match git_ops::commit_summary(worktree_path, "HEAD") {
+        Ok(commit) => format!(
+            "commit <code>{}</code> ({})",
+            escape(&commit.sha),
+            escape(&commit.subject)
+        ),
+        Err(e) => {
+            log::warn!("Failed to read HEAD of {:?} for export: {}", worktree_path, e);
+            "an unknown commit".to_string()
+        }
+    };
+    let footer = format!(
+        "Exported {} from <code>{}</code> in {}/{} at {}; file SHA-256 <code>{}</code>",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        escape(path),
+        escape(workspace),
+        escape(branch),
+        commit,
+        blocks::content_hash(content)
+    );
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}{}<footer>{}</footer>\n</body>\n</html>\n",
+        escape(&title),
+        STYLE,
+        front_matter.as_ref().map(render_metadata).unwrap_or_default(),
+        render_body(content, path, worktree_path, max_image_bytes),
+        footer
+    )
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/export?path=x&format=html -
+/// Download a runbook as a self-contained HTML page
+pub async fn export_runbook(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let query = query.into_inner();
+    match query.format.as_deref().unwrap_or("html") {
+        "html" => {}
+        other => {
+            return Err(ApiError::validation(
+                "invalid_export_format",
+                format!("Unsupported export format '{}', expected html", other),
+            ));
+        }
+    }
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let content = workspace::read_text_file(&config, &worktree_path, &query.path)?;
+
+    let max_image_bytes = config.get_max_read_bytes();
+    let name = Path::new(&query.path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().replace(['"', '\\'], "_"))
+        .unwrap_or_else(|| "runbook".to_string());
+    let html = logging::block(move || {
+        render(&worktree_path, &workspace, &branch, &query.path, &content, max_image_bytes)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.html\"", name),
+        ))
+        .body(html))
+}
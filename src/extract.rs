@@ -0,0 +1,251 @@
+use actix_web::dev::{self, ServiceResponse};
+use actix_web::error::{JsonPayloadError, QueryPayloadError};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
+use actix_web::{web, FromRequest, HttpRequest, ResponseError};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::ops::{Deref, DerefMut};
+
+use crate::errors::ApiError;
+
+/// JSON bodies up to `limit` bytes; anything that doesn't parse gets the same
+/// error body as handler errors instead of actix's plain text
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, req| {
+            log::warn!("Rejected JSON body for {} {}: {}", req.method(), req.path(), err);
+            json_error(err).into()
+        })
+}
+
+/// Raw bodies (`Bytes`/`String` extractors) up to `limit` bytes. Unlike
+/// `JsonConfig` it takes no error handler; `payload_errors` gives its
+/// overflows the JSON error body.
+pub fn payload_config(limit: usize) -> web::PayloadConfig {
+    web::PayloadConfig::new(limit)
+}
+
+/// Replace the plain text of a 413 that didn't come from a handler, such as
+/// a raw body over `payload_config`'s limit, with the JSON error body
+pub fn payload_errors<B: 'static>() -> ErrorHandlers<B> {
+    ErrorHandlers::new().handler(StatusCode::PAYLOAD_TOO_LARGE, |res: ServiceResponse<B>| {
+        let json = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+        if json {
+            return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+        }
+        let (req, _) = res.into_parts();
+        log::warn!("Rejected body for {} {}: over the size limit", req.method(), req.path());
+        let response = ApiError::payload_too_large("body_too_large", "Request body exceeds the size limit")
+            .error_response();
+        Ok(ErrorHandlerResponse::Response(
+            ServiceResponse::new(req, response).map_into_right_body(),
+        ))
+    })
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| match err {
+        QueryPayloadError::Deserialize(e) => {
+            let message = e.to_string();
+            with_field(
+                ApiError::validation("invalid_query", format!("Invalid query string: {}", message)),
+                &message,
+            )
+            .into()
+        }
+        other => other.into(),
+    })
+}
+
+/// `web::Json` that also names the field holding a value of the wrong type.
+/// The body is read and checked as `web::Json` reads it, per `json_config`,
+/// then deserialized here, where its text is at hand to find the field.
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T> Json<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Json<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        let raw = web::Json::<Box<RawValue>>::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            let raw = raw.await?.into_inner();
+            serde_json::from_str(raw.get()).map(Json).map_err(|e| {
+                log::warn!("Rejected JSON body for {} {}: {}", req.method(), req.path(), e);
+                invalid_body(&e, raw.get()).into()
+            })
+        })
+    }
+}
+
+fn json_error(err: JsonPayloadError) -> ApiError {
+    match err {
+        JsonPayloadError::OverflowKnownLength { length, limit } => ApiError::payload_too_large(
+            "body_too_large",
+            format!("Request body of {} bytes exceeds the {} byte limit", length, limit),
+        )
+        .with_details(serde_json::json!({ "length": length, "limit": limit })),
+        JsonPayloadError::Overflow { limit } => ApiError::payload_too_large(
+            "body_too_large",
+            format!("Request body exceeds the {} byte limit", limit),
+        )
+        .with_details(serde_json::json!({ "limit": limit })),
+        JsonPayloadError::ContentType => ApiError::validation(
+            "invalid_content_type",
+            "Expected a JSON body with Content-Type: application/json",
+        ),
+        JsonPayloadError::Deserialize(e) => {
+            let message = e.to_string();
+            with_field(
+                ApiError::validation("invalid_body", format!("Invalid request body: {}", message)),
+                &message,
+            )
+        }
+        other => ApiError::validation("invalid_body", format!("Invalid request body: {}", other)),
+    }
+}
+
+/// 400 `invalid_body` for well-formed JSON that doesn't fit the request
+/// type, with `details.field` the path to the value that doesn't, e.g.
+/// `steps[1].timeout`
+fn invalid_body(e: &serde_json::Error, text: &str) -> ApiError {
+    let message = e.to_string();
+    let error = ApiError::validation("invalid_body", format!("Invalid request body: {}", message));
+
+    let line_start = match e.line() {
+        0 | 1 => 0,
+        line => text.match_indices('\n').nth(line - 2).map_or(text.len(), |(i, _)| i + 1),
+    };
+    let mut end = line_start + e.column();
+    // A missing field is reported past the object's closing brace
+    if message.starts_with("missing field") {
+        end = end.saturating_sub(1);
+    }
+    let mut open = open_at(text.get(..end).unwrap_or(text));
+    // Missing, unknown or duplicate fields are named in the message
+    if let Some(field) = named_field(&message) {
+        match open.last_mut() {
+            Some(Open::Object(key)) => *key = Some(field.to_string()),
+            _ => open.push(Open::Object(Some(field.to_string()))),
+        }
+    }
+
+    let mut field = String::new();
+    for open in open {
+        match open {
+            Open::Object(Some(key)) => {
+                if !field.is_empty() {
+                    field.push('.');
+                }
+                field.push_str(&key);
+            }
+            Open::Object(None) => {}
+            Open::Array(index) => field.push_str(&format!("[{}]", index)),
+        }
+    }
+    if field.is_empty() {
+        error
+    } else {
+        error.with_details(serde_json::json!({ "field": field }))
+    }
+}
+
+/// An object or array `open_at` is inside of
+enum Open {
+    /// With the key of the member being read, `None` before the first
+    Object(Option<String>),
+    /// With the index of the element being read
+    Array(usize),
+}
+
+/// The objects and arrays still open at the end of `text`, the start of a
+/// valid JSON document
+fn open_at(text: &str) -> Vec<Open> {
+    let mut open = Vec::new();
+    let mut expect_key = false;
+    let mut chars = text.char_indices();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '{' => {
+                open.push(Open::Object(None));
+                expect_key = true;
+            }
+            '[' => open.push(Open::Array(0)),
+            '}' | ']' => {
+                open.pop();
+            }
+            ',' => match open.last_mut() {
+                Some(Open::Object(_)) => expect_key = true,
+                Some(Open::Array(index)) => *index += 1,
+                None => {}
+            },
+            '"' => {
+                let mut escaped = false;
+                let mut end = text.len();
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                if std::mem::take(&mut expect_key) {
+                    if let Some(Open::Object(key)) = open.last_mut() {
+                        *key = serde_json::from_str(&text[start..end]).ok();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    open
+}
+
+/// The field serde named in its message (missing, unknown or duplicate
+/// field), if any
+fn named_field(message: &str) -> Option<&str> {
+    message
+        .split_once("field `")
+        .and_then(|(_, rest)| rest.split_once('`'))
+        .map(|(field, _)| field)
+}
+
+/// Add the field serde named in its message as `details.field`, if any
+fn with_field(error: ApiError, message: &str) -> ApiError {
+    match named_field(message) {
+        Some(field) => error.with_details(serde_json::json!({ "field": field })),
+        None => error,
+    }
+}
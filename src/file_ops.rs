@@ -1,6 +1,9 @@
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileEntry {
@@ -9,6 +12,11 @@ pub struct FileEntry {
     pub is_dir: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub children: Option<Vec<FileEntry>>,
+    /// Parsed YAML front matter (only populated on request)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontmatter: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub frontmatter_warnings: Vec<String>,
 }
 
 /// List files in a directory (recursively for markdown files)
@@ -72,6 +80,8 @@ fn list_files_recursive(dir: &Path, base_path: &Path) -> Result<Vec<FileEntry>,
                     path: relative,
                     is_dir: true,
                     children: Some(children),
+                    frontmatter: None,
+                    frontmatter_warnings: Vec::new(),
                 });
             }
         } else if name.ends_with(".md") || name.ends_with(".markdown") {
@@ -81,6 +91,8 @@ fn list_files_recursive(dir: &Path, base_path: &Path) -> Result<Vec<FileEntry>,
                 path: relative,
                 is_dir: false,
                 children: None,
+                frontmatter: None,
+                frontmatter_warnings: Vec::new(),
             });
         }
     }
@@ -99,22 +111,375 @@ fn has_markdown_files(entries: &[FileEntry]) -> bool {
     })
 }
 
-/// Read file content
-pub fn read_file(base_path: &Path, file_path: &str) -> Result<String, std::io::Error> {
+/// Upper bound on how much of a file is scanned looking for the closing
+/// front matter delimiter
+const MAX_FRONTMATTER_BYTES: usize = 64 * 1024;
+
+/// Parse the leading `---` YAML block of a markdown file. Only the front matter
+/// itself is read from disk. Missing front matter yields `None` with no
+/// warnings; malformed front matter yields `None` plus a description of the
+/// problem.
+pub fn parse_frontmatter(path: &Path) -> (Option<serde_json::Value>, Vec<String>) {
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) => return (None, vec![format!("Failed to open file: {}", e)]),
+    };
+    let mut reader = BufReader::new(file);
+
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(_) if line.trim_end() == "---" => {}
+        // No front matter (or unreadable/binary start of file)
+        _ => return (None, Vec::new()),
+    }
+
+    let mut yaml = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => return (None, vec!["Front matter is not terminated by '---'".to_string()]),
+            Ok(_) => {}
+            Err(e) => return (None, vec![format!("Failed to read front matter: {}", e)]),
+        }
+
+        let trimmed = line.trim_end();
+        if trimmed == "---" || trimmed == "..." {
+            break;
+        }
+
+        yaml.push_str(&line);
+        if yaml.len() > MAX_FRONTMATTER_BYTES {
+            return (
+                None,
+                vec![format!("Front matter exceeds {} bytes", MAX_FRONTMATTER_BYTES)],
+            );
+        }
+    }
+
+    match serde_yaml::from_str::<serde_json::Value>(&yaml) {
+        Ok(value @ serde_json::Value::Object(_)) => (Some(value), Vec::new()),
+        Ok(serde_json::Value::Null) => (None, Vec::new()),
+        Ok(_) => (None, vec!["Front matter is not a key/value mapping".to_string()]),
+        Err(e) => (None, vec![format!("Invalid front matter: {}", e)]),
+    }
+}
+
+/// Populate `frontmatter` for every file in the tree
+pub fn annotate_frontmatter(base_path: &Path, entries: &mut [FileEntry]) {
+    for entry in entries {
+        if entry.is_dir {
+            if let Some(children) = entry.children.as_mut() {
+                annotate_frontmatter(base_path, children);
+            }
+        } else {
+            let (frontmatter, warnings) = parse_frontmatter(&base_path.join(&entry.path));
+            entry.frontmatter = frontmatter;
+            entry.frontmatter_warnings = warnings;
+        }
+    }
+}
+
+/// Keep only files whose front matter has `key` equal to `value` (or, for
+/// list values, containing `value`), dropping directories left empty.
+/// Entries must already be annotated.
+pub fn filter_by_frontmatter(entries: Vec<FileEntry>, key: &str, value: &str) -> Vec<FileEntry> {
+    entries
+        .into_iter()
+        .filter_map(|mut entry| {
+            if entry.is_dir {
+                let children = filter_by_frontmatter(entry.children.take().unwrap_or_default(), key, value);
+                if children.is_empty() {
+                    return None;
+                }
+                entry.children = Some(children);
+                return Some(entry);
+            }
+
+            let matches = entry
+                .frontmatter
+                .as_ref()
+                .and_then(|fm| fm.get(key))
+                .map(|v| match v {
+                    serde_json::Value::Array(items) => items.iter().any(|i| scalar_eq(i, value)),
+                    other => scalar_eq(other, value),
+                })
+                .unwrap_or(false);
+
+            matches.then_some(entry)
+        })
+        .collect()
+}
+
+/// Drop front matter from the tree again (used when filtering without `detail`)
+pub fn strip_frontmatter(entries: &mut [FileEntry]) {
+    for entry in entries {
+        entry.frontmatter = None;
+        entry.frontmatter_warnings.clear();
+        if let Some(children) = entry.children.as_mut() {
+            strip_frontmatter(children);
+        }
+    }
+}
+
+fn scalar_eq(v: &serde_json::Value, expected: &str) -> bool {
+    match v {
+        serde_json::Value::String(s) => s == expected,
+        serde_json::Value::Number(n) => n.to_string() == expected,
+        serde_json::Value::Bool(b) => b.to_string() == expected,
+        _ => false,
+    }
+}
+
+/// A single search hit within a file
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchMatch {
+    pub path: String,
+    pub line_number: usize,
+    pub line: String,
+    /// Byte offsets of the match within `line`
+    pub match_start: usize,
+    pub match_end: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub matches: Vec<SearchMatch>,
+    pub truncated: bool,
+}
+
+/// Build the matcher used by `search`. Literal queries are escaped so both
+/// modes share the same matching code.
+pub fn build_search_pattern(
+    query: &str,
+    regex: bool,
+    case_sensitive: bool,
+) -> Result<Regex, regex::Error> {
+    let pattern = if regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+}
+
+/// Paths of all files listed by `list_files`, in tree order
+pub fn list_file_paths(base_path: &Path) -> Result<Vec<String>, std::io::Error> {
+    let mut files = Vec::new();
+    collect_file_paths(&list_files(base_path, None)?, &mut files);
+    Ok(files)
+}
+
+/// Search the contents of all files listed by `list_files`
+pub fn search(base_path: &Path, pattern: &Regex, limit: usize) -> Result<SearchResults, std::io::Error> {
+    let files = list_file_paths(base_path)?;
+
+    // Read and scan files on a handful of threads; results are kept per file so
+    // the output order matches the tree order regardless of scheduling
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(files.len().max(1));
+    let chunk_size = files.len().div_ceil(workers).max(1);
+
+    let mut per_file: Vec<Vec<SearchMatch>> = Vec::with_capacity(files.len());
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|rel| search_file(base_path, rel, pattern, limit + 1))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            per_file.extend(handle.join().unwrap_or_default());
+        }
+    });
+
+    let mut matches: Vec<SearchMatch> = per_file.into_iter().flatten().collect();
+    let truncated = matches.len() > limit;
+    matches.truncate(limit);
+
+    Ok(SearchResults { matches, truncated })
+}
+
+fn collect_file_paths(entries: &[FileEntry], out: &mut Vec<String>) {
+    for entry in entries {
+        if entry.is_dir {
+            if let Some(children) = &entry.children {
+                collect_file_paths(children, out);
+            }
+        } else {
+            out.push(entry.path.clone());
+        }
+    }
+}
+
+/// Search a single file, returning at most `max` matches. Unreadable and
+/// binary files are skipped.
+fn search_file(base_path: &Path, rel: &str, pattern: &Regex, max: usize) -> Vec<SearchMatch> {
+    let mut matches = Vec::new();
+
+    let bytes = match fs::read(base_path.join(rel)) {
+        Ok(b) => b,
+        Err(e) => {
+            log::debug!("Skipping unreadable file {}: {}", rel, e);
+            return matches;
+        }
+    };
+
+    // Treat NUL bytes or invalid UTF-8 as binary content
+    if bytes.contains(&0) {
+        return matches;
+    }
+    let content = match std::str::from_utf8(&bytes) {
+        Ok(c) => c,
+        Err(_) => return matches,
+    };
+
+    for (idx, line) in content.lines().enumerate() {
+        for m in pattern.find_iter(line) {
+            // Empty matches (e.g. `^`) carry no useful highlight
+            if m.start() == m.end() {
+                continue;
+            }
+            matches.push(SearchMatch {
+                path: rel.to_string(),
+                line_number: idx + 1,
+                line: line.to_string(),
+                match_start: m.start(),
+                match_end: m.end(),
+            });
+            if matches.len() >= max {
+                return matches;
+            }
+        }
+    }
+
+    matches
+}
+
+/// Errors returned by `read_file`
+#[derive(Debug)]
+pub enum ReadFileError {
+    Io(std::io::Error),
+    /// File is larger than the configured read limit
+    TooLarge { size: u64, limit: u64 },
+    /// File is not valid UTF-8 text
+    Binary { size: u64 },
+}
+
+impl std::fmt::Display for ReadFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadFileError::Io(e) => write!(f, "{}", e),
+            ReadFileError::TooLarge { size, limit } => {
+                write!(f, "File is {} bytes, exceeding the {} byte limit", size, limit)
+            }
+            ReadFileError::Binary { size } => write!(f, "File is binary ({} bytes)", size),
+        }
+    }
+}
+
+impl From<std::io::Error> for ReadFileError {
+    fn from(e: std::io::Error) -> Self {
+        ReadFileError::Io(e)
+    }
+}
+
+/// Read file content as text, refusing files over `max_bytes` or not valid UTF-8
+pub fn read_file(base_path: &Path, file_path: &str, max_bytes: u64) -> Result<String, ReadFileError> {
+    let full_path = resolve_file(base_path, file_path)?;
+
+    let size = fs::metadata(&full_path)?.len();
+    if size > max_bytes {
+        return Err(ReadFileError::TooLarge { size, limit: max_bytes });
+    }
+
+    let bytes = fs::read(&full_path)?;
+    String::from_utf8(bytes).map_err(|_| ReadFileError::Binary { size })
+}
+
+/// Resolve a path to an existing file or directory inside `base_path`
+pub fn resolve_path(base_path: &Path, file_path: &str) -> Result<PathBuf, std::io::Error> {
     let full_path = safe_join(base_path, file_path)?;
 
     if !full_path.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Path not found: {}", file_path),
+        ));
+    }
+
+    Ok(full_path)
+}
+
+/// Resolve a path to an existing regular file inside `base_path`
+pub fn resolve_file(base_path: &Path, file_path: &str) -> Result<PathBuf, std::io::Error> {
+    let full_path = safe_join(base_path, file_path)?;
+
+    if !full_path.is_file() {
         return Err(std::io::Error::new(
             std::io::ErrorKind::NotFound,
             format!("File not found: {}", file_path),
         ));
     }
 
-    fs::read_to_string(&full_path)
+    Ok(full_path)
+}
+
+/// Git LFS pointer a file holds in place of its content
+#[derive(Debug, Clone, Serialize)]
+pub struct LfsPointer {
+    /// `sha256:<hex>` of the content
+    pub oid: String,
+    /// Size of the content in bytes
+    pub size: u64,
+}
+
+/// The spec caps pointer files at this size
+const MAX_LFS_POINTER_BYTES: u64 = 1024;
+const LFS_POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// The LFS pointer the file at `full_path` holds, if it is one
+pub fn lfs_pointer(full_path: &Path) -> Option<LfsPointer> {
+    let size = fs::metadata(full_path).ok()?.len();
+    if size > MAX_LFS_POINTER_BYTES {
+        return None;
+    }
+    parse_lfs_pointer(&fs::read_to_string(full_path).ok()?)
+}
+
+fn parse_lfs_pointer(content: &str) -> Option<LfsPointer> {
+    let mut lines = content.lines();
+    if lines.next()? != LFS_POINTER_VERSION {
+        return None;
+    }
+    let (mut oid, mut size) = (None, None);
+    for line in lines {
+        match line.split_once(' ')? {
+            ("oid", value) => oid = Some(value.to_string()),
+            ("size", value) => size = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some(LfsPointer { oid: oid?, size: size? })
 }
 
 /// Write file content
 pub fn write_file(base_path: &Path, file_path: &str, content: &str) -> Result<(), std::io::Error> {
+    write_bytes(base_path, file_path, content.as_bytes())
+}
+
+/// Write file content that may not be text
+pub fn write_bytes(base_path: &Path, file_path: &str, content: &[u8]) -> Result<(), std::io::Error> {
     let full_path = safe_join(base_path, file_path)?;
 
     // Ensure parent directory exists
@@ -122,7 +487,93 @@ pub fn write_file(base_path: &Path, file_path: &str, content: &str) -> Result<()
         fs::create_dir_all(parent)?;
     }
 
-    fs::write(&full_path, content)
+    atomic_write(&full_path, content)
+}
+
+/// Write `content` to a temporary file next to `path`, fsync it, and rename it
+/// over the destination so readers never observe a partially written file.
+/// The original file's permissions are preserved; on failure the temporary
+/// file is removed and the original is left untouched.
+pub fn atomic_write(path: &Path, content: &[u8]) -> Result<(), std::io::Error> {
+    let mut pending = PendingWrite::new(path)?;
+    pending.write(content)?;
+    pending.commit()
+}
+
+/// A file being written under a temporary name next to its destination,
+/// which `commit` renames over it as `atomic_write` does. Content can be
+/// written in pieces as it arrives; dropped without committing, the
+/// temporary file is removed.
+pub struct PendingWrite {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: fs::File,
+    committed: bool,
+}
+
+impl PendingWrite {
+    /// Start writing `file_path` inside `base_path`, creating its parent
+    /// directories
+    pub fn create(base_path: &Path, file_path: &str) -> Result<Self, std::io::Error> {
+        let full_path = safe_join(base_path, file_path)?;
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Self::new(&full_path)
+    }
+
+    fn new(path: &Path) -> Result<Self, std::io::Error> {
+        let parent = path.parent().unwrap_or_else(|| Path::new("."));
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file");
+        // Hidden so a leftover temp file never shows up in list_files
+        let tmp_path = parent.join(format!(".{}.{}.tmp", file_name, Uuid::new_v4()));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp_path,
+            file,
+            committed: false,
+        })
+    }
+
+    /// The destination
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&mut self, content: &[u8]) -> Result<(), std::io::Error> {
+        self.file.write_all(content)
+    }
+
+    /// Replace the destination with what was written
+    pub fn commit(mut self) -> Result<(), std::io::Error> {
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            self.file.set_permissions(metadata.permissions())?;
+        }
+        self.file.sync_all()?;
+        fs::rename(&self.tmp_path, &self.path)?;
+        self.committed = true;
+
+        // Persist the rename itself; failure here doesn't affect the new content
+        if let Ok(dir) = fs::File::open(self.path.parent().unwrap_or_else(|| Path::new("."))) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
 }
 
 /// Create a new file
@@ -149,7 +600,7 @@ pub fn create_file(base_path: &Path, file_path: &str, content: Option<&str>) ->
             .unwrap_or("Untitled")
     );
 
-    fs::write(&full_path, content.unwrap_or(&default_content))
+    atomic_write(&full_path, content.unwrap_or(&default_content).as_bytes())
 }
 
 /// Delete a file
@@ -167,36 +618,185 @@ pub fn delete_file(base_path: &Path, file_path: &str) -> Result<(), std::io::Err
 }
 
 /// Safely join paths, preventing directory traversal attacks
-fn safe_join(base: &Path, path: &str) -> Result<PathBuf, std::io::Error> {
+///
+/// The relative path is normalized component by component: `..`, absolute
+/// roots/prefixes and NUL bytes are rejected outright, and containment is then
+/// verified by canonicalizing the deepest ancestor that already exists, so
+/// symlinks pointing outside `base` are caught even for files not yet created.
+pub fn safe_join(base: &Path, path: &str) -> Result<PathBuf, std::io::Error> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg.to_string());
+
+    if path.contains('\0') {
+        return Err(invalid("Path contains NUL byte"));
+    }
+
     let path = path.trim_start_matches('/');
 
-    // Check for directory traversal
-    if path.contains("..") {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Directory traversal not allowed",
-        ));
+    let mut normalized = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => {
+                // Backslash is a separator on Windows; don't let `..\` slip
+                // through as a single "normal" component elsewhere either
+                if part.to_string_lossy().split('\\').any(|p| p == "..") {
+                    return Err(invalid("Directory traversal not allowed"));
+                }
+                normalized.push(part);
+            }
+            Component::CurDir => {}
+            Component::ParentDir => return Err(invalid("Directory traversal not allowed")),
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(invalid("Absolute paths not allowed"))
+            }
+        }
     }
 
-    let joined = base.join(path);
+    if normalized.as_os_str().is_empty() {
+        return Err(invalid("Path must not be empty"));
+    }
+
+    let joined = base.join(&normalized);
 
     // Verify the resulting path is within base
-    let canonical_base = base.canonicalize().unwrap_or_else(|_| base.to_path_buf());
-    let canonical_joined = joined
-        .canonicalize()
-        .unwrap_or_else(|_| joined.clone());
+    let canonical_base = base.canonicalize()?;
+    let mut existing = joined.as_path();
+    while fs::symlink_metadata(existing).is_err() {
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => return Err(invalid("Path escapes base directory")),
+        };
+    }
+    let canonical_existing = existing.canonicalize()?;
 
-    if !canonical_joined.starts_with(&canonical_base) {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Path escapes base directory",
-        ));
+    if !canonical_existing.starts_with(&canonical_base) {
+        return Err(invalid("Path escapes base directory"));
     }
 
     Ok(joined)
 }
 
+/// Result of a bounded disk usage walk
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskUsage {
+    pub bytes: u64,
+    pub entries: usize,
+    /// The walk stopped at its entry limit, so `bytes` is a lower bound
+    pub truncated: bool,
+}
+
+/// Total size of everything under `path`, visiting at most `max_entries`
+/// entries. Symlinks are counted but not followed.
+pub fn disk_usage(path: &Path, max_entries: usize) -> DiskUsage {
+    let mut usage = DiskUsage {
+        bytes: 0,
+        entries: 0,
+        truncated: false,
+    };
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if usage.entries >= max_entries {
+                usage.truncated = true;
+                return usage;
+            }
+            usage.entries += 1;
+
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                usage.bytes += metadata.len();
+            }
+        }
+    }
+
+    usage
+}
+
+/// Bytes `ReverseLines` reads at a time
+const REVERSE_CHUNK: usize = 64 * 1024;
+
+/// The lines of a file from last to first, each with the offset it starts
+/// at, reading backwards a chunk at a time so only the lines consumed are
+/// read. Empty lines are skipped.
+pub struct ReverseLines {
+    file: fs::File,
+    /// Start of the bytes not read yet
+    pos: u64,
+    /// Bytes from `pos` up to the lines already returned
+    pending: Vec<u8>,
+}
+
+impl ReverseLines {
+    pub fn open(path: &Path) -> Result<Self, std::io::Error> {
+        let file = fs::File::open(path)?;
+        let pos = file.metadata()?.len();
+        Ok(Self {
+            file,
+            pos,
+            pending: Vec::new(),
+        })
+    }
+
+    fn read_chunk(&mut self) -> Result<(), std::io::Error> {
+        use std::io::{Read, Seek, SeekFrom};
+        let len = REVERSE_CHUNK.min(self.pos as usize);
+        self.pos -= len as u64;
+        let mut chunk = vec![0; len];
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        self.file.read_exact(&mut chunk)?;
+        chunk.append(&mut self.pending);
+        self.pending = chunk;
+        Ok(())
+    }
+}
+
+impl Iterator for ReverseLines {
+    type Item = Result<(u64, String), std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.pending.iter().rposition(|&b| b == b'\n') {
+                Some(newline) => {
+                    let line = self.pending.split_off(newline + 1);
+                    self.pending.truncate(newline);
+                    Some((self.pos + newline as u64 + 1, line))
+                }
+                None if self.pos == 0 => Some((0, std::mem::take(&mut self.pending))),
+                None => None,
+            };
+            match line {
+                Some((offset, line)) if !line.is_empty() => {
+                    return Some(Ok((offset, String::from_utf8_lossy(&line).into_owned())));
+                }
+                Some(_) if self.pos == 0 && self.pending.is_empty() => return None,
+                Some(_) => {}
+                None => {
+                    if let Err(e) = self.read_chunk() {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// Check if a path is a valid markdown file
 pub fn is_markdown_file(path: &str) -> bool {
     path.ends_with(".md") || path.ends_with(".markdown")
 }
+
+/// Whether `head`, the start of a file, looks like text: UTF-8, allowing
+/// for a character cut off at the end
+pub fn is_text(head: &[u8]) -> bool {
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
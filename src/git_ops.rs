@@ -1,43 +1,455 @@
-use std::path::Path;
-use std::process::Command;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::git_scope;
 
 /// Result type for git operations
-pub type GitResult<T> = Result<T, String>;
+pub type GitResult<T> = Result<T, GitError>;
+
+/// A git operation that failed, with git's own explanation when it got as
+/// far as running
+#[derive(Debug, Clone)]
+pub struct GitError {
+    pub message: String,
+    /// stderr of a git command that exited non-zero
+    pub stderr: Option<String>,
+}
+
+impl GitError {
+    fn failed(message: &str, stderr: &[u8]) -> Self {
+        Self {
+            message: message.to_string(),
+            stderr: Some(String::from_utf8_lossy(stderr).trim().to_string()),
+        }
+    }
+
+    /// Prefix the message with what the caller was trying to do
+    pub fn context(mut self, context: &str) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+}
+
+impl std::fmt::Display for GitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.stderr {
+            Some(stderr) => write!(f, "{}: {}", self.message, stderr),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for GitError {}
+
+impl From<String> for GitError {
+    fn from(message: String) -> Self {
+        Self { message, stderr: None }
+    }
+}
+
+/// How often a controlled git command checks whether it should stop
+const CONTROL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Least time between two reported progress lines of one command; git
+/// rewrites its counters far more often than anyone needs to see them
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Limits for the git commands a background job runs: a deadline and a
+/// cancellation flag, both checked while each command runs
+#[derive(Debug, Clone, Default)]
+pub struct RunControl {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl RunControl {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Some(Instant::now() + timeout),
+        }
+    }
+
+    /// Kill the running command, and refuse to start further ones
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// A guard cancelling this control when dropped, unless disarmed first
+    pub fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop(Some(self.clone()))
+    }
+
+    fn stop_reason(&self) -> Option<&'static str> {
+        if self.is_cancelled() {
+            Some("cancelled")
+        } else if self.is_timed_out() {
+            Some("timed out")
+        } else {
+            None
+        }
+    }
+}
+
+/// Cancels a `RunControl` when dropped before `disarm`. A request running
+/// git holds one across its await, so when actix drops the request because
+/// the client went away, the git commands are killed instead of running on
+/// for nobody.
+pub struct CancelOnDrop(Option<RunControl>);
+
+impl CancelOnDrop {
+    pub fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(control) = self.0.take() {
+            log::info!("Client went away; stopping its git commands");
+            control.cancel();
+        }
+    }
+}
+
+/// Receives git's progress lines ("Receiving objects:  45% (9/20)")
+pub type ProgressReporter = Arc<dyn Fn(&str) + Send + Sync>;
+
+thread_local! {
+    static RUN_CONTROL: RefCell<Option<RunControl>> = const { RefCell::new(None) };
+    static PROGRESS: RefCell<Option<ProgressReporter>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with every git command it starts on this thread under `control`
+pub fn with_control<R>(control: RunControl, f: impl FnOnce() -> R) -> R {
+    let previous = RUN_CONTROL.with(|current| current.replace(Some(control)));
+    let result = f();
+    RUN_CONTROL.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Run `f` with clones, fetches and pushes it starts on this thread
+/// reporting their progress to `report`. Progress lines are left out of the
+/// stderr kept for error messages.
+pub fn with_progress<R>(report: ProgressReporter, f: impl FnOnce() -> R) -> R {
+    let previous = PROGRESS.with(|current| current.replace(Some(report)));
+    let result = f();
+    PROGRESS.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// `args` with `--progress` after the subcommand when progress is being
+/// reported (git only writes it to a terminal otherwise)
+fn progress_args<'a>(args: &[&'a str]) -> Vec<&'a str> {
+    let mut args = args.to_vec();
+    if PROGRESS.with(|current| current.borrow().is_some()) {
+        args.insert(1, "--progress");
+    }
+    args
+}
+
+trait ControlledOutput {
+    /// `output()`, except that under `with_control` the command is killed
+    /// as soon as it is cancelled or out of time, and under `with_progress`
+    /// its progress lines are reported as they arrive
+    fn controlled_output(&mut self) -> std::io::Result<Output>;
+}
+
+impl ControlledOutput for Command {
+    fn controlled_output(&mut self) -> std::io::Result<Output> {
+        let control = RUN_CONTROL.with(|current| current.borrow().clone());
+        let progress = PROGRESS.with(|current| current.borrow().clone());
+        if control.is_none() && progress.is_none() {
+            return self.output();
+        }
+        let stop_reason = || control.as_ref().and_then(RunControl::stop_reason);
+        let stopped = |reason: &str| std::io::Error::new(std::io::ErrorKind::Interrupted, format!("git {}", reason));
+        if let Some(reason) = stop_reason() {
+            return Err(stopped(reason));
+        }
+
+        // In a process group of its own, so that stopping it also stops the
+        // remote helpers and ssh it starts
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            self.process_group(0);
+        }
+        let mut child = self
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Drain both pipes while waiting so a chatty command can't block on them
+        let stdout = read_to_end_in_background(child.stdout.take());
+        let stderr = match progress {
+            Some(report) => read_progress_in_background(child.stderr.take(), report),
+            None => read_to_end_in_background(child.stderr.take()),
+        };
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if let Some(reason) = stop_reason() {
+                kill_process_group(&mut child);
+                return Err(stopped(reason));
+            }
+            std::thread::sleep(CONTROL_POLL_INTERVAL);
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+/// Kill `child` and the processes in its group, then reap it
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        // Without unsafe code there is no killpg; kill(1) takes a group
+        let group = format!("-{}", child.id());
+        let killed = Command::new("kill")
+            .args(["-s", "KILL", "--", &group])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if !killed.is_ok_and(|status| status.success()) {
+            let _ = child.kill();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn read_to_end_in_background(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Like `read_to_end_in_background` for git's stderr, passing progress lines
+/// to `report` (at most one per `PROGRESS_INTERVAL`, plus the final line of
+/// each counter) instead of keeping them
+fn read_progress_in_background(
+    pipe: Option<impl Read + Send + 'static>,
+    report: ProgressReporter,
+) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut kept = Vec::new();
+        let Some(mut pipe) = pipe else { return kept };
+        let mut line = Vec::new();
+        let mut last_report: Option<Instant> = None;
+        let mut chunk = [0u8; 4096];
+        while let Ok(n) = pipe.read(&mut chunk) {
+            if n == 0 {
+                break;
+            }
+            for &byte in &chunk[..n] {
+                // Counters are redrawn in place with a carriage return
+                if byte != b'\r' && byte != b'\n' {
+                    line.push(byte);
+                    continue;
+                }
+                let text = String::from_utf8_lossy(&line).trim().to_string();
+                if is_progress_line(&text) {
+                    let due = last_report.is_none_or(|at| at.elapsed() >= PROGRESS_INTERVAL);
+                    let finished = text.ends_with("done.")
+                        || progress_counter().captures(&text).is_some_and(|counter| &counter[1] == "100");
+                    if due || finished {
+                        report(&text);
+                        last_report = Some(Instant::now());
+                    }
+                } else if !line.is_empty() {
+                    kept.extend_from_slice(&line);
+                    kept.push(b'\n');
+                }
+                line.clear();
+            }
+        }
+        kept.extend_from_slice(&line);
+        kept
+    })
+}
+
+/// Whether a line of git's stderr is a progress counter such as
+/// "Receiving objects:  45% (9/20)" or "remote: Enumerating objects: 5, done."
+/// Counters git prints in another language are recognized by their
+/// "45% (9/20)"
+fn is_progress_line(line: &str) -> bool {
+    let line = line.strip_prefix("remote: ").unwrap_or(line);
+    let Some((title, rest)) = line.split_once(": ") else {
+        return false;
+    };
+    let english = title.starts_with(|c: char| c.is_ascii_uppercase())
+        && title.chars().all(|c| c.is_ascii_alphabetic() || c == ' ')
+        && rest.trim_start().starts_with(|c: char| c.is_ascii_digit());
+    english || progress_counter().is_match(rest)
+}
+
+fn progress_counter() -> &'static Regex {
+    static COUNTER: OnceLock<Regex> = OnceLock::new();
+    COUNTER.get_or_init(|| Regex::new(r"(\d{1,3})\s?%\s*\((\d+)/(\d+)\)").unwrap())
+}
+
+fn progress_throughput() -> &'static Regex {
+    static THROUGHPUT: OnceLock<Regex> = OnceLock::new();
+    THROUGHPUT.get_or_init(|| Regex::new(r"\d+(?:[.,]\d+)?\s?[KMGT]?i?B/s").unwrap())
+}
+
+/// A progress line of git's, with what could be made of it. Everything but
+/// `line` is None when the line has no percentage git's way, so that a
+/// wording or language this doesn't know still reaches people as it is.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GitProgress {
+    /// What git is doing, e.g. "Receiving objects"
+    pub phase: Option<String>,
+    pub percent: Option<u8>,
+    pub current: Option<u64>,
+    pub total: Option<u64>,
+    /// Transfer rate as git words it, e.g. "1.21 MiB/s"
+    pub throughput: Option<String>,
+    /// The line itself
+    pub line: String,
+}
+
+/// Make what can be made of a progress line, e.g.
+/// "Receiving objects:  45% (9/20), 1.20 MiB | 1.21 MiB/s"
+pub fn parse_progress(line: &str) -> GitProgress {
+    let mut progress = GitProgress {
+        phase: None,
+        percent: None,
+        current: None,
+        total: None,
+        throughput: None,
+        line: line.to_string(),
+    };
+    let Some(counter) = progress_counter().captures(line) else {
+        return progress;
+    };
+    let Some(percent) = counter[1].parse::<u8>().ok().filter(|percent| *percent <= 100) else {
+        return progress;
+    };
+    let unprefixed = line.strip_prefix("remote: ").unwrap_or(line);
+    progress.phase = unprefixed
+        .split_once(':')
+        .map(|(phase, _)| phase.trim().to_string())
+        .filter(|phase| !phase.is_empty());
+    progress.percent = Some(percent);
+    progress.current = counter[2].parse().ok();
+    progress.total = counter[3].parse().ok();
+    progress.throughput = progress_throughput().find(line).map(|rate| rate.as_str().to_string());
+    progress
+}
 
 /// Run a git command and return stdout
 fn run_git(args: &[&str], cwd: &Path) -> GitResult<String> {
     log::debug!("Running git {:?} in {:?}", args, cwd);
 
-    let output = Command::new("git")
+    let output = git_scope::command(cwd)
         .args(args)
         .current_dir(cwd)
-        .output()
+        .controlled_output()
         .map_err(|e| format!("Failed to run git: {}", e))?;
 
     if output.status.success() {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Git command failed: {}", stderr))
+        Err(GitError::failed("Git command failed", &output.stderr))
     }
 }
 
+/// Run a git command with `input` on its stdin and return stdout
+fn run_git_with_input(args: &[&str], cwd: &Path, input: &[u8]) -> GitResult<String> {
+    let output = git_output_with_input(args, cwd, input)?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(GitError::failed("Git command failed", &output.stderr))
+    }
+}
+
+/// Run a git command with `input` on its stdin, whatever its exit status
+fn git_output_with_input(args: &[&str], cwd: &Path, input: &[u8]) -> GitResult<Output> {
+    log::debug!("Running git {:?} in {:?} with {} bytes of input", args, cwd, input.len());
+
+    let mut child = git_scope::command(cwd)
+        .args(args)
+        .current_dir(cwd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    // Written from a thread so git can't block on a full stdout meanwhile
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    let _ = writer.join();
+    Ok(output)
+}
+
 /// Clone a repository as a bare clone
 pub fn clone_repo(url: &str, path: &Path) -> GitResult<()> {
     log::info!("Cloning repository {} to {:?}", url, path);
 
-    let output = Command::new("git")
-        .args(["clone", "--bare", url])
+    let output = git_scope::command(path)
+        .args(progress_args(&["clone", "--bare", url]))
         .arg(path)
-        .output()
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git clone: {}", e))?;
+
+    if output.status.success() {
+        log::info!("Clone completed successfully");
+        Ok(())
+    } else {
+        Err(GitError::failed("Git clone failed", &output.stderr))
+    }
+}
+
+/// Bare clone of a repository on this host, hardlinking objects when both
+/// sides are on the same filesystem
+pub fn clone_local(source: &Path, path: &Path) -> GitResult<()> {
+    log::info!("Cloning local repository {:?} to {:?}", source, path);
+
+    let output = git_scope::command(path)
+        .args(["clone", "--bare", "--local", "--"])
+        .arg(source)
+        .arg(path)
+        .controlled_output()
         .map_err(|e| format!("Failed to run git clone: {}", e))?;
 
     if output.status.success() {
         log::info!("Clone completed successfully");
         Ok(())
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Git clone failed: {}", stderr))
+        Err(GitError::failed("Git clone failed", &output.stderr))
     }
 }
 
@@ -55,37 +467,35 @@ pub fn create_worktree(
     );
 
     // Check if branch exists
-    let branches_output = run_git(&["branch", "--list", branch_name], repo_path)?;
+    let branches_output = run_git(&["branch", "--list", "--", branch_name], repo_path)?;
     let branch_exists = !branches_output.trim().is_empty();
 
     if branch_exists {
         // Create worktree for existing branch
-        let output = Command::new("git")
-            .args(["worktree", "add"])
+        let output = git_scope::command(repo_path)
+            .args(["worktree", "add", "--"])
             .arg(worktree_path)
             .arg(branch_name)
             .current_dir(repo_path)
-            .output()
+            .controlled_output()
             .map_err(|e| format!("Failed to run git worktree add: {}", e))?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Git worktree add failed: {}", stderr));
+            return Err(GitError::failed("Git worktree add failed", &output.stderr));
         }
     } else {
         // Create new branch from source
         let source = from_branch.unwrap_or("HEAD");
-        let output = Command::new("git")
-            .args(["worktree", "add", "-b", branch_name])
+        let output = git_scope::command(repo_path)
+            .args(["worktree", "add", "-b", branch_name, "--"])
             .arg(worktree_path)
             .arg(source)
             .current_dir(repo_path)
-            .output()
+            .controlled_output()
             .map_err(|e| format!("Failed to run git worktree add: {}", e))?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Git worktree add failed: {}", stderr));
+            return Err(GitError::failed("Git worktree add failed", &output.stderr));
         }
     }
 
@@ -93,6 +503,24 @@ pub fn create_worktree(
     Ok(())
 }
 
+/// Create a worktree with a detached HEAD at `commit`
+pub fn create_detached_worktree(repo_path: &Path, worktree_path: &Path, commit: &str) -> GitResult<()> {
+    log::info!("Creating worktree at {:?} detached at {}", worktree_path, commit);
+
+    let output = git_scope::command(repo_path)
+        .args(["worktree", "add", "--detach", "--"])
+        .arg(worktree_path)
+        .arg(commit)
+        .current_dir(repo_path)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git worktree add: {}", e))?;
+
+    if !output.status.success() {
+        return Err(GitError::failed("Git worktree add failed", &output.stderr));
+    }
+    Ok(())
+}
+
 /// List all worktrees for a repository
 pub fn list_worktrees(repo_path: &Path) -> GitResult<Vec<String>> {
     let output = run_git(&["worktree", "list", "--porcelain"], repo_path)?;
@@ -100,17 +528,13 @@ pub fn list_worktrees(repo_path: &Path) -> GitResult<Vec<String>> {
     let worktrees: Vec<String> = output
         .lines()
         .filter_map(|line| {
-            if let Some(path) = line.strip_prefix("worktree ") {
-                Some(
-                    Path::new(path)
-                        .file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("")
-                        .to_string(),
-                )
-            } else {
-                None
-            }
+            line.strip_prefix("worktree ").map(|path| {
+                Path::new(path)
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("")
+                    .to_string()
+            })
         })
         .filter(|s| !s.is_empty())
         .collect();
@@ -118,6 +542,25 @@ pub fn list_worktrees(repo_path: &Path) -> GitResult<Vec<String>> {
     Ok(worktrees)
 }
 
+/// Paths of the worktrees registered with a repository, not counting the
+/// bare repository itself
+pub fn registered_worktrees(repo_path: &Path) -> GitResult<Vec<PathBuf>> {
+    let output = run_git(&["worktree", "list", "--porcelain"], repo_path)?;
+
+    let worktrees = output
+        .split("\n\n")
+        .filter(|block| !block.lines().any(|line| line == "bare"))
+        .filter_map(|block| {
+            block
+                .lines()
+                .find_map(|line| line.strip_prefix("worktree "))
+                .map(PathBuf::from)
+        })
+        .collect();
+
+    Ok(worktrees)
+}
+
 /// Remove a worktree
 pub fn remove_worktree(
     repo_path: &Path,
@@ -127,11 +570,11 @@ pub fn remove_worktree(
     log::info!("Removing worktree: {:?}", worktree_path);
 
     // Remove worktree
-    let output = Command::new("git")
+    let output = git_scope::command(repo_path)
         .args(["worktree", "remove", "--force"])
         .arg(worktree_path)
         .current_dir(repo_path)
-        .output()
+        .controlled_output()
         .map_err(|e| format!("Failed to run git worktree remove: {}", e))?;
 
     if !output.status.success() {
@@ -148,6 +591,14 @@ pub fn remove_worktree(
     Ok(())
 }
 
+/// Re-link worktrees with the repository after either has been moved
+pub fn repair_worktrees(repo_path: &Path, worktree_paths: &[PathBuf]) -> GitResult<()> {
+    let mut args = vec!["worktree", "repair"];
+    args.extend(worktree_paths.iter().filter_map(|p| p.to_str()));
+    run_git(&args, repo_path)?;
+    Ok(())
+}
+
 /// List all branches in a repository
 pub fn list_branches(repo_path: &Path) -> GitResult<Vec<String>> {
     let output = run_git(&["branch", "--format=%(refname:short)"], repo_path)?;
@@ -161,88 +612,522 @@ pub fn list_branches(repo_path: &Path) -> GitResult<Vec<String>> {
     Ok(branches)
 }
 
-/// Stage and commit files in a worktree
-pub fn commit_files(
-    worktree_path: &Path,
-    files: &[String],
-    message: &str,
-) -> GitResult<String> {
-    log::info!("Committing {} files in {:?}", files.len(), worktree_path);
+/// Whether `commit` is `of` or one of its ancestors
+pub fn is_ancestor(repo_path: &Path, commit: &str, of: &str) -> GitResult<bool> {
+    let output = git_scope::command(repo_path)
+        .args(["merge-base", "--is-ancestor", "--end-of-options", commit, of])
+        .current_dir(repo_path)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git merge-base: {}", e))?;
+    // 1 means it isn't; anything else is a failure
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(GitError::failed("Git merge-base failed", &output.stderr)),
+    }
+}
+
+/// A line `git grep` matched
+#[derive(Debug, Clone)]
+pub struct GrepMatch {
+    pub path: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// Lines of the markdown files committed at `rev` that match `pattern`, a
+/// POSIX extended regex or with `literal` a fixed string; at most `max` of
+/// them, and whether there were more. No worktree is needed. git grep takes
+/// no `--end-of-options`, so `rev` must be a full ref name or a commit ID
+pub fn grep_rev(
+    repo_path: &Path,
+    rev: &str,
+    pattern: &str,
+    literal: bool,
+    case_sensitive: bool,
+    max: usize,
+) -> GitResult<(Vec<GrepMatch>, bool)> {
+    let mut command = git_scope::command(repo_path);
+    command.args(["grep", "--no-color", "-z", "-n", "-I"]);
+    command.arg(if literal { "-F" } else { "-E" });
+    if !case_sensitive {
+        command.arg("-i");
+    }
+    let output = command
+        .args(["-e", pattern, rev, "--", "*.md", "*.markdown"])
+        .current_dir(repo_path)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git grep: {}", e))?;
+    // 1 means nothing matched
+    match output.status.code() {
+        Some(0) => {}
+        Some(1) => return Ok((Vec::new(), false)),
+        _ => return Err(GitError::failed("Git grep failed", &output.stderr)),
+    }
+
+    // Each line is `<rev>:<path>\0<line>\0<text>`
+    let prefix = format!("{}:", rev);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut matches = Vec::new();
+    for line in stdout.lines() {
+        let mut fields = line.splitn(3, '\0');
+        let (Some(path), Some(number), Some(text)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if matches.len() == max {
+            return Ok((matches, true));
+        }
+        matches.push(GrepMatch {
+            path: path.strip_prefix(&prefix).unwrap_or(path).to_string(),
+            line: number.parse().unwrap_or(0),
+            text: text.to_string(),
+        });
+    }
+    Ok((matches, false))
+}
 
-    // Stage files
+/// Delete a local branch whatever it is merged into; callers check
+pub fn delete_branch(repo_path: &Path, name: &str) -> GitResult<()> {
+    log::info!("Deleting branch {} of {:?}", name, repo_path);
+    run_git(&["branch", "-D", "--", name], repo_path)?;
+    Ok(())
+}
+
+/// Forget worktrees whose directory is gone, and the remote-tracking
+/// branches of `remotes` whose branch the remote no longer has
+pub fn prune(repo_path: &Path, remotes: &[Remote]) -> GitResult<()> {
+    log::info!("Pruning {:?}", repo_path);
+    run_git(&["worktree", "prune"], repo_path)?;
+    for remote in remotes {
+        run_git(&["remote", "prune", "--end-of-options", &remote.name], repo_path)?;
+    }
+    Ok(())
+}
+
+/// Stage the given files (relative to the worktree root), deletions included.
+/// The paths reach git NUL-separated on stdin as literal pathspecs, so no
+/// name is taken for an option or for pathspec magic such as `:(top)`.
+pub fn stage_files(worktree_path: &Path, files: &[String]) -> GitResult<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let mut pathspecs = Vec::new();
     for file in files {
-        run_git(&["add", file], worktree_path)?;
+        pathspecs.extend_from_slice(file.as_bytes());
+        pathspecs.push(0);
+    }
+    run_git_with_input(
+        &["--literal-pathspecs", "add", "-A", "--pathspec-from-file=-", "--pathspec-file-nul", "--"],
+        worktree_path,
+        &pathspecs,
+    )?;
+    Ok(())
+}
+
+/// Whether `file_path` (relative to the worktree root) is in the index
+pub fn is_tracked(worktree_path: &Path, file_path: &str) -> GitResult<bool> {
+    let output = run_git(&["--literal-pathspecs", "ls-files", "-z", "--", file_path], worktree_path)?;
+    Ok(!output.is_empty())
+}
+
+/// Stage every change in the worktree, untracked files included
+pub fn stage_all(worktree_path: &Path) -> GitResult<()> {
+    run_git(&["add", "-A", "--", "."], worktree_path)?;
+    Ok(())
+}
+
+/// Whether the index differs from HEAD
+pub fn has_staged_changes(worktree_path: &Path) -> GitResult<bool> {
+    let output = run_git(&["diff", "--cached", "--name-only", "-z"], worktree_path)?;
+    Ok(!output.is_empty())
+}
+
+/// How commits are signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SigningFormat {
+    Ssh,
+    #[default]
+    Gpg,
+}
+
+impl SigningFormat {
+    /// The format as `gpg.format` names it
+    pub fn git_name(self) -> &'static str {
+        match self {
+            SigningFormat::Ssh => "ssh",
+            SigningFormat::Gpg => "openpgp",
+        }
+    }
+}
+
+/// Key commits are signed with: for `ssh` the path of the key, for `gpg`
+/// its ID or fingerprint
+#[derive(Debug, Clone)]
+pub struct Signing {
+    pub format: SigningFormat,
+    pub key: String,
+}
+
+impl Signing {
+    /// `-c` options making git sign with this key
+    fn config_args(&self) -> [String; 4] {
+        [
+            "-c".to_string(),
+            format!("gpg.format={}", self.format.git_name()),
+            "-c".to_string(),
+            format!("user.signingkey={}", self.key),
+        ]
+    }
+
+    /// Why commits can't be signed with this key, if they can't: the key
+    /// or the program signing with it is missing
+    pub fn check(&self) -> Result<(), String> {
+        match self.format {
+            SigningFormat::Ssh => {
+                if Command::new("ssh-keygen").arg("-?").output().is_err() {
+                    return Err("ssh-keygen is not installed".to_string());
+                }
+                if !Path::new(&self.key).is_file() {
+                    return Err(format!("SSH signing key {} not found", self.key));
+                }
+            }
+            SigningFormat::Gpg => {
+                let listed = Command::new("gpg")
+                    .args(["--batch", "--list-secret-keys", "--", &self.key])
+                    .output()
+                    .map_err(|_| "gpg is not installed".to_string())?;
+                if !listed.status.success() {
+                    return Err(format!("GPG secret key {} not found", self.key));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a failed commit failed to sign, going by git's stderr
+pub fn is_signing_failure(e: &GitError) -> bool {
+    e.stderr.as_deref().is_some_and(|stderr| {
+        let stderr = stderr.to_lowercase();
+        stderr.contains("failed to sign") || stderr.contains("signing") || stderr.contains("ssh-keygen")
+    })
+}
+
+/// Hooks `git commit` runs that can refuse the commit
+const COMMIT_HOOKS: [&str; 3] = ["pre-commit", "prepare-commit-msg", "commit-msg"];
+
+/// A commit that didn't happen
+#[derive(Debug)]
+pub enum CommitError {
+    /// One of `hooks` refused it. Git passes what hooks print on stdout to
+    /// its stderr, so their output is mostly in `stderr`.
+    Hook {
+        hooks: Vec<String>,
+        stdout: String,
+        stderr: String,
+    },
+    Git(GitError),
+}
+
+impl From<GitError> for CommitError {
+    fn from(e: GitError) -> Self {
+        CommitError::Git(e)
+    }
+}
+
+/// Commit hooks the repository of a worktree has installed (honouring
+/// `core.hooksPath`), e.g. by `pre-commit install`
+pub fn commit_hooks(worktree_path: &Path) -> GitResult<Vec<String>> {
+    let mut hooks = Vec::new();
+    for hook in COMMIT_HOOKS {
+        let path = run_git(&["rev-parse", "--git-path", &format!("hooks/{}", hook)], worktree_path)?;
+        if is_executable(&worktree_path.join(path.trim())) {
+            hooks.push(hook.to_string());
+        }
+    }
+    Ok(hooks)
+}
+
+/// Git only runs hooks it may execute
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
     }
+}
 
-    // Commit
-    let output = run_git(&["commit", "-m", message], worktree_path)?;
+/// Commit what is staged, returning the new commit's hash; signed with
+/// `signing` if given, and without running the commit hooks if `no_verify`
+pub fn commit_staged(
+    worktree_path: &Path,
+    message: &str,
+    signing: Option<&Signing>,
+    no_verify: bool,
+) -> Result<String, CommitError> {
+    log::info!("Committing in {:?}", worktree_path);
 
-    // Get commit hash
+    let hooks = if no_verify { Vec::new() } else { commit_hooks(worktree_path)? };
+    let mut args: Vec<String> = signing.map(|signing| signing.config_args().to_vec()).unwrap_or_default();
+    args.extend(["commit".to_string(), "-m".to_string(), message.to_string()]);
+    if signing.is_some() {
+        args.push("-S".to_string());
+    }
+    if no_verify {
+        args.push("--no-verify".to_string());
+    }
+    let output = git_scope::command(worktree_path)
+        .args(&args)
+        .current_dir(worktree_path)
+        .controlled_output()
+        .map_err(|e| GitError::from(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        // Git reports failures of its own, such as failing to sign, as it
+        // writes the commit object, after the hooks have passed
+        if hooks.is_empty() || stderr.contains("failed to write commit object") {
+            return Err(GitError::failed("Git command failed", &output.stderr).into());
+        }
+        return Err(CommitError::Hook {
+            hooks,
+            stdout: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            stderr,
+        });
+    }
     let hash = run_git(&["rev-parse", "HEAD"], worktree_path)?;
 
     log::info!("Created commit: {}", hash.trim());
     Ok(hash.trim().to_string())
 }
 
-/// Push the current branch to origin
-pub fn push_branch(worktree_path: &Path) -> GitResult<()> {
-    log::info!("Pushing branch from {:?}", worktree_path);
+/// Whether commit `hash` carries a signature
+pub fn is_signed(worktree_path: &Path, hash: &str) -> GitResult<bool> {
+    let commit = run_git(&["cat-file", "commit", hash], worktree_path)?;
+    // Headers end at the first blank line
+    let headers = commit.split("\n\n").next().unwrap_or_default();
+    Ok(headers.lines().any(|line| line.starts_with("gpgsig")))
+}
+
+/// A file a commit changed
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedFile {
+    /// `A`, `M`, `D` or `T`, as `git diff-tree --name-status` reports it
+    pub status: String,
+    pub path: String,
+}
+
+/// The files commit `hash` changed
+pub fn committed_files(worktree_path: &Path, hash: &str) -> GitResult<Vec<ChangedFile>> {
+    let output = run_git(
+        &["diff-tree", "--no-commit-id", "--name-status", "-r", "-z", "--root", "--end-of-options", hash],
+        worktree_path,
+    )?;
+    let mut fields = output.split('\0').filter(|field| !field.is_empty());
+    let mut files = Vec::new();
+    while let (Some(status), Some(path)) = (fields.next(), fields.next()) {
+        files.push(ChangedFile {
+            status: status.to_string(),
+            path: path.to_string(),
+        });
+    }
+    Ok(files)
+}
+
+/// Push the current branch to `remote`
+pub fn push_branch(worktree_path: &Path, remote: &str) -> GitResult<()> {
+    log::info!("Pushing branch from {:?} to {}", worktree_path, remote);
 
-    run_git(&["push", "-u", "origin", "HEAD"], worktree_path)?;
+    run_git(&progress_args(&["push", "-u", "--end-of-options", remote, "HEAD"]), worktree_path)?;
 
     log::info!("Push completed successfully");
     Ok(())
 }
 
-/// Fetch updates from origin
-pub fn fetch_origin(repo_path: &Path) -> GitResult<()> {
-    log::info!("Fetching from origin for {:?}", repo_path);
+/// Point the `origin` remote at a new URL
+pub fn set_remote_url(repo_path: &Path, url: &str) -> GitResult<()> {
+    log::info!("Setting origin of {:?} to {}", repo_path, url);
 
-    run_git(&["fetch", "--all"], repo_path)?;
+    run_git(&["remote", "set-url", "origin", url], repo_path)?;
+    Ok(())
+}
+
+/// URL `origin` currently points at
+pub fn get_remote_url(repo_path: &Path) -> GitResult<String> {
+    Ok(run_git(&["remote", "get-url", "origin"], repo_path)?.trim().to_string())
+}
+
+/// A remote of a repository
+#[derive(Debug, Clone, Serialize)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+}
+
+/// Remotes of a repository, by name
+pub fn list_remotes(repo_path: &Path) -> GitResult<Vec<Remote>> {
+    let names = run_git(&["remote"], repo_path)?;
+    let mut remotes = Vec::new();
+    for name in names.lines().filter(|name| !name.is_empty()) {
+        let url = run_git(&["remote", "get-url", "--end-of-options", name], repo_path)?;
+        remotes.push(Remote {
+            name: name.to_string(),
+            url: url.trim().to_string(),
+        });
+    }
+    remotes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(remotes)
+}
+
+pub fn add_remote(repo_path: &Path, name: &str, url: &str) -> GitResult<()> {
+    log::info!("Adding remote {} of {:?}: {}", name, repo_path, url);
+    run_git(&["remote", "add", "--end-of-options", name, url], repo_path)?;
+    Ok(())
+}
+
+/// Remove a remote along with its remote-tracking branches
+pub fn remove_remote(repo_path: &Path, name: &str) -> GitResult<()> {
+    log::info!("Removing remote {} of {:?}", name, repo_path);
+    run_git(&["remote", "remove", "--end-of-options", name], repo_path)?;
+    Ok(())
+}
+
+/// Check that a remote URL is reachable by listing its refs
+pub fn check_remote(repo_path: &Path, url: &str) -> GitResult<()> {
+    let output = git_scope::command(repo_path)
+        .args(["ls-remote", "--heads", url])
+        .current_dir(repo_path)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git ls-remote: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(GitError::failed("Git ls-remote failed", &output.stderr))
+    }
+}
+
+/// Fetch updates from `remote`, or from every remote
+pub fn fetch_remotes(repo_path: &Path, remote: Option<&str>) -> GitResult<()> {
+    log::info!("Fetching from {} for {:?}", remote.unwrap_or("all remotes"), repo_path);
+
+    match remote {
+        Some(remote) => run_git(&progress_args(&["fetch", "--end-of-options", remote]), repo_path)?,
+        None => run_git(&progress_args(&["fetch", "--all"]), repo_path)?,
+    };
 
     log::info!("Fetch completed successfully");
     Ok(())
 }
 
-/// Pull updates for a specific branch (fetch + merge)
+/// Fast-forward a worktree to `branch_name` of `remote`
 pub fn pull_branch(
     repo_path: &Path,
     worktree_path: &Path,
-    _branch_name: &str,
+    remote: &str,
+    branch_name: &str,
 ) -> GitResult<()> {
-    log::info!("Pulling updates in {:?}", worktree_path);
+    log::info!("Pulling {} from {} in {:?}", branch_name, remote, worktree_path);
 
     // Fetch in bare repo first
-    fetch_origin(repo_path)?;
+    fetch_remotes(repo_path, Some(remote))?;
 
     // Pull in worktree
-    run_git(&["pull", "--ff-only"], worktree_path)?;
+    run_git(&["pull", "--ff-only", remote, branch_name], worktree_path)?;
 
     log::info!("Pull completed successfully");
     Ok(())
 }
 
-/// Rebase current branch on top of base branch
+/// Rebase current branch on top of base branch as `remote` has it
 pub fn rebase_on_base(
     worktree_path: &Path,
+    remote: &str,
     base_branch: &str,
 ) -> GitResult<()> {
     log::info!(
-        "Rebasing {:?} on top of {}",
+        "Rebasing {:?} on top of {}/{}",
         worktree_path,
+        remote,
         base_branch
     );
 
-    // Fetch latest first
-    run_git(&["fetch", "origin", base_branch], worktree_path)?;
+    // Fetch latest first; remotes of a bare clone have no remote-tracking
+    // branches, so the rebase goes onto what was fetched
+    run_git(&["fetch", "--end-of-options", remote, base_branch], worktree_path)?;
 
     // Rebase
-    run_git(&["rebase", &format!("origin/{}", base_branch)], worktree_path)?;
+    run_git(&["rebase", "--end-of-options", "FETCH_HEAD"], worktree_path)?;
 
     log::info!("Rebase completed successfully");
     Ok(())
 }
 
+/// A path with unmerged entries in a worktree's index
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub path: String,
+    /// Index stages the path has: 1 the common base, 2 ours, 3 theirs. A
+    /// side that deleted the file has no stage.
+    pub stages: Vec<u8>,
+}
+
+/// Unmerged paths of a worktree, in index order
+pub fn list_conflicts(worktree_path: &Path) -> GitResult<Vec<Conflict>> {
+    let output = run_git(&["ls-files", "--unmerged", "-z"], worktree_path)?;
+    let mut conflicts: Vec<Conflict> = Vec::new();
+    // Each entry is "<mode> <object> <stage>\t<path>"
+    for entry in output.split('\0').filter(|entry| !entry.is_empty()) {
+        let Some((info, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let Some(stage) = info.rsplit(' ').next().and_then(|stage| stage.parse().ok()) else {
+            continue;
+        };
+        match conflicts.last_mut() {
+            Some(last) if last.path == path => last.stages.push(stage),
+            _ => conflicts.push(Conflict {
+                path: path.to_string(),
+                stages: vec![stage],
+            }),
+        }
+    }
+    Ok(conflicts)
+}
+
+/// The operation a worktree is in the middle of (`rebase`, `merge`,
+/// `cherry-pick` or `revert`), if any
+pub fn pending_operation(worktree_path: &Path) -> Option<&'static str> {
+    [
+        ("rebase-merge", "rebase"),
+        ("rebase-apply", "rebase"),
+        ("MERGE_HEAD", "merge"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| {
+        run_git(&["rev-parse", "--git-path", marker], worktree_path)
+            .is_ok_and(|path| worktree_path.join(path.trim()).exists())
+    })
+    .map(|(_, operation)| operation)
+}
+
+/// Abort `operation`, as named by `pending_operation`, putting the worktree
+/// back where it was before it started
+pub fn abort_operation(worktree_path: &Path, operation: &str) -> GitResult<()> {
+    log::info!("Aborting {} in {:?}", operation, worktree_path);
+    run_git(&[operation, "--abort"], worktree_path)?;
+    Ok(())
+}
+
 /// Rename a branch
 pub fn rename_branch(
     worktree_path: &Path,
@@ -250,16 +1135,33 @@ pub fn rename_branch(
 ) -> GitResult<()> {
     log::info!("Renaming branch to {} in {:?}", new_name, worktree_path);
 
-    run_git(&["branch", "-m", new_name], worktree_path)?;
+    run_git(&["branch", "-m", "--", new_name], worktree_path)?;
 
     log::info!("Branch renamed successfully");
     Ok(())
 }
 
-/// Get the current branch name of a worktree
-pub fn get_current_branch(worktree_path: &Path) -> GitResult<String> {
-    let output = run_git(&["rev-parse", "--abbrev-ref", "HEAD"], worktree_path)?;
-    Ok(output.trim().to_string())
+/// Get the branch checked out in a worktree; `None` when HEAD is detached,
+/// e.g. in the middle of a rebase
+pub fn get_current_branch(worktree_path: &Path) -> GitResult<Option<String>> {
+    // Fails quietly, with nothing printed, when HEAD is detached
+    match run_git(&["symbolic-ref", "--quiet", "--short", "HEAD"], worktree_path) {
+        Ok(output) => Ok(Some(output.trim().to_string())),
+        Err(_) if is_repo_healthy(worktree_path) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Commits on HEAD of a worktree that `base` lacks, and commits on `base`
+/// that HEAD lacks
+pub fn ahead_behind(worktree_path: &Path, base: &str) -> GitResult<(u32, u32)> {
+    let range = format!("HEAD...{}", base);
+    let output = run_git(
+        &["rev-list", "--left-right", "--count", "--end-of-options", &range, "--"],
+        worktree_path,
+    )?;
+    let mut counts = output.split_whitespace().map(|count| count.parse().unwrap_or(0));
+    Ok((counts.next().unwrap_or(0), counts.next().unwrap_or(0)))
 }
 
 /// Check if there are uncommitted changes
@@ -268,7 +1170,533 @@ pub fn has_uncommitted_changes(worktree_path: &Path) -> GitResult<bool> {
     Ok(!output.trim().is_empty())
 }
 
-/// Get git status
-pub fn get_status(worktree_path: &Path) -> GitResult<String> {
-    run_git(&["status", "--short"], worktree_path)
+/// Uncommitted changes in a worktree, with `status` the two-letter code of
+/// `git status --porcelain` ("M ", " M", "??", ...)
+pub fn get_status(worktree_path: &Path) -> GitResult<Vec<ChangedFile>> {
+    let output = run_git(&["status", "--porcelain", "-z"], worktree_path)?;
+    let mut entries = output.split('\0').filter(|entry| !entry.is_empty());
+    let mut changes = Vec::new();
+    while let Some(entry) = entries.next() {
+        let (status, path) = entry.split_at(entry.len().min(3));
+        // Renames and copies are followed by the original path
+        if status.starts_with(['R', 'C']) {
+            entries.next();
+        }
+        changes.push(ChangedFile {
+            status: status.trim_end_matches(' ').to_string(),
+            path: path.to_string(),
+        });
+    }
+    Ok(changes)
+}
+
+/// Whether a worktree's checkout declares submodules
+pub fn has_submodules(worktree_path: &Path) -> bool {
+    worktree_path.join(".gitmodules").is_file()
+}
+
+/// Check out the submodules a worktree's HEAD records, cloning the missing
+/// ones, recursively. Relative submodule URLs resolve against the parent's
+/// `origin`, and the clones use the same git configuration, so the same
+/// credential helpers apply.
+pub fn update_submodules(worktree_path: &Path) -> GitResult<()> {
+    log::info!("Updating submodules in {:?}", worktree_path);
+
+    let mut args = vec!["submodule", "update", "--init", "--recursive"];
+    if PROGRESS.with(|current| current.borrow().is_some()) {
+        args.push("--progress");
+    }
+    let output = git_scope::command(worktree_path)
+        .args(&args)
+        .current_dir(worktree_path)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git submodule update: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(GitError::failed("Git submodule update failed", &output.stderr))
+    }
+}
+
+/// Commits a worktree's index records for its submodules, by path
+pub fn submodule_pointers(worktree_path: &Path) -> GitResult<Vec<(String, String)>> {
+    let output = run_git(&["ls-files", "--stage", "-z"], worktree_path)?;
+    Ok(output
+        .split('\0')
+        .filter_map(|entry| {
+            let (info, path) = entry.split_once('\t')?;
+            let mut fields = info.split(' ');
+            // Mode 160000 marks a gitlink
+            (fields.next()? == "160000").then(|| (path.to_string(), fields.next().unwrap_or_default().to_string()))
+        })
+        .collect())
+}
+
+/// State of a submodule of a worktree
+#[derive(Debug, Clone, Serialize)]
+pub struct SubmoduleStatus {
+    /// Relative to the worktree; nested submodules are included
+    pub path: String,
+    /// The checked-out commit, or the recorded one when not initialized
+    pub commit: String,
+    pub initialized: bool,
+    /// The checked-out commit isn't the one the parent records
+    pub commit_mismatch: bool,
+    /// Has merge conflicts in the parent
+    pub conflicted: bool,
+    /// Has uncommitted or untracked changes of its own
+    pub dirty: bool,
+}
+
+/// Status of every submodule of a worktree
+pub fn submodule_status(worktree_path: &Path) -> GitResult<Vec<SubmoduleStatus>> {
+    if !has_submodules(worktree_path) {
+        return Ok(Vec::new());
+    }
+    let output = run_git(&["submodule", "status", "--recursive"], worktree_path)?;
+    let dirty = dirty_submodules(worktree_path)?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut chars = line.chars();
+            let state = chars.next()?;
+            let (commit, rest) = chars.as_str().split_once(' ')?;
+            // The path is followed by " (<describe>)" when the commit is checked out
+            let path = match rest.rfind(" (") {
+                Some(at) if rest.ends_with(')') => &rest[..at],
+                _ => rest,
+            };
+            Some(SubmoduleStatus {
+                path: path.to_string(),
+                commit: commit.to_string(),
+                initialized: state != '-',
+                commit_mismatch: state == '+',
+                conflicted: state == 'U',
+                dirty: dirty.iter().any(|dirty| dirty == path),
+            })
+        })
+        .collect())
+}
+
+/// Paths of a worktree's submodules (nested ones included) holding changes
+/// of their own
+fn dirty_submodules(worktree_path: &Path) -> GitResult<Vec<String>> {
+    let output = run_git(&["status", "--porcelain=v2", "-z", "--ignore-submodules=none"], worktree_path)?;
+    let mut dirty = Vec::new();
+    for entry in output.split('\0') {
+        // "1 <XY> <sub> ..." with <sub> "S<c><m><u>" for a submodule
+        let fields: Vec<&str> = entry.splitn(9, ' ').collect();
+        if fields.len() == 9 && fields[0] == "1" && fields[2].starts_with('S') && fields[2][2..].contains(['M', 'U']) {
+            let path = fields[8];
+            for nested in dirty_submodules(&worktree_path.join(path))? {
+                dirty.push(format!("{}/{}", path, nested));
+            }
+            dirty.push(path.to_string());
+        }
+    }
+    Ok(dirty)
+}
+
+/// Whether `.gitattributes` content sends any path through the LFS filter
+fn attributes_use_lfs(attributes: &str) -> bool {
+    attributes
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .any(|line| line.split_whitespace().skip(1).any(|attr| attr == "filter=lfs"))
+}
+
+/// Whether a worktree's checkout stores files in Git LFS
+pub fn worktree_uses_lfs(worktree_path: &Path) -> bool {
+    std::fs::read_to_string(worktree_path.join(".gitattributes")).is_ok_and(|attributes| attributes_use_lfs(&attributes))
+}
+
+/// Whether the HEAD of a repository (bare or not) stores files in Git LFS
+pub fn repo_uses_lfs(repo_path: &Path) -> bool {
+    show_file_at(repo_path, "HEAD", ".gitattributes")
+        .ok()
+        .flatten()
+        .is_some_and(|attributes| attributes_use_lfs(&String::from_utf8_lossy(&attributes)))
+}
+
+/// Whether the git-lfs binary is installed
+pub fn lfs_available() -> bool {
+    Command::new("git")
+        .args(["lfs", "version"])
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Set up the LFS filters for a repository, so checkouts download LFS
+/// content instead of leaving pointer files
+pub fn lfs_install(repo_path: &Path) -> GitResult<()> {
+    log::info!("Installing Git LFS in {:?}", repo_path);
+
+    run_git(&["lfs", "install", "--local"], repo_path)?;
+    Ok(())
+}
+
+/// Download the LFS content of a worktree's checkout and replace the
+/// pointer files with it
+pub fn lfs_pull(worktree_path: &Path) -> GitResult<()> {
+    log::info!("Pulling Git LFS content in {:?}", worktree_path);
+
+    let output = git_scope::command(worktree_path)
+        .args(["lfs", "pull"])
+        .current_dir(worktree_path)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git lfs pull: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(GitError::failed("Git LFS pull failed", &output.stderr))
+    }
+}
+
+/// An ignore pattern `check_ignore` found deciding about a path
+#[derive(Debug, Clone)]
+pub struct IgnoreMatch {
+    /// File the pattern is in, as git names it
+    pub source: String,
+    pub line: usize,
+    pub pattern: String,
+    pub path: String,
+}
+
+impl IgnoreMatch {
+    /// Whether the pattern ignores the path rather than re-including it
+    pub fn ignores(&self) -> bool {
+        !self.pattern.starts_with('!')
+    }
+}
+
+/// The patterns deciding whether each of `paths` (relative to the worktree)
+/// is ignored, from one `git check-ignore --stdin` run however many paths
+/// there are; paths no pattern matches are left out. Tracked files are
+/// never ignored. `extra_excludes` is read like `core.excludesFile`, with
+/// less precedence than the `.gitignore` files.
+pub fn check_ignore(
+    worktree_path: &Path,
+    paths: &[String],
+    extra_excludes: Option<&Path>,
+) -> GitResult<Vec<IgnoreMatch>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut input = Vec::new();
+    for path in paths {
+        input.extend_from_slice(path.as_bytes());
+        input.push(0);
+    }
+    let excludes = extra_excludes.map(|path| format!("core.excludesFile={}", path.display()));
+    let mut args = Vec::new();
+    if let Some(excludes) = &excludes {
+        args.extend(["-c", excludes.as_str()]);
+    }
+    args.extend(["check-ignore", "--verbose", "-z", "--stdin"]);
+
+    let output = git_output_with_input(&args, worktree_path, &input)?;
+    // 1 is "nothing matched"
+    if !output.status.success() && output.status.code() != Some(1) {
+        return Err(GitError::failed("Git check-ignore failed", &output.stderr));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = stdout.split('\0').collect();
+    Ok(fields
+        .chunks_exact(4)
+        .map(|record| IgnoreMatch {
+            source: record[0].to_string(),
+            line: record[1].parse().unwrap_or(0),
+            pattern: record[2].to_string(),
+            path: record[3].to_string(),
+        })
+        .collect())
+}
+
+/// Path of the repository's `info/exclude`, shared by all its worktrees
+pub fn info_exclude_path(worktree_path: &Path) -> GitResult<PathBuf> {
+    let path = run_git(&["rev-parse", "--git-path", "info/exclude"], worktree_path)?;
+    Ok(worktree_path.join(path.trim()))
+}
+
+/// The `.gitignore` files of a worktree, tracked or not (unless ignored
+/// themselves), top-level first
+pub fn gitignore_files(worktree_path: &Path) -> GitResult<Vec<String>> {
+    let output = run_git(
+        &["ls-files", "-z", "--cached", "--others", "--exclude-standard", "--", ".gitignore", "*/.gitignore"],
+        worktree_path,
+    )?;
+    let mut files: Vec<String> = output.split('\0').filter(|f| !f.is_empty()).map(str::to_string).collect();
+    files.sort_by(|a, b| a.matches('/').count().cmp(&b.matches('/').count()).then(a.cmp(b)));
+    files.dedup();
+    Ok(files)
+}
+
+/// Snapshot the current worktree contents (tracked and untracked files, minus
+/// anything ignored) into a tree object without touching the real index
+pub fn snapshot_worktree(worktree_path: &Path) -> GitResult<String> {
+    let index_path = run_git(&["rev-parse", "--git-path", "index"], worktree_path)?;
+    let index_path = worktree_path.join(index_path.trim());
+
+    // Seed the temporary index from the real one so unchanged files aren't rehashed
+    let tmp_index = std::env::temp_dir().join(format!("runotepad-index-{}", uuid::Uuid::new_v4()));
+    if index_path.exists() {
+        std::fs::copy(&index_path, &tmp_index)
+            .map_err(|e| format!("Failed to copy index: {}", e))?;
+    }
+
+    let result = run_git_with_index(&["add", "-A"], worktree_path, &tmp_index)
+        .and_then(|_| run_git_with_index(&["write-tree"], worktree_path, &tmp_index))
+        .map(|tree| tree.trim().to_string());
+
+    let _ = std::fs::remove_file(&tmp_index);
+    result
+}
+
+/// Run a git command against an alternate index file
+fn run_git_with_index(args: &[&str], cwd: &Path, index: &Path) -> GitResult<String> {
+    log::debug!("Running git {:?} in {:?} with index {:?}", args, cwd, index);
+
+    let output = git_scope::command(cwd)
+        .args(args)
+        .env("GIT_INDEX_FILE", index)
+        .current_dir(cwd)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(GitError::failed("Git command failed", &output.stderr))
+    }
+}
+
+/// Check whether an object (e.g. `<tree>:<path>`) exists
+pub fn object_exists(repo_path: &Path, object: &str) -> bool {
+    run_git(&["cat-file", "-e", "--end-of-options", object], repo_path).is_ok()
+}
+
+/// Start `git archive` for a tree-ish, returning the child with stdout piped
+pub fn spawn_archive(
+    repo_path: &Path,
+    treeish: &str,
+    format: &str,
+    prefix: &str,
+) -> GitResult<std::process::Child> {
+    log::info!("Archiving {} as {} in {:?}", treeish, format, repo_path);
+
+    git_scope::command(repo_path)
+        .args(["archive", &format!("--format={}", format), &format!("--prefix={}", prefix), "--end-of-options", treeish])
+        .current_dir(repo_path)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git archive: {}", e).into())
+}
+
+/// Read a file's content at a revision, or `None` if it doesn't exist there
+pub fn show_file_at(worktree_path: &Path, rev: &str, file_path: &str) -> GitResult<Option<Vec<u8>>> {
+    let object = format!("{}:{}", rev, file_path);
+
+    if !object_exists(worktree_path, &object) {
+        return Ok(None);
+    }
+
+    let output = git_scope::command(worktree_path)
+        .args(["show", "--end-of-options", &object])
+        .current_dir(worktree_path)
+        .controlled_output()
+        .map_err(|e| format!("Failed to run git show: {}", e))?;
+
+    if output.status.success() {
+        Ok(Some(output.stdout))
+    } else {
+        Err(GitError::failed("Git show failed", &output.stderr))
+    }
+}
+
+/// The commit `rev` points at, or `None` if it names none
+pub fn resolve_commit(repo_path: &Path, rev: &str) -> Option<String> {
+    let object = format!("{}^{{commit}}", rev);
+    run_git(&["rev-parse", "--verify", "--quiet", "--end-of-options", &object], repo_path)
+        .ok()
+        .map(|sha| sha.trim().to_string())
+}
+
+/// The last commit reachable from `rev` that still had `file_path`, i.e. the
+/// parent of the commit that deleted it; `None` if it was never deleted
+pub fn last_commit_with_file(worktree_path: &Path, rev: &str, file_path: &str) -> GitResult<Option<String>> {
+    let deleted_in = run_git(
+        &["log", "-1", "--diff-filter=D", "--format=%H", "--end-of-options", rev, "--", file_path],
+        worktree_path,
+    )?;
+    let deleted_in = deleted_in.trim();
+    if deleted_in.is_empty() {
+        return Ok(None);
+    }
+    Ok(resolve_commit(worktree_path, &format!("{}^", deleted_in)))
+}
+
+/// Commit metadata shown in workspace details
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitSummary {
+    pub sha: String,
+    pub subject: String,
+    pub author: String,
+    pub date: String,
+}
+
+/// Summarize the commit a revision points at
+pub fn commit_summary(repo_path: &Path, rev: &str) -> GitResult<CommitSummary> {
+    let output = run_git(
+        &["log", "-1", "--format=%H%x00%s%x00%an%x00%aI", "--end-of-options", rev],
+        repo_path,
+    )?;
+
+    let mut fields = output.trim_end_matches('\n').splitn(4, '\0');
+    match (fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some(sha), Some(subject), Some(author), Some(date)) if !sha.is_empty() => Ok(CommitSummary {
+            sha: sha.to_string(),
+            subject: subject.to_string(),
+            author: author.to_string(),
+            date: date.to_string(),
+        }),
+        _ => Err(format!("Unexpected git log output for {}", rev).into()),
+    }
+}
+
+/// A commit as `log_stream` reads it
+#[derive(Debug, Clone, Serialize)]
+pub struct LoggedCommit {
+    pub sha: String,
+    pub subject: String,
+    pub author: String,
+    /// Author date, as git prints it
+    pub date: String,
+    pub committed_at: DateTime<Utc>,
+}
+
+/// Commits reachable from a revision, newest first as `git log` lists them,
+/// read from git as they are consumed. Dropping the stream stops git.
+pub struct LogStream {
+    child: std::process::Child,
+    stdout: std::io::BufReader<std::process::ChildStdout>,
+}
+
+/// Start `git log` for `rev` in `cwd`
+pub fn log_stream(cwd: &Path, rev: &str) -> GitResult<LogStream> {
+    let mut child = git_scope::command(cwd)
+        .args(["log", "-z", "--format=%H%x1f%s%x1f%an%x1f%aI%x1f%cI", "--end-of-options", rev, "--"])
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to run git log: {}", e))?;
+    let stdout = std::io::BufReader::new(child.stdout.take().expect("stdout is piped"));
+    Ok(LogStream { child, stdout })
+}
+
+impl Iterator for LogStream {
+    type Item = GitResult<LoggedCommit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use std::io::BufRead;
+        let mut record = Vec::new();
+        match self.stdout.read_until(0, &mut record) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(format!("Failed to read git log: {}", e).into())),
+        }
+        let record = String::from_utf8_lossy(&record);
+        let fields: Vec<&str> = record.trim_end_matches('\0').trim_end_matches('\n').split('\x1f').collect();
+        let [sha, subject, author, date, committed_at] = fields[..] else {
+            return Some(Err(format!("Unexpected git log output: {:?}", record).into()));
+        };
+        let Ok(committed_at) = DateTime::parse_from_rfc3339(committed_at) else {
+            return Some(Err(format!("Unexpected commit date in git log: {:?}", committed_at).into()));
+        };
+        Some(Ok(LoggedCommit {
+            sha: sha.to_string(),
+            subject: subject.to_string(),
+            author: author.to_string(),
+            date: date.to_string(),
+            committed_at: committed_at.with_timezone(&Utc),
+        }))
+    }
+}
+
+impl Drop for LogStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Basic health check: git still recognizes the directory as a repository
+pub fn is_repo_healthy(repo_path: &Path) -> bool {
+    run_git(&["rev-parse", "--git-dir"], repo_path).is_ok()
+}
+
+/// Whether `path` is itself a repository (bare, or a work tree with `.git`),
+/// rather than merely a directory somewhere inside one
+pub fn is_repository_root(path: &Path) -> bool {
+    match run_git(&["rev-parse", "--absolute-git-dir"], path) {
+        Ok(git_dir) => {
+            let git_dir = Path::new(git_dir.trim());
+            git_dir == path || git_dir == path.join(".git")
+        }
+        Err(_) => false,
+    }
+}
+
+/// When the repository last fetched, judged by FETCH_HEAD (absent until the
+/// first fetch after cloning)
+pub fn last_fetch_time(repo_path: &Path) -> Option<DateTime<Utc>> {
+    let modified = std::fs::metadata(repo_path.join("FETCH_HEAD")).ok()?.modified().ok()?;
+    Some(modified.into())
+}
+
+/// Fingerprint of a repository's local branches and the HEADs of its
+/// worktrees, which changes with any commit, checkout or branch made through
+/// git directly; cheaper than asking git, since it only reads the git dir
+pub fn refs_stamp(repo_path: &Path) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    // Git replaces ref files rather than rewriting them, so their size and
+    // modification time are enough
+    fn hash_metadata(path: &Path, hasher: &mut DefaultHasher) {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return;
+        };
+        path.hash(hasher);
+        if metadata.is_dir() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(path)
+                .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+                .unwrap_or_default();
+            entries.sort();
+            for entry in entries {
+                hash_metadata(&entry, hasher);
+            }
+        } else {
+            metadata.len().hash(hasher);
+            metadata.modified().ok().hash(hasher);
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    hash_metadata(&repo_path.join("refs/heads"), &mut hasher);
+    hash_metadata(&repo_path.join("packed-refs"), &mut hasher);
+    let mut worktrees: Vec<PathBuf> = std::fs::read_dir(repo_path.join("worktrees"))
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    worktrees.sort();
+    for worktree in worktrees {
+        worktree.hash(&mut hasher);
+        std::fs::read(worktree.join("HEAD")).ok().hash(&mut hasher);
+    }
+    hasher.finish()
 }
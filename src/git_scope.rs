@@ -0,0 +1,163 @@
+//! Git environment of each workspace. Every git command `git_ops` runs in a
+//! workspace's directory gets:
+//!
+//! - `GIT_CONFIG_GLOBAL` naming the `gitconfig` generated in the workspace
+//!   directory from its settings (identity, signing key, credential user) in
+//!   place of the server user's `~/.gitconfig`, so credential helpers and
+//!   settings of one workspace never reach another
+//! - `GIT_TERMINAL_PROMPT=0`, and ssh in batch mode, so a remote asking for a
+//!   password fails at once instead of waiting on a terminal nobody watches
+//! - with a `git_password`, `GIT_ASKPASS` naming a script that answers git's
+//!   prompts with the workspace's credential. The script reads it from the
+//!   command's environment, so the password is never written out in clear.
+//!
+//! Commands outside any workspace (reading an import's source) only get the
+//! prompt settings. The server user's `user.name` and `user.email` are the
+//! identity of workspaces that don't set one.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{OnceLock, RwLock};
+
+use crate::config::WorkspaceConfig;
+
+const CONFIG_FILE: &str = "gitconfig";
+const ASKPASS_FILE: &str = "askpass.sh";
+
+/// Answers "Username for ..." with the username and anything else (the
+/// password prompt) with the password
+const ASKPASS_SCRIPT: &str = r#"#!/bin/sh
+case "$1" in
+Username*) printf '%s\n' "$RUNOTEPAD_GIT_USERNAME" ;;
+*) printf '%s\n' "$RUNOTEPAD_GIT_PASSWORD" ;;
+esac
+"#;
+
+/// Credential git is given when a remote asks for one
+#[derive(Debug, Clone)]
+pub struct Credential {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone)]
+struct Scope {
+    config_file: PathBuf,
+    askpass: PathBuf,
+    credential: Option<Credential>,
+}
+
+/// Scopes by workspace directory
+static SCOPES: RwLock<BTreeMap<PathBuf, Scope>> = RwLock::new(BTreeMap::new());
+
+/// The server user's own identity, read once before any scope applies
+fn server_identity() -> &'static (Option<String>, Option<String>) {
+    static IDENTITY: OnceLock<(Option<String>, Option<String>)> = OnceLock::new();
+    IDENTITY.get_or_init(|| {
+        let get = |key: &str| {
+            let output = Command::new("git").args(["config", "--global", "--get", key]).output().ok()?;
+            let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (output.status.success() && !value.is_empty()).then_some(value)
+        };
+        (get("user.name"), get("user.email"))
+    })
+}
+
+/// `value` quoted for a git config file
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+/// The generated config file of a workspace
+fn render(name: &str, ws_config: &WorkspaceConfig) -> String {
+    let (server_name, server_email) = server_identity();
+    let mut user = Vec::new();
+    if let Some(value) = ws_config.git_user_name.as_ref().or(server_name.as_ref()) {
+        user.push(format!("\tname = {}", quote(value)));
+    }
+    if let Some(value) = ws_config.git_user_email.as_ref().or(server_email.as_ref()) {
+        user.push(format!("\temail = {}", quote(value)));
+    }
+
+    let mut content = format!(
+        "# Generated from the settings of workspace '{}'; rewritten when they change\n",
+        name
+    );
+    if let Some(signing) = ws_config.signing() {
+        content.push_str(&format!("[gpg]\n\tformat = {}\n", signing.format.git_name()));
+        user.push(format!("\tsigningkey = {}", quote(&signing.key)));
+    }
+    if !user.is_empty() {
+        content.push_str(&format!("[user]\n{}\n", user.join("\n")));
+    }
+    if let Some(username) = &ws_config.git_username {
+        content.push_str(&format!("[credential]\n\tusername = {}\n", quote(username)));
+    }
+    content
+}
+
+/// Write the config file and askpass script of the workspace in
+/// `workspace_path` and run its git commands with them from now on
+pub fn register(
+    workspace_path: &Path,
+    name: &str,
+    ws_config: &WorkspaceConfig,
+    credential: Option<Credential>,
+) -> std::io::Result<()> {
+    fs::create_dir_all(workspace_path)?;
+    let config_file = workspace_path.join(CONFIG_FILE);
+    fs::write(&config_file, render(name, ws_config))?;
+
+    let askpass = workspace_path.join(ASKPASS_FILE);
+    fs::write(&askpass, ASKPASS_SCRIPT)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&askpass, fs::Permissions::from_mode(0o700))?;
+    }
+
+    SCOPES.write().unwrap().insert(
+        workspace_path.to_path_buf(),
+        Scope {
+            config_file,
+            askpass,
+            credential,
+        },
+    );
+    Ok(())
+}
+
+/// Stop scoping commands in `workspace_path`, once it is removed or moved
+pub fn unregister(workspace_path: &Path) {
+    SCOPES.write().unwrap().remove(workspace_path);
+}
+
+/// `git` to run in or on `path`, with the environment of the workspace it
+/// lies in
+pub fn command(path: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.env("GIT_TERMINAL_PROMPT", "0");
+    if std::env::var_os("GIT_SSH_COMMAND").is_none() {
+        command.env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+    }
+
+    let scopes = SCOPES.read().unwrap();
+    let Some(scope) = path.ancestors().find_map(|dir| scopes.get(dir)) else {
+        return command;
+    };
+    command.env("GIT_CONFIG_GLOBAL", &scope.config_file);
+    if let Some(credential) = &scope.credential {
+        command
+            .env("GIT_ASKPASS", &scope.askpass)
+            .env("RUNOTEPAD_GIT_USERNAME", &credential.username)
+            .env("RUNOTEPAD_GIT_PASSWORD", &credential.password);
+    }
+    command
+}
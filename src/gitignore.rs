@@ -0,0 +1,339 @@
+//! Ignore rules of a worktree. The runbook tree leaves out files git
+//! ignores, so generated output can be hidden by adding it to `.gitignore`;
+//! these endpoints show where a worktree's rules come from and edit the
+//! top-level `.gitignore`, previewing which listed files new patterns hide.
+//! The preview asks `git check-ignore` about all listed files at once.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::audit;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::file_ops::{self, FileEntry};
+use crate::git_ops;
+use crate::logging;
+use crate::quota;
+use crate::workspace;
+use crate::AppState;
+
+const GITIGNORE: &str = ".gitignore";
+
+/// Files a preview names for each pattern; the count covers all of them
+const PREVIEW_PATHS: usize = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct IgnoreQuery {
+    /// Only preview the new patterns, leave the file as it is
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateIgnoreRequest {
+    /// New content of the top-level `.gitignore`
+    pub content: String,
+}
+
+/// A file ignore rules are read from
+#[derive(Debug, Serialize)]
+pub struct IgnoreSource {
+    /// Relative to the worktree, or `info/exclude`
+    pub path: String,
+    /// `gitignore` or `exclude`
+    pub kind: &'static str,
+    pub content: String,
+    /// Lines that are patterns, without blank lines and comments
+    pub patterns: Vec<String>,
+}
+
+/// What a new pattern would hide
+#[derive(Debug, Serialize)]
+pub struct PatternPreview {
+    pub line: usize,
+    pub pattern: String,
+    /// Listed files the pattern would hide
+    pub hides: usize,
+    /// The first of those files
+    pub paths: Vec<String>,
+}
+
+/// A line of a `.gitignore` as git reads it: None for blank lines and
+/// comments, else the pattern without unescaped trailing spaces
+fn pattern_of(line: &str) -> Option<&str> {
+    let trimmed = line.trim_end_matches(' ');
+    // "\ " keeps the space it escapes
+    let escaped_space = trimmed.len() < line.len() && trailing_backslashes(trimmed) % 2 == 1;
+    let pattern = if escaped_space { &line[..trimmed.len() + 1] } else { trimmed };
+    (!pattern.is_empty() && !pattern.starts_with('#')).then_some(pattern)
+}
+
+fn trailing_backslashes(text: &str) -> usize {
+    text.chars().rev().take_while(|&c| c == '\\').count()
+}
+
+/// Why git can't use `pattern`, if it can't. Git accepts every line, but
+/// these never match anything.
+fn check_pattern(pattern: &str) -> Result<(), &'static str> {
+    let body = pattern.strip_prefix('!').unwrap_or(pattern);
+    if body.trim_matches('/').is_empty() {
+        return Err("pattern matches nothing");
+    }
+    if trailing_backslashes(body) % 2 == 1 {
+        return Err("pattern ends with an unescaped backslash");
+    }
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                // A `]` right after the opening (or its negation) is literal
+                if matches!(chars.peek(), Some('!' | '^')) {
+                    chars.next();
+                }
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                }
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    if c == '\\' {
+                        chars.next();
+                    } else if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err("character class '[' is never closed");
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// 400 `invalid_ignore_pattern`, with each bad `line` in `details.errors`,
+/// unless every line of `content` is a usable pattern
+fn check_content(content: &str) -> Result<(), ApiError> {
+    let mut errors = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let problem = if line.contains('\0') {
+            Err("line contains a NUL character")
+        } else if line.ends_with('\r') {
+            // Git keeps the carriage return as part of the pattern
+            Err("line ends with a carriage return")
+        } else {
+            pattern_of(line).map_or(Ok(()), check_pattern)
+        };
+        if let Err(message) = problem {
+            errors.push(serde_json::json!({ "line": index + 1, "pattern": line, "message": message }));
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(ApiError::validation(
+        "invalid_ignore_pattern",
+        format!("{} line(s) aren't valid gitignore patterns", errors.len()),
+    )
+    .with_details(serde_json::json!({ "errors": errors })))
+}
+
+fn source(path: String, kind: &'static str, content: String) -> IgnoreSource {
+    let patterns = content.lines().filter_map(pattern_of).map(str::to_string).collect();
+    IgnoreSource {
+        path,
+        kind,
+        content,
+        patterns,
+    }
+}
+
+fn flatten_files(entries: &[FileEntry], paths: &mut Vec<String>) {
+    for entry in entries {
+        match &entry.children {
+            Some(children) => flatten_files(children, paths),
+            None if !entry.is_dir => paths.push(entry.path.clone()),
+            None => {}
+        }
+    }
+}
+
+/// Drop `ignored` files from a listing, and directories left without any
+fn drop_files(entries: &mut Vec<FileEntry>, ignored: &std::collections::HashSet<String>) {
+    entries.retain_mut(|entry| match &mut entry.children {
+        Some(children) => {
+            drop_files(children, ignored);
+            !children.is_empty()
+        }
+        None => entry.is_dir || !ignored.contains(&entry.path),
+    });
+}
+
+/// Leave the files git ignores out of a listing of `worktree_path`
+pub fn prune_ignored(worktree_path: &Path, entries: &mut Vec<FileEntry>) -> git_ops::GitResult<()> {
+    let mut paths = Vec::new();
+    flatten_files(entries, &mut paths);
+    let ignored = git_ops::check_ignore(worktree_path, &paths, None)?
+        .into_iter()
+        .filter(|found| found.ignores())
+        .map(|found| found.path)
+        .collect();
+    drop_files(entries, &ignored);
+    Ok(())
+}
+
+/// The files of the runbook tree as it is listed now
+fn listed_files(worktree_path: &Path) -> Result<Vec<String>, ApiError> {
+    let mut entries =
+        file_ops::list_files(worktree_path, None).map_err(|e| ApiError::io("Failed to list files", e))?;
+    prune_ignored(worktree_path, &mut entries).map_err(|e| e.context("Failed to check ignored files"))?;
+    let mut paths = Vec::new();
+    flatten_files(&entries, &mut paths);
+    Ok(paths)
+}
+
+/// What each pattern of `content` that the current top-level `.gitignore`
+/// lacks would hide of the listed files, from one `check-ignore` run with
+/// the new patterns as extra excludes
+fn preview(worktree_path: &Path, current: &str, content: &str) -> Result<Vec<PatternPreview>, ApiError> {
+    let existing: Vec<&str> = current.lines().filter_map(pattern_of).collect();
+    let mut previews = Vec::new();
+    let mut excludes = String::new();
+    // Line of each new pattern in the excludes file, by line in `content`
+    let mut by_excludes_line = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let Some(pattern) = pattern_of(line) else { continue };
+        if existing.contains(&pattern) {
+            continue;
+        }
+        excludes.push_str(line);
+        excludes.push('\n');
+        by_excludes_line.insert(by_excludes_line.len() + 1, previews.len());
+        previews.push(PatternPreview {
+            line: index + 1,
+            pattern: pattern.to_string(),
+            hides: 0,
+            paths: Vec::new(),
+        });
+    }
+    if previews.is_empty() {
+        return Ok(previews);
+    }
+
+    let listed = listed_files(worktree_path)?;
+    let excludes_path = std::env::temp_dir().join(format!("runotepad-exclude-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&excludes_path, excludes).map_err(|e| ApiError::io("Failed to write the preview patterns", e))?;
+    let found = git_ops::check_ignore(worktree_path, &listed, Some(&excludes_path));
+    let _ = std::fs::remove_file(&excludes_path);
+
+    let excludes_source = excludes_path.to_string_lossy();
+    for found in found.map_err(|e| e.context("Failed to check ignored files"))? {
+        if found.source != excludes_source || !found.ignores() {
+            continue;
+        }
+        if let Some(&index) = by_excludes_line.get(&found.line) {
+            let preview = &mut previews[index];
+            preview.hides += 1;
+            if preview.paths.len() < PREVIEW_PATHS {
+                preview.paths.push(found.path);
+            }
+        }
+    }
+    Ok(previews)
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/ignore - The files the
+/// worktree's ignore rules come from: its `.gitignore` files and the
+/// repository's `info/exclude`
+pub async fn get_ignore(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    let sources = logging::block(move || -> Result<Vec<IgnoreSource>, ApiError> {
+        let failed = |e: git_ops::GitError| ApiError::from(e.context("Failed to find ignore files"));
+        let mut sources = Vec::new();
+        for file in git_ops::gitignore_files(&worktree_path).map_err(failed)? {
+            if let Ok(content) = std::fs::read_to_string(worktree_path.join(&file)) {
+                sources.push(source(file, "gitignore", content));
+            }
+        }
+        let exclude = git_ops::info_exclude_path(&worktree_path).map_err(failed)?;
+        if let Ok(content) = std::fs::read_to_string(exclude) {
+            sources.push(source("info/exclude".to_string(), "exclude", content));
+        }
+        Ok(sources)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "sources": sources })))
+}
+
+/// PUT /api/workspaces/{name}/branches/{branch}/ignore - Replace the
+/// top-level `.gitignore`, previewing what its new patterns hide; with
+/// `?dry_run=true` only the preview
+pub async fn update_ignore(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<IgnoreQuery>,
+    body: Json<UpdateIgnoreRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    check_content(&body.content)?;
+    if !query.dry_run {
+        quota::require_space(&config, &workspace)?;
+    }
+
+    let content = body.into_inner().content;
+    let bytes = content.len();
+    let dry_run = query.dry_run;
+    let listings = state.listings.clone();
+    let previews = {
+        let workspace = workspace.clone();
+        let branch = branch.clone();
+        logging::block(move || -> Result<Vec<PatternPreview>, ApiError> {
+            let current = std::fs::read_to_string(worktree_path.join(GITIGNORE)).unwrap_or_default();
+            let previews = preview(&worktree_path, &current, &content)?;
+            if !dry_run {
+                let written = file_ops::write_file(&worktree_path, GITIGNORE, &content);
+                listings.invalidate_tree(&workspace, &branch);
+                written.map_err(|e| ApiError::io("Failed to save .gitignore", e))?;
+            }
+            Ok(previews)
+        })
+        .await??
+    };
+
+    if !dry_run {
+        config.add_workspace_usage(&workspace, bytes as u64);
+        audit::record(
+            &req,
+            "ignore.update",
+            serde_json::json!({
+                "workspace": workspace,
+                "branch": branch,
+                "path": GITIGNORE,
+                "bytes": bytes
+            }),
+        );
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": GITIGNORE,
+        "saved": !dry_run,
+        "preview": previews
+    })))
+}
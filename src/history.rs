@@ -0,0 +1,344 @@
+//! Execution history: every runbook block run and checklist tick in a
+//! workspace, appended to `executions.jsonl` in the workspace directory,
+//! with the captured output of worktree runs kept beside it in
+//! `executions/<id>.json`. Entries past the configured count or age are
+//! pruned as new ones are appended.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::auth;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::file_ops;
+use crate::logging;
+use crate::workspace;
+use crate::AppState;
+
+const HISTORY_FILE: &str = "executions.jsonl";
+const OUTPUT_DIR: &str = "executions";
+const DEFAULT_EXECUTION_LIMIT: usize = 100;
+const MAX_EXECUTION_LIMIT: usize = 1000;
+
+/// One line of the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Execution {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub token: Option<String>,
+    pub branch: String,
+    pub path: String,
+    /// SHA-256 of the file as it was when acted on
+    pub file_hash: String,
+    #[serde(flatten)]
+    pub kind: ExecutionKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExecutionKind {
+    BlockRun {
+        block_index: usize,
+        block_hash: String,
+        /// Terminal session the block was typed into; such runs have no
+        /// exit code, duration or output
+        session_id: Option<String>,
+        /// Label of that session when the block ran
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_label: Option<String>,
+        /// Token that approved a `requires_approval` block
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        approved_by: Option<String>,
+        /// Schedule that ran the block
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        schedule_id: Option<String>,
+        exit_code: Option<i32>,
+        timed_out: bool,
+        duration_ms: Option<u64>,
+    },
+    /// A block run was checked and resolved but not run; the substituted
+    /// code isn't kept since variable values may be sensitive
+    DryRun {
+        block_index: usize,
+        block_hash: String,
+        session_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        approved_by: Option<String>,
+    },
+    /// A checklist step was ticked or unticked
+    ChecklistToggle {
+        step_index: usize,
+        checked: bool,
+        text: String,
+    },
+}
+
+/// Output of a worktree run, cut to `max_recorded_output_bytes`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+}
+
+/// How much history a workspace keeps
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub max_entries: usize,
+    pub max_age: Option<chrono::Duration>,
+}
+
+impl Retention {
+    pub fn from_config(config: &ConfigManager) -> Self {
+        Self {
+            max_entries: config.get_execution_history_max_entries(),
+            max_age: config
+                .get_execution_history_max_age_days()
+                .map(|days| chrono::Duration::days(days as i64)),
+        }
+    }
+
+    fn keeps(&self, execution: &Execution, now: DateTime<Utc>) -> bool {
+        self.max_age.is_none_or(|age| now - execution.timestamp <= age)
+    }
+}
+
+fn history_file(workspace_path: &Path) -> PathBuf {
+    workspace_path.join(HISTORY_FILE)
+}
+
+fn output_file(workspace_path: &Path, id: &str) -> PathBuf {
+    workspace_path.join(OUTPUT_DIR).join(format!("{}.json", id))
+}
+
+/// Every entry of a workspace's history, oldest first
+fn read_all(workspace_path: &Path) -> std::io::Result<Vec<Execution>> {
+    let file = match File::open(history_file(workspace_path)) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        // A line still being appended can't be parsed yet; skip it
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// A workspace's history from the newest entry back, read lazily
+pub fn newest_first(workspace_path: &Path) -> std::io::Result<impl Iterator<Item = std::io::Result<Execution>>> {
+    let lines = match file_ops::ReverseLines::open(&history_file(workspace_path)) {
+        Ok(lines) => Some(lines),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    Ok(lines.into_iter().flatten().filter_map(|line| match line {
+        // A line still being appended can't be parsed yet; skip it
+        Ok((_, line)) => serde_json::from_str(&line).ok().map(Ok),
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+/// Serializes appends and prunes. Prunes replace the file by renaming, so
+/// readers don't need to wait.
+pub struct ExecutionHistory {
+    lock: Mutex<()>,
+}
+
+impl ExecutionHistory {
+    pub fn new() -> Self {
+        Self { lock: Mutex::new(()) }
+    }
+
+    pub fn append(
+        &self,
+        workspace_path: &Path,
+        execution: &Execution,
+        output: Option<&StoredOutput>,
+        retention: Retention,
+    ) -> std::io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(output) = output {
+            let path = output_file(workspace_path, &execution.id);
+            fs::create_dir_all(workspace_path.join(OUTPUT_DIR))?;
+            file_ops::atomic_write(&path, serde_json::to_string(output)?.as_bytes())?;
+        }
+
+        let mut line = serde_json::to_string(execution)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(history_file(workspace_path))?;
+        // One write per line so readers never see half an entry
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        self.prune(workspace_path, retention)
+    }
+
+    /// Drop entries past `retention`, and their output
+    fn prune(&self, workspace_path: &Path, retention: Retention) -> std::io::Result<()> {
+        let entries = read_all(workspace_path)?;
+        let now = Utc::now();
+        let expired = entries.iter().take_while(|entry| !retention.keeps(entry, now)).count();
+        let drop = expired.max(entries.len().saturating_sub(retention.max_entries));
+        if drop == 0 {
+            return Ok(());
+        }
+
+        let mut kept = String::new();
+        for entry in &entries[drop..] {
+            kept.push_str(&serde_json::to_string(entry)?);
+            kept.push('\n');
+        }
+        file_ops::atomic_write(&history_file(workspace_path), kept.as_bytes())?;
+        for entry in &entries[..drop] {
+            let _ = fs::remove_file(output_file(workspace_path, &entry.id));
+        }
+        log::debug!("Pruned {} executions from {:?}", drop, workspace_path);
+        Ok(())
+    }
+}
+
+impl Default for ExecutionHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Append `execution` to `workspace`'s history; a failure is logged rather
+/// than failing the request, since what it records already happened
+pub async fn record(
+    state: &Arc<AppState>,
+    config: &ConfigManager,
+    workspace: &str,
+    execution: Execution,
+    output: Option<StoredOutput>,
+) {
+    let workspace_path = config.workspace_path(workspace);
+    let retention = Retention::from_config(config);
+    let state = state.clone();
+    let id = execution.id.clone();
+    let appended = logging::block(move || {
+        state.executions.append(&workspace_path, &execution, output.as_ref(), retention)
+    })
+    .await;
+    match appended {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::error!("Failed to record execution {} in the history: {}", id, e),
+        Err(e) => log::error!("Failed to record execution {} in the history: {}", id, e),
+    }
+}
+
+/// Entries for `path` (or any file) strictly after `since`, oldest first,
+/// up to `limit`; or the latest `limit` entries when `since` is not given
+fn read(
+    workspace_path: &Path,
+    path: Option<&str>,
+    since: Option<DateTime<Utc>>,
+    limit: usize,
+) -> std::io::Result<Vec<Execution>> {
+    let mut entries = VecDeque::new();
+    for entry in read_all(workspace_path)? {
+        if path.is_some_and(|path| entry.path != path) {
+            continue;
+        }
+        match since {
+            Some(since) if entry.timestamp <= since => continue,
+            Some(_) => {
+                entries.push_back(entry);
+                if entries.len() == limit {
+                    break;
+                }
+            }
+            None => {
+                entries.push_back(entry);
+                if entries.len() > limit {
+                    entries.pop_front();
+                }
+            }
+        }
+    }
+    Ok(entries.into())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExecutionsQuery {
+    pub path: Option<String>,
+    pub limit: Option<usize>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// GET /api/workspaces/{name}/executions - Page through a workspace's
+/// execution history
+pub async fn list_executions(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+    query: web::Query<ExecutionsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let workspace = path.into_inner();
+    workspace::require_workspace(&config, &workspace)?;
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_EXECUTION_LIMIT).clamp(1, MAX_EXECUTION_LIMIT);
+
+    let workspace_path = config.workspace_path(&workspace);
+    let entries = logging::block(move || read(&workspace_path, query.path.as_deref(), query.since, limit))
+        .await?
+        .map_err(|e| ApiError::io("Failed to read execution history", e))?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+/// GET /api/executions/{id} - One execution, with its stored output
+pub async fn get_execution(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let workspaces: Vec<(String, PathBuf)> = config
+        .get_workspaces()
+        .into_keys()
+        .map(|name| {
+            let path = config.workspace_path(&name);
+            (name, path)
+        })
+        .collect();
+
+    let found = logging::block(move || -> std::io::Result<_> {
+        for (workspace, workspace_path) in workspaces {
+            let Some(execution) = read_all(&workspace_path)?.into_iter().find(|entry| entry.id == id) else {
+                continue;
+            };
+            let output = match fs::read(output_file(&workspace_path, &id)) {
+                Ok(data) => serde_json::from_slice::<StoredOutput>(&data).ok(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            return Ok(Some((workspace, execution, output)));
+        }
+        Ok(None)
+    })
+    .await?
+    .map_err(|e| ApiError::io("Failed to read execution history", e))?;
+
+    let Some((workspace, execution, output)) = found else {
+        return Err(ApiError::not_found("execution_not_found", "Execution not found"));
+    };
+    auth::require_workspace_access(&req, &workspace)?;
+    let mut body = serde_json::to_value(&execution).unwrap_or_default();
+    body["workspace"] = serde_json::json!(workspace);
+    body["output"] = serde_json::json!(output);
+    Ok(HttpResponse::Ok().json(body))
+}
@@ -0,0 +1,802 @@
+//! Background jobs for git operations that can take a while: clones,
+//! fetches, pulls, pushes and rebases. Their endpoints queue a job and
+//! answer 202 with its record right away; a fixed pool of worker threads
+//! runs the jobs, each under a time limit and cancellable. Every change to a
+//! job record is published as an `Event::Job`. Job records live in memory
+//! only, and only the latest `MAX_FINISHED_JOBS` finished ones are kept.
+//!
+//! With `?sync=true` the same work runs within the request, under the same
+//! time limit, and its git commands are killed if the client disconnects.
+//!
+//! Jobs queued together as a batch (see `bulk`) share the pool with the
+//! rest, but only as many of a batch run at once as it was given, so a
+//! fetch of every workspace doesn't hold up other work. A batch keeps the
+//! final record of each of its jobs, and audits each job's outcome.
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::api;
+use crate::audit;
+use crate::auth;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::events::{Event, EventBus};
+use crate::git_ops::{self, GitProgress, RunControl};
+use crate::logging::{self, LogContext};
+use crate::AppState;
+
+/// Finished jobs kept for `GET /api/jobs`; older ones are forgotten
+const MAX_FINISHED_JOBS: usize = 200;
+/// Batches kept; the oldest finished ones are forgotten beyond this
+const MAX_BATCHES: usize = 50;
+const DEFAULT_JOB_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    CloneWorkspace,
+    Fetch,
+    Pull,
+    Push,
+    Rebase,
+    Verify,
+    Prune,
+    CleanupMerged,
+}
+
+impl JobKind {
+    /// As serialized, and as keyed in `job_timeouts`
+    pub fn name(self) -> &'static str {
+        match self {
+            JobKind::CloneWorkspace => "clone_workspace",
+            JobKind::Fetch => "fetch",
+            JobKind::Pull => "pull",
+            JobKind::Push => "push",
+            JobKind::Rebase => "rebase",
+            JobKind::Verify => "verify",
+            JobKind::Prune => "prune",
+            JobKind::CleanupMerged => "cleanup_merged",
+        }
+    }
+
+    /// Time limit without a configured one: clones and pushes move whole
+    /// repositories' worth of objects, the rest mostly wait on the remote
+    pub fn default_timeout(self) -> Duration {
+        match self {
+            JobKind::CloneWorkspace => Duration::from_secs(30 * 60),
+            JobKind::Push => Duration::from_secs(15 * 60),
+            JobKind::Fetch
+            | JobKind::Pull
+            | JobKind::Rebase
+            | JobKind::Verify
+            | JobKind::Prune
+            | JobKind::CleanupMerged => Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Time limit of each kind of job: its `job_timeouts` entry, else
+/// `job_timeout_secs`, else its default
+#[derive(Debug, Clone)]
+pub struct JobTimeouts {
+    configured: crate::config::JobTimeoutsConfig,
+    fallback: Option<Duration>,
+}
+
+impl JobTimeouts {
+    pub fn from_config(config: &ConfigManager) -> Self {
+        Self {
+            configured: config.get_job_timeouts(),
+            fallback: config.get_job_timeout(),
+        }
+    }
+
+    pub fn get(&self, kind: JobKind) -> Duration {
+        let configured = match kind {
+            JobKind::CloneWorkspace => self.configured.clone_workspace,
+            JobKind::Fetch => self.configured.fetch,
+            JobKind::Pull => self.configured.pull,
+            JobKind::Push => self.configured.push,
+            JobKind::Rebase => self.configured.rebase,
+            JobKind::Verify => self.configured.verify,
+            JobKind::Prune => self.configured.prune,
+            JobKind::CleanupMerged => self.configured.cleanup_merged,
+        };
+        configured
+            .map(Duration::from_secs)
+            .or(self.fallback)
+            .unwrap_or_else(|| kind.default_timeout())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+/// A job as reported by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: JobKind,
+    pub workspace: String,
+    pub branch: Option<String>,
+    /// The batch the job was queued in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+    pub state: JobState,
+    /// What the job is doing right now, for people
+    pub progress: Option<String>,
+    /// `progress` taken apart when it is a progress line of git's, for a
+    /// progress bar
+    pub git_progress: Option<GitProgress>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// The body the synchronous endpoint would have answered with
+    pub result: Option<Value>,
+    /// `{code, message, details}` as in error responses
+    pub error: Option<Value>,
+}
+
+type Work = Box<dyn FnOnce(&JobContext) -> Result<Value, ApiError> + Send>;
+
+/// An operation to run in the background
+pub struct Job {
+    kind: JobKind,
+    workspace: String,
+    branch: Option<String>,
+    batch: Option<String>,
+    work: Work,
+}
+
+impl Job {
+    /// `work` runs on a worker thread and returns the response body for
+    /// success; git commands it runs are killed on cancellation or timeout
+    pub fn new(
+        kind: JobKind,
+        workspace: String,
+        branch: Option<String>,
+        work: impl FnOnce(&JobContext) -> Result<Value, ApiError> + Send + 'static,
+    ) -> Self {
+        Self {
+            kind,
+            workspace,
+            branch,
+            batch: None,
+            work: Box::new(work),
+        }
+    }
+}
+
+/// Handed to a running job to report progress
+#[derive(Clone)]
+pub struct JobContext {
+    record: Option<(Arc<Shared>, String)>,
+}
+
+impl JobContext {
+    pub fn progress(&self, text: impl Into<String>) {
+        self.update(text.into(), None);
+    }
+
+    /// Report a progress line of a git command the job runs
+    pub fn git_progress(&self, line: &str) {
+        self.update(line.to_string(), Some(git_ops::parse_progress(line)));
+    }
+
+    fn update(&self, text: String, git_progress: Option<GitProgress>) {
+        log::debug!("Job progress: {}", text);
+        if let Some((shared, id)) = &self.record {
+            if let Some(entry) = shared.table.lock().unwrap().jobs.get_mut(id) {
+                entry.info.progress = Some(text);
+                entry.info.git_progress = git_progress;
+                shared.publish(&entry.info);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SyncQuery {
+    /// Run the operation within the request, as before jobs existed
+    #[serde(default)]
+    pub sync: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobListQuery {
+    pub workspace: Option<String>,
+    pub limit: Option<usize>,
+}
+
+struct Entry {
+    info: JobInfo,
+    /// Set once the job starts running
+    control: Option<RunControl>,
+    cancel_requested: bool,
+}
+
+/// A job of a batch, as listed when it is queued
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchJob {
+    pub workspace: String,
+    pub job_id: String,
+}
+
+/// Jobs queued together for several workspaces
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchInfo {
+    pub batch_id: String,
+    pub operation: JobKind,
+    pub created_at: DateTime<Utc>,
+    /// Jobs of the batch run at once at most
+    pub concurrency: usize,
+    pub jobs: Vec<BatchJob>,
+}
+
+/// Jobs of a batch in each state
+#[derive(Debug, Default, Serialize)]
+pub struct StateCounts {
+    pub queued: usize,
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+}
+
+/// A batch and where its jobs are at
+#[derive(Debug, Serialize)]
+pub struct BatchStatus {
+    #[serde(flatten)]
+    pub info: BatchInfo,
+    /// Whether every job has finished
+    pub finished: bool,
+    pub counts: StateCounts,
+    pub job_states: Vec<JobInfo>,
+}
+
+struct Batch {
+    info: BatchInfo,
+    running: usize,
+    /// Records of its finished jobs, kept after the job table forgets them
+    finished: HashMap<String, JobInfo>,
+    audit: audit::Recorder,
+}
+
+impl Batch {
+    fn has_room(&self) -> bool {
+        self.running < self.info.concurrency
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.len() == self.info.jobs.len()
+    }
+}
+
+struct Pending {
+    id: String,
+    kind: JobKind,
+    batch: Option<String>,
+    work: Work,
+    log_ctx: Option<LogContext>,
+}
+
+struct Table {
+    jobs: HashMap<String, Entry>,
+    /// IDs oldest first, for listing and for forgetting finished jobs
+    order: VecDeque<String>,
+    pending: VecDeque<Pending>,
+    batches: HashMap<String, Batch>,
+    /// Batch IDs oldest first, for forgetting finished batches
+    batch_order: VecDeque<String>,
+    shutdown: bool,
+}
+
+impl Table {
+    /// Position of the first pending job whose batch, if any, may start
+    /// another job
+    fn next_runnable(&self) -> Option<usize> {
+        self.pending.iter().position(|pending| {
+            pending
+                .batch
+                .as_ref()
+                .is_none_or(|batch| self.batches.get(batch).is_none_or(Batch::has_room))
+        })
+    }
+
+    fn insert(&mut self, info: &JobInfo, job: Job) {
+        self.order.push_back(info.id.clone());
+        self.pending.push_back(Pending {
+            id: info.id.clone(),
+            kind: job.kind,
+            batch: job.batch,
+            work: job.work,
+            log_ctx: logging::current(),
+        });
+        self.jobs.insert(
+            info.id.clone(),
+            Entry {
+                info: info.clone(),
+                control: None,
+                cancel_requested: false,
+            },
+        );
+    }
+
+    /// Drop the oldest finished batches beyond `MAX_BATCHES`
+    fn forget_old_batches(&mut self) {
+        let mut excess = self.batch_order.len().saturating_sub(MAX_BATCHES);
+        let batches = &mut self.batches;
+        self.batch_order.retain(|id| {
+            let forget = excess > 0 && batches.get(id).is_none_or(Batch::is_finished);
+            if forget {
+                batches.remove(id);
+                excess -= 1;
+            }
+            !forget
+        });
+    }
+}
+
+struct Shared {
+    table: Mutex<Table>,
+    available: Condvar,
+    timeouts: JobTimeouts,
+    events: EventBus,
+}
+
+impl Shared {
+    fn publish(&self, info: &JobInfo) {
+        self.events.publish(Event::Job { job: info.clone() });
+    }
+}
+
+/// The job records and the worker threads running them
+pub struct JobQueue {
+    shared: Arc<Shared>,
+}
+
+impl JobQueue {
+    pub fn new(workers: usize, timeouts: JobTimeouts, events: EventBus) -> Self {
+        let shared = Arc::new(Shared {
+            table: Mutex::new(Table {
+                jobs: HashMap::new(),
+                order: VecDeque::new(),
+                pending: VecDeque::new(),
+                batches: HashMap::new(),
+                batch_order: VecDeque::new(),
+                shutdown: false,
+            }),
+            available: Condvar::new(),
+            timeouts,
+            events,
+        });
+        for n in 0..workers {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name(format!("job-worker-{}", n))
+                .spawn(move || work_loop(shared))
+                .expect("failed to start job worker");
+        }
+        Self { shared }
+    }
+
+    pub fn timeout(&self, kind: JobKind) -> Duration {
+        self.shared.timeouts.get(kind)
+    }
+
+    /// Queue `job` and return its record
+    pub fn enqueue(&self, job: Job) -> JobInfo {
+        let info = new_record(&job);
+        let mut table = self.shared.table.lock().unwrap();
+        table.insert(&info, job);
+        self.shared.publish(&info);
+        drop(table);
+        self.shared.available.notify_one();
+        info
+    }
+
+    /// Queue `jobs` as one batch of `operation`, of which at most
+    /// `concurrency` run at once; `audit` records each job's outcome
+    pub fn enqueue_batch(&self, operation: JobKind, jobs: Vec<Job>, concurrency: usize, audit: audit::Recorder) -> BatchInfo {
+        let batch_id = uuid::Uuid::new_v4().to_string();
+        let mut records = Vec::with_capacity(jobs.len());
+        let mut table = self.shared.table.lock().unwrap();
+        for mut job in jobs {
+            job.batch = Some(batch_id.clone());
+            let info = new_record(&job);
+            table.insert(&info, job);
+            self.shared.publish(&info);
+            records.push(info);
+        }
+        let info = BatchInfo {
+            batch_id: batch_id.clone(),
+            operation,
+            created_at: Utc::now(),
+            concurrency: concurrency.max(1),
+            jobs: records
+                .iter()
+                .map(|record| BatchJob {
+                    workspace: record.workspace.clone(),
+                    job_id: record.id.clone(),
+                })
+                .collect(),
+        };
+        log::info!("Queued batch {} of {} {} jobs", batch_id, records.len(), operation.name());
+        table.batches.insert(
+            batch_id.clone(),
+            Batch {
+                info: info.clone(),
+                running: 0,
+                finished: HashMap::new(),
+                audit,
+            },
+        );
+        table.batch_order.push_back(batch_id);
+        table.forget_old_batches();
+        drop(table);
+        self.shared.available.notify_all();
+        info
+    }
+
+    /// Batch `id` with the current record of each of its jobs
+    pub fn batch(&self, id: &str) -> Option<BatchStatus> {
+        let table = self.shared.table.lock().unwrap();
+        let batch = table.batches.get(id)?;
+        let mut counts = StateCounts::default();
+        let mut job_states = Vec::with_capacity(batch.info.jobs.len());
+        for job in &batch.info.jobs {
+            let Some(info) = batch
+                .finished
+                .get(&job.job_id)
+                .or_else(|| table.jobs.get(&job.job_id).map(|entry| &entry.info))
+            else {
+                continue;
+            };
+            *match info.state {
+                JobState::Queued => &mut counts.queued,
+                JobState::Running => &mut counts.running,
+                JobState::Succeeded => &mut counts.succeeded,
+                JobState::Failed => &mut counts.failed,
+                JobState::Cancelled => &mut counts.cancelled,
+            } += 1;
+            job_states.push(info.clone());
+        }
+        Some(BatchStatus {
+            info: batch.info.clone(),
+            finished: batch.is_finished(),
+            counts,
+            job_states,
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobInfo> {
+        self.shared.table.lock().unwrap().jobs.get(id).map(|e| e.info.clone())
+    }
+
+    /// Newest first, only those `include` accepts
+    pub fn list(&self, include: impl Fn(&JobInfo) -> bool, limit: usize) -> Vec<JobInfo> {
+        let table = self.shared.table.lock().unwrap();
+        table
+            .order
+            .iter()
+            .rev()
+            .filter_map(|id| table.jobs.get(id))
+            .filter(|e| include(&e.info))
+            .take(limit)
+            .map(|e| e.info.clone())
+            .collect()
+    }
+
+    /// Stop a job: a queued one never starts its git commands, a running
+    /// one has its current command killed. `None` if there is no such job.
+    pub fn cancel(&self, id: &str) -> Option<Result<JobInfo, JobInfo>> {
+        let mut table = self.shared.table.lock().unwrap();
+        let entry = table.jobs.get_mut(id)?;
+        if entry.info.state.is_finished() {
+            return Some(Err(entry.info.clone()));
+        }
+        entry.cancel_requested = true;
+        match &entry.control {
+            Some(control) => control.cancel(),
+            None => {
+                // Still runs when a worker picks it up, so it can clean up,
+                // but its git commands refuse to start
+                finish(&mut entry.info, JobState::Cancelled, None, Some(cancelled_error()));
+                self.shared.publish(&entry.info);
+            }
+        }
+        log::info!("Cancelled job {}", id);
+        let info = entry.info.clone();
+        if info.state.is_finished() {
+            settle(&mut table, &info);
+        }
+        Some(Ok(info))
+    }
+}
+
+impl Drop for JobQueue {
+    fn drop(&mut self) {
+        self.shared.table.lock().unwrap().shutdown = true;
+        self.shared.available.notify_all();
+    }
+}
+
+fn new_record(job: &Job) -> JobInfo {
+    let info = JobInfo {
+        id: uuid::Uuid::new_v4().to_string(),
+        kind: job.kind,
+        workspace: job.workspace.clone(),
+        branch: job.branch.clone(),
+        batch: job.batch.clone(),
+        state: JobState::Queued,
+        progress: None,
+        git_progress: None,
+        created_at: Utc::now(),
+        started_at: None,
+        finished_at: None,
+        result: None,
+        error: None,
+    };
+    log::info!("Queued {:?} job {} for workspace '{}'", info.kind, info.id, info.workspace);
+    info
+}
+
+fn cancelled_error() -> Value {
+    ApiError::conflict("job_cancelled", "Job was cancelled").body()["error"].clone()
+}
+
+/// Keep the final record of a job of a batch, and audit its outcome, once
+fn settle(table: &mut Table, info: &JobInfo) {
+    let Some(batch) = info.batch.as_ref().and_then(|batch| table.batches.get_mut(batch)) else {
+        return;
+    };
+    if batch.finished.insert(info.id.clone(), info.clone()).is_some() {
+        return;
+    }
+    batch.audit.record(
+        "workspace.bulk_outcome",
+        serde_json::json!({
+            "batch": batch.info.batch_id,
+            "operation": batch.info.operation,
+            "workspace": info.workspace,
+            "job": info.id,
+            "state": info.state,
+            "error": info.error.as_ref().map(|error| &error["code"])
+        }),
+    );
+}
+
+fn finish(info: &mut JobInfo, state: JobState, result: Option<Value>, error: Option<Value>) {
+    info.state = state;
+    info.finished_at = Some(Utc::now());
+    info.result = result;
+    info.error = error;
+}
+
+/// Run `work` under `control`, turning a stop for lack of time into a 504
+/// naming the operation. Progress lines of the git commands it runs become
+/// the job's progress.
+fn execute(work: Work, ctx: &JobContext, control: RunControl, kind: JobKind, timeout: Duration) -> Result<Value, ApiError> {
+    let reporter = ctx.clone();
+    let outcome = git_ops::with_control(control.clone(), || {
+        git_ops::with_progress(Arc::new(move |line: &str| reporter.git_progress(line)), || {
+            std::panic::catch_unwind(AssertUnwindSafe(|| work(ctx)))
+        })
+    });
+    match outcome {
+        Ok(Err(_)) if control.is_timed_out() && !control.is_cancelled() => Err(ApiError::timeout(
+            "job_timed_out",
+            format!("Operation {} did not finish within {} seconds", kind.name(), timeout.as_secs()),
+        )
+        .with_details(serde_json::json!({ "operation": kind, "timeout_secs": timeout.as_secs() }))),
+        Ok(result) => result,
+        Err(_) => Err(ApiError::internal("Job panicked")),
+    }
+}
+
+fn work_loop(shared: Arc<Shared>) {
+    loop {
+        let mut table = shared.table.lock().unwrap();
+        let Pending {
+            id,
+            kind,
+            batch,
+            work,
+            log_ctx,
+        } = loop {
+            if table.shutdown {
+                return;
+            }
+            match table.next_runnable().and_then(|position| table.pending.remove(position)) {
+                Some(next) => break next,
+                None => table = shared.available.wait(table).unwrap(),
+            }
+        };
+        if let Some(batch) = batch.as_ref().and_then(|batch| table.batches.get_mut(batch)) {
+            batch.running += 1;
+        }
+
+        let timeout = shared.timeouts.get(kind);
+        let control = RunControl::new(timeout);
+        match table.jobs.get_mut(&id) {
+            Some(entry) => {
+                if entry.cancel_requested {
+                    control.cancel();
+                } else {
+                    entry.info.state = JobState::Running;
+                    entry.info.started_at = Some(Utc::now());
+                    shared.publish(&entry.info);
+                }
+                entry.control = Some(control.clone());
+            }
+            // Cancelled while queued and already forgotten
+            None => control.cancel(),
+        }
+        drop(table);
+
+        let ctx = JobContext {
+            record: Some((shared.clone(), id.clone())),
+        };
+        let outcome = logging::with_context(log_ctx, || {
+            log::info!("Running job {}", id);
+            execute(work, &ctx, control.clone(), kind, timeout)
+        });
+
+        let mut table = shared.table.lock().unwrap();
+        if let Some(entry) = table.jobs.get_mut(&id) {
+            if !entry.info.state.is_finished() {
+                match outcome {
+                    Ok(result) => finish(&mut entry.info, JobState::Succeeded, Some(result), None),
+                    Err(_) if entry.cancel_requested => {
+                        finish(&mut entry.info, JobState::Cancelled, None, Some(cancelled_error()))
+                    }
+                    Err(e) => {
+                        log::warn!("Job {} failed: {}", id, e);
+                        finish(&mut entry.info, JobState::Failed, None, Some(e.body()["error"].clone()))
+                    }
+                }
+                entry.info.progress = None;
+                entry.info.git_progress = None;
+                shared.publish(&entry.info);
+            }
+            entry.control = None;
+            let info = entry.info.clone();
+            settle(&mut table, &info);
+        }
+        if let Some(batch) = batch.as_ref().and_then(|batch| table.batches.get_mut(batch)) {
+            batch.running -= 1;
+            // A job of the batch waiting for this slot may start now
+            shared.available.notify_all();
+        }
+        forget_old_jobs(&mut table);
+    }
+}
+
+/// Drop the oldest finished jobs beyond `MAX_FINISHED_JOBS`
+fn forget_old_jobs(table: &mut Table) {
+    let finished = table
+        .jobs
+        .values()
+        .filter(|e| e.info.state.is_finished() && e.control.is_none())
+        .count();
+    let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+    if excess == 0 {
+        return;
+    }
+    let jobs = &mut table.jobs;
+    table.order.retain(|id| {
+        let forget = excess > 0
+            && jobs
+                .get(id)
+                .is_some_and(|e| e.info.state.is_finished() && e.control.is_none());
+        if forget {
+            jobs.remove(id);
+            excess -= 1;
+        }
+        !forget
+    });
+}
+
+/// Answer a request for `job`: queue it and respond 202 with its record,
+/// or with `?sync=true` run it within the request and respond `status`
+/// with its result. A sync run is stopped when the client disconnects.
+pub async fn submit(state: &AppState, query: &SyncQuery, job: Job, status: StatusCode) -> Result<HttpResponse, ApiError> {
+    if !query.sync {
+        let info = state.jobs.enqueue(job);
+        return Ok(HttpResponse::Accepted()
+            .insert_header(("Location", format!("{}/jobs/{}", api::V1_PREFIX, info.id)))
+            .json(info));
+    }
+
+    let kind = job.kind;
+    let timeout = state.jobs.timeout(kind);
+    let control = RunControl::new(timeout);
+    let cancel_on_disconnect = control.cancel_on_drop();
+    let result = logging::block(move || {
+        let ctx = JobContext { record: None };
+        execute(job.work, &ctx, control, kind, timeout)
+    })
+    .await;
+    cancel_on_disconnect.disarm();
+    Ok(HttpResponse::build(status).json(result??))
+}
+
+fn job_not_found(id: &str) -> ApiError {
+    ApiError::not_found("job_not_found", format!("Job '{}' not found", id))
+}
+
+/// Job `id`, if the token of `req` may use its workspace
+fn find_job(req: &HttpRequest, state: &AppState, id: &str) -> Result<JobInfo, ApiError> {
+    let info = state.jobs.get(id).ok_or_else(|| job_not_found(id))?;
+    auth::require_workspace_access(req, &info.workspace)?;
+    Ok(info)
+}
+
+/// GET /api/jobs - Recent jobs, newest first; only those of workspaces the
+/// token may use
+pub async fn list_jobs(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<JobListQuery>,
+) -> HttpResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_JOB_LIMIT).clamp(1, MAX_FINISHED_JOBS);
+    let include = |info: &JobInfo| {
+        query.workspace.as_deref().is_none_or(|w| info.workspace == w)
+            && auth::may_use_workspace(&req, &info.workspace)
+    };
+    HttpResponse::Ok().json(state.jobs.list(include, limit))
+}
+
+/// GET /api/jobs/{id} - State, progress and outcome of a job
+pub async fn get_job(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    Ok(HttpResponse::Ok().json(find_job(&req, &state, &id)?))
+}
+
+/// POST /api/jobs/{id}/cancel - Stop a queued or running job
+pub async fn cancel_job(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    find_job(&req, &state, &id)?;
+    match state.jobs.cancel(&id) {
+        None => Err(job_not_found(&id)),
+        Some(Err(info)) => Err(ApiError::conflict("job_finished", format!("Job '{}' has already finished", id))
+            .with_details(serde_json::json!({ "state": info.state }))),
+        Some(Ok(info)) => {
+            audit::record(
+                &req,
+                "job.cancel",
+                serde_json::json!({ "job": id, "kind": info.kind, "workspace": info.workspace }),
+            );
+            Ok(HttpResponse::Ok().json(info))
+        }
+    }
+}
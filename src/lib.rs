@@ -0,0 +1,978 @@
+//! Runotepad server: runbook workspaces backed by git, plus terminal
+//! sessions over a WebSocket. `app_factory` builds the HTTP application; the
+//! binary only parses options, loads the config and serves it.
+
+pub mod activity;
+pub mod api;
+pub mod approvals;
+pub mod assets;
+pub mod audit;
+pub mod blocks;
+pub mod branch_naming;
+pub mod branch_search;
+pub mod bulk;
+pub mod bundle;
+pub mod auth;
+pub mod caching;
+pub mod capabilities;
+pub mod checklist;
+pub mod cli;
+pub mod commit_message;
+pub mod config;
+pub mod conflicts;
+pub mod console;
+pub mod diagnostics;
+pub mod diff;
+pub mod drafts;
+pub mod errors;
+pub mod events;
+pub mod export;
+pub mod extract;
+pub mod file_ops;
+pub mod git_ops;
+pub mod git_scope;
+pub mod gitignore;
+pub mod history;
+pub mod jobs;
+pub mod lint;
+pub mod listing_cache;
+pub mod logging;
+pub mod notifications;
+pub mod patch;
+pub mod quota;
+pub mod rate_limit;
+pub mod raw;
+pub mod render;
+pub mod repair;
+pub mod remotes;
+pub mod runbook;
+pub mod scheduler;
+pub mod secrets;
+pub mod server_log;
+pub mod sessions;
+pub mod terminal_output;
+pub mod tls;
+pub mod trash;
+pub mod unix_socket;
+pub mod variables;
+pub mod watcher;
+pub mod workspace;
+pub mod workspace_env;
+pub mod worktree_lock;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::{middleware, web, App, HttpMessage, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use portable_pty::{CommandBuilder, NativePtySystem, PtySize, PtySystem};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use uuid::Uuid;
+
+use config::{ConfigManager, TokenIdentity, TokenScope};
+use watcher::{ChangeKind, FileWatcher};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum WsMessage {
+    #[serde(rename = "create")]
+    Create {
+        id: Option<String>,
+        /// Name to tell the session apart by, e.g. "prod-db shell"
+        label: Option<String>,
+        /// Runbook the session is opened for
+        runbook_path: Option<String>,
+        /// Workspace to start the shell in, with its environment
+        workspace: Option<String>,
+        /// Start in this branch's worktree of `workspace`
+        branch: Option<String>,
+    },
+    #[serde(rename = "input")]
+    Input { session_id: String, data: String },
+    #[serde(rename = "resize")]
+    Resize {
+        session_id: String,
+        cols: u16,
+        rows: u16,
+    },
+    #[serde(rename = "close")]
+    Close {
+        session_id: String,
+        /// Append the session's transcript to the runbook it was opened for
+        #[serde(default)]
+        attach_transcript: bool,
+    },
+    #[serde(rename = "relabel")]
+    Relabel { session_id: String, label: Option<String> },
+}
+
+impl WsMessage {
+    /// Every `type` a client may send, as `kind` names them
+    const KINDS: &'static [&'static str] = &["create", "input", "resize", "close", "relabel"];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            WsMessage::Create { .. } => "create",
+            WsMessage::Input { .. } => "input",
+            WsMessage::Resize { .. } => "resize",
+            WsMessage::Close { .. } => "close",
+            WsMessage::Relabel { .. } => "relabel",
+        }
+    }
+
+    /// The terminal session the message is about, if it names one
+    fn session_id(&self) -> Option<&str> {
+        match self {
+            WsMessage::Create { id, .. } => id.as_deref(),
+            WsMessage::Input { session_id, .. }
+            | WsMessage::Resize { session_id, .. }
+            | WsMessage::Close { session_id, .. }
+            | WsMessage::Relabel { session_id, .. } => Some(session_id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+enum WsResponse {
+    #[serde(rename = "created")]
+    Created {
+        session_id: String,
+        label: Option<String>,
+        runbook_path: Option<String>,
+    },
+    #[serde(rename = "relabeled")]
+    Relabeled { session_id: String, label: Option<String> },
+    #[serde(rename = "output")]
+    Output { session_id: String, data: String },
+    #[serde(rename = "closed")]
+    Closed {
+        session_id: String,
+        /// Where the transcript was attached, when it was asked for
+        #[serde(skip_serializing_if = "Option::is_none")]
+        transcript: Option<sessions::AttachedTranscript>,
+        /// Why the server closed the session, when the client didn't ask
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<&'static str>,
+    },
+    #[serde(rename = "error")]
+    Error { message: String },
+    #[serde(rename = "file_changed")]
+    FileChanged {
+        workspace: String,
+        branch: String,
+        path: String,
+        kind: ChangeKind,
+    },
+}
+
+/// Queue `resp` for a client; output goes through the sender task's
+/// coalescing instead, as `Outgoing::Output`
+fn outgoing(resp: &WsResponse) -> terminal_output::Outgoing {
+    terminal_output::Outgoing::Message(serde_json::to_string(resp).unwrap())
+}
+
+struct PtySession {
+    writer: Box<dyn Write + Send>,
+    master: Box<dyn portable_pty::MasterPty + Send>,
+    child: Box<dyn portable_pty::Child + Send + Sync>,
+    /// Messages to the WebSocket the session was opened from
+    output: mpsc::UnboundedSender<terminal_output::Outgoing>,
+    info: sessions::SessionInfo,
+    /// Output so far, written by the reader thread
+    transcript: Arc<std::sync::Mutex<sessions::Transcript>>,
+    /// Latest output, when `session_scrollback_bytes` keeps any
+    scrollback: Option<Arc<std::sync::Mutex<sessions::Scrollback>>>,
+}
+
+impl PtySession {
+    /// Current working directory of the shell, where the platform exposes it
+    fn cwd(&self) -> Option<std::path::PathBuf> {
+        let pid = self.child.process_id()?;
+        std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+    }
+
+    /// Exit code of the shell, once it has exited
+    fn exit_code(&mut self) -> Option<u32> {
+        self.child.try_wait().ok().flatten().map(|status| status.exit_code())
+    }
+
+    /// Send `signal` to the shell and to the program in the foreground of
+    /// its terminal, then kill them unless the shell exits within
+    /// `TERMINATE_GRACE`. Returns whether the shell had already exited,
+    /// whether it had to be killed, and its exit code.
+    fn terminate(&mut self, signal: sessions::Signal) -> (bool, bool, Option<u32>) {
+        if let Some(exit_code) = self.exit_code() {
+            return (true, false, Some(exit_code));
+        }
+        let mut targets: Vec<String> = self.child.process_id().map(|pid| pid.to_string()).into_iter().collect();
+        // A negative ID is the process group
+        if let Some(group) = self.master.process_group_leader() {
+            targets.push(format!("-{}", group));
+        }
+        let send = |signal: sessions::Signal| {
+            let sent = std::process::Command::new("kill")
+                .args(["-s", signal.name(), "--"])
+                .args(&targets)
+                .stderr(std::process::Stdio::null())
+                .status();
+            if let Err(e) = sent {
+                log::warn!("Failed to run kill: {}", e);
+            }
+        };
+
+        send(signal);
+        let deadline = std::time::Instant::now() + TERMINATE_GRACE;
+        while std::time::Instant::now() < deadline {
+            if let Some(exit_code) = self.exit_code() {
+                return (false, false, Some(exit_code));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        log::warn!("Shell of session {} outlived {:?}, killing it", self.info.session_id, signal);
+        send(sessions::Signal::SIGKILL);
+        let _ = self.child.wait();
+        (false, true, None)
+    }
+}
+
+/// How long a shell signaled through the API gets to exit before it is
+/// killed
+const TERMINATE_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+
+pub struct AppState {
+    sessions: Mutex<HashMap<String, PtySession>>,
+    /// Failed token checks per address, shared by every authenticated route
+    pub auth_limiter: auth::AuthLimiter,
+    /// API request budgets per token or address
+    pub rate_limiter: rate_limit::RateLimiter,
+    /// Browser console messages forwarded per address
+    pub console_limiter: console::ConsoleLimiter,
+    /// The latest forwarded browser console messages
+    pub console_log: console::ConsoleBuffer,
+    /// Workspace names with a create, rename or delete in progress
+    pub workspace_guards: workspace::WorkspaceGuards,
+    pub watcher: Arc<FileWatcher>,
+    /// Recent file trees and branch lists
+    pub listings: Arc<listing_cache::ListingCache>,
+    pub audit_log: Arc<audit::AuditLog>,
+    /// Published to `GET /api/events` subscribers and WebSocket clients
+    pub events: events::EventBus,
+    /// Background git operations and their workers
+    pub jobs: jobs::JobQueue,
+    /// Block runs per workspace
+    pub executions: history::ExecutionHistory,
+    /// Held while a checklist toggle or a raw save checks a file's revision
+    /// and replaces it
+    pub file_write_lock: std::sync::Mutex<()>,
+    /// Held by git operations that change a worktree
+    pub worktree_locks: Arc<worktree_lock::WorktreeLocks>,
+    /// Unused approvals to run `requires_approval` blocks
+    pub approvals: approvals::Approvals,
+    /// Sends events to the configured webhooks
+    pub notifier: notifications::Notifier,
+    /// Schedules with a run in progress
+    pub scheduler: scheduler::Scheduler,
+    /// Where the web UI is served from
+    pub assets: assets::Source,
+    /// Stale locks and interrupted operations found in worktrees
+    pub attention: repair::AttentionBoard,
+}
+
+impl AppState {
+    pub fn new(config: &ConfigManager, audit_log: Arc<audit::AuditLog>) -> Self {
+        let events = events::EventBus::new();
+        let listings = Arc::new(listing_cache::ListingCache::new(config.get_listing_cache_ttl()));
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            auth_limiter: auth::AuthLimiter::new(),
+            rate_limiter: rate_limit::RateLimiter::new(),
+            console_limiter: console::ConsoleLimiter::new(),
+            console_log: console::ConsoleBuffer::new(config.get_console_buffer_entries()),
+            workspace_guards: workspace::WorkspaceGuards::new(),
+            watcher: Arc::new(FileWatcher::new(events.clone(), listings.clone())),
+            listings,
+            audit_log,
+            jobs: jobs::JobQueue::new(config.get_job_workers(), jobs::JobTimeouts::from_config(config), events.clone()),
+            notifier: notifications::Notifier::start(config.get_notifications(), &events),
+            events,
+            executions: history::ExecutionHistory::new(),
+            file_write_lock: std::sync::Mutex::new(()),
+            worktree_locks: Arc::new(worktree_lock::WorktreeLocks::new()),
+            approvals: approvals::Approvals::new(),
+            scheduler: scheduler::Scheduler::new(),
+            assets: assets::Source::resolve(config.static_dir().map(|dir| dir.as_path())),
+            attention: repair::AttentionBoard::new(),
+        }
+    }
+
+    /// IDs of terminal sessions whose shell is working at or below `path`
+    pub async fn sessions_under(&self, path: &std::path::Path) -> Vec<String> {
+        let sessions = self.sessions.lock().await;
+        sessions
+            .iter()
+            .filter(|(_, session)| session.cwd().is_some_and(|cwd| cwd.starts_with(path)))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// The open terminal sessions, oldest first
+    pub async fn session_infos(&self) -> Vec<sessions::SessionInfo> {
+        let sessions = self.sessions.lock().await;
+        let mut infos: Vec<sessions::SessionInfo> = sessions
+            .values()
+            .map(|session| sessions::SessionInfo {
+                cwd: session.cwd().map(|cwd| cwd.to_string_lossy().into_owned()),
+                ..session.info.clone()
+            })
+            .collect();
+        infos.sort_by_key(|info| info.opened_at);
+        infos
+    }
+
+    /// Label of terminal session `id`, if it is open and has one
+    pub async fn session_label(&self, id: &str) -> Option<String> {
+        self.sessions.lock().await.get(id).and_then(|session| session.info.label.clone())
+    }
+
+    /// Terminal session `id`, or 404 unless it is open
+    pub async fn session_info(&self, id: &str) -> Result<sessions::SessionInfo, errors::ApiError> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(id).ok_or_else(|| session_not_found(id))?;
+        Ok(session.info.clone())
+    }
+
+    /// Terminal session `id` and its scrollback, or 404 unless it is open
+    pub async fn session_scrollback(
+        &self,
+        id: &str,
+    ) -> Result<(sessions::SessionInfo, Option<Arc<std::sync::Mutex<sessions::Scrollback>>>), errors::ApiError> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(id).ok_or_else(|| session_not_found(id))?;
+        Ok((session.info.clone(), session.scrollback.clone()))
+    }
+
+    /// Type `data` into terminal session `id`, as if from its WebSocket
+    pub async fn write_to_session(&self, id: &str, data: &[u8]) -> Result<(), errors::ApiError> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(id).ok_or_else(|| session_not_found(id))?;
+        session
+            .writer
+            .write_all(data)
+            .and_then(|_| session.writer.flush())
+            .map_err(|e| errors::ApiError::io("Failed to write to terminal", e))
+    }
+
+    /// Close terminal session `id` with `signal` (see
+    /// `PtySession::terminate`) and tell its client it was terminated
+    pub async fn terminate_session(
+        &self,
+        id: &str,
+        signal: sessions::Signal,
+    ) -> Result<sessions::Terminated, errors::ApiError> {
+        let mut session = self.sessions.lock().await.remove(id).ok_or_else(|| session_not_found(id))?;
+        log::info!("Terminating session {} with {:?}", id, signal);
+        let (session, (already_exited, forced, exit_code)) = logging::block(move || {
+            let outcome = session.terminate(signal);
+            (session, outcome)
+        })
+        .await?;
+        let resp = WsResponse::Closed {
+            session_id: id.to_string(),
+            transcript: None,
+            reason: Some("terminated_by_admin"),
+        };
+        let _ = session.output.send(outgoing(&resp));
+        Ok(sessions::Terminated {
+            session_id: id.to_string(),
+            label: session.info.label.clone(),
+            signal,
+            already_exited,
+            forced,
+            exit_code,
+        })
+    }
+
+    /// Kill the shells of terminal sessions working at or below `path` and
+    /// tell their clients the sessions are closed; returns their IDs
+    pub async fn close_sessions_under(&self, path: &std::path::Path) -> Vec<String> {
+        let ids = self.sessions_under(path).await;
+        let mut sessions = self.sessions.lock().await;
+        for id in &ids {
+            let Some(mut session) = sessions.remove(id) else {
+                continue;
+            };
+            log::info!("Closing session {} working in {:?}", id, path);
+            if let Err(e) = session.child.kill() {
+                log::warn!("Failed to kill shell of session {}: {:?}", id, e);
+            }
+            let resp = WsResponse::Closed {
+                session_id: id.clone(),
+                transcript: None,
+                reason: Some("workspace_deleted"),
+            };
+            let _ = session.output.send(outgoing(&resp));
+            // Reap the shell so it doesn't linger as a zombie
+            std::thread::spawn(move || {
+                let _ = session.child.wait();
+            });
+        }
+        ids
+    }
+}
+
+fn session_not_found(id: &str) -> errors::ApiError {
+    errors::ApiError::not_found("session_not_found", format!("Terminal session '{}' not found", id))
+}
+
+async fn ws_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+    audit_log: web::Data<Arc<audit::AuditLog>>,
+) -> actix_web::Result<HttpResponse> {
+    log::info!("WebSocket connection request from {:?}", req.peer_addr());
+    log::debug!("Request headers: {:?}", auth::redacted_headers(req.headers()));
+
+    // The middleware already requires read_write for /ws; this keeps terminals
+    // closed to read-only tokens even if that routing rule changes
+    let can_open_terminals = req
+        .extensions()
+        .get::<TokenIdentity>()
+        .is_some_and(|identity| identity.scope.allows(TokenScope::ReadWrite));
+    let terminals_refused = if config.read_only() {
+        Some("Server is in read-only mode")
+    } else if !can_open_terminals {
+        Some("Token scope does not allow opening terminals")
+    } else {
+        None
+    };
+    let actor = audit::Actor::from_request(&req);
+    let identity = req.extensions().get::<TokenIdentity>().cloned();
+    let audit_log = audit_log.get_ref().clone();
+
+    let (mut response, mut session, mut msg_stream) = match actix_ws::handle(&req, body) {
+        Ok(result) => {
+            log::info!("WebSocket handshake successful");
+            result
+        }
+        Err(e) => {
+            log::error!("WebSocket handshake failed: {:?}", e);
+            return Err(e);
+        }
+    };
+    // Browsers drop a connection whose handshake doesn't pick one of the
+    // subprotocols they offered; the token itself is never echoed
+    if auth::offers_ws_protocol(&req) {
+        response.headers_mut().insert(
+            actix_web::http::header::SEC_WEBSOCKET_PROTOCOL,
+            actix_web::http::header::HeaderValue::from_static(auth::WS_PROTOCOL),
+        );
+    }
+
+    let state = state.get_ref().clone();
+    let config = config.get_ref().clone();
+    let (tx, rx) = mpsc::unbounded_channel::<terminal_output::Outgoing>();
+    // The connection's tasks keep logging with the upgrade request's ID
+    let conn_ctx = logging::current().unwrap_or_default();
+
+    // Spawn task to send messages from rx to websocket, merging terminal
+    // output queued back to back
+    let mut session_clone = session.clone();
+    actix_rt::spawn(logging::scope(conn_ctx.clone(), async move {
+        log::debug!("Started WebSocket sender task");
+        let mut queue = terminal_output::Coalescer::new(rx);
+        while let Some(next) = queue.next().await {
+            let msg = match next {
+                terminal_output::Outgoing::Output { session_id, data } => {
+                    serde_json::to_string(&WsResponse::Output { session_id, data }).unwrap()
+                }
+                terminal_output::Outgoing::Message(msg) => msg,
+            };
+            log::trace!("Sending WS message: {} bytes", msg.len());
+            if session_clone.text(msg).await.is_err() {
+                log::warn!("Failed to send WebSocket message, closing sender");
+                break;
+            }
+        }
+        log::debug!("WebSocket sender task ended");
+    }));
+
+    // Forward file change notifications from watched worktrees
+    let mut file_events = state.events.subscribe();
+    let events_tx = tx.clone();
+    let events_identity = identity.clone();
+    let forwarder = actix_rt::spawn(logging::scope(conn_ctx.clone(), async move {
+        loop {
+            match file_events.recv().await {
+                Ok(events::Event::FileChanged(event))
+                    if sessions::may_use(events_identity.as_ref(), Some(&event.workspace)) =>
+                {
+                    let resp = WsResponse::FileChanged {
+                        workspace: event.workspace,
+                        branch: event.branch,
+                        path: event.path,
+                        kind: event.kind,
+                    };
+                    if events_tx.send(outgoing(&resp)).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("WebSocket client missed {} events", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }));
+
+    // Handle incoming websocket messages
+    actix_rt::spawn(logging::scope(conn_ctx.clone(), async move {
+        log::info!("Started WebSocket receiver task");
+        // Sessions of workspaces the token may not use are left alone as
+        // if they didn't exist
+        let allowed = |info: &sessions::SessionInfo| sessions::may_use(identity.as_ref(), info.workspace.as_deref());
+        while let Some(result) = msg_stream.next().await {
+            match result {
+                Ok(msg) => {
+                    match msg {
+                        actix_ws::Message::Text(text) => {
+                            let text_str = text.to_string();
+                            // Never log the content: it carries terminal input
+                            log::trace!("Received WS message: {} bytes", text_str.len());
+
+                            match serde_json::from_str::<WsMessage>(&text_str) {
+                                Ok(ws_msg) => {
+                                    let msg_ctx = match ws_msg.session_id() {
+                                        Some(id) => conn_ctx.with_session(id),
+                                        None => conn_ctx.clone(),
+                                    };
+                                    logging::scope(msg_ctx, async {
+                                        log::debug!("Received {} message", ws_msg.kind());
+                                        match ws_msg {
+                                            WsMessage::Create { .. } | WsMessage::Input { .. }
+                                                if terminals_refused.is_some() =>
+                                            {
+                                                let resp = WsResponse::Error {
+                                                    message: terminals_refused.unwrap_or_default().to_string(),
+                                                };
+                                                let _ = session.text(serde_json::to_string(&resp).unwrap()).await;
+                                            }
+                                            WsMessage::Create { id, label, runbook_path, workspace, branch } => {
+                                                let access = sessions::check_access(identity.as_ref(), workspace.as_deref());
+                                                if access.is_err() {
+                                                    audit_log.record_as(
+                                                        &actor,
+                                                        "workspace.denied",
+                                                        "WS /ws".to_string(),
+                                                        serde_json::json!({ "workspace": workspace }),
+                                                    );
+                                                }
+                                                let start = access.and_then(|()| match (&workspace, &branch) {
+                                                    (Some(workspace), branch) => {
+                                                        workspace_env::terminal_start(&config, workspace, branch.as_deref())
+                                                            .map(Some)
+                                                    }
+                                                    (None, Some(_)) => Err(errors::ApiError::validation(
+                                                        "invalid_session",
+                                                        "A branch can only be given with a workspace",
+                                                    )),
+                                                    (None, None) => Ok(None),
+                                                });
+                                                let info = sessions::SessionInfo {
+                                                    session_id: id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+                                                    label: label.as_deref().and_then(sessions::clean_label),
+                                                    runbook_path: runbook_path.as_deref().and_then(sessions::clean_runbook_path),
+                                                    workspace,
+                                                    branch,
+                                                    opened_at: chrono::Utc::now(),
+                                                    opened_by: actor.token.clone(),
+                                                    cwd: None,
+                                                };
+                                                let session_id = info.session_id.clone();
+                                                log::info!("Creating PTY session: {} ({:?})", session_id, info.label);
+
+                                                let scrollback_bytes = config.get_session_scrollback_bytes();
+                                                let created = match start {
+                                                    Ok(start) => {
+                                                        create_pty_session(info.clone(), start, scrollback_bytes, &state, tx.clone()).await
+                                                    }
+                                                    Err(e) => Err(e.to_string().into()),
+                                                };
+                                                match created {
+                                                    Ok(_) => {
+                                                        log::info!("PTY session created successfully: {}", session_id);
+                                                        audit_log.record_as(
+                                                            &actor,
+                                                            "terminal.create",
+                                                            "WS /ws".to_string(),
+                                                            serde_json::json!({
+                                                                "session_id": session_id,
+                                                                "label": info.label,
+                                                                "runbook_path": info.runbook_path,
+                                                                "workspace": info.workspace,
+                                                                "branch": info.branch,
+                                                            }),
+                                                        );
+                                                        state.events.publish(events::Event::SessionOpened {
+                                                            session_id: session_id.clone(),
+                                                            label: info.label.clone(),
+                                                            token: actor.token.clone(),
+                                                        });
+                                                        let resp = WsResponse::Created {
+                                                            session_id: session_id.clone(),
+                                                            label: info.label,
+                                                            runbook_path: info.runbook_path,
+                                                        };
+                                                        let resp_json = serde_json::to_string(&resp).unwrap();
+                                                        log::debug!("Sending response: {}", resp_json);
+                                                        if let Err(e) = session.text(resp_json).await {
+                                                            log::error!("Failed to send created response: {:?}", e);
+                                                        }
+                                                    }
+                                                    Err(e) => {
+                                                        log::error!("Failed to create PTY session: {:?}", e);
+                                                        let resp = WsResponse::Error {
+                                                            message: e.to_string(),
+                                                        };
+                                                        let _ = session
+                                                            .text(serde_json::to_string(&resp).unwrap())
+                                                            .await;
+                                                    }
+                                                }
+                                            }
+                                            WsMessage::Input { session_id, data } => {
+                                                log::trace!("Input for session {}: {} bytes", session_id, data.len());
+                                                let mut sessions = state.sessions.lock().await;
+                                                if let Some(pty_session) = sessions.get_mut(&session_id).filter(|s| allowed(&s.info)) {
+                                                    if let Err(e) = pty_session.writer.write_all(data.as_bytes()) {
+                                                        log::error!("Failed to write to PTY: {:?}", e);
+                                                    }
+                                                    if let Err(e) = pty_session.writer.flush() {
+                                                        log::error!("Failed to flush PTY: {:?}", e);
+                                                    }
+                                                } else {
+                                                    log::warn!("Session not found: {}", session_id);
+                                                }
+                                            }
+                                            WsMessage::Resize { session_id, cols, rows } => {
+                                                log::debug!("Resize session {} to {}x{}", session_id, cols, rows);
+                                                let sessions = state.sessions.lock().await;
+                                                if let Some(pty_session) = sessions.get(&session_id).filter(|s| allowed(&s.info)) {
+                                                    if let Err(e) = pty_session.master.resize(PtySize {
+                                                        rows,
+                                                        cols,
+                                                        pixel_width: 0,
+                                                        pixel_height: 0,
+                                                    }) {
+                                                        log::error!("Failed to resize PTY: {:?}", e);
+                                                    }
+                                                }
+                                            }
+                                            WsMessage::Close { session_id, attach_transcript } => {
+                                                log::info!("Closing session: {}", session_id);
+                                                let closed = {
+                                                    let mut sessions = state.sessions.lock().await;
+                                                    match sessions.get(&session_id) {
+                                                        Some(pty_session) if !allowed(&pty_session.info) => None,
+                                                        _ => sessions.remove(&session_id),
+                                                    }
+                                                };
+                                                let mut transcript = None;
+                                                if let Some(mut pty_session) = closed.filter(|_| attach_transcript) {
+                                                    let exit_code = pty_session.exit_code();
+                                                    let (section, truncated) = {
+                                                        let recorded = pty_session.transcript.lock().unwrap();
+                                                        let section = sessions::transcript_section(
+                                                            &pty_session.info,
+                                                            &recorded,
+                                                            chrono::Utc::now(),
+                                                            exit_code,
+                                                        );
+                                                        (section, recorded.omitted() > 0)
+                                                    };
+                                                    let info = pty_session.info.clone();
+                                                    // Hang up on the shell before the file work
+                                                    drop(pty_session);
+                                                    let attached =
+                                                        sessions::attach_transcript(&config, &state, &info, section, truncated).await;
+                                                    match attached {
+                                                        Ok(attached) => {
+                                                            audit_log.record_as(
+                                                                &actor,
+                                                                "terminal.transcript",
+                                                                "WS /ws".to_string(),
+                                                                serde_json::json!({
+                                                                    "session_id": session_id,
+                                                                    "workspace": info.workspace,
+                                                                    "branch": info.branch,
+                                                                    "path": attached.path,
+                                                                    "bytes": attached.bytes,
+                                                                    "exit_code": exit_code,
+                                                                }),
+                                                            );
+                                                            transcript = Some(attached);
+                                                        }
+                                                        Err(e) => {
+                                                            log::warn!("Failed to attach transcript of {}: {}", session_id, e);
+                                                            let resp = WsResponse::Error {
+                                                                message: format!("Transcript not attached: {}", e),
+                                                            };
+                                                            let _ = session.text(serde_json::to_string(&resp).unwrap()).await;
+                                                        }
+                                                    }
+                                                }
+                                                let resp = WsResponse::Closed {
+                                                    session_id,
+                                                    transcript,
+                                                    reason: None,
+                                                };
+                                                let _ = session.text(serde_json::to_string(&resp).unwrap()).await;
+                                            }
+                                            WsMessage::Relabel { session_id, label } => {
+                                                let label = label.as_deref().and_then(sessions::clean_label);
+                                                let previous = {
+                                                    let mut sessions = state.sessions.lock().await;
+                                                    sessions.get_mut(&session_id).filter(|s| allowed(&s.info)).map(|pty_session| {
+                                                        std::mem::replace(&mut pty_session.info.label, label.clone())
+                                                    })
+                                                };
+                                                let resp = match previous {
+                                                    Some(previous) => {
+                                                        log::info!("Relabeled session {}: {:?} -> {:?}", session_id, previous, label);
+                                                        audit_log.record_as(
+                                                            &actor,
+                                                            "terminal.relabel",
+                                                            "WS /ws".to_string(),
+                                                            serde_json::json!({
+                                                                "session_id": session_id,
+                                                                "label": label,
+                                                                "previous_label": previous,
+                                                            }),
+                                                        );
+                                                        WsResponse::Relabeled { session_id, label }
+                                                    }
+                                                    None => WsResponse::Error {
+                                                        message: format!("Terminal session '{}' not found", session_id),
+                                                    },
+                                                };
+                                                let _ = session.text(serde_json::to_string(&resp).unwrap()).await;
+                                            }
+                                        }
+                                    })
+                                    .await;
+                                }
+                                Err(e) => {
+                                    log::error!("Failed to parse WS message: {:?}", e);
+                                }
+                            }
+                        }
+                        actix_ws::Message::Binary(data) => {
+                            log::debug!("Received binary message: {} bytes", data.len());
+                        }
+                        actix_ws::Message::Ping(data) => {
+                            log::trace!("Received ping");
+                            let _ = session.pong(&data).await;
+                        }
+                        actix_ws::Message::Pong(_) => {
+                            log::trace!("Received pong");
+                        }
+                        actix_ws::Message::Close(reason) => {
+                            log::info!("WebSocket close received: {:?}", reason);
+                            break;
+                        }
+                        _ => {
+                            log::debug!("Received other message type");
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("WebSocket receive error: {:?}", e);
+                    break;
+                }
+            }
+        }
+        forwarder.abort();
+        log::info!("WebSocket receiver task ended");
+    }));
+
+    Ok(response)
+}
+
+/// Open a terminal session; `start` is the directory to start the shell in
+/// and variables to set, for sessions opened for a workspace, and
+/// `scrollback_bytes` the latest output kept of it
+async fn create_pty_session(
+    info: sessions::SessionInfo,
+    start: Option<(std::path::PathBuf, Vec<(String, String)>)>,
+    scrollback_bytes: usize,
+    state: &Arc<AppState>,
+    tx: mpsc::UnboundedSender<terminal_output::Outgoing>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let session_id = info.session_id.as_str();
+    log::debug!("Initializing PTY system");
+    let pty_system = NativePtySystem::default();
+
+    log::debug!("Opening PTY pair");
+    let pair = pty_system.openpty(PtySize {
+        rows: 24,
+        cols: 80,
+        pixel_width: 0,
+        pixel_height: 0,
+    })?;
+
+    log::debug!("Building command");
+    let mut cmd = CommandBuilder::new_default_prog();
+    if let Some((cwd, env)) = start {
+        cmd.cwd(cwd);
+        for (name, value) in env {
+            cmd.env(name, value);
+        }
+    }
+    log::info!("Spawning shell process");
+    let child = pair.slave.spawn_command(cmd)?;
+
+    log::debug!("Getting PTY writer and reader");
+    let writer = pair.master.take_writer()?;
+    let mut reader = pair.master.try_clone_reader()?;
+
+    let session_id_clone = session_id.to_string();
+    let output = tx.clone();
+    let transcript = Arc::new(std::sync::Mutex::new(sessions::Transcript::default()));
+    let recording = transcript.clone();
+    let scrollback =
+        (scrollback_bytes > 0).then(|| Arc::new(std::sync::Mutex::new(sessions::Scrollback::new(scrollback_bytes))));
+    let scrolling = scrollback.clone();
+    let log_ctx = logging::current().unwrap_or_default().with_session(session_id);
+
+    // Spawn blocking task to read from PTY. Its output is never logged beyond
+    // byte counts at trace level: it is whatever the terminal shows.
+    log::debug!("Starting PTY reader thread for session {}", session_id);
+    std::thread::spawn(move || logging::with_context(Some(log_ctx), || {
+        log::debug!("PTY reader thread started for {}", session_id_clone);
+        let mut buf = [0u8; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => {
+                    log::info!("PTY EOF for session {}", session_id_clone);
+                    break;
+                }
+                Ok(n) => {
+                    recording.lock().unwrap().push(&buf[..n]);
+                    if let Some(scrolling) = &scrolling {
+                        scrolling.lock().unwrap().push(&buf[..n]);
+                    }
+                    let data = String::from_utf8_lossy(&buf[..n]).to_string();
+                    log::trace!("PTY output for {}: {} bytes", session_id_clone, n);
+                    let chunk = terminal_output::Outgoing::Output {
+                        session_id: session_id_clone.clone(),
+                        data,
+                    };
+                    if tx.send(chunk).is_err() {
+                        log::warn!("Failed to send PTY output, channel closed");
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::error!("PTY read error for {}: {:?}", session_id_clone, e);
+                    break;
+                }
+            }
+        }
+        log::debug!("PTY reader thread ended for {}", session_id_clone);
+    }));
+
+    let pty_session = PtySession {
+        writer,
+        master: pair.master,
+        child,
+        output,
+        info: info.clone(),
+        transcript,
+        scrollback,
+    };
+
+    state
+        .sessions
+        .lock()
+        .await
+        .insert(session_id.to_string(), pty_session);
+
+    log::info!("PTY session {} registered", session_id);
+    Ok(())
+}
+
+/// The application with every route, middleware and shared state; `main`
+/// serves it and the integration tests call it directly
+pub fn app_factory(
+    config: Arc<ConfigManager>,
+    state: Arc<AppState>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let max_request_bytes = config.get_max_request_bytes();
+    let max_save_bytes = config.get_max_save_bytes();
+    let compress = config.get_compress_responses();
+    // `state` moves into app data below
+    let ui = assets::configure(&state.assets);
+
+    App::new()
+        .wrap(extract::payload_errors())
+        .wrap(caching::UiCacheControl)
+        // Inside RequireAuth, which tells it the token
+        .wrap(rate_limit::RateLimit)
+        .wrap(auth::RequireAuth)
+        .wrap(middleware::Condition::new(compress, middleware::Compress::default()))
+        .wrap(logging::RequestId)
+        // Query strings are left out: tokens can be passed as `?token=`
+        .wrap(
+            middleware::Logger::new(
+                r#"%a "%{request_line}xi" %s %b "%{referer}xi" "%{User-Agent}i" %T req=%{X-Request-Id}o"#,
+            )
+            .custom_request_replace("request_line", |req| {
+                format!("{} {} {:?}", req.method(), req.path(), req.version())
+            })
+            .custom_request_replace("referer", |req| {
+                req.headers()
+                    .get(actix_web::http::header::REFERER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|referer| referer.split(['?', '#']).next().unwrap_or_default().to_string())
+                    .unwrap_or_else(|| "-".to_string())
+            }),
+        )
+        .app_data(web::Data::new(state.watcher.clone()))
+        .app_data(web::Data::new(state.events.clone()))
+        .app_data(web::Data::new(state.audit_log.clone()))
+        .app_data(web::Data::new(state))
+        .app_data(web::Data::new(config))
+        .app_data(extract::json_config(max_request_bytes))
+        .app_data(extract::payload_config(max_request_bytes))
+        .app_data(extract::query_config())
+        // WebSocket endpoint
+        .route("/ws", web::get().to(ws_handler))
+        // Unversioned before the aliases, which would otherwise claim it
+        .route("/api/version", web::get().to(api::version))
+        // Readiness probe; needs no token
+        .route("/readyz", web::get().to(diagnostics::readyz))
+        // Worktree files for external tools; needs a token like /api
+        .route("/raw/{workspace}/{branch}/{path:.*}", web::get().to(raw::serve))
+        .service(web::scope(api::V1_PREFIX).configure(api::routes(max_save_bytes)))
+        .service(
+            web::scope(api::LEGACY_PREFIX)
+                .wrap(api::Deprecated)
+                .configure(api::routes(max_save_bytes)),
+        )
+        // Static files (must be last)
+        .configure(ui)
+}
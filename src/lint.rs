@@ -0,0 +1,206 @@
+//! Checks of the links, images and code blocks of runbooks, so a link to a
+//! runbook that was moved or renamed is found before someone follows it
+//! mid-incident. Files are parsed with `blocks::parse`, like the blocks
+//! endpoint does.
+
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::blocks::{self, Heading};
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::file_ops;
+use crate::logging;
+use crate::workspace;
+
+const DEFAULT_LINT_LIMIT: usize = 500;
+const MAX_LINT_LIMIT: usize = 5000;
+
+/// A problem found in a runbook
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    pub path: String,
+    pub line: usize,
+    /// `broken_link`, `broken_anchor`, `missing_image` or `empty_code_block`
+    pub rule: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LintResults {
+    pub problems: Vec<Problem>,
+    pub files_checked: usize,
+    /// More problems were found than the limit
+    pub truncated: bool,
+}
+
+/// Anchors of `headings`, to look links up in
+fn anchors(headings: &[Heading]) -> HashSet<String> {
+    blocks::heading_anchors(headings).into_iter().collect()
+}
+
+fn is_markdown(path: &str) -> bool {
+    path.ends_with(".md") || path.ends_with(".markdown")
+}
+
+struct Linter<'a> {
+    worktree_path: &'a Path,
+    max_bytes: u64,
+    /// Anchors of the files read so far; None for those that can't be read
+    anchors: HashMap<String, Option<HashSet<String>>>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(worktree_path: &'a Path, max_bytes: u64) -> Self {
+        Self {
+            worktree_path,
+            max_bytes,
+            anchors: HashMap::new(),
+        }
+    }
+
+    fn anchors_of(&mut self, path: &str) -> Option<&HashSet<String>> {
+        if !self.anchors.contains_key(path) {
+            let anchors = file_ops::read_file(self.worktree_path, path, self.max_bytes)
+                .ok()
+                .map(|content| anchors(&blocks::parse(&content).headings));
+            self.anchors.insert(path.to_string(), anchors);
+        }
+        self.anchors.get(path).and_then(Option::as_ref)
+    }
+
+    /// Problems of the runbook at `path` holding `content`, by line
+    fn lint(&mut self, path: &str, content: &str) -> Vec<Problem> {
+        let runbook = blocks::parse(content);
+        self.anchors.insert(path.to_string(), Some(anchors(&runbook.headings)));
+        let mut problems = Vec::new();
+        let mut report = |line: usize, rule: &'static str, message: String| {
+            problems.push(Problem {
+                path: path.to_string(),
+                line,
+                rule,
+                message,
+            })
+        };
+
+        for block in &runbook.blocks {
+            if block.code.trim().is_empty() {
+                report(block.start_line, "empty_code_block", "Code block is empty".to_string());
+            }
+        }
+
+        for link in &runbook.links {
+            if link.dest.is_empty() || blocks::is_url(&link.dest) {
+                continue;
+            }
+            let (target, anchor) = match link.dest.split_once('#') {
+                Some((target, anchor)) => (target, Some(blocks::percent_decode(anchor))),
+                None => (link.dest.as_str(), None),
+            };
+            let missing_rule = if link.image { "missing_image" } else { "broken_link" };
+            let target = if target.split('?').next().unwrap_or_default().is_empty() {
+                path.to_string()
+            } else {
+                let resolved = blocks::link_path(path, &blocks::percent_decode(target))
+                    .filter(|resolved| file_ops::resolve_path(self.worktree_path, resolved).is_ok());
+                match resolved {
+                    Some(resolved) => resolved,
+                    None => {
+                        report(
+                            link.line,
+                            missing_rule,
+                            format!("'{}' points at a file that doesn't exist in the worktree", link.dest),
+                        );
+                        continue;
+                    }
+                }
+            };
+            let Some(anchor) = anchor.filter(|anchor| !anchor.is_empty() && !link.image) else {
+                continue;
+            };
+            if !is_markdown(&target) {
+                continue;
+            }
+            if self.anchors_of(&target).is_some_and(|anchors| !anchors.contains(&anchor.to_lowercase())) {
+                report(
+                    link.line,
+                    "broken_anchor",
+                    format!("No heading in '{}' has the anchor '#{}'", target, anchor),
+                );
+            }
+        }
+
+        problems.sort_by_key(|problem| problem.line);
+        problems
+    }
+}
+
+/// Lint every runbook of a worktree, stopping once more than `limit`
+/// problems are found
+fn lint_tree(worktree_path: &Path, max_bytes: u64, limit: usize) -> std::io::Result<LintResults> {
+    let mut linter = Linter::new(worktree_path, max_bytes);
+    let mut results = LintResults {
+        problems: Vec::new(),
+        files_checked: 0,
+        truncated: false,
+    };
+    for path in file_ops::list_file_paths(worktree_path)? {
+        let content = match file_ops::read_file(worktree_path, &path, max_bytes) {
+            Ok(content) => content,
+            Err(e) => {
+                log::debug!("Not linting {}: {}", path, e);
+                continue;
+            }
+        };
+        results.problems.extend(linter.lint(&path, &content));
+        results.files_checked += 1;
+        if results.problems.len() > limit {
+            results.problems.truncate(limit);
+            results.truncated = true;
+            break;
+        }
+    }
+    Ok(results)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LintQuery {
+    /// Only this file; every runbook of the worktree when absent
+    pub path: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/lint?path=x - Broken links,
+/// anchors and images and empty code blocks of a runbook, or of every
+/// runbook in the worktree
+pub async fn lint(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<LintQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let limit = query.limit.unwrap_or(DEFAULT_LINT_LIMIT).min(MAX_LINT_LIMIT);
+    let max_bytes = config.get_max_read_bytes();
+
+    let results = match &query.path {
+        Some(file_path) => {
+            let content = workspace::read_text_file(&config, &worktree_path, file_path)?;
+            let mut problems = Linter::new(&worktree_path, max_bytes).lint(file_path, &content);
+            let truncated = problems.len() > limit;
+            problems.truncate(limit);
+            LintResults {
+                problems,
+                files_checked: 1,
+                truncated,
+            }
+        }
+        None => logging::block(move || lint_tree(&worktree_path, max_bytes, limit))
+            .await?
+            .map_err(|e| ApiError::io("Failed to list files", e))?,
+    };
+    Ok(HttpResponse::Ok().json(results))
+}
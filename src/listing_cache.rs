@@ -0,0 +1,149 @@
+//! Short-lived copies of worktree file trees and workspace branch lists,
+//! which the UI polls every few seconds: walking a big worktree or asking
+//! git about every branch on each poll keeps the workers busy. Entries
+//! expire after `listing_cache_ttl_secs`, and are dropped as soon as the
+//! API changes a worktree or its branches, or the file watcher sees a
+//! change made some other way.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::file_ops::FileEntry;
+use crate::workspace::BranchInfo;
+
+struct Cached<T> {
+    value: Arc<T>,
+    stored: Instant,
+    /// What the listing was loaded from, if that can be checked cheaply
+    stamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TreeKey {
+    workspace: String,
+    branch: String,
+    /// Whether the entries carry their front matter
+    frontmatter: bool,
+}
+
+pub struct ListingCache {
+    /// Zero turns caching off
+    ttl: Duration,
+    trees: Mutex<HashMap<TreeKey, Cached<Vec<FileEntry>>>>,
+    /// Stamped with `git_ops::refs_stamp`, since checkouts and commits in a
+    /// terminal touch the git dir, which the watcher doesn't see
+    branches: Mutex<HashMap<String, Cached<Vec<BranchInfo>>>>,
+    /// Bumped by every invalidation, so a listing loaded while something
+    /// changed isn't stored
+    generation: AtomicU64,
+}
+
+impl ListingCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            trees: Mutex::new(HashMap::new()),
+            branches: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// To pass to `store_*` along with a listing loaded after calling this
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn fresh<T>(&self, cached: Option<&Cached<T>>) -> Option<Arc<T>> {
+        cached
+            .filter(|cached| cached.stored.elapsed() < self.ttl)
+            .map(|cached| cached.value.clone())
+    }
+
+    fn storable(&self, generation: u64) -> bool {
+        !self.ttl.is_zero() && generation == self.generation()
+    }
+
+    pub fn tree(&self, workspace: &str, branch: &str, frontmatter: bool) -> Option<Arc<Vec<FileEntry>>> {
+        let key = TreeKey {
+            workspace: workspace.to_string(),
+            branch: branch.to_string(),
+            frontmatter,
+        };
+        self.fresh(self.trees.lock().unwrap().get(&key))
+    }
+
+    pub fn store_tree(
+        &self,
+        generation: u64,
+        workspace: &str,
+        branch: &str,
+        frontmatter: bool,
+        files: Arc<Vec<FileEntry>>,
+    ) {
+        let mut trees = self.trees.lock().unwrap();
+        if !self.storable(generation) {
+            return;
+        }
+        trees.retain(|_, cached| cached.stored.elapsed() < self.ttl);
+        let key = TreeKey {
+            workspace: workspace.to_string(),
+            branch: branch.to_string(),
+            frontmatter,
+        };
+        trees.insert(
+            key,
+            Cached {
+                value: files,
+                stored: Instant::now(),
+                stamp: 0,
+            },
+        );
+    }
+
+    /// The branch list of `workspace`, if it was stored with `stamp`
+    pub fn branches(&self, workspace: &str, stamp: u64) -> Option<Arc<Vec<BranchInfo>>> {
+        let branches = self.branches.lock().unwrap();
+        self.fresh(branches.get(workspace).filter(|cached| cached.stamp == stamp))
+    }
+
+    pub fn store_branches(&self, generation: u64, workspace: &str, stamp: u64, branches: Arc<Vec<BranchInfo>>) {
+        let mut cached = self.branches.lock().unwrap();
+        if !self.storable(generation) {
+            return;
+        }
+        cached.retain(|_, cached| cached.stored.elapsed() < self.ttl);
+        cached.insert(
+            workspace.to_string(),
+            Cached {
+                value: branches,
+                stored: Instant::now(),
+                stamp,
+            },
+        );
+    }
+
+    /// Drop the file trees of a worktree, after its files changed
+    pub fn invalidate_tree(&self, workspace: &str, branch: &str) {
+        let mut trees = self.trees.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        trees.retain(|key, _| key.workspace != workspace || key.branch != branch);
+    }
+
+    /// Drop a worktree's file trees and its workspace's branch list, after
+    /// a commit, pull or other change to what it has checked out
+    pub fn invalidate_worktree(&self, workspace: &str, branch: &str) {
+        self.invalidate_tree(workspace, branch);
+        self.branches.lock().unwrap().remove(workspace);
+    }
+
+    /// Drop everything cached about a workspace
+    pub fn invalidate_workspace(&self, workspace: &str) {
+        let mut trees = self.trees.lock().unwrap();
+        let mut branches = self.branches.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        trees.retain(|key, _| key.workspace != workspace);
+        branches.remove(workspace);
+    }
+}
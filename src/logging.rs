@@ -0,0 +1,246 @@
+//! Log output setup and the context attached to log lines.
+//!
+//! Every HTTP request gets an ID (`X-Request-Id`); terminal sessions are
+//! identified by their session ID. Whatever is logged while handling one of
+//! them carries that ID, in text output as a `[req=...]`/`[session=...]`
+//! prefix and in JSON output as separate fields.
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::Write;
+use log::Log;
+use std::sync::OnceLock;
+
+use crate::server_log;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// `[timestamp LEVEL target] message`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// IDs of the request or terminal session a log line belongs to
+#[derive(Debug, Clone, Default)]
+pub struct LogContext {
+    pub request_id: Option<String>,
+    pub session_id: Option<String>,
+}
+
+impl LogContext {
+    pub fn request(request_id: impl Into<String>) -> Self {
+        Self {
+            request_id: Some(request_id.into()),
+            session_id: None,
+        }
+    }
+
+    pub fn with_session(&self, session_id: impl Into<String>) -> Self {
+        Self {
+            request_id: self.request_id.clone(),
+            session_id: Some(session_id.into()),
+        }
+    }
+}
+
+tokio::task_local! {
+    static TASK_CONTEXT: LogContext;
+}
+
+thread_local! {
+    // For plain threads (PTY readers, web::block), which task-locals don't reach
+    static THREAD_CONTEXT: RefCell<Option<LogContext>> = const { RefCell::new(None) };
+}
+
+/// The context of the running task or thread, if any
+pub fn current() -> Option<LogContext> {
+    TASK_CONTEXT
+        .try_with(|ctx| ctx.clone())
+        .ok()
+        .or_else(|| THREAD_CONTEXT.with(|ctx| ctx.borrow().clone()))
+}
+
+/// Run `fut` with `ctx` attached to its log lines
+pub async fn scope<F: Future>(ctx: LogContext, fut: F) -> F::Output {
+    TASK_CONTEXT.scope(ctx, fut).await
+}
+
+/// Run `f` on this thread with `ctx` attached to its log lines
+pub fn with_context<R>(ctx: Option<LogContext>, f: impl FnOnce() -> R) -> R {
+    let previous = THREAD_CONTEXT.with(|current| current.replace(ctx));
+    let result = f();
+    THREAD_CONTEXT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// `web::block` that keeps the caller's context for the blocking closure
+pub async fn block<F, R>(f: F) -> Result<R, actix_web::error::BlockingError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let ctx = current();
+    actix_web::web::block(move || with_context(ctx, f)).await
+}
+
+/// The installed logger: output per `init`'s filter and format, teed into
+/// the admin log capture
+struct Logger {
+    output: env_logger::Logger,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.output.enabled(metadata) || metadata.level() <= server_log::capture().level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.output.log(record);
+        server_log::capture().record(record);
+    }
+
+    fn flush(&self) {
+        self.output.flush();
+    }
+}
+
+/// Log to the output only, never to the admin log capture; for lines that
+/// may carry what it must not show
+pub fn log_uncaptured(level: log::Level, target: &str, args: std::fmt::Arguments) {
+    if let Some(logger) = LOGGER.get() {
+        logger
+            .output
+            .log(&log::Record::builder().level(level).target(target).args(args).build());
+    }
+}
+
+/// Install the global logger. `filter` uses `RUST_LOG` syntax and falls back
+/// to `RUST_LOG`, then `debug`.
+pub fn init(filter: Option<&str>, format: LogFormat) {
+    let mut builder = match filter {
+        Some(filter) => {
+            let mut builder = env_logger::Builder::new();
+            builder.parse_filters(filter);
+            builder
+        }
+        None => env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug")),
+    };
+
+    match format {
+        LogFormat::Text => builder.format(|buf, record| {
+            let mut prefix = String::new();
+            if let Some(ctx) = current() {
+                if let Some(id) = &ctx.request_id {
+                    prefix.push_str(&format!("[req={}] ", id));
+                }
+                if let Some(id) = &ctx.session_id {
+                    prefix.push_str(&format!("[session={}] ", id));
+                }
+            }
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}{}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                prefix,
+                record.args()
+            )
+        }),
+        LogFormat::Json => builder.format(|buf, record| {
+            let ctx = current().unwrap_or_default();
+            let line = serde_json::json!({
+                "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+                "request_id": ctx.request_id,
+                "session_id": ctx.session_id,
+            });
+            writeln!(buf, "{}", line)
+        }),
+    };
+    let output = builder.build();
+    let max_level = output.filter().max(server_log::capture().level());
+    if LOGGER.set(Logger { output }).is_err() {
+        panic!("logging::init called twice");
+    }
+    log::set_logger(LOGGER.get().unwrap()).expect("no other logger is installed");
+    log::set_max_level(max_level);
+}
+
+/// Accept a client-supplied ID only if it is short and plain enough to log
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Middleware giving each request an ID: the client's `X-Request-Id` if it
+/// sent a usable one, otherwise a new UUID. The ID is attached to the log
+/// lines of everything handling the request and echoed back in the
+/// response's `X-Request-Id` header.
+pub struct RequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddleware { service }))
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| valid_request_id(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+        let ctx = LogContext::request(request_id.clone());
+        // Inner middleware (auth) does part of its work in `call` itself
+        let fut = TASK_CONTEXT.sync_scope(ctx.clone(), || self.service.call(req));
+        Box::pin(async move {
+            let mut res = TASK_CONTEXT.scope(ctx, fut).await?;
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        })
+    }
+}
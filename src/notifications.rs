@@ -0,0 +1,266 @@
+//! Outbound webhooks: events from the event bus are POSTed as JSON to the
+//! webhooks in the `notifications` section of the config. Each webhook has
+//! its own thread and subscription, so a slow receiver only delays its own
+//! deliveries and never a request; one that falls too far behind misses
+//! events, which are counted as dropped.
+//!
+//! The body is `{"id", "event", "timestamp", "text", "data"}`, where `text`
+//! is a one-line summary (enough for a Slack incoming webhook) and `data` is
+//! the event as `GET /api/events` streams it. With a `secret`, the
+//! `X-Runotepad-Signature` header holds `sha256=<hex HMAC-SHA256 of the
+//! body>`.
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::config::{NotificationsConfig, WebhookConfig};
+use crate::events::{Event, EventBus};
+use crate::AppState;
+
+pub const SIGNATURE_HEADER: &str = "X-Runotepad-Signature";
+pub const EVENT_HEADER: &str = "X-Runotepad-Event";
+pub const DELIVERY_HEADER: &str = "X-Runotepad-Delivery";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// Wait before the second attempt; doubled for each one after
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Name of `event` for webhook filters, or None for events that aren't sent
+/// (job progress and file changes are too chatty)
+pub fn event_name(event: &Event) -> Option<&'static str> {
+    Some(match event {
+        Event::Job { .. } | Event::FileChanged(_) => return None,
+        Event::WorkspaceCreated { .. } => "workspace_created",
+        Event::WorkspaceUpdated { .. } => "workspace_updated",
+        Event::WorkspaceRenamed { .. } => "workspace_renamed",
+        Event::WorkspaceDeleted { .. } => "workspace_deleted",
+        Event::BranchCreated { .. } => "branch_created",
+        Event::BranchDeleted { .. } => "branch_deleted",
+        Event::BranchRenamed { .. } => "branch_renamed",
+        Event::Git { operation, .. } => match *operation {
+            "commit" => "branch_committed",
+            "push" => "branch_pushed",
+            "pull" => "branch_pulled",
+            "rebase" => "branch_rebased",
+            "fetch" => "workspace_fetched",
+            _ => return None,
+        },
+        Event::BlockExecuted { .. } => "block_executed",
+        Event::SessionOpened { .. } => "session_opened",
+        Event::ScheduleFailed { .. } => "schedule_failed",
+    })
+}
+
+/// One line describing `event`, for chat receivers
+fn summary(event: &Event) -> String {
+    let who = |token: &Option<String>| token.clone().unwrap_or_else(|| "someone".to_string());
+    match event {
+        Event::Job { job } => format!("Job {} is {:?}", job.id, job.state),
+        Event::FileChanged(change) => format!("{} changed in {}/{}", change.path, change.workspace, change.branch),
+        Event::WorkspaceCreated { workspace } => format!("Workspace {} was created", workspace),
+        Event::WorkspaceUpdated { workspace } => format!("Workspace {} was updated", workspace),
+        Event::WorkspaceRenamed { workspace, new_name } => {
+            format!("Workspace {} was renamed to {}", workspace, new_name)
+        }
+        Event::WorkspaceDeleted { workspace } => format!("Workspace {} was deleted", workspace),
+        Event::BranchCreated { workspace, branch } => format!("Branch {} was created in {}", branch, workspace),
+        Event::BranchDeleted { workspace, branch } => format!("Branch {} was deleted in {}", branch, workspace),
+        Event::BranchRenamed { workspace, branch, new_name } => {
+            format!("Branch {} was renamed to {} in {}", branch, new_name, workspace)
+        }
+        Event::Git { workspace, branch, operation, .. } => match branch {
+            Some(branch) => format!("{} finished on {}/{}", operation, workspace, branch),
+            None => format!("{} finished on {}", operation, workspace),
+        },
+        Event::BlockExecuted { workspace, branch, path, block_index, token, .. } => format!(
+            "{} is running block {} of {} in {}/{}",
+            who(token),
+            block_index,
+            path,
+            workspace,
+            branch
+        ),
+        Event::SessionOpened { session_id, label, token } => match label {
+            Some(label) => format!("{} opened terminal session {} ({})", who(token), label, session_id),
+            None => format!("{} opened terminal session {}", who(token), session_id),
+        },
+        Event::ScheduleFailed { schedule_id, workspace, branch, path, block_index, exit_code, error, .. } => {
+            let step = block_index.map(|index| format!(" at block {}", index)).unwrap_or_default();
+            let cause = match (exit_code, error) {
+                (_, Some(error)) => error.clone(),
+                (Some(code), None) => format!("exit code {}", code),
+                (None, None) => "no exit code".to_string(),
+            };
+            format!("Schedule {} of {} in {}/{} failed{}: {}", schedule_id, path, workspace, branch, step, cause)
+        }
+    }
+}
+
+/// `sha256=<hex HMAC-SHA256 of body>`
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Deliveries to one webhook so far
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookStatus {
+    pub name: String,
+    pub events: Vec<String>,
+    pub delivered: u64,
+    /// Events given up on after `max_attempts`
+    pub failed: u64,
+    /// Events missed because earlier deliveries were still being retried
+    pub dropped: u64,
+    pub last_delivered_at: Option<DateTime<Utc>>,
+    pub last_failed_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+struct Webhook {
+    config: WebhookConfig,
+    agent: ureq::Agent,
+    max_attempts: u32,
+    status: Arc<Mutex<WebhookStatus>>,
+}
+
+impl Webhook {
+    fn wants(&self, name: &str, event: &Event) -> bool {
+        let config = &self.config;
+        (config.events.is_empty() || config.events.iter().any(|wanted| wanted == name))
+            && (config.workspaces.is_empty() || config.workspaces.iter().any(|workspace| event.concerns(workspace)))
+    }
+
+    fn post(&self, name: &str, id: &str, body: &str) -> Result<(), String> {
+        let mut request = self
+            .agent
+            .post(&self.config.url)
+            .set("Content-Type", "application/json")
+            .set(EVENT_HEADER, name)
+            .set(DELIVERY_HEADER, id);
+        if let Some(secret) = &self.config.secret {
+            request = request.set(SIGNATURE_HEADER, &signature(secret, body.as_bytes()));
+        }
+        match request.send_string(body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, _)) => Err(format!("Receiver answered {}", code)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Send `event`, retrying with a growing delay
+    fn deliver(&self, name: &str, event: &Event) {
+        let id = Uuid::new_v4().to_string();
+        let body = serde_json::json!({
+            "id": id,
+            "event": name,
+            "timestamp": Utc::now(),
+            "text": summary(event),
+            "data": event,
+        })
+        .to_string();
+
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=self.max_attempts {
+            match self.post(name, &id, &body) {
+                Ok(()) => {
+                    log::debug!("Delivered {} to webhook {}", name, self.config.name);
+                    let mut status = self.status.lock().unwrap();
+                    status.delivered += 1;
+                    status.last_delivered_at = Some(Utc::now());
+                    return;
+                }
+                Err(e) if attempt < self.max_attempts => {
+                    log::debug!("Delivering {} to webhook {} failed ({}); retrying", name, self.config.name, e);
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Giving up delivering {} to webhook {} after {} attempts: {}",
+                        name, self.config.name, attempt, e
+                    );
+                    let mut status = self.status.lock().unwrap();
+                    status.failed += 1;
+                    status.last_failed_at = Some(Utc::now());
+                    status.last_error = Some(e);
+                }
+            }
+        }
+    }
+
+    fn run(self, mut events: broadcast::Receiver<Event>) {
+        loop {
+            match events.blocking_recv() {
+                Ok(event) => {
+                    if let Some(name) = event_name(&event).filter(|name| self.wants(name, &event)) {
+                        self.deliver(name, &event);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("Webhook {} fell {} events behind; they are not sent", self.config.name, missed);
+                    self.status.lock().unwrap().dropped += missed;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+/// The webhooks' delivery threads and their status
+pub struct Notifier {
+    statuses: Vec<Arc<Mutex<WebhookStatus>>>,
+}
+
+impl Notifier {
+    /// Start delivering `events` to the configured webhooks; the threads
+    /// stop when the event bus is dropped
+    pub fn start(config: NotificationsConfig, events: &EventBus) -> Self {
+        let timeout = config.timeout_secs.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+        let max_attempts = config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1);
+        let mut statuses = Vec::new();
+        for webhook in config.webhooks {
+            let status = Arc::new(Mutex::new(WebhookStatus {
+                name: webhook.name.clone(),
+                events: webhook.events.clone(),
+                delivered: 0,
+                failed: 0,
+                dropped: 0,
+                last_delivered_at: None,
+                last_failed_at: None,
+                last_error: None,
+            }));
+            statuses.push(status.clone());
+            let webhook = Webhook {
+                config: webhook,
+                agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+                max_attempts,
+                status,
+            };
+            let receiver = events.subscribe();
+            std::thread::spawn(move || webhook.run(receiver));
+        }
+        Self { statuses }
+    }
+
+    pub fn status(&self) -> Vec<WebhookStatus> {
+        self.statuses.iter().map(|status| status.lock().unwrap().clone()).collect()
+    }
+}
+
+/// GET /api/notifications/status - Delivery counts per webhook
+pub async fn notifications_status(state: web::Data<Arc<AppState>>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "webhooks": state.notifier.status() }))
+}
@@ -0,0 +1,220 @@
+//! Edits applied to a file's text, so an autosave can send what changed
+//! instead of the whole document. Edits are byte ranges of the content as
+//! the file read returned it, or a unified diff against it, which is turned
+//! into the same edits.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+/// Replace `start_byte..end_byte` of the content with `replacement`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Edit {
+    pub start_byte: usize,
+    pub end_byte: usize,
+    pub replacement: String,
+}
+
+/// Why the edit (or diff hunk) at `index` can't be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchError {
+    pub index: usize,
+    pub message: String,
+}
+
+impl PatchError {
+    fn edit(index: usize, problem: impl std::fmt::Display) -> Self {
+        PatchError {
+            index,
+            message: format!("Edit {} {}", index, problem),
+        }
+    }
+
+    fn hunk(index: usize, problem: impl std::fmt::Display) -> Self {
+        PatchError {
+            index,
+            message: format!("Hunk {} {}", index, problem),
+        }
+    }
+}
+
+/// Apply `edits` to `content`. Edits are in content order and don't
+/// overlap; each range has to start and end on a character boundary.
+/// Insertions at the same position are applied in the order given.
+pub fn apply_edits(content: &str, edits: &[Edit]) -> Result<String, PatchError> {
+    let mut updated = String::with_capacity(content.len());
+    let mut copied = 0;
+    for (index, edit) in edits.iter().enumerate() {
+        if edit.start_byte > edit.end_byte {
+            return Err(PatchError::edit(
+                index,
+                format!("starts at byte {} after it ends at {}", edit.start_byte, edit.end_byte),
+            ));
+        }
+        if edit.end_byte > content.len() {
+            return Err(PatchError::edit(
+                index,
+                format!("ends at byte {} past the end of the {}-byte content", edit.end_byte, content.len()),
+            ));
+        }
+        if edit.start_byte < copied {
+            return Err(PatchError::edit(
+                index,
+                format!("overlaps the edit before it, which ends at byte {}", copied),
+            ));
+        }
+        for offset in [edit.start_byte, edit.end_byte] {
+            if !content.is_char_boundary(offset) {
+                return Err(PatchError::edit(index, format!("byte {} is inside a UTF-8 character", offset)));
+            }
+        }
+        updated.push_str(&content[copied..edit.start_byte]);
+        updated.push_str(&edit.replacement);
+        copied = edit.end_byte;
+    }
+    updated.push_str(&content[copied..]);
+    Ok(updated)
+}
+
+fn hunk_header() -> &'static Regex {
+    static HEADER: OnceLock<Regex> = OnceLock::new();
+    HEADER.get_or_init(|| Regex::new(r"^@@ -(\d+)(?:,(\d+))? \+(\d+)(?:,(\d+))? @@").unwrap())
+}
+
+/// A hunk's lines, with their line endings
+#[derive(Default)]
+struct Hunk {
+    old_start: usize,
+    old_count: usize,
+    new_count: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+/// The hunks of a single-file unified diff. Lines before the first hunk
+/// (`---`/`+++` headers and the like) are skipped.
+fn parse_hunks(diff: &str) -> Result<Vec<Hunk>, PatchError> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    // Whether the last line read was removed (or kept) and added, for the
+    // "\ No newline at end of file" after it
+    let mut last: Option<(bool, bool)> = None;
+    for line in diff.split_inclusive('\n') {
+        if let Some(header) = hunk_header().captures(line) {
+            let number = |i: usize| header.get(i).map_or(Ok(1), |m| m.as_str().parse::<usize>());
+            let index = hunks.len();
+            let invalid = |_| PatchError::hunk(index, "has a line number out of range");
+            hunks.push(Hunk {
+                old_start: number(1).map_err(invalid)?,
+                old_count: number(2).map_err(invalid)?,
+                new_count: number(4).map_err(invalid)?,
+                ..Hunk::default()
+            });
+            last = None;
+            continue;
+        }
+        let index = hunks.len().saturating_sub(1);
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        if line.starts_with('\\') {
+            let Some((old, new)) = last else {
+                return Err(PatchError::hunk(index, "has a '\\' line that doesn't follow a line"));
+            };
+            for (side, lines) in [(old, &mut hunk.old), (new, &mut hunk.new)] {
+                if let Some(line) = lines.last_mut().filter(|_| side) {
+                    line.pop();
+                }
+            }
+            continue;
+        }
+        if hunk.old.len() == hunk.old_count && hunk.new.len() == hunk.new_count {
+            // Past the end of the hunk, e.g. the header of another file
+            if line.starts_with("--- ") || line.starts_with("diff ") {
+                return Err(PatchError::hunk(index, "is followed by a second file; send one file's diff"));
+            }
+            continue;
+        }
+        // Some tools strip the space of a blank context line
+        let (marker, text) = match line {
+            "\n" | "\r\n" => (' ', line),
+            _ => {
+                let marker = line.chars().next().unwrap_or(' ');
+                (marker, &line[marker.len_utf8()..])
+            }
+        };
+        let text = if text.ends_with('\n') { text.to_string() } else { format!("{}\n", text) };
+        let (old, new) = match marker {
+            ' ' => (true, true),
+            '-' => (true, false),
+            '+' => (false, true),
+            _ => return Err(PatchError::hunk(index, format!("has a line starting with '{}'", marker))),
+        };
+        if old {
+            hunk.old.push(text.clone());
+        }
+        if new {
+            hunk.new.push(text);
+        }
+        if hunk.old.len() > hunk.old_count || hunk.new.len() > hunk.new_count {
+            return Err(PatchError::hunk(index, "has more lines than its header says"));
+        }
+        last = Some((old, new));
+    }
+    for (index, hunk) in hunks.iter().enumerate() {
+        if hunk.old.len() != hunk.old_count || hunk.new.len() != hunk.new_count {
+            return Err(PatchError::hunk(index, "has fewer lines than its header says"));
+        }
+    }
+    Ok(hunks)
+}
+
+/// The edits a unified diff of `content` makes. Every hunk has to apply
+/// where its header says, context and all; errors give the hunk's index.
+pub fn diff_edits(content: &str, diff: &str) -> Result<Vec<Edit>, PatchError> {
+    let hunks = parse_hunks(diff)?;
+    if hunks.is_empty() {
+        return Err(PatchError {
+            index: 0,
+            message: "The diff has no hunks".to_string(),
+        });
+    }
+    // Byte offset of the start of every line, and of the end
+    let mut line_starts = vec![0];
+    line_starts.extend(content.match_indices('\n').map(|(offset, _)| offset + 1));
+    if *line_starts.last().unwrap() != content.len() {
+        line_starts.push(content.len());
+    }
+    let line_count = line_starts.len() - 1;
+
+    let mut next_line = 0;
+    hunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, hunk)| {
+            // A hunk removing nothing names the line it comes after
+            let first = if hunk.old_count == 0 { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+            if first < next_line {
+                return Err(PatchError::hunk(index, "overlaps the hunk before it"));
+            }
+            next_line = first + hunk.old_count;
+            if first + hunk.old_count > line_count {
+                return Err(PatchError::hunk(
+                    index,
+                    format!("goes past the end of the {}-line content", line_count),
+                ));
+            }
+            let (start_byte, end_byte) = (line_starts[first], line_starts[first + hunk.old_count]);
+            if content[start_byte..end_byte] != hunk.old.concat() {
+                return Err(PatchError::hunk(
+                    index,
+                    format!("doesn't match the content at line {}", first + 1),
+                ));
+            }
+            Ok(Edit {
+                start_byte,
+                end_byte,
+                replacement: hunk.new.concat(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,135 @@
+//! Disk quotas of workspaces, so one big clone on a shared server can't
+//! fill the disk under everyone's worktrees. `max_workspace_size_bytes`
+//! limits every workspace, and a workspace's `max_size_bytes` overrides it.
+//!
+//! Sizing a workspace means walking all of it, so the size is scanned every
+//! `quota_scan_interval_secs` in the background and cached; saves add what
+//! they write to the cached size meanwhile. A workspace over its quota
+//! can't get new worktrees or file writes (507 `quota_exceeded`) until
+//! files are deleted and it is scanned again, or `POST .../verify` rescans
+//! it. Clones are checked once the repository is on disk, and local
+//! imports before they start.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::file_ops;
+use crate::logging;
+
+/// A workspace's disk space against its quota, as the detail endpoint
+/// shows it
+#[derive(Debug, Serialize)]
+pub struct QuotaUsage {
+    /// None until the workspace has been scanned
+    pub used_bytes: Option<u64>,
+    pub quota_bytes: Option<u64>,
+    pub scanned_at: Option<DateTime<Utc>>,
+    pub exceeded: bool,
+}
+
+/// Rescan the workspaces that have a quota every
+/// `quota_scan_interval_secs` for as long as the server runs
+pub fn start(config: Arc<ConfigManager>) {
+    actix_rt::spawn(async move {
+        let mut interval = tokio::time::interval(config.get_quota_scan_interval());
+        loop {
+            interval.tick().await;
+            let limited: Vec<String> = config
+                .get_workspaces()
+                .into_keys()
+                .filter(|name| config.workspace_quota(name).is_some())
+                .collect();
+            for name in limited {
+                let scan_config = config.clone();
+                let scanned = logging::block(move || {
+                    let usage = scan_config.measure_workspace(&name);
+                    (name, usage)
+                })
+                .await;
+                if let Ok((name, usage)) = scanned {
+                    if let Some(quota) = config.workspace_quota(&name).filter(|quota| usage.bytes > *quota) {
+                        log::warn!("Workspace '{}' takes {} bytes, over its quota of {}", name, usage.bytes, quota);
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn exceeded(workspace: &str, used: u64, quota: u64) -> ApiError {
+    ApiError::insufficient_storage(
+        "quota_exceeded",
+        format!(
+            "Workspace '{}' takes {} bytes, over its quota of {} bytes",
+            workspace, used, quota
+        ),
+    )
+    .with_details(serde_json::json!({ "used_bytes": used, "quota_bytes": quota }))
+}
+
+/// 507 `quota_exceeded` if `workspace` is over its quota, by its last scan
+/// or by scanning it now if it hasn't been yet
+pub fn require_space(config: &ConfigManager, workspace: &str) -> Result<(), ApiError> {
+    let Some(quota) = config.workspace_quota(workspace) else {
+        return Ok(());
+    };
+    let usage = config
+        .workspace_usage(workspace)
+        .unwrap_or_else(|| config.measure_workspace(workspace));
+    if usage.bytes > quota {
+        return Err(exceeded(workspace, usage.bytes, quota));
+    }
+    Ok(())
+}
+
+/// 507 `quota_exceeded` if importing the repository at `source` would put
+/// `workspace` over its quota
+pub fn require_space_for_import(config: &ConfigManager, workspace: &str, source: &Path) -> Result<(), ApiError> {
+    let Some(quota) = config.workspace_quota(workspace) else {
+        return Ok(());
+    };
+    let size = file_ops::disk_usage(source, usize::MAX).bytes;
+    if size > quota {
+        return Err(exceeded(workspace, size, quota));
+    }
+    Ok(())
+}
+
+/// Scan a freshly cloned workspace with a quota; 507 `quota_exceeded` if it came out
+/// over its quota
+pub fn check_clone(config: &ConfigManager, workspace: &str) -> Result<(), ApiError> {
+    let Some(quota) = config.workspace_quota(workspace) else {
+        return Ok(());
+    };
+    let usage = config.measure_workspace(workspace);
+    if usage.bytes > quota {
+        return Err(exceeded(workspace, usage.bytes, quota));
+    }
+    Ok(())
+}
+
+/// Rescan a workspace after a change that may have grown it by a lot, if
+/// it has a quota; otherwise just forget its size
+pub fn rescan(config: &ConfigManager, workspace: &str) {
+    if config.workspace_quota(workspace).is_some() {
+        config.measure_workspace(workspace);
+    } else {
+        config.invalidate_workspace_usage(workspace);
+    }
+}
+
+/// `workspace`'s last scanned size against its quota
+pub fn usage(config: &ConfigManager, workspace: &str) -> QuotaUsage {
+    let usage = config.workspace_usage(workspace);
+    let quota = config.workspace_quota(workspace);
+    QuotaUsage {
+        used_bytes: usage.map(|u| u.bytes),
+        quota_bytes: quota,
+        scanned_at: usage.map(|u| u.scanned_at),
+        exceeded: matches!((usage, quota), (Some(u), Some(q)) if u.bytes > q),
+    }
+}
@@ -0,0 +1,220 @@
+//! Request budgets for the API, so one client polling expensive endpoints
+//! can't starve everyone else. Each token (or, on paths that need none, each
+//! address) has a token bucket per request class that refills continuously;
+//! a request finding its bucket empty gets `429` with `Retry-After`.
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::{web, HttpMessage, ResponseError};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api;
+use crate::config::{ConfigManager, RateLimitsConfig, TokenIdentity};
+use crate::errors::ApiError;
+use crate::unix_socket;
+use crate::AppState;
+
+const DEFAULT_READS_PER_MINUTE: u32 = 1200;
+const DEFAULT_EXPENSIVE_READS_PER_MINUTE: u32 = 300;
+const DEFAULT_MUTATIONS_PER_MINUTE: u32 = 600;
+/// Drop buckets that have refilled once the table grows past this many
+const RATE_LIMITER_PRUNE_AT: usize = 4096;
+
+/// What a request costs the server, each with its own budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    Read,
+    ExpensiveRead,
+    Mutation,
+}
+
+impl RequestClass {
+    /// Class of an API request; None for paths outside the API
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        let path = api::unversioned_path(path);
+        if path.starts_with("/raw/") {
+            return Some(RequestClass::Read);
+        }
+        if !path.starts_with("/api/") {
+            return None;
+        }
+        if method != Method::GET && method != Method::HEAD {
+            return Some(RequestClass::Mutation);
+        }
+        // Worktree walks, diffs and git status
+        let expensive = ["/files", "/search", "/file/diff", "/status", "/lint", "/export", "/archive"];
+        if path.starts_with("/api/workspaces/") && expensive.iter().any(|suffix| path.ends_with(suffix)) {
+            Some(RequestClass::ExpensiveRead)
+        } else {
+            Some(RequestClass::Read)
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            RequestClass::Read => "reads",
+            RequestClass::ExpensiveRead => "expensive reads",
+            RequestClass::Mutation => "changes",
+        }
+    }
+
+    /// Requests per minute, 0 for no limit
+    fn per_minute(self, limits: &RateLimitsConfig) -> u32 {
+        match self {
+            RequestClass::Read => limits.reads_per_minute.unwrap_or(DEFAULT_READS_PER_MINUTE),
+            RequestClass::ExpensiveRead => limits
+                .expensive_reads_per_minute
+                .unwrap_or(DEFAULT_EXPENSIVE_READS_PER_MINUTE),
+            RequestClass::Mutation => limits.mutations_per_minute.unwrap_or(DEFAULT_MUTATIONS_PER_MINUTE),
+        }
+    }
+}
+
+/// Who a budget belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Token(String),
+    Address(IpAddr),
+}
+
+/// A full bucket holds a minute's worth of requests
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, per_minute: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_minute / 60.0).min(per_minute);
+        self.updated = now;
+    }
+}
+
+/// Buckets per client and request class. Buckets that have refilled are no
+/// different from new ones, so they are dropped once the table gets big.
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(Client, RequestClass), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a request from `client`'s budget for `class`; the time until
+    /// there is one again if it is used up
+    fn take(&self, client: Client, class: RequestClass, limits: &RateLimitsConfig) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > RATE_LIMITER_PRUNE_AT {
+            buckets.retain(|(_, class), bucket| {
+                let per_minute = class.per_minute(limits) as f64;
+                bucket.refill(per_minute);
+                bucket.tokens < per_minute
+            });
+        }
+
+        let per_minute = class.per_minute(limits) as f64;
+        let bucket = buckets.entry((client, class)).or_insert(Bucket {
+            tokens: per_minute,
+            updated: Instant::now(),
+        });
+        bucket.refill(per_minute);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) * 60.0 / per_minute))
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 429 if the request is over its client's budget
+fn check(req: &ServiceRequest) -> Result<(), ApiError> {
+    let Some(class) = RequestClass::of(req.method(), req.path()) else {
+        return Ok(());
+    };
+    let (Some(state), Some(config)) = (
+        req.app_data::<web::Data<Arc<AppState>>>(),
+        req.app_data::<web::Data<Arc<ConfigManager>>>(),
+    ) else {
+        return Ok(());
+    };
+    let limits = config.get_rate_limits();
+    if class.per_minute(&limits) == 0 {
+        return Ok(());
+    }
+
+    let identity = req.extensions().get::<TokenIdentity>().map(|identity| identity.name.clone());
+    let client = match identity {
+        Some(name) => Client::Token(name),
+        None => match unix_socket::peer_ip(req.request()) {
+            Some(ip) => Client::Address(ip),
+            None => return Ok(()),
+        },
+    };
+    state.rate_limiter.take(client.clone(), class, &limits).map_err(|wait| {
+        log::warn!("Rate limiting {} of {:?}", class.describe(), client);
+        ApiError::too_many_requests(format!("Too many {}, slow down", class.describe()), wait)
+    })
+}
+
+/// Middleware applying the budgets; must run inside `RequireAuth`, which
+/// tells it the token
+pub struct RateLimit;
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Err(e) = check(&req) {
+            let res = req.into_response(e.error_response()).map_into_right_body();
+            return Box::pin(async { Ok(res) });
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
+    }
+}
@@ -0,0 +1,111 @@
+//! `/raw/{workspace}/{branch}/{path}`: worktree files over plain GETs, for
+//! wikis, scripts and other tools that want the bytes rather than the API's
+//! JSON. Files are streamed as they are on disk with a best-effort content
+//! type and an ETag; directories answer with a short JSON listing of what
+//! they hold. Nothing here writes, so read-only tokens are enough, passed
+//! as a Bearer header or `?token=` for tools that can't set headers.
+//! Branches with a slash in their name are given percent-encoded.
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::file_ops::{self, FileEntry};
+use crate::gitignore;
+use crate::logging;
+use crate::watcher::FileWatcher;
+use crate::workspace;
+
+/// An entry of a directory listing
+#[derive(Debug, Serialize)]
+pub struct RawEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Bytes, for files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RawListing {
+    pub path: String,
+    pub entries: Vec<RawEntry>,
+}
+
+/// GET /raw/{workspace}/{branch}/{path} - A worktree file as it is on disk, or a listing of a directory
+pub async fn serve(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch, file_path) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
+
+    let file_path = file_path.trim_matches('/').to_string();
+    let full_path = resolve(&worktree_path, &file_path).map_err(|e| ApiError::io("Failed to read file", e))?;
+    if !full_path.is_dir() {
+        return workspace::serve_file(&req, &config, &worktree_path, &file_path, None);
+    }
+
+    let listing = logging::block(move || list_directory(&worktree_path, &file_path)).await??;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .json(listing))
+}
+
+/// `path` in the worktree, which is its root when empty
+fn resolve(worktree_path: &Path, path: &str) -> std::io::Result<PathBuf> {
+    if path.is_empty() {
+        return Ok(worktree_path.to_path_buf());
+    }
+    file_ops::safe_join(worktree_path, path)
+}
+
+/// The immediate children of `dir` (relative to the worktree, empty for
+/// its root), directories first, leaving out dotfiles and what git ignores
+fn list_directory(worktree_path: &Path, dir: &str) -> Result<RawListing, ApiError> {
+    let failed = |e| ApiError::io("Failed to list directory", e);
+    let full_path = resolve(worktree_path, dir).map_err(failed)?;
+
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(&full_path).map_err(failed)? {
+        let entry = entry.map_err(failed)?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = if dir.is_empty() { name.clone() } else { format!("{}/{}", dir, name) };
+        entries.push(FileEntry {
+            name,
+            path,
+            is_dir: entry.path().is_dir(),
+            children: None,
+            frontmatter: None,
+            frontmatter_warnings: Vec::new(),
+        });
+    }
+    gitignore::prune_ignored(worktree_path, &mut entries).map_err(|e| e.context("Failed to check ignored files"))?;
+
+    let mut entries: Vec<RawEntry> = entries
+        .into_iter()
+        .map(|entry| RawEntry {
+            size: if entry.is_dir {
+                None
+            } else {
+                std::fs::metadata(full_path.join(&entry.name)).ok().map(|m| m.len())
+            },
+            kind: if entry.is_dir { "dir" } else { "file" },
+            name: entry.name,
+        })
+        .collect();
+    entries.sort_by(|a, b| (a.kind != "dir", &a.name).cmp(&(b.kind != "dir", &b.name)));
+
+    Ok(RawListing { path: dir.to_string(), entries })
+}
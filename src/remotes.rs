@@ -0,0 +1,172 @@
+//! Remotes of a workspace's repository besides the `origin` it was cloned
+//! from, for fork-based work: push to the fork, rebase onto the project.
+//! The workspace's `push_remote` and `upstream_remote` (see `PATCH
+//! /api/workspaces/{name}`) pick the remotes push, pull and rebase use, and
+//! `?remote=` overrides them for one request. Remotes are plain `git remote`
+//! entries of the bare repository, so their URLs authenticate through the
+//! same git credential helpers as the clone URL.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::audit;
+use crate::config::{validate_remote_name, ConfigManager, WorkspaceConfig};
+use crate::errors::ApiError;
+use crate::events::{Event, EventBus};
+use crate::extract::Json;
+use crate::git_ops;
+use crate::logging;
+use crate::workspace;
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct RemoteQuery {
+    /// Remote to use instead of the workspace's preference
+    pub remote: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddRemoteRequest {
+    pub name: String,
+    pub url: String,
+    /// Check the URL with `git ls-remote` before adding it
+    #[serde(default = "default_true")]
+    pub verify: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn remote_not_found(name: &str) -> ApiError {
+    ApiError::not_found("remote_not_found", format!("Remote '{}' not found", name))
+}
+
+/// 400 `invalid_remote_name` unless `name` is a valid remote name, 404
+/// `remote_not_found` unless the repository has it
+pub fn require_remote(repo_path: &Path, name: &str) -> Result<(), ApiError> {
+    validate_remote_name(name).map_err(|message| ApiError::validation("invalid_remote_name", message))?;
+    let remotes = git_ops::list_remotes(repo_path).map_err(|e| e.context("Failed to list remotes"))?;
+    if !remotes.iter().any(|remote| remote.name == name) {
+        return Err(remote_not_found(name));
+    }
+    Ok(())
+}
+
+/// The remote a request uses: its `?remote=` if given, otherwise
+/// `preferred`, the workspace's preference
+pub fn resolve(repo_path: &Path, query: &RemoteQuery, preferred: &str) -> Result<String, ApiError> {
+    let remote = query.remote.as_deref().unwrap_or(preferred);
+    require_remote(repo_path, remote)?;
+    Ok(remote.to_string())
+}
+
+fn remote_list(repo_path: &Path, ws_config: &WorkspaceConfig) -> Result<serde_json::Value, ApiError> {
+    let remotes = git_ops::list_remotes(repo_path).map_err(|e| e.context("Failed to list remotes"))?;
+    Ok(serde_json::json!({
+        "remotes": remotes,
+        "push_remote": ws_config.push_remote(),
+        "upstream_remote": ws_config.upstream_remote()
+    }))
+}
+
+/// GET /api/workspaces/{name}/remotes - Remotes of the repository and the
+/// ones pushes and pulls use
+pub async fn list_remotes(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let ws_config = workspace::require_workspace(&config, &name)?;
+    workspace::require_usable_repo(&config, &name).await?;
+    let repo_path = config.repo_path(&name);
+    let body = logging::block(move || remote_list(&repo_path, &ws_config)).await??;
+    Ok(HttpResponse::Ok().json(body))
+}
+
+/// POST /api/workspaces/{name}/remotes - Add a remote; answers like the GET
+pub async fn add_remote(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
+    path: web::Path<String>,
+    body: Json<AddRemoteRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let ws_config = workspace::require_workspace(&config, &name)?;
+    workspace::require_usable_repo(&config, &name).await?;
+    let remote = body.name.clone();
+    validate_remote_name(&remote).map_err(|message| ApiError::validation("invalid_remote_name", message))?;
+    let url = workspace::check_repo_url(&body.url, "url")?.to_string();
+
+    let repo_path = config.repo_path(&name);
+    if git_ops::list_remotes(&repo_path)
+        .map_err(|e| e.context("Failed to list remotes"))?
+        .iter()
+        .any(|existing| existing.name == remote)
+    {
+        return Err(ApiError::conflict("remote_exists", format!("Remote '{}' already exists", remote)));
+    }
+    if body.verify {
+        workspace::verify_repo_url(&repo_path, &url).await?;
+    }
+
+    git_ops::add_remote(&repo_path, &remote, &url).map_err(|e| e.context("Failed to add remote"))?;
+    state.listings.invalidate_workspace(&name);
+    events.publish(Event::WorkspaceUpdated { workspace: name.clone() });
+    audit::record(
+        &req,
+        "workspace.remote.add",
+        serde_json::json!({ "workspace": name, "remote": remote, "url": url }),
+    );
+
+    let body = logging::block(move || remote_list(&repo_path, &ws_config)).await??;
+    Ok(HttpResponse::Created().json(body))
+}
+
+/// DELETE /api/workspaces/{name}/remotes/{remote} - Remove a remote, unless
+/// the workspace pushes to or pulls from it; answers like the GET
+pub async fn remove_remote(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (name, remote) = path.into_inner();
+    let ws_config = workspace::require_workspace(&config, &name)?;
+    workspace::require_usable_repo(&config, &name).await?;
+    let repo_path = config.repo_path(&name);
+    require_remote(&repo_path, &remote)?;
+
+    let uses: Vec<&str> = [
+        ("push_remote", ws_config.push_remote()),
+        ("upstream_remote", ws_config.upstream_remote()),
+    ]
+    .into_iter()
+    .filter(|(_, used)| *used == remote)
+    .map(|(preference, _)| preference)
+    .collect();
+    if !uses.is_empty() {
+        return Err(ApiError::conflict(
+            "remote_in_use",
+            format!("Remote '{}' is the workspace's {}; choose another first", remote, uses.join(" and ")),
+        )
+        .with_details(serde_json::json!({ "preferences": uses })));
+    }
+
+    git_ops::remove_remote(&repo_path, &remote).map_err(|e| e.context("Failed to remove remote"))?;
+    state.listings.invalidate_workspace(&name);
+    events.publish(Event::WorkspaceUpdated { workspace: name.clone() });
+    audit::record(
+        &req,
+        "workspace.remote.remove",
+        serde_json::json!({ "workspace": name, "remote": remote }),
+    );
+
+    let body = logging::block(move || remote_list(&repo_path, &ws_config)).await??;
+    Ok(HttpResponse::Ok().json(body))
+}
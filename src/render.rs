@@ -0,0 +1,156 @@
+//! Markdown rendered to HTML on the server, for clients that can't or
+//! shouldn't run a markdown renderer of their own. HTML written in the
+//! markdown is kept as far as it is harmless: the output goes through
+//! ammonia, which removes scripts, event handlers, `style` attributes and
+//! `javascript:` URLs. Relative links and images point at the file in the
+//! worktree under `/raw/{workspace}/{branch}/...`, and fenced code blocks
+//! carry a `language-*` class for highlighting on the client.
+
+use actix_web::{web, HttpResponse};
+use ammonia::{UrlRelative, UrlRelativeEvaluate};
+use pulldown_cmark::{Options, Parser};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::blocks;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::logging;
+use crate::workspace::{self, FileQuery};
+
+/// Table cell alignments pulldown-cmark writes as `style`, the only styles
+/// let through
+const ALIGNMENTS: &[&str] = &["text-align: left", "text-align: center", "text-align: right"];
+
+/// Markdown extensions of rendered runbooks
+pub fn markdown_options() -> Options {
+    Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+}
+
+/// `text` percent-encoded as one segment of a URL path
+fn encode_segment(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// URL of the worktree file `path` under `/raw`
+pub fn raw_url(workspace: &str, branch: &str, path: &str) -> String {
+    let path: Vec<String> = path.split('/').map(encode_segment).collect();
+    format!("/raw/{}/{}/{}", encode_segment(workspace), encode_segment(branch), path.join("/"))
+}
+
+/// Rewrites the relative URLs of a rendered file to `/raw` ones
+struct RawLinks {
+    workspace: String,
+    branch: String,
+    /// The rendered file, empty for unsaved content without a path
+    file_path: String,
+}
+
+impl<'a> UrlRelativeEvaluate<'a> for RawLinks {
+    /// Anchors alone stay as they are, others keep their `#anchor`; links
+    /// out of the worktree are dropped
+    fn evaluate<'url>(&self, url: &'url str) -> Option<Cow<'url, str>> {
+        if url.starts_with('#') {
+            return Some(Cow::Borrowed(url));
+        }
+        let (target, anchor) = match url.split_once('#') {
+            Some((target, anchor)) => (target, format!("#{}", anchor)),
+            None => (url, String::new()),
+        };
+        let path = blocks::link_path(&self.file_path, &blocks::percent_decode(target))?;
+        Some(Cow::Owned(format!("{}{}", raw_url(&self.workspace, &self.branch, &path), anchor)))
+    }
+}
+
+/// `content`, the markdown of `file_path` in the worktree of `branch`
+/// (relative links start from the worktree's root without one), as
+/// sanitized HTML
+pub fn to_html(content: &str, workspace: &str, branch: &str, file_path: Option<&str>) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new_ext(content, markdown_options()));
+
+    let links = RawLinks {
+        workspace: workspace.to_string(),
+        branch: branch.to_string(),
+        file_path: file_path.unwrap_or_default().to_string(),
+    };
+    ammonia::Builder::default()
+        // Task list checkboxes
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("code", ["class"])
+        .add_tag_attributes("th", ["style"])
+        .add_tag_attributes("td", ["style"])
+        .attribute_filter(|element, attribute, value| {
+            let allowed = match (element, attribute) {
+                ("code", "class") => {
+                    value.starts_with("language-") && !value.contains(char::is_whitespace)
+                }
+                ("input", "type") => value == "checkbox",
+                ("th" | "td", "style") => ALIGNMENTS.contains(&value),
+                _ => true,
+            };
+            allowed.then_some(Cow::Borrowed(value))
+        })
+        .url_relative(UrlRelative::Custom(Box::new(links)))
+        .clean(&html)
+        .to_string()
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/file/render?path=x - A
+/// markdown file as sanitized HTML
+pub async fn render_file(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+    let content = workspace::read_text_file(&config, &worktree_path, &query.path)?;
+
+    let file_path = query.path.clone();
+    let html = logging::block(move || to_html(&content, &workspace, &branch, Some(&file_path))).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": query.path,
+        "html": html
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenderRequest {
+    /// Where the content will be saved, for its relative links
+    #[serde(default)]
+    pub path: Option<String>,
+    pub content: String,
+}
+
+/// POST /api/workspaces/{name}/branches/{branch}/file/render - Render
+/// unsaved content, answered like the GET without reading the file
+pub async fn render_content(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    body: Json<RenderRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    workspace::require_worktree(&config, &workspace, &branch)?;
+
+    let RenderRequest { path, content } = body.into_inner();
+    let file_path = path.clone();
+    let html = logging::block(move || to_html(&content, &workspace, &branch, file_path.as_deref())).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": path,
+        "html": html
+    })))
+}
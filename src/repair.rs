@@ -0,0 +1,469 @@
+//! Cleaning up after git was killed halfway: a crash or restart mid-rebase
+//! or mid-fetch leaves lock files such as `index.lock` behind, and a
+//! rebase, merge, cherry-pick or revert half done, after which every git
+//! command in the worktree fails until someone cleans up by hand. At
+//! startup, and on `POST /api/workspaces/{name}/repair`, each workspace's
+//! repository is scanned for both.
+//!
+//! A lock file is only removed once it is older than `stale_lock_secs` and
+//! no live process holds it: none has it open, and no git process is
+//! working in the workspace. Interrupted operations are aborted when
+//! `abort_interrupted_operations` is set (or the repair request asks for
+//! it); otherwise their branch is listed with `needs_attention`, so the UI
+//! can offer to abort them.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audit;
+use crate::config::{sanitize_branch_name, ConfigManager, WorkspaceStatus};
+use crate::errors::ApiError;
+use crate::git_ops;
+use crate::logging;
+use crate::workspace;
+use crate::worktree_lock::WorktreeLocks;
+use crate::AppState;
+
+/// Something left in a worktree that git can't get past on its own
+#[derive(Debug, Clone, Serialize)]
+pub struct Attention {
+    /// `rebase_interrupted`, `merge_interrupted`, `cherry_pick_interrupted`,
+    /// `revert_interrupted`, `lock_held` or `stale_lock`
+    pub condition: String,
+    pub message: String,
+    /// What has to go away for the condition to be over
+    #[serde(skip)]
+    cause: Cause,
+}
+
+#[derive(Debug, Clone)]
+enum Cause {
+    Operation(&'static str),
+    Lock(PathBuf),
+}
+
+impl Attention {
+    fn interrupted(operation: &'static str, message: String) -> Self {
+        Self {
+            condition: format!("{}_interrupted", operation.replace('-', "_")),
+            message,
+            cause: Cause::Operation(operation),
+        }
+    }
+
+    /// Whether the condition still holds in `worktree_path`, e.g. after a
+    /// rebase was aborted in a terminal
+    fn holds(&self, worktree_path: &Path) -> bool {
+        match &self.cause {
+            Cause::Operation(operation) => git_ops::pending_operation(worktree_path) == Some(*operation),
+            Cause::Lock(path) => path.exists(),
+        }
+    }
+}
+
+/// Conditions found per worktree by the last scan of its workspace
+#[derive(Default)]
+pub struct AttentionBoard {
+    /// By workspace, then worktree directory name
+    found: Mutex<HashMap<String, HashMap<String, Vec<Attention>>>>,
+}
+
+impl AttentionBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The conditions of the worktree of `branch` that still hold
+    pub fn of(&self, workspace: &str, branch: &str, worktree_path: &Path) -> Vec<Attention> {
+        let mut found = self.found.lock().unwrap();
+        let Some(worktrees) = found.get_mut(workspace) else {
+            return Vec::new();
+        };
+        let dir = sanitize_branch_name(branch);
+        let Some(conditions) = worktrees.get_mut(&dir) else {
+            return Vec::new();
+        };
+        conditions.retain(|attention| attention.holds(worktree_path));
+        if conditions.is_empty() {
+            worktrees.remove(&dir);
+        }
+        worktrees.get(&dir).cloned().unwrap_or_default()
+    }
+
+    /// Replace what is known about the worktrees of `workspace`, or of the
+    /// one worktree `only` when a repair was limited to it
+    fn update(&self, workspace: &str, only: Option<&str>, found: HashMap<String, Vec<Attention>>) {
+        let mut board = self.found.lock().unwrap();
+        let worktrees = board.entry(workspace.to_string()).or_default();
+        match only {
+            Some(dir) => {
+                worktrees.remove(dir);
+            }
+            None => worktrees.clear(),
+        }
+        worktrees.extend(found.into_iter().filter(|(_, conditions)| !conditions.is_empty()));
+    }
+}
+
+/// A lock file older than `stale_lock_secs`
+#[derive(Debug, Serialize)]
+pub struct StaleLock {
+    /// Relative to the workspace's repository
+    pub path: String,
+    /// The branch whose worktree the lock belongs to; none for locks of the
+    /// repository itself (refs, packed-refs, config)
+    pub branch: Option<String>,
+    pub age_secs: u64,
+    pub removed: bool,
+    /// The process found holding it, which is left alone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub held_by: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A rebase, merge, cherry-pick or revert found half done
+#[derive(Debug, Serialize)]
+pub struct InterruptedOperation {
+    pub branch: String,
+    pub operation: &'static str,
+    pub aborted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepairReport {
+    pub workspace: String,
+    pub locks: Vec<StaleLock>,
+    pub operations: Vec<InterruptedOperation>,
+}
+
+impl RepairReport {
+    /// Whether anything was removed or aborted
+    pub fn changed(&self) -> bool {
+        self.locks.iter().any(|lock| lock.removed) || self.operations.iter().any(|operation| operation.aborted)
+    }
+
+    fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "workspace": self.workspace,
+            "removed_locks": self
+                .locks
+                .iter()
+                .filter(|lock| lock.removed)
+                .map(|lock| &lock.path)
+                .collect::<Vec<_>>(),
+            "aborted": self
+                .operations
+                .iter()
+                .filter(|operation| operation.aborted)
+                .map(|operation| serde_json::json!({ "branch": operation.branch, "operation": operation.operation }))
+                .collect::<Vec<_>>()
+        })
+    }
+}
+
+/// A live process that has `lock` open, or a git process working somewhere
+/// under `dir`. Fails where processes can't be listed (no `/proc`), in which
+/// case nothing may be assumed to be free.
+fn holder(dir: &Path, lock: Option<&Path>) -> std::io::Result<Option<u32>> {
+    let dir = dir.canonicalize()?;
+    let lock = lock.map(|lock| lock.canonicalize().unwrap_or_else(|_| lock.to_path_buf()));
+    for entry in fs::read_dir("/proc")? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+        let proc_dir = entry.path();
+        let is_git = fs::read_to_string(proc_dir.join("comm")).is_ok_and(|comm| comm.trim().starts_with("git"));
+        if is_git && fs::read_link(proc_dir.join("cwd")).is_ok_and(|cwd| cwd.starts_with(&dir)) {
+            return Ok(Some(pid));
+        }
+        let Some(lock) = &lock else {
+            continue;
+        };
+        // Processes of other users can't be looked into
+        let Ok(fds) = fs::read_dir(proc_dir.join("fd")) else {
+            continue;
+        };
+        if fds
+            .filter_map(|fd| fd.ok())
+            .any(|fd| fs::read_link(fd.path()).is_ok_and(|target| &target == lock))
+        {
+            return Ok(Some(pid));
+        }
+    }
+    Ok(None)
+}
+
+/// Lock files under the repository's git directory (which holds those of
+/// its worktrees too), leaving out the object store
+fn find_locks(dir: &Path, locks: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if entry.file_name() != "objects" {
+                find_locks(&path, locks);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lock") {
+            locks.push(path);
+        }
+    }
+}
+
+/// Worktree directory a lock under `worktrees/<id>/` of the repository
+/// belongs to, as git recorded it in that directory's `gitdir` file
+fn worktree_of_lock(repo_path: &Path, lock: &Path) -> Option<PathBuf> {
+    let relative = lock.strip_prefix(repo_path.join("worktrees")).ok()?;
+    let id = relative.components().next()?;
+    let gitdir = fs::read_to_string(repo_path.join("worktrees").join(id).join("gitdir")).ok()?;
+    Path::new(gitdir.trim()).parent().map(Path::to_path_buf)
+}
+
+/// Branch a worktree directory was created for
+fn branch_of(branches: &[String], worktree_path: &Path) -> String {
+    let dir = worktree_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    branches
+        .iter()
+        .find(|branch| sanitize_branch_name(branch) == dir)
+        .cloned()
+        .unwrap_or(dir)
+}
+
+/// Remove the stale locks of `workspace` and deal with its interrupted
+/// operations, in every worktree or only the one of `only_branch`; returns
+/// what was found and the conditions left per worktree directory
+fn scan(
+    config: &ConfigManager,
+    worktree_locks: &WorktreeLocks,
+    workspace: &str,
+    only_branch: Option<&str>,
+    abort: bool,
+) -> (RepairReport, HashMap<String, Vec<Attention>>) {
+    let workspace_path = config.workspace_path(workspace);
+    let repo_path = config.repo_path(workspace);
+    let branches = git_ops::list_branches(&repo_path).unwrap_or_default();
+    let only = only_branch.map(sanitize_branch_name);
+    let in_scope = |worktree: &Option<PathBuf>| match (&only, worktree) {
+        (Some(only), Some(worktree)) => worktree.file_name().is_some_and(|name| name.to_string_lossy() == *only),
+        // Repository locks get in the way of every worktree
+        _ => true,
+    };
+    let max_age = config.get_stale_lock_age();
+    let mut attention: HashMap<String, Vec<Attention>> = HashMap::new();
+    let dir_of = |worktree: &Path| worktree.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+    let mut found = Vec::new();
+    find_locks(&repo_path, &mut found);
+    found.sort();
+    let mut locks = Vec::new();
+    for path in found {
+        let worktree = worktree_of_lock(&repo_path, &path);
+        if !in_scope(&worktree) {
+            continue;
+        }
+        let age = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| modified.elapsed().unwrap_or(Duration::ZERO))
+            .unwrap_or(Duration::ZERO);
+        if age < max_age {
+            continue;
+        }
+        let relative = path.strip_prefix(&repo_path).unwrap_or(&path).to_string_lossy().to_string();
+        let mut lock = StaleLock {
+            path: relative.clone(),
+            branch: worktree.as_deref().map(|worktree| branch_of(&branches, worktree)),
+            age_secs: age.as_secs(),
+            removed: false,
+            held_by: None,
+            error: None,
+        };
+        let (condition, message) = match holder(&workspace_path, Some(&path)) {
+            Ok(Some(pid)) => {
+                log::warn!("Lock {:?} of workspace '{}' is held by process {}", path, workspace, pid);
+                lock.held_by = Some(pid);
+                ("lock_held", format!("{} is held by process {}", relative, pid))
+            }
+            Ok(None) => match fs::remove_file(&path) {
+                Ok(()) => {
+                    log::warn!("Removed stale lock {:?} of workspace '{}'", path, workspace);
+                    lock.removed = true;
+                    locks.push(lock);
+                    continue;
+                }
+                Err(e) => {
+                    lock.error = Some(e.to_string());
+                    ("stale_lock", format!("{} could not be removed: {}", relative, e))
+                }
+            },
+            Err(e) => {
+                lock.error = Some(format!("Can't tell whether a process holds it: {}", e));
+                ("stale_lock", format!("{} may still be held, can't list processes: {}", relative, e))
+            }
+        };
+        if let Some(worktree) = &worktree {
+            attention.entry(dir_of(worktree)).or_default().push(Attention {
+                condition: condition.to_string(),
+                message,
+                cause: Cause::Lock(path.clone()),
+            });
+        }
+        locks.push(lock);
+    }
+
+    let mut operations = Vec::new();
+    let worktrees = git_ops::registered_worktrees(&repo_path).unwrap_or_default();
+    for worktree in worktrees.into_iter().filter(|worktree| in_scope(&Some(worktree.clone()))) {
+        let Some(operation) = git_ops::pending_operation(&worktree) else {
+            continue;
+        };
+        let branch = branch_of(&branches, &worktree);
+        let mut interrupted = InterruptedOperation {
+            branch: branch.clone(),
+            operation,
+            aborted: false,
+            error: None,
+        };
+        let message = match holder(&worktree, None) {
+            Ok(Some(pid)) => format!("A {} is under way in process {}", operation, pid),
+            // While one of the server's own git operations holds the
+            // worktree, the operation may be that one, still running
+            Ok(None) if abort => match worktree_locks.try_acquire(&worktree, "repair") {
+                None => {
+                    interrupted.error = Some("The worktree is busy with another operation".to_string());
+                    format!("A {} is unfinished; the worktree was busy, so it wasn't aborted", operation)
+                }
+                Some(_lock) => match git_ops::abort_operation(&worktree, operation) {
+                    Ok(()) => {
+                        log::warn!("Aborted an interrupted {} on '{}' in workspace '{}'", operation, branch, workspace);
+                        interrupted.aborted = true;
+                        operations.push(interrupted);
+                        continue;
+                    }
+                    Err(e) => {
+                        interrupted.error = Some(e.to_string());
+                        format!("The interrupted {} could not be aborted: {}", operation, e)
+                    }
+                },
+            },
+            Ok(None) => {
+                log::warn!("Branch '{}' of workspace '{}' is in the middle of a {}", branch, workspace, operation);
+                format!("A {} was left unfinished", operation)
+            }
+            Err(e) => format!("A {} is unfinished; can't tell whether it is still running: {}", operation, e),
+        };
+        attention
+            .entry(dir_of(&worktree))
+            .or_default()
+            .push(Attention::interrupted(operation, message));
+        operations.push(interrupted);
+    }
+
+    let report = RepairReport {
+        workspace: workspace.to_string(),
+        locks,
+        operations,
+    };
+    (report, attention)
+}
+
+/// Repair `workspace` and note what is left on the attention board; None
+/// while the repository can't be worked on (being cloned, missing, corrupt)
+pub fn repair(
+    config: &ConfigManager,
+    state: &AppState,
+    workspace: &str,
+    only_branch: Option<&str>,
+    abort: bool,
+) -> Option<RepairReport> {
+    if !config.verify_workspace(workspace).repo_usable() {
+        return None;
+    }
+    let (report, attention) = scan(config, &state.worktree_locks, workspace, only_branch, abort);
+    let only = only_branch.map(sanitize_branch_name);
+    state.attention.update(workspace, only.as_deref(), attention);
+    // Worktrees registered with git may be back in order without a lock
+    config.invalidate_workspace_status(workspace);
+    Some(report)
+}
+
+/// Clean up every workspace before the server starts taking requests; a
+/// read-only server leaves interrupted operations as they are
+pub fn startup(config: &ConfigManager, state: &AppState) {
+    let abort = config.abort_interrupted_operations() && !config.read_only();
+    let mut names: Vec<String> = config.get_workspaces().into_keys().collect();
+    names.sort();
+    for name in names {
+        if config.workspace_status(&name) == WorkspaceStatus::Cloning {
+            continue;
+        }
+        let Some(report) = repair(config, state, &name, None, abort) else {
+            continue;
+        };
+        if report.changed() {
+            let actor = audit::Actor { token: None, ip: None };
+            state
+                .audit_log
+                .record_as(&actor, "workspace.repair", "STARTUP".to_string(), report.summary());
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepairQuery {
+    /// Only the worktree of this branch (and the repository's own locks)
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// Abort interrupted operations [default: `abort_interrupted_operations`]
+    #[serde(default)]
+    pub abort: Option<bool>,
+}
+
+/// POST /api/workspaces/{name}/repair?branch=x&abort=true - Remove stale git
+/// locks and abort or report interrupted operations
+pub async fn repair_workspace(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    query: web::Query<RepairQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    workspace::require_workspace(&config, &name)?;
+    workspace::require_usable_repo(&config, &name).await?;
+    let RepairQuery { branch, abort } = query.into_inner();
+    if let Some(branch) = &branch {
+        workspace::require_worktree(&config, &name, branch)?;
+    }
+    let abort = abort.unwrap_or_else(|| config.abort_interrupted_operations());
+
+    let report = {
+        let config = config.get_ref().clone();
+        let state = state.get_ref().clone();
+        let name = name.clone();
+        logging::block(move || repair(&config, &state, &name, branch.as_deref(), abort)).await?
+    };
+    let report = report.ok_or_else(|| {
+        ApiError::conflict("workspace_unavailable", format!("Workspace '{}' can't be repaired now", name))
+    })?;
+    state.listings.invalidate_workspace(&name);
+
+    if report.changed() {
+        audit::record(&req, "workspace.repair", report.summary());
+    }
+    Ok(HttpResponse::Ok().json(report))
+}
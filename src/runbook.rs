@@ -0,0 +1,591 @@
+//! Running runbook steps: a shell code block of a markdown file is either
+//! typed into a terminal session the operator has open, or run once in the
+//! worktree with its output captured. Either way the response names the
+//! block and the file revision that ran, so the audit log does too.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::approvals;
+use crate::audit;
+use crate::blocks::{self, CodeBlock};
+use crate::config::{ConfigManager, ScheduleConfig};
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::extract::Json;
+use crate::file_ops;
+use crate::history;
+use crate::sessions;
+use crate::variables;
+use crate::workspace;
+use crate::workspace_env;
+use crate::AppState;
+
+/// Language tags of blocks that may be run, and the shell each runs under
+const RUNNABLE_LANGUAGES: &[(&str, &str)] = &[("bash", "bash"), ("sh", "sh"), ("shell", "sh")];
+
+/// Time limit of a run without a `timeout` attribute on its block
+const DEFAULT_RUN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Output kept per stream; the rest is read and dropped
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// How long to wait for the output pipes to close once a timed-out shell is
+/// killed; a background process it started may hold them open
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize)]
+pub struct RunBlockRequest {
+    pub path: String,
+    pub block_index: usize,
+    /// Type the block into this terminal session instead of running it here
+    pub session_id: Option<String>,
+    /// Write the output into an `output` block after the code block
+    #[serde(default)]
+    pub record_output: bool,
+    /// Values of the block's `{{name}}` variables
+    #[serde(default)]
+    pub variables: serde_json::Map<String, serde_json::Value>,
+    /// From `POST /api/approvals`, for blocks with `requires_approval`
+    pub approval_token: Option<String>,
+    /// Check the run and resolve its code, but don't run it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Output of a block run in the worktree
+#[derive(Debug, Serialize)]
+pub struct ExecOutput {
+    /// None when the shell was killed
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether output past `MAX_OUTPUT_BYTES` was dropped
+    pub truncated: bool,
+    pub timed_out: bool,
+    pub duration_ms: u128,
+}
+
+/// Which block of which file revision ran, and how
+#[derive(Debug, Serialize)]
+pub struct BlockRun {
+    /// ID of the run in the workspace's execution history
+    pub execution_id: String,
+    pub path: String,
+    pub block_index: usize,
+    pub language: Option<String>,
+    pub start_line: usize,
+    /// SHA-256 of the file and of the block's code as they were run
+    pub file_hash: String,
+    pub block_hash: String,
+    /// Set when the block was typed into a terminal session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Label of that session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_label: Option<String>,
+    /// Token that approved the run of a `requires_approval` block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approved_by: Option<String>,
+    /// Set when the block ran in the worktree
+    #[serde(flatten)]
+    pub output: Option<ExecOutput>,
+    /// Whether the output was written into the runbook
+    pub recorded_output: bool,
+    /// Set for dry runs, which only say what would run
+    #[serde(flatten)]
+    pub dry_run: Option<DryRun>,
+}
+
+/// What a dry run would have done
+#[derive(Debug, Serialize)]
+pub struct DryRun {
+    pub dry_run: bool,
+    /// The code after variable substitution, preceded by the assignments of
+    /// its variables: what would be typed, or the equivalent of the worktree run
+    pub command: String,
+    pub shell: &'static str,
+    /// Where a worktree run would run; None when typed into a session
+    pub worktree_path: Option<String>,
+    pub timeout_secs: Option<u64>,
+}
+
+/// The shell a block runs under, if its language may be run at all
+fn shell_for(block: &CodeBlock) -> Option<&'static str> {
+    let language = block.language.as_deref()?;
+    RUNNABLE_LANGUAGES
+        .iter()
+        .find(|(tag, _)| tag.eq_ignore_ascii_case(language))
+        .map(|(_, shell)| *shell)
+}
+
+/// Body of the `output` block recording a run: a comment line with when it
+/// ran and how it ended, then stdout and stderr cut to `limit` bytes
+fn recorded_output(output: &ExecOutput, limit: usize) -> String {
+    let ended = match output.exit_code {
+        _ if output.timed_out => "timed out".to_string(),
+        Some(code) => format!("exit code {}", code),
+        None => "killed".to_string(),
+    };
+    let mut text = format!("# {} {}\n", Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), ended);
+    let captured = format!("{}{}", output.stdout, output.stderr);
+    let (kept, truncated) = truncate(&captured, limit);
+    text.push_str(kept);
+    if truncated {
+        text.push_str("\n# output truncated\n");
+    }
+    text
+}
+
+/// `text` cut to at most `limit` bytes on a character boundary, and whether
+/// anything was cut
+fn truncate(text: &str, limit: usize) -> (&str, bool) {
+    if text.len() <= limit {
+        return (text, false);
+    }
+    let mut end = limit;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&text[..end], true)
+}
+
+/// A run's output as kept in the execution history
+fn stored_output(output: &ExecOutput, limit: usize) -> history::StoredOutput {
+    let (stdout, stdout_cut) = truncate(&output.stdout, limit);
+    let (stderr, stderr_cut) = truncate(&output.stderr, limit);
+    history::StoredOutput {
+        stdout: stdout.to_string(),
+        stderr: stderr.to_string(),
+        truncated: output.truncated || stdout_cut || stderr_cut,
+    }
+}
+
+fn run_timeout(block: &CodeBlock) -> Result<Duration, ApiError> {
+    match block.attributes.get("timeout") {
+        None => Ok(DEFAULT_RUN_TIMEOUT),
+        Some(value) => value.parse().map(Duration::from_secs).map_err(|_| {
+            ApiError::validation(
+                "invalid_block_attribute",
+                format!("Block timeout '{}' is not a whole number of seconds", value),
+            )
+        }),
+    }
+}
+
+/// Read a stream to the end, keeping the first `MAX_OUTPUT_BYTES`
+async fn read_capped(mut reader: impl AsyncRead + Unpin) -> (Vec<u8>, bool) {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let room = MAX_OUTPUT_BYTES - kept.len();
+                kept.extend_from_slice(&buf[..n.min(room)]);
+                truncated |= n > room;
+            }
+        }
+    }
+    (kept, truncated)
+}
+
+/// Run `code` under `shell` in `cwd` with `env` added to the environment,
+/// killing it after `timeout`
+async fn exec(
+    shell: &str,
+    code: &str,
+    cwd: &std::path::Path,
+    env: Vec<(String, String)>,
+    timeout: Duration,
+) -> Result<ExecOutput, ApiError> {
+    let started = Instant::now();
+    let mut child = Command::new(shell)
+        .arg("-c")
+        .arg(code)
+        .current_dir(cwd)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| ApiError::io(format!("Failed to start {}", shell), e))?;
+
+    let stdout = tokio::spawn(read_capped(child.stdout.take().expect("stdout is piped")));
+    let stderr = tokio::spawn(read_capped(child.stderr.take().expect("stderr is piped")));
+
+    let (status, timed_out) = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => (Some(status.map_err(|e| ApiError::io("Failed to wait for the shell", e))?), false),
+        Err(_) => {
+            log::warn!("Block run exceeded {:?}, killing it", timeout);
+            let _ = child.kill().await;
+            (None, true)
+        }
+    };
+
+    let drain = |task: tokio::task::JoinHandle<(Vec<u8>, bool)>| async move {
+        match tokio::time::timeout(DRAIN_TIMEOUT, task).await {
+            Ok(Ok(output)) => output,
+            _ => (Vec::new(), false),
+        }
+    };
+    let (stdout, stdout_truncated) = drain(stdout).await;
+    let (stderr, stderr_truncated) = drain(stderr).await;
+
+    Ok(ExecOutput {
+        exit_code: status.and_then(|status| status.code()),
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        truncated: stdout_truncated || stderr_truncated,
+        timed_out,
+        duration_ms: started.elapsed().as_millis(),
+    })
+}
+
+/// A runnable block of a runbook, with what it needs to run
+struct LoadedBlock {
+    /// The whole file, for its hash
+    content: String,
+    block: CodeBlock,
+    declared: Vec<variables::Variable>,
+    shell: &'static str,
+}
+
+/// Block `block_index` of the runbook at `path`; 404 `block_not_found` or
+/// 400 `block_not_runnable`
+fn load_block(
+    config: &ConfigManager,
+    worktree_path: &std::path::Path,
+    path: &str,
+    block_index: usize,
+) -> Result<LoadedBlock, ApiError> {
+    let content = workspace::read_text_file(config, worktree_path, path)?;
+    let mut runbook = blocks::parse(&content);
+    let count = runbook.blocks.len();
+    if block_index >= count {
+        return Err(ApiError::not_found(
+            "block_not_found",
+            format!("'{}' has no code block {}", path, block_index),
+        )
+        .with_details(serde_json::json!({ "block_count": count })));
+    }
+    let (declared, _) = variables::load(worktree_path, path, runbook.front_matter.as_deref());
+    let block = runbook.blocks.swap_remove(block_index);
+
+    let Some(shell) = shell_for(&block) else {
+        return Err(ApiError::validation("block_not_runnable", "Only bash, sh and shell blocks can be run")
+            .with_details(serde_json::json!({
+                "language": block.language,
+                "allowed": RUNNABLE_LANGUAGES.iter().map(|(tag, _)| tag).collect::<Vec<_>>()
+            })));
+    };
+    Ok(LoadedBlock { content, block, declared, shell })
+}
+
+/// Indices of the blocks of `content` that can be run, in order
+pub fn runnable_blocks(content: &str) -> Vec<usize> {
+    blocks::parse(content)
+        .blocks
+        .iter()
+        .filter(|block| shell_for(block).is_some())
+        .map(|block| block.index)
+        .collect()
+}
+
+impl BlockRun {
+    fn new(path: String, loaded: &LoadedBlock) -> Self {
+        Self {
+            execution_id: Uuid::new_v4().to_string(),
+            path,
+            block_index: loaded.block.index,
+            language: loaded.block.language.clone(),
+            start_line: loaded.block.start_line,
+            file_hash: blocks::content_hash(&loaded.content),
+            block_hash: blocks::content_hash(&loaded.block.code),
+            session_id: None,
+            session_label: None,
+            approved_by: None,
+            output: None,
+            recorded_output: false,
+            dry_run: None,
+        }
+    }
+
+    /// Whether the block ran in the worktree and exited with 0
+    pub fn succeeded(&self) -> bool {
+        self.output.as_ref().is_some_and(|output| output.exit_code == Some(0))
+    }
+
+    /// The history entry for this run
+    fn execution(&self, token: Option<String>, branch: &str, schedule_id: Option<String>) -> history::Execution {
+        history::Execution {
+            id: self.execution_id.clone(),
+            timestamp: Utc::now(),
+            token,
+            branch: branch.to_string(),
+            path: self.path.clone(),
+            file_hash: self.file_hash.clone(),
+            kind: match self.dry_run {
+                Some(_) => history::ExecutionKind::DryRun {
+                    block_index: self.block_index,
+                    block_hash: self.block_hash.clone(),
+                    session_id: self.session_id.clone(),
+                    session_label: self.session_label.clone(),
+                    approved_by: self.approved_by.clone(),
+                },
+                None => history::ExecutionKind::BlockRun {
+                    block_index: self.block_index,
+                    block_hash: self.block_hash.clone(),
+                    session_id: self.session_id.clone(),
+                    session_label: self.session_label.clone(),
+                    approved_by: self.approved_by.clone(),
+                    schedule_id,
+                    exit_code: self.output.as_ref().and_then(|output| output.exit_code),
+                    timed_out: self.output.as_ref().is_some_and(|output| output.timed_out),
+                    duration_ms: self.output.as_ref().map(|output| output.duration_ms as u64),
+                },
+            },
+        }
+    }
+}
+
+/// POST /api/workspaces/{name}/branches/{branch}/blocks/run - Run a shell
+/// block of a markdown file, in a terminal session or in the worktree
+pub async fn run_block(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    body: Json<RunBlockRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let body = body.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    let loaded = load_block(&config, &worktree_path, &body.path, body.block_index)?;
+    let mut run = BlockRun::new(body.path, &loaded);
+    let LoadedBlock { block, declared, shell, .. } = loaded;
+
+    if body.record_output && body.session_id.is_some() {
+        return Err(ApiError::validation(
+            "output_not_captured",
+            "Output of a block typed into a terminal session can't be recorded",
+        ));
+    }
+    if let Some(session_id) = &body.session_id {
+        sessions::require_access(&req, &state.session_info(session_id).await?)?;
+    }
+    let substituted = variables::substitute(&block.code, &declared, &body.variables)?;
+
+    let token = audit::Actor::from_request(&req).token;
+    if approvals::required(&block) {
+        let request = approvals::ApprovalRequest {
+            workspace: &workspace,
+            branch: &branch,
+            path: &run.path,
+            block_index: run.block_index,
+            block_hash: &run.block_hash,
+            runner: token.as_deref(),
+        };
+        // A dry run leaves the approval for the real run
+        let approval = if body.dry_run {
+            state.approvals.check(body.approval_token.as_deref(), &request)?
+        } else {
+            state.approvals.take(body.approval_token.as_deref(), &request)?
+        };
+        run.approved_by = Some(approval.approver);
+    }
+
+    if !body.dry_run {
+        state.events.publish(Event::BlockExecuted {
+            workspace: workspace.clone(),
+            branch: branch.clone(),
+            path: run.path.clone(),
+            block_index: run.block_index,
+            execution_id: run.execution_id.clone(),
+            token: token.clone(),
+            session_id: body.session_id.clone(),
+        });
+    }
+
+    let mut recorded = Ok(());
+    match body.session_id {
+        Some(session_id) if body.dry_run => {
+            run.session_label = state.session_label(&session_id).await;
+            run.dry_run = Some(DryRun {
+                dry_run: true,
+                command: substituted.script(),
+                shell,
+                worktree_path: None,
+                timeout_secs: None,
+            });
+            run.session_id = Some(session_id);
+        }
+        None if body.dry_run => {
+            run.dry_run = Some(DryRun {
+                dry_run: true,
+                command: substituted.script(),
+                shell,
+                worktree_path: Some(worktree_path.to_string_lossy().into_owned()),
+                timeout_secs: Some(run_timeout(&block)?.as_secs()),
+            });
+        }
+        Some(session_id) => {
+            let mut input = substituted.script();
+            if !input.ends_with('\n') {
+                input.push('\n');
+            }
+            state.write_to_session(&session_id, input.as_bytes()).await?;
+            run.session_label = state.session_label(&session_id).await;
+            log::info!("Typed block {} of {} into session {}", run.block_index, run.path, session_id);
+            run.session_id = Some(session_id);
+        }
+        None => {
+            let mut env = workspace_env::resolve(&config, &workspace)?;
+            env.extend(substituted.variables);
+            let output = exec(shell, &substituted.code, &worktree_path, env, run_timeout(&block)?).await?;
+            log::info!(
+                "Ran block {} of {}: exit code {:?} in {}ms",
+                run.block_index, run.path, output.exit_code, output.duration_ms
+            );
+            if body.record_output {
+                let text = recorded_output(&output, config.get_max_recorded_output_bytes());
+                recorded = record(&worktree_path, &run, &text);
+                run.recorded_output = recorded.is_ok();
+            }
+            run.output = Some(output);
+        }
+    }
+
+    let execution = run.execution(token, &branch, None);
+    let stored = run
+        .output
+        .as_ref()
+        .map(|output| stored_output(output, config.get_max_recorded_output_bytes()));
+    history::record(&state, &config, &workspace, execution, stored).await;
+
+    audit::record(
+        &req,
+        "block.run",
+        serde_json::json!({
+            "execution_id": run.execution_id,
+            "workspace": workspace,
+            "branch": branch,
+            "path": run.path,
+            "block_index": run.block_index,
+            "file_hash": run.file_hash,
+            "block_hash": run.block_hash,
+            "session_id": run.session_id,
+            "session_label": run.session_label,
+            "approved_by": run.approved_by,
+            "dry_run": run.dry_run.is_some(),
+            // Names only: values may be sensitive
+            "variables": block.variables,
+            "exit_code": run.output.as_ref().and_then(|output| output.exit_code),
+            "timed_out": run.output.as_ref().is_some_and(|output| output.timed_out),
+            "recorded_output": run.recorded_output,
+        }),
+    );
+
+    // The run happened either way, so a failure to record it carries it
+    match recorded {
+        Ok(()) => Ok(HttpResponse::Ok().json(run)),
+        Err(e) => {
+            let run = serde_json::to_value(&run).unwrap_or_default();
+            Err(e.with_details(serde_json::json!({ "run": run })))
+        }
+    }
+}
+
+/// Run block `block_index` of a schedule's runbook in its worktree, on
+/// behalf of the token that created the schedule. Blocks that need an
+/// approval are refused: there is no one to approve a scheduled run.
+pub async fn run_scheduled(
+    state: &Arc<AppState>,
+    config: &ConfigManager,
+    schedule: &ScheduleConfig,
+    block_index: usize,
+) -> Result<BlockRun, ApiError> {
+    let worktree_path = workspace::require_worktree(config, &schedule.workspace, &schedule.branch)?;
+    let loaded = load_block(config, &worktree_path, &schedule.path, block_index)?;
+    let mut run = BlockRun::new(schedule.path.clone(), &loaded);
+    if approvals::required(&loaded.block) {
+        return Err(ApiError::forbidden(
+            "approval_required",
+            format!("Block {} of '{}' requires an approval, so it can't run on a schedule", block_index, run.path),
+        ));
+    }
+    let substituted = variables::substitute(&loaded.block.code, &loaded.declared, &schedule.variables)?;
+
+    state.events.publish(Event::BlockExecuted {
+        workspace: schedule.workspace.clone(),
+        branch: schedule.branch.clone(),
+        path: run.path.clone(),
+        block_index: run.block_index,
+        execution_id: run.execution_id.clone(),
+        token: schedule.created_by.clone(),
+        session_id: None,
+    });
+    let mut env = workspace_env::resolve(config, &schedule.workspace)?;
+    env.extend(substituted.variables);
+    let output = exec(loaded.shell, &substituted.code, &worktree_path, env, run_timeout(&loaded.block)?).await?;
+    log::info!(
+        "Schedule {} ran block {} of {}: exit code {:?} in {}ms",
+        schedule.id, run.block_index, run.path, output.exit_code, output.duration_ms
+    );
+    let stored = stored_output(&output, config.get_max_recorded_output_bytes());
+    run.output = Some(output);
+
+    let execution = run.execution(schedule.created_by.clone(), &schedule.branch, Some(schedule.id.clone()));
+    history::record(state, config, &schedule.workspace, execution, Some(stored)).await;
+
+    let actor = audit::Actor {
+        token: schedule.created_by.clone(),
+        ip: None,
+    };
+    state.audit_log.record_as(
+        &actor,
+        "block.run",
+        format!("SCHEDULE {}", schedule.id),
+        serde_json::json!({
+            "execution_id": run.execution_id,
+            "schedule_id": schedule.id,
+            "workspace": schedule.workspace,
+            "branch": schedule.branch,
+            "path": run.path,
+            "block_index": run.block_index,
+            "file_hash": run.file_hash,
+            "block_hash": run.block_hash,
+            "variables": loaded.block.variables,
+            "exit_code": run.output.as_ref().and_then(|output| output.exit_code),
+            "timed_out": run.output.as_ref().is_some_and(|output| output.timed_out),
+        }),
+    );
+    Ok(run)
+}
+
+/// Write a run's output into its runbook, unless the file changed while the
+/// block ran
+fn record(worktree_path: &std::path::Path, run: &BlockRun, output: &str) -> Result<(), ApiError> {
+    let content = file_ops::resolve_file(worktree_path, &run.path)
+        .and_then(std::fs::read_to_string)
+        .map_err(|e| ApiError::io("Failed to read runbook", e))?;
+    let changed = || {
+        ApiError::conflict("runbook_changed", format!("'{}' changed while the block ran", run.path))
+    };
+    if blocks::content_hash(&content) != run.file_hash {
+        return Err(changed());
+    }
+    let updated = blocks::record_output(&content, run.block_index, output).ok_or_else(changed)?;
+    file_ops::write_file(worktree_path, &run.path, &updated)
+        .map_err(|e| ApiError::io("Failed to record output", e))
+}
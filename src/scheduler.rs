@@ -0,0 +1,366 @@
+//! Scheduled runbook runs: the `schedules` section of the config names
+//! blocks of a runbook to run in its worktree on a cron schedule (UTC). A
+//! background task checks for due schedules every few seconds and runs each
+//! through the same path as a one-shot block run, so the runs land in the
+//! execution history and the audit log. A run stops at the first block
+//! that fails and publishes `ScheduleFailed`, which webhooks can alert on.
+//!
+//! A schedule still running when it comes due again is skipped. One whose
+//! workspace or worktree is gone is disabled, with the reason kept in the
+//! config, instead of failing on every tick.
+//!
+//! In read-only mode schedules are paused: nothing runs until the server is
+//! restarted without it.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth;
+use crate::blocks;
+use crate::config::{AllBlocks, BlockSelection, ConfigManager, ScheduleConfig};
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::extract::Json;
+use crate::runbook;
+use crate::workspace;
+use crate::AppState;
+
+/// How often the scheduler looks for due schedules
+const TICK: Duration = Duration::from_secs(5);
+
+/// `expr` parsed as a five-field cron expression; 400 `invalid_cron`
+fn parse_cron(expr: &str) -> Result<Cron, ApiError> {
+    Cron::new(expr)
+        .parse()
+        .map_err(|e| ApiError::validation("invalid_cron", format!("Invalid cron expression '{}': {}", expr, e)))
+}
+
+/// When `schedule` is next due after `after`, if it is enabled and its
+/// expression still parses
+fn next_run(schedule: &ScheduleConfig, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if !schedule.enabled {
+        return None;
+    }
+    parse_cron(&schedule.cron)
+        .ok()?
+        .find_next_occurrence(after, false)
+        .ok()
+}
+
+/// IDs of the schedules with a run in progress
+pub struct Scheduler {
+    running: Mutex<HashSet<String>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            running: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn is_running(&self, id: &str) -> bool {
+        self.running.lock().unwrap().contains(id)
+    }
+
+    /// Mark `id` as running; false if it already was
+    fn begin(&self, id: &str) -> bool {
+        self.running.lock().unwrap().insert(id.to_string())
+    }
+
+    fn finish(&self, id: &str) {
+        self.running.lock().unwrap().remove(id);
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check for due schedules every `TICK` for as long as the server runs
+pub fn start(state: Arc<AppState>, config: Arc<ConfigManager>) {
+    if config.read_only() {
+        log::warn!("Read-only mode: schedules are paused");
+    }
+    actix_rt::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        let mut last_tick = Utc::now();
+        loop {
+            interval.tick().await;
+            let now = Utc::now();
+            run_due(&state, &config, &last_tick, &now);
+            last_tick = now;
+        }
+    });
+}
+
+/// Start the runs of the schedules that came due after `since`, up to
+/// `now`; the IDs of those started. Nothing runs in read-only mode, which
+/// covers what the server does on its own as well as requests.
+pub fn run_due(state: &Arc<AppState>, config: &Arc<ConfigManager>, since: &DateTime<Utc>, now: &DateTime<Utc>) -> Vec<String> {
+    if config.read_only() {
+        return Vec::new();
+    }
+    let mut started = Vec::new();
+    for schedule in config.get_schedules() {
+        if next_run(&schedule, since).is_some_and(|due| due <= *now) {
+            let id = schedule.id.clone();
+            match trigger(state, config, schedule) {
+                Ok(()) => started.push(id),
+                Err(e) => log::debug!("Scheduled run not started: {}", e),
+            }
+        }
+    }
+    started
+}
+
+/// `ScheduleFailed` for a run of `schedule` that stopped at `block_index`
+fn failed(
+    schedule: &ScheduleConfig,
+    block_index: Option<usize>,
+    run: Option<&runbook::BlockRun>,
+    error: Option<String>,
+) -> Event {
+    Event::ScheduleFailed {
+        schedule_id: schedule.id.clone(),
+        workspace: schedule.workspace.clone(),
+        branch: schedule.branch.clone(),
+        path: schedule.path.clone(),
+        block_index,
+        execution_id: run.map(|run| run.execution_id.clone()),
+        exit_code: run.and_then(|run| run.output.as_ref()).and_then(|output| output.exit_code),
+        error,
+    }
+}
+
+/// Start a run of `schedule` in the background. 409 `schedule_running` if
+/// the last run hasn't finished; if its worktree is gone, the schedule is
+/// disabled and the 404 returned.
+fn trigger(state: &Arc<AppState>, config: &Arc<ConfigManager>, schedule: ScheduleConfig) -> Result<(), ApiError> {
+    if let Err(e) = workspace::require_worktree(config, &schedule.workspace, &schedule.branch) {
+        let reason = e.to_string();
+        log::warn!("Disabling schedule {}: {}", schedule.id, reason);
+        if let Err(save_error) = config.disable_schedule(&schedule.id, reason.clone()) {
+            log::error!("Failed to disable schedule {}: {}", schedule.id, save_error);
+        }
+        state.events.publish(failed(&schedule, None, None, Some(reason)));
+        return Err(e);
+    }
+    if !state.scheduler.begin(&schedule.id) {
+        log::warn!("Schedule {} is still running; skipping this run", schedule.id);
+        return Err(ApiError::conflict(
+            "schedule_running",
+            format!("Schedule '{}' is still running", schedule.id),
+        ));
+    }
+
+    let state = state.clone();
+    let config = config.clone();
+    actix_rt::spawn(async move {
+        log::info!("Running schedule {} ({} in {}/{})", schedule.id, schedule.path, schedule.workspace, schedule.branch);
+        if let Some(event) = run(&state, &config, &schedule).await {
+            log::warn!("Schedule {} failed", schedule.id);
+            state.events.publish(event);
+        }
+        state.scheduler.finish(&schedule.id);
+    });
+    Ok(())
+}
+
+/// Run the blocks of `schedule` in order, stopping at the first that fails;
+/// the `ScheduleFailed` event for that failure, if any
+async fn run(state: &Arc<AppState>, config: &ConfigManager, schedule: &ScheduleConfig) -> Option<Event> {
+    let indices = match &schedule.blocks {
+        BlockSelection::Indices(indices) => indices.clone(),
+        BlockSelection::All(AllBlocks::All) => {
+            match workspace::require_worktree(config, &schedule.workspace, &schedule.branch)
+                .and_then(|worktree_path| workspace::read_text_file(config, &worktree_path, &schedule.path))
+            {
+                Ok(content) => runbook::runnable_blocks(&content),
+                Err(e) => return Some(failed(schedule, None, None, Some(e.to_string()))),
+            }
+        }
+    };
+    for index in indices {
+        match runbook::run_scheduled(state, config, schedule, index).await {
+            Ok(run) if run.succeeded() => {}
+            Ok(run) => {
+                let error = run
+                    .output
+                    .as_ref()
+                    .filter(|output| output.timed_out)
+                    .map(|_| "Timed out".to_string());
+                return Some(failed(schedule, Some(index), Some(&run), error));
+            }
+            Err(e) => return Some(failed(schedule, Some(index), None, Some(e.to_string()))),
+        }
+    }
+    None
+}
+
+/// A schedule as the API lists it
+#[derive(Debug, Serialize)]
+pub struct ScheduleInfo {
+    #[serde(flatten)]
+    pub schedule: ScheduleConfig,
+    pub next_run: Option<DateTime<Utc>>,
+    pub running: bool,
+}
+
+fn info(state: &AppState, schedule: ScheduleConfig) -> ScheduleInfo {
+    ScheduleInfo {
+        next_run: next_run(&schedule, &Utc::now()),
+        running: state.scheduler.is_running(&schedule.id),
+        schedule,
+    }
+}
+
+fn schedule_not_found(id: &str) -> ApiError {
+    ApiError::not_found("schedule_not_found", format!("Schedule '{}' not found", id))
+}
+
+/// Schedule `id`, if the token of `req` may use its workspace
+fn find_schedule(req: &HttpRequest, config: &ConfigManager, id: &str) -> Result<ScheduleConfig, ApiError> {
+    let schedule = config
+        .get_schedules()
+        .into_iter()
+        .find(|schedule| schedule.id == id)
+        .ok_or_else(|| schedule_not_found(id))?;
+    auth::require_workspace_access(req, &schedule.workspace)?;
+    Ok(schedule)
+}
+
+/// GET /api/schedules - List schedules with when they next run; only those
+/// in workspaces the token may use
+pub async fn list_schedules(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+) -> HttpResponse {
+    let schedules: Vec<ScheduleInfo> = config
+        .get_schedules()
+        .into_iter()
+        .filter(|schedule| auth::may_use_workspace(&req, &schedule.workspace))
+        .map(|schedule| info(&state, schedule))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "schedules": schedules }))
+}
+
+fn all_blocks() -> BlockSelection {
+    BlockSelection::All(AllBlocks::All)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateScheduleRequest {
+    pub workspace: String,
+    pub branch: String,
+    pub path: String,
+    /// Block indices, or `"all"` for every runnable block
+    #[serde(default = "all_blocks")]
+    pub blocks: BlockSelection,
+    pub cron: String,
+    #[serde(default)]
+    pub variables: serde_json::Map<String, serde_json::Value>,
+}
+
+/// POST /api/schedules - Run blocks of a runbook on a cron schedule
+pub async fn create_schedule(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+    body: Json<CreateScheduleRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    auth::require_workspace_access(&req, &body.workspace)?;
+    parse_cron(&body.cron)?;
+    let worktree_path = workspace::require_worktree(&config, &body.workspace, &body.branch)?;
+    let content = workspace::read_text_file(&config, &worktree_path, &body.path)?;
+    if let BlockSelection::Indices(indices) = &body.blocks {
+        let count = blocks::parse(&content).blocks.len();
+        if let Some(index) = indices.iter().find(|index| **index >= count) {
+            return Err(ApiError::not_found(
+                "block_not_found",
+                format!("'{}' has no code block {}", body.path, index),
+            )
+            .with_details(serde_json::json!({ "block_count": count })));
+        }
+    }
+
+    let schedule = ScheduleConfig {
+        id: Uuid::new_v4().to_string(),
+        workspace: body.workspace,
+        branch: body.branch,
+        path: body.path,
+        blocks: body.blocks,
+        cron: body.cron,
+        variables: body.variables,
+        enabled: true,
+        disabled_reason: None,
+        created_at: Utc::now(),
+        created_by: audit::Actor::from_request(&req).token,
+    };
+    config
+        .add_schedule(schedule.clone())
+        .map_err(|e| ApiError::internal(format!("Failed to save config: {}", e)))?;
+    log::info!("Scheduled {} in {}/{} at '{}'", schedule.path, schedule.workspace, schedule.branch, schedule.cron);
+    audit::record(
+        &req,
+        "schedule.create",
+        serde_json::json!({
+            "schedule_id": schedule.id,
+            "workspace": schedule.workspace,
+            "branch": schedule.branch,
+            "path": schedule.path,
+            "blocks": schedule.blocks,
+            "cron": schedule.cron,
+            // Names only: values may be sensitive
+            "variables": schedule.variables.keys().collect::<Vec<_>>(),
+        }),
+    );
+    Ok(HttpResponse::Created().json(info(&state, schedule)))
+}
+
+/// DELETE /api/schedules/{id} - Remove a schedule; a run in progress
+/// finishes
+pub async fn delete_schedule(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    find_schedule(&req, &config, &id)?;
+    match config.remove_schedule(&id) {
+        Ok(true) => {
+            log::info!("Removed schedule {}", id);
+            audit::record(&req, "schedule.delete", serde_json::json!({ "schedule_id": id }));
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "message": format!("Schedule '{}' removed", id)
+            })))
+        }
+        Ok(false) => Err(schedule_not_found(&id)),
+        Err(e) => Err(ApiError::internal(format!("Failed to save config: {}", e))),
+    }
+}
+
+/// POST /api/schedules/{id}/run - Start a run of a schedule now
+pub async fn run_schedule(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let schedule = find_schedule(&req, &config, &id)?;
+    trigger(&state, &config, schedule)?;
+    audit::record(&req, "schedule.run", serde_json::json!({ "schedule_id": id }));
+    Ok(HttpResponse::Accepted().json(serde_json::json!({ "schedule_id": id, "running": true })))
+}
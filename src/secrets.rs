@@ -0,0 +1,152 @@
+//! Encryption of secret values kept in the config file. The key is derived
+//! from a server secret file (`secret_key_file`, by default `secret.key` next
+//! to the config file) that is created with random content the first time a
+//! secret is stored; it can be kept on a different volume than the config,
+//! so a copy of the config alone doesn't give the secrets away.
+//!
+//! A sealed value is `v1:` followed by the base64 of a random nonce and the
+//! ChaCha20-Poly1305 ciphertext. The name the value is stored under is
+//! authenticated with it, so values can't be swapped between names.
+
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+const SEALED_PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+/// Shortest secret file accepted; a generated one holds 64 hex digits
+const MIN_SECRET_LEN: usize = 32;
+
+/// Domain of the derived key, so the secret file could serve other uses
+const KEY_CONTEXT: &[u8] = b"runotepad secret_env v1";
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// The secret file couldn't be read or created
+    Io(io::Error),
+    /// The secret file is shorter than `MIN_SECRET_LEN`
+    WeakSecret,
+    /// Not a sealed value, or sealed with another key
+    Undecryptable,
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::Io(e) => write!(f, "{}", e),
+            SecretError::WeakSecret => {
+                write!(f, "the secret key file must hold at least {} characters", MIN_SECRET_LEN)
+            }
+            SecretError::Undecryptable => write!(f, "the value can't be decrypted with the current secret key"),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+impl From<io::Error> for SecretError {
+    fn from(e: io::Error) -> Self {
+        SecretError::Io(e)
+    }
+}
+
+/// Key sealing secret values
+pub struct SecretKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl SecretKey {
+    fn derive(secret: &[u8]) -> Result<Self, SecretError> {
+        let secret = secret.trim_ascii();
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(SecretError::WeakSecret);
+        }
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC takes keys of any length");
+        mac.update(KEY_CONTEXT);
+        let key = mac.finalize().into_bytes();
+        Ok(Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        })
+    }
+
+    /// The key from the secret file at `path`
+    pub fn load(path: &Path) -> Result<Self, SecretError> {
+        Self::derive(&std::fs::read(path)?)
+    }
+
+    /// The key from the secret file at `path`, creating the file (readable
+    /// by the owner only) if there is none yet
+    pub fn load_or_create(path: &Path) -> Result<Self, SecretError> {
+        match std::fs::read(path) {
+            Ok(secret) => Self::derive(&secret),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut bytes = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut bytes);
+                let secret: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                create_secret_file(path, secret.as_bytes())?;
+                log::info!("Created secret key file {:?}", path);
+                Self::derive(secret.as_bytes())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// `value` sealed for storage under `name`
+    pub fn seal(&self, name: &str, value: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: name.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .expect("encrypting to memory doesn't fail");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        format!("{}{}", SEALED_PREFIX, base64::engine::general_purpose::STANDARD.encode(sealed))
+    }
+
+    /// The value `seal` stored under `name`
+    pub fn open(&self, name: &str, sealed: &str) -> Result<String, SecretError> {
+        let sealed = sealed
+            .strip_prefix(SEALED_PREFIX)
+            .and_then(|encoded| base64::engine::general_purpose::STANDARD.decode(encoded).ok())
+            .filter(|sealed| sealed.len() > NONCE_LEN)
+            .ok_or(SecretError::Undecryptable)?;
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: name.as_bytes(),
+        };
+        let value = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| SecretError::Undecryptable)?;
+        String::from_utf8(value).map_err(|_| SecretError::Undecryptable)
+    }
+}
+
+fn create_secret_file(path: &Path, secret: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(secret)?;
+    file.sync_all()
+}
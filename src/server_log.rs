@@ -0,0 +1,218 @@
+//! The server's own log, for the admin UI: records at or above
+//! `log_buffer_level` are kept in a ring buffer (`GET /api/admin/logs`) and
+//! streamed to whoever is tailing them (`GET /api/admin/logs/stream`).
+//!
+//! What can't be shown there never reaches the capture: browser console
+//! messages (which may hold terminal output) are written past it with
+//! `logging::log_uncaptured`, the PTY reader only logs byte counts, and the
+//! access log leaves out query strings, where `?token=` would be.
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::errors::ApiError;
+use crate::logging;
+
+/// Records kept until `configure` says otherwise
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// Records a live tail may fall behind before it is disconnected
+const CHANNEL_CAPACITY: usize = 1024;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+const DEFAULT_LOG_LIMIT: usize = 100;
+
+/// A log record as the admin endpoints return it
+#[derive(Debug, Clone, Serialize)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Utc>,
+    pub level: &'static str,
+    pub target: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+struct Buffer {
+    level: LevelFilter,
+    capacity: usize,
+    records: VecDeque<LogRecord>,
+}
+
+/// The ring buffer and live channel the logger tees records into
+pub struct LogCapture {
+    buffer: Mutex<Buffer>,
+    sender: broadcast::Sender<LogRecord>,
+}
+
+/// The process-wide capture; the logger is global, so this is too
+pub fn capture() -> &'static LogCapture {
+    static CAPTURE: OnceLock<LogCapture> = OnceLock::new();
+    CAPTURE.get_or_init(|| LogCapture {
+        buffer: Mutex::new(Buffer {
+            level: LevelFilter::Info,
+            capacity: DEFAULT_CAPACITY,
+            records: VecDeque::new(),
+        }),
+        sender: broadcast::channel(CHANNEL_CAPACITY).0,
+    })
+}
+
+impl LogCapture {
+    /// Keep the latest `capacity` records at `level` or above
+    pub fn configure(&self, level: LevelFilter, capacity: usize) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.level = level;
+        buffer.capacity = capacity;
+        while buffer.records.len() > capacity {
+            buffer.records.pop_front();
+        }
+        if level > log::max_level() {
+            log::set_max_level(level);
+        }
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        self.buffer.lock().unwrap().level
+    }
+
+    /// Keep `record` if it is at the configured level
+    pub(crate) fn record(&self, record: &log::Record) {
+        let mut buffer = self.buffer.lock().unwrap();
+        if record.level() > buffer.level || buffer.capacity == 0 {
+            return;
+        }
+        let ctx = logging::current().unwrap_or_default();
+        let record = LogRecord {
+            timestamp: Utc::now(),
+            level: record.level().as_str(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            request_id: ctx.request_id,
+            session_id: ctx.session_id,
+        };
+        if buffer.records.len() == buffer.capacity {
+            buffer.records.pop_front();
+        }
+        buffer.records.push_back(record.clone());
+        // No one tailing is fine
+        let _ = self.sender.send(record);
+    }
+
+    /// Records at `level` or above, paged like the audit log: strictly after
+    /// `since` (oldest first, up to `limit`), or the latest `limit`
+    fn query(&self, level: LevelFilter, since: Option<DateTime<Utc>>, limit: usize) -> Vec<LogRecord> {
+        let buffer = self.buffer.lock().unwrap();
+        let matching = buffer.records.iter().filter(|record| at_level(record, level));
+        match since {
+            Some(since) => matching
+                .filter(|record| record.timestamp > since)
+                .take(limit)
+                .cloned()
+                .collect(),
+            None => {
+                let mut latest: Vec<LogRecord> = matching.rev().take(limit).cloned().collect();
+                latest.reverse();
+                latest
+            }
+        }
+    }
+}
+
+fn at_level(record: &LogRecord, level: LevelFilter) -> bool {
+    log::Level::from_str(record.level).is_ok_and(|record_level| record_level <= level)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogQuery {
+    /// Only records at this level or more severe
+    pub level: Option<String>,
+    pub limit: Option<usize>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+fn parse_level(level: Option<&str>) -> Result<LevelFilter, ApiError> {
+    match level {
+        None => Ok(LevelFilter::Trace),
+        Some(level) => match log::Level::from_str(level) {
+            Ok(level) => Ok(level.to_level_filter()),
+            Err(_) => Err(ApiError::validation("invalid_query", format!("Unknown level '{}'", level))
+                .with_details(serde_json::json!({ "allowed": ["trace", "debug", "info", "warn", "error"] }))),
+        },
+    }
+}
+
+/// GET /api/admin/logs - Recent server log records
+pub async fn list_logs(query: web::Query<LogQuery>) -> Result<HttpResponse, ApiError> {
+    let level = parse_level(query.level.as_deref())?;
+    let limit = query.limit.unwrap_or(DEFAULT_LOG_LIMIT).max(1);
+    Ok(HttpResponse::Ok().json(capture().query(level, query.since, limit)))
+}
+
+fn frame(data: &serde_json::Value) -> web::Bytes {
+    web::Bytes::from(format!("data: {}\n\n", data))
+}
+
+/// GET /api/admin/logs/stream - Stream server log records as
+/// `text/event-stream`
+pub async fn stream_logs(query: web::Query<LogQuery>) -> Result<HttpResponse, ApiError> {
+    struct Stream {
+        records: broadcast::Receiver<LogRecord>,
+        heartbeat: tokio::time::Interval,
+        level: LevelFilter,
+    }
+
+    let start = tokio::time::Instant::now() + HEARTBEAT_INTERVAL;
+    let stream = Stream {
+        records: capture().sender.subscribe(),
+        heartbeat: tokio::time::interval_at(start, HEARTBEAT_INTERVAL),
+        level: parse_level(query.level.as_deref())?,
+    };
+    log::debug!("Log stream opened");
+
+    // The state is None once the stream has ended
+    let body = futures::stream::unfold(Some(stream), |stream| async move {
+        let mut stream = stream?;
+        loop {
+            tokio::select! {
+                _ = stream.heartbeat.tick() => {
+                    let beat = web::Bytes::from_static(b": heartbeat\n\n");
+                    return Some((Ok::<_, std::io::Error>(beat), Some(stream)));
+                }
+                received = stream.records.recv() => match received {
+                    Ok(record) => {
+                        if !at_level(&record, stream.level) {
+                            continue;
+                        }
+                        let data = serde_json::to_value(&record).unwrap_or_default();
+                        return Some((Ok(frame(&data)), Some(stream)));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        let data = serde_json::json!({ "type": "lagged", "missed": missed });
+                        return Some((Ok(frame(&data)), None));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // Keep nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        // Compression would hold events back until the encoder's buffer fills
+        .insert_header(actix_web::http::header::ContentEncoding::Identity)
+        .streaming(body))
+}
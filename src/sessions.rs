@@ -0,0 +1,625 @@
+//! Terminal sessions as the API lists them. A session can be opened with a
+//! label ("prod-db shell") and the runbook it serves, so the server side can
+//! tell five incident terminals apart; the label is carried into the audit
+//! log and the execution history next to the session ID.
+//!
+//! The output of every session is recorded, its start and its end kept when
+//! it runs long, so that a session opened for a runbook can attach what was
+//! done in it to the runbook when it is closed. With
+//! `session_scrollback_bytes` set, the latest output of each session is also
+//! kept for reading and searching over REST, once it has scrolled past.
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::audit;
+use crate::auth;
+use crate::blocks;
+use crate::config::{ConfigManager, TokenIdentity};
+use crate::errors::ApiError;
+use crate::file_ops;
+use crate::git_ops;
+use crate::logging;
+use crate::quota;
+use crate::workspace;
+use crate::AppState;
+
+/// Longest label kept, in characters
+pub const MAX_LABEL_CHARS: usize = 64;
+const MAX_RUNBOOK_PATH_CHARS: usize = 1024;
+
+/// Output kept from the start of a session, in bytes
+pub const TRANSCRIPT_HEAD_BYTES: usize = 32 * 1024;
+/// Output kept from the end of a session, in bytes
+pub const TRANSCRIPT_TAIL_BYTES: usize = 32 * 1024;
+
+const DEFAULT_BUFFER_MATCHES: usize = 100;
+const MAX_BUFFER_MATCHES: usize = 1000;
+/// Output shown before and after a match, in bytes
+const MATCH_CONTEXT_BYTES: usize = 80;
+
+/// `text` without control characters, trimmed and cut to `max_chars`; None
+/// if nothing is left
+fn clean(text: &str, max_chars: usize) -> Option<String> {
+    let cleaned: String = text.chars().filter(|c| !c.is_control()).collect();
+    let cleaned: String = cleaned.trim().chars().take(max_chars).collect();
+    let cleaned = cleaned.trim_end();
+    (!cleaned.is_empty()).then(|| cleaned.to_string())
+}
+
+pub fn clean_label(label: &str) -> Option<String> {
+    clean(label, MAX_LABEL_CHARS)
+}
+
+pub fn clean_runbook_path(path: &str) -> Option<String> {
+    clean(path, MAX_RUNBOOK_PATH_CHARS)
+}
+
+/// What a terminal session is and who opened it
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub label: Option<String>,
+    /// Runbook the session was opened for
+    pub runbook_path: Option<String>,
+    /// Workspace (and branch) the shell was started in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub opened_at: DateTime<Utc>,
+    pub opened_by: Option<String>,
+    /// Working directory of the shell, where the platform exposes it; only
+    /// filled in when listing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+}
+
+/// Output of a terminal session as it was written to the terminal: the
+/// first and the last bytes of it, and how much there was
+#[derive(Debug, Default)]
+pub struct Transcript {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    total: u64,
+}
+
+impl Transcript {
+    pub fn push(&mut self, data: &[u8]) {
+        self.total += data.len() as u64;
+        let room = TRANSCRIPT_HEAD_BYTES - self.head.len();
+        let (head, rest) = data.split_at(room.min(data.len()));
+        self.head.extend_from_slice(head);
+        self.tail.extend(rest);
+        let excess = self.tail.len().saturating_sub(TRANSCRIPT_TAIL_BYTES);
+        self.tail.drain(..excess);
+    }
+
+    /// Bytes left out between the head and the tail
+    pub fn omitted(&self) -> u64 {
+        self.total - (self.head.len() + self.tail.len()) as u64
+    }
+
+    /// What the terminal showed, as plain text
+    pub fn text(&self) -> String {
+        let mut text = terminal_text(&String::from_utf8_lossy(&self.head));
+        if self.omitted() > 0 {
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str(&format!("[... {} bytes left out ...]\n", self.omitted()));
+        }
+        let tail: Vec<u8> = self.tail.iter().copied().collect();
+        text.push_str(&terminal_text(&String::from_utf8_lossy(&tail)));
+        text
+    }
+}
+
+/// The latest output of a terminal session, up to `capacity` bytes, with
+/// the offset of its first byte in everything the session wrote
+#[derive(Debug)]
+pub struct Scrollback {
+    data: VecDeque<u8>,
+    capacity: usize,
+    total: u64,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Scrollback {
+            data: VecDeque::new(),
+            capacity,
+            total: 0,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.total += data.len() as u64;
+        self.data.extend(data);
+        let excess = self.data.len().saturating_sub(self.capacity);
+        self.data.drain(..excess);
+    }
+
+    /// Offset of the first byte kept, which is how much scrolled out
+    pub fn start_offset(&self) -> u64 {
+        self.total - self.data.len() as u64
+    }
+
+    /// The last `tail_bytes` kept, or all of them, and their offset
+    pub fn tail(&self, tail_bytes: Option<usize>) -> (u64, Vec<u8>) {
+        let skip = tail_bytes.map_or(0, |tail| self.data.len().saturating_sub(tail));
+        (self.start_offset() + skip as u64, self.data.iter().skip(skip).copied().collect())
+    }
+}
+
+/// Skip the rest of an escape sequence whose ESC was just read
+fn skip_escape(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    match chars.next() {
+        // CSI: parameters up to a final byte
+        Some('[') => {
+            for c in chars.by_ref() {
+                if ('\u{40}'..='\u{7e}').contains(&c) {
+                    break;
+                }
+            }
+        }
+        // OSC, DCS and the like: up to BEL or ESC \
+        Some(']' | 'P' | 'X' | '^' | '_') => {
+            while let Some(c) = chars.next() {
+                if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                    break;
+                }
+            }
+        }
+        // Character set designations take one more character
+        Some('(' | ')' | '*' | '+' | '#' | '%') => {
+            chars.next();
+        }
+        _ => {}
+    }
+}
+
+/// `output` of a terminal without escape sequences and control characters,
+/// with carriage returns and backspaces applied to the line they are on
+pub fn terminal_text(output: &str) -> String {
+    let mut text = String::new();
+    // Where the line being written starts in `text`
+    let mut line_start = 0;
+    let mut chars = output.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' => skip_escape(&mut chars),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            // The line is written over, as progress bars do
+            '\r' => text.truncate(line_start),
+            '\n' => {
+                text.push('\n');
+                line_start = text.len();
+            }
+            '\u{8}' => {
+                if text.len() > line_start {
+                    text.pop();
+                }
+            }
+            '\t' => text.push('\t'),
+            c if c.is_control() => {}
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// Markdown section with the transcript of a session and when and how it
+/// ended; `exit_code` is None for a shell that was still running
+pub fn transcript_section(
+    info: &SessionInfo,
+    transcript: &Transcript,
+    ended_at: DateTime<Utc>,
+    exit_code: Option<u32>,
+) -> String {
+    let text = transcript.text();
+    let fence = blocks::fence_for(&text);
+    let mut section = format!(
+        "## Terminal transcript: {}\n\n- Session: {}\n- Started: {}\n- Ended: {}\n",
+        info.label.as_deref().unwrap_or(&info.session_id),
+        info.session_id,
+        info.opened_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        ended_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+    );
+    match exit_code {
+        Some(code) => section.push_str(&format!("- Exit code: {}\n", code)),
+        None => section.push_str("- Exit code: none, the shell was still running\n"),
+    }
+    if transcript.omitted() > 0 {
+        section.push_str(&format!(
+            "- Output: {} bytes, of which the middle {} are left out\n",
+            transcript.total,
+            transcript.omitted()
+        ));
+    }
+    section.push_str(&format!("\n{}text\n{}", fence, text));
+    if !text.is_empty() && !text.ends_with('\n') {
+        section.push('\n');
+    }
+    section.push_str(&fence);
+    section.push('\n');
+    section
+}
+
+/// Where a session's transcript was written
+#[derive(Debug, Clone, Serialize)]
+pub struct AttachedTranscript {
+    /// Relative to the worktree: the runbook, or its `.transcript.md`
+    pub path: String,
+    pub bytes: usize,
+    pub truncated: bool,
+}
+
+/// `runbook.transcript.md` next to `runbook.md`
+fn sibling_path(runbook_path: &str) -> String {
+    let stem = runbook_path
+        .strip_suffix(".md")
+        .or_else(|| runbook_path.strip_suffix(".markdown"))
+        .unwrap_or(runbook_path);
+    format!("{}.transcript.md", stem)
+}
+
+/// Append `section`, a transcript of the session `info` describes, to the
+/// runbook it was opened for and stage it. A runbook whose end would
+/// swallow the section (an unclosed fence) or that isn't markdown gets it in
+/// a `.transcript.md` file next to it instead.
+pub async fn attach_transcript(
+    config: &Arc<ConfigManager>,
+    state: &Arc<AppState>,
+    info: &SessionInfo,
+    section: String,
+    truncated: bool,
+) -> Result<AttachedTranscript, ApiError> {
+    let (Some(runbook_path), Some(workspace), Some(branch)) = (&info.runbook_path, &info.workspace, &info.branch)
+    else {
+        return Err(ApiError::validation(
+            "transcript_not_attachable",
+            "Only a session opened for a runbook in a branch of a workspace can attach its transcript",
+        ));
+    };
+    let worktree_path = workspace::require_worktree(config, workspace, branch)?;
+    quota::require_space(config, workspace)?;
+    // Staging the file writes the index, like the worktree's git operations
+    let _lock = workspace::lock_worktree(config, state, &worktree_path, "attach_transcript").await?;
+
+    let config = config.clone();
+    let state = state.clone();
+    let (runbook_path, workspace, branch) = (runbook_path.clone(), workspace.clone(), branch.clone());
+    logging::block(move || {
+        let read = |path: &str| {
+            file_ops::resolve_file(&worktree_path, path)
+                .and_then(std::fs::read_to_string)
+                .map_err(|e| ApiError::io("Failed to read runbook", e))
+        };
+        let _guard = state.file_write_lock.lock().unwrap();
+        let content = read(&runbook_path)?;
+        let appended = file_ops::is_markdown_file(&runbook_path)
+            .then(|| blocks::append_section(&content, &section))
+            .flatten();
+        let (path, updated) = match appended {
+            Some(updated) => (runbook_path.clone(), updated),
+            None => {
+                let path = sibling_path(&runbook_path);
+                let existing = match read(&path) {
+                    Ok(existing) => existing,
+                    Err(ApiError::Io(_, e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                        format!("# Transcripts of {}\n", runbook_path)
+                    }
+                    Err(e) => return Err(e),
+                };
+                let updated = blocks::append_section(&existing, &section).ok_or_else(|| {
+                    ApiError::conflict(
+                        "transcript_not_attachable",
+                        format!("'{}' ends in an unclosed block; close it to attach transcripts", path),
+                    )
+                })?;
+                (path, updated)
+            }
+        };
+
+        let written = file_ops::write_file(&worktree_path, &path, &updated);
+        state.listings.invalidate_tree(&workspace, &branch);
+        written.map_err(|e| ApiError::io("Failed to attach transcript", e))?;
+        config.add_workspace_usage(&workspace, section.len() as u64);
+        git_ops::stage_files(&worktree_path, std::slice::from_ref(&path))
+            .map_err(|e| e.context("Failed to stage transcript"))?;
+
+        Ok(AttachedTranscript {
+            path,
+            bytes: section.len(),
+            truncated,
+        })
+    })
+    .await?
+}
+
+/// Whether `identity` may use a terminal started in `workspace`; those
+/// started outside a workspace are only for tokens without workspace limits
+pub fn may_use(identity: Option<&TokenIdentity>, workspace: Option<&str>) -> bool {
+    identity.is_none_or(|identity| match workspace {
+        Some(workspace) => identity.allows_workspace(workspace),
+        None => !identity.is_restricted(),
+    })
+}
+
+/// 403 `workspace_forbidden` unless `identity` may open a terminal in
+/// `workspace`
+pub fn check_access(identity: Option<&TokenIdentity>, workspace: Option<&str>) -> Result<(), ApiError> {
+    match workspace {
+        _ if may_use(identity, workspace) => Ok(()),
+        Some(workspace) => Err(auth::workspace_forbidden(workspace)),
+        None => Err(ApiError::forbidden(
+            "workspace_forbidden",
+            "Token is limited to some workspaces; open the terminal in one of them",
+        )),
+    }
+}
+
+/// 403 `workspace_forbidden` unless the token of `req` may use session `info`
+pub fn require_access(req: &HttpRequest, info: &SessionInfo) -> Result<(), ApiError> {
+    match &info.workspace {
+        Some(workspace) => auth::require_workspace_access(req, workspace),
+        None => auth::require_unrestricted(req, "use terminals started outside a workspace"),
+    }
+}
+
+/// GET /api/sessions - Open terminal sessions, oldest first; only those
+/// the token may use
+pub async fn list_sessions(req: HttpRequest, state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let identity = req.extensions().get::<TokenIdentity>().cloned();
+    let sessions: Vec<SessionInfo> = state
+        .session_infos()
+        .await
+        .into_iter()
+        .filter(|info| may_use(identity.as_ref(), info.workspace.as_deref()))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "sessions": sessions }))
+}
+
+/// Signal a terminal session can be closed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Signal {
+    SIGHUP,
+    SIGINT,
+    SIGTERM,
+    SIGKILL,
+}
+
+impl Signal {
+    /// `SIGTERM`, `sigterm` or `TERM`
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        [Signal::SIGHUP, Signal::SIGINT, Signal::SIGTERM, Signal::SIGKILL]
+            .into_iter()
+            .find(|signal| signal.name() == name)
+    }
+
+    /// Name as `kill -s` takes it
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::SIGHUP => "HUP",
+            Signal::SIGINT => "INT",
+            Signal::SIGTERM => "TERM",
+            Signal::SIGKILL => "KILL",
+        }
+    }
+}
+
+/// How a terminal session was closed from the API
+#[derive(Debug, Serialize)]
+pub struct Terminated {
+    pub session_id: String,
+    pub label: Option<String>,
+    pub signal: Signal,
+    /// The shell had exited before the signal; the session was only
+    /// cleaned up
+    pub already_exited: bool,
+    /// The shell outlived the signal and was killed
+    pub forced: bool,
+    /// None when the shell died of a signal
+    pub exit_code: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TerminateQuery {
+    /// `SIGHUP`, `SIGINT`, `SIGTERM` (the default) or `SIGKILL`
+    pub signal: Option<String>,
+}
+
+/// DELETE /api/sessions/{id}?signal=SIGKILL - Close any terminal session:
+/// signal its shell and the program it is running, kill them if they
+/// outlive the signal, and tell the session's WebSocket client
+pub async fn terminate_session(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    query: web::Query<TerminateQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let signal = match query.signal.as_deref() {
+        Some(name) => Signal::parse(name).ok_or_else(|| {
+            ApiError::validation("invalid_signal", format!("Unknown signal '{}'", name))
+                .with_details(serde_json::json!({ "allowed": ["SIGHUP", "SIGINT", "SIGTERM", "SIGKILL"] }))
+        })?,
+        None => Signal::SIGTERM,
+    };
+
+    require_access(&req, &state.session_info(&id).await?)?;
+    let terminated = state.terminate_session(&id, signal).await?;
+    audit::record(
+        &req,
+        "terminal.terminate",
+        serde_json::json!({
+            "session_id": terminated.session_id,
+            "label": terminated.label,
+            "signal": terminated.signal,
+            "already_exited": terminated.already_exited,
+            "forced": terminated.forced,
+        }),
+    );
+    Ok(HttpResponse::Ok().json(terminated))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BufferQuery {
+    /// Only the last bytes kept [default: all of them]
+    pub tail_bytes: Option<usize>,
+    /// Take escape sequences and control characters out, as `terminal_text`
+    /// does
+    #[serde(default)]
+    pub strip_ansi: bool,
+}
+
+/// The scrollback of session `id`, if the token of `req` may use it
+async fn require_scrollback(
+    req: &HttpRequest,
+    state: &AppState,
+    id: &str,
+) -> Result<(SessionInfo, Arc<std::sync::Mutex<Scrollback>>), ApiError> {
+    let (info, scrollback) = state.session_scrollback(id).await?;
+    require_access(req, &info)?;
+    let scrollback = scrollback.ok_or_else(|| {
+        ApiError::validation(
+            "scrollback_disabled",
+            "Terminal output isn't kept; set session_scrollback_bytes to keep it",
+        )
+    })?;
+    Ok((info, scrollback))
+}
+
+/// GET /api/sessions/{id}/buffer?tail_bytes=n - The latest output of a
+/// terminal session
+pub async fn read_buffer(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    query: web::Query<BufferQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let (info, scrollback) = require_scrollback(&req, &state, &id).await?;
+    let (start_offset, data) = scrollback.lock().unwrap().tail(query.tail_bytes);
+    let content = match query.strip_ansi {
+        true => terminal_text(&String::from_utf8_lossy(&data)),
+        false => String::from_utf8_lossy(&data).into_owned(),
+    };
+
+    // Terminal output can hold secrets, so every read is recorded
+    audit::record(
+        &req,
+        "terminal.buffer",
+        serde_json::json!({
+            "session_id": info.session_id,
+            "label": info.label,
+            "bytes": data.len()
+        }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "session_id": info.session_id,
+        "start_offset": start_offset,
+        "bytes": data.len(),
+        "stripped": query.strip_ansi,
+        "content": content
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BufferSearchQuery {
+    pub q: String,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    #[serde(default)]
+    pub strip_ansi: bool,
+    pub limit: Option<usize>,
+}
+
+/// A match in the output of a session. Offsets are in everything the
+/// session wrote, or with `strip_ansi` in the stripped scrollback.
+#[derive(Debug, Serialize)]
+pub struct BufferMatch {
+    pub start_byte: u64,
+    pub end_byte: u64,
+    pub before: String,
+    pub text: String,
+    pub after: String,
+}
+
+/// Up to `limit` matches of `q` in `output`, a scrollback starting at
+/// `start_offset`, and whether there were more
+pub fn search_output(
+    output: &[u8],
+    start_offset: u64,
+    query: &BufferSearchQuery,
+    limit: usize,
+) -> Result<(Vec<BufferMatch>, bool), regex::Error> {
+    let stripped;
+    let (base, haystack) = match query.strip_ansi {
+        true => {
+            stripped = terminal_text(&String::from_utf8_lossy(output));
+            (0, stripped.as_bytes())
+        }
+        false => (start_offset, output),
+    };
+    let pattern = regex::bytes::RegexBuilder::new(&regex::escape(&query.q))
+        .case_insensitive(!query.case_sensitive)
+        .build()?;
+    let text = |range: std::ops::Range<usize>| String::from_utf8_lossy(&haystack[range]).into_owned();
+    let mut found = pattern.find_iter(haystack);
+    let matches = found
+        .by_ref()
+        .take(limit)
+        .map(|m| BufferMatch {
+            start_byte: base + m.start() as u64,
+            end_byte: base + m.end() as u64,
+            before: text(m.start().saturating_sub(MATCH_CONTEXT_BYTES)..m.start()),
+            text: text(m.range()),
+            after: text(m.end()..(m.end() + MATCH_CONTEXT_BYTES).min(haystack.len())),
+        })
+        .collect();
+    Ok((matches, found.next().is_some()))
+}
+
+/// GET /api/sessions/{id}/buffer/search?q=x - Find a string in the latest
+/// output of a terminal session
+pub async fn search_buffer(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    query: web::Query<BufferSearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    if query.q.is_empty() {
+        return Err(ApiError::validation("empty_query", "Search query must not be empty"));
+    }
+    let (info, scrollback) = require_scrollback(&req, &state, &id).await?;
+    let (start_offset, output) = scrollback.lock().unwrap().tail(None);
+    let limit = query.limit.unwrap_or(DEFAULT_BUFFER_MATCHES).min(MAX_BUFFER_MATCHES);
+    let (matches, truncated) = search_output(&output, start_offset, &query, limit)
+        .map_err(|e| ApiError::validation("invalid_query", format!("Invalid search query: {}", e)))?;
+
+    // The query isn't recorded: it may well be the secret looked for
+    audit::record(
+        &req,
+        "terminal.buffer_search",
+        serde_json::json!({
+            "session_id": info.session_id,
+            "label": info.label,
+            "matches": matches.len()
+        }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "session_id": info.session_id,
+        "start_offset": start_offset,
+        "stripped": query.strip_ansi,
+        "matches": matches,
+        "truncated": truncated
+    })))
+}
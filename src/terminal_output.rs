@@ -0,0 +1,89 @@
+//! Batching of what a WebSocket connection sends. Programs that redraw the
+//! screen (htop, watch) make a terminal write thousands of small chunks a
+//! second; sent as a message each, they saturate the socket and the
+//! browser. Output of a session queued back to back is merged into one
+//! message, in order. A lone chunk goes out at once, so keystroke echoes
+//! aren't delayed; only once a burst is under way does the sender wait a
+//! few milliseconds for the rest of it.
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Most output merged into one message, in bytes
+pub const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Longest a burst of output is held back waiting for more of it
+pub const MAX_DELAY: Duration = Duration::from_millis(5);
+
+/// A message queued for a WebSocket client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outgoing {
+    /// Output of a terminal session
+    Output { session_id: String, data: String },
+    /// Any other message, already serialized
+    Message(String),
+}
+
+/// Reads a connection's queue, merging output of the same session
+pub struct Coalescer {
+    rx: mpsc::UnboundedReceiver<Outgoing>,
+    /// A message taken off the queue that couldn't join the last one
+    held: Option<Outgoing>,
+}
+
+impl Coalescer {
+    pub fn new(rx: mpsc::UnboundedReceiver<Outgoing>) -> Self {
+        Self { rx, held: None }
+    }
+
+    /// The next message to send; `None` once every sender is gone and the
+    /// queue is drained
+    pub async fn next(&mut self) -> Option<Outgoing> {
+        let first = match self.held.take() {
+            Some(message) => message,
+            None => self.rx.recv().await?,
+        };
+        let Outgoing::Output { session_id, mut data } = first else {
+            return Some(first);
+        };
+
+        // Merge what is already queued without waiting
+        let mut merged = 0;
+        while let Ok(next) = self.rx.try_recv() {
+            if !self.append(&session_id, &mut data, next) {
+                return Some(Outgoing::Output { session_id, data });
+            }
+            merged += 1;
+        }
+
+        // More than one chunk was waiting: output is arriving in a burst,
+        // and the rest of it is likely a moment away
+        if merged > 0 {
+            let deadline = Instant::now() + MAX_DELAY;
+            while let Ok(Some(next)) = tokio::time::timeout_at(deadline, self.rx.recv()).await {
+                if !self.append(&session_id, &mut data, next) {
+                    break;
+                }
+            }
+        }
+        Some(Outgoing::Output { session_id, data })
+    }
+
+    /// Append `next` to the output of `session_id` if it is more of it and
+    /// fits, otherwise hold it for the next message
+    fn append(&mut self, session_id: &str, data: &mut String, next: Outgoing) -> bool {
+        match next {
+            Outgoing::Output { session_id: ref id, data: ref more }
+                if id == session_id && data.len() + more.len() <= MAX_MESSAGE_BYTES =>
+            {
+                data.push_str(more);
+                true
+            }
+            other => {
+                self.held = Some(other);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use rustls::{Certificate, PrivateKey, ServerConfig, SignatureScheme};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Build a rustls server config from PEM certificate chain and private key
+/// files, failing with a readable message if either doesn't parse or the key
+/// doesn't belong to the certificate.
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig, String> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+
+    verify_key_matches(&certs[0], &key).map_err(|e| {
+        format!(
+            "TLS key {:?} does not match certificate {:?}: {}",
+            key_path, cert_path, e
+        )
+    })?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid TLS certificate/key: {}", e))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open TLS certificate {:?}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to parse TLS certificate {:?}: {}", path, e))?;
+
+    if certs.is_empty() {
+        return Err(format!("No PEM certificates found in {:?}", path));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open TLS key {:?}: {}", path, e))?;
+    let mut reader = BufReader::new(file);
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| format!("Failed to parse TLS key {:?}: {}", path, e))?
+        {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(format!("No PEM private key found in {:?}", path)),
+        }
+    }
+}
+
+/// Sign a probe message with the private key and verify it against the
+/// certificate's public key
+fn verify_key_matches(cert: &Certificate, key: &PrivateKey) -> Result<(), String> {
+    let signing_key = rustls::sign::any_supported_type(key)
+        .map_err(|_| "unsupported private key type".to_string())?;
+
+    let schemes = [
+        SignatureScheme::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::RSA_PKCS1_SHA256,
+    ];
+    let signer = signing_key
+        .choose_scheme(&schemes)
+        .ok_or_else(|| "no supported signature scheme for key".to_string())?;
+
+    let algorithm: &webpki::SignatureAlgorithm = match signer.scheme() {
+        SignatureScheme::ED25519 => &webpki::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        _ => &webpki::RSA_PKCS1_2048_8192_SHA256,
+    };
+
+    let probe = b"runotepad tls key check";
+    let signature = signer
+        .sign(probe)
+        .map_err(|e| format!("failed to sign with key: {}", e))?;
+
+    let end_entity = webpki::EndEntityCert::try_from(cert.0.as_slice())
+        .map_err(|e| format!("failed to parse certificate: {:?}", e))?;
+
+    end_entity
+        .verify_signature(algorithm, probe, &signature)
+        .map_err(|_| "signature check failed".to_string())
+}
@@ -0,0 +1,281 @@
+//! Trash for files deleted through the API. A deleted file is moved to
+//! `.runotepad-trash/<id>/<path>` in its worktree, where `<id>` starts with
+//! the time of the deletion, and can be put back until it is purged: by
+//! hand, or automatically once it is older than `trash_retention_days`. The
+//! trash directory is hidden like every dotfile and ignored by git, so it
+//! doesn't show up in file lists, searches, commits or archives.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::audit;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::file_ops;
+use crate::workspace;
+use crate::AppState;
+
+pub const TRASH_DIR: &str = ".runotepad-trash";
+
+/// Format of the deletion time an id starts with
+const ID_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// A file in the trash
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashItem {
+    pub id: String,
+    /// Where the file was, relative to the worktree
+    pub path: String,
+    pub deleted_at: DateTime<Utc>,
+    pub size: u64,
+}
+
+fn trash_root(worktree_path: &Path) -> PathBuf {
+    worktree_path.join(TRASH_DIR)
+}
+
+/// When the item `id` was deleted, or `None` if `id` isn't an item id
+fn deleted_at(id: &str) -> Option<DateTime<Utc>> {
+    let (time, suffix) = id.split_once('-')?;
+    // Ids come from requests too, and are joined to the trash directory
+    if suffix.is_empty() || !suffix.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(time, ID_TIME_FORMAT)
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// The item stored under `id`, if there is one
+fn read_item(worktree_path: &Path, id: &str) -> Option<TrashItem> {
+    let deleted_at = deleted_at(id)?;
+    let item_dir = trash_root(worktree_path).join(id);
+    // An item directory holds the one file, under its original path
+    let mut path = item_dir.clone();
+    loop {
+        let mut entries = fs::read_dir(&path).ok()?.filter_map(|e| e.ok());
+        let entry = entries.next()?;
+        path = entry.path();
+        let file_type = entry.file_type().ok()?;
+        if file_type.is_file() {
+            let relative = path.strip_prefix(&item_dir).ok()?.to_string_lossy().to_string();
+            return Some(TrashItem {
+                id: id.to_string(),
+                path: relative,
+                deleted_at,
+                size: entry.metadata().ok()?.len(),
+            });
+        }
+        if !file_type.is_dir() {
+            return None;
+        }
+    }
+}
+
+/// Every item in a worktree's trash, most recently deleted first
+pub fn list(worktree_path: &Path) -> io::Result<Vec<TrashItem>> {
+    let entries = match fs::read_dir(trash_root(worktree_path)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut items: Vec<TrashItem> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| read_item(worktree_path, &e.file_name().to_string_lossy()))
+        .collect();
+    items.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(items)
+}
+
+/// Move `file_path` into the trash instead of deleting it
+pub fn move_to_trash(worktree_path: &Path, file_path: &str) -> io::Result<TrashItem> {
+    let full_path = file_ops::resolve_file(worktree_path, file_path)?;
+    let relative = full_path
+        .strip_prefix(worktree_path)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| PathBuf::from(file_path));
+    if relative.starts_with(TRASH_DIR) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is already in the trash", file_path),
+        ));
+    }
+
+    let root = trash_root(worktree_path);
+    fs::create_dir_all(&root)?;
+    // Keeps the trash out of `git status`, commits and archives
+    let ignore = root.join(".gitignore");
+    if !ignore.exists() {
+        fs::write(&ignore, "*\n")?;
+    }
+
+    let now = Utc::now();
+    let uuid = uuid::Uuid::new_v4().simple().to_string();
+    let id = format!("{}-{}", now.format(ID_TIME_FORMAT), &uuid[..8]);
+    let destination = root.join(&id).join(&relative);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let size = fs::metadata(&full_path)?.len();
+    fs::rename(&full_path, &destination)?;
+
+    Ok(TrashItem {
+        id,
+        path: relative.to_string_lossy().to_string(),
+        deleted_at: now,
+        size,
+    })
+}
+
+#[derive(Debug)]
+pub enum RestoreError {
+    Io(io::Error),
+    /// No item has this id
+    NotFound,
+    /// Something is at the original path again
+    Occupied(TrashItem),
+}
+
+impl From<io::Error> for RestoreError {
+    fn from(e: io::Error) -> Self {
+        RestoreError::Io(e)
+    }
+}
+
+/// Put item `id` back where it was
+pub fn restore(worktree_path: &Path, id: &str) -> Result<TrashItem, RestoreError> {
+    let item = read_item(worktree_path, id).ok_or(RestoreError::NotFound)?;
+    let destination = file_ops::safe_join(worktree_path, &item.path)?;
+    if fs::symlink_metadata(&destination).is_ok() {
+        return Err(RestoreError::Occupied(item));
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let item_dir = trash_root(worktree_path).join(id);
+    fs::rename(item_dir.join(&item.path), &destination)?;
+    fs::remove_dir_all(&item_dir)?;
+    Ok(item)
+}
+
+/// Remove the items deleted before `cutoff` (all of them without one);
+/// the ids removed
+pub fn purge(worktree_path: &Path, cutoff: Option<DateTime<Utc>>) -> io::Result<Vec<String>> {
+    let mut purged = Vec::new();
+    for item in list(worktree_path)? {
+        if cutoff.is_none_or(|cutoff| item.deleted_at < cutoff) {
+            fs::remove_dir_all(trash_root(worktree_path).join(&item.id))?;
+            purged.push(item.id);
+        }
+    }
+    Ok(purged)
+}
+
+/// Remove the items older than `trash_retention_days`
+pub fn purge_expired(config: &ConfigManager, worktree_path: &Path) {
+    let cutoff = Utc::now() - chrono::Duration::days(config.get_trash_retention_days() as i64);
+    match purge(worktree_path, Some(cutoff)) {
+        Ok(purged) if !purged.is_empty() => {
+            log::info!("Purged {} expired items from the trash of {:?}", purged.len(), worktree_path)
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("Failed to purge the trash of {:?}: {}", worktree_path, e),
+    }
+}
+
+fn trash_item_not_found(id: &str) -> ApiError {
+    ApiError::not_found("trash_item_not_found", format!("Trash item '{}' not found", id))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/trash - Deleted files that
+/// can be restored, most recent first
+pub async fn list_trash(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    purge_expired(&config, &worktree_path);
+    let items = list(&worktree_path).map_err(|e| ApiError::io("Failed to list the trash", e))?;
+    Ok(HttpResponse::Ok().json(items))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+    pub id: String,
+}
+
+/// POST /api/workspaces/{name}/branches/{branch}/trash/restore - Put a
+/// deleted file back
+pub async fn restore_item(
+    req: HttpRequest,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    body: Json<RestoreRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    let restored = restore(&worktree_path, &body.id);
+    state.listings.invalidate_tree(&workspace, &branch);
+    let item = restored.map_err(|e| match e {
+        RestoreError::NotFound => trash_item_not_found(&body.id),
+        RestoreError::Occupied(item) => ApiError::conflict(
+            "path_occupied",
+            format!("'{}' exists again; move it away to restore the deleted file", item.path),
+        )
+        .with_details(serde_json::json!({ "path": item.path })),
+        RestoreError::Io(e) => ApiError::io("Failed to restore file", e),
+    })?;
+
+    audit::record(
+        &req,
+        "file.restore_from_trash",
+        serde_json::json!({ "workspace": workspace, "branch": branch, "path": item.path, "id": item.id }),
+    );
+    Ok(HttpResponse::Ok().json(item))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeRequest {
+    /// Only this item; the whole trash when absent
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// POST /api/workspaces/{name}/branches/{branch}/trash/purge - Delete
+/// trashed files for good
+pub async fn purge_trash(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    body: Json<PurgeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = workspace::require_worktree(&config, &workspace, &branch)?;
+
+    let purged = match &body.id {
+        Some(id) => {
+            let item = read_item(&worktree_path, id).ok_or_else(|| trash_item_not_found(id))?;
+            fs::remove_dir_all(trash_root(&worktree_path).join(&item.id))
+                .map_err(|e| ApiError::io("Failed to purge the trash", e))?;
+            vec![item.id]
+        }
+        None => purge(&worktree_path, None).map_err(|e| ApiError::io("Failed to purge the trash", e))?,
+    };
+
+    audit::record(
+        &req,
+        "trash.purge",
+        serde_json::json!({ "workspace": workspace, "branch": branch, "purged": purged }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "purged": purged })))
+}
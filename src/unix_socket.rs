@@ -0,0 +1,76 @@
+//! Listening on a Unix domain socket, for running behind a reverse proxy on
+//! the same host without opening a TCP port. Requests over the socket are
+//! served by the same application as TCP ones, including `/ws` upgrades.
+
+use actix_web::dev::Extensions;
+use actix_web::HttpRequest;
+use std::any::Any;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+/// Connection data marking a request that came in over the Unix socket
+#[derive(Debug, Clone, Copy)]
+pub struct UnixPeer;
+
+/// `HttpServer::on_connect` callback tagging socket connections with
+/// `UnixPeer`. Only listeners added with `listen_uds` run it; `bind_uds`
+/// skips the callback.
+pub fn tag_connection(connection: &dyn Any, data: &mut Extensions) {
+    if connection.downcast_ref::<actix_web::rt::net::UnixStream>().is_some() {
+        data.insert(UnixPeer);
+    }
+}
+
+/// Address a request is rate limited by. Socket peers have none, so they
+/// share the loopback address, like clients behind a local proxy do.
+pub fn peer_ip(req: &HttpRequest) -> Option<IpAddr> {
+    match req.peer_addr() {
+        Some(addr) => Some(addr.ip()),
+        None if req.conn_data::<UnixPeer>().is_some() => Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        None => None,
+    }
+}
+
+/// Bind a socket at `path` for `HttpServer::listen_uds`, with `mode` as its
+/// permissions
+pub fn bind(path: &Path, mode: u32) -> io::Result<std::os::unix::net::UnixListener> {
+    remove_stale(path)?;
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    set_mode(path, mode)?;
+    Ok(listener)
+}
+
+/// Make way for binding `path`: a socket file left behind by a server that
+/// is gone is removed, while a live one or any other file is an error
+fn remove_stale(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} exists and is not a socket", path),
+        ));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("Another server is listening on {:?}", path),
+        ));
+    }
+    log::info!("Removing stale socket {:?}", path);
+    std::fs::remove_file(path)
+}
+
+/// Give the bound socket its configured permissions; connecting needs write
+/// permission on the socket
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
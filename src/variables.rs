@@ -0,0 +1,356 @@
+//! Runbook variables: `{{name}}` placeholders in code blocks, filled in when
+//! a block runs. A runbook declares them under `variables` in its front
+//! matter, or at the top level of a `vars.yaml` next to it (the front matter
+//! wins):
+//!
+//! ```yaml
+//! variables:
+//!   region: us-east-1                      # default, a string
+//!   replicas: { type: number, default: 3 }
+//!   cluster: { type: string, description: Target cluster }   # required
+//! ```
+//!
+//! Values never become shell code: each placeholder is replaced by a
+//! reference to a `RUNOTEPAD_VAR_<name>` shell variable holding the value,
+//! quoted to suit the quotes the placeholder sits in.
+
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::errors::ApiError;
+use crate::file_ops;
+
+/// File next to a runbook declaring its variables
+pub const VARS_FILE: &str = "vars.yaml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariableType {
+    String,
+    Number,
+    Boolean,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Variable {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: VariableType,
+    /// Used when a run doesn't give a value; without one the variable is
+    /// required
+    pub default: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_.-]*)\s*\}\}").unwrap())
+}
+
+/// Names of the variables `code` refers to, in order of first use
+pub fn references(code: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for captures in placeholder().captures_iter(code) {
+        let name = &captures[1];
+        if !names.iter().any(|known| known == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// A YAML scalar as the text it substitutes
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_type(name: &str, value: Option<&Value>, warnings: &mut Vec<String>) -> VariableType {
+    match value.and_then(Value::as_str) {
+        None | Some("string") => VariableType::String,
+        Some("number") => VariableType::Number,
+        Some("boolean") => VariableType::Boolean,
+        Some(other) => {
+            warnings.push(format!("Variable '{}' has unknown type '{}'; treated as a string", name, other));
+            VariableType::String
+        }
+    }
+}
+
+/// Declarations in a `variables` mapping; malformed ones are skipped with a
+/// warning
+fn parse_declarations(mapping: &Value, source: &str, warnings: &mut Vec<String>) -> Vec<Variable> {
+    let Some(mapping) = mapping.as_object() else {
+        warnings.push(format!("Variables in {} are not a mapping", source));
+        return Vec::new();
+    };
+    let mut variables = Vec::new();
+    for (name, declaration) in mapping {
+        let variable = match declaration {
+            Value::Null => Variable {
+                name: name.clone(),
+                kind: VariableType::String,
+                default: None,
+                description: None,
+            },
+            Value::Object(fields) => Variable {
+                name: name.clone(),
+                kind: parse_type(name, fields.get("type"), warnings),
+                default: fields.get("default").and_then(scalar_text),
+                description: fields.get("description").and_then(Value::as_str).map(str::to_string),
+            },
+            scalar => match scalar_text(scalar) {
+                Some(default) => Variable {
+                    name: name.clone(),
+                    kind: match scalar {
+                        Value::Number(_) => VariableType::Number,
+                        Value::Bool(_) => VariableType::Boolean,
+                        _ => VariableType::String,
+                    },
+                    default: Some(default),
+                    description: None,
+                },
+                None => {
+                    warnings.push(format!("Variable '{}' in {} is not a value or a mapping", name, source));
+                    continue;
+                }
+            },
+        };
+        variables.push(variable);
+    }
+    variables
+}
+
+/// Variables declared by a runbook's front matter (YAML text) and its
+/// `vars.yaml` (YAML text), by name
+pub fn declarations(front_matter: Option<&str>, vars_file: Option<&str>) -> (Vec<Variable>, Vec<String>) {
+    let mut warnings = Vec::new();
+    let mut declared: BTreeMap<String, Variable> = BTreeMap::new();
+
+    if let Some(text) = vars_file {
+        match serde_yaml::from_str::<Value>(text) {
+            Ok(Value::Null) => {}
+            Ok(mapping) => {
+                for variable in parse_declarations(&mapping, VARS_FILE, &mut warnings) {
+                    declared.insert(variable.name.clone(), variable);
+                }
+            }
+            Err(e) => warnings.push(format!("Invalid {}: {}", VARS_FILE, e)),
+        }
+    }
+    if let Some(text) = front_matter {
+        // Front matter problems are reported by the file listing
+        if let Ok(Value::Object(fields)) = serde_yaml::from_str::<Value>(text) {
+            if let Some(mapping) = fields.get("variables") {
+                for variable in parse_declarations(mapping, "front matter", &mut warnings) {
+                    declared.insert(variable.name.clone(), variable);
+                }
+            }
+        }
+    }
+    (declared.into_values().collect(), warnings)
+}
+
+/// Variables declared for the runbook at `runbook_path` in a worktree, given
+/// its front matter, plus warnings about malformed declarations
+pub fn load(worktree_path: &Path, runbook_path: &str, front_matter: Option<&str>) -> (Vec<Variable>, Vec<String>) {
+    let vars_path = match Path::new(runbook_path).parent() {
+        Some(dir) => dir.join(VARS_FILE),
+        None => PathBuf::from(VARS_FILE),
+    };
+    let vars_file = match file_ops::resolve_file(worktree_path, &vars_path.to_string_lossy()) {
+        Ok(path) => std::fs::read_to_string(path).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return declarations(front_matter, None),
+        Err(e) => Err(e.to_string()),
+    };
+    match vars_file {
+        Ok(text) => declarations(front_matter, Some(&text)),
+        Err(e) => {
+            let (variables, mut warnings) = declarations(front_matter, None);
+            warnings.push(format!("Failed to read {}: {}", VARS_FILE, e));
+            (variables, warnings)
+        }
+    }
+}
+
+/// Quote `value` as one shell word; values made only of characters the
+/// shell treats literally are left as they are
+pub fn shell_quote(value: &str) -> String {
+    let literal = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !value.is_empty() && value.chars().all(literal) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// The quotes in effect at some point of shell code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+/// The quotes still open at the end of `code`. Comments are skipped, so an
+/// apostrophe in one doesn't count. Heredocs aren't understood, but a wrong
+/// guess can only garble a value, not run it.
+fn open_quotes(code: &str) -> Quoting {
+    let mut quoting = Quoting::None;
+    let mut previous = None;
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match (quoting, c) {
+            (Quoting::Single, '\'') => quoting = Quoting::None,
+            (Quoting::Single, _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (Quoting::Double, '"') => quoting = Quoting::None,
+            (Quoting::Double, _) => {}
+            (Quoting::None, '\'') => quoting = Quoting::Single,
+            (Quoting::None, '"') => quoting = Quoting::Double,
+            (Quoting::None, '#') if previous.is_none_or(|p: char| p.is_whitespace() || ";&|()".contains(p)) => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            _ => {}
+        }
+        previous = Some(c);
+    }
+    quoting
+}
+
+/// A block's code with its placeholders replaced by references to shell
+/// variables, and the values of those variables
+#[derive(Debug, Clone)]
+pub struct Substituted {
+    pub code: String,
+    /// `RUNOTEPAD_VAR_*` names and the values they hold, for the environment
+    /// of the shell running `code`
+    pub variables: Vec<(String, String)>,
+}
+
+impl Substituted {
+    /// The variables assigned up front, then the code: what is typed into a
+    /// terminal session, where there is no environment to pass
+    pub fn script(&self) -> String {
+        if self.variables.is_empty() {
+            return self.code.clone();
+        }
+        let assignments: Vec<String> = self
+            .variables
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, shell_quote(value)))
+            .collect();
+        format!("{}\n{}", assignments.join(" "), self.code)
+    }
+}
+
+/// `RUNOTEPAD_VAR_` and `name` with what a shell variable name can't hold
+/// replaced by `_`, numbered if that clashes with a name taken already
+fn shell_variable(name: &str, taken: &[(String, String)]) -> String {
+    let base: String = format!("RUNOTEPAD_VAR_{}", name)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    let mut candidate = base.clone();
+    let mut n = 1;
+    while taken.iter().any(|(taken, _)| *taken == candidate) {
+        n += 1;
+        candidate = format!("{}_{}", base, n);
+    }
+    candidate
+}
+
+fn check_type(variable: &Variable, value: &str) -> Result<(), ApiError> {
+    let valid = match variable.kind {
+        VariableType::String => true,
+        VariableType::Number => value.parse::<f64>().is_ok_and(f64::is_finite),
+        VariableType::Boolean => value == "true" || value == "false",
+    };
+    if valid {
+        return Ok(());
+    }
+    Err(ApiError::validation(
+        "invalid_variable",
+        format!("Variable '{}' must be a {:?}", variable.name, variable.kind).to_lowercase(),
+    )
+    .with_details(serde_json::json!({ "name": variable.name, "type": variable.kind, "value": value })))
+}
+
+/// `code` with every placeholder replaced by a reference to a shell variable
+/// holding its value: the one given for the run, else the declared default.
+/// 400 listing the variables that have neither.
+pub fn substitute(
+    code: &str,
+    declared: &[Variable],
+    given: &serde_json::Map<String, Value>,
+) -> Result<Substituted, ApiError> {
+    let mut values = Vec::new();
+    let mut unresolved = Vec::new();
+    for name in references(code) {
+        let variable = declared.iter().find(|variable| variable.name == name);
+        let value = match given.get(&name) {
+            Some(value) => Some(scalar_text(value).ok_or_else(|| {
+                ApiError::validation(
+                    "invalid_variable",
+                    format!("Value of variable '{}' must be a string, number or boolean", name),
+                )
+                .with_details(serde_json::json!({ "name": name }))
+            })?),
+            None => variable.and_then(|variable| variable.default.clone()),
+        };
+        match value {
+            Some(value) => {
+                if let Some(variable) = variable {
+                    check_type(variable, &value)?;
+                }
+                values.push((name, value));
+            }
+            None => unresolved.push(name),
+        }
+    }
+    if !unresolved.is_empty() {
+        return Err(ApiError::validation(
+            "unresolved_variables",
+            format!("No value for {}", unresolved.join(", ")),
+        )
+        .with_details(serde_json::json!({ "variables": unresolved })));
+    }
+
+    let mut variables: Vec<(String, String)> = Vec::new();
+    let mut names = BTreeMap::new();
+    for (name, value) in values {
+        let variable = shell_variable(&name, &variables);
+        names.insert(name, variable.clone());
+        variables.push((variable, value));
+    }
+
+    let mut substituted = String::with_capacity(code.len());
+    let mut end = 0;
+    for captures in placeholder().captures_iter(code) {
+        let found = captures.get(0).expect("group 0 is the match");
+        substituted.push_str(&code[end..found.start()]);
+        let variable = &names[&captures[1]];
+        // The shell expands a variable without parsing its value
+        match open_quotes(&code[..found.start()]) {
+            Quoting::None => substituted.push_str(&format!("\"${{{}}}\"", variable)),
+            Quoting::Double => substituted.push_str(&format!("${{{}}}", variable)),
+            Quoting::Single => substituted.push_str(&format!("'\"${{{}}}\"'", variable)),
+        }
+        end = found.end();
+    }
+    substituted.push_str(&code[end..]);
+    Ok(Substituted {
+        code: substituted,
+        variables,
+    })
+}
@@ -0,0 +1,210 @@
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::events::{Event, EventBus};
+use crate::listing_cache::ListingCache;
+
+/// How long a path must be quiet before its change is reported. Editors that
+/// write a temp file and rename it over the original produce a burst of
+/// events that collapses into a single notification.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// A debounced change to a file inside a watched worktree
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChangeEvent {
+    pub workspace: String,
+    pub branch: String,
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+/// Raw event forwarded from a notify callback to the debouncer thread
+struct RawEvent {
+    workspace: String,
+    branch: String,
+    root: PathBuf,
+    path: PathBuf,
+    kind: EventKind,
+}
+
+struct Pending {
+    workspace: String,
+    branch: String,
+    root: PathBuf,
+    created: bool,
+    last_seen: Instant,
+}
+
+/// Watches worktrees, publishes file changes as `Event::FileChanged` and
+/// drops the cached file trees of worktrees that changed
+pub struct FileWatcher {
+    watchers: Mutex<HashMap<PathBuf, RecommendedWatcher>>,
+    raw_tx: std_mpsc::Sender<RawEvent>,
+}
+
+impl FileWatcher {
+    pub fn new(events: EventBus, listings: Arc<ListingCache>) -> Self {
+        let (raw_tx, raw_rx) = std_mpsc::channel();
+        std::thread::spawn(move || debounce_loop(raw_rx, events, listings));
+
+        Self {
+            watchers: Mutex::new(HashMap::new()),
+            raw_tx,
+        }
+    }
+
+    /// Start watching a worktree if it isn't watched already
+    pub fn ensure_watch(&self, workspace: &str, branch: &str, worktree_path: &Path) {
+        let mut watchers = self.watchers.lock().unwrap();
+        if watchers.contains_key(worktree_path) {
+            return;
+        }
+
+        let tx = self.raw_tx.clone();
+        let workspace_name = workspace.to_string();
+        let branch_name = branch.to_string();
+        let root = worktree_path.to_path_buf();
+
+        let handler = move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                for path in event.paths {
+                    let _ = tx.send(RawEvent {
+                        workspace: workspace_name.clone(),
+                        branch: branch_name.clone(),
+                        root: root.clone(),
+                        path,
+                        kind: event.kind,
+                    });
+                }
+            }
+            Err(e) => log::warn!("File watcher error: {:?}", e),
+        };
+
+        let mut watcher = match notify::recommended_watcher(handler) {
+            Ok(w) => w,
+            Err(e) => {
+                log::error!("Failed to create file watcher: {:?}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = watcher.watch(worktree_path, RecursiveMode::Recursive) {
+            log::error!("Failed to watch {:?}: {:?}", worktree_path, e);
+            return;
+        }
+
+        log::info!("Watching worktree {:?}", worktree_path);
+        watchers.insert(worktree_path.to_path_buf(), watcher);
+    }
+
+    /// Stop watching every worktree at or below `path`
+    pub fn unwatch(&self, path: &Path) {
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|watched, _| {
+            let keep = !watched.starts_with(path);
+            if !keep {
+                log::info!("Stopped watching worktree {:?}", watched);
+            }
+            keep
+        });
+    }
+}
+
+/// Whether a path inside a worktree should be reported. Hidden files and
+/// directories (including `.git` internals and editor swap files) are ignored,
+/// matching what `file_ops::list_files` shows.
+fn is_reportable(relative: &Path) -> bool {
+    !relative
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+fn debounce_loop(raw_rx: std_mpsc::Receiver<RawEvent>, events: EventBus, listings: Arc<ListingCache>) {
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+
+    loop {
+        match raw_rx.recv_timeout(DEBOUNCE / 4) {
+            Ok(raw) => {
+                let relative = match raw.path.strip_prefix(&raw.root) {
+                    Ok(r) if !r.as_os_str().is_empty() => r.to_path_buf(),
+                    _ => continue,
+                };
+                // Changed ignore rules change what is listed, unreported
+                if relative.file_name().is_some_and(|name| name == ".gitignore")
+                    && !matches!(raw.kind, EventKind::Access(_))
+                {
+                    listings.invalidate_tree(&raw.workspace, &raw.branch);
+                }
+                if !is_reportable(&relative) || matches!(raw.kind, EventKind::Access(_)) {
+                    continue;
+                }
+
+                let entry = pending.entry(raw.path.clone()).or_insert_with(|| Pending {
+                    workspace: raw.workspace,
+                    branch: raw.branch,
+                    root: raw.root,
+                    created: matches!(raw.kind, EventKind::Create(_)),
+                    last_seen: Instant::now(),
+                });
+                entry.last_seen = Instant::now();
+            }
+            Err(std_mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std_mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        let now = Instant::now();
+        let ready: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.last_seen) >= DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in ready {
+            let Some(p) = pending.remove(&path) else { continue };
+            // Directories and short-lived files aren't reported, but may
+            // still be listed
+            listings.invalidate_tree(&p.workspace, &p.branch);
+
+            // Decide the kind from the final state rather than the event
+            // sequence; files created and removed within the window (editor
+            // temp files) are not reported at all
+            let kind = match (path.exists(), p.created) {
+                (true, true) => ChangeKind::Created,
+                (true, false) => ChangeKind::Modified,
+                (false, false) => ChangeKind::Deleted,
+                (false, true) => continue,
+            };
+
+            if path.is_dir() {
+                continue;
+            }
+
+            let relative = path
+                .strip_prefix(&p.root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+
+            log::debug!("File {:?}: {}/{}/{}", kind, p.workspace, p.branch, relative);
+            events.publish(Event::FileChanged(FileChangeEvent {
+                workspace: p.workspace,
+                branch: p.branch,
+                path: relative,
+                kind,
+            }));
+        }
+    }
+}
@@ -1,31 +1,130 @@
-use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-
+use std::io::Read;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::audit;
 use crate::auth;
-use crate::config::{sanitize_branch_name, ConfigManager};
-use crate::file_ops::{self, FileEntry};
-use crate::git_ops;
+use crate::blocks;
+use crate::branch_naming;
+use crate::caching;
+use crate::commit_message;
+use crate::config::{
+    detached_worktree_name, is_detached_worktree, sanitize_branch_name, validate_branch_name, validate_workspace_name,
+    ConfigManager, TokenIdentity, TokenScope, WorkspaceConfig, WorkspaceStatus, GIT_PASSWORD_SECRET,
+};
+use crate::diff;
+use crate::drafts;
+use crate::errors::ApiError;
+use crate::events::{Event, EventBus};
+use crate::extract::Json;
+use crate::file_ops::{self, FileEntry, ReadFileError};
+use crate::git_ops::{self, CommitError};
+use crate::gitignore;
+use crate::jobs::{self, Job, JobContext, JobKind, SyncQuery};
+use crate::logging;
+use crate::patch;
+use crate::quota;
+use crate::remotes::{self, RemoteQuery};
+use crate::repair;
+use crate::secrets::SecretKey;
+use crate::trash;
+use crate::variables;
+use crate::watcher::FileWatcher;
+use crate::workspace_env;
+use crate::worktree_lock::WorktreeGuard;
+use crate::AppState;
 
 // Request/Response types
 
 #[derive(Debug, Deserialize)]
 pub struct CreateWorkspaceRequest {
     pub name: String,
-    pub repo_url: String,
+    /// Remote to clone; exactly one of `repo_url` and `local_path` is given
+    #[serde(default)]
+    pub repo_url: Option<String>,
+    /// Repository already on the server host, below the configured import root
+    #[serde(default)]
+    pub local_path: Option<PathBuf>,
     pub base_branch: String,
 }
 
+/// Partial update of a workspace; fields left out are unchanged
+#[derive(Debug, Deserialize)]
+pub struct UpdateWorkspaceRequest {
+    pub repo_url: Option<String>,
+    /// Remote pushes go to
+    pub push_remote: Option<String>,
+    /// Remote pulls and rebases take the base branch from
+    pub upstream_remote: Option<String>,
+    /// Replaces the branches protected from commits, pushes, renames and
+    /// deletion
+    pub protected_branches: Option<Vec<String>>,
+    /// Regex branch names must match; empty to remove it
+    pub branch_name_pattern: Option<String>,
+    /// Template of generated branch names; empty to remove it
+    pub branch_name_template: Option<String>,
+    /// Regex commit messages must contain a match of; empty to remove it
+    pub commit_message_pattern: Option<String>,
+    /// Template commit messages are prefilled with; empty to remove it
+    pub commit_message_template: Option<String>,
+    /// Only warn about commit messages that don't match the pattern
+    pub commit_message_advisory: Option<bool>,
+    /// Key commits are signed with; empty to stop signing
+    pub signing_key_path: Option<String>,
+    pub signing_format: Option<git_ops::SigningFormat>,
+    /// Author and committer of commits; empty to use the server user's
+    pub git_user_name: Option<String>,
+    pub git_user_email: Option<String>,
+    /// Credential given to remotes asking for one; empty to remove it
+    pub git_username: Option<String>,
+    pub git_password: Option<String>,
+    /// Check the new URL with `git ls-remote` before switching to it
+    #[serde(default = "default_true")]
+    pub verify: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameWorkspaceRequest {
+    pub new_name: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateBranchRequest {
-    pub branch_name: String,
+    pub branch_name: Option<String>,
+    /// Generate the name from the workspace's `branch_name_template`
+    /// instead of giving `branch_name`
+    pub auto_name: Option<branch_naming::AutoName>,
     pub from_branch: Option<String>,
+    /// Tag or commit to check out with a detached HEAD instead of creating
+    /// a branch; needs `detached`
+    #[serde(rename = "ref")]
+    pub rev: Option<String>,
+    #[serde(default)]
+    pub detached: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CommitRequest {
     pub message: String,
+    /// Paths to stage, relative to the worktree root
+    #[serde(default)]
     pub files: Vec<String>,
+    /// Stage every change in the worktree, as well as `files`
+    #[serde(default)]
+    pub all: bool,
+    /// Skip the repository's commit hooks
+    #[serde(default)]
+    pub no_verify: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -33,9 +132,79 @@ pub struct SaveFileRequest {
     pub content: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CreateFileRequest {
+    /// Starts with a heading named after the file when not given
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+/// Changes to a file read at `base_hash`; exactly one of `edits` and `diff`
+/// is given
+#[derive(Debug, Deserialize)]
+pub struct PatchFileRequest {
+    pub base_hash: String,
+    pub edits: Option<Vec<patch::Edit>>,
+    /// Unified diff of the file
+    pub diff: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreFileRequest {
+    pub path: String,
+    /// Revision to restore from [default: HEAD]
+    #[serde(rename = "ref")]
+    pub rev: Option<String>,
+    /// Replace the file even if it differs from the restored content
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct FileQuery {
     pub path: String,
+    /// Stream the raw bytes instead of returning JSON (for downloads)
+    #[serde(default)]
+    pub raw: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListFilesQuery {
+    /// Include parsed front matter for each file
+    #[serde(default)]
+    pub detail: bool,
+    /// Only return files whose front matter matches `key:value`
+    pub tag: Option<String>,
+    /// Walk the worktree even if a recent listing is cached
+    #[serde(default)]
+    pub fresh: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListBranchesQuery {
+    /// Ask git even if a recent listing is cached
+    #[serde(default)]
+    pub fresh: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    pub limit: Option<usize>,
+}
+
+/// Default and maximum number of search matches returned per request
+const DEFAULT_SEARCH_LIMIT: usize = 200;
+const MAX_SEARCH_LIMIT: usize = 5000;
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    pub path: Option<String>,
+    pub format: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -43,6 +212,13 @@ pub struct ChangeBaseBranchRequest {
     pub new_base_branch: String,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ProtectionQuery {
+    /// Go ahead on a protected branch
+    #[serde(default, rename = "override")]
+    pub override_protection: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RenameBranchRequest {
     pub new_name: String,
@@ -54,6 +230,64 @@ pub struct WorkspaceInfo {
     pub repo_url: String,
     pub base_branch: String,
     pub created_at: String,
+    pub status: WorkspaceStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkspaceDetail {
+    pub name: String,
+    pub repo_url: String,
+    pub base_branch: String,
+    pub created_at: String,
+    pub worktree_count: usize,
+    pub disk_usage: file_ops::DiskUsage,
+    pub last_fetch: Option<String>,
+    pub head_commit: Option<git_ops::CommitSummary>,
+    pub healthy: bool,
+    pub status: WorkspaceStatus,
+    /// Disk space by the last quota scan against the workspace's quota
+    pub quota: quota::QuotaUsage,
+    /// Pattern and template branch names follow
+    pub branch_naming: branch_naming::BranchNaming,
+    /// Pattern and template commit messages follow
+    pub commit_messages: commit_message::CommitMessagePolicy,
+    /// How commits are signed; None if they aren't
+    pub signing: Option<SigningInfo>,
+    /// Identity and credential the workspace's git commands run with
+    pub git_identity: GitIdentityInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SigningInfo {
+    pub format: git_ops::SigningFormat,
+    pub key: String,
+}
+
+fn signing_info(ws_config: &WorkspaceConfig) -> Option<SigningInfo> {
+    ws_config.signing().map(|signing| SigningInfo {
+        format: signing.format,
+        key: signing.key,
+    })
+}
+
+/// A workspace's git identity; the password itself is never returned
+#[derive(Debug, Serialize)]
+pub struct GitIdentityInfo {
+    pub user_name: Option<String>,
+    pub user_email: Option<String>,
+    pub username: Option<String>,
+    pub has_password: bool,
+}
+
+impl GitIdentityInfo {
+    fn of(ws_config: &WorkspaceConfig) -> Self {
+        Self {
+            user_name: ws_config.git_user_name.clone(),
+            user_email: ws_config.git_user_email.clone(),
+            username: ws_config.git_username.clone(),
+            has_password: ws_config.git_password.is_some(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -61,616 +295,2900 @@ pub struct BranchInfo {
     pub name: String,
     pub is_worktree: bool,
     pub worktree_path: Option<String>,
+    /// Branch the worktree actually has checked out; None when HEAD is
+    /// detached or there is no worktree
+    pub checked_out_branch: Option<String>,
+    pub head_commit: Option<String>,
+    /// Whether the worktree has uncommitted changes
+    pub dirty: Option<bool>,
+    /// Commits on the worktree's HEAD that the workspace's base branch
+    /// lacks
+    pub ahead: Option<u32>,
+    /// Commits on the base branch that the worktree's HEAD lacks
+    pub behind: Option<u32>,
+    /// The git commands for the fields above took too long, so they are
+    /// left empty
+    pub stats_timeout: bool,
+    /// Stale locks or an interrupted operation found in the worktree
+    pub needs_attention: Vec<repair::Attention>,
+    /// A worktree pinned to a tag or commit rather than a branch
+    pub detached: bool,
+}
+
+/// What git says about a worktree, for its `BranchInfo`
+struct WorktreeStats {
+    checked_out_branch: Option<String>,
+    head_commit: Option<String>,
+    dirty: Option<bool>,
+    ahead_behind: Option<(u32, u32)>,
+}
+
+impl WorktreeStats {
+    fn collect(worktree_dir: &Path, base_branch: &str) -> Self {
+        Self {
+            checked_out_branch: git_ops::get_current_branch(worktree_dir).ok().flatten(),
+            head_commit: git_ops::resolve_commit(worktree_dir, "HEAD"),
+            dirty: git_ops::has_uncommitted_changes(worktree_dir).ok(),
+            ahead_behind: git_ops::ahead_behind(worktree_dir, base_branch).ok(),
+        }
+    }
+
+    /// Collect the stats on the blocking pool; None if that takes longer
+    /// than `timeout`, in which case the git commands still running are
+    /// killed
+    async fn collect_within(worktree_dir: PathBuf, base_branch: String, timeout: Duration) -> Option<Self> {
+        let control = git_ops::RunControl::new(timeout);
+        let collected = logging::block(move || {
+            git_ops::with_control(control.clone(), || {
+                let stats = Self::collect(&worktree_dir, &base_branch);
+                (!control.is_timed_out()).then_some(stats)
+            })
+        });
+        tokio::time::timeout(timeout, collected).await.ok()?.ok()?
+    }
+
+    fn apply(self, info: &mut BranchInfo) {
+        info.checked_out_branch = self.checked_out_branch;
+        info.head_commit = self.head_commit;
+        info.dirty = self.dirty;
+        info.ahead = self.ahead_behind.map(|(ahead, _)| ahead);
+        info.behind = self.ahead_behind.map(|(_, behind)| behind);
+    }
+}
+
+/// Workspace names with a create, rename or delete under way, so a second
+/// request for the same name is turned away instead of racing on disk
+pub struct WorkspaceGuards {
+    in_flight: Arc<Mutex<HashMap<String, &'static str>>>,
+}
+
+impl WorkspaceGuards {
+    pub fn new() -> Self {
+        Self {
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Claim `name` for `operation` until the guard is dropped; on conflict
+    /// returns the operation already holding it. The guard may outlive the
+    /// request, e.g. to be held by a background clone.
+    pub fn claim(&self, name: &str, operation: &'static str) -> Result<WorkspaceGuard, &'static str> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(current) = in_flight.get(name) {
+            return Err(current);
+        }
+        in_flight.insert(name.to_string(), operation);
+        Ok(WorkspaceGuard {
+            in_flight: self.in_flight.clone(),
+            name: name.to_string(),
+        })
+    }
+}
+
+impl Default for WorkspaceGuards {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct WorkspaceGuard {
+    in_flight: Arc<Mutex<HashMap<String, &'static str>>>,
+    name: String,
+}
+
+impl Drop for WorkspaceGuard {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.name);
+    }
+}
+
+fn claim_workspace(
+    state: &AppState,
+    name: &str,
+    operation: &'static str,
+) -> Result<WorkspaceGuard, ApiError> {
+    state.workspace_guards.claim(name, operation).map_err(|current| {
+        ApiError::conflict(
+            "operation_in_progress",
+            format!("Workspace '{}' {} already in progress", name, current),
+        )
+    })
+}
+
+/// Configuration of an existing workspace, or 404
+pub fn require_workspace(config: &ConfigManager, name: &str) -> Result<WorkspaceConfig, ApiError> {
+    config.get_workspace(name).ok_or_else(|| ApiError::workspace_not_found(name))
+}
+
+/// Path of a checked-out worktree, or 404 if the workspace or the worktree
+/// doesn't exist
+pub fn require_worktree(config: &ConfigManager, workspace: &str, branch: &str) -> Result<PathBuf, ApiError> {
+    require_workspace(config, workspace)?;
+    let worktree_path = config.worktree_path(workspace, branch);
+    if !worktree_path.exists() {
+        return Err(ApiError::worktree_not_found(branch));
+    }
+    Ok(worktree_path)
+}
+
+/// 409 `branch_mismatch` unless the worktree of `branch` still has that
+/// branch checked out; a checkout in a terminal or a failed rebase can leave
+/// it on another branch or none, and git would then act on that one.
+/// Worktrees pinned to a tag or commit get `detached_head` instead
+fn require_checked_out(worktree_path: &Path, branch: &str) -> Result<(), ApiError> {
+    require_branch(branch)?;
+    let checked_out = git_ops::get_current_branch(worktree_path)
+        .map_err(|e| e.context("Failed to read the checked-out branch"))?;
+    if checked_out.as_deref() == Some(branch) {
+        return Ok(());
+    }
+    let message = match &checked_out {
+        Some(other) => format!("The worktree of '{}' has '{}' checked out", branch, other),
+        None => format!("The worktree of '{}' has no branch checked out (detached HEAD)", branch),
+    };
+    Err(ApiError::conflict("branch_mismatch", message)
+        .with_details(serde_json::json!({ "branch": branch, "checked_out_branch": checked_out })))
+}
+
+/// 409 `detached_head` for a worktree pinned to a tag or commit, which has
+/// no branch to commit to, push, pull, rebase or rename
+fn require_branch(branch: &str) -> Result<(), ApiError> {
+    if !is_detached_worktree(branch) {
+        return Ok(());
+    }
+    Err(ApiError::conflict(
+        "detached_head",
+        format!("Worktree '{}' is pinned to a tag or commit; create a branch to change it", branch),
+    )
+    .with_details(serde_json::json!({ "branch": branch })))
+}
+
+/// Whether the request's token has write scope, as bypassing a safeguard
+/// needs even where the route itself doesn't
+fn may_write(req: &HttpRequest) -> bool {
+    req.extensions()
+        .get::<TokenIdentity>()
+        .is_some_and(|identity| identity.scope.allows(TokenScope::ReadWrite))
+}
+
+/// 403 `protected_branch` if `branch` is protected in the workspace, unless
+/// the request overrides the protection with a token that may write; true
+/// if it did
+fn require_unprotected(
+    req: &HttpRequest,
+    ws_config: &WorkspaceConfig,
+    branch: &str,
+    query: &ProtectionQuery,
+) -> Result<bool, ApiError> {
+    if !ws_config.is_protected(branch) {
+        return Ok(false);
+    }
+    if query.override_protection && may_write(req) {
+        log::warn!("Protection of branch '{}' overridden", branch);
+        return Ok(true);
+    }
+    Err(ApiError::forbidden(
+        "protected_branch",
+        format!("Branch '{}' is protected; work on another branch or pass override=true", branch),
+    )
+    .with_details(serde_json::json!({ "branch": branch })))
+}
+
+/// A workspace's status; a stale cached one is rechecked with git, so off
+/// the worker like `verify_workspace`
+pub async fn workspace_status(config: &Arc<ConfigManager>, name: &str) -> Result<WorkspaceStatus, ApiError> {
+    let config = config.clone();
+    let name = name.to_string();
+    Ok(logging::block(move || config.workspace_status(&name)).await?)
+}
+
+/// 409 naming the problem when a workspace's repository can't be used
+pub async fn require_usable_repo(config: &Arc<ConfigManager>, workspace: &str) -> Result<(), ApiError> {
+    let config = config.clone();
+    let workspace = workspace.to_string();
+    logging::block(move || check_usable_repo(&config, &workspace)).await?
+}
+
+/// `require_usable_repo` for code already off the worker, such as jobs
+pub fn check_usable_repo(config: &ConfigManager, workspace: &str) -> Result<(), ApiError> {
+    let status = config.workspace_status(workspace);
+    if status.repo_usable() {
+        Ok(())
+    } else {
+        Err(ApiError::conflict(
+            "workspace_unavailable",
+            format!("Workspace '{}': {}", workspace, status.describe()),
+        )
+        .with_details(serde_json::json!({ "status": status })))
+    }
+}
+
+fn invalid_workspace_name(message: String) -> ApiError {
+    ApiError::validation("invalid_workspace_name", message)
+}
+
+fn invalid_branch_name(message: String) -> ApiError {
+    ApiError::validation("invalid_branch_name", message)
+}
+
+/// Failure to persist the config file after a change
+fn config_save_failed(e: impl std::fmt::Display) -> ApiError {
+    ApiError::internal(format!("Failed to save workspace config: {}", e))
 }
 
 // API Handlers
 
-/// GET /api/workspaces - List all workspaces
+/// GET /api/workspaces - List the workspaces the token may use
 pub async fn list_workspaces(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
-    let workspaces: Vec<WorkspaceInfo> = config
+) -> Result<HttpResponse, ApiError> {
+    let visible: Vec<_> = config
         .get_workspaces()
         .into_iter()
-        .map(|(name, ws)| WorkspaceInfo {
-            name,
-            repo_url: ws.repo_url,
-            base_branch: ws.base_branch,
-            created_at: ws.created_at.to_rfc3339(),
-        })
+        .filter(|(name, _)| auth::may_use_workspace(&req, name))
         .collect();
 
-    HttpResponse::Ok().json(workspaces)
+    // Stale statuses are rechecked with git
+    let config = config.get_ref().clone();
+    let workspaces: Vec<WorkspaceInfo> = logging::block(move || {
+        visible
+            .into_iter()
+            .map(|(name, ws)| WorkspaceInfo {
+                status: config.workspace_status(&name),
+                name,
+                repo_url: ws.repo_url,
+                base_branch: ws.base_branch,
+                created_at: ws.created_at.to_rfc3339(),
+            })
+            .collect()
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(workspaces))
 }
 
-/// POST /api/workspaces - Create a new workspace (clone repo)
+/// Entries visited when sizing a workspace, to keep the detail endpoint fast
+/// on very large repositories
+const DISK_USAGE_MAX_ENTRIES: usize = 100_000;
+
+/// GET /api/workspaces/{name} - Workspace details with repository statistics
+pub async fn get_workspace(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let ws_config = require_workspace(&config, &name)?;
+
+    let workspace_path = config.workspace_path(&name);
+    let repo_path = config.repo_path(&name);
+    let base_branch = ws_config.base_branch.clone();
+
+    let (healthy, worktree_count, head_commit, last_fetch, disk_usage) = logging::block(move || {
+        let healthy = git_ops::is_repo_healthy(&repo_path);
+        // The bare repo lists itself as the first worktree
+        let worktree_count = git_ops::list_worktrees(&repo_path)
+            .map(|w| w.len().saturating_sub(1))
+            .unwrap_or(0);
+        let head_commit = git_ops::commit_summary(&repo_path, &base_branch).ok();
+        let last_fetch = git_ops::last_fetch_time(&repo_path);
+        let disk_usage = file_ops::disk_usage(&workspace_path, DISK_USAGE_MAX_ENTRIES);
+        (healthy, worktree_count, head_commit, last_fetch, disk_usage)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(WorkspaceDetail {
+        status: workspace_status(&config, &name).await?,
+        quota: quota::usage(&config, &name),
+        branch_naming: branch_naming::BranchNaming::of(&ws_config),
+        commit_messages: commit_message::CommitMessagePolicy::of(&ws_config),
+        signing: signing_info(&ws_config),
+        git_identity: GitIdentityInfo::of(&ws_config),
+        name,
+        repo_url: ws_config.repo_url,
+        base_branch: ws_config.base_branch,
+        created_at: ws_config.created_at.to_rfc3339(),
+        worktree_count,
+        disk_usage,
+        last_fetch: last_fetch.map(|t| t.to_rfc3339()),
+        head_commit,
+        healthy,
+    }))
+}
+
+/// POST /api/workspaces - Create a new workspace; the clone runs as a
+/// background job
 pub async fn create_workspace(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
-    body: web::Json<CreateWorkspaceRequest>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
+    query: web::Query<SyncQuery>,
+    body: Json<CreateWorkspaceRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
     let name = &body.name;
-    let repo_url = &body.repo_url;
     let base_branch = &body.base_branch;
 
+    validate_workspace_name(name).map_err(invalid_workspace_name)?;
+    validate_branch_name(base_branch).map_err(invalid_branch_name)?;
+    auth::require_workspace_access(&req, name)?;
+
+    // Held by the clone job until it finishes
+    let guard = claim_workspace(&state, name, "creation")?;
+
     // Check if workspace already exists
     if config.get_workspace(name).is_some() {
-        return HttpResponse::Conflict().json(serde_json::json!({
-            "error": format!("Workspace '{}' already exists", name)
-        }));
+        return Err(ApiError::conflict(
+            "workspace_exists",
+            format!("Workspace '{}' already exists", name),
+        ));
     }
 
+    let local_path = match (&body.repo_url, &body.local_path) {
+        (Some(_), None) => None,
+        (None, Some(local)) => {
+            let local = resolve_import_path(&config, local)?;
+            quota::require_space_for_import(&config, name, &local)?;
+            Some(local)
+        }
+        _ => {
+            return Err(ApiError::validation(
+                "invalid_source",
+                "Give exactly one of repo_url and local_path",
+            ));
+        }
+    };
+
     // Create workspace directory
     let workspace_path = config.workspace_path(name);
     let repo_path = config.repo_path(name);
     let worktrees_path = config.worktrees_path(name);
 
-    if let Err(e) = std::fs::create_dir_all(&workspace_path) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to create workspace directory: {}", e)
-        }));
+    let abandon = {
+        let name = name.clone();
+        move |config: &ConfigManager| {
+            let _ = std::fs::remove_dir_all(&workspace_path);
+            let _ = config.remove_workspace(&name);
+            config.invalidate_workspace_status(&name);
+            config.invalidate_workspace_usage(&name);
+        }
+    };
+
+    if let Err(e) = config.begin_clone(name) {
+        abandon(&config);
+        return Err(ApiError::io("Failed to create workspace directory", e));
+    }
+
+    // Save workspace config up front so it is listed as cloning meanwhile
+    let source_url = match &local_path {
+        Some(local) => local.to_string_lossy().to_string(),
+        None => body.repo_url.clone().unwrap_or_default(),
+    };
+    if let Err(e) = config.add_workspace(name.clone(), source_url.clone(), base_branch.clone()) {
+        abandon(&config);
+        return Err(config_save_failed(e));
     }
 
     if let Err(e) = std::fs::create_dir_all(&worktrees_path) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to create worktrees directory: {}", e)
-        }));
+        abandon(&config);
+        return Err(ApiError::io("Failed to create worktrees directory", e));
+    }
+
+    let config = config.get_ref().clone();
+    let events = events.get_ref().clone();
+    let listings = state.listings.clone();
+    let audit = audit::Recorder::from_request(&req);
+    let job = Job::new(JobKind::CloneWorkspace, name.clone(), None, move |job| {
+        let _guard = guard;
+        let name = &body.name;
+        let base_branch = &body.base_branch;
+
+        // Clone repository; a local import hardlinks objects where it can and
+        // then tracks the source's own origin, so later fetches skip the copy
+        job.progress(format!("Cloning {}", source_url));
+        let cloned = match &local_path {
+            Some(local) => git_ops::clone_local(local, &repo_path).and_then(|()| {
+                match git_ops::get_remote_url(local) {
+                    Ok(upstream) => {
+                        git_ops::set_remote_url(&repo_path, &upstream)?;
+                        Ok(upstream)
+                    }
+                    Err(_) => Ok(source_url),
+                }
+            }),
+            None => git_ops::clone_repo(&source_url, &repo_path).map(|()| source_url),
+        };
+        let repo_url = match cloned {
+            Ok(url) => url,
+            Err(e) => {
+                // Cleanup on failure
+                abandon(&config);
+                return Err(e.context("Failed to clone repository").into());
+            }
+        };
+        let repo_url = &repo_url;
+
+        if let Err(e) = quota::check_clone(&config, name) {
+            abandon(&config);
+            return Err(e);
+        }
+
+        // Later checkouts then download LFS content as they go
+        if git_ops::repo_uses_lfs(&repo_path) {
+            if git_ops::lfs_available() {
+                job.progress("Setting up Git LFS");
+                if let Err(e) = git_ops::lfs_install(&repo_path) {
+                    log::warn!("Failed to set up Git LFS for workspace {}: {}", name, e);
+                }
+            } else {
+                log::warn!("Workspace {} uses Git LFS but git-lfs is not installed", name);
+            }
+        }
+
+        if local_path.is_some() {
+            if let Err(e) = config.update_workspace_repo_url(name, repo_url.clone()) {
+                abandon(&config);
+                return Err(config_save_failed(e));
+            }
+        }
+        config.finish_clone(name);
+        listings.invalidate_workspace(name);
+        events.publish(Event::WorkspaceCreated { workspace: name.clone() });
+
+        audit.record(
+            "workspace.create",
+            serde_json::json!({
+                "workspace": name,
+                "repo_url": repo_url,
+                "local_path": local_path,
+                "base_branch": base_branch
+            }),
+        );
+
+        Ok(serde_json::json!({
+            "name": name,
+            "repo_url": repo_url,
+            "imported_from": local_path,
+            "base_branch": base_branch,
+            "message": "Workspace created successfully"
+        }))
+    });
+    jobs::submit(&state, &query, job, StatusCode::CREATED).await
+}
+
+/// Canonical path of a repository to import, which must lie below the
+/// configured import root
+fn resolve_import_path(config: &ConfigManager, local: &Path) -> Result<PathBuf, ApiError> {
+    let Some(root) = config.import_root() else {
+        return Err(ApiError::forbidden(
+            "import_disabled",
+            "Importing local repositories is disabled; set import_root to allow it",
+        ));
+    };
+
+    let root = root.canonicalize().map_err(|e| {
+        ApiError::internal(format!("Import root {:?} is not accessible: {}", root, e))
+    })?;
+    let path = local.canonicalize().map_err(|e| {
+        ApiError::validation(
+            "invalid_local_path",
+            format!("Local path {:?} is not accessible: {}", local, e),
+        )
+    })?;
+
+    // Canonical on both sides, so `..` and symlinks can't escape the root
+    if !path.starts_with(&root) {
+        return Err(ApiError::forbidden(
+            "outside_import_root",
+            format!("Local path {:?} is outside the import root", local),
+        ));
+    }
+
+    if !git_ops::is_repository_root(&path) {
+        return Err(ApiError::validation(
+            "not_a_repository",
+            format!("Local path {:?} is not a git repository", local),
+        ));
+    }
+
+    Ok(path)
+}
+
+/// POST /api/workspaces/{name}/verify - Recheck a workspace's status now
+pub async fn verify_workspace(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    require_workspace(&config, &name)?;
+
+    let check_config = config.get_ref().clone();
+    let check_name = name.clone();
+    let status = logging::block(move || {
+        quota::rescan(&check_config, &check_name);
+        check_config.verify_workspace(&check_name)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "name": name,
+        "status": status,
+        "message": status.describe(),
+        "quota": quota::usage(&config, &name)
+    })))
+}
+
+/// POST /api/workspaces/{name}/fetch - Fetch every remote branch into the
+/// workspace's repository (background job)
+pub async fn fetch_workspace(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
+    path: web::Path<String>,
+    query: web::Query<SyncQuery>,
+    remote: web::Query<RemoteQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    require_workspace(&config, &name)?;
+    require_usable_repo(&config, &name).await?;
+    let repo_path = config.repo_path(&name);
+    let remote = match &remote.remote {
+        Some(remote) => {
+            remotes::require_remote(&repo_path, remote)?;
+            Some(remote.clone())
+        }
+        None => None,
+    };
+
+    let job = fetch_job(&config, &state, events.get_ref().clone(), name, remote, audit::Recorder::from_request(&req));
+    jobs::submit(&state, &query, job, StatusCode::OK).await
+}
+
+/// Job fetching `remote`, or every remote, into the repository of workspace
+/// `name`; also queued for each workspace by a bulk fetch
+pub fn fetch_job(
+    config: &ConfigManager,
+    state: &AppState,
+    events: EventBus,
+    name: String,
+    remote: Option<String>,
+    audit: audit::Recorder,
+) -> Job {
+    let repo_path = config.repo_path(&name);
+    let listings = state.listings.clone();
+    Job::new(JobKind::Fetch, name.clone(), None, move |job| {
+        job.progress(match &remote {
+            Some(remote) => format!("Fetching from {}", remote),
+            None => "Fetching from all remotes".to_string(),
+        });
+        let fetched = git_ops::fetch_remotes(&repo_path, remote.as_deref());
+        listings.invalidate_workspace(&name);
+        fetched.map_err(|e| e.context("Failed to fetch"))?;
+
+        events.publish(Event::Git {
+            workspace: name.clone(),
+            branch: None,
+            operation: "fetch",
+            commit_id: None,
+        });
+        audit.record("git.fetch", serde_json::json!({ "workspace": name, "remote": remote }));
+
+        Ok(serde_json::json!({
+            "message": "Fetch completed successfully",
+            "last_fetch": git_ops::last_fetch_time(&repo_path).map(|t| t.to_rfc3339())
+        }))
+    })
+}
+
+/// `url` trimmed, or 400 `invalid_repo_url` naming `field` if git couldn't
+/// take it as a repository URL
+pub fn check_repo_url<'a>(url: &'a str, field: &str) -> Result<&'a str, ApiError> {
+    let url = url.trim();
+    // A leading dash would be parsed by git as an option
+    if url.is_empty() || url.starts_with('-') {
+        return Err(ApiError::validation(
+            "invalid_repo_url",
+            format!("Invalid repository URL '{}'", url),
+        )
+        .with_details(serde_json::json!({ "field": field })));
+    }
+    Ok(url)
+}
+
+/// 400 `remote_unreachable` with git's stderr unless `git ls-remote` can
+/// list `url`, using the repository's credential setup
+pub async fn verify_repo_url(repo_path: &Path, url: &str) -> Result<(), ApiError> {
+    let check_path = repo_path.to_path_buf();
+    let check_url = url.to_string();
+    if let Err(e) = logging::block(move || git_ops::check_remote(&check_path, &check_url)).await? {
+        return Err(ApiError::validation(
+            "remote_unreachable",
+            format!("Repository '{}' is not reachable", url),
+        )
+        .with_details(serde_json::json!({ "stderr": e.stderr })));
+    }
+    Ok(())
+}
+
+/// Check the identity and credential fields of an update; returns the
+/// password sealed for the config, empty if it is to be removed. A password
+/// needs a username to go with it.
+fn check_git_identity(
+    config: &ConfigManager,
+    name: &str,
+    body: &UpdateWorkspaceRequest,
+) -> Result<Option<String>, ApiError> {
+    let fields = [
+        ("git_user_name", &body.git_user_name),
+        ("git_user_email", &body.git_user_email),
+        ("git_username", &body.git_username),
+        ("git_password", &body.git_password),
+    ];
+    for (field, value) in fields {
+        if value.as_deref().is_some_and(|value| value.contains(['\n', '\r', '\0'])) {
+            return Err(ApiError::validation(
+                "invalid_git_identity",
+                format!("{} can't contain line breaks or NUL characters", field),
+            ));
+        }
     }
 
-    // Clone repository
-    if let Err(e) = git_ops::clone_repo(repo_url, &repo_path) {
-        // Cleanup on failure
-        let _ = std::fs::remove_dir_all(&workspace_path);
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to clone repository: {}", e)
-        }));
+    let Some(password) = body.git_password.as_deref().filter(|p| !p.is_empty()) else {
+        return Ok(body.git_password.clone());
+    };
+    let username = match &body.git_username {
+        Some(username) => Some(username.clone()),
+        None => require_workspace(config, name)?.git_username,
+    };
+    if username.is_none_or(|username| username.is_empty()) {
+        return Err(ApiError::validation(
+            "invalid_git_identity",
+            "A git_password needs a git_username",
+        ));
     }
+    let key = SecretKey::load_or_create(config.secret_key_path())
+        .map_err(|e| workspace_env::secret_key_error(config, e))?;
+    Ok(Some(key.seal(GIT_PASSWORD_SECRET, password)))
+}
+
+/// PATCH /api/workspaces/{name} - Update workspace settings
+pub async fn update_workspace(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
+    path: web::Path<String>,
+    body: Json<UpdateWorkspaceRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    require_workspace(&config, &name)?;
+
+    if body.repo_url.is_none()
+        && body.push_remote.is_none()
+        && body.upstream_remote.is_none()
+        && body.protected_branches.is_none()
+        && body.branch_name_pattern.is_none()
+        && body.branch_name_template.is_none()
+        && body.commit_message_pattern.is_none()
+        && body.commit_message_template.is_none()
+        && body.commit_message_advisory.is_none()
+        && body.signing_key_path.is_none()
+        && body.signing_format.is_none()
+        && body.git_user_name.is_none()
+        && body.git_user_email.is_none()
+        && body.git_username.is_none()
+        && body.git_password.is_none()
+    {
+        return Err(ApiError::validation("no_changes", "No fields to update"));
+    }
+    let repo_url = body.repo_url.as_deref().map(|url| check_repo_url(url, "repo_url")).transpose()?;
+    for branch in body.protected_branches.iter().flatten() {
+        validate_branch_name(branch).map_err(invalid_branch_name)?;
+    }
+    if let Some(pattern) = body.branch_name_pattern.as_deref().filter(|p| !p.is_empty()) {
+        branch_naming::check_pattern(pattern)?;
+    }
+    if let Some(template) = body.branch_name_template.as_deref().filter(|t| !t.is_empty()) {
+        branch_naming::check_template(template)?;
+    }
+    if let Some(pattern) = body.commit_message_pattern.as_deref().filter(|p| !p.is_empty()) {
+        commit_message::check_pattern(pattern)?;
+    }
+    if let Some(template) = body.commit_message_template.as_deref().filter(|t| !t.is_empty()) {
+        commit_message::check_template(template)?;
+    }
+    let git_password = check_git_identity(&config, &name, &body)?;
+
+    let repo_path = config.repo_path(&name);
+    for remote in [&body.push_remote, &body.upstream_remote].into_iter().flatten() {
+        remotes::require_remote(&repo_path, remote)?;
+    }
+
+    // First, so a new repo_url is verified with the new credential
+    if body.git_user_name.is_some()
+        || body.git_user_email.is_some()
+        || body.git_username.is_some()
+        || git_password.is_some()
+    {
+        config
+            .set_workspace_git_identity(
+                &name,
+                body.git_user_name.clone(),
+                body.git_user_email.clone(),
+                body.git_username.clone(),
+                git_password,
+            )
+            .map_err(config_save_failed)?;
+    }
+
+    if let Some(repo_url) = repo_url {
+        if body.verify {
+            verify_repo_url(&repo_path, repo_url).await?;
+        }
+
+        let previous_url = git_ops::get_remote_url(&repo_path).ok();
+
+        git_ops::set_remote_url(&repo_path, repo_url).map_err(|e| e.context("Failed to update remote"))?;
+
+        if let Err(e) = config.update_workspace_repo_url(&name, repo_url.to_string()) {
+            // Keep git and the config in agreement
+            if let Some(previous) = previous_url {
+                let _ = git_ops::set_remote_url(&repo_path, &previous);
+            }
+            return Err(config_save_failed(e));
+        }
+    }
+
+    if body.push_remote.is_some() || body.upstream_remote.is_some() {
+        config
+            .set_workspace_remotes(&name, body.push_remote.clone(), body.upstream_remote.clone())
+            .map_err(config_save_failed)?;
+    }
+    if let Some(branches) = &body.protected_branches {
+        let mut branches = branches.clone();
+        branches.sort();
+        branches.dedup();
+        config
+            .set_workspace_protected_branches(&name, branches)
+            .map_err(config_save_failed)?;
+    }
+    if body.branch_name_pattern.is_some() || body.branch_name_template.is_some() {
+        config
+            .set_workspace_branch_naming(&name, body.branch_name_pattern.clone(), body.branch_name_template.clone())
+            .map_err(config_save_failed)?;
+    }
+    if body.commit_message_pattern.is_some()
+        || body.commit_message_template.is_some()
+        || body.commit_message_advisory.is_some()
+    {
+        config
+            .set_workspace_commit_messages(
+                &name,
+                body.commit_message_pattern.clone(),
+                body.commit_message_template.clone(),
+                body.commit_message_advisory,
+            )
+            .map_err(config_save_failed)?;
+    }
+    if body.signing_key_path.is_some() || body.signing_format.is_some() {
+        config
+            .set_workspace_signing(&name, body.signing_key_path.clone(), body.signing_format)
+            .map_err(config_save_failed)?;
+    }
+
+    state.listings.invalidate_workspace(&name);
+    events.publish(Event::WorkspaceUpdated { workspace: name.clone() });
+    audit::record(
+        &req,
+        "workspace.update",
+        serde_json::json!({
+            "workspace": name,
+            "repo_url": repo_url,
+            "push_remote": body.push_remote,
+            "upstream_remote": body.upstream_remote,
+            "protected_branches": body.protected_branches,
+            "branch_name_pattern": body.branch_name_pattern,
+            "branch_name_template": body.branch_name_template,
+            "commit_message_pattern": body.commit_message_pattern,
+            "commit_message_template": body.commit_message_template,
+            "commit_message_advisory": body.commit_message_advisory,
+            "signing_key_path": body.signing_key_path,
+            "signing_format": body.signing_format,
+            "git_user_name": body.git_user_name,
+            "git_user_email": body.git_user_email,
+            "git_username": body.git_username,
+            // Whether it changed only
+            "git_password": body.git_password.is_some()
+        }),
+    );
+
+    let ws_config = require_workspace(&config, &name)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "name": name,
+        "repo_url": ws_config.repo_url,
+        "push_remote": ws_config.push_remote(),
+        "upstream_remote": ws_config.upstream_remote(),
+        "protected_branches": ws_config.protected_branches(),
+        "branch_naming": branch_naming::BranchNaming::of(&ws_config),
+        "commit_messages": commit_message::CommitMessagePolicy::of(&ws_config),
+        "signing": signing_info(&ws_config),
+        "git_identity": GitIdentityInfo::of(&ws_config),
+        "status": workspace_status(&config, &name).await?,
+        "message": "Workspace updated"
+    })))
+}
+
+/// Checked-out worktree directories of a workspace, as found on disk
+fn worktree_dirs(config: &ConfigManager, workspace: &str) -> Vec<PathBuf> {
+    std::fs::read_dir(config.worktrees_path(workspace))
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.join(".git").exists())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Lock every worktree of `workspace`, for operations that move or remove
+/// them all at once
+async fn lock_worktrees(
+    config: &ConfigManager,
+    state: &AppState,
+    workspace: &str,
+    operation: &'static str,
+) -> Result<Vec<WorktreeGuard>, ApiError> {
+    let mut locks = Vec::new();
+    for worktree_path in worktree_dirs(config, workspace) {
+        locks.push(lock_worktree(config, state, &worktree_path, operation).await?);
+    }
+    Ok(locks)
+}
+
+/// POST /api/workspaces/{name}/rename - Rename a workspace and its directory
+pub async fn rename_workspace(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    events: web::Data<EventBus>,
+    path: web::Path<String>,
+    body: Json<RenameWorkspaceRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let new_name = body.new_name.trim();
+
+    let _guard = claim_workspace(&state, &name, "rename")?;
+    require_workspace(&config, &name)?;
+    validate_workspace_name(new_name).map_err(invalid_workspace_name)?;
+
+    let old_path = config.workspace_path(&name);
+    let new_path = config.workspace_path(new_name);
+
+    let already_exists = || {
+        ApiError::conflict(
+            "workspace_exists",
+            format!("Workspace '{}' already exists", new_name),
+        )
+    };
+
+    if config.get_workspace(new_name).is_some() || new_path.exists() {
+        return Err(already_exists());
+    }
+
+    let _new_guard = claim_workspace(&state, new_name, "rename")?;
+    let _locks = lock_worktrees(&config, &state, &name, "rename_workspace").await?;
+
+    // Shells inside the old directory follow the rename but keep a stale
+    // $PWD; check before moving, while their cwd still names the old path
+    let stale_sessions = state.sessions_under(&old_path).await;
+
+    if !config.rename_workspace(&name, new_name).map_err(config_save_failed)? {
+        return Err(already_exists());
+    }
+
+    watcher.unwatch(&old_path);
+    if let Err(e) = std::fs::rename(&old_path, &new_path) {
+        if let Err(rollback) = config.rename_workspace(new_name, &name) {
+            log::error!("Failed to restore workspace '{}' in config: {}", name, rollback);
+        }
+        config.scope_git(&name);
+        return Err(ApiError::io("Failed to rename workspace directory", e));
+    }
+    config.scope_git(new_name);
+
+    config.invalidate_workspace_status(&name);
+    config.invalidate_workspace_status(new_name);
+    config.invalidate_workspace_usage(&name);
+    config.invalidate_workspace_usage(new_name);
+    state.listings.invalidate_workspace(&name);
+    state.listings.invalidate_workspace(new_name);
+
+    // Worktree metadata stores absolute paths in both directions
+    let worktrees = worktree_dirs(&config, new_name);
+    let repair_error = git_ops::repair_worktrees(&config.repo_path(new_name), &worktrees)
+        .err()
+        .map(|e| e.to_string());
+    if let Some(e) = &repair_error {
+        log::warn!("Failed to repair worktrees of '{}': {}", new_name, e);
+    }
+
+    if !stale_sessions.is_empty() {
+        log::warn!(
+            "Terminal sessions {:?} were working inside renamed workspace '{}'",
+            stale_sessions,
+            name
+        );
+    }
+
+    events.publish(Event::WorkspaceRenamed {
+        workspace: name.clone(),
+        new_name: new_name.to_string(),
+    });
+    audit::record(
+        &req,
+        "workspace.rename",
+        serde_json::json!({ "workspace": name, "new_name": new_name }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "name": new_name,
+        "stale_sessions": stale_sessions,
+        "repair_error": repair_error,
+        "message": format!("Workspace '{}' renamed to '{}'", name, new_name)
+    })))
+}
+
+/// DELETE /api/workspaces/{name} - Delete a workspace
+pub async fn delete_workspace(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    events: web::Data<EventBus>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+
+    let _guard = claim_workspace(&state, &name, "deletion")?;
+    require_workspace(&config, &name)?;
+    let _locks = lock_worktrees(&config, &state, &name, "delete_workspace").await?;
+
+    // Tear down from the inside out and drop the config entry last, so a
+    // deletion that fails part way can be finished by calling again
+    let workspace_path = config.workspace_path(&name);
+    let repo_path = config.repo_path(&name);
+    watcher.unwatch(&workspace_path);
+
+    let closed_sessions = state.close_sessions_under(&workspace_path).await;
+
+    let mut removed_worktrees = Vec::new();
+    for worktree_path in worktree_dirs(&config, &name) {
+        let worktree_name = worktree_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if let Err(e) = git_ops::remove_worktree(&repo_path, &worktree_path, &worktree_name) {
+            return Err(ApiError::internal(format!(
+                "Failed to remove worktree '{}': {}",
+                worktree_name, e
+            ))
+            .with_details(serde_json::json!({
+                "closed_sessions": closed_sessions,
+                "removed_worktrees": removed_worktrees
+            })));
+        }
+        removed_worktrees.push(worktree_name);
+    }
+
+    // Remove workspace directory
+    match std::fs::remove_dir_all(&workspace_path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(ApiError::internal(format!(
+                "Failed to remove workspace directory: {}",
+                e
+            ))
+            .with_details(serde_json::json!({
+                "closed_sessions": closed_sessions,
+                "removed_worktrees": removed_worktrees
+            })));
+        }
+    }
+
+    // Remove from config
+    config.invalidate_workspace_status(&name);
+    config.invalidate_workspace_usage(&name);
+    state.listings.invalidate_workspace(&name);
+    config.remove_workspace(&name).map_err(config_save_failed)?;
+
+    events.publish(Event::WorkspaceDeleted { workspace: name.clone() });
+    audit::record(
+        &req,
+        "workspace.delete",
+        serde_json::json!({
+            "workspace": name,
+            "closed_sessions": closed_sessions,
+            "removed_worktrees": removed_worktrees
+        }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "closed_sessions": closed_sessions,
+        "removed_worktrees": removed_worktrees,
+        "message": format!("Workspace '{}' deleted", name)
+    })))
+}
+
+/// GET /api/workspaces/{name}/branches - List branches/worktrees
+pub async fn list_branches(
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    query: web::Query<ListBranchesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    use futures::StreamExt;
+
+    let workspace = path.into_inner();
+    let ws_config = require_workspace(&config, &workspace)?;
+    require_usable_repo(&config, &workspace).await?;
+
+    let repo_path = config.repo_path(&workspace);
+    let stamp = git_ops::refs_stamp(&repo_path);
+    if let Some(branches) = state.listings.branches(&workspace, stamp).filter(|_| !query.fresh) {
+        return Ok(HttpResponse::Ok().json(&*branches));
+    }
+    let generation = state.listings.generation();
+
+    let worktrees_path = config.worktrees_path(&workspace);
+
+    // Get all branches from repo
+    let branches = git_ops::list_branches(&repo_path).map_err(|e| e.context("Failed to list branches"))?;
+
+    // Get active worktrees
+    let worktrees = git_ops::list_worktrees(&repo_path).unwrap_or_default();
+
+    let mut result: Vec<BranchInfo> = branches
+        .into_iter()
+        .map(|name| {
+            let sanitized = sanitize_branch_name(&name);
+            let is_worktree = worktrees.contains(&sanitized);
+            let worktree_dir = is_worktree.then(|| worktrees_path.join(&sanitized));
+
+            BranchInfo {
+                needs_attention: worktree_dir
+                    .as_ref()
+                    .map(|dir| state.attention.of(&workspace, &name, dir))
+                    .unwrap_or_default(),
+                name,
+                is_worktree,
+                worktree_path: worktree_dir.map(|dir| dir.to_string_lossy().to_string()),
+                checked_out_branch: None,
+                head_commit: None,
+                dirty: None,
+                ahead: None,
+                behind: None,
+                stats_timeout: false,
+                detached: false,
+            }
+        })
+        .collect();
+    result.extend(worktrees.iter().filter(|name| is_detached_worktree(name)).map(|name| {
+        let worktree_dir = worktrees_path.join(name);
+        BranchInfo {
+            name: name.clone(),
+            is_worktree: true,
+            needs_attention: state.attention.of(&workspace, name, &worktree_dir),
+            worktree_path: Some(worktree_dir.to_string_lossy().to_string()),
+            checked_out_branch: None,
+            head_commit: None,
+            dirty: None,
+            ahead: None,
+            behind: None,
+            stats_timeout: false,
+            detached: true,
+        }
+    }));
+
+    // Each worktree takes a few git commands: run them for several worktrees
+    // at once, and list any whose commands hang without their stats
+    let timeout = config.get_branch_stats_timeout();
+    let stats: Vec<(usize, Option<WorktreeStats>)> = futures::stream::iter(
+        result
+            .iter()
+            .enumerate()
+            .filter_map(|(i, info)| Some((i, PathBuf::from(info.worktree_path.as_ref()?)))),
+    )
+    .map(|(i, worktree_dir)| {
+        let base_branch = ws_config.base_branch.clone();
+        async move { (i, WorktreeStats::collect_within(worktree_dir, base_branch, timeout).await) }
+    })
+    .buffer_unordered(config.get_branch_stats_workers())
+    .collect()
+    .await;
+    for (i, stats) in stats {
+        match stats {
+            Some(stats) => stats.apply(&mut result[i]),
+            None => {
+                log::warn!("Timed out collecting git stats of {:?}", result[i].worktree_path);
+                result[i].stats_timeout = true;
+            }
+        }
+    }
+
+    let complete = !result.iter().any(|info| info.stats_timeout);
+    let result = Arc::new(result);
+    if complete {
+        state.listings.store_branches(generation, &workspace, stamp, result.clone());
+    }
+    Ok(HttpResponse::Ok().json(&*result))
+}
+
+/// POST /api/workspaces/{name}/branches - Create a new worktree
+pub async fn create_branch(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    events: web::Data<EventBus>,
+    path: web::Path<String>,
+    body: Json<CreateBranchRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let workspace = path.into_inner();
+    let ws_config = require_workspace(&config, &workspace)?;
+
+    let repo_path = config.repo_path(&workspace);
+    if body.detached {
+        return create_detached(req, config, state, watcher, events, workspace, body.into_inner()).await;
+    }
+    if body.rev.is_some() {
+        return Err(ApiError::validation(
+            "invalid_body",
+            "ref is only taken with detached: true; start a branch from it with from_branch",
+        ));
+    }
+    let branch_name = &match (&body.branch_name, &body.auto_name) {
+        (Some(name), None) => name.clone(),
+        (None, Some(auto_name)) => {
+            let user = audit::Actor::from_request(&req).token.unwrap_or_else(|| "anonymous".to_string());
+            branch_naming::generate(&ws_config, &user, auto_name)?
+        }
+        _ => return Err(ApiError::validation("invalid_body", "Give either branch_name or auto_name")),
+    };
+    let from_branch = body.from_branch.as_deref().or(Some(&ws_config.base_branch));
+
+    validate_branch_name(branch_name)
+        .and_then(|()| from_branch.map_or(Ok(()), validate_branch_name))
+        .map_err(invalid_branch_name)?;
+    branch_naming::require_allowed(&ws_config, branch_name)?;
+
+    require_usable_repo(&config, &workspace).await?;
+    quota::require_space(&config, &workspace)?;
+
+    let worktree_path = config.worktree_path(&workspace, branch_name);
+
+    // Create worktree
+    let created = git_ops::create_worktree(&repo_path, &worktree_path, branch_name, from_branch);
+    config.invalidate_workspace_status(&workspace);
+    state.listings.invalidate_worktree(&workspace, branch_name);
+    created.map_err(|e| e.context("Failed to create worktree"))?;
+    quota::rescan(&config, &workspace);
+
+    let lock = lock_worktree(&config, &state, &worktree_path, "create_branch").await?;
+    let (submodule_error, lfs_error) = prepare_checkout(&worktree_path);
+    drop(lock);
+    watcher.ensure_watch(&workspace, branch_name, &worktree_path);
+
+    events.publish(Event::BranchCreated {
+        workspace: workspace.clone(),
+        branch: branch_name.clone(),
+    });
+    audit::record(
+        &req,
+        "branch.create",
+        serde_json::json!({ "workspace": workspace, "branch": branch_name, "from_branch": from_branch }),
+    );
+
+    let mut response = serde_json::json!({
+        "branch": branch_name,
+        "worktree_path": worktree_path.to_string_lossy(),
+        "message": "Worktree created successfully"
+    });
+    if let Some(error) = submodule_error {
+        response["submodule_error"] = error.into();
+    }
+    if let Some(error) = lfs_error {
+        response["lfs_error"] = error.into();
+    }
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Create a worktree pinned to the tag or commit `ref`, named
+/// `detached:<ref>`; commits, pushes, pulls, rebases and renames refuse it
+async fn create_detached(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    events: web::Data<EventBus>,
+    workspace: String,
+    body: CreateBranchRequest,
+) -> Result<HttpResponse, ApiError> {
+    let rev = match body {
+        CreateBranchRequest {
+            rev: Some(rev),
+            branch_name: None,
+            auto_name: None,
+            from_branch: None,
+            ..
+        } => rev,
+        _ => {
+            return Err(ApiError::validation(
+                "invalid_body",
+                "A detached worktree takes a ref and no branch_name, auto_name or from_branch",
+            ))
+        }
+    };
+    // Tags follow the rules of branch names, and commit IDs pass them
+    validate_branch_name(&rev).map_err(|_| ApiError::validation("invalid_ref", format!("Invalid ref '{}'", rev)))?;
+
+    require_usable_repo(&config, &workspace).await?;
+    quota::require_space(&config, &workspace)?;
+
+    let repo_path = config.repo_path(&workspace);
+    let commit = git_ops::resolve_commit(&repo_path, &rev)
+        .ok_or_else(|| ApiError::not_found("revision_not_found", format!("Revision '{}' not found", rev)))?;
+    let name = detached_worktree_name(&rev);
+    let worktree_path = config.worktree_path(&workspace, &name);
+
+    let created = git_ops::create_detached_worktree(&repo_path, &worktree_path, &commit);
+    config.invalidate_workspace_status(&workspace);
+    state.listings.invalidate_worktree(&workspace, &name);
+    created.map_err(|e| e.context("Failed to create worktree"))?;
+    quota::rescan(&config, &workspace);
+
+    let lock = lock_worktree(&config, &state, &worktree_path, "create_branch").await?;
+    let (submodule_error, lfs_error) = prepare_checkout(&worktree_path);
+    drop(lock);
+    watcher.ensure_watch(&workspace, &name, &worktree_path);
+
+    events.publish(Event::BranchCreated {
+        workspace: workspace.clone(),
+        branch: name.clone(),
+    });
+    audit::record(
+        &req,
+        "branch.create",
+        serde_json::json!({ "workspace": workspace, "branch": name, "ref": rev, "commit": commit, "detached": true }),
+    );
+
+    let mut response = serde_json::json!({
+        "branch": name,
+        "worktree_path": worktree_path.to_string_lossy(),
+        "detached": true,
+        "head_commit": commit,
+        "message": format!("Worktree created at '{}'", rev)
+    });
+    if let Some(error) = submodule_error {
+        response["submodule_error"] = error.into();
+    }
+    if let Some(error) = lfs_error {
+        response["lfs_error"] = error.into();
+    }
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Initialize the submodules and fetch the Git LFS content of a new
+/// worktree if it has any; the worktree is usable without them, so failures
+/// are returned to be reported, not fatal
+fn prepare_checkout(worktree_path: &Path) -> (Option<String>, Option<String>) {
+    let submodule_error = git_ops::has_submodules(worktree_path)
+        .then(|| git_ops::update_submodules(worktree_path).err())
+        .flatten()
+        .map(|e| {
+            log::warn!("Failed to initialize submodules in {:?}: {}", worktree_path, e);
+            e.to_string()
+        });
+    let lfs_error = git_ops::worktree_uses_lfs(worktree_path)
+        .then(|| fetch_lfs_content(worktree_path).err())
+        .flatten()
+        .inspect(|e| log::warn!("Git LFS content of {:?} not downloaded: {}", worktree_path, e));
+    (submodule_error, lfs_error)
+}
+
+/// Replace the LFS pointer files of a new worktree with their content
+fn fetch_lfs_content(worktree_path: &Path) -> Result<(), String> {
+    if !git_ops::lfs_available() {
+        return Err("The repository uses Git LFS but git-lfs is not installed; LFS files are left as pointers".to_string());
+    }
+    git_ops::lfs_install(worktree_path)
+        .and_then(|()| git_ops::lfs_pull(worktree_path))
+        .map_err(|e| e.to_string())
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/status - Checked-out branch,
+/// uncommitted changes, submodule states and conditions needing attention
+/// of a worktree
+pub async fn branch_status(
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let checked_out_branch = git_ops::get_current_branch(&worktree_path)
+        .map_err(|e| e.context("Failed to read the checked-out branch"))?;
+    let changes = git_ops::get_status(&worktree_path).map_err(|e| e.context("Failed to read git status"))?;
+    let submodules =
+        git_ops::submodule_status(&worktree_path).map_err(|e| e.context("Failed to read submodule status"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "branch": branch,
+        "checked_out_branch": checked_out_branch,
+        "head_commit": git_ops::resolve_commit(&worktree_path, "HEAD"),
+        "changes": changes,
+        "submodules": submodules,
+        "needs_attention": state.attention.of(&workspace, &branch, &worktree_path),
+    })))
+}
+
+/// Commits the index of a worktree records for its submodules, to tell
+/// after a pull or rebase whether they have to be updated
+fn submodule_pointers(worktree_path: &Path) -> Result<Vec<(String, String)>, ApiError> {
+    Ok(git_ops::submodule_pointers(worktree_path).map_err(|e| e.context("Failed to read submodule commits"))?)
+}
+
+/// Update the submodules of a worktree if a pull or rebase moved their
+/// commits away from `before`; whether it did
+fn update_moved_submodules(
+    job: &JobContext,
+    worktree_path: &Path,
+    before: &[(String, String)],
+) -> Result<bool, ApiError> {
+    if !git_ops::has_submodules(worktree_path) || submodule_pointers(worktree_path)? == before {
+        return Ok(false);
+    }
+    job.progress("Updating submodules");
+    git_ops::update_submodules(worktree_path).map_err(|e| e.context("Failed to update submodules"))?;
+    Ok(true)
+}
+
+/// DELETE /api/workspaces/{name}/branches/{branch} - Delete a worktree
+pub async fn delete_branch(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    events: web::Data<EventBus>,
+    path: web::Path<(String, String)>,
+    protection: web::Query<ProtectionQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let ws_config = require_workspace(&config, &workspace)?;
+    let overridden = require_unprotected(&req, &ws_config, &branch, &protection)?;
+
+    let repo_path = config.repo_path(&workspace);
+    let worktree_path = config.worktree_path(&workspace, &branch);
+    let worktree_name = sanitize_branch_name(&branch);
+    let _lock = lock_worktree(&config, &state, &worktree_path, "delete_branch").await?;
+
+    watcher.unwatch(&worktree_path);
+
+    let removed = git_ops::remove_worktree(&repo_path, &worktree_path, &worktree_name);
+    config.invalidate_workspace_status(&workspace);
+    state.listings.invalidate_worktree(&workspace, &branch);
+    removed.map_err(|e| e.context("Failed to remove worktree"))?;
+    drafts::discard_branch(&config, &workspace, &branch);
+
+    events.publish(Event::BranchDeleted {
+        workspace: workspace.clone(),
+        branch: branch.clone(),
+    });
+    audit::record(
+        &req,
+        "branch.delete",
+        serde_json::json!({ "workspace": workspace, "branch": branch, "override": overridden }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": format!("Worktree '{}' deleted", branch)
+    })))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/files - List files, leaving
+/// out those git ignores
+pub async fn list_files(
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ListFilesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
+
+    let tag = match query.tag.as_deref() {
+        Some(t) => match t.split_once(':') {
+            Some((key, value)) if !key.is_empty() => Some((key.to_string(), value.to_string())),
+            _ => {
+                return Err(ApiError::validation(
+                    "invalid_tag_filter",
+                    format!("Invalid tag filter '{}', expected key:value", t),
+                ));
+            }
+        },
+        None => None,
+    };
+
+    let frontmatter = query.detail || tag.is_some();
+    let cached = state.listings.tree(&workspace, &branch, frontmatter).filter(|_| !query.fresh);
+    let files = match cached {
+        Some(files) => files,
+        None => {
+            let generation = state.listings.generation();
+            let mut files: Vec<FileEntry> = file_ops::list_files(&worktree_path, None)
+                .map_err(|e| ApiError::io("Failed to list files", e))?;
+            if let Err(e) = gitignore::prune_ignored(&worktree_path, &mut files) {
+                log::warn!("Failed to leave ignored files out of {:?}: {}", worktree_path, e);
+            }
+            if frontmatter {
+                file_ops::annotate_frontmatter(&worktree_path, &mut files);
+            }
+            let files = Arc::new(files);
+            state.listings.store_tree(generation, &workspace, &branch, frontmatter, files.clone());
+            files
+        }
+    };
+
+    if let Some((key, value)) = tag {
+        let mut files = file_ops::filter_by_frontmatter(files.to_vec(), &key, &value);
+        if !query.detail {
+            file_ops::strip_frontmatter(&mut files);
+        }
+        return Ok(HttpResponse::Ok().json(files));
+    }
+
+    Ok(HttpResponse::Ok().json(&*files))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/file?path=x - Read file
+pub async fn read_file(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let file_path = &query.path;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
+    reject_lfs_pointer(&worktree_path, file_path)?;
+
+    if query.raw {
+        let file = file_ops::resolve_file(&worktree_path, file_path)
+            .and_then(actix_files::NamedFile::open)
+            .map_err(|e| ApiError::io("Failed to read file", e))?;
+        return Ok(file
+            .use_etag(true)
+            .use_last_modified(true)
+            .into_response(&req));
+    }
+
+    let content = read_text_file(&config, &worktree_path, file_path)?;
+    let draft = drafts::find(&config, &workspace, &branch, &worktree_path, file_path);
+    // The hash that identifies the revision of a file run or ticked. It
+    // doesn't cover the draft, so the answer isn't cached while there is one
+    let etag = caching::etag(&blocks::content_hash(&content));
+    if draft.is_none() && caching::is_fresh(&req, &etag) {
+        return Ok(not_modified(etag));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .json(serde_json::json!({
+            "path": file_path,
+            "content": content,
+            "has_draft": draft.is_some(),
+            "draft_saved_at": draft.map(|draft| draft.saved_at)
+        })))
+}
+
+/// How much of a file with an unknown extension is read to tell text from
+/// binary
+const CONTENT_SNIFF_BYTES: u64 = 8 * 1024;
+
+fn not_modified(etag: String) -> HttpResponse {
+    // Without a body at all, which compression would otherwise add
+    HttpResponse::NotModified()
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(actix_web::body::None::new())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawFileQuery {
+    pub path: String,
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/file/raw?path=x - Stream a
+/// file's content, for documents too big to pass around as a JSON string
+pub async fn read_raw_file(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<RawFileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let file_path = &query.path;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
+    serve_file(&req, &config, &worktree_path, file_path, Some(markdown_content_type()))
+}
+
+fn markdown_content_type() -> header::ContentType {
+    header::ContentType("text/markdown; charset=utf-8".parse().unwrap())
+}
+
+/// Best-effort content type of a worktree file: by its extension, and for
+/// extensions that don't tell, plain text if `head` (its first bytes) is
+/// UTF-8
+pub fn content_type_of(file_path: &str, head: &[u8]) -> header::ContentType {
+    if file_ops::is_markdown_file(file_path) {
+        return markdown_content_type();
+    }
+    let extension = Path::new(file_path).extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+    let guessed = actix_files::file_extension_to_mime(&extension);
+    if guessed.essence_str() != "application/octet-stream" {
+        if guessed.type_().as_str() == "text" && guessed.get_param("charset").is_none() {
+            return header::ContentType(format!("{}; charset=utf-8", guessed).parse().unwrap());
+        }
+        return header::ContentType(guessed);
+    }
+    if file_ops::is_text(head) {
+        header::ContentType::plaintext()
+    } else {
+        header::ContentType::octet_stream()
+    }
+}
+
+/// Stream `file_path` of a worktree with an ETag (or answer 304 if the
+/// client has it), refusing LFS pointers and files over `max_read_bytes`.
+/// Sent as `content_type`, or else as `content_type_of` guesses.
+pub fn serve_file(
+    req: &HttpRequest,
+    config: &ConfigManager,
+    worktree_path: &Path,
+    file_path: &str,
+    content_type: Option<header::ContentType>,
+) -> Result<HttpResponse, ApiError> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    reject_lfs_pointer(worktree_path, file_path)?;
+
+    let failed = |e| ApiError::io("Failed to read file", e);
+    let full_path = file_ops::resolve_file(worktree_path, file_path).map_err(failed)?;
+    // Hashed and sent from the same open file: saves replace files rather
+    // than rewrite them, so the ETag always matches the body
+    let mut file = std::fs::File::open(&full_path).map_err(failed)?;
+    let size = file.metadata().map_err(failed)?.len();
+    let limit = config.get_max_read_bytes();
+    if size > limit {
+        return Err(file_too_large(size, limit));
+    }
+    let etag = caching::etag(&blocks::reader_hash(&mut file).map_err(failed)?);
+    if caching::is_fresh(req, &etag) {
+        return Ok(not_modified(etag));
+    }
+    let content_type = match content_type {
+        Some(content_type) => content_type,
+        None => {
+            let mut head = Vec::new();
+            file.seek(SeekFrom::Start(0)).map_err(failed)?;
+            (&mut file).take(CONTENT_SNIFF_BYTES).read_to_end(&mut head).map_err(failed)?;
+            content_type_of(file_path, &head)
+        }
+    };
+    file.seek(SeekFrom::Start(0)).map_err(failed)?;
+
+    let mut response = actix_files::NamedFile::from_file(file, &full_path)
+        .map_err(failed)?
+        .set_content_type(content_type.0)
+        .disable_content_disposition()
+        .use_etag(false)
+        .use_last_modified(false)
+        .into_response(req);
+    let headers = response.headers_mut();
+    headers.insert(header::ETAG, header::HeaderValue::from_str(&etag).unwrap());
+    headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-cache"));
+    Ok(response)
+}
+
+/// 409 for a file that holds a Git LFS pointer instead of its content, so
+/// the pointer text isn't served in its place
+fn reject_lfs_pointer(worktree_path: &Path, file_path: &str) -> Result<(), ApiError> {
+    // A file that can't be resolved is reported by the read itself
+    let Some(pointer) = file_ops::resolve_file(worktree_path, file_path)
+        .ok()
+        .and_then(|full_path| file_ops::lfs_pointer(&full_path))
+    else {
+        return Ok(());
+    };
+    let lfs_available = git_ops::lfs_available();
+    let reason = if lfs_available {
+        "its content hasn't been downloaded"
+    } else {
+        "git-lfs is not installed on the server"
+    };
+    Err(ApiError::conflict(
+        "lfs_pointer",
+        format!("'{}' is a Git LFS pointer: {}", file_path, reason),
+    )
+    .with_details(serde_json::json!({ "oid": pointer.oid, "size": pointer.size, "lfs_available": lfs_available })))
+}
+
+fn file_too_large(size: u64, limit: u64) -> ApiError {
+    ApiError::payload_too_large(
+        "file_too_large",
+        format!("File is too large to open as text ({} bytes, limit {})", size, limit),
+    )
+    .with_details(serde_json::json!({ "size": size, "limit": limit }))
+}
+
+/// Read a file of a worktree as text, within the configured size limit
+pub fn read_text_file(config: &ConfigManager, worktree_path: &Path, file_path: &str) -> Result<String, ApiError> {
+    file_ops::read_file(worktree_path, file_path, config.get_max_read_bytes()).map_err(|e| match e {
+        ReadFileError::TooLarge { size, limit } => file_too_large(size, limit),
+        ReadFileError::Binary { size } => ApiError::unsupported_media_type(
+            "binary_file",
+            "File is not valid UTF-8 text",
+        )
+        .with_details(serde_json::json!({ "binary": true, "size": size })),
+        ReadFileError::Io(e) => ApiError::io("Failed to read file", e),
+    })
+}
+
+/// A heading of a document outline, with the headings below it
+#[derive(Debug, Serialize)]
+pub struct OutlineHeading {
+    pub level: u8,
+    pub title: String,
+    /// GitHub's anchor for the heading, to link to it with `#anchor`
+    pub anchor: String,
+    pub line: usize,
+    pub children: Vec<OutlineHeading>,
+}
+
+/// The headings of `items` deeper than `parent_level`, up to the next one
+/// that isn't, each holding the deeper ones after it
+fn nest_headings(
+    items: &mut std::iter::Peekable<impl Iterator<Item = OutlineHeading>>,
+    parent_level: u8,
+) -> Vec<OutlineHeading> {
+    let mut nested = Vec::new();
+    while let Some(mut heading) = items.next_if(|heading| heading.level > parent_level) {
+        heading.children = nest_headings(items, heading.level);
+        nested.push(heading);
+    }
+    nested
+}
+
+/// Heading tree and step locations of markdown `content`
+fn outline(path: Option<&str>, content: &str) -> serde_json::Value {
+    let runbook = blocks::parse(content);
+    let anchors = blocks::heading_anchors(&runbook.headings);
+    let mut headings = runbook
+        .headings
+        .into_iter()
+        .zip(anchors)
+        .map(|(heading, anchor)| OutlineHeading {
+            level: heading.level,
+            title: heading.title,
+            anchor,
+            line: heading.line,
+            children: Vec::new(),
+        })
+        .peekable();
+    let code_blocks: Vec<serde_json::Value> = runbook
+        .blocks
+        .iter()
+        .map(|block| {
+            serde_json::json!({
+                "index": block.index,
+                "language": block.language,
+                "start_line": block.start_line,
+                "end_line": block.end_line,
+                "section": block.section
+            })
+        })
+        .collect();
+    let tasks: Vec<serde_json::Value> = runbook
+        .tasks
+        .iter()
+        .map(|task| {
+            serde_json::json!({
+                "index": task.index,
+                "checked": task.checked,
+                "line": task.line,
+                "section": task.section
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "path": path,
+        "headings": nest_headings(&mut headings, 0),
+        "code_blocks": code_blocks,
+        "tasks": tasks
+    })
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/file/outline?path=x - Heading
+/// tree of a markdown file, with the lines of its code blocks and tasks
+pub async fn file_outline(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let content = read_text_file(&config, &worktree_path, &query.path)?;
+    Ok(HttpResponse::Ok().json(outline(Some(&query.path), &content)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OutlineRequest {
+    /// Only echoed back
+    #[serde(default)]
+    pub path: Option<String>,
+    pub content: String,
+}
+
+/// POST /api/workspaces/{name}/branches/{branch}/file/outline - Outline of
+/// unsaved content, answered like the GET without reading the file
+pub async fn content_outline(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    body: Json<OutlineRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    require_worktree(&config, &workspace, &branch)?;
+
+    Ok(HttpResponse::Ok().json(outline(body.path.as_deref(), &body.content)))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/blocks?path=x - Fenced code
+/// blocks of a markdown file as runbook steps, with its headings as an outline
+pub async fn list_blocks(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let content = read_text_file(&config, &worktree_path, &query.path)?;
+    let runbook = blocks::parse(&content);
+    let (declared, warnings) = variables::load(&worktree_path, &query.path, runbook.front_matter.as_deref());
+    let mut body = serde_json::json!({
+        "path": query.path,
+        "headings": runbook.headings,
+        "blocks": runbook.blocks,
+        "variables": declared
+    });
+    if !warnings.is_empty() {
+        body["variable_warnings"] = serde_json::json!(warnings);
+    }
+    Ok(HttpResponse::Ok().json(body))
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/file/diff?path=x - Diff working copy against HEAD
+pub async fn file_diff(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let full_path = file_ops::safe_join(&worktree_path, &query.path)
+        .map_err(|e| ApiError::io("Invalid path", e))?;
+    let rel_path = full_path
+        .strip_prefix(&worktree_path)
+        .unwrap_or(&full_path)
+        .to_string_lossy()
+        .to_string();
+
+    let committed = git_ops::show_file_at(&worktree_path, "HEAD", &rel_path)
+        .map_err(|e| e.context("Failed to read committed file"))?;
+
+    let current = if full_path.is_file() {
+        Some(std::fs::read(&full_path).map_err(|e| ApiError::io("Failed to read file", e))?)
+    } else {
+        None
+    };
 
-    // Save workspace config
-    if let Err(e) = config.add_workspace(name.clone(), repo_url.clone(), base_branch.clone()) {
-        // Cleanup on failure
-        let _ = std::fs::remove_dir_all(&workspace_path);
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to save workspace config: {}", e)
-        }));
+    if committed.is_none() && current.is_none() {
+        return Err(ApiError::not_found(
+            "file_not_found",
+            format!("File '{}' not found in worktree or HEAD", query.path),
+        ));
     }
 
-    HttpResponse::Created().json(serde_json::json!({
-        "name": name,
-        "repo_url": repo_url,
-        "base_branch": base_branch,
-        "message": "Workspace created successfully"
-    }))
+    Ok(HttpResponse::Ok().json(diff::diff_file(&rel_path, committed.as_deref(), current.as_deref())))
 }
 
-/// DELETE /api/workspaces/{name} - Delete a workspace
-pub async fn delete_workspace(
+/// POST /api/workspaces/{name}/branches/{branch}/file/restore - Bring back a
+/// file's content from a revision. Without a `ref` (or with `HEAD`), a file
+/// missing at HEAD is taken from the last commit that had it.
+pub async fn restore_file(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
-    path: web::Path<String>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
-    let name = path.into_inner();
-
-    // Check if workspace exists
-    if config.get_workspace(&name).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", name)
-        }));
-    }
-
-    // Remove workspace directory
-    let workspace_path = config.workspace_path(&name);
-    if let Err(e) = std::fs::remove_dir_all(&workspace_path) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to remove workspace directory: {}", e)
-        }));
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
+    path: web::Path<(String, String)>,
+    body: Json<RestoreFileRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let body = body.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let full_path = file_ops::safe_join(&worktree_path, &body.path)
+        .map_err(|e| ApiError::io("Invalid path", e))?;
+    let rel_path = full_path
+        .strip_prefix(&worktree_path)
+        .unwrap_or(&full_path)
+        .to_string_lossy()
+        .to_string();
+
+    let _lock = lock_worktree(&config, &state, &worktree_path, "restore_file").await?;
+    let rev = body.rev.as_deref().unwrap_or("HEAD");
+    let commit = git_ops::resolve_commit(&worktree_path, rev).ok_or_else(|| {
+        ApiError::not_found("revision_not_found", format!("Revision '{}' not found", rev))
+    })?;
+    let mut from_deleted = false;
+    let mut restored = git_ops::show_file_at(&worktree_path, &commit, &rel_path)
+        .map_err(|e| e.context("Failed to read committed file"))?
+        .map(|content| (commit.clone(), content));
+    if restored.is_none() && rev == "HEAD" {
+        if let Some(earlier) = git_ops::last_commit_with_file(&worktree_path, &commit, &rel_path)
+            .map_err(|e| e.context("Failed to search history"))?
+        {
+            restored = git_ops::show_file_at(&worktree_path, &earlier, &rel_path)
+                .map_err(|e| e.context("Failed to read committed file"))?
+                .map(|content| (earlier, content));
+            from_deleted = restored.is_some();
+        }
     }
+    let Some((commit, content)) = restored else {
+        return Err(ApiError::not_found(
+            "file_not_found",
+            format!("File '{}' not found at '{}' or in its history", body.path, rev),
+        ));
+    };
 
-    // Remove from config
-    if let Err(e) = config.remove_workspace(&name) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to remove workspace from config: {}", e)
-        }));
+    let current = if full_path.is_file() {
+        Some(std::fs::read(&full_path).map_err(|e| ApiError::io("Failed to read file", e))?)
+    } else {
+        None
+    };
+    let overwrites = current.as_ref().is_some_and(|current| *current != content);
+    if overwrites && !body.overwrite {
+        return Err(ApiError::conflict(
+            "file_modified",
+            format!("'{}' differs from its content at {}; pass overwrite to replace it", body.path, rev),
+        )
+        .with_details(serde_json::json!({
+            "current_hash": current.as_deref().map(blocks::content_hash),
+            "commit": commit,
+        })));
     }
 
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": format!("Workspace '{}' deleted", name)
-    }))
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
+    let written = file_ops::write_bytes(&worktree_path, &rel_path, &content);
+    state.listings.invalidate_tree(&workspace, &branch);
+    written.map_err(|e| ApiError::io("Failed to restore file", e))?;
+    let summary = git_ops::commit_summary(&worktree_path, &commit)
+        .map_err(|e| e.context("Failed to read commit"))?;
+    log::info!("Restored {} in {}/{} from {}", rel_path, workspace, branch, commit);
+
+    audit::record(
+        &req,
+        "file.restore",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "path": body.path,
+            "ref": rev,
+            "commit": commit,
+            "overwrote": overwrites,
+        }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": body.path,
+        "commit": summary,
+        "from_deleted": from_deleted,
+        "bytes": content.len(),
+    })))
 }
 
-/// GET /api/workspaces/{name}/branches - List branches/worktrees
-pub async fn list_branches(
-    req: HttpRequest,
+/// GET /api/workspaces/{name}/branches/{branch}/search?q=x - Full-text search
+pub async fn search_files(
     config: web::Data<Arc<ConfigManager>>,
-    path: web::Path<String>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
+    path: web::Path<(String, String)>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let query = query.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    if query.q.is_empty() {
+        return Err(ApiError::validation("empty_query", "Search query must not be empty"));
     }
 
-    let workspace = path.into_inner();
+    let pattern = file_ops::build_search_pattern(&query.q, query.regex, query.case_sensitive)
+        .map_err(|e| ApiError::validation("invalid_regex", format!("Invalid regex: {}", e)))?;
 
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
-    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .min(MAX_SEARCH_LIMIT);
 
-    let repo_path = config.repo_path(&workspace);
-    let worktrees_path = config.worktrees_path(&workspace);
+    let results = logging::block(move || file_ops::search(&worktree_path, &pattern, limit))
+        .await?
+        .map_err(|e| ApiError::io("Failed to search files", e))?;
+    Ok(HttpResponse::Ok().json(results))
+}
 
-    // Get all branches from repo
-    let branches = match git_ops::list_branches(&repo_path) {
-        Ok(b) => b,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to list branches: {}", e)
-            }));
+/// GET /api/workspaces/{name}/branches/{branch}/archive - Download worktree as zip/tar.gz
+pub async fn download_archive(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ArchiveQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let (format, extension, content_type) = match query.format.as_deref().unwrap_or("zip") {
+        "zip" => ("zip", "zip", "application/zip"),
+        "tar.gz" | "tgz" => ("tar.gz", "tar.gz", "application/gzip"),
+        other => {
+            return Err(ApiError::validation(
+                "invalid_archive_format",
+                format!("Unsupported archive format '{}', expected zip or tar.gz", other),
+            ));
         }
     };
 
-    // Get active worktrees
-    let worktrees = git_ops::list_worktrees(&repo_path).unwrap_or_default();
-
-    let result: Vec<BranchInfo> = branches
-        .into_iter()
-        .map(|name| {
-            let sanitized = sanitize_branch_name(&name);
-            let is_worktree = worktrees.contains(&sanitized);
-            let worktree_path = if is_worktree {
-                Some(worktrees_path.join(&sanitized).to_string_lossy().to_string())
-            } else {
-                None
-            };
-
-            BranchInfo {
-                name,
-                is_worktree,
-                worktree_path,
+    // Resolve the optional subdirectory relative to the worktree root
+    let subpath = match query.path.as_deref().filter(|p| !p.trim_matches('/').is_empty()) {
+        Some(p) => match file_ops::resolve_path(&worktree_path, p) {
+            Ok(full) if full.is_dir() => Some(
+                full.strip_prefix(&worktree_path)
+                    .unwrap_or(&full)
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            Ok(_) => {
+                return Err(ApiError::validation(
+                    "not_a_directory",
+                    format!("'{}' is not a directory", p),
+                ));
             }
-        })
-        .collect();
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+                return Err(ApiError::io("Invalid path", e));
+            }
+            Err(_) => {
+                return Err(ApiError::not_found("path_not_found", format!("Path '{}' not found", p)));
+            }
+        },
+        None => None,
+    };
 
-    HttpResponse::Ok().json(result)
-}
+    let snapshot_path = worktree_path.clone();
+    let tree = logging::block(move || git_ops::snapshot_worktree(&snapshot_path))
+        .await?
+        .map_err(|e| e.context("Failed to snapshot worktree"))?;
+
+    let mut name = format!("{}-{}", workspace, sanitize_branch_name(&branch));
+    let treeish = match &subpath {
+        Some(sub) => {
+            let dir_name = Path::new(sub)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            name = format!("{}-{}", name, dir_name);
+            format!("{}:{}", tree, sub)
+        }
+        None => tree,
+    };
 
-/// POST /api/workspaces/{name}/branches - Create a new worktree
-pub async fn create_branch(
-    req: HttpRequest,
-    config: web::Data<Arc<ConfigManager>>,
-    path: web::Path<String>,
-    body: web::Json<CreateBranchRequest>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
+    // Directories holding only ignored files don't make it into the snapshot
+    if !git_ops::object_exists(&worktree_path, &treeish) {
+        return Err(ApiError::not_found(
+            "nothing_to_archive",
+            "Nothing to archive at the requested path",
+        ));
     }
 
-    let workspace = path.into_inner();
+    let child = git_ops::spawn_archive(&worktree_path, &treeish, format, &format!("{}/", name))
+        .map_err(|e| e.context("Failed to create archive"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        // Already compressed
+        .insert_header(header::ContentEncoding::Identity)
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.{}\"", name, extension),
+        ))
+        .streaming(stream_child_stdout(child)))
+}
 
-    // Check if workspace exists
-    let ws_config = match config.get_workspace(&workspace) {
-        Some(c) => c,
-        None => {
-            return HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Workspace '{}' not found", workspace)
-            }));
+/// Stream a child process's stdout as a response body. A bounded channel
+/// provides backpressure so large outputs never accumulate in memory; the
+/// child is killed if the client goes away.
+fn stream_child_stdout(
+    mut child: std::process::Child,
+) -> impl futures::Stream<Item = Result<web::Bytes, std::io::Error>> {
+    let (tx, rx) = mpsc::channel::<Result<web::Bytes, std::io::Error>>(8);
+    let stdout = child.stdout.take();
+
+    std::thread::spawn(move || {
+        if let Some(mut stdout) = stdout {
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if tx.blocking_send(Ok(web::Bytes::copy_from_slice(&buf[..n]))).is_err() {
+                            log::debug!("Client disconnected, killing streaming child process");
+                            let _ = child.kill();
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = child.kill();
+                        let _ = tx.blocking_send(Err(e));
+                        break;
+                    }
+                }
+            }
         }
-    };
-
-    let repo_path = config.repo_path(&workspace);
-    let branch_name = &body.branch_name;
-    let from_branch = body.from_branch.as_deref().or(Some(&ws_config.base_branch));
-    let worktree_path = config.worktree_path(&workspace, branch_name);
 
-    // Create worktree
-    if let Err(e) = git_ops::create_worktree(&repo_path, &worktree_path, branch_name, from_branch) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to create worktree: {}", e)
-        }));
-    }
+        match child.wait_with_output() {
+            Ok(output) if !output.status.success() => {
+                let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+                log::error!("Streaming child process failed: {}", stderr);
+                let _ = tx.blocking_send(Err(std::io::Error::other(stderr)));
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("Failed to wait for streaming child process: {}", e),
+        }
+    });
 
-    HttpResponse::Created().json(serde_json::json!({
-        "branch": branch_name,
-        "worktree_path": worktree_path.to_string_lossy(),
-        "message": "Worktree created successfully"
-    }))
+    futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) })
 }
 
-/// DELETE /api/workspaces/{name}/branches/{branch} - Delete a worktree
-pub async fn delete_branch(
+/// PUT /api/workspaces/{name}/branches/{branch}/file?path=x - Save file
+pub async fn save_file(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
     path: web::Path<(String, String)>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    query: web::Query<FileQuery>,
+    body: Json<SaveFileRequest>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
+    let file_path = &query.path;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
 
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
-    }
+    quota::require_space(&config, &workspace)?;
 
-    let repo_path = config.repo_path(&workspace);
-    let worktree_path = config.worktree_path(&workspace, &branch);
-    let worktree_name = sanitize_branch_name(&branch);
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
 
-    if let Err(e) = git_ops::remove_worktree(&repo_path, &worktree_path, &worktree_name) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to remove worktree: {}", e)
-        }));
-    }
+    let written = file_ops::write_file(&worktree_path, file_path, &body.content);
+    state.listings.invalidate_tree(&workspace, &branch);
+    written.map_err(|e| ApiError::io("Failed to save file", e))?;
+    config.add_workspace_usage(&workspace, body.content.len() as u64);
 
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": format!("Worktree '{}' deleted", branch)
-    }))
+    // Only the size of the new content is recorded, never the content itself
+    audit::record(
+        &req,
+        "file.save",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "path": file_path,
+            "bytes": body.content.len()
+        }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "File saved successfully",
+        "path": file_path
+    })))
 }
 
-/// GET /api/workspaces/{name}/branches/{branch}/files - List files
-pub async fn list_files(
+/// POST /api/workspaces/{name}/branches/{branch}/file?path=x - Create a file
+/// that doesn't exist yet
+pub async fn create_file(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
     path: web::Path<(String, String)>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    query: web::Query<FileQuery>,
+    body: Json<CreateFileRequest>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
+    let file_path = &query.path;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
 
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
-    }
+    quota::require_space(&config, &workspace)?;
 
-    let worktree_path = config.worktree_path(&workspace, &branch);
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
 
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found. Create it first.", branch)
-        }));
+    // Check and write under one lock, or two creates of the same path could
+    // both find it missing
+    let created = {
+        let _guard = state.file_write_lock.lock().unwrap();
+        file_ops::create_file(&worktree_path, file_path, body.content.as_deref())
+    };
+    state.listings.invalidate_tree(&workspace, &branch);
+    created.map_err(|e| ApiError::io("Failed to create file", e))?;
+    let bytes = file_ops::resolve_file(&worktree_path, file_path)
+        .and_then(std::fs::metadata)
+        .map(|m| m.len())
+        .unwrap_or(0);
+    config.add_workspace_usage(&workspace, bytes);
+
+    audit::record(
+        &req,
+        "file.create",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "path": file_path,
+            "bytes": bytes
+        }),
+    );
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "message": "File created successfully",
+        "path": file_path,
+        "bytes": bytes
+    })))
+}
+
+/// The content of `file_path` with `body`'s changes, if it is still at
+/// `base_hash`
+fn patched_content(
+    config: &ConfigManager,
+    worktree_path: &Path,
+    file_path: &str,
+    body: &PatchFileRequest,
+) -> Result<String, ApiError> {
+    let content = read_text_file(config, worktree_path, file_path)?;
+    let current_hash = blocks::content_hash(&content);
+    if current_hash != body.base_hash {
+        // With the content, so the client can rebase its edits without
+        // reading the file again
+        return Err(ApiError::conflict("file_changed", format!("'{}' changed since it was read", file_path))
+            .with_details(serde_json::json!({ "file_hash": current_hash, "content": content })));
     }
 
-    let files: Vec<FileEntry> = match file_ops::list_files(&worktree_path, None) {
-        Ok(f) => f,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to list files: {}", e)
-            }));
-        }
+    let invalid = |code, e: patch::PatchError| {
+        ApiError::validation(code, e.message).with_details(serde_json::json!({ "index": e.index }))
     };
-
-    HttpResponse::Ok().json(files)
+    let updated = match (&body.edits, &body.diff) {
+        (Some(edits), None) => patch::apply_edits(&content, edits).map_err(|e| invalid("invalid_edit", e))?,
+        (None, Some(diff)) => patch::diff_edits(&content, diff)
+            .and_then(|edits| patch::apply_edits(&content, &edits))
+            .map_err(|e| invalid("invalid_diff", e))?,
+        _ => return Err(ApiError::validation("invalid_body", "Give exactly one of 'edits' and 'diff'")),
+    };
+    let limit = config.get_max_save_bytes();
+    if updated.len() > limit {
+        return Err(ApiError::payload_too_large(
+            "file_too_large",
+            format!("Patched file exceeds the {} byte limit", limit),
+        )
+        .with_details(serde_json::json!({ "size": updated.len(), "limit": limit })));
+    }
+    file_ops::write_file(worktree_path, file_path, &updated).map_err(|e| ApiError::io("Failed to save file", e))?;
+    Ok(updated)
 }
 
-/// GET /api/workspaces/{name}/branches/{branch}/file?path=x - Read file
-pub async fn read_file(
+/// PATCH /api/workspaces/{name}/branches/{branch}/file?path=x - Apply edits
+/// to a file, if it is still at the revision they were made against
+pub async fn patch_file(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
     path: web::Path<(String, String)>,
     query: web::Query<FileQuery>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    body: Json<PatchFileRequest>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
     let file_path = &query.path;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
 
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
-    }
-
-    let worktree_path = config.worktree_path(&workspace, &branch);
+    quota::require_space(&config, &workspace)?;
 
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found", branch)
-        }));
-    }
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
 
-    match file_ops::read_file(&worktree_path, file_path) {
-        Ok(content) => HttpResponse::Ok().json(serde_json::json!({
+    // Check and write under one lock, or two patches against the same
+    // revision could both pass the check
+    let patched = {
+        let _guard = state.file_write_lock.lock().unwrap();
+        patched_content(&config, &worktree_path, file_path, &body)
+    };
+    state.listings.invalidate_tree(&workspace, &branch);
+    let updated = patched?;
+    config.add_workspace_usage(&workspace, updated.len() as u64);
+
+    let file_hash = blocks::content_hash(&updated);
+    audit::record(
+        &req,
+        "file.patch",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
             "path": file_path,
-            "content": content
-        })),
-        Err(e) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Failed to read file: {}", e)
-        })),
-    }
+            "bytes": updated.len()
+        }),
+    );
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, caching::etag(&file_hash)))
+        .json(serde_json::json!({
+            "message": "File saved successfully",
+            "path": file_path,
+            "file_hash": file_hash,
+            "bytes": updated.len()
+        })))
 }
 
-/// PUT /api/workspaces/{name}/branches/{branch}/file?path=x - Save file
-pub async fn save_file(
+/// PUT /api/workspaces/{name}/branches/{branch}/file/raw?path=x - Save the
+/// request body as a file, written to a temporary file as it arrives and
+/// renamed into place. With `If-Match`, only if the file is still at that
+/// revision.
+pub async fn save_raw_file(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
     path: web::Path<(String, String)>,
-    query: web::Query<FileQuery>,
-    body: web::Json<SaveFileRequest>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
+    query: web::Query<RawFileQuery>,
+    mut body: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    use futures::StreamExt;
 
     let (workspace, branch) = path.into_inner();
     let file_path = &query.path;
-
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let limit = config.get_max_save_bytes();
+    let too_large = || {
+        ApiError::payload_too_large(
+            "body_too_large",
+            format!("Request body exceeds the {} byte limit", limit),
+        )
+        .with_details(serde_json::json!({ "limit": limit }))
+    };
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return Err(too_large());
     }
-
-    let worktree_path = config.worktree_path(&workspace, &branch);
-
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found", branch)
-        }));
+    quota::require_space(&config, &workspace)?;
+
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
+
+    let failed = |e| ApiError::io("Failed to save file", e);
+    let mut pending = file_ops::PendingWrite::create(&worktree_path, file_path).map_err(failed)?;
+    let mut hasher = blocks::ContentHasher::new();
+    let mut bytes = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| {
+            ApiError::validation("invalid_body", format!("Failed to read request body: {}", e))
+        })?;
+        bytes += chunk.len();
+        if bytes > limit {
+            return Err(too_large());
+        }
+        hasher.update(&chunk);
+        pending.write(&chunk).map_err(failed)?;
     }
-
-    if let Err(e) = file_ops::write_file(&worktree_path, file_path, &body.content) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to save file: {}", e)
-        }));
+    let file_hash = hasher.finish();
+
+    {
+        // Check and replace under one lock, or two saves of the same
+        // revision could both pass the check
+        let _guard = state.file_write_lock.lock().unwrap();
+        if req.headers().contains_key(header::IF_MATCH) {
+            let current = match std::fs::File::open(pending.path()) {
+                Ok(file) => Some(blocks::reader_hash(file).map_err(failed)?),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(failed(e)),
+            };
+            if !caching::if_match(&req, current.as_deref().map(caching::etag).as_deref()) {
+                return Err(ApiError::precondition_failed(
+                    "file_changed",
+                    format!("'{}' changed since it was read", file_path),
+                )
+                .with_details(serde_json::json!({ "file_hash": current })));
+            }
+        }
+        pending.commit().map_err(failed)?;
     }
+    state.listings.invalidate_tree(&workspace, &branch);
+    config.add_workspace_usage(&workspace, bytes as u64);
+
+    audit::record(
+        &req,
+        "file.save",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "path": file_path,
+            "bytes": bytes
+        }),
+    );
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, caching::etag(&file_hash)))
+        .json(serde_json::json!({
+            "message": "File saved successfully",
+            "path": file_path,
+            "bytes": bytes,
+            "file_hash": file_hash
+        })))
+}
 
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": "File saved successfully",
-        "path": file_path
-    }))
+#[derive(Debug, Deserialize)]
+pub struct DeleteFileQuery {
+    pub path: String,
+    /// Delete for good instead of moving the file to the trash
+    #[serde(default)]
+    pub permanent: bool,
 }
 
-/// POST /api/workspaces/{name}/branches/{branch}/commit - Commit files
-pub async fn commit_files(
+/// DELETE /api/workspaces/{name}/branches/{branch}/file?path=x - Move a file
+/// to the worktree's trash, or with `permanent` delete it
+pub async fn delete_file(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    watcher: web::Data<Arc<FileWatcher>>,
     path: web::Path<(String, String)>,
-    body: web::Json<CommitRequest>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    query: web::Query<DeleteFileQuery>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
+    let file_path = &query.path;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
 
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
-    }
+    watcher.ensure_watch(&workspace, &branch, &worktree_path);
 
-    let worktree_path = config.worktree_path(&workspace, &branch);
+    let deleted = if query.permanent {
+        file_ops::resolve_file(&worktree_path, file_path)
+            .and_then(|_| file_ops::delete_file(&worktree_path, file_path))
+            .map(|()| None)
+    } else {
+        trash::purge_expired(&config, &worktree_path);
+        trash::move_to_trash(&worktree_path, file_path).map(Some)
+    };
+    state.listings.invalidate_tree(&workspace, &branch);
+    let item = deleted.map_err(|e| ApiError::io("Failed to delete file", e))?;
+
+    audit::record(
+        &req,
+        "file.delete",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "path": file_path,
+            "permanent": query.permanent,
+            "trash_id": item.as_ref().map(|item| &item.id)
+        }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": file_path,
+        "permanent": query.permanent,
+        "trash": item
+    })))
+}
 
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found", branch)
-        }));
-    }
+/// Take the lock of a worktree for `operation`, a git operation that
+/// changes it, waiting for the one holding it up to the configured time
+pub async fn lock_worktree(
+    config: &ConfigManager,
+    state: &AppState,
+    worktree_path: &Path,
+    operation: &'static str,
+) -> Result<WorktreeGuard, ApiError> {
+    state
+        .worktree_locks
+        .acquire(worktree_path, operation, config.get_worktree_lock_timeout())
+        .await
+}
 
-    match git_ops::commit_files(&worktree_path, &body.files, &body.message) {
-        Ok(commit_id) => HttpResponse::Ok().json(serde_json::json!({
-            "message": "Commit created successfully",
-            "commit_id": commit_id
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to commit: {}", e)
-        })),
-    }
+fn nothing_to_commit() -> ApiError {
+    ApiError::validation("nothing_to_commit", "There are no changes to commit")
 }
 
-/// POST /api/workspaces/{name}/branches/{branch}/push - Push branch
-pub async fn push_branch(
+/// POST /api/workspaces/{name}/branches/{branch}/commit - Commit files, or
+/// with `all` every change, and list what the commit includes
+pub async fn commit_files(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
     path: web::Path<(String, String)>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    protection: web::Query<ProtectionQuery>,
+    body: Json<CommitRequest>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
+    let ws_config = require_workspace(&config, &workspace)?;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+    require_checked_out(&worktree_path, &branch)?;
+    let overridden = require_unprotected(&req, &ws_config, &branch, &protection)?;
+    if body.files.is_empty() && !body.all {
+        return Err(ApiError::validation("invalid_body", "Give the files to commit, or all: true"));
+    }
+    if body.no_verify && !may_write(&req) {
+        return Err(ApiError::forbidden(
+            "insufficient_scope",
+            "Skipping commit hooks needs a token with read_write scope",
+        ));
+    }
 
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
+    let mut files = Vec::with_capacity(body.files.len());
+    for file in &body.files {
+        let full_path = file_ops::safe_join(&worktree_path, file).map_err(|e| ApiError::io("Invalid path", e))?;
+        let rel_path = full_path.strip_prefix(&worktree_path).unwrap_or(&full_path);
+        let rel_path = rel_path.to_string_lossy().into_owned();
+        // Deleted files are still in the index
+        let known = full_path.symlink_metadata().is_ok()
+            || git_ops::is_tracked(&worktree_path, &rel_path).map_err(|e| e.context("Failed to read index"))?;
+        if !known {
+            return Err(ApiError::not_found("path_not_found", format!("'{}' not found in the worktree", file)));
+        }
+        files.push(rel_path);
     }
 
-    let worktree_path = config.worktree_path(&workspace, &branch);
+    let _lock = lock_worktree(&config, &state, &worktree_path, "commit").await?;
+    if !git_ops::has_uncommitted_changes(&worktree_path).map_err(|e| e.context("Failed to read status"))? {
+        return Err(nothing_to_commit());
+    }
+    let warning = if ws_config.commit_message_pattern.is_some() {
+        // The files the example message names
+        let changed = if body.all {
+            changed_paths(&worktree_path)?
+        } else {
+            files.clone()
+        };
+        commit_message::check(&ws_config, &body.message, &branch, &changed)?
+    } else {
+        None
+    };
+    git_ops::stage_files(&worktree_path, &files).map_err(|e| e.context("Failed to stage files"))?;
+    if body.all {
+        git_ops::stage_all(&worktree_path).map_err(|e| e.context("Failed to stage changes"))?;
+    }
+    if !git_ops::has_staged_changes(&worktree_path).map_err(|e| e.context("Failed to read status"))? {
+        return Err(nothing_to_commit());
+    }
 
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found", branch)
-        }));
+    let signing = ws_config.signing();
+    // To tell which files the hooks change, if they refuse the commit
+    let status_before = git_ops::get_status(&worktree_path).map_err(|e| e.context("Failed to read status"))?;
+    let committed = git_ops::commit_staged(&worktree_path, &body.message, signing.as_ref(), body.no_verify);
+    state.listings.invalidate_worktree(&workspace, &branch);
+    let commit_id = committed.map_err(|e| match e {
+        CommitError::Hook { hooks, stdout, stderr } => hook_failed(&worktree_path, &status_before, hooks, stdout, stderr),
+        CommitError::Git(e) => match &signing {
+            Some(signing) if git_ops::is_signing_failure(&e) => signing_failed(signing, e),
+            _ => e.context("Failed to commit").into(),
+        },
+    })?;
+    let committed = git_ops::committed_files(&worktree_path, &commit_id)
+        .map_err(|e| e.context("Failed to list committed files"))?;
+    let signed = git_ops::is_signed(&worktree_path, &commit_id).map_err(|e| e.context("Failed to read commit"))?;
+
+    events.publish(Event::Git {
+        workspace: workspace.clone(),
+        branch: Some(branch.clone()),
+        operation: "commit",
+        commit_id: Some(commit_id.clone()),
+    });
+    audit::record(
+        &req,
+        "git.commit",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "files": committed.iter().map(|file| &file.path).collect::<Vec<_>>(),
+            "all": body.all,
+            "message": body.message,
+            "commit_id": commit_id,
+            "signed": signed,
+            "no_verify": body.no_verify,
+            "override": overridden,
+            "warning": warning
+        }),
+    );
+
+    let mut response = serde_json::json!({
+        "message": "Commit created successfully",
+        "commit_id": commit_id,
+        "signed": signed,
+        "files": committed
+    });
+    if let Some(warning) = warning {
+        response["warning"] = serde_json::json!(warning);
     }
+    Ok(HttpResponse::Ok().json(response))
+}
 
-    if let Err(e) = git_ops::push_branch(&worktree_path) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to push: {}", e)
-        }));
+/// 422 `hook_failed` for a commit a hook refused, with what the hooks
+/// printed and the files they changed, e.g. a formatter's rewrites
+fn hook_failed(
+    worktree_path: &Path,
+    status_before: &[git_ops::ChangedFile],
+    hooks: Vec<String>,
+    stdout: String,
+    stderr: String,
+) -> ApiError {
+    let modified: Vec<String> = git_ops::get_status(worktree_path)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|change| {
+            !status_before
+                .iter()
+                .any(|before| before.path == change.path && before.status == change.status)
+        })
+        .map(|change| change.path)
+        .collect();
+    let mut message = format!("Commit refused by a commit hook ({})", hooks.join(", "));
+    if !modified.is_empty() {
+        message.push_str("; files were modified by hooks, review and retry");
     }
+    ApiError::unprocessable("hook_failed", message).with_details(serde_json::json!({
+        "hooks": hooks,
+        "stdout": stdout,
+        "stderr": stderr,
+        "files_modified": modified
+    }))
+}
 
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": "Push completed successfully"
+/// 500 `signing_failed` for a commit that couldn't be signed, with why:
+/// the key or program is missing, or the key couldn't be used (e.g. it is
+/// locked by a passphrase)
+fn signing_failed(signing: &git_ops::Signing, e: git_ops::GitError) -> ApiError {
+    let reason = signing.check().err();
+    ApiError::server(
+        "signing_failed",
+        format!("Failed to sign the commit: {}", reason.as_deref().unwrap_or("the key couldn't be used")),
+    )
+    .with_details(serde_json::json!({
+        "format": signing.format,
+        "reason": reason,
+        "stderr": e.stderr
     }))
 }
 
+/// Paths of the uncommitted changes in a worktree
+fn changed_paths(worktree_path: &Path) -> Result<Vec<String>, ApiError> {
+    let changes = git_ops::get_status(worktree_path).map_err(|e| e.context("Failed to read status"))?;
+    Ok(changes.into_iter().map(|change| change.path).collect())
+}
+
+/// GET /api/workspaces/{name}/branches/{branch}/commit/template - The
+/// workspace's commit message template filled in for the worktree's
+/// changes, with the pattern messages must match
+pub async fn commit_template(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let ws_config = require_workspace(&config, &workspace)?;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+
+    let body = logging::block(move || -> Result<serde_json::Value, ApiError> {
+        let files = changed_paths(&worktree_path)?;
+        Ok(serde_json::json!({
+            "message": commit_message::fill_template(&ws_config, &branch, &files),
+            "files": files,
+            "policy": commit_message::CommitMessagePolicy::of(&ws_config)
+        }))
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(body))
+}
+
+/// POST /api/workspaces/{name}/branches/{branch}/push - Push branch
+/// (background job)
+#[allow(clippy::too_many_arguments)]
+pub async fn push_branch(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
+    path: web::Path<(String, String)>,
+    query: web::Query<SyncQuery>,
+    remote: web::Query<RemoteQuery>,
+    protection: web::Query<ProtectionQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (workspace, branch) = path.into_inner();
+    let ws_config = require_workspace(&config, &workspace)?;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+    require_checked_out(&worktree_path, &branch)?;
+    let overridden = require_unprotected(&req, &ws_config, &branch, &protection)?;
+    let remote = remotes::resolve(&config.repo_path(&workspace), &remote, ws_config.push_remote())?;
+    let lock = lock_worktree(&config, &state, &worktree_path, "push").await?;
+
+    let events = events.get_ref().clone();
+    let audit = audit::Recorder::from_request(&req);
+    let job = Job::new(JobKind::Push, workspace.clone(), Some(branch.clone()), move |job| {
+        let _lock = lock;
+        job.progress(format!("Pushing to {}", remote));
+        git_ops::push_branch(&worktree_path, &remote).map_err(|e| e.context("Failed to push"))?;
+
+        events.publish(Event::Git {
+            workspace: workspace.clone(),
+            branch: Some(branch.clone()),
+            operation: "push",
+            commit_id: None,
+        });
+        audit.record(
+            "git.push",
+            serde_json::json!({ "workspace": workspace, "branch": branch, "remote": remote, "override": overridden }),
+        );
+
+        Ok(serde_json::json!({
+            "message": "Push completed successfully",
+            "remote": remote
+        }))
+    });
+    jobs::submit(&state, &query, job, StatusCode::OK).await
+}
+
 /// POST /api/workspaces/{name}/branches/{branch}/pull - Pull updates
+/// (background job)
 pub async fn pull_branch(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
     path: web::Path<(String, String)>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    query: web::Query<SyncQuery>,
+    remote: web::Query<RemoteQuery>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
-
-    // Check if workspace exists
-    let ws_config = match config.get_workspace(&workspace) {
-        Some(c) => c,
-        None => {
-            return HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Workspace '{}' not found", workspace)
-            }));
-        }
-    };
-
+    let ws_config = require_workspace(&config, &workspace)?;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+    require_checked_out(&worktree_path, &branch)?;
     let repo_path = config.repo_path(&workspace);
-    let worktree_path = config.worktree_path(&workspace, &branch);
-
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found", branch)
-        }));
-    }
-
-    if let Err(e) = git_ops::pull_branch(&repo_path, &worktree_path, &ws_config.base_branch) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to pull: {}", e)
-        }));
-    }
-
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": "Pull completed successfully"
-    }))
+    let remote = remotes::resolve(&repo_path, &remote, ws_config.upstream_remote())?;
+    let lock = lock_worktree(&config, &state, &worktree_path, "pull").await?;
+
+    let events = events.get_ref().clone();
+    let listings = state.listings.clone();
+    let audit = audit::Recorder::from_request(&req);
+    let job = Job::new(JobKind::Pull, workspace.clone(), Some(branch.clone()), move |job| {
+        let _lock = lock;
+        let submodules_before = submodule_pointers(&worktree_path)?;
+        job.progress(format!("Fetching from {} and fast-forwarding", remote));
+        let synced = git_ops::pull_branch(&repo_path, &worktree_path, &remote, &branch)
+            .map_err(|e| ApiError::from(e.context("Failed to pull")))
+            .and_then(|()| update_moved_submodules(job, &worktree_path, &submodules_before));
+        listings.invalidate_worktree(&workspace, &branch);
+        let submodules_updated = synced?;
+
+        events.publish(Event::Git {
+            workspace: workspace.clone(),
+            branch: Some(branch.clone()),
+            operation: "pull",
+            commit_id: None,
+        });
+        audit.record(
+            "git.pull",
+            serde_json::json!({ "workspace": workspace, "branch": branch, "remote": remote }),
+        );
+
+        Ok(serde_json::json!({
+            "message": "Pull completed successfully",
+            "remote": remote,
+            "submodules_updated": submodules_updated
+        }))
+    });
+    jobs::submit(&state, &query, job, StatusCode::OK).await
 }
 
-/// POST /api/workspaces/{name}/branches/{branch}/rebase - Rebase on base branch
+/// POST /api/workspaces/{name}/branches/{branch}/rebase - Rebase on base
+/// branch (background job)
 pub async fn rebase_branch(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
     path: web::Path<(String, String)>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    query: web::Query<SyncQuery>,
+    remote: web::Query<RemoteQuery>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
+    let ws_config = require_workspace(&config, &workspace)?;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+    require_checked_out(&worktree_path, &branch)?;
+    let remote = remotes::resolve(&config.repo_path(&workspace), &remote, ws_config.upstream_remote())?;
+    let lock = lock_worktree(&config, &state, &worktree_path, "rebase").await?;
+
+    let events = events.get_ref().clone();
+    let listings = state.listings.clone();
+    let audit = audit::Recorder::from_request(&req);
+    let job = Job::new(JobKind::Rebase, workspace.clone(), Some(branch.clone()), move |job| {
+        let _lock = lock;
+        let submodules_before = submodule_pointers(&worktree_path)?;
+        job.progress(format!("Rebasing onto {}/{}", remote, ws_config.base_branch));
+        let synced = git_ops::rebase_on_base(&worktree_path, &remote, &ws_config.base_branch)
+            .map_err(|e| rebase_failed(&worktree_path, e))
+            .and_then(|()| update_moved_submodules(job, &worktree_path, &submodules_before));
+        listings.invalidate_worktree(&workspace, &branch);
+        let submodules_updated = synced?;
+
+        events.publish(Event::Git {
+            workspace: workspace.clone(),
+            branch: Some(branch.clone()),
+            operation: "rebase",
+            commit_id: None,
+        });
+        audit.record(
+            "git.rebase",
+            serde_json::json!({
+                "workspace": workspace,
+                "branch": branch,
+                "remote": remote,
+                "onto": ws_config.base_branch
+            }),
+        );
+
+        Ok(serde_json::json!({
+            "message": format!("Rebase on '{}/{}' completed successfully", remote, ws_config.base_branch),
+            "remote": remote,
+            "submodules_updated": submodules_updated
+        }))
+    });
+    jobs::submit(&state, &query, job, StatusCode::OK).await
+}
 
-    // Check if workspace exists
-    let ws_config = match config.get_workspace(&workspace) {
-        Some(c) => c,
-        None => {
-            return HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Workspace '{}' not found", workspace)
-            }));
-        }
-    };
-
-    let worktree_path = config.worktree_path(&workspace, &branch);
-
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found", branch)
-        }));
-    }
-
-    if let Err(e) = git_ops::rebase_on_base(&worktree_path, &ws_config.base_branch) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to rebase: {}", e)
-        }));
+/// 409 `rebase_conflict` listing the conflicted paths if a rebase stopped
+/// on conflicts, which are left for `.../conflicts` to resolve; otherwise
+/// the git failure
+fn rebase_failed(worktree_path: &Path, e: git_ops::GitError) -> ApiError {
+    let conflicts: Vec<String> = git_ops::list_conflicts(worktree_path)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|conflict| conflict.path)
+        .collect();
+    if conflicts.is_empty() {
+        return e.context("Failed to rebase").into();
     }
-
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": format!("Rebase on '{}' completed successfully", ws_config.base_branch)
-    }))
+    ApiError::conflict(
+        "rebase_conflict",
+        format!("Rebase stopped on conflicts in {} file(s)", conflicts.len()),
+    )
+    .with_details(serde_json::json!({ "conflicts": conflicts }))
 }
 
 /// POST /api/workspaces/{name}/branches/{branch}/checkout - Change base branch
 pub async fn change_base_branch(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
     path: web::Path<(String, String)>,
-    body: web::Json<ChangeBaseBranchRequest>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    body: Json<ChangeBaseBranchRequest>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, _branch) = path.into_inner();
-
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
-    }
-
-    if let Err(e) = config.update_workspace_base_branch(&workspace, body.new_base_branch.clone()) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to update base branch: {}", e)
-        }));
-    }
-
-    HttpResponse::Ok().json(serde_json::json!({
+    require_workspace(&config, &workspace)?;
+    validate_branch_name(&body.new_base_branch).map_err(invalid_branch_name)?;
+
+    config
+        .update_workspace_base_branch(&workspace, body.new_base_branch.clone())
+        .map_err(config_save_failed)?;
+    state.listings.invalidate_workspace(&workspace);
+
+    events.publish(Event::WorkspaceUpdated { workspace: workspace.clone() });
+    audit::record(
+        &req,
+        "workspace.base_branch",
+        serde_json::json!({ "workspace": workspace, "base_branch": body.new_base_branch }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": format!("Base branch changed to '{}'", body.new_base_branch)
-    }))
+    })))
 }
 
 /// POST /api/workspaces/{name}/branches/{branch}/rename - Rename branch
 pub async fn rename_branch(
     req: HttpRequest,
     config: web::Data<Arc<ConfigManager>>,
+    state: web::Data<Arc<AppState>>,
+    events: web::Data<EventBus>,
     path: web::Path<(String, String)>,
-    body: web::Json<RenameBranchRequest>,
-) -> HttpResponse {
-    if let Err(resp) = auth::check_auth(&req, &config) {
-        return resp;
-    }
-
+    protection: web::Query<ProtectionQuery>,
+    body: Json<RenameBranchRequest>,
+) -> Result<HttpResponse, ApiError> {
     let (workspace, branch) = path.into_inner();
-
-    // Check if workspace exists
-    if config.get_workspace(&workspace).is_none() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Workspace '{}' not found", workspace)
-        }));
-    }
-
-    let worktree_path = config.worktree_path(&workspace, &branch);
-
-    if !worktree_path.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Worktree '{}' not found", branch)
-        }));
-    }
-
-    if let Err(e) = git_ops::rename_branch(&worktree_path, &body.new_name) {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to rename branch: {}", e)
-        }));
-    }
-
-    HttpResponse::Ok().json(serde_json::json!({
+    let ws_config = require_workspace(&config, &workspace)?;
+    validate_branch_name(&body.new_name).map_err(invalid_branch_name)?;
+    branch_naming::require_allowed(&ws_config, &body.new_name)?;
+    let worktree_path = require_worktree(&config, &workspace, &branch)?;
+    require_branch(&branch)?;
+    let overridden = require_unprotected(&req, &ws_config, &branch, &protection)?;
+
+    let _lock = lock_worktree(&config, &state, &worktree_path, "rename_branch").await?;
+    let renamed = git_ops::rename_branch(&worktree_path, &body.new_name);
+    state.listings.invalidate_workspace(&workspace);
+    renamed.map_err(|e| e.context("Failed to rename branch"))?;
+
+    events.publish(Event::BranchRenamed {
+        workspace: workspace.clone(),
+        branch: branch.clone(),
+        new_name: body.new_name.clone(),
+    });
+    audit::record(
+        &req,
+        "branch.rename",
+        serde_json::json!({
+            "workspace": workspace,
+            "branch": branch,
+            "new_name": body.new_name,
+            "override": overridden
+        }),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": format!("Branch renamed to '{}'", body.new_name)
-    }))
+    })))
 }
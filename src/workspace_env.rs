@@ -0,0 +1,178 @@
+//! Default environment of a workspace: `env` holds plain values and
+//! `secret_env` values sealed with the server's secret key (see `secrets`).
+//! Both are set in terminal sessions opened for the workspace and in blocks
+//! run in its worktrees, so credentials for each environment don't have to be
+//! exported by hand. Secret values are never returned, only their names.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::audit;
+use crate::config::ConfigManager;
+use crate::errors::ApiError;
+use crate::extract::Json;
+use crate::secrets::SecretKey;
+use crate::workspace;
+
+/// Whether `name` can be exported by a shell
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn invalid_env(message: String) -> ApiError {
+    ApiError::validation("invalid_env", message)
+}
+
+pub fn secret_key_error(config: &ConfigManager, e: crate::secrets::SecretError) -> ApiError {
+    ApiError::internal(format!("Secret key file {:?}: {}", config.secret_key_path(), e))
+}
+
+/// Variables to set for a terminal or run in `workspace`, secrets decrypted
+pub fn resolve(config: &ConfigManager, workspace: &str) -> Result<Vec<(String, String)>, ApiError> {
+    let workspace_config = workspace::require_workspace(config, workspace)?;
+    let mut vars: Vec<(String, String)> = workspace_config.env.into_iter().collect();
+    if workspace_config.secret_env.is_empty() {
+        return Ok(vars);
+    }
+    let key = SecretKey::load(config.secret_key_path()).map_err(|e| secret_key_error(config, e))?;
+    for (name, sealed) in workspace_config.secret_env {
+        let value = key.open(&name, &sealed).map_err(|e| {
+            ApiError::internal(format!("Secret '{}' of workspace '{}': {}", name, workspace, e))
+        })?;
+        vars.push((name, value));
+    }
+    Ok(vars)
+}
+
+/// Where a terminal opened for `workspace` starts, and the variables it
+/// gets: the worktree of `branch`, or the workspace directory without one
+pub fn terminal_start(
+    config: &ConfigManager,
+    workspace: &str,
+    branch: Option<&str>,
+) -> Result<(PathBuf, Vec<(String, String)>), ApiError> {
+    let cwd = match branch {
+        Some(branch) => workspace::require_worktree(config, workspace, branch)?,
+        None => {
+            workspace::require_workspace(config, workspace)?;
+            config.workspace_path(workspace)
+        }
+    };
+    Ok((cwd, resolve(config, workspace)?))
+}
+
+/// GET /api/workspaces/{name}/env - Plain variables with their values and
+/// the names of the secret ones
+pub async fn get_env(
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let workspace_config = workspace::require_workspace(&config, &path)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "env": workspace_config.env,
+        "secret_env": workspace_config.secret_env.keys().collect::<Vec<_>>(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateEnvRequest {
+    /// Replaces the plain variables; unchanged when absent
+    pub env: Option<BTreeMap<String, String>>,
+    /// Replaces the secret variables; unchanged when absent. A `null` value
+    /// keeps the one stored under that name.
+    pub secret_env: Option<BTreeMap<String, Option<String>>>,
+}
+
+/// PUT /api/workspaces/{name}/env - Replace a workspace's variables;
+/// answers like the GET
+pub async fn update_env(
+    req: HttpRequest,
+    config: web::Data<Arc<ConfigManager>>,
+    path: web::Path<String>,
+    body: Json<UpdateEnvRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let body = body.into_inner();
+    let current = workspace::require_workspace(&config, &name)?;
+
+    let env = body.env.unwrap_or(current.env);
+    let secret_names: Vec<&String> = match &body.secret_env {
+        Some(secret_env) => secret_env.keys().collect(),
+        None => current.secret_env.keys().collect(),
+    };
+    for (var, value) in &env {
+        if value.contains('\0') {
+            return Err(invalid_env(format!("Value of '{}' contains a NUL character", var)));
+        }
+    }
+    for var in env.keys().chain(secret_names.iter().copied()) {
+        if !valid_name(var) {
+            return Err(invalid_env(format!(
+                "'{}' is not a variable name: use letters, digits and '_', not starting with a digit",
+                var
+            )));
+        }
+    }
+    if let Some(var) = secret_names.iter().find(|var| env.contains_key(var.as_str())) {
+        return Err(invalid_env(format!("'{}' is both a plain and a secret variable", var)));
+    }
+
+    let secret_env = match body.secret_env {
+        None => current.secret_env,
+        Some(secret_env) => {
+            let mut key = None;
+            let mut sealed = BTreeMap::new();
+            for (var, value) in secret_env {
+                let value = match value {
+                    Some(value) if value.contains('\0') => {
+                        return Err(invalid_env(format!("Value of '{}' contains a NUL character", var)));
+                    }
+                    Some(value) => value,
+                    None => match current.secret_env.get(&var) {
+                        Some(stored) => {
+                            sealed.insert(var, stored.clone());
+                            continue;
+                        }
+                        None => return Err(invalid_env(format!("Secret '{}' has no stored value to keep", var))),
+                    },
+                };
+                if key.is_none() {
+                    key = Some(
+                        SecretKey::load_or_create(config.secret_key_path())
+                            .map_err(|e| secret_key_error(&config, e))?,
+                    );
+                }
+                let key = key.as_ref().expect("loaded above");
+                sealed.insert(var.clone(), key.seal(&var, &value));
+            }
+            sealed
+        }
+    };
+
+    let env_names: Vec<String> = env.keys().cloned().collect();
+    let secret_names: Vec<String> = secret_env.keys().cloned().collect();
+    let response = serde_json::json!({ "env": env, "secret_env": secret_names });
+    match config.set_workspace_env(&name, env, secret_env) {
+        Ok(true) => {}
+        Ok(false) => return Err(ApiError::workspace_not_found(&name)),
+        Err(e) => return Err(ApiError::internal(format!("Failed to save config: {}", e))),
+    }
+    log::info!(
+        "Updated environment of workspace {}: {} variables, {} secrets",
+        name,
+        env_names.len(),
+        secret_names.len()
+    );
+    audit::record(
+        &req,
+        "workspace.env.update",
+        // Names only: values may be sensitive
+        serde_json::json!({ "workspace": name, "env": env_names, "secret_env": secret_names }),
+    );
+    Ok(HttpResponse::Ok().json(response))
+}
@@ -0,0 +1,115 @@
+//! Locks serializing the git operations that change a worktree. Nothing
+//! else stops a pull and a commit (or two rebases) from running in the same
+//! worktree at once, and git's own `index.lock` turns that into a failure
+//! of one of them. Every endpoint that changes a worktree with git takes its
+//! lock first, waiting up to `worktree_lock_timeout_secs` for the operation
+//! holding it before answering 423; a job holds the lock from when it is
+//! queued until it has run. Reads (status, diffs, logs) don't take it.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::errors::ApiError;
+
+/// The operation holding a worktree's lock
+#[derive(Debug, Clone, Serialize)]
+pub struct Holder {
+    pub operation: &'static str,
+    pub since: DateTime<Utc>,
+}
+
+struct Slot {
+    lock: Arc<tokio::sync::Mutex<()>>,
+    holder: Mutex<Option<Holder>>,
+}
+
+/// A worktree's lock, held until dropped
+pub struct WorktreeGuard {
+    slot: Arc<Slot>,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl Drop for WorktreeGuard {
+    fn drop(&mut self) {
+        *self.slot.holder.lock().unwrap() = None;
+    }
+}
+
+/// The lock of every worktree, keyed by its canonical path
+#[derive(Default)]
+pub struct WorktreeLocks {
+    slots: Mutex<HashMap<PathBuf, Arc<Slot>>>,
+}
+
+impl WorktreeLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn slot(&self, worktree_path: &Path) -> Arc<Slot> {
+        let key = worktree_path.canonicalize().unwrap_or_else(|_| worktree_path.to_path_buf());
+        let mut slots = self.slots.lock().unwrap();
+        // Forget the locks nobody holds or waits for
+        slots.retain(|_, slot| Arc::strong_count(slot) > 1);
+        slots
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(Slot {
+                    lock: Arc::new(tokio::sync::Mutex::new(())),
+                    holder: Mutex::new(None),
+                })
+            })
+            .clone()
+    }
+
+    /// Take the lock of the worktree at `worktree_path` for `operation`,
+    /// waiting up to `wait` for it; 423 `worktree_locked` naming the
+    /// operation holding it if it isn't free by then
+    pub async fn acquire(
+        &self,
+        worktree_path: &Path,
+        operation: &'static str,
+        wait: Duration,
+    ) -> Result<WorktreeGuard, ApiError> {
+        let slot = self.slot(worktree_path);
+        let guard = match tokio::time::timeout(wait, slot.lock.clone().lock_owned()).await {
+            Ok(guard) => guard,
+            Err(_) => {
+                let holder = slot.holder.lock().unwrap().clone();
+                let busy_with = holder.as_ref().map_or("another operation", |holder| holder.operation);
+                log::warn!("Gave up waiting {:?} for {:?}, busy with {}", wait, worktree_path, busy_with);
+                return Err(ApiError::locked(
+                    "worktree_locked",
+                    format!("Worktree is busy with {}; try again once it has finished", busy_with),
+                )
+                .with_details(serde_json::json!({
+                    "operation": holder.as_ref().map(|holder| holder.operation),
+                    "since": holder.as_ref().map(|holder| holder.since),
+                    "waited_secs": wait.as_secs()
+                })));
+            }
+        };
+        *slot.holder.lock().unwrap() = Some(Holder {
+            operation,
+            since: Utc::now(),
+        });
+        Ok(WorktreeGuard { slot, _guard: guard })
+    }
+
+    /// Take the lock of the worktree at `worktree_path` for `operation` if
+    /// it is free, for work that would rather skip a busy worktree
+    pub fn try_acquire(&self, worktree_path: &Path, operation: &'static str) -> Option<WorktreeGuard> {
+        let slot = self.slot(worktree_path);
+        let guard = slot.lock.clone().try_lock_owned().ok()?;
+        *slot.holder.lock().unwrap() = Some(Holder {
+            operation,
+            since: Utc::now(),
+        });
+        Some(WorktreeGuard { slot, _guard: guard })
+    }
+}
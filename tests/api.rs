@@ -0,0 +1,4271 @@
+//! HTTP routes exercised through `app_factory` against a local fixture
+//! repository.
+
+mod common;
+
+use actix_http::Request;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::body::MessageBody;
+use actix_web::test;
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::time::Duration;
+
+use common::{git, read_events, request, send, wait_for_job, TestEnv};
+
+/// Create workspace `name` from the fixture repository with a `main` worktree
+async fn create_workspace<S, B>(env: &TestEnv, app: &S, name: &str)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let repo = env.fixture_repo();
+    let (status, body) = send(
+        app,
+        request(
+            "POST",
+            "/api/workspaces?sync=true",
+            Some(json!({ "name": name, "repo_url": repo, "base_branch": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 201, "{}", body);
+
+    let (status, body) = send(
+        app,
+        request(
+            "POST",
+            &format!("/api/workspaces/{}/branches", name),
+            Some(json!({ "branch_name": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 201, "{}", body);
+}
+
+/// Start cloning `name` from an HTTP "server" that accepts connections but
+/// never answers, and return the job. The listener must outlive the job.
+async fn start_stalled_clone<S, B>(app: &S, listener: &TcpListener, name: &str) -> Value
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+    let (status, job) = send(
+        app,
+        request(
+            "POST",
+            "/api/workspaces",
+            Some(json!({ "name": name, "repo_url": url, "base_branch": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 202, "{}", job);
+    job
+}
+
+#[actix_web::test]
+async fn requests_without_a_valid_token_are_rejected() {
+    let env = TestEnv::new("auth");
+    let app = env.app().await;
+
+    let req = test::TestRequest::get().uri("/api/workspaces");
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["error"]["code"], "auth_required");
+
+    let req = test::TestRequest::get()
+        .uri("/api/workspaces")
+        .insert_header(("Authorization", "Bearer wrong"));
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["error"]["code"], "invalid_token");
+
+    let (status, _) = send(&app, request("GET", "/api/workspaces", None)).await;
+    assert_eq!(status, 200);
+}
+
+#[actix_web::test]
+async fn responses_carry_a_request_id() {
+    let env = TestEnv::new("request-id");
+    let app = env.app().await;
+
+    let req = request("GET", "/api/workspaces", None).insert_header(("X-Request-Id", "test-42"));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.headers().get("x-request-id").unwrap(), "test-42");
+
+    let req = test::TestRequest::get().uri("/api/workspaces");
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 401);
+    assert!(resp.headers().contains_key("x-request-id"));
+}
+
+#[actix_web::test]
+async fn workspace_create_list_delete() {
+    let env = TestEnv::new("workspaces");
+    let app = env.app().await;
+
+    create_workspace(&env, &app, "docs").await;
+    assert!(env.worktree("docs", "main").join("README.md").exists());
+
+    let (status, body) = send(&app, request("GET", "/api/workspaces", None)).await;
+    assert_eq!(status, 200);
+    let names: Vec<_> = body.as_array().unwrap().iter().map(|w| w["name"].clone()).collect();
+    assert_eq!(names, vec![json!("docs")]);
+
+    let (status, body) = send(&app, request("DELETE", "/api/workspaces/docs", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["removed_worktrees"], json!(["main"]));
+    assert!(!env.config.get_workspace_dir().join("docs").exists());
+
+    let (_, body) = send(&app, request("GET", "/api/workspaces", None)).await;
+    assert_eq!(body, json!([]));
+
+    let (status, body) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "workspace_not_found");
+}
+
+#[actix_web::test]
+async fn saved_files_read_back() {
+    let env = TestEnv::new("files");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let uri = "/api/workspaces/docs/branches/main/file?path=docs/new.md";
+    let content = "# New runbook\n\n```bash\necho hi\n```\n";
+    let (status, body) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200, "{}", body);
+
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["content"], content);
+
+    let (status, body) = send(
+        &app,
+        request("GET", "/api/workspaces/docs/branches/main/file?path=docs/missing.md", None),
+    )
+    .await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "path_not_found");
+}
+
+#[actix_web::test]
+async fn files_are_created_only_where_missing() {
+    let env = TestEnv::new("create-file");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let uri = "/api/workspaces/docs/branches/main/file?path=docs/restart.md";
+    let (status, body) = send(&app, request("POST", uri, Some(json!({})))).await;
+    assert_eq!(status, 201, "{}", body);
+    let (_, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(body["content"], "# restart\n\nNew runbook created.\n");
+
+    let (status, body) = send(&app, request("POST", uri, Some(json!({ "content": "again" })))).await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["code"], "path_exists");
+
+    let outside = env.worktree("docs", "main").join("../outside.md");
+    let (status, _) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/main/file?path=docs/../../outside.md",
+            Some(json!({ "content": "x" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert!(!outside.exists());
+}
+
+#[actix_web::test]
+async fn commit_records_saved_changes() {
+    let env = TestEnv::new("commit");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let (status, _) = send(
+        &app,
+        request(
+            "PUT",
+            "/api/workspaces/docs/branches/main/file?path=docs/alerts.md",
+            Some(json!({ "content": "# Alerts\n\nHighCPU fires above 95%\n" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let (status, body) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/main/commit?override=true",
+            Some(json!({ "message": "Raise the CPU threshold", "files": ["docs/alerts.md"] })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+
+    let worktree = env.worktree("docs", "main");
+    assert_eq!(body["commit_id"], git(&["rev-parse", "HEAD"], &worktree));
+    assert_eq!(git(&["log", "-1", "--format=%s"], &worktree), "Raise the CPU threshold");
+    assert_eq!(git(&["status", "--porcelain"], &worktree), "");
+    assert_eq!(body["files"], json!([{ "status": "M", "path": "docs/alerts.md" }]));
+
+    let commit = |body: Value| request("POST", "/api/workspaces/docs/branches/main/commit?override=true", Some(body));
+    let (status, body) = send(&app, commit(json!({ "message": "Again", "all": true }))).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "nothing_to_commit");
+
+    let (status, body) = send(&app, commit(json!({ "message": "Escape", "files": ["../config.json"] }))).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_path");
+
+    // Everything, including new and deleted files
+    std::fs::write(worktree.join("new.md"), "# New\n").unwrap();
+    std::fs::remove_file(worktree.join("README.md")).unwrap();
+    let (status, body) = send(&app, commit(json!({ "message": "Tidy up", "all": true }))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        body["files"],
+        json!([{ "status": "D", "path": "README.md" }, { "status": "A", "path": "new.md" }])
+    );
+    assert_eq!(git(&["status", "--porcelain"], &worktree), "");
+}
+
+#[actix_web::test]
+async fn clone_runs_as_a_background_job() {
+    let env = TestEnv::new("jobs");
+    let app = env.app().await;
+    let repo = env.fixture_repo();
+
+    let (status, job) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces",
+            Some(json!({ "name": "docs", "repo_url": repo, "base_branch": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 202, "{}", job);
+    assert_eq!(job["kind"], "clone_workspace");
+    assert_eq!(job["workspace"], "docs");
+
+    let id = job["id"].as_str().unwrap();
+    let job = wait_for_job(&app, id).await;
+    assert_eq!(job["state"], "succeeded", "{}", job);
+    assert_eq!(job["result"]["name"], "docs");
+    assert!(env.config.repo_path("docs").join("HEAD").exists());
+
+    let (_, jobs) = send(&app, request("GET", "/api/jobs?workspace=docs", None)).await;
+    assert_eq!(jobs[0]["id"], id);
+    let (_, jobs) = send(&app, request("GET", "/api/jobs?workspace=other", None)).await;
+    assert_eq!(jobs, json!([]));
+
+    let (status, body) = send(&app, request("POST", &format!("/api/jobs/{}/cancel", id), None)).await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["code"], "job_finished");
+
+    let (status, body) = send(&app, request("GET", "/api/jobs/no-such-job", None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "job_not_found");
+}
+
+#[actix_web::test]
+async fn cancelled_clone_is_cleaned_up() {
+    let env = TestEnv::new("cancel");
+    let app = env.app().await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+    let job = start_stalled_clone(&app, &listener, "stalled").await;
+    let id = job["id"].as_str().unwrap();
+    let (status, body) = send(&app, request("POST", &format!("/api/jobs/{}/cancel", id), None)).await;
+    assert_eq!(status, 200, "{}", body);
+
+    let job = wait_for_job(&app, id).await;
+    assert_eq!(job["state"], "cancelled", "{}", job);
+    assert_eq!(job["error"]["code"], "job_cancelled");
+
+    let (status, _) = send(&app, request("GET", "/api/workspaces/stalled", None)).await;
+    assert_eq!(status, 404);
+    assert!(!env.config.get_workspace_dir().join("stalled").exists());
+}
+
+#[actix_web::test]
+async fn concurrent_creates_of_one_workspace_conflict() {
+    let env = TestEnv::new("create-race");
+    let app = env.app().await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let repo = env.fixture_repo();
+    let create = |name: &str| {
+        let body = json!({ "name": name, "repo_url": repo, "base_branch": "main" });
+        send(&app, request("POST", "/api/workspaces?sync=true", Some(body)))
+    };
+
+    // A clone still running holds the name
+    let job = start_stalled_clone(&app, &listener, "stalled").await;
+    let (status, body) = create("stalled").await;
+    assert_eq!(status, 409, "{}", body);
+    assert_eq!(body["error"]["code"], "operation_in_progress");
+    let id = job["id"].as_str().unwrap();
+    send(&app, request("POST", &format!("/api/jobs/{}/cancel", id), None)).await;
+    wait_for_job(&app, id).await;
+
+    // Of two creates sent together, exactly one wins
+    let ((first, first_body), (second, second_body)) = tokio::join!(create("docs"), create("docs"));
+    let mut statuses = [first, second];
+    statuses.sort();
+    assert_eq!(statuses, [201, 409], "{} {}", first_body, second_body);
+    let loser = if first == 409 { first_body } else { second_body };
+    assert_eq!(loser["error"]["code"], "operation_in_progress");
+    assert_eq!(env.config.get_workspaces().len(), 1);
+    let (status, body) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(status, 200, "{}", body);
+}
+
+#[actix_web::test]
+async fn jobs_past_the_timeout_are_stopped() {
+    let env = TestEnv::with_config("timeout", json!({ "job_timeout_secs": 1 }));
+    let app = env.app().await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+    let job = start_stalled_clone(&app, &listener, "stalled").await;
+    let job = wait_for_job(&app, job["id"].as_str().unwrap()).await;
+    assert_eq!(job["state"], "failed", "{}", job);
+    assert_eq!(job["error"]["code"], "job_timed_out");
+    let (status, _) = send(&app, request("GET", "/api/workspaces/stalled", None)).await;
+    assert_eq!(status, 404);
+
+    let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+    let (status, body) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces?sync=true",
+            Some(json!({ "name": "stalled", "repo_url": url, "base_branch": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 504);
+    assert_eq!(body["error"]["code"], "job_timed_out");
+}
+
+#[actix_web::test]
+async fn sync_operations_time_out_per_operation_and_stop_when_the_client_leaves() {
+    let env = TestEnv::with_config("sync-timeout", json!({ "job_timeouts": { "clone_workspace": 1 } }));
+    let app = env.app().await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+
+    let create = json!({ "name": "stalled", "repo_url": url, "base_branch": "main" });
+    let (status, body) = send(&app, request("POST", "/api/v1/workspaces?sync=true", Some(create))).await;
+    assert_eq!(status, 504, "{}", body);
+    assert_eq!(body["error"]["code"], "job_timed_out");
+    assert_eq!(body["error"]["message"], "Operation clone_workspace did not finish within 1 seconds");
+    assert_eq!(body["error"]["details"], json!({ "operation": "clone_workspace", "timeout_secs": 1 }));
+    assert!(!env.config.get_workspace_dir().join("stalled").exists());
+
+    // Fetches keep their far longer default, but stop along with the
+    // request: git's HTTP helper, in git's process group, hangs up
+    create_workspace(&env, &app, "docs").await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+    let remote = json!({ "name": "stalled", "url": url, "verify": false });
+    let (status, body) = send(&app, request("POST", "/api/v1/workspaces/docs/remotes", Some(remote))).await;
+    assert_eq!(status, 201, "{}", body);
+    let (hung_up, received) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut buf = [0u8; 4096];
+        loop {
+            match std::io::Read::read(&mut stream, &mut buf) {
+                Ok(0) => break hung_up.send(true),
+                Ok(_) => continue,
+                Err(_) => break hung_up.send(false),
+            }
+        }
+    });
+    let fetch = request("POST", "/api/v1/workspaces/docs/fetch?sync=true&remote=stalled", None).to_request();
+    assert!(tokio::time::timeout(Duration::from_secs(2), app.call(fetch)).await.is_err());
+    assert_eq!(received.recv_timeout(Duration::from_secs(20)), Ok(true));
+}
+
+#[actix_web::test]
+async fn events_stream_job_and_workspace_changes() {
+    let env = TestEnv::new("events");
+    let app = env.app().await;
+    let repo = env.fixture_repo();
+
+    let resp = test::call_service(&app, request("GET", "/api/events?workspace=docs", None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/event-stream");
+
+    // Filtered out: another workspace
+    create_workspace(&env, &app, "other").await;
+
+    let (status, job) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces",
+            Some(json!({ "name": "docs", "repo_url": repo, "base_branch": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 202, "{}", job);
+
+    let events = read_events(resp.into_body(), |event| {
+        event["type"] == "job" && event["job"]["state"] == "succeeded"
+    })
+    .await;
+    assert_eq!(events[0]["type"], "job", "{:?}", events);
+    assert_eq!(events[0]["job"]["id"], job["id"]);
+    assert_eq!(events[0]["job"]["state"], "queued");
+    assert!(events.iter().all(|e| e["type"] == "job" || e["workspace"] == "docs"), "{:?}", events);
+    assert!(events.iter().any(|e| e["type"] == "workspace_created"), "{:?}", events);
+    assert!(events.iter().any(|e| e["job"]["state"] == "running"), "{:?}", events);
+}
+
+#[actix_web::test]
+async fn ui_is_served_from_the_configured_static_dir() {
+    // Inside the environment's directory, which is only created by `with_config`
+    let ui = std::env::temp_dir()
+        .join(format!("runotepad-api-test-{}-static-dir", std::process::id()))
+        .join("ui");
+    let env = TestEnv::with_config("static-dir", json!({ "static_dir": ui }));
+    std::fs::create_dir_all(&ui).unwrap();
+    std::fs::write(ui.join("index.html"), "<div id=\"app\"></div>").unwrap();
+    let app = env.app().await;
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::read_body(resp).await, "<div id=\"app\"></div>");
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/missing.js").to_request()).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[actix_web::test]
+async fn versioned_and_legacy_paths_share_handlers() {
+    let env = TestEnv::new("versions");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let resp = test::call_service(&app, request("GET", "/api/v1/workspaces/docs/branches", None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert!(!resp.headers().contains_key("deprecation"));
+    let current: Value = test::read_body_json(resp).await;
+
+    let resp = test::call_service(&app, request("GET", "/api/workspaces/docs/branches", None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("deprecation").unwrap(), "true");
+    assert_eq!(
+        resp.headers().get("link").unwrap(),
+        "</api/v1/workspaces/docs/branches>; rel=\"successor-version\""
+    );
+    let legacy: Value = test::read_body_json(resp).await;
+    assert_eq!(current, legacy);
+
+    // Errors and auth behave the same under both prefixes
+    for prefix in ["/api", "/api/v1"] {
+        let (status, body) = send(&app, request("GET", &format!("{}/workspaces/missing", prefix), None)).await;
+        assert_eq!(status, 404);
+        assert_eq!(body["error"]["code"], "workspace_not_found");
+        let req = test::TestRequest::get().uri(&format!("{}/workspaces", prefix));
+        let (status, _) = send(&app, req).await;
+        assert_eq!(status, 401);
+    }
+
+    let req = test::TestRequest::get().uri("/api/version");
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["api_versions"], json!(["v1"]));
+}
+
+#[actix_web::test]
+async fn markdown_code_blocks_are_listed_as_steps() {
+    let env = TestEnv::new("blocks");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let content = "# Restart\n\nIntro\n\n## API\n\n\
+        ```bash {name=restart-api timeout=60 confirm}\nsystemctl restart api\n```\n\n    \
+        indented, not a step\n\n\
+        ```sql {name=\"check rows\" =oops}\nselect 1;\n```\n\n\
+        ~~~~\nunclosed\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let uri = "/api/v1/workspaces/docs/branches/main/blocks?path=runbook.md";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        body["headings"],
+        json!([
+            { "level": 1, "title": "Restart", "line": 1 },
+            { "level": 2, "title": "API", "line": 5 }
+        ])
+    );
+
+    let blocks = body["blocks"].as_array().unwrap();
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[0]["index"], 0);
+    assert_eq!(blocks[0]["language"], "bash");
+    assert_eq!(
+        blocks[0]["attributes"],
+        json!({ "name": "restart-api", "timeout": "60", "confirm": "true" })
+    );
+    assert_eq!(blocks[0]["code"], "systemctl restart api\n");
+    assert_eq!((blocks[0]["start_line"].as_u64(), blocks[0]["end_line"].as_u64()), (Some(7), Some(9)));
+    assert_eq!(blocks[0]["section"], 1);
+    assert!(blocks[0].get("warnings").is_none());
+
+    assert_eq!(blocks[1]["attributes"], json!({ "name": "check rows" }));
+    assert_eq!(blocks[1]["warnings"].as_array().unwrap().len(), 1);
+
+    assert_eq!(blocks[2]["language"], Value::Null);
+    assert_eq!(blocks[2]["code"], "unclosed\n");
+    assert_eq!(blocks[2]["warnings"].as_array().unwrap().len(), 1);
+
+    let uri = "/api/v1/workspaces/docs/branches/main/blocks?path=missing.md";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "path_not_found");
+}
+
+#[actix_web::test]
+async fn shell_blocks_run_in_the_worktree() {
+    let env = TestEnv::new("run");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let content = "# Checks\n\n```bash\npwd\necho oops >&2\nexit 3\n```\n\n```python\nprint(1)\n```\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let run = |block_index: usize, session_id: Option<&str>| {
+        request(
+            "POST",
+            "/api/v1/workspaces/docs/branches/main/blocks/run",
+            Some(json!({ "path": "runbook.md", "block_index": block_index, "session_id": session_id })),
+        )
+    };
+
+    let (status, body) = send(&app, run(0, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["block_index"], 0);
+    assert_eq!(body["exit_code"], 3);
+    assert_eq!(body["stderr"], "oops\n");
+    let worktree = env.worktree("docs", "main").canonicalize().unwrap();
+    assert_eq!(body["stdout"].as_str().unwrap().trim_end(), worktree.to_str().unwrap());
+    assert_eq!(body["timed_out"], false);
+    assert_eq!(body["file_hash"].as_str().unwrap().len(), 64);
+    assert_ne!(body["file_hash"], body["block_hash"]);
+
+    let (status, body) = send(&app, run(1, None)).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "block_not_runnable");
+    assert_eq!(body["error"]["details"]["language"], "python");
+
+    let (status, body) = send(&app, run(2, None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "block_not_found");
+
+    let (status, body) = send(&app, run(0, Some("no-such-session"))).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "session_not_found");
+}
+
+#[actix_web::test]
+async fn recorded_output_replaces_the_previous_run() {
+    let env = TestEnv::new("record");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let content = "# Count\n\n```bash\necho run >> runs.log\nwc -l < runs.log\n```\nAfter\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let run = |session_id: Option<&str>| {
+        request(
+            "POST",
+            "/api/v1/workspaces/docs/branches/main/blocks/run",
+            Some(json!({
+                "path": "runbook.md",
+                "block_index": 0,
+                "session_id": session_id,
+                "record_output": true
+            })),
+        )
+    };
+    let runbook = env.worktree("docs", "main").join("runbook.md");
+    for count in ["1", "2"] {
+        let (status, body) = send(&app, run(None)).await;
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body["recorded_output"], true);
+
+        let saved = std::fs::read_to_string(&runbook).unwrap();
+        let (before, rest) = saved.split_once("```output\n# ").unwrap();
+        assert_eq!(before, "# Count\n\n```bash\necho run >> runs.log\nwc -l < runs.log\n```\n\n");
+        let (comment, rest) = rest.split_once('\n').unwrap();
+        assert!(comment.ends_with("exit code 0"), "{}", comment);
+        assert_eq!(rest, format!("{}\n```\nAfter\n", count));
+    }
+
+    let (status, body) = send(&app, run(Some("some-session"))).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "output_not_captured");
+}
+
+#[actix_web::test]
+async fn block_runs_are_kept_in_the_execution_history() {
+    let env = TestEnv::with_config("history", json!({ "execution_history_max_entries": 2 }));
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let content = "```bash\necho run >> runs.log\nwc -l < runs.log\n```\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let (status, body) = send(
+            &app,
+            request(
+                "POST",
+                "/api/v1/workspaces/docs/branches/main/blocks/run",
+                Some(json!({ "path": "runbook.md", "block_index": 0 })),
+            ),
+        )
+        .await;
+        assert_eq!(status, 200, "{}", body);
+        ids.push(body["execution_id"].as_str().unwrap().to_string());
+    }
+
+    // Only the latest two are kept
+    let (status, body) = send(&app, request("GET", "/api/v1/workspaces/docs/executions", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    let listed: Vec<&str> = body.as_array().unwrap().iter().map(|e| e["id"].as_str().unwrap()).collect();
+    assert_eq!(listed, [ids[1].as_str(), ids[2].as_str()]);
+    assert_eq!(body[0]["branch"], "main");
+    assert_eq!(body[0]["path"], "runbook.md");
+    assert_eq!(body[0]["token"], "default");
+    assert_eq!(body[0]["exit_code"], 0);
+    assert_eq!(body[0]["block_hash"].as_str().unwrap().len(), 64);
+
+    let uri = "/api/v1/workspaces/docs/executions?path=other.md";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!([]));
+
+    let (status, body) = send(&app, request("GET", &format!("/api/v1/executions/{}", ids[2]), None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["workspace"], "docs");
+    assert_eq!(body["output"]["stdout"], "3\n");
+
+    let (status, body) = send(&app, request("GET", &format!("/api/v1/executions/{}", ids[0]), None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "execution_not_found");
+}
+
+#[actix_web::test]
+async fn block_variables_are_substituted_shell_quoted() {
+    let env = TestEnv::new("variables");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let runbook = "---\ntitle: Scale\nvariables:\n  region: us-east-1\n  replicas: { type: number, default: 3 }\n---\n\
+        # Scale\n\n```bash\necho {{region}} {{ cluster }} {{replicas}}\necho \"{{cluster}}\" '{{region}}' # it's\n```\n";
+    let vars = "cluster:\n  description: Target cluster\n";
+    for (path, content) in [("ops/scale.md", runbook), ("ops/vars.yaml", vars)] {
+        let uri = format!("/api/v1/workspaces/docs/branches/main/file?path={}", path);
+        let (status, _) = send(&app, request("PUT", &uri, Some(json!({ "content": content })))).await;
+        assert_eq!(status, 200);
+    }
+
+    let uri = "/api/v1/workspaces/docs/branches/main/blocks?path=ops/scale.md";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["headings"], json!([{ "level": 1, "title": "Scale", "line": 7 }]));
+    assert_eq!(body["blocks"][0]["variables"], json!(["region", "cluster", "replicas"]));
+    let declared: Vec<(&str, &str)> = body["variables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["name"].as_str().unwrap(), v["type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(declared, [("cluster", "string"), ("region", "string"), ("replicas", "number")]);
+
+    let run = |variables: Value| {
+        request(
+            "POST",
+            "/api/v1/workspaces/docs/branches/main/blocks/run",
+            Some(json!({ "path": "ops/scale.md", "block_index": 0, "variables": variables })),
+        )
+    };
+
+    let (status, body) = send(&app, run(json!({}))).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "unresolved_variables");
+    assert_eq!(body["error"]["details"]["variables"], json!(["cluster"]));
+
+    let (status, body) = send(&app, run(json!({ "cluster": "prod; touch injected", "replicas": "many" }))).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_variable");
+    assert_eq!(body["error"]["details"]["name"], "replicas");
+
+    let (status, body) = send(&app, run(json!({ "cluster": "prod; touch injected", "replicas": 5 }))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["stdout"], "us-east-1 prod; touch injected 5\nprod; touch injected us-east-1\n");
+
+    // Placeholders inside quotes are no way in either
+    let cluster = "$(touch injected) `touch injected` \"; touch injected; \" '; touch injected; '";
+    let (status, body) = send(&app, run(json!({ "cluster": cluster }))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["stdout"], format!("us-east-1 {} 3\n{} us-east-1\n", cluster, cluster));
+    assert!(!env.worktree("docs", "main").join("injected").exists());
+}
+
+#[actix_web::test]
+async fn checklist_steps_are_ticked_in_the_file() {
+    let env = TestEnv::new("checklist");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let content = "# Failover\n\n- [ ] Page the on-call DBA\n- [x] Freeze deploys\n\n## Verify\n\n- [ ] Check replication lag\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let uri = "/api/v1/workspaces/docs/branches/main/checklist?path=runbook.md";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    let steps = body["steps"].as_array().unwrap();
+    assert_eq!(steps.len(), 3);
+    assert_eq!(steps[0]["text"], "Page the on-call DBA");
+    assert_eq!(steps[1]["checked"], true);
+    assert_eq!(body["headings"][steps[2]["section"].as_u64().unwrap() as usize]["title"], "Verify");
+    let stale_hash = body["file_hash"].as_str().unwrap().to_string();
+
+    let toggle = json!({ "step": 2, "checked": true, "file_hash": stale_hash });
+    let (status, body) = send(&app, request("PUT", uri, Some(toggle.clone()))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["steps"][2]["checked"], true);
+    assert_ne!(body["file_hash"], stale_hash);
+
+    let file = std::fs::read_to_string(env.worktree("docs", "main").join("runbook.md")).unwrap();
+    assert!(file.contains("- [x] Check replication lag\n"), "{}", file);
+
+    // A toggle against the revision before the last one is refused
+    let (status, body) = send(&app, request("PUT", uri, Some(toggle))).await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["code"], "file_changed");
+
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200);
+    let toggle = json!({ "step": 7, "checked": true, "file_hash": body["file_hash"] });
+    let (status, body) = send(&app, request("PUT", uri, Some(toggle))).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "step_not_found");
+
+    let (status, body) = send(&app, request("GET", "/api/v1/workspaces/docs/executions", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["kind"], "checklist_toggle");
+    assert_eq!(body[0]["step_index"], 2);
+    assert_eq!(body[0]["checked"], true);
+    assert_eq!(body[0]["token"], "default");
+    assert_eq!(body[0]["file_hash"], stale_hash);
+}
+
+#[actix_web::test]
+async fn blocks_requiring_approval_run_once_approved_by_another_token() {
+    let env = TestEnv::new("approvals");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let content = "```bash {requires_approval}\necho failed over\n```\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let run_uri = "/api/v1/workspaces/docs/branches/main/blocks/run";
+    let run = json!({ "path": "runbook.md", "block_index": 0 });
+    let (status, body) = send(&app, request("POST", run_uri, Some(run))).await;
+    assert_eq!(status, 403, "{}", body);
+    assert_eq!(body["error"]["code"], "approval_required");
+    assert_eq!(body["error"]["details"]["reason"], "missing");
+    let block_hash = body["error"]["details"]["block_hash"].clone();
+
+    let approval = json!({
+        "workspace": "docs",
+        "branch": "main",
+        "path": "runbook.md",
+        "block_index": 0,
+        "block_hash": block_hash
+    });
+    let (status, body) = send(&app, request("POST", "/api/v1/approvals", Some(approval.clone()))).await;
+    assert_eq!(status, 201, "{}", body);
+    let run = json!({ "path": "runbook.md", "block_index": 0, "approval_token": body["approval_token"] });
+    let (status, body) = send(&app, request("POST", run_uri, Some(run))).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["details"]["reason"], "self_approved");
+
+    let (status, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "reviewer" })))).await;
+    assert_eq!(status, 201, "{}", body);
+    let req = request("POST", "/api/v1/approvals", Some(approval))
+        .insert_header(("Authorization", format!("Bearer {}", body["token"].as_str().unwrap())));
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["approver"], "reviewer");
+
+    let run = json!({ "path": "runbook.md", "block_index": 0, "approval_token": body["approval_token"] });
+    let mut dry_run = run.clone();
+    dry_run["dry_run"] = json!(true);
+    let (status, body) = send(&app, request("POST", run_uri, Some(dry_run))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["approved_by"], "reviewer");
+
+    // The dry run didn't use the approval up
+    let (status, body) = send(&app, request("POST", run_uri, Some(run.clone()))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["stdout"], "failed over\n");
+    assert_eq!(body["approved_by"], "reviewer");
+
+    // An approval allows one run
+    let (status, body) = send(&app, request("POST", run_uri, Some(run))).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["details"]["reason"], "unknown");
+
+    let (status, body) = send(&app, request("GET", "/api/v1/workspaces/docs/executions", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body.as_array().unwrap().len(), 2);
+    assert_eq!(body[1]["kind"], "block_run");
+    assert_eq!(body[1]["approved_by"], "reviewer");
+}
+
+#[actix_web::test]
+async fn dry_runs_resolve_blocks_without_running_them() {
+    let env = TestEnv::new("dry-run");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let content = "---\nvariables:\n  target: { default: it }\n---\n```bash {timeout=30}\ntouch {{target}}\n```\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let run_uri = "/api/v1/workspaces/docs/branches/main/blocks/run";
+    let run = json!({
+        "path": "runbook.md",
+        "block_index": 0,
+        "dry_run": true,
+        "record_output": true,
+        "variables": { "target": "a file" }
+    });
+    let (status, body) = send(&app, request("POST", run_uri, Some(run))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["dry_run"], true);
+    assert_eq!(body["command"], "RUNOTEPAD_VAR_target='a file'\ntouch \"${RUNOTEPAD_VAR_target}\"\n");
+    assert_eq!(body["shell"], "bash");
+    assert_eq!(body["timeout_secs"], 30);
+    assert!(body["worktree_path"].as_str().unwrap().ends_with("main"), "{}", body);
+    assert_eq!(body["recorded_output"], false);
+    assert!(body.get("exit_code").is_none());
+    assert!(!env.worktree("docs", "main").join("a file").exists());
+
+    let run = json!({ "path": "runbook.md", "block_index": 0, "dry_run": true, "session_id": "nope" });
+    let (status, body) = send(&app, request("POST", run_uri, Some(run))).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "session_not_found");
+
+    let (status, body) = send(&app, request("GET", "/api/v1/workspaces/docs/executions", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["kind"], "dry_run");
+    assert_eq!(body[0]["block_index"], 0);
+    assert!(body[0].get("command").is_none());
+}
+
+#[actix_web::test]
+async fn matching_events_are_sent_to_signed_webhooks() {
+    let (url, received) = common::webhook_receiver();
+    let env = TestEnv::with_config(
+        "webhooks",
+        json!({
+            "notifications": {
+                "max_attempts": 1,
+                "webhooks": [
+                    { "name": "on-call", "url": url, "secret": "s3cret", "events": ["block_executed"] },
+                    { "name": "unreachable", "url": "http://127.0.0.1:1/hook", "workspaces": ["docs"] }
+                ]
+            }
+        }),
+    );
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=runbook.md";
+    let content = "```bash\ntrue\n```\n";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+    let run = json!({ "path": "runbook.md", "block_index": 0 });
+    let (status, body) = send(&app, request("POST", "/api/v1/workspaces/docs/branches/main/blocks/run", Some(run))).await;
+    assert_eq!(status, 200, "{}", body);
+
+    // Only the block run matches the first webhook's filter
+    let hook = received.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(hook.headers["x-runotepad-event"], "block_executed");
+    let signature = runotepad::notifications::signature("s3cret", hook.body.as_bytes());
+    assert_eq!(hook.headers["x-runotepad-signature"], signature);
+    let payload: Value = serde_json::from_str(&hook.body).unwrap();
+    assert_eq!(payload["event"], "block_executed");
+    assert_eq!(payload["data"]["execution_id"], body["execution_id"]);
+    assert_eq!(payload["data"]["token"], "default");
+    assert!(payload["text"].as_str().unwrap().contains("runbook.md"), "{}", payload);
+
+    // The unreachable webhook gave up on workspace_created and
+    // branch_created, and more
+    let mut status_body = Value::Null;
+    for _ in 0..100 {
+        let (status, body) = send(&app, request("GET", "/api/v1/notifications/status", None)).await;
+        assert_eq!(status, 200);
+        status_body = body;
+        if status_body["webhooks"][1]["failed"].as_u64().unwrap() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let webhooks = &status_body["webhooks"];
+    assert_eq!(webhooks[0]["name"], "on-call");
+    assert_eq!(webhooks[0]["delivered"], 1);
+    assert_eq!(webhooks[0]["failed"], 0);
+    assert_eq!(webhooks[1]["name"], "unreachable");
+    assert!(webhooks[1]["failed"].as_u64().unwrap() >= 2, "{}", status_body);
+    assert!(webhooks[1]["last_error"].is_string());
+    assert!(webhooks[1].get("url").is_none());
+}
+
+#[actix_web::test]
+async fn runbooks_export_as_self_contained_html() {
+    let env = TestEnv::new("export");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let runbook = "---\ntitle: DB failover\nseverity: 1\n---\n# Failover\n\n\
+        ![topology](../images/topology.png)\n\n![gone](missing.png) ![escape](../../../etc/x.png)\n\n\
+        ```bash\npg_ctl promote\n```\n\n```output\n# 2024-01-01T00:00:00Z exit code 0\nserver promoted\n```\n";
+    let worktree = env.worktree("docs", "main");
+    std::fs::create_dir_all(worktree.join("images")).unwrap();
+    std::fs::write(worktree.join("images/topology.png"), b"\x89PNG fake").unwrap();
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=ops/failover.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": runbook })))).await;
+    assert_eq!(status, 200);
+
+    let uri = "/api/v1/workspaces/docs/branches/main/export?path=ops/failover.md&format=html";
+    let resp = test::call_service(&app, request("GET", uri, None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"failover.html\""
+    );
+    let html = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(html.contains("<title>DB failover</title>"), "{}", html);
+    assert!(html.contains("<dt>severity</dt><dd>1</dd>"), "{}", html);
+    assert!(html.contains("src=\"data:image/png;base64,iVBORyBmYWtl\""), "{}", html);
+    assert!(html.contains("Image missing.png not included: not found"), "{}", html);
+    assert!(html.contains("Image ../../../etc/x.png not included: outside the worktree"), "{}", html);
+    assert!(html.contains("<code class=\"language-output\">"), "{}", html);
+    assert!(html.contains("server promoted"), "{}", html);
+    let head = common::git(&["rev-parse", "HEAD"], &worktree);
+    assert!(html.contains(&head), "{}", html);
+
+    let uri = "/api/v1/workspaces/docs/branches/main/export?path=ops/failover.md&format=pdf";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_export_format");
+}
+
+#[actix_web::test]
+async fn scheduled_runs_stop_at_a_failing_block_and_alert() {
+    let (url, received) = common::webhook_receiver();
+    let env = TestEnv::with_config(
+        "schedules",
+        json!({
+            "notifications": {
+                "max_attempts": 1,
+                "webhooks": [{ "name": "on-call", "url": url, "events": ["schedule_failed"] }]
+            }
+        }),
+    );
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=backups.md";
+    let content = "```bash\necho \"checking {{bucket}}\"\n```\n\n```python\nprint(1)\n```\n\n\
+        ```bash\nexit 3\n```\n\n```bash\necho unreachable\n```\n";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+
+    let schedule = json!({
+        "workspace": "docs",
+        "branch": "main",
+        "path": "backups.md",
+        "cron": "0 3 * * 1",
+        "variables": { "bucket": "nightly" }
+    });
+    let (status, body) = send(&app, request("POST", "/api/v1/schedules", Some(schedule.clone()))).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["blocks"], "all");
+    assert_eq!(body["enabled"], true);
+    assert_eq!(body["created_by"], "default");
+    assert!(body["next_run"].is_string());
+    let id = body["id"].as_str().unwrap().to_string();
+
+    let mut invalid = schedule.clone();
+    invalid["cron"] = json!("every monday");
+    let (status, body) = send(&app, request("POST", "/api/v1/schedules", Some(invalid))).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_cron");
+    let mut invalid = schedule.clone();
+    invalid["blocks"] = json!([0, 9]);
+    let (status, body) = send(&app, request("POST", "/api/v1/schedules", Some(invalid))).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "block_not_found");
+
+    let run_uri = format!("/api/v1/schedules/{}/run", id);
+    let (status, body) = send(&app, request("POST", &run_uri, None)).await;
+    assert_eq!(status, 202, "{}", body);
+
+    // The run happens on this test's runtime, so wait without blocking it
+    let mut hook = None;
+    for _ in 0..200 {
+        if let Ok(received) = received.try_recv() {
+            hook = Some(received);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let hook = hook.expect("no schedule_failed delivery");
+    // The python block is skipped and the run stops at `exit 3`
+    let payload: Value = serde_json::from_str(&hook.body).unwrap();
+    assert_eq!(payload["event"], "schedule_failed");
+    assert_eq!(payload["data"]["schedule_id"], id);
+    assert_eq!(payload["data"]["block_index"], 2);
+    assert_eq!(payload["data"]["exit_code"], 3);
+
+    let (status, body) = send(&app, request("GET", "/api/v1/workspaces/docs/executions", None)).await;
+    assert_eq!(status, 200);
+    let runs = body.as_array().unwrap();
+    assert_eq!(runs.len(), 2, "{}", body);
+    assert_eq!(runs[0]["block_index"], 0);
+    assert_eq!(runs[0]["exit_code"], 0);
+    assert_eq!(runs[1]["block_index"], 2);
+    assert_eq!(runs[1]["exit_code"], 3);
+    assert_eq!(runs[1]["execution_id"], Value::Null);
+    assert_eq!(runs[1]["id"], payload["data"]["execution_id"]);
+    assert!(runs.iter().all(|run| run["schedule_id"] == id && run["token"] == "default"));
+    let uri = format!("/api/v1/executions/{}", runs[0]["id"].as_str().unwrap());
+    let (_, execution) = send(&app, request("GET", &uri, None)).await;
+    assert_eq!(execution["output"]["stdout"], "checking nightly\n");
+
+    // A schedule whose workspace is deleted is turned off on its next run
+    create_workspace(&env, &app, "scratch").await;
+    let uri = "/api/v1/workspaces/scratch/branches/main/file?path=backups.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+    let mut orphan = schedule.clone();
+    orphan["workspace"] = json!("scratch");
+    let (status, body) = send(&app, request("POST", "/api/v1/schedules", Some(orphan))).await;
+    assert_eq!(status, 201);
+    let orphan_id = body["id"].as_str().unwrap().to_string();
+    let (status, _) = send(&app, request("DELETE", "/api/v1/workspaces/scratch", None)).await;
+    assert_eq!(status, 200);
+    let (status, body) = send(&app, request("POST", &format!("/api/v1/schedules/{}/run", orphan_id), None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "workspace_not_found");
+
+    let (status, body) = send(&app, request("GET", "/api/v1/schedules", None)).await;
+    assert_eq!(status, 200);
+    let orphan = body["schedules"].as_array().unwrap().iter().find(|s| s["id"] == orphan_id.as_str()).unwrap();
+    assert_eq!(orphan["enabled"], false);
+    assert!(orphan["disabled_reason"].as_str().unwrap().contains("scratch"), "{}", orphan);
+    assert_eq!(orphan["next_run"], Value::Null);
+
+    let (status, _) = send(&app, request("DELETE", &format!("/api/v1/schedules/{}", id), None)).await;
+    assert_eq!(status, 200);
+    let (status, body) = send(&app, request("DELETE", &format!("/api/v1/schedules/{}", id), None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "schedule_not_found");
+}
+
+#[actix_web::test]
+async fn schedules_are_paused_in_read_only_mode() {
+    use runotepad::audit::AuditLog;
+    use runotepad::config::{ConfigManager, ConfigOverrides};
+    use runotepad::{app_factory, scheduler, AppState};
+    use std::sync::Arc;
+
+    let env = TestEnv::new("schedules-read-only");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=tick.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": "```bash\ntouch ran\n```\n" })))).await;
+    assert_eq!(status, 200);
+    let schedule = json!({ "workspace": "docs", "branch": "main", "path": "tick.md", "cron": "* * * * *" });
+    let (status, body) = send(&app, request("POST", "/api/v1/schedules", Some(schedule))).await;
+    assert_eq!(status, 201, "{}", body);
+    let id = body["id"].as_str().unwrap().to_string();
+
+    // The same config and workspaces, served read-only
+    let config = Arc::new(
+        ConfigManager::new(ConfigOverrides {
+            config_file: Some(env.dir.join("config.json")),
+            workspace_dir: Some(env.config.get_workspace_dir().clone()),
+            token: Some(common::TOKEN.to_string()),
+            read_only: true,
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let audit_log = AuditLog::open(config.audit_log_path().clone()).unwrap();
+    let state = Arc::new(AppState::new(&config, Arc::new(audit_log)));
+
+    let now = chrono::Utc::now();
+    let since = now - chrono::Duration::minutes(2);
+    assert!(scheduler::run_due(&state, &config, &since, &now).is_empty());
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let ran = env.worktree("docs", "main").join("ran");
+    assert!(!ran.exists());
+    let read_only_app = test::init_service(app_factory(config.clone(), state.clone())).await;
+    let (status, body) = send(&read_only_app, request("GET", "/api/v1/workspaces/docs/executions", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!([]));
+
+    // Writable, the schedule was due
+    assert_eq!(scheduler::run_due(&env.state, &env.config, &since, &now), [id]);
+    for _ in 0..100 {
+        if ran.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(ran.exists());
+}
+
+#[actix_web::test]
+async fn browser_console_entries_are_kept_for_querying() {
+    let env = TestEnv::with_config(
+        "console",
+        json!({ "allow_unauthenticated_console": true, "console_buffer_entries": 3 }),
+    );
+    let app = env.app().await;
+
+    // Batches and single messages; posting needs no token here
+    let batch = json!([
+        { "level": "info", "message": "loaded", "timestamp": "10:00:00.000" },
+        { "level": "error", "message": "boom\n  at main.ts:1", "timestamp": "10:00:00.100" },
+        { "level": "warn", "message": "slow" }
+    ]);
+    let req = test::TestRequest::post()
+        .uri("/api/v1/console")
+        .insert_header(("User-Agent", "Firefox/128.0"))
+        .peer_addr("10.1.2.3:5555".parse().unwrap())
+        .set_json(batch);
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body, json!({ "accepted": 3, "dropped": 0 }));
+    let long = json!({ "level": "error", "message": "x".repeat(5000) });
+    let (status, _) = send(&app, request("POST", "/api/v1/console", Some(long))).await;
+    assert_eq!(status, 200);
+
+    // Only the latest three are kept
+    let (status, body) = send(&app, request("GET", "/api/v1/console", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    let entries = body.as_array().unwrap();
+    assert_eq!(entries.len(), 3, "{}", body);
+    assert_eq!(entries[0]["message"], "boom\n  at main.ts:1");
+    assert_eq!(entries[0]["client_timestamp"], "10:00:00.100");
+    assert_eq!(entries[0]["user_agent"], "Firefox/128.0");
+    assert_eq!(entries[0]["ip"], "10.1.2.3");
+    assert_eq!(entries[1]["client_timestamp"], Value::Null);
+    assert_eq!(entries[2]["truncated"], true);
+    assert!(entries[2]["message"].as_str().unwrap().ends_with("... (904 more chars)"));
+
+    let (status, body) = send(&app, request("GET", "/api/v1/console?level=error&limit=1", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["truncated"], true);
+    let since = entries[0]["timestamp"].as_str().unwrap().replace('+', "%2B");
+    let (status, body) = send(&app, request("GET", &format!("/api/v1/console?level=warn&since={}", since), None)).await;
+    assert_eq!(status, 200);
+    assert!(body.as_array().unwrap().iter().all(|entry| entry["level"] != "info"), "{}", body);
+    let (status, body) = send(&app, request("GET", "/api/v1/console?level=loud", None)).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_query");
+
+    // Reading the entries back always needs a read_write token
+    let req = test::TestRequest::get().uri("/api/v1/console");
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 401);
+    let (_, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "viewer", "scope": "read" })))).await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/console")
+        .insert_header(("Authorization", format!("Bearer {}", body["token"].as_str().unwrap())));
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["code"], "insufficient_scope");
+
+    let (status, body) = send(&app, request("DELETE", "/api/v1/console", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["cleared"], 3);
+    let (_, body) = send(&app, request("GET", "/api/v1/console", None)).await;
+    assert_eq!(body, json!([]));
+}
+
+#[actix_web::test]
+async fn server_log_is_tailed_without_tokens_or_browser_messages() {
+    // No output, so only the admin capture sees records
+    runotepad::logging::init(Some("off"), runotepad::logging::LogFormat::Text);
+    runotepad::server_log::capture().configure(log::LevelFilter::Debug, 1000);
+    let env = TestEnv::with_config("server-log", json!({ "allow_unauthenticated_console": true }));
+    let app = env.app().await;
+
+    let stream = request("GET", "/api/v1/admin/logs/stream?level=info", None);
+    let resp = test::call_service(&app, stream.to_request()).await;
+    assert_eq!(resp.status(), 200);
+    let (status, _) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "ops" })))).await;
+    assert_eq!(status, 201);
+    let records = read_events(resp.into_body(), |record| {
+        record["message"] == "Created API token \"ops\" (ReadWrite)"
+    })
+    .await;
+    let created = records.last().unwrap();
+    assert_eq!(created["level"], "INFO");
+    assert_eq!(created["target"], "runotepad::auth");
+    assert!(created["request_id"].is_string());
+    assert!(created["timestamp"].is_string());
+    assert!(records.iter().all(|record| ["INFO", "WARN", "ERROR"].contains(&record["level"].as_str().unwrap())));
+
+    // A token in the query string and a forwarded browser message
+    let uri = format!("/api/v1/auth/check?token={}", common::TOKEN);
+    let (status, _) = send(&app, test::TestRequest::get().uri(&uri)).await;
+    assert_eq!(status, 200);
+    let message = json!({ "level": "error", "message": "Received message: terminal output" });
+    let (status, _) = send(&app, test::TestRequest::post().uri("/api/v1/console").set_json(message)).await;
+    assert_eq!(status, 200);
+
+    let (status, body) = send(&app, request("GET", "/api/v1/admin/logs?limit=1000", None)).await;
+    assert_eq!(status, 200);
+    let records = body.as_array().unwrap();
+    let access = records
+        .iter()
+        .find(|record| record["message"].as_str().unwrap().contains("GET /api/v1/auth/check HTTP/1.1"));
+    assert!(access.is_some(), "{}", body);
+    for record in records {
+        let message = record["message"].as_str().unwrap();
+        assert!(!message.contains(common::TOKEN), "{}", message);
+        assert!(!message.contains("terminal output"), "{}", message);
+    }
+    let (status, body) = send(&app, request("GET", "/api/v1/admin/logs?level=chatty", None)).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_query");
+
+    // Only for read_write tokens
+    let (_, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "viewer", "scope": "read" })))).await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/admin/logs")
+        .insert_header(("Authorization", format!("Bearer {}", body["token"].as_str().unwrap())));
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 403);
+}
+
+#[actix_web::test]
+async fn readiness_reports_failed_checks_with_details_for_tokens_only() {
+    let env = TestEnv::new("readyz");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let (status, body) = send(&app, test::TestRequest::get().uri("/readyz")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["failed"], 0);
+    assert!(body.get("checks").is_none());
+
+    // A repository gone from under the server fails its workspace's check
+    std::fs::remove_dir_all(env.config.get_workspace_dir().join("docs/repo")).unwrap();
+    env.config.invalidate_workspace_status("docs");
+    let (status, body) = send(&app, test::TestRequest::get().uri("/readyz")).await;
+    assert_eq!(status, 503);
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["failed"], 1);
+    assert!(body.get("checks").is_none());
+
+    let (status, body) = send(&app, request("GET", "/readyz", None)).await;
+    assert_eq!(status, 503);
+    let checks = body["checks"].as_array().unwrap();
+    let docs = checks.iter().find(|check| check["name"] == "workspace docs").unwrap();
+    assert_eq!(docs["level"], "fail");
+    assert_eq!(docs["message"], "repository directory is missing");
+    assert!(checks.iter().any(|check| check["name"] == "git" && check["level"] == "pass"));
+}
+
+#[actix_web::test]
+async fn sessions_are_listed() {
+    let env = TestEnv::new("sessions");
+    let app = env.app().await;
+
+    // Terminals are opened over the WebSocket, which the e2e tests cover
+    let (status, body) = send(&app, request("GET", "/api/v1/sessions", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "sessions": [] }));
+}
+
+#[actix_web::test]
+async fn sessions_are_terminated_by_admins_only_when_they_exist() {
+    let env = TestEnv::new("terminate-session");
+    let app = env.app().await;
+
+    let (status, body) = send(&app, request("DELETE", "/api/v1/sessions/gone?signal=SIGKILL", None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "session_not_found");
+    let (status, body) = send(&app, request("DELETE", "/api/v1/sessions/gone?signal=SIGSTOP", None)).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_signal");
+
+    // Only for read_write tokens
+    let (_, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "viewer", "scope": "read" })))).await;
+    let req = test::TestRequest::delete()
+        .uri("/api/v1/sessions/gone")
+        .insert_header(("Authorization", format!("Bearer {}", body["token"].as_str().unwrap())));
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 403);
+}
+
+#[actix_web::test]
+async fn session_buffers_are_for_read_write_tokens() {
+    let env = TestEnv::with_config("session-buffer", json!({ "session_scrollback_bytes": 4096 }));
+    let app = env.app().await;
+
+    for uri in ["/api/v1/sessions/gone/buffer?tail_bytes=100", "/api/v1/sessions/gone/buffer/search?q=error"] {
+        let (status, body) = send(&app, request("GET", uri, None)).await;
+        assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "session_not_found"));
+    }
+    let (status, body) = send(&app, request("GET", "/api/v1/sessions/gone/buffer/search?q=", None)).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, "empty_query"));
+
+    let (_, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "viewer", "scope": "read" })))).await;
+    let viewer = format!("Bearer {}", body["token"].as_str().unwrap());
+    let req = test::TestRequest::get().uri("/api/v1/sessions/gone/buffer").insert_header(("Authorization", viewer.clone()));
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 403);
+    // Listing the sessions is still open to them
+    let req = test::TestRequest::get().uri("/api/v1/sessions").insert_header(("Authorization", viewer));
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 200);
+
+    let (_, body) = send(&app, request("GET", "/api/v1/capabilities", None)).await;
+    assert_eq!(body["features"]["session_scrollback"], true);
+    assert_eq!(body["limits"]["session_scrollback_bytes"], 4096);
+}
+
+#[actix_web::test]
+async fn config_exports_leave_secrets_out_unless_asked_for() {
+    let env = TestEnv::with_config(
+        "export",
+        json!({
+            "notifications": {
+                "webhooks": [{ "name": "on-call", "url": "https://hooks.example.com/x", "secret": "hook-key" }]
+            }
+        }),
+    );
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let update = json!({ "env": { "STAGE": "prod" }, "secret_env": { "DB_PASSWORD": "hunter2" } });
+    let (status, _) = send(&app, request("PUT", "/api/v1/workspaces/docs/env", Some(update))).await;
+    assert_eq!(status, 200);
+
+    let (status, body) = send(&app, request("GET", "/api/v1/admin/export", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["format"], "runotepad-bundle");
+    assert_eq!(body["bundle_version"], 1);
+    assert_eq!(body["includes_secrets"], false);
+    assert_eq!(
+        body["redacted"],
+        json!([
+            "notifications.webhooks.on-call.secret",
+            "tokens.default",
+            "workspaces.docs.secret_env.DB_PASSWORD"
+        ])
+    );
+    let docs = &body["config"]["workspaces"]["docs"];
+    assert_eq!(docs["base_branch"], "main");
+    assert_eq!(docs["env"], json!({ "STAGE": "prod" }));
+    assert!(docs.get("secret_env").is_none());
+    assert_eq!(body["config"]["tokens"], json!([]));
+    assert!(!body.to_string().contains("hook-key"));
+
+    // Secrets come opened, since the key stays behind
+    let (status, body) = send(&app, request("GET", "/api/v1/admin/export?include_secrets=true", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["includes_secrets"], true);
+    assert_eq!(body["redacted"], json!([]));
+    assert_eq!(body["config"]["workspaces"]["docs"]["secret_env"], json!({ "DB_PASSWORD": "hunter2" }));
+    assert_eq!(body["config"]["notifications"]["webhooks"][0]["secret"], "hook-key");
+    assert!(body["config"]["tokens"][0]["hash"].as_str().unwrap().starts_with("sha256$"));
+
+    // Only for read_write tokens
+    let (_, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "viewer", "scope": "read" })))).await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/admin/export")
+        .insert_header(("Authorization", format!("Bearer {}", body["token"].as_str().unwrap())));
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 403);
+}
+
+#[actix_web::test]
+async fn workspace_env_is_set_for_runs_with_secrets_sealed() {
+    // The key lives apart from the config, as it would on another volume
+    let keys = std::env::temp_dir().join(format!("runotepad-api-test-{}-env-keys", std::process::id()));
+    let _ = std::fs::remove_dir_all(&keys);
+    let key_file = keys.join("secret.key");
+    let env = TestEnv::with_config("env", json!({ "secret_key_file": key_file }));
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let env_uri = "/api/v1/workspaces/docs/env";
+    let update = json!({
+        "env": { "STAGE": "prod" },
+        "secret_env": { "DB_PASSWORD": "hunter2 with spaces" },
+    });
+    let (status, body) = send(&app, request("PUT", env_uri, Some(update))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body, json!({ "env": { "STAGE": "prod" }, "secret_env": ["DB_PASSWORD"] }));
+    assert!(key_file.exists());
+
+    let (status, body) = send(&app, request("GET", env_uri, None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "env": { "STAGE": "prod" }, "secret_env": ["DB_PASSWORD"] }));
+    let config_file = std::fs::read_to_string(env.dir.join("config.json")).unwrap();
+    assert!(config_file.contains("DB_PASSWORD"));
+    assert!(!config_file.contains("hunter2"));
+
+    let content = "# Deploy\n\n```bash\necho \"$STAGE:$DB_PASSWORD\"\n```\n";
+    let uri = "/api/v1/workspaces/docs/branches/main/file?path=deploy.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+    let run_uri = "/api/v1/workspaces/docs/branches/main/blocks/run";
+    let run = json!({ "path": "deploy.md", "block_index": 0 });
+    let (status, body) = send(&app, request("POST", run_uri, Some(run.clone()))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["stdout"], "prod:hunter2 with spaces\n");
+
+    // A null secret keeps its value; plain variables left out are unchanged
+    let update = json!({ "secret_env": { "DB_PASSWORD": null, "API_KEY": "k" } });
+    let (status, body) = send(&app, request("PUT", env_uri, Some(update))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["secret_env"], json!(["API_KEY", "DB_PASSWORD"]));
+    let (status, body) = send(&app, request("POST", run_uri, Some(run))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["stdout"], "prod:hunter2 with spaces\n");
+
+    for update in [
+        json!({ "env": { "1BAD": "x" } }),
+        json!({ "env": { "API_KEY": "plain" } }),
+        json!({ "secret_env": { "UNKNOWN": null } }),
+    ] {
+        let (status, body) = send(&app, request("PUT", env_uri, Some(update))).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["code"], "invalid_env");
+    }
+
+    let (status, body) = send(&app, request("GET", "/api/v1/audit", None)).await;
+    assert_eq!(status, 200);
+    assert!(!body.to_string().contains("hunter2"));
+    let _ = std::fs::remove_dir_all(&keys);
+}
+
+#[actix_web::test]
+async fn files_are_restored_from_git_history() {
+    let env = TestEnv::new("restore");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    let restore_uri = "/api/v1/workspaces/docs/branches/main/file/restore";
+    let file_uri = "/api/v1/workspaces/docs/branches/main/file?path=README.md";
+    let head = git(&["rev-parse", "HEAD"], &worktree);
+
+    let (status, _) = send(&app, request("PUT", file_uri, Some(json!({ "content": "mangled\n" })))).await;
+    assert_eq!(status, 200);
+    let (status, body) = send(&app, request("POST", restore_uri, Some(json!({ "path": "README.md" })))).await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["code"], "file_modified");
+    assert_eq!(body["error"]["details"]["current_hash"], runotepad::blocks::content_hash("mangled\n"));
+
+    let restore = json!({ "path": "README.md", "ref": "HEAD", "overwrite": true });
+    let (status, body) = send(&app, request("POST", restore_uri, Some(restore))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["commit"]["sha"], head);
+    assert_eq!(body["from_deleted"], false);
+    assert_eq!(std::fs::read_to_string(worktree.join("README.md")).unwrap(), "# Fixture\n");
+
+    // Deleted and committed: found in the last commit that had it
+    git(&["rm", "-q", "docs/alerts.md"], &worktree);
+    git(&["commit", "-q", "-m", "Remove alerts"], &worktree);
+    let (status, body) = send(&app, request("POST", restore_uri, Some(json!({ "path": "docs/alerts.md" })))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["commit"]["sha"], head);
+    assert_eq!(body["commit"]["subject"], "Initial runbooks");
+    assert_eq!(body["from_deleted"], true);
+    assert!(std::fs::read_to_string(worktree.join("docs/alerts.md")).unwrap().contains("HighCPU"));
+
+    let (status, body) = send(&app, request("POST", restore_uri, Some(json!({ "path": "never.md" })))).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "file_not_found");
+    let restore = json!({ "path": "README.md", "ref": "no-such-branch" });
+    let (status, body) = send(&app, request("POST", restore_uri, Some(restore))).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "revision_not_found");
+}
+
+#[actix_web::test]
+async fn commits_never_stage_outside_the_named_paths() {
+    let env = TestEnv::new("commit-paths");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "other" }))),
+    )
+    .await;
+    assert_eq!(status, 201, "{}", body);
+    let worktree = env.worktree("docs", "main");
+    let other = env.worktree("docs", "other");
+    std::fs::write(other.join("stray.md"), "# Stray\n").unwrap();
+    std::fs::write(worktree.join("README.md"), "# Changed\n").unwrap();
+    let commit = |files: Value| {
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/main/commit?override=true",
+            Some(json!({ "message": "Hostile names", "files": files })),
+        )
+    };
+
+    for path in ["../other/stray.md", "docs/../../other/stray.md"] {
+        let (status, body) = send(&app, commit(json!([path]))).await;
+        assert_eq!(status, 400, "{}: {}", path, body);
+        assert_eq!(body["error"]["code"], "invalid_path");
+    }
+    // Absolute paths are taken relative to the worktree, like file reads
+    let (status, body) = send(&app, commit(json!([other.join("stray.md")]))).await;
+    assert_eq!(status, 404, "{}", body);
+    assert_eq!(body["error"]["code"], "path_not_found");
+    assert_eq!(git(&["diff", "--cached", "--name-only"], &worktree), "");
+    assert_eq!(git(&["status", "--porcelain"], &other), "?? stray.md");
+
+    // Names git would otherwise read as options, globs or pathspec magic
+    let hostile = ["-n", "--all", "*.md", ":(top)x.md", "with space.md", "line\nbreak.md"];
+    for name in hostile {
+        std::fs::write(worktree.join(name), "hostile\n").unwrap();
+    }
+    let (status, body) = send(&app, commit(json!(hostile))).await;
+    assert_eq!(status, 200, "{}", body);
+    let mut committed: Vec<&str> = body["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["path"].as_str().unwrap())
+        .collect();
+    committed.sort();
+    let mut expected = hostile.to_vec();
+    expected.sort();
+    assert_eq!(committed, expected);
+    // README.md matches `*.md` as a glob, but was left alone
+    assert_eq!(git(&["status", "--porcelain"], &worktree), "M README.md");
+    assert_eq!(git(&["status", "--porcelain"], &other), "?? stray.md");
+}
+
+#[actix_web::test]
+async fn git_operations_refuse_a_worktree_on_another_branch() {
+    let env = TestEnv::new("checked-out");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    let head = git(&["rev-parse", "HEAD"], &worktree);
+
+    let (status, branches) = send(&app, request("GET", "/api/v1/workspaces/docs/branches", None)).await;
+    assert_eq!(status, 200);
+    let main = branches.as_array().unwrap().iter().find(|b| b["name"] == "main").unwrap();
+    assert_eq!(main["checked_out_branch"], "main");
+    assert_eq!(main["head_commit"], head);
+
+    // A checkout typed into a terminal
+    git(&["checkout", "-q", "-b", "surprise"], &worktree);
+    std::fs::write(worktree.join("README.md"), "# Changed\n").unwrap();
+    let (status, branches) = send(&app, request("GET", "/api/v1/workspaces/docs/branches", None)).await;
+    assert_eq!(status, 200);
+    let main = branches.as_array().unwrap().iter().find(|b| b["name"] == "main").unwrap();
+    assert_eq!(main["checked_out_branch"], "surprise");
+
+    let commit = json!({ "message": "Oops", "all": true });
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/v1/workspaces/docs/branches/main/commit", Some(commit)),
+    )
+    .await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["code"], "branch_mismatch");
+    assert_eq!(body["error"]["details"], json!({ "branch": "main", "checked_out_branch": "surprise" }));
+    for operation in ["push", "pull", "rebase"] {
+        let uri = format!("/api/v1/workspaces/docs/branches/main/{}?sync=true", operation);
+        let (status, body) = send(&app, request("POST", &uri, None)).await;
+        assert_eq!(status, 409, "{}: {}", operation, body);
+        assert_eq!(body["error"]["code"], "branch_mismatch");
+    }
+
+    git(&["checkout", "-q", "--detach"], &worktree);
+    let (status, body) = send(&app, request("POST", "/api/v1/workspaces/docs/branches/main/push", None)).await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["details"]["checked_out_branch"], Value::Null);
+    assert_eq!(git(&["rev-parse", "main"], &worktree), head);
+}
+
+#[actix_web::test]
+async fn submodules_are_initialized_updated_and_reported() {
+    // git refuses file:// submodules unless told otherwise
+    for (key, value) in [
+        ("GIT_CONFIG_COUNT", "1"),
+        ("GIT_CONFIG_KEY_0", "protocol.file.allow"),
+        ("GIT_CONFIG_VALUE_0", "always"),
+    ] {
+        std::env::set_var(key, value);
+    }
+    let env = TestEnv::new("submodules");
+    let app = env.app().await;
+    let fixture = env.fixture_repo();
+    let src = env.dir.join("fixture-src");
+
+    let lib_src = env.dir.join("lib-src");
+    std::fs::create_dir_all(&lib_src).unwrap();
+    std::fs::write(lib_src.join("common.md"), "# Common steps\n").unwrap();
+    git(&["init", "-q", "-b", "main"], &lib_src);
+    git(&["add", "-A"], &lib_src);
+    git(&["commit", "-q", "-m", "Common steps"], &lib_src);
+    git(&["submodule", "add", "-q", lib_src.to_str().unwrap(), "lib"], &src);
+    git(&["commit", "-q", "-m", "Add lib"], &src);
+    git(&["push", "-q", fixture.to_str().unwrap(), "main"], &src);
+
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    assert_eq!(std::fs::read_to_string(worktree.join("lib/common.md")).unwrap(), "# Common steps\n");
+
+    let status_uri = "/api/v1/workspaces/docs/branches/main/status";
+    let (status, body) = send(&app, request("GET", status_uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["checked_out_branch"], "main");
+    assert_eq!(body["changes"], json!([]));
+    let lib_head = git(&["rev-parse", "HEAD"], &lib_src);
+    assert_eq!(
+        body["submodules"],
+        json!([{
+            "path": "lib",
+            "commit": lib_head,
+            "initialized": true,
+            "commit_mismatch": false,
+            "conflicted": false,
+            "dirty": false
+        }])
+    );
+
+    std::fs::write(worktree.join("lib/scratch.md"), "notes\n").unwrap();
+    let (_, body) = send(&app, request("GET", status_uri, None)).await;
+    assert_eq!(body["submodules"][0]["dirty"], true);
+    assert_eq!(body["changes"], json!([{ "status": " M", "path": "lib" }]));
+    std::fs::remove_file(worktree.join("lib/scratch.md")).unwrap();
+
+    // A pull that moves the submodule checks out its new commit
+    std::fs::write(lib_src.join("rollback.md"), "# Rollback\n").unwrap();
+    git(&["add", "-A"], &lib_src);
+    git(&["commit", "-q", "-m", "Rollback steps"], &lib_src);
+    let new_lib_head = git(&["rev-parse", "HEAD"], &lib_src);
+    git(&["-C", "lib", "pull", "-q", "origin", "main"], &src);
+    git(&["commit", "-q", "-am", "Bump lib"], &src);
+    git(&["push", "-q", fixture.to_str().unwrap(), "main"], &src);
+
+    let uri = "/api/v1/workspaces/docs/branches/main/pull?sync=true";
+    let (status, body) = send(&app, request("POST", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["submodules_updated"], true);
+    assert!(worktree.join("lib/rollback.md").exists());
+    let (_, body) = send(&app, request("GET", status_uri, None)).await;
+    assert_eq!(body["submodules"][0]["commit"], new_lib_head);
+    assert_eq!(body["submodules"][0]["commit_mismatch"], false);
+
+    let (status, body) = send(&app, request("POST", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["submodules_updated"], false);
+
+    git(&["checkout", "-q", &lib_head], &worktree.join("lib"));
+    let (_, body) = send(&app, request("GET", status_uri, None)).await;
+    assert_eq!(body["submodules"][0]["commit"], lib_head);
+    assert_eq!(body["submodules"][0]["commit_mismatch"], true);
+}
+
+#[actix_web::test]
+async fn lfs_pointers_are_reported_instead_of_served() {
+    let env = TestEnv::new("lfs");
+    let app = env.app().await;
+    let fixture = env.fixture_repo();
+    let src = env.dir.join("fixture-src");
+    let pointer = "version https://git-lfs.github.com/spec/v1\n\
+                   oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+                   size 12345\n";
+    std::fs::write(src.join(".gitattributes"), "*.png filter=lfs diff=lfs merge=lfs -text\n").unwrap();
+    std::fs::write(src.join("docs/architecture.png"), pointer).unwrap();
+    git(&["add", "-A"], &src);
+    git(&["commit", "-q", "-m", "Add architecture diagram"], &src);
+    git(&["push", "-q", fixture.to_str().unwrap(), "main"], &src);
+    let lfs_available = std::process::Command::new("git")
+        .args(["lfs", "version"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+
+    let (status, body) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces?sync=true",
+            Some(json!({ "name": "docs", "repo_url": fixture, "base_branch": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 201, "{}", body);
+    // The pointer's object exists nowhere, so even git-lfs can't fetch it
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "main" }))),
+    )
+    .await;
+    assert_eq!(status, 201, "{}", body);
+    assert!(body["lfs_error"].is_string(), "{}", body);
+
+    let (status, body) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(status, 200);
+    let expected = if lfs_available { "ok" } else { "lfs_unavailable" };
+    assert_eq!(body["status"], expected);
+
+    for uri in [
+        "/api/workspaces/docs/branches/main/file?path=docs/architecture.png",
+        "/api/workspaces/docs/branches/main/file?path=docs/architecture.png&raw=true",
+    ] {
+        let (status, body) = send(&app, request("GET", uri, None)).await;
+        assert_eq!(status, 409, "{}", uri);
+        assert_eq!(body["error"]["code"], "lfs_pointer");
+        assert_eq!(
+            body["error"]["details"],
+            json!({
+                "oid": "sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393",
+                "size": 12345,
+                "lfs_available": lfs_available
+            })
+        );
+    }
+    let (status, _) = send(&app, request("GET", "/api/workspaces/docs/branches/main/file?path=README.md", None)).await;
+    assert_eq!(status, 200);
+}
+
+#[actix_web::test]
+async fn deleted_files_go_to_the_trash_and_can_be_restored() {
+    let env = TestEnv::new("trash");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    let base = "/api/v1/workspaces/docs/branches/main";
+
+    let (status, body) = send(&app, request("DELETE", &format!("{}/file?path=docs/alerts.md", base), None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["trash"]["path"], "docs/alerts.md");
+    let id = body["trash"]["id"].as_str().unwrap().to_string();
+    assert!(!worktree.join("docs/alerts.md").exists());
+
+    // Out of sight everywhere but the trash listing
+    let (_, files) = send(&app, request("GET", &format!("{}/files", base), None)).await;
+    assert_eq!(files, json!([{ "name": "README.md", "path": "README.md", "is_dir": false }]));
+    let (_, found) = send(&app, request("GET", &format!("{}/search?q=HighCPU", base), None)).await;
+    assert_eq!(found["matches"], json!([]));
+    let (_, status_body) = send(&app, request("GET", &format!("{}/status", base), None)).await;
+    assert_eq!(status_body["changes"], json!([{ "status": " D", "path": "docs/alerts.md" }]));
+    let (status, trash) = send(&app, request("GET", &format!("{}/trash", base), None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(trash.as_array().unwrap().len(), 1);
+    assert_eq!(trash[0]["id"], id.as_str());
+    assert_eq!(trash[0]["size"], "# Alerts\n\nHighCPU fires above 90%\n".len());
+
+    std::fs::write(worktree.join("docs/alerts.md"), "# New alerts\n").unwrap();
+    let restore = json!({ "id": id });
+    let (status, body) = send(&app, request("POST", &format!("{}/trash/restore", base), Some(restore.clone()))).await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["code"], "path_occupied");
+    assert_eq!(body["error"]["details"]["path"], "docs/alerts.md");
+    std::fs::remove_file(worktree.join("docs/alerts.md")).unwrap();
+    let (status, body) = send(&app, request("POST", &format!("{}/trash/restore", base), Some(restore.clone()))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        std::fs::read_to_string(worktree.join("docs/alerts.md")).unwrap(),
+        "# Alerts\n\nHighCPU fires above 90%\n"
+    );
+    let (status, body) = send(&app, request("POST", &format!("{}/trash/restore", base), Some(restore))).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "trash_item_not_found");
+    let escape = json!({ "id": "20260101T000000.000Z-../../README.md" });
+    let (status, _) = send(&app, request("POST", &format!("{}/trash/restore", base), Some(escape))).await;
+    assert_eq!(status, 404);
+
+    // Purged by hand, or once older than trash_retention_days
+    let (_, body) = send(&app, request("DELETE", &format!("{}/file?path=README.md", base), None)).await;
+    let readme_id = body["trash"]["id"].as_str().unwrap().to_string();
+    let (_, body) = send(&app, request("DELETE", &format!("{}/file?path=docs/alerts.md", base), None)).await;
+    let alerts_id = body["trash"]["id"].as_str().unwrap().to_string();
+    let trash_dir = worktree.join(".runotepad-trash");
+    let expired = "20200101T000000.000Z-0badc0de";
+    std::fs::rename(trash_dir.join(&alerts_id), trash_dir.join(expired)).unwrap();
+    let (_, trash) = send(&app, request("GET", &format!("{}/trash", base), None)).await;
+    assert_eq!(trash.as_array().unwrap().len(), 1);
+    assert!(!trash_dir.join(expired).exists());
+    let (status, body) = send(&app, request("POST", &format!("{}/trash/purge", base), Some(json!({})))).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["purged"], json!([readme_id]));
+    let (_, trash) = send(&app, request("GET", &format!("{}/trash", base), None)).await;
+    assert_eq!(trash, json!([]));
+
+    git(&["checkout", "--", "."], &worktree);
+    let (status, body) = send(
+        &app,
+        request("DELETE", &format!("{}/file?path=README.md&permanent=true", base), None),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["trash"], Value::Null);
+    assert!(!worktree.join("README.md").exists());
+    let (_, trash) = send(&app, request("GET", &format!("{}/trash", base), None)).await;
+    assert_eq!(trash, json!([]));
+    let (status, _) = send(&app, request("DELETE", &format!("{}/file?path=README.md", base), None)).await;
+    assert_eq!(status, 404);
+}
+
+#[actix_web::test]
+async fn runbooks_are_linted_for_broken_links() {
+    let env = TestEnv::new("lint");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    std::fs::write(
+        worktree.join("docs/ops.md"),
+        "# Ops\n\n## Restart the API\n\n\
+         See [alerts](alerts.md#alerts), [gone](missing.md), [wrong](./alerts.md#nope),\n\
+         [here](#restart-the-api), [nowhere](#nowhere), [web](https://example.com), [up](../README.md).\n\n\
+         ![diagram](img/arch.png)\n\n```bash\n```\n",
+    )
+    .unwrap();
+
+    let expected = json!([
+        { "path": "docs/ops.md", "line": 5, "rule": "broken_link",
+          "message": "'missing.md' points at a file that doesn't exist in the worktree" },
+        { "path": "docs/ops.md", "line": 5, "rule": "broken_anchor",
+          "message": "No heading in 'docs/alerts.md' has the anchor '#nope'" },
+        { "path": "docs/ops.md", "line": 6, "rule": "broken_anchor",
+          "message": "No heading in 'docs/ops.md' has the anchor '#nowhere'" },
+        { "path": "docs/ops.md", "line": 8, "rule": "missing_image",
+          "message": "'img/arch.png' points at a file that doesn't exist in the worktree" },
+        { "path": "docs/ops.md", "line": 10, "rule": "empty_code_block", "message": "Code block is empty" },
+    ]);
+    let base = "/api/v1/workspaces/docs/branches/main/lint";
+    let (status, body) = send(&app, request("GET", &format!("{}?path=docs/ops.md", base), None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["problems"], expected);
+    assert_eq!(body["files_checked"], 1);
+    assert_eq!(body["truncated"], false);
+
+    let (status, body) = send(&app, request("GET", base, None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["problems"], expected);
+    assert_eq!(body["files_checked"], 3);
+
+    let (_, body) = send(&app, request("GET", &format!("{}?limit=2", base), None)).await;
+    assert_eq!(body["problems"].as_array().unwrap().len(), 2);
+    assert_eq!(body["truncated"], true);
+
+    let (status, body) = send(&app, request("GET", &format!("{}?path=docs/none.md", base), None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "path_not_found");
+}
+
+#[actix_web::test]
+async fn outlines_nest_headings_of_saved_and_unsaved_content() {
+    let env = TestEnv::new("outline");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    std::fs::write(
+        worktree.join("ops.md"),
+        "# Ops\n\n## Restart\n\n- [ ] Drain\n\n### Check `api`\n\n```bash\ncurl api\n```\n\n## Restart\n\n# Notes\n",
+    )
+    .unwrap();
+
+    let uri = "/api/v1/workspaces/docs/branches/main/file/outline";
+    let (status, body) = send(&app, request("GET", &format!("{}?path=ops.md", uri), None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        body,
+        json!({
+            "path": "ops.md",
+            "headings": [
+                { "level": 1, "title": "Ops", "anchor": "ops", "line": 1, "children": [
+                    { "level": 2, "title": "Restart", "anchor": "restart", "line": 3, "children": [
+                        { "level": 3, "title": "Check api", "anchor": "check-api", "line": 7, "children": [] }
+                    ] },
+                    { "level": 2, "title": "Restart", "anchor": "restart-1", "line": 13, "children": [] }
+                ] },
+                { "level": 1, "title": "Notes", "anchor": "notes", "line": 15, "children": [] }
+            ],
+            "code_blocks": [{ "index": 0, "language": "bash", "start_line": 9, "end_line": 11, "section": 2 }],
+            "tasks": [{ "index": 0, "checked": false, "line": 5, "section": 1 }]
+        })
+    );
+
+    // Unsaved edits, starting below the top level
+    let edit = json!({ "path": "ops.md", "content": "### Deep\n\n## Shallower\n\n- [x] Done\n" });
+    let (status, body) = send(&app, request("POST", uri, Some(edit))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        body["headings"],
+        json!([
+            { "level": 3, "title": "Deep", "anchor": "deep", "line": 1, "children": [] },
+            { "level": 2, "title": "Shallower", "anchor": "shallower", "line": 3, "children": [] }
+        ])
+    );
+    assert_eq!(body["tasks"], json!([{ "index": 0, "checked": true, "line": 5, "section": 1 }]));
+    assert_eq!(body["code_blocks"], json!([]));
+}
+
+/// Send a bare HTTP/1.1 request over a Unix socket; the status and the whole
+/// response
+async fn unix_socket_get(path: &std::path::Path, uri: &str) -> (u16, String) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", uri);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, response)
+}
+
+#[actix_web::test]
+async fn server_listens_on_a_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let env = TestEnv::new("unix-socket");
+    let socket = env.dir.join("runotepad.sock");
+    // Left behind by a server that is gone
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+
+    let listener = runotepad::unix_socket::bind(&socket, 0o660).unwrap();
+    let (config, state) = (env.config.clone(), env.state.clone());
+    let server = actix_web::HttpServer::new(move || runotepad::app_factory(config.clone(), state.clone()))
+        .on_connect(runotepad::unix_socket::tag_connection)
+        .workers(1)
+        .listen_uds(listener)
+        .unwrap()
+        .run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o660);
+    // A live socket isn't taken over
+    assert!(runotepad::unix_socket::bind(&socket, 0o660).is_err());
+
+    let (status, response) = unix_socket_get(&socket, &format!("/api/auth/check?token={}", common::TOKEN)).await;
+    assert_eq!(status, 200, "{}", response);
+    assert!(response.contains("\"valid\":true"), "{}", response);
+
+    // Socket peers have no address, but are still rate limited
+    let mut statuses = Vec::new();
+    for _ in 0..12 {
+        statuses.push(unix_socket_get(&socket, "/api/workspaces?token=wrong").await.0);
+    }
+    assert_eq!(statuses.first(), Some(&401));
+    assert_eq!(statuses.last(), Some(&429));
+
+    handle.stop(true).await;
+}
+
+#[actix_web::test]
+async fn conditional_reads_of_unchanged_files_get_304() {
+    let env = TestEnv::new("etag");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let uri = "/api/workspaces/docs/branches/main/file?path=README.md";
+
+    let resp = test::call_service(&app, request("GET", uri, None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    let etag = resp.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+    assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-cache");
+
+    // Also when the response would otherwise be compressed
+    for encoding in ["identity", "gzip"] {
+        let req = request("GET", uri, None)
+            .insert_header(("If-None-Match", etag.as_str()))
+            .insert_header(("Accept-Encoding", encoding));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), 304, "{}", encoding);
+        assert_eq!(resp.headers().get("ETag").unwrap().to_str().unwrap(), etag);
+        assert!(test::read_body(resp).await.is_empty(), "{}", encoding);
+    }
+
+    // A changed file is sent again, with its new tag
+    std::fs::write(env.worktree("docs", "main").join("README.md"), "# Changed\n").unwrap();
+    let req = request("GET", uri, None).insert_header(("If-None-Match", etag.as_str()));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_ne!(resp.headers().get("ETag").unwrap().to_str().unwrap(), etag);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["content"], "# Changed\n");
+}
+
+#[actix_web::test]
+async fn worktree_files_are_served_raw_to_read_tokens() {
+    let env = TestEnv::new("raw");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    std::fs::create_dir_all(worktree.join("ops/img")).unwrap();
+    std::fs::write(worktree.join("ops/deploy.md"), "# Deploy\n").unwrap();
+    std::fs::write(worktree.join("ops/img/logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+    std::fs::write(worktree.join("ops/Makefile"), "all:\n\ttrue\n").unwrap();
+    std::fs::write(worktree.join("ops/blob"), [0xff, 0xfe, 0x00]).unwrap();
+    std::fs::write(worktree.join("ops/build.log"), "noise\n").unwrap();
+    std::fs::write(worktree.join("ops/.hidden"), "x").unwrap();
+    std::fs::write(worktree.join(".gitignore"), "*.log\n").unwrap();
+
+    let resp = test::call_service(&app, request("GET", "/raw/docs/main/ops/deploy.md", None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "text/markdown; charset=utf-8");
+    assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-cache");
+    let etag = resp.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+    assert_eq!(test::read_body(resp).await, "# Deploy\n");
+    let req = request("GET", "/raw/docs/main/ops/deploy.md", None).insert_header(("If-None-Match", etag.as_str()));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 304);
+
+    for (path, content_type) in [
+        ("ops/img/logo.png", "image/png"),
+        ("ops/Makefile", "text/plain; charset=utf-8"),
+        ("ops/blob", "application/octet-stream"),
+    ] {
+        let resp = test::call_service(&app, request("GET", &format!("/raw/docs/main/{}", path), None).to_request()).await;
+        assert_eq!(resp.status(), 200, "{}", path);
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), content_type, "{}", path);
+    }
+
+    // Directories list their children, without dotfiles or ignored files
+    let (status, body) = send(&app, request("GET", "/raw/docs/main/ops/", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "path": "ops",
+            "entries": [
+                { "name": "img", "type": "dir" },
+                { "name": "Makefile", "type": "file", "size": 11 },
+                { "name": "blob", "type": "file", "size": 3 },
+                { "name": "deploy.md", "type": "file", "size": 9 }
+            ]
+        })
+    );
+    let (status, body) = send(&app, request("GET", "/raw/docs/main/", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["path"], "");
+    assert!(body["entries"].as_array().unwrap().iter().any(|entry| entry["name"] == "ops"));
+
+    let (status, _) = send(&app, request("GET", "/raw/docs/main/ops/missing.md", None)).await;
+    assert_eq!(status, 404);
+    let (status, _) = send(&app, request("GET", "/raw/docs/main/..%2F..%2Fconfig.json", None)).await;
+    assert_eq!(status, 400);
+    let (status, body) = send(&app, request("GET", "/raw/docs/nope/ops/deploy.md", None)).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "worktree_not_found");
+
+    // A token is needed, but a read-only one does
+    let req = test::TestRequest::get().uri("/raw/docs/main/ops/deploy.md");
+    assert_eq!(test::call_service(&app, req.to_request()).await.status(), 401);
+    let (_, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "wiki", "scope": "read" })))).await;
+    let uri = format!("/raw/docs/main/ops/deploy.md?token={}", body["token"].as_str().unwrap());
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::read_body(resp).await, "# Deploy\n");
+}
+
+#[actix_web::test]
+async fn markdown_is_rendered_without_active_content() {
+    let env = TestEnv::new("render");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let hostile = r#"# Restart
+
+<script>alert(1)</script>
+<img src="x.png" onerror="alert(2)">
+<a href="JaVaScRiPt:alert(4)">four</a> <a href="&#106;avascript:alert(5)">five</a>
+<div style="background:url(javascript:alert(6))" onclick="alert(7)">styled</div>
+<iframe src="https://example.com"></iframe><object data="x.swf"></object>
+<svg><script>alert(8)</script><a xlink:href="javascript:alert(9)">svg</a></svg>
+<form action="https://example.com"><button formaction="javascript:alert(10)">go</button></form>
+<details><summary>More</summary>kept</details>
+
+![flow](<img/flow chart.png>) [next](../ops/deploy.md#rollback) [top](#restart) [out](../../../etc/passwd) [click](javascript:alert(3))
+
+```bash
+systemctl restart app
+```
+
+- [x] drained
+
+| a | b |
+|:--|--:|
+| 1 | 2 |
+"#;
+    std::fs::create_dir_all(env.worktree("docs", "main").join("runbooks")).unwrap();
+    std::fs::write(env.worktree("docs", "main").join("runbooks/restart.md"), hostile).unwrap();
+
+    let uri = "/api/v1/workspaces/docs/branches/main/file/render?path=runbooks/restart.md";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["path"], "runbooks/restart.md");
+    let html = body["html"].as_str().unwrap();
+    let lower = html.to_lowercase();
+    for active in ["<script", "alert(", "javascript", "onerror", "onclick", "style=\"background", "<iframe", "<object", "<form", "formaction"] {
+        assert!(!lower.contains(active), "{} in {}", active, html);
+    }
+    assert!(html.contains("<h1>Restart</h1>"), "{}", html);
+    assert!(html.contains("<details><summary>More</summary>kept</details>"), "{}", html);
+    assert!(html.contains(r#"src="/raw/docs/main/runbooks/img/flow%20chart.png""#), "{}", html);
+    assert!(html.contains(r#"href="/raw/docs/main/ops/deploy.md#rollback""#), "{}", html);
+    assert!(html.contains(r##"href="#restart""##), "{}", html);
+    assert!(!html.contains("passwd\""), "{}", html);
+    assert!(html.contains(r#"<code class="language-bash">systemctl restart app"#), "{}", html);
+    assert!(html.contains(r#"<input disabled="" type="checkbox" checked="">"#), "{}", html);
+    assert!(html.contains(r#"<th style="text-align: left">a</th>"#), "{}", html);
+
+    // Unsaved content, with a branch that needs encoding in the links
+    let (status, _) = send(&app, request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "fix/links" })))).await;
+    assert_eq!(status, 201);
+    let (status, body) = send(
+        &app,
+        request(
+            "POST",
+            "/api/v1/workspaces/docs/branches/fix%2Flinks/file/render",
+            Some(json!({ "content": "<b onmouseover=\"alert(1)\">![a](a.png)</b>" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["path"], Value::Null);
+    assert_eq!(body["html"], "<p><b><img src=\"/raw/docs/fix%2Flinks/a.png\" alt=\"a\"></b></p>\n");
+}
+
+#[actix_web::test]
+async fn tokens_limited_to_workspaces_only_reach_those() {
+    let env = TestEnv::new("token-workspaces");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    create_workspace(&env, &app, "ops").await;
+
+    let create = json!({ "name": "oncall", "workspaces": ["docs", "docs"] });
+    let (status, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(create))).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["workspaces"], json!(["docs"]));
+    let oncall = body["token"].as_str().unwrap().to_string();
+    let as_oncall = |method: &str, uri: &str, body: Option<Value>| {
+        let mut req = request(method, uri, body);
+        req = req.insert_header(("Authorization", format!("Bearer {}", oncall)));
+        req
+    };
+    let create = json!({ "name": "bad", "workspaces": ["../ops"] });
+    let (status, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(create))).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_workspace_name");
+
+    let (status, body) = send(&app, as_oncall("GET", "/api/v1/workspaces", None)).await;
+    assert_eq!(status, 200);
+    let names: Vec<&str> = body.as_array().unwrap().iter().map(|ws| ws["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["docs"]);
+    let (status, _) = send(&app, as_oncall("GET", "/api/v1/workspaces/docs/branches/main/files", None)).await;
+    assert_eq!(status, 200);
+    let (status, _) = send(&app, as_oncall("GET", "/raw/docs/main/README.md", None)).await;
+    assert_eq!(status, 200);
+    for (method, uri) in [
+        ("GET", "/api/v1/workspaces/ops/branches/main/files"),
+        ("GET", "/api/workspaces/%6Fps"),
+        ("DELETE", "/api/v1/workspaces/ops"),
+        ("GET", "/raw/ops/main/README.md"),
+    ] {
+        let (status, body) = send(&app, as_oncall(method, uri, None)).await;
+        assert_eq!(status, 403, "{} {}", method, uri);
+        assert_eq!(body["error"]["code"], "workspace_forbidden");
+        assert_eq!(body["error"]["details"]["workspace"], "ops");
+    }
+    let create = json!({ "name": "infra", "repo_url": env.fixture_repo(), "base_branch": "main" });
+    let (status, body) = send(&app, as_oncall("POST", "/api/v1/workspaces?sync=true", Some(create))).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["code"], "workspace_forbidden");
+
+    // It can't widen its own access or look at the whole server
+    for (method, uri, body) in [
+        ("POST", "/api/v1/auth/tokens", Some(json!({ "name": "escape" }))),
+        ("PATCH", "/api/v1/auth/tokens/oncall", Some(json!({ "workspaces": [] }))),
+        ("GET", "/api/v1/auth/tokens", None),
+        ("POST", "/api/v1/auth/rotate?name=default", None),
+        ("GET", "/api/v1/audit", None),
+        ("GET", "/api/v1/admin/export", None),
+    ] {
+        let (status, body) = send(&app, as_oncall(method, uri, body)).await;
+        assert_eq!(status, 403, "{} {}", method, uri);
+        assert_eq!(body["error"]["code"], "workspace_forbidden");
+    }
+    let (status, body) = send(&app, as_oncall("GET", "/api/v1/auth/check", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["workspaces"], json!(["docs"]));
+
+    // Admins edit the list
+    let update = json!({ "workspaces": ["docs", "ops"] });
+    let (status, body) = send(&app, request("PATCH", "/api/v1/auth/tokens/oncall", Some(update))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["workspaces"], json!(["docs", "ops"]));
+    let (status, _) = send(&app, as_oncall("GET", "/api/v1/workspaces/ops/branches/main/files", None)).await;
+    assert_eq!(status, 200);
+    let (status, _) = send(&app, request("PATCH", "/api/v1/auth/tokens/nobody", Some(json!({ "workspaces": [] })))).await;
+    assert_eq!(status, 404);
+    let (_, body) = send(&app, request("GET", "/api/v1/auth/tokens", None)).await;
+    let listed = body.as_array().unwrap().iter().find(|token| token["name"] == "oncall").unwrap();
+    assert_eq!(listed["workspaces"], json!(["docs", "ops"]));
+
+    // Denials are audited; the log is written in the background
+    let mut denied = Vec::new();
+    for _ in 0..50 {
+        let (_, body) = send(&app, request("GET", "/api/v1/audit", None)).await;
+        denied = body.as_array().unwrap().iter().filter(|entry| entry["action"] == "workspace.denied").cloned().collect();
+        if denied.len() == 11 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(denied.len(), 11, "{:?}", denied);
+    assert!(denied.iter().all(|entry| entry["token"] == "oncall"));
+    assert_eq!(denied[0]["summary"]["workspace"], "ops");
+}
+
+#[actix_web::test]
+async fn tokens_limited_to_workspaces_miss_other_workspaces_runs_jobs_and_events() {
+    let env = TestEnv::new("token-workspace-runs");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    create_workspace(&env, &app, "ops").await;
+
+    let create = json!({ "name": "oncall", "workspaces": ["docs"] });
+    let (status, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(create))).await;
+    assert_eq!(status, 201, "{}", body);
+    let oncall = body["token"].as_str().unwrap().to_string();
+    let as_oncall = |method: &str, uri: &str, body: Option<Value>| {
+        request(method, uri, body).insert_header(("Authorization", format!("Bearer {}", oncall)))
+    };
+
+    // What the admin token has in ops: a schedule, a block run and a job
+    let content = "# Ops\n\n```bash\necho ops\n```\n";
+    let uri = "/api/v1/workspaces/ops/branches/main/file?path=runbook.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+    let schedule = json!({ "workspace": "ops", "branch": "main", "path": "runbook.md", "cron": "0 0 1 1 *" });
+    let (status, schedule) = send(&app, request("POST", "/api/v1/schedules", Some(schedule))).await;
+    assert_eq!(status, 201, "{}", schedule);
+    let schedule_id = schedule["id"].as_str().unwrap();
+    let run = json!({ "path": "runbook.md", "block_index": 0 });
+    let (status, run) = send(&app, request("POST", "/api/v1/workspaces/ops/branches/main/blocks/run", Some(run))).await;
+    assert_eq!(status, 200, "{}", run);
+    let execution_id = run["execution_id"].as_str().unwrap();
+    let clone = json!({ "name": "infra", "repo_url": env.fixture_repo(), "base_branch": "main" });
+    let (status, job) = send(&app, request("POST", "/api/v1/workspaces", Some(clone))).await;
+    assert_eq!(status, 202, "{}", job);
+    let job_id = job["id"].as_str().unwrap();
+    wait_for_job(&app, job_id).await;
+
+    let approval = json!({
+        "workspace": "ops", "branch": "main", "path": "runbook.md", "block_index": 0, "block_hash": run["block_hash"]
+    });
+    let schedule = json!({ "workspace": "ops", "branch": "main", "path": "runbook.md", "cron": "* * * * *" });
+    for (method, uri, body, workspace) in [
+        ("POST", "/api/v1/schedules".to_string(), Some(schedule), "ops"),
+        ("POST", format!("/api/v1/schedules/{}/run", schedule_id), None, "ops"),
+        ("DELETE", format!("/api/v1/schedules/{}", schedule_id), None, "ops"),
+        ("POST", "/api/v1/approvals".to_string(), Some(approval), "ops"),
+        ("GET", format!("/api/v1/executions/{}", execution_id), None, "ops"),
+        ("GET", format!("/api/v1/jobs/{}", job_id), None, "infra"),
+        ("POST", format!("/api/v1/jobs/{}/cancel", job_id), None, "infra"),
+        ("GET", "/api/v1/events?workspace=ops".to_string(), None, "ops"),
+    ] {
+        let (status, body) = send(&app, as_oncall(method, &uri, body)).await;
+        assert_eq!(status, 403, "{} {}: {}", method, uri, body);
+        assert_eq!(body["error"]["code"], "workspace_forbidden");
+        assert_eq!(body["error"]["details"]["workspace"], workspace);
+    }
+
+    // Lists leave them out
+    let (status, body) = send(&app, as_oncall("GET", "/api/v1/schedules", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["schedules"], json!([]));
+    let (_, body) = send(&app, request("GET", "/api/v1/schedules", None)).await;
+    assert_eq!(body["schedules"].as_array().unwrap().len(), 1);
+    let (status, body) = send(&app, as_oncall("GET", "/api/v1/jobs", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!([]));
+    let (_, body) = send(&app, request("GET", "/api/v1/jobs", None)).await;
+    assert_eq!(body[0]["id"], job_id);
+
+    // And so does the event stream
+    let resp = test::call_service(&app, as_oncall("GET", "/api/v1/events", None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    for workspace in ["ops", "docs"] {
+        let branch = json!({ "branch_name": "rotate" });
+        let uri = format!("/api/v1/workspaces/{}/branches", workspace);
+        let (status, body) = send(&app, request("POST", &uri, Some(branch))).await;
+        assert_eq!(status, 201, "{}", body);
+    }
+    let events = read_events(resp.into_body(), |event| event["type"] == "branch_created").await;
+    assert!(events.iter().all(|e| e["workspace"] == "docs"), "{:?}", events);
+}
+
+#[actix_web::test]
+async fn responses_are_compressed_unless_disabled() {
+    let env = TestEnv::new("compress");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let uri = "/api/workspaces/docs/branches/main/files";
+
+    let req = request("GET", uri, None).insert_header(("Accept-Encoding", "gzip"));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "gzip");
+
+    // Event streams would be held back in the encoder
+    let req = request("GET", "/api/events", None).insert_header(("Accept-Encoding", "gzip"));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "identity");
+
+    let env = TestEnv::with_config("compress-off", json!({ "compress_responses": false }));
+    let app = env.app().await;
+    let req = request("GET", "/api/workspaces", None).insert_header(("Accept-Encoding", "gzip"));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("Content-Encoding").is_none());
+}
+
+#[actix_web::test]
+async fn ui_files_are_cached_by_whether_their_names_are_hashed() {
+    let ui = std::env::temp_dir()
+        .join(format!("runotepad-api-test-{}-ui-cache", std::process::id()))
+        .join("ui");
+    let env = TestEnv::with_config("ui-cache", json!({ "static_dir": ui }));
+    std::fs::create_dir_all(&ui).unwrap();
+    std::fs::write(ui.join("index.html"), "<div id=\"app\"></div>").unwrap();
+    std::fs::write(ui.join("bundle.3f9a1c2e.js"), "console.log(1)").unwrap();
+    let app = env.app().await;
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-cache");
+    let etag = resp.headers().get("ETag").unwrap().clone();
+
+    let req = test::TestRequest::get().uri("/").insert_header(("If-None-Match", etag));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 304);
+    assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-cache");
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/bundle.3f9a1c2e.js").to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("Cache-Control").unwrap(),
+        "public, max-age=31536000, immutable"
+    );
+
+    // API responses are left alone
+    let resp = test::call_service(&app, request("GET", "/api/workspaces", None).to_request()).await;
+    assert!(resp.headers().get("Cache-Control").is_none());
+}
+
+#[actix_web::test]
+async fn server_tuning_is_resolved_and_validated() {
+    use runotepad::config::{ConfigManager, ConfigOverrides};
+
+    let env = TestEnv::with_config(
+        "server-tuning",
+        json!({ "workers": 3, "keep_alive_secs": 0, "max_connections": 100 }),
+    );
+    let tuning = env.config.server_tuning();
+    assert_eq!(tuning.workers, 3);
+    assert_eq!(tuning.keep_alive, None);
+    assert_eq!(tuning.client_request_timeout, Duration::from_secs(5));
+    assert_eq!(tuning.max_connections, 100);
+
+    // The command line wins over the file
+    let overrides = ConfigOverrides {
+        config_file: Some(env.dir.join("config.json")),
+        workspace_dir: Some(env.dir.join("workspaces")),
+        workers: Some(2),
+        keep_alive_secs: Some(30),
+        ..Default::default()
+    };
+    let tuning = ConfigManager::new(overrides.clone()).unwrap().server_tuning();
+    assert_eq!(tuning.workers, 2);
+    assert_eq!(tuning.keep_alive, Some(Duration::from_secs(30)));
+
+    for overrides in [
+        ConfigOverrides { workers: Some(0), ..overrides.clone() },
+        ConfigOverrides { client_request_timeout_secs: Some(0), ..overrides.clone() },
+        ConfigOverrides { max_connections: Some(0), ..overrides.clone() },
+    ] {
+        let error = ConfigManager::new(overrides).err().unwrap().to_string();
+        assert!(error.contains("must be at least 1"), "{}", error);
+    }
+}
+
+#[actix_web::test]
+async fn expensive_reads_are_rate_limited_per_token() {
+    let env = TestEnv::with_config(
+        "rate-limit",
+        json!({ "rate_limits": { "expensive_reads_per_minute": 3, "mutations_per_minute": 0 } }),
+    );
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let files = "/api/v1/workspaces/docs/branches/main/files";
+
+    for _ in 0..3 {
+        let (status, body) = send(&app, request("GET", files, None)).await;
+        assert_eq!(status, 200, "{}", body);
+    }
+    let resp = test::call_service(&app, request("GET", files, None).to_request()).await;
+    assert_eq!(resp.status(), 429);
+    let retry_after: u64 = resp.headers().get("Retry-After").unwrap().to_str().unwrap().parse().unwrap();
+    assert!((1..=21).contains(&retry_after), "{}", retry_after);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"]["code"], "rate_limited");
+
+    // Legacy paths share the budget; cheap reads have their own
+    let (status, _) = send(&app, request("GET", "/api/workspaces/docs/branches/main/search?q=x", None)).await;
+    assert_eq!(status, 429);
+    let (status, _) = send(&app, request("GET", "/api/v1/workspaces/docs/branches/main/file?path=README.md", None)).await;
+    assert_eq!(status, 200);
+
+    // Other tokens have their own budgets; mutations aren't limited here
+    let (status, body) = send(&app, request("POST", "/api/v1/auth/tokens", Some(json!({ "name": "dashboard" })))).await;
+    assert_eq!(status, 201, "{}", body);
+    let req = test::TestRequest::get()
+        .uri(files)
+        .insert_header(("Authorization", format!("Bearer {}", body["token"].as_str().unwrap())));
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 200, "{}", body);
+}
+
+/// Paths of a file listing, depth first
+fn listed_paths(files: &Value) -> Vec<String> {
+    let mut paths = Vec::new();
+    for entry in files.as_array().unwrap() {
+        paths.push(entry["path"].as_str().unwrap().to_string());
+        if let Some(children) = entry.get("children") {
+            paths.extend(listed_paths(children));
+        }
+    }
+    paths
+}
+
+#[actix_web::test]
+async fn listings_are_cached_until_the_worktree_changes() {
+    let env = TestEnv::new("listing-cache");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    let files_uri = "/api/v1/workspaces/docs/branches/main/files";
+    let files = || async { listed_paths(&send(&app, request("GET", files_uri, None)).await.1) };
+
+    let listed = files().await;
+    assert!(listed.contains(&"README.md".to_string()), "{:?}", listed);
+
+    // Written behind the server's back: the cached tree is served until the
+    // watcher reports the change, unless asked for a fresh one
+    std::fs::write(worktree.join("outside.md"), "# Outside\n").unwrap();
+    assert_eq!(files().await, listed);
+    let (status, fresh) = send(&app, request("GET", &format!("{}?fresh=true", files_uri), None)).await;
+    assert_eq!(status, 200);
+    assert!(listed_paths(&fresh).contains(&"outside.md".to_string()));
+
+    std::fs::write(worktree.join("watched.md"), "# Watched\n").unwrap();
+    let mut seen = false;
+    for _ in 0..100 {
+        if files().await.contains(&"watched.md".to_string()) {
+            seen = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(seen, "watcher never invalidated the cached tree");
+
+    // Cached branch lists are checked against the git dir, so branches and
+    // checkouts made in a terminal show up at once
+    let branches_uri = "/api/v1/workspaces/docs/branches";
+    let (_, branches) = send(&app, request("GET", branches_uri, None)).await;
+    let (_, cached) = send(&app, request("GET", branches_uri, None)).await;
+    assert_eq!(cached, branches);
+    git(&["branch", "outside"], &worktree);
+    let (_, listed) = send(&app, request("GET", branches_uri, None)).await;
+    assert!(listed.as_array().unwrap().iter().any(|b| b["name"] == "outside"), "{}", listed);
+    let (status, fresh) = send(&app, request("GET", &format!("{}?fresh=true", branches_uri), None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(fresh, listed);
+}
+
+#[actix_web::test]
+async fn listings_are_invalidated_by_every_change() {
+    let env = TestEnv::new("listing-invalidation");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let base = "/api/v1/workspaces/docs/branches/main";
+    let files_uri = format!("{}/files", base);
+    let files = || async { listed_paths(&send(&app, request("GET", &files_uri, None)).await.1) };
+    let branches = || async { send(&app, request("GET", "/api/v1/workspaces/docs/branches", None)).await.1 };
+    let head = |branches: &Value| {
+        let main = branches.as_array().unwrap().iter().find(|b| b["name"] == "main").unwrap();
+        main["head_commit"].clone()
+    };
+    let has = |files: &[String], path: &str| files.iter().any(|p| p == path);
+
+    // Pull brings in upstream files and moves the head
+    let before = head(&branches().await);
+    assert!(!has(&files().await, "pulled.md"));
+    let upstream = env.dir.join("fixture-src");
+    std::fs::write(upstream.join("pulled.md"), "# Upstream\n").unwrap();
+    git(&["add", "pulled.md"], &upstream);
+    git(&["commit", "-q", "-m", "Upstream change"], &upstream);
+    git(&["push", "-q", env.fixture_repo().to_str().unwrap(), "main"], &upstream);
+    let (status, body) = send(&app, request("POST", &format!("{}/pull?sync=true", base), None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(has(&files().await, "pulled.md"));
+    assert_ne!(head(&branches().await), before);
+
+    // Save
+    assert!(!has(&files().await, "new.md"));
+    let (status, _) = send(&app, request("PUT", &format!("{}/file?path=new.md", base), Some(json!({ "content": "# New\n" })))).await;
+    assert_eq!(status, 200);
+    assert!(has(&files().await, "new.md"));
+
+    // Commit
+    let before = head(&branches().await);
+    let commit = json!({ "message": "Add new", "files": ["new.md"] });
+    let (status, body) = send(&app, request("POST", &format!("{}/commit?override=true", base), Some(commit))).await;
+    assert_eq!(status, 200, "{}", body);
+    let after = head(&branches().await);
+    assert_ne!(after, before);
+    assert_eq!(after, body["commit_id"]);
+
+    // Delete to the trash, then restore
+    let (status, body) = send(&app, request("DELETE", &format!("{}/file?path=new.md", base), None)).await;
+    assert_eq!(status, 200);
+    assert!(!has(&files().await, "new.md"));
+    let restore = json!({ "id": body["trash"]["id"] });
+    let (status, _) = send(&app, request("POST", &format!("{}/trash/restore", base), Some(restore))).await;
+    assert_eq!(status, 200);
+    assert!(has(&files().await, "new.md"));
+
+    // Delete for good, then restore from history
+    let (status, _) = send(&app, request("DELETE", &format!("{}/file?path=new.md&permanent=true", base), None)).await;
+    assert_eq!(status, 200);
+    assert!(!has(&files().await, "new.md"));
+    let (status, _) = send(&app, request("POST", &format!("{}/file/restore", base), Some(json!({ "path": "new.md" })))).await;
+    assert_eq!(status, 200);
+    assert!(has(&files().await, "new.md"));
+
+    // Worktree create and delete
+    let names = |branches: Value| -> Vec<(String, bool)> {
+        branches
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| (b["name"].as_str().unwrap().to_string(), b["is_worktree"].as_bool().unwrap()))
+            .collect()
+    };
+    assert!(!names(branches().await).contains(&("feature".to_string(), true)));
+    let create = json!({ "branch_name": "feature" });
+    let (status, _) = send(&app, request("POST", "/api/v1/workspaces/docs/branches", Some(create))).await;
+    assert_eq!(status, 201);
+    assert!(names(branches().await).contains(&("feature".to_string(), true)));
+    let feature_files = "/api/v1/workspaces/docs/branches/feature/files";
+    let (status, _) = send(&app, request("GET", feature_files, None)).await;
+    assert_eq!(status, 200);
+    let (status, _) = send(&app, request("DELETE", "/api/v1/workspaces/docs/branches/feature", None)).await;
+    assert_eq!(status, 200);
+    assert!(!names(branches().await).contains(&("feature".to_string(), true)));
+    let (status, _) = send(&app, request("GET", feature_files, None)).await;
+    assert_eq!(status, 404);
+}
+
+#[actix_web::test]
+async fn bad_request_bodies_get_json_errors() {
+    use actix_web::{web, App};
+    use runotepad::extract;
+
+    let env = TestEnv::with_config("bodies", json!({ "max_request_bytes": 1000 }));
+    let app = env.app().await;
+    let post = |uri: &str, body: &str| {
+        request("POST", uri, None)
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body.to_string())
+    };
+
+    let (status, body) = send(&app, post("/api/workspaces", r#"{"name": "docs", "#)).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["code"], "invalid_body");
+
+    // The field is named for values of the wrong type as for missing ones
+    let bodies = [
+        (r#"{"name": 5, "repo_url": "https://example.com/docs.git"}"#, "name"),
+        (r#"{"repo_url": "https://example.com/docs.git"}"#, "name"),
+        (r#"{"name": "docs", "repo_url": {"url": "x"}}"#, "repo_url"),
+    ];
+    for (payload, field) in bodies {
+        let (status, body) = send(&app, post("/api/workspaces", payload)).await;
+        assert_eq!(status, 400, "{}", body);
+        assert_eq!(body["error"]["code"], "invalid_body");
+        assert_eq!(body["error"]["details"]["field"], field, "{}", payload);
+    }
+    let payload = r#"{"operation": "prune", "workspaces": ["docs", {"name": "ops"}]}"#;
+    let (status, body) = send(&app, post("/api/admin/workspaces/bulk", payload)).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["details"]["field"], "workspaces[1]");
+
+    let payload = json!({ "name": "docs", "repo_url": "x".repeat(1000) }).to_string();
+    let (status, body) = send(&app, post("/api/workspaces", &payload)).await;
+    assert_eq!(status, 413);
+    assert_eq!(body["error"]["code"], "body_too_large");
+    assert!(env.config.get_workspaces().is_empty());
+
+    // Raw bodies over the limit get the same error body
+    let raw = test::init_service(
+        App::new()
+            .wrap(extract::payload_errors())
+            .app_data(extract::payload_config(16))
+            .route("/", web::post().to(|body: String| async move { body })),
+    )
+    .await;
+    let (status, body) = send(&raw, test::TestRequest::post().uri("/").set_payload("x".repeat(17))).await;
+    assert_eq!(status, 413);
+    assert_eq!(body["error"]["code"], "body_too_large");
+    let (status, body) = send(&raw, test::TestRequest::post().uri("/").set_payload("x".repeat(16))).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, "x".repeat(16));
+}
+
+#[actix_web::test]
+async fn raw_file_bodies_stream_within_the_size_limits() {
+    let env = TestEnv::with_config("raw-file", json!({ "max_read_bytes": 2000, "max_save_bytes": 1000 }));
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    let uri = "/api/v1/workspaces/docs/branches/main/file/raw?path=reports/big.md";
+    let put = |content: &str| request("PUT", uri, None).set_payload(content.to_string());
+
+    // Quotes and newlines would cost extra bytes escaped in JSON
+    let content = "\"quoted\"\n".repeat(100);
+    let hash = runotepad::blocks::content_hash(&content);
+    let resp = test::call_service(&app, put(&content).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("ETag").unwrap().to_str().unwrap(), format!("\"{}\"", hash));
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["bytes"], content.len());
+    assert_eq!(body["file_hash"], hash.as_str());
+    assert_eq!(std::fs::read_to_string(worktree.join("reports/big.md")).unwrap(), content);
+
+    let resp = test::call_service(&app, request("GET", uri, None).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "text/markdown; charset=utf-8");
+    let etag = resp.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+    assert_eq!(etag, format!("\"{}\"", hash));
+    assert_eq!(test::read_body(resp).await, content.as_bytes());
+    let req = request("GET", uri, None).insert_header(("If-None-Match", etag.as_str()));
+    let resp = test::call_service(&app, req.to_request()).await;
+    assert_eq!(resp.status(), 304);
+
+    // If-Match only lets a save through against the revision read
+    let req = put("# Mine\n").insert_header(("If-Match", "\"0000\""));
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 412);
+    assert_eq!(body["error"]["code"], "file_changed");
+    assert_eq!(body["error"]["details"]["file_hash"], hash.as_str());
+    let req = put("# Mine\n").insert_header(("If-Match", etag.as_str()));
+    let (status, body) = send(&app, req).await;
+    assert_eq!(status, 200, "{}", body);
+    let req = put("# Theirs\n").insert_header(("If-Match", etag.as_str()));
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 412);
+    let missing = "/api/v1/workspaces/docs/branches/main/file/raw?path=missing.md";
+    let req = request("PUT", missing, None).set_payload("# New\n").insert_header(("If-Match", "*"));
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, 412);
+    assert!(!worktree.join("missing.md").exists());
+    assert_eq!(std::fs::read_to_string(worktree.join("reports/big.md")).unwrap(), "# Mine\n");
+
+    // The same limits and path rules as the JSON endpoints
+    let (status, body) = send(&app, put(&"x".repeat(1001))).await;
+    assert_eq!(status, 413);
+    assert_eq!(body["error"]["code"], "body_too_large");
+    assert_eq!(std::fs::read_to_string(worktree.join("reports/big.md")).unwrap(), "# Mine\n");
+    let leftovers: Vec<_> = std::fs::read_dir(worktree.join("reports")).unwrap().collect();
+    assert_eq!(leftovers.len(), 1, "temporary files left behind: {:?}", leftovers);
+    std::fs::write(worktree.join("reports/big.md"), "x".repeat(2001)).unwrap();
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 413);
+    assert_eq!(body["error"]["code"], "file_too_large");
+    for method in ["GET", "PUT"] {
+        let escape = "/api/v1/workspaces/docs/branches/main/file/raw?path=../../config.json";
+        let (status, body) = send(&app, request(method, escape, None).set_payload("{}")).await;
+        assert_eq!(status, 400, "{}", method);
+        assert_eq!(body["error"]["code"], "invalid_path");
+    }
+}
+
+/// Add worktrees `wt00`, `wt01`, ... to workspace `docs` with git directly
+fn add_worktrees(env: &TestEnv, count: usize) {
+    let repo = env.config.repo_path("docs");
+    for i in 0..count {
+        let name = format!("wt{:02}", i);
+        let dir = env.config.worktrees_path("docs").join(&name);
+        git(&["worktree", "add", "-q", "-b", &name, dir.to_str().unwrap(), "main"], &repo);
+    }
+}
+
+#[actix_web::test]
+async fn branch_stats_are_collected_concurrently_within_a_timeout() {
+    let env = TestEnv::new("branch-stats");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    add_worktrees(&env, 2);
+    let worktrees = env.config.worktrees_path("docs");
+    std::fs::write(worktrees.join("wt00/README.md"), "# Changed\n").unwrap();
+    git(&["commit", "-q", "--allow-empty", "-m", "Ahead"], &worktrees.join("wt01"));
+
+    let (status, branches) = send(&app, request("GET", "/api/v1/workspaces/docs/branches", None)).await;
+    assert_eq!(status, 200);
+    let branch = |name: &str| branches.as_array().unwrap().iter().find(|b| b["name"] == name).unwrap().clone();
+    let stats = |b: Value| (b["dirty"].clone(), b["ahead"].clone(), b["behind"].clone(), b["stats_timeout"].clone());
+    assert_eq!(stats(branch("main")), (json!(false), json!(0), json!(0), json!(false)));
+    assert_eq!(stats(branch("wt00")), (json!(true), json!(0), json!(0), json!(false)));
+    assert_eq!(stats(branch("wt01")), (json!(false), json!(1), json!(0), json!(false)));
+    assert_eq!(branch("wt01")["checked_out_branch"], "wt01");
+
+    // Worktrees whose stats take too long are listed without them
+    let env = TestEnv::with_config("branch-stats-timeout", json!({ "branch_stats_timeout_secs": 0 }));
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, branches) = send(&app, request("GET", "/api/v1/workspaces/docs/branches", None)).await;
+    assert_eq!(status, 200);
+    let main = &branches[0];
+    assert_eq!(main["name"], "main");
+    assert_eq!(main["is_worktree"], true);
+    assert_eq!(main["stats_timeout"], true);
+    assert_eq!(main["head_commit"], Value::Null);
+    assert_eq!(main["dirty"], Value::Null);
+}
+
+/// fsmonitor hook `git status` runs in every worktree. The first call in
+/// each `wt*` worktree pairs up with another one's, waiting up to 10s for
+/// it, and writes how many were running into `concurrency.log` before both
+/// go on.
+const PAIRING_HOOK: &str = r#"#!/bin/sh
+dir=$(dirname "$0")
+case "$(basename "$PWD")" in wt*) ;; *) exit 1 ;; esac
+[ -e "$dir/met-$(basename "$PWD")" ] && exit 1
+touch "$dir/met-$(basename "$PWD")"
+echo + >> "$dir/hook.log"
+ticket=$(grep -c + "$dir/hook.log")
+i=0
+while [ $((ticket % 2)) -eq 1 ] && [ "$(grep -c + "$dir/hook.log")" -le "$ticket" ] && [ "$i" -lt 100 ]; do
+    i=$((i + 1))
+    sleep 0.1
+done
+echo $(( $(grep -c + "$dir/hook.log") - $(grep -c -- - "$dir/hook.log") )) >> "$dir/concurrency.log"
+# Stay until the partner has counted too
+while [ "$(wc -l < "$dir/concurrency.log")" -lt $(( (ticket + 1) / 2 * 2 )) ] && [ "$i" -lt 200 ]; do
+    i=$((i + 1))
+    sleep 0.1
+done
+echo - >> "$dir/hook.log"
+exit 1
+"#;
+
+#[actix_web::test]
+async fn branch_stats_are_collected_at_most_workers_at_a_time() {
+    let env = TestEnv::with_config(
+        "branch-stats-workers",
+        json!({ "branch_stats_workers": 2, "branch_stats_timeout_secs": 30 }),
+    );
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    add_worktrees(&env, 4);
+
+    let hook = env.dir.join("hook/fsmonitor");
+    std::fs::create_dir_all(hook.parent().unwrap()).unwrap();
+    std::fs::write(&hook, PAIRING_HOOK).unwrap();
+    std::fs::set_permissions(&hook, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    git(&["config", "core.fsmonitor", hook.to_str().unwrap()], &env.config.repo_path("docs"));
+
+    let (status, branches) = send(&app, request("GET", "/api/v1/workspaces/docs/branches?fresh=true", None)).await;
+    assert_eq!(status, 200);
+    for branch in branches.as_array().unwrap() {
+        assert_eq!(branch["stats_timeout"], false, "{}", branch);
+        assert_eq!(branch["dirty"], false, "{}", branch);
+    }
+
+    // Each worktree's stats were collected alongside another one's, never
+    // two others'
+    let log = std::fs::read_to_string(env.dir.join("hook/concurrency.log")).unwrap();
+    assert_eq!(log.lines().collect::<Vec<_>>(), ["2", "2", "2", "2"]);
+}
+
+#[actix_web::test]
+async fn workspaces_over_their_quota_refuse_writes() {
+    // A clone that comes out over the quota is removed again
+    let env = TestEnv::with_config("quota-clone", json!({ "max_workspace_size_bytes": 1 }));
+    let app = env.app().await;
+    let repo = env.fixture_repo();
+    let (status, body) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces?sync=true",
+            Some(json!({ "name": "docs", "repo_url": repo, "base_branch": "main" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 507, "{}", body);
+    assert_eq!(body["error"]["code"], "quota_exceeded");
+    assert_eq!(body["error"]["details"]["quota_bytes"], 1);
+    let (status, _) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(status, 404);
+    assert!(!env.config.workspace_path("docs").exists());
+
+    let quota: u64 = 50 * 1024 * 1024;
+    let env = TestEnv::with_config("quota", json!({ "max_workspace_size_bytes": quota }));
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let (status, detail) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(detail["quota"]["quota_bytes"], quota);
+    assert_eq!(detail["quota"]["exceeded"], false);
+    assert!(detail["quota"]["used_bytes"].as_u64().unwrap() > 0);
+
+    let save = |path: &str| {
+        request(
+            "PUT",
+            &format!("/api/workspaces/docs/branches/main/file?path={}", path),
+            Some(json!({ "content": "# Notes\n" })),
+        )
+    };
+    let (status, _) = send(&app, save("notes.md")).await;
+    assert_eq!(status, 200);
+
+    // Files put there some other way count once the workspace is rescanned;
+    // a sparse file takes its length without taking the disk space
+    let big = env.worktree("docs", "main").join("big.bin");
+    std::fs::File::create(&big).unwrap().set_len(quota).unwrap();
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/verify", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["quota"]["exceeded"], true);
+    let (_, detail) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(detail["quota"]["exceeded"], true);
+    assert!(detail["quota"]["used_bytes"].as_u64().unwrap() > quota);
+
+    let (status, body) = send(&app, save("more.md")).await;
+    assert_eq!(status, 507, "{}", body);
+    assert_eq!(body["error"]["code"], "quota_exceeded");
+    assert_eq!(body["error"]["details"]["quota_bytes"], quota);
+    let raw = request("PUT", "/api/workspaces/docs/branches/main/file/raw?path=more.md", None).set_payload("# More\n");
+    let (status, _) = send(&app, raw).await;
+    assert_eq!(status, 507);
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "feature" }))),
+    )
+    .await;
+    assert_eq!(status, 507, "{}", body);
+    assert!(!env.worktree("docs", "feature").exists());
+
+    // Reads still work, and freeing the space lifts the stop
+    let (status, _) = send(&app, request("GET", "/api/workspaces/docs/branches/main/file?path=notes.md", None)).await;
+    assert_eq!(status, 200);
+    std::fs::remove_file(&big).unwrap();
+    send(&app, request("POST", "/api/workspaces/docs/verify", None)).await;
+    let (status, _) = send(&app, save("more.md")).await;
+    assert_eq!(status, 200);
+}
+
+#[actix_web::test]
+async fn forks_push_to_one_remote_and_rebase_from_another() {
+    let env = TestEnv::new("remotes");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let origin = env.fixture_repo();
+    let fork = env.dir.join("fork.git");
+    git(&["clone", "-q", "--bare", origin.to_str().unwrap(), fork.to_str().unwrap()], &env.dir);
+
+    let (status, body) = send(&app, request("GET", "/api/workspaces/docs/remotes", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["remotes"][0]["name"], "origin");
+    assert_eq!(body["push_remote"], "origin");
+    assert_eq!(body["upstream_remote"], "origin");
+
+    let add = |name: &str, url: &str| {
+        request("POST", "/api/workspaces/docs/remotes", Some(json!({ "name": name, "url": url })))
+    };
+    let (status, body) = send(&app, add("fork", fork.to_str().unwrap())).await;
+    assert_eq!(status, 201, "{}", body);
+    let names: Vec<&str> = body["remotes"].as_array().unwrap().iter().map(|r| r["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["fork", "origin"]);
+    assert_eq!(body["remotes"][0]["url"], fork.to_str().unwrap());
+    for (name, url, status, code) in [
+        ("fork", fork.to_str().unwrap(), 409, "remote_exists"),
+        ("-f", fork.to_str().unwrap(), 400, "invalid_remote_name"),
+        ("other", "--upload-pack=touch", 400, "invalid_repo_url"),
+        ("other", env.dir.join("missing.git").to_str().unwrap(), 400, "remote_unreachable"),
+    ] {
+        let (got, body) = send(&app, add(name, url)).await;
+        assert_eq!((got, body["error"]["code"].as_str().unwrap()), (status, code), "{}", body);
+    }
+
+    // Push to the fork, keep rebasing onto the project
+    let patch = |body: Value| request("PATCH", "/api/workspaces/docs", Some(body));
+    let (status, body) = send(&app, patch(json!({ "push_remote": "nowhere" }))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "remote_not_found"));
+    let (status, body) = send(&app, patch(json!({ "push_remote": "fork" }))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["push_remote"], "fork");
+    assert_eq!(body["upstream_remote"], "origin");
+    assert_eq!(env.config.get_workspace("docs").unwrap().push_remote.as_deref(), Some("fork"));
+
+    let worktree = env.worktree("docs", "main");
+    git(&["commit", "-q", "--allow-empty", "-m", "Local change"], &worktree);
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/branches/main/push?sync=true&override=true", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["remote"], "fork");
+    assert_eq!(git(&["log", "-1", "--format=%s", "main"], &fork), "Local change");
+    assert_eq!(git(&["log", "-1", "--format=%s", "main"], &origin), "Initial runbooks");
+
+    // Meanwhile the project moves on
+    let upstream_work = env.dir.join("upstream-work");
+    git(&["clone", "-q", origin.to_str().unwrap(), upstream_work.to_str().unwrap()], &env.dir);
+    git(&["commit", "-q", "--allow-empty", "-m", "Upstream change"], &upstream_work);
+    git(&["push", "-q", "origin", "main"], &upstream_work);
+
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/branches/main/rebase?sync=true", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["remote"], "origin");
+    assert_eq!(git(&["log", "--format=%s", "-2"], &worktree), "Local change\nUpstream change");
+
+    // A request can pick another remote; the fork lacks the upstream change
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches/main/push?sync=true&remote=origin&override=true", None),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(git(&["log", "-1", "--format=%s", "main"], &origin), "Local change");
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches/main/pull?sync=true&remote=nowhere", None),
+    )
+    .await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "remote_not_found"));
+
+    // Remotes the workspace uses can't be removed
+    let (status, body) = send(&app, request("DELETE", "/api/workspaces/docs/remotes/fork", None)).await;
+    assert_eq!(status, 409, "{}", body);
+    assert_eq!(body["error"]["code"], "remote_in_use");
+    assert_eq!(body["error"]["details"]["preferences"], json!(["push_remote"]));
+    let (status, _) = send(&app, patch(json!({ "push_remote": "origin" }))).await;
+    assert_eq!(status, 200);
+    assert_eq!(env.config.get_workspace("docs").unwrap().push_remote, None);
+    let (status, body) = send(&app, request("DELETE", "/api/workspaces/docs/remotes/fork", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["remotes"].as_array().unwrap().len(), 1);
+    let (status, _) = send(&app, request("DELETE", "/api/workspaces/docs/remotes/fork", None)).await;
+    assert_eq!(status, 404);
+    let (status, _) = send(&app, request("DELETE", "/api/workspaces/docs/remotes/origin", None)).await;
+    assert_eq!(status, 409);
+}
+
+#[actix_web::test]
+async fn protected_branches_refuse_direct_changes() {
+    let env = TestEnv::new("protected");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, _) = send(
+        &app,
+        request("PUT", "/api/workspaces/docs/branches/main/file?path=notes.md", Some(json!({ "content": "# Notes\n" }))),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    // The base branch is protected unless the request overrides it
+    let commit = |branch: &str, query: &str| {
+        request(
+            "POST",
+            &format!("/api/workspaces/docs/branches/{}/commit{}", branch, query),
+            Some(json!({ "message": "Add notes", "files": ["notes.md"] })),
+        )
+    };
+    let (status, body) = send(&app, commit("main", "")).await;
+    assert_eq!(status, 403, "{}", body);
+    assert_eq!(body["error"]["code"], "protected_branch");
+    assert_eq!(body["error"]["details"]["branch"], "main");
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/branches/main/push?sync=true", None)).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (403, "protected_branch"));
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches/main/rename", Some(json!({ "new_name": "trunk" }))),
+    )
+    .await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (403, "protected_branch"));
+    let (status, body) = send(&app, request("DELETE", "/api/workspaces/docs/branches/main", None)).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (403, "protected_branch"));
+    assert!(env.worktree("docs", "main").exists());
+    let (status, body) = send(&app, commit("main", "?override=true")).await;
+    assert_eq!(status, 200, "{}", body);
+
+    // The list is the workspace's to edit; new branches under a protected
+    // name can still be created
+    let patch = |body: Value| request("PATCH", "/api/workspaces/docs", Some(body));
+    let (status, body) = send(&app, patch(json!({ "protected_branches": ["bad..name"] }))).await;
+    assert_eq!(status, 400, "{}", body);
+    let (status, body) = send(&app, patch(json!({ "protected_branches": ["release", "release"] }))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["protected_branches"], json!(["release"]));
+    assert_eq!(
+        env.config.get_workspace("docs").unwrap().protected_branches,
+        Some(vec!["release".to_string()])
+    );
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "release" }))),
+    )
+    .await;
+    assert_eq!(status, 201, "{}", body);
+    std::fs::write(env.worktree("docs", "release").join("notes.md"), "# Release notes\n").unwrap();
+    let (status, body) = send(&app, commit("release", "")).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (403, "protected_branch"));
+
+    // main is no longer protected
+    std::fs::write(env.worktree("docs", "main").join("notes.md"), "# More notes\n").unwrap();
+    let (status, body) = send(&app, commit("main", "")).await;
+    assert_eq!(status, 200, "{}", body);
+}
+
+#[actix_web::test]
+async fn branch_names_follow_the_workspace_policy() {
+    let env = TestEnv::new("branch-naming");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let patch = |body: Value| request("PATCH", "/api/workspaces/docs", Some(body));
+    for (body, code) in [
+        (json!({ "branch_name_pattern": "runbook/(" }), "invalid_branch_pattern"),
+        (json!({ "branch_name_template": "runbook/{who}/{slug}" }), "invalid_branch_template"),
+        (json!({ "branch_name_template": "runbook/{user}" }), "invalid_branch_template"),
+    ] {
+        let (status, body) = send(&app, patch(body)).await;
+        assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, code), "{}", body);
+    }
+    let policy = json!({
+        "branch_name_pattern": "runbook/[a-z0-9._-]+/[a-z0-9-]+",
+        "branch_name_template": "runbook/{user}/{slug}"
+    });
+    let (status, body) = send(&app, patch(policy)).await;
+    assert_eq!(status, 200, "{}", body);
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(body["branch_naming"]["pattern"], "runbook/[a-z0-9._-]+/[a-z0-9-]+");
+    assert_eq!(body["branch_naming"]["template"], "runbook/{user}/{slug}");
+
+    // Names must match the whole pattern
+    let create = |body: Value| request("POST", "/api/workspaces/docs/branches", Some(body));
+    for name in ["junk", "runbook/default/fix-disk-alert/extra"] {
+        let (status, body) = send(&app, create(json!({ "branch_name": name }))).await;
+        assert_eq!(status, 400, "{}", body);
+        assert_eq!(body["error"]["code"], "branch_name_policy");
+        assert_eq!(body["error"]["details"]["pattern"], "runbook/[a-z0-9._-]+/[a-z0-9-]+");
+    }
+    let (status, _) = send(&app, create(json!({ "branch_name": "junk", "auto_name": { "slug": "x" } }))).await;
+    assert_eq!(status, 400);
+
+    // ...or be generated from the template
+    let (status, body) = send(&app, create(json!({ "auto_name": { "slug": "Fix disk alert!" } }))).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["branch"], "runbook/default/fix-disk-alert");
+    assert!(std::path::Path::new(body["worktree_path"].as_str().unwrap()).exists());
+    let (status, body) = send(&app, create(json!({ "auto_name": { "slug": "!!" } }))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, "invalid_slug"));
+
+    let rename = |new_name: &str| {
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/runbook%2Fdefault%2Ffix-disk-alert/rename",
+            Some(json!({ "new_name": new_name })),
+        )
+    };
+    let (status, body) = send(&app, rename("junk")).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, "branch_name_policy"));
+    let (status, body) = send(&app, rename("runbook/default/fix-cpu-alert")).await;
+    assert_eq!(status, 200, "{}", body);
+
+    // Without a policy any valid name goes, and nothing is generated
+    let (status, _) = send(&app, patch(json!({ "branch_name_pattern": "", "branch_name_template": "" }))).await;
+    assert_eq!(status, 200);
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(body["branch_naming"], json!({ "pattern": null, "template": null }));
+    let (status, body) = send(&app, create(json!({ "auto_name": { "slug": "fix" } }))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, "no_branch_template"));
+    let (status, body) = send(&app, create(json!({ "branch_name": "junk" }))).await;
+    assert_eq!(status, 201, "{}", body);
+}
+
+#[actix_web::test]
+async fn commit_messages_follow_the_workspace_policy() {
+    let env = TestEnv::new("commit-messages");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, _) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "fix-alerts" }))),
+    )
+    .await;
+    assert_eq!(status, 201);
+    let worktree = env.worktree("docs", "fix-alerts");
+    for file in ["a.md", "b.md", "c.md", "d.md", "e.md"] {
+        std::fs::write(worktree.join(file), "# Notes\n").unwrap();
+    }
+
+    let patch = |body: Value| request("PATCH", "/api/workspaces/docs", Some(body));
+    for (body, code) in [
+        (json!({ "commit_message_pattern": "(feat" }), "invalid_commit_pattern"),
+        (json!({ "commit_message_template": "fix: {ticket}" }), "invalid_commit_template"),
+    ] {
+        let (status, body) = send(&app, patch(body)).await;
+        assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, code), "{}", body);
+    }
+    let policy = json!({
+        "commit_message_pattern": r"^(feat|fix|docs)(\(.+\))?: .+ \[[A-Z]+-\d+\]",
+        "commit_message_template": "docs({branch}): update {files} [OPS-0]"
+    });
+    let (status, body) = send(&app, patch(policy)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["commit_messages"]["advisory"], false);
+
+    // The UI prefills the form with the template
+    let (status, body) = send(&app, request("GET", "/api/workspaces/docs/branches/fix-alerts/commit/template", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["message"], "docs(fix-alerts): update a.md, b.md, c.md and 2 more [OPS-0]");
+    assert_eq!(body["files"].as_array().unwrap().len(), 5);
+    assert_eq!(body["policy"]["template"], "docs({branch}): update {files} [OPS-0]");
+
+    // Messages that don't match are refused, with an example
+    let commit = |message: &str, files: Value| {
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/fix-alerts/commit",
+            Some(json!({ "message": message, "files": files })),
+        )
+    };
+    let (status, body) = send(&app, commit("Update stuff", json!(["a.md", "b.md"]))).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["code"], "commit_message_policy");
+    assert_eq!(body["error"]["details"]["example"], "docs(fix-alerts): update a.md and b.md [OPS-0]");
+    assert!(body["error"]["details"]["pattern"].as_str().unwrap().starts_with("^(feat"));
+    assert_eq!(git(&["status", "--porcelain", "a.md"], &worktree), "?? a.md");
+    let (status, body) = send(&app, commit("docs: add notes [OPS-12]", json!(["a.md"]))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.get("warning").is_none());
+
+    // An advisory pattern only warns
+    let (status, _) = send(&app, patch(json!({ "commit_message_advisory": true }))).await;
+    assert_eq!(status, 200);
+    let (status, body) = send(&app, commit("Update stuff", json!(["b.md"]))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(body["warning"].as_str().unwrap().contains("doesn't match"));
+    assert_eq!(git(&["log", "-1", "--format=%s"], &worktree), "Update stuff");
+
+    // Without a pattern anything goes
+    let (status, _) = send(&app, patch(json!({ "commit_message_pattern": "", "commit_message_advisory": false }))).await;
+    assert_eq!(status, 200);
+    let (status, body) = send(&app, commit("Whatever", json!(["c.md"]))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.get("warning").is_none());
+}
+
+#[actix_web::test]
+async fn rebase_conflicts_can_be_resolved_through_the_api() {
+    let env = TestEnv::new("conflicts");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let origin = env.fixture_repo();
+    let worktree = env.worktree("docs", "main");
+
+    // Both sides change the same line, and add the same binary file
+    std::fs::write(worktree.join("docs/alerts.md"), "# Alerts\n\nHighCPU fires above 80%\n").unwrap();
+    std::fs::write(worktree.join("logo.png"), b"\x89PNG\x00ours").unwrap();
+    git(&["add", "-A"], &worktree);
+    git(&["commit", "-q", "-m", "Lower the threshold"], &worktree);
+    let upstream_work = env.dir.join("upstream-work");
+    git(&["clone", "-q", origin.to_str().unwrap(), upstream_work.to_str().unwrap()], &env.dir);
+    std::fs::write(upstream_work.join("docs/alerts.md"), "# Alerts\n\nHighCPU fires above 95%\n").unwrap();
+    std::fs::write(upstream_work.join("logo.png"), b"\x89PNG\x00theirs").unwrap();
+    git(&["add", "-A"], &upstream_work);
+    git(&["commit", "-q", "-m", "Raise the threshold"], &upstream_work);
+    git(&["push", "-q", "origin", "main"], &upstream_work);
+
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/branches/main/rebase?sync=true", None)).await;
+    assert_eq!(status, 409, "{}", body);
+    assert_eq!(body["error"]["code"], "rebase_conflict");
+    assert_eq!(body["error"]["details"]["conflicts"], json!(["docs/alerts.md", "logo.png"]));
+
+    let base = "/api/workspaces/docs/branches/main/conflicts";
+    let (status, body) = send(&app, request("GET", base, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["operation"], "rebase");
+    assert_eq!(
+        body["conflicts"],
+        json!([
+            { "path": "docs/alerts.md", "stages": [1, 2, 3], "binary": false },
+            { "path": "logo.png", "stages": [2, 3], "binary": true }
+        ])
+    );
+
+    // Each side of the text file, as a rebase names them: ours is upstream
+    let (status, body) = send(&app, request("GET", &format!("{}/file?path=docs/alerts.md", base), None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["base"], "# Alerts\n\nHighCPU fires above 90%\n");
+    assert_eq!(body["ours"], "# Alerts\n\nHighCPU fires above 95%\n");
+    assert_eq!(body["theirs"], "# Alerts\n\nHighCPU fires above 80%\n");
+    assert!(body["working"].as_str().unwrap().contains("<<<<<<<"));
+    let (status, body) = send(&app, request("GET", &format!("{}/file?path=logo.png", base), None)).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (409, "binary_conflict"));
+    let (status, body) = send(&app, request("GET", &format!("{}/file?path=README.md", base), None)).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "conflict_not_found"));
+
+    let resolve = |path: &str, content: &str| {
+        request("POST", &format!("{}/resolve", base), Some(json!({ "path": path, "content": content })))
+    };
+    let (status, body) = send(&app, resolve("logo.png", "text")).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (409, "binary_conflict"));
+    let (status, body) = send(&app, resolve("docs/alerts.md", "# Alerts\n\nHighCPU fires above 85%\n")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["remaining"], json!(["logo.png"]));
+    assert_eq!(body["all_resolved"], false);
+    assert_eq!(body["operation"], "rebase");
+    assert_eq!(git(&["diff", "--cached", "--name-only", "--diff-filter=M"], &worktree), "docs/alerts.md");
+    assert_eq!(
+        std::fs::read_to_string(worktree.join("docs/alerts.md")).unwrap(),
+        "# Alerts\n\nHighCPU fires above 85%\n"
+    );
+
+    // The binary file is settled in a terminal
+    git(&["checkout", "--theirs", "--", "logo.png"], &worktree);
+    git(&["add", "logo.png"], &worktree);
+    let (_, body) = send(&app, request("GET", base, None)).await;
+    assert_eq!(body["conflicts"], json!([]));
+}
+
+#[actix_web::test]
+async fn commits_are_signed_with_the_workspace_key() {
+    let env = TestEnv::new("signing");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, _) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "signed" }))),
+    )
+    .await;
+    assert_eq!(status, 201);
+    let worktree = env.worktree("docs", "signed");
+    let key = env.dir.join("signing_key");
+    let commit = |file: &str| {
+        std::fs::write(worktree.join(file), "# Notes\n").unwrap();
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/signed/commit",
+            Some(json!({ "message": format!("Add {}", file), "files": [file] })),
+        )
+    };
+
+    // A key that isn't there shows in the status, and commits fail to sign
+    let patch = |body: Value| request("PATCH", "/api/workspaces/docs", Some(body));
+    let (status, body) = send(&app, patch(json!({ "signing_key_path": key, "signing_format": "ssh" }))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["status"], "signing_unavailable");
+    let (status, body) = send(&app, commit("a.md")).await;
+    assert_eq!(status, 500, "{}", body);
+    assert_eq!(body["error"]["code"], "signing_failed");
+    assert!(body["error"]["details"]["reason"].as_str().unwrap().contains("not found"));
+    assert_eq!(git(&["log", "-1", "--format=%s"], &worktree), "Initial runbooks");
+
+    let generated = std::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "runotepad", "-f"])
+        .arg(&key)
+        .status()
+        .unwrap();
+    assert!(generated.success());
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/verify", None)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "ok");
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs", None)).await;
+    assert_eq!(body["signing"], json!({ "format": "ssh", "key": key }));
+
+    let (status, body) = send(&app, commit("a.md")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["signed"], true);
+    assert!(git(&["cat-file", "commit", "HEAD"], &worktree).contains("-----BEGIN SSH SIGNATURE-----"));
+
+    // Without a key commits aren't signed
+    let (status, _) = send(&app, patch(json!({ "signing_key_path": "" }))).await;
+    assert_eq!(status, 200);
+    let (status, body) = send(&app, commit("b.md")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["signed"], false);
+}
+
+#[actix_web::test]
+async fn commit_hook_failures_are_reported_with_their_output() {
+    let env = TestEnv::new("hooks");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, _) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "hooked" }))),
+    )
+    .await;
+    assert_eq!(status, 201);
+    let worktree = env.worktree("docs", "hooked");
+
+    // A formatter that fixes trailing whitespace and refuses the commit
+    let hook = worktree.join(git(&["rev-parse", "--git-path", "hooks/pre-commit"], &worktree));
+    std::fs::create_dir_all(hook.parent().unwrap()).unwrap();
+    std::fs::write(
+        &hook,
+        "#!/bin/sh\necho 'checking formatting'\necho 'trailing whitespace in docs/alerts.md' >&2\n\
+         sed -i 's/ *$//' docs/alerts.md\nexit 1\n",
+    )
+    .unwrap();
+    std::process::Command::new("chmod").arg("+x").arg(&hook).status().unwrap();
+
+    std::fs::write(worktree.join("docs/alerts.md"), "# Alerts   \n\nHighCPU fires above 80%\n").unwrap();
+    let commit = |no_verify: bool| {
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/hooked/commit",
+            Some(json!({ "message": "Lower the threshold", "files": ["docs/alerts.md"], "no_verify": no_verify })),
+        )
+    };
+    let (status, body) = send(&app, commit(false)).await;
+    assert_eq!(status, 422, "{}", body);
+    assert_eq!(body["error"]["code"], "hook_failed");
+    assert!(body["error"]["message"].as_str().unwrap().contains("review and retry"));
+    let details = &body["error"]["details"];
+    assert_eq!(details["hooks"], json!(["pre-commit"]));
+    // Git passes what hooks print on stdout to its stderr
+    assert_eq!(details["stderr"], "checking formatting\ntrailing whitespace in docs/alerts.md");
+    assert_eq!(details["files_modified"], json!(["docs/alerts.md"]));
+    assert_eq!(git(&["log", "-1", "--format=%s"], &worktree), "Initial runbooks");
+
+    // Skipping the hooks commits as is
+    let (status, body) = send(&app, commit(true)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(git(&["log", "-1", "--format=%s"], &worktree), "Lower the threshold");
+}
+
+#[actix_web::test]
+async fn git_runs_with_the_workspace_config_and_never_prompts() {
+    let env = TestEnv::new("git-scope");
+    let app = env.app().await;
+    let (server, authorizations) = common::auth_required_server();
+
+    // Without a credential git gives up at the first challenge instead of
+    // waiting on a password prompt
+    let url = format!("{}/private.git", server);
+    let clone = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces?sync=true",
+            Some(json!({ "name": "private", "repo_url": url, "base_branch": "main" })),
+        ),
+    );
+    let (status, body) = tokio::time::timeout(Duration::from_secs(30), clone)
+        .await
+        .expect("clone waited on a credential prompt");
+    assert_ne!(status, 201, "{}", body);
+    assert_eq!(authorizations.recv().unwrap(), None);
+    let (status, _) = send(&app, request("GET", "/api/workspaces/private", None)).await;
+    assert_eq!(status, 404);
+
+    create_workspace(&env, &app, "docs").await;
+    let (status, body) = send(
+        &app,
+        request(
+            "PATCH",
+            "/api/workspaces/docs",
+            Some(json!({ "git_password": "s3cret" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["code"], "invalid_git_identity");
+
+    let (status, body) = send(
+        &app,
+        request(
+            "PATCH",
+            "/api/workspaces/docs",
+            Some(json!({
+                "git_user_name": "Runbook Bot",
+                "git_user_email": "bot@example.com",
+                "git_username": "alice",
+                "git_password": "s3cret"
+            })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        body["git_identity"],
+        json!({
+            "user_name": "Runbook Bot",
+            "user_email": "bot@example.com",
+            "username": "alice",
+            "has_password": true
+        })
+    );
+
+    // The generated config carries the settings, and the password is
+    // stored nowhere in clear
+    let workspace_path = env.config.get_workspace_dir().join("docs");
+    let gitconfig = std::fs::read_to_string(workspace_path.join("gitconfig")).unwrap();
+    assert!(gitconfig.contains("name = \"Runbook Bot\""), "{}", gitconfig);
+    assert!(gitconfig.contains("username = \"alice\""), "{}", gitconfig);
+    assert!(!gitconfig.contains("s3cret"));
+    assert!(!std::fs::read_to_string(env.dir.join("config.json")).unwrap().contains("s3cret"));
+
+    // Verifying a new URL answers the challenge with the credential
+    let (status, body) = send(
+        &app,
+        request("PATCH", "/api/workspaces/docs", Some(json!({ "repo_url": url }))),
+    )
+    .await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["code"], "remote_unreachable");
+    let sent: Vec<Option<String>> = authorizations.try_iter().collect();
+    assert!(
+        sent.contains(&Some("Basic YWxpY2U6czNjcmV0".to_string())),
+        "{:?}",
+        sent
+    );
+}
+
+#[actix_web::test]
+async fn ignore_rules_are_edited_with_a_preview_of_what_they_hide() {
+    let env = TestEnv::new("ignore");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, _) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "reports" }))),
+    )
+    .await;
+    assert_eq!(status, 201);
+    let worktree = env.worktree("docs", "reports");
+    std::fs::create_dir_all(worktree.join("out/daily")).unwrap();
+    std::fs::write(worktree.join("out/summary.md"), "# Summary\n").unwrap();
+    std::fs::write(worktree.join("out/daily/monday.md"), "# Monday\n").unwrap();
+    std::fs::write(worktree.join("draft.md"), "# Draft\n").unwrap();
+    std::fs::write(worktree.join("docs/.gitignore"), "scratch.md\n").unwrap();
+    std::fs::write(worktree.join("docs/scratch.md"), "# Scratch\n").unwrap();
+    let exclude = std::path::PathBuf::from(git(&["rev-parse", "--git-path", "info/exclude"], &worktree));
+    std::fs::write(worktree.join(exclude), "# local\ndraft.md\n").unwrap();
+
+    let listed = |files: &Value| {
+        let mut paths = Vec::new();
+        let mut stack: Vec<&Value> = files.as_array().unwrap().iter().collect();
+        while let Some(entry) = stack.pop() {
+            match entry["children"].as_array() {
+                Some(children) => stack.extend(children),
+                None => paths.push(entry["path"].as_str().unwrap().to_string()),
+            }
+        }
+        paths.sort();
+        paths
+    };
+    let files_uri = "/api/workspaces/docs/branches/reports/files?fresh=true";
+    let (status, files) = send(&app, request("GET", files_uri, None)).await;
+    assert_eq!(status, 200, "{}", files);
+    assert_eq!(
+        listed(&files),
+        ["README.md", "docs/alerts.md", "out/daily/monday.md", "out/summary.md"]
+    );
+
+    let uri = "/api/workspaces/docs/branches/reports/ignore";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    let sources: Vec<(&str, &str)> = body["sources"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|source| (source["path"].as_str().unwrap(), source["kind"].as_str().unwrap()))
+        .collect();
+    assert_eq!(sources, [("docs/.gitignore", "gitignore"), ("info/exclude", "exclude")]);
+    assert_eq!(body["sources"][1]["patterns"], json!(["draft.md"]));
+
+    let (status, body) = send(
+        &app,
+        request("PUT", uri, Some(json!({ "content": "ok/\n[abc\n!\nend\\\n" }))),
+    )
+    .await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["code"], "invalid_ignore_pattern");
+    let bad_lines: Vec<u64> = body["error"]["details"]["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(bad_lines, [2, 3, 4]);
+
+    // The preview counts what each pattern hides, all in one batch
+    let content = "# generated\nout/\n*.tmp\n";
+    let (status, body) = send(
+        &app,
+        request("PUT", &format!("{}?dry_run=true", uri), Some(json!({ "content": content }))),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["saved"], false);
+    assert_eq!(
+        body["preview"],
+        json!([
+            { "line": 2, "pattern": "out/", "hides": 2, "paths": ["out/daily/monday.md", "out/summary.md"] },
+            { "line": 3, "pattern": "*.tmp", "hides": 0, "paths": [] }
+        ])
+    );
+    assert!(!worktree.join(".gitignore").exists());
+
+    let (status, body) = send(&app, request("PUT", uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["saved"], true);
+    assert_eq!(std::fs::read_to_string(worktree.join(".gitignore")).unwrap(), content);
+    let (_, files) = send(&app, request("GET", "/api/workspaces/docs/branches/reports/files", None)).await;
+    assert_eq!(listed(&files), ["README.md", "docs/alerts.md"]);
+
+    // Patterns already in the file aren't previewed again
+    let (_, body) = send(
+        &app,
+        request("PUT", &format!("{}?dry_run=true", uri), Some(json!({ "content": "out/\n*.md\n" }))),
+    )
+    .await;
+    assert_eq!(body["preview"][0]["pattern"], "*.md");
+    assert_eq!(body["preview"][0]["hides"], 0, "tracked files stay listed");
+}
+
+#[actix_web::test]
+async fn branch_activity_merges_commits_file_changes_and_executions() {
+    let env = TestEnv::new("activity");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let file_uri = "/api/workspaces/docs/branches/main/file?path=runbook.md";
+    let content = "# Failover\n\n- [ ] Page the on-call DBA\n";
+    let (status, _) = send(&app, request("PUT", file_uri, Some(json!({ "content": content })))).await;
+    assert_eq!(status, 200);
+    let uri = "/api/workspaces/docs/branches/main/checklist?path=runbook.md";
+    let (_, body) = send(&app, request("GET", uri, None)).await;
+    let toggle = json!({ "step": 0, "checked": true, "file_hash": body["file_hash"] });
+    let (status, _) = send(&app, request("PUT", uri, Some(toggle))).await;
+    assert_eq!(status, 200);
+    let (status, body) = send(
+        &app,
+        request(
+            "POST",
+            "/api/workspaces/docs/branches/main/commit?override=true",
+            Some(json!({ "message": "Add failover runbook", "all": true })),
+        ),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    let (status, _) = send(
+        &app,
+        request("DELETE", "/api/workspaces/docs/branches/main/file?path=docs/alerts.md", None),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    // The audit log is written in the background
+    let activity = "/api/workspaces/docs/branches/main/activity";
+    let mut feed = Value::Null;
+    for _ in 0..50 {
+        let (status, body) = send(&app, request("GET", activity, None)).await;
+        assert_eq!(status, 200, "{}", body);
+        feed = body;
+        if feed["items"].as_array().unwrap().iter().any(|item| item["type"] == "file_delete") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let items = feed["items"].as_array().unwrap();
+    let mut types: Vec<&str> = items.iter().map(|item| item["type"].as_str().unwrap()).collect();
+    types.sort();
+    assert_eq!(types, ["commit", "commit", "execution", "file_delete", "file_save"], "{}", feed);
+    assert!(feed["next_cursor"].is_null());
+    let mut subjects: Vec<&str> = items.iter().filter_map(|item| item["subject"].as_str()).collect();
+    subjects.sort();
+    assert_eq!(subjects, ["Add failover runbook", "Initial runbooks"]);
+    let save = items.iter().find(|item| item["type"] == "file_save").unwrap();
+    assert_eq!((save["path"].as_str(), save["token"].as_str()), (Some("runbook.md"), Some("default")));
+    let execution = items.iter().find(|item| item["type"] == "execution").unwrap();
+    assert_eq!(execution["kind"], "checklist_toggle");
+    let timestamps: Vec<&str> = items.iter().map(|item| item["timestamp"].as_str().unwrap()).collect();
+    assert!(timestamps.iter().all(|timestamp| chrono::DateTime::parse_from_rfc3339(timestamp).is_ok()));
+
+    // Paging one item at a time walks the same feed, even as new activity
+    // lands on top of it
+    let mut paged = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let uri = match &cursor {
+            Some(cursor) => format!("{}?limit=1&before={}", activity, cursor),
+            None => format!("{}?limit=1", activity),
+        };
+        let (status, page) = send(&app, request("GET", &uri, None)).await;
+        assert_eq!(status, 200, "{}", page);
+        paged.extend(page["items"].as_array().unwrap().iter().cloned());
+        if paged.len() == 1 {
+            let (status, _) = send(&app, request("PUT", file_uri, Some(json!({ "content": "# Later\n" })))).await;
+            assert_eq!(status, 200);
+        }
+        match page["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+    assert_eq!(&paged, items);
+
+    let (status, body) = send(&app, request("GET", &format!("{}?before=yesterday", activity), None)).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["code"], "invalid_cursor");
+}
+
+#[actix_web::test]
+async fn stale_locks_and_interrupted_operations_are_cleaned_up() {
+    let env = TestEnv::with_config("repair", json!({ "stale_lock_secs": 0 }));
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let worktree = env.worktree("docs", "main");
+    let repo = env.config.repo_path("docs");
+
+    // A merge stopped before its commit, and the locks of a killed git
+    git(&["switch", "-q", "-c", "side"], &worktree);
+    std::fs::write(worktree.join("docs/new.md"), "# New\n").unwrap();
+    git(&["add", "-A"], &worktree);
+    git(&["commit", "-q", "-m", "Add a runbook"], &worktree);
+    git(&["switch", "-q", "main"], &worktree);
+    git(&["merge", "-q", "--no-ff", "--no-commit", "side"], &worktree);
+    let index_lock = repo.join("worktrees/main/index.lock");
+    std::fs::write(&index_lock, "").unwrap();
+    // Held open by this process, as git holds a lock it is writing
+    let held_lock = repo.join("packed-refs.lock");
+    let held = std::fs::File::create(&held_lock).unwrap();
+
+    runotepad::repair::startup(&env.config, &env.state);
+    assert!(!index_lock.exists());
+    assert!(held_lock.exists());
+    assert!(worktree.join("docs/new.md").exists());
+
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs/branches?fresh=true", None)).await;
+    let main = body.as_array().unwrap().iter().find(|branch| branch["name"] == "main").unwrap();
+    assert_eq!(main["needs_attention"][0]["condition"], "merge_interrupted", "{}", main);
+    let side = body.as_array().unwrap().iter().find(|branch| branch["name"] == "side").unwrap();
+    assert_eq!(side["needs_attention"], json!([]));
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs/branches/main/status", None)).await;
+    assert_eq!(body["needs_attention"][0]["condition"], "merge_interrupted");
+
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/repair?branch=main", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["locks"][0]["path"], "packed-refs.lock");
+    assert_eq!(body["locks"][0]["removed"], false);
+    assert_eq!(body["locks"][0]["held_by"], std::process::id());
+    assert_eq!(body["operations"], json!([{ "branch": "main", "operation": "merge", "aborted": false }]));
+
+    // A worktree busy with another operation is left alone
+    let busy = env.state.worktree_locks.try_acquire(&worktree, "commit").unwrap();
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/repair?branch=main&abort=true", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["operations"][0]["aborted"], false, "{}", body);
+    assert!(worktree.join("docs/new.md").exists());
+    drop(busy);
+
+    drop(held);
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/repair?abort=true", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["locks"][0]["removed"], true);
+    assert_eq!(body["operations"][0]["aborted"], true);
+    assert!(!held_lock.exists());
+    assert!(!worktree.join("docs/new.md").exists());
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs/branches/main/status", None)).await;
+    assert_eq!(body["needs_attention"], json!([]));
+
+    let (status, body) = send(&app, request("POST", "/api/workspaces/docs/repair?branch=nope", None)).await;
+    assert_eq!(status, 404, "{}", body);
+
+    // Both cleanups are audited; the log is written in the background
+    let mut repairs = Vec::new();
+    for _ in 0..50 {
+        let (_, body) = send(&app, request("GET", "/api/v1/audit", None)).await;
+        repairs = body.as_array().unwrap().iter().filter(|entry| entry["action"] == "workspace.repair").cloned().collect();
+        if repairs.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(repairs.len(), 2, "{:?}", repairs);
+    let startup = repairs.iter().find(|entry| entry["route"] == "STARTUP").unwrap();
+    assert_eq!(startup["summary"]["removed_locks"], json!(["worktrees/main/index.lock"]));
+}
+
+#[actix_web::test]
+async fn bulk_operations_run_as_a_batch_of_jobs() {
+    let env = TestEnv::new("bulk");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    create_workspace(&env, &app, "ops").await;
+    let main = env.worktree("docs", "main");
+
+    // "done" and "wip" are merged into main, "wip" has uncommitted work and
+    // "fresh" no commits of its own
+    let create_branch = |branch: &'static str| {
+        let uri = "/api/workspaces/docs/branches";
+        send(&app, request("POST", uri, Some(json!({ "branch_name": branch }))))
+    };
+    for branch in ["done", "wip"] {
+        let (status, body) = create_branch(branch).await;
+        assert_eq!(status, 201, "{}", body);
+        let worktree = env.worktree("docs", branch);
+        std::fs::write(worktree.join(format!("{}.md", branch)), "# Notes\n").unwrap();
+        git(&["add", "-A"], &worktree);
+        git(&["commit", "-q", "-m", "Add notes"], &worktree);
+        git(&["merge", "-q", "--no-ff", "-m", "Merge", branch], &main);
+    }
+    std::fs::write(env.worktree("docs", "wip").join("wip.md"), "# Notes, edited\n").unwrap();
+    let (status, body) = create_branch("fresh").await;
+    assert_eq!(status, 201, "{}", body);
+
+    let wait_for_batch = |id: String| {
+        let app = &app;
+        async move {
+            for _ in 0..200 {
+                let (status, body) = send(app, request("GET", &format!("/api/admin/workspaces/bulk/{}", id), None)).await;
+                assert_eq!(status, 200, "{}", body);
+                if body["finished"] == true {
+                    return body;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            panic!("batch {} did not finish", id);
+        }
+    };
+
+    let bulk = |operation: &str, workspaces: Value| {
+        request(
+            "POST",
+            "/api/admin/workspaces/bulk",
+            Some(json!({ "operation": operation, "workspaces": workspaces })),
+        )
+    };
+    let (status, body) = send(&app, bulk("cleanup_merged", json!(["docs"]))).await;
+    assert_eq!(status, 202, "{}", body);
+    assert_eq!(body["operation"], "cleanup_merged");
+    assert_eq!(body["concurrency"], 1);
+    assert_eq!(body["jobs"][0]["workspace"], "docs");
+    let batch = wait_for_batch(body["batch_id"].as_str().unwrap().to_string()).await;
+    assert_eq!(batch["counts"]["succeeded"], 1, "{}", batch);
+    let result = &batch["job_states"][0]["result"];
+    assert_eq!(result["removed"], json!(["done"]));
+    assert_eq!(result["skipped"], json!([{ "branch": "wip", "reason": "uncommitted_changes" }]));
+    assert!(!env.worktree("docs", "done").exists());
+    let repo = env.config.repo_path("docs");
+    assert_eq!(git(&["branch", "--list", "done"], &repo), "");
+    assert_ne!(git(&["branch", "--list", "fresh"], &repo), "");
+
+    // A broken workspace fails its job without stopping the batch
+    std::fs::remove_dir_all(env.config.repo_path("ops")).unwrap();
+    let (status, body) = send(&app, bulk("verify", json!(["*"]))).await;
+    assert_eq!(status, 202, "{}", body);
+    assert_eq!(body["jobs"].as_array().unwrap().len(), 2);
+    let batch = wait_for_batch(body["batch_id"].as_str().unwrap().to_string()).await;
+    assert_eq!(batch["counts"]["succeeded"], 1, "{}", batch);
+    assert_eq!(batch["counts"]["failed"], 1, "{}", batch);
+    let failed = batch["job_states"].as_array().unwrap().iter().find(|job| job["state"] == "failed").unwrap();
+    assert_eq!(failed["workspace"], "ops");
+    assert_eq!(failed["error"]["code"], "workspace_unhealthy");
+    assert_eq!(failed["batch"], batch["batch_id"]);
+
+    let (status, body) = send(&app, bulk("verify", json!(["docs", "nope"]))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "workspace_not_found"));
+    let (status, _) = send(&app, bulk("reclone", json!(["*"]))).await;
+    assert_eq!(status, 400);
+    let (status, body) = send(&app, request("GET", "/api/admin/workspaces/bulk/nope", None)).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "batch_not_found"));
+
+    // One entry per batch and one per workspace; the log is written in the
+    // background
+    let mut entries = Vec::new();
+    for _ in 0..50 {
+        let (_, body) = send(&app, request("GET", "/api/v1/audit", None)).await;
+        entries = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry["action"].as_str().unwrap().starts_with("workspace.bulk"))
+            .cloned()
+            .collect();
+        if entries.len() == 5 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(entries.len(), 5, "{:?}", entries);
+    let outcomes: Vec<&Value> = entries.iter().filter(|entry| entry["action"] == "workspace.bulk_outcome").collect();
+    assert_eq!(outcomes.len(), 3);
+    assert!(outcomes
+        .iter()
+        .any(|entry| entry["summary"]["workspace"] == "ops" && entry["summary"]["error"] == "workspace_unhealthy"));
+}
+
+#[actix_web::test]
+async fn detached_worktrees_are_pinned_to_a_tag_or_commit() {
+    let env = TestEnv::new("detached");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let main = env.worktree("docs", "main");
+
+    std::fs::write(main.join("runbook.md"), "# Release 1.4\n").unwrap();
+    git(&["add", "-A"], &main);
+    git(&["commit", "-q", "-m", "Release 1.4"], &main);
+    git(&["tag", "v1.4"], &main);
+    let release = git(&["rev-parse", "HEAD"], &main);
+    std::fs::write(main.join("runbook.md"), "# Release 1.5\n").unwrap();
+    git(&["commit", "-q", "-a", "-m", "Release 1.5"], &main);
+
+    let create = |body: Value| request("POST", "/api/workspaces/docs/branches", Some(body));
+    let (status, body) = send(&app, create(json!({ "ref": "v1.4", "detached": true }))).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["branch"], "detached:v1.4");
+    assert_eq!(body["detached"], true);
+    assert_eq!(body["head_commit"], release.as_str());
+
+    let uri = "/api/workspaces/docs/branches/detached:v1.4/file?path=runbook.md";
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["content"], "# Release 1.4\n");
+
+    // A short commit ID, and a ref named like a branch, get their own worktrees
+    let short = &release[..7];
+    let (status, body) = send(&app, create(json!({ "ref": short, "detached": true }))).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["branch"], format!("detached:{}", short));
+    let (status, body) = send(&app, create(json!({ "ref": "main", "detached": true }))).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(git(&["rev-parse", "--abbrev-ref", "HEAD"], &main), "main");
+
+    let (status, body) = send(&app, request("GET", "/api/workspaces/docs/branches?fresh=true", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    let branches = body.as_array().unwrap();
+    let pinned = branches.iter().find(|branch| branch["name"] == "detached:v1.4").unwrap();
+    assert_eq!(pinned["detached"], true);
+    assert_eq!(pinned["is_worktree"], true);
+    assert_eq!(pinned["head_commit"], release.as_str());
+    assert_eq!(pinned["checked_out_branch"], Value::Null);
+    assert_eq!(pinned["behind"], 1);
+    let main_info = branches.iter().find(|branch| branch["name"] == "main").unwrap();
+    assert_eq!(main_info["detached"], false);
+    assert_eq!(branches.iter().filter(|branch| branch["detached"] == true).count(), 3);
+
+    // Nothing can be committed, pushed, pulled, rebased or renamed there
+    std::fs::write(env.worktree("docs", "detached:v1.4").join("runbook.md"), "# Edited\n").unwrap();
+    for (method, action, body) in [
+        ("POST", "commit", Some(json!({ "message": "Edit", "all": true }))),
+        ("POST", "push", None),
+        ("POST", "pull", None),
+        ("POST", "rebase", None),
+        ("POST", "rename", Some(json!({ "new_name": "release" }))),
+    ] {
+        let uri = format!("/api/workspaces/docs/branches/detached:v1.4/{}", action);
+        let (status, body) = send(&app, request(method, &uri, body)).await;
+        assert_eq!(status, 409, "{}: {}", action, body);
+        assert_eq!(body["error"]["code"], "detached_head", "{}", action);
+    }
+
+    let (status, body) = send(&app, create(json!({ "ref": "v9.9", "detached": true }))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "revision_not_found"));
+    let (status, _) = send(&app, create(json!({ "ref": "v1.4" }))).await;
+    assert_eq!(status, 400);
+    let (status, _) = send(&app, create(json!({ "ref": "v1.4", "detached": true, "branch_name": "x" }))).await;
+    assert_eq!(status, 400);
+    let (status, body) = send(&app, create(json!({ "ref": "v1.4^", "detached": true }))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, "invalid_ref"));
+
+    let uri = "/api/workspaces/docs/branches/detached:v1.4";
+    let (status, body) = send(&app, request("DELETE", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(!env.worktree("docs", "detached:v1.4").exists());
+    assert_eq!(git(&["tag", "--list", "v1.4"], &main), "v1.4");
+}
+
+#[actix_web::test]
+async fn search_covers_every_branch() {
+    let env = TestEnv::new("branch-search");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let main = env.worktree("docs", "main");
+
+    std::fs::write(main.join("failover.md"), "# Failover\n\nPromote the replica.\n").unwrap();
+    std::fs::write(main.join("notes.txt"), "Promote the replica.\n").unwrap();
+    git(&["add", "-A"], &main);
+    git(&["commit", "-q", "-m", "Add the failover runbook"], &main);
+    // A branch without a worktree
+    git(&["branch", "archived"], &main);
+
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "update" }))),
+    )
+    .await;
+    assert_eq!(status, 201, "{}", body);
+    let update = env.worktree("docs", "update");
+    std::fs::write(update.join("failover.md"), "# Failover\n\nPromote the replica, then repoint DNS.\n").unwrap();
+    git(&["commit", "-q", "-a", "-m", "Repoint DNS"], &update);
+    // Uncommitted changes aren't searched
+    std::fs::write(update.join("draft.md"), "Promote the replica by hand\n").unwrap();
+
+    let search = |query: &str| request("GET", &format!("/api/workspaces/docs/search?{}", query), None);
+    let (status, body) = send(&app, search("q=PROMOTE%20THE")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["branches_searched"], 3);
+    assert_eq!(body["warnings"], json!([]));
+    let found: Vec<(&str, &str, u64)> = body["matches"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| (m["branch"].as_str().unwrap(), m["path"].as_str().unwrap(), m["line"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(found, [("archived", "failover.md", 3), ("main", "failover.md", 3), ("update", "failover.md", 3)]);
+    let updated = body["matches"].as_array().unwrap().iter().find(|m| m["branch"] == "update").unwrap();
+    assert_eq!(updated["snippet"], "Promote the replica, then repoint DNS.");
+
+    let (_, body) = send(&app, search("q=PROMOTE&case_sensitive=true")).await;
+    assert_eq!(body["matches"], json!([]));
+    let (_, body) = send(&app, search("q=repoint%20(DNS|the%20VIP)&regex=true")).await;
+    assert_eq!(body["matches"].as_array().unwrap().len(), 1);
+    assert_eq!(body["matches"][0]["branch"], "update");
+    // Literal by default
+    let (_, body) = send(&app, search("q=repoint%20(DNS|the%20VIP)")).await;
+    assert_eq!(body["matches"], json!([]));
+
+    let (_, body) = send(&app, search("q=replica&limit=2")).await;
+    assert_eq!(body["matches"].as_array().unwrap().len(), 2);
+    assert_eq!(body["truncated"], true);
+    let (_, body) = send(&app, search("q=e&per_branch=1")).await;
+    assert_eq!(body["matches"].as_array().unwrap().len(), 3);
+    assert_eq!(body["truncated_branches"], json!(["archived", "main", "update"]));
+
+    let (status, body) = send(&app, search("q=(unclosed&regex=true")).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, "invalid_regex"));
+    let (status, _) = send(&app, search("q=")).await;
+    assert_eq!(status, 400);
+    // A pattern git can't read fails per branch, not the whole search
+    let (status, body) = send(&app, search("q=(%3Fi)replica&regex=true")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["matches"], json!([]));
+    assert_eq!(body["warnings"].as_array().unwrap().len(), 3);
+    assert_eq!(body["warnings"][0]["branch"], "archived");
+
+    let (status, _) = send(&app, request("GET", "/api/workspaces/nope/search?q=x", None)).await;
+    assert_eq!(status, 404);
+}
+
+#[actix_web::test]
+async fn capabilities_match_the_mounted_routes() {
+    let env = TestEnv::new("capabilities");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+
+    let (status, body) = send(&app, request("GET", "/api/v1/capabilities", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["read_only"], false);
+    assert_eq!(body["features"]["terminals"], true);
+    assert_eq!(body["features"]["notifications"], false);
+    assert_eq!(body["limits"]["max_save_bytes"], env.config.get_max_save_bytes());
+    assert_eq!(body["websocket"]["subprotocol"], "runotepad");
+    assert_eq!(body["websocket"]["client_messages"], json!(["create", "input", "resize", "close", "relabel"]));
+    let advertised = body["features"].as_object().unwrap();
+    assert_eq!(advertised.len(), runotepad::capabilities::FEATURES.len());
+
+    // Every feature's route is mounted: a route that isn't gets the router's
+    // bare 404, where handlers answer with an error body. Requests are sent
+    // without a body, so nothing is changed
+    for feature in runotepad::capabilities::FEATURES {
+        assert!(advertised.contains_key(feature.name));
+        let Some(route) = feature.route else {
+            continue;
+        };
+        let (method, path) = route.split_once(' ').unwrap();
+        let uri = path
+            .replace("{name}", "docs")
+            .replace("{workspace}", "docs")
+            .replace("{branch}", "main")
+            .replace("{path}", "README.md");
+        let req = request(method, &uri, None).to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        if resp.status() == 404 {
+            let bytes = actix_web::test::read_body(resp).await;
+            assert!(!bytes.is_empty(), "{} ({}) is not mounted", feature.name, route);
+        }
+    }
+
+    // Read-only mode turns off what changes things, and the endpoint can be
+    // opened up for a login page
+    let env = TestEnv::with_config(
+        "capabilities-read-only",
+        json!({ "read_only": true, "allow_unauthenticated_capabilities": true }),
+    );
+    let app = env.app().await;
+    let req = actix_web::test::TestRequest::get().uri("/api/capabilities").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["read_only"], true);
+    assert_eq!(body["features"]["terminals"], false);
+    assert_eq!(body["features"]["bulk_operations"], false);
+    assert_eq!(body["features"]["search"], true);
+
+    let env = TestEnv::new("capabilities-token");
+    let app = env.app().await;
+    let req = actix_web::test::TestRequest::get().uri("/api/capabilities").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn drafts_are_kept_outside_the_worktree() {
+    let env = TestEnv::new("drafts");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let main = env.worktree("docs", "main");
+    let drafts = env.config.workspace_path("docs").join(".runotepad-drafts");
+
+    let uri = "/api/workspaces/docs/branches/main/file/draft?path=docs/alerts.md";
+    let (status, body) = send(&app, request("PUT", uri, Some(json!({ "content": "# Alerts, half edited\n" })))).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["bytes"], 22);
+    let saved_at = body["saved_at"].clone();
+
+    let (status, body) = send(&app, request("GET", "/api/workspaces/docs/branches/main/file?path=docs/alerts.md", None)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["has_draft"], true);
+    assert_eq!(body["draft_saved_at"], saved_at);
+    assert_ne!(body["content"], "# Alerts, half edited\n");
+
+    // The same file however the path is written
+    let (status, body) = send(
+        &app,
+        request("GET", "/api/workspaces/docs/branches/main/file/draft?path=./docs/alerts.md", None),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["content"], "# Alerts, half edited\n");
+    assert_eq!(body["saved_at"], saved_at);
+
+    // Nowhere git, file lists or searches look
+    assert_eq!(git(&["status", "--porcelain"], &main), "");
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs/branches/main/search?q=half%20edited", None)).await;
+    assert_eq!(body["matches"], json!([]));
+    assert_eq!(drafts.join("main").read_dir().unwrap().count(), 1);
+
+    let outside = "/api/workspaces/docs/branches/main/file/draft?path=../x.md";
+    let (status, _) = send(&app, request("PUT", outside, Some(json!({ "content": "x" })))).await;
+    assert_eq!(status, 400);
+
+    let (status, body) = send(&app, request("DELETE", uri, None)).await;
+    assert_eq!(status, 200, "{}", body);
+    let (status, body) = send(&app, request("GET", uri, None)).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (404, "draft_not_found"));
+    let (_, body) = send(&app, request("GET", "/api/workspaces/docs/branches/main/file?path=docs/alerts.md", None)).await;
+    assert_eq!(body["has_draft"], false);
+    assert_eq!(body["draft_saved_at"], Value::Null);
+    let (status, _) = send(&app, request("DELETE", uri, None)).await;
+    assert_eq!(status, 404);
+
+    // Old drafts are purged, and a branch's go with its worktree
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": "old" })))).await;
+    assert_eq!(status, 200);
+    assert_eq!(runotepad::drafts::purge(&env.config, "docs", Duration::from_secs(60)), 0);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(runotepad::drafts::purge(&env.config, "docs", Duration::from_millis(10)), 1);
+    assert!(!drafts.join("main").exists());
+
+    let (status, _) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "edit" }))),
+    )
+    .await;
+    assert_eq!(status, 201);
+    let uri = "/api/workspaces/docs/branches/edit/file/draft?path=new.md";
+    let (status, _) = send(&app, request("PUT", uri, Some(json!({ "content": "# New\n" })))).await;
+    assert_eq!(status, 200);
+    assert!(drafts.join("edit").exists());
+    let (status, _) = send(&app, request("DELETE", "/api/workspaces/docs/branches/edit", None)).await;
+    assert_eq!(status, 200);
+    assert!(!drafts.join("edit").exists());
+}
+
+#[actix_web::test]
+async fn patches_apply_edits_against_the_revision_read() {
+    let env = TestEnv::new("patch");
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let main = env.worktree("docs", "main");
+    let uri = "/api/workspaces/docs/branches/main/file?path=notes.md";
+    std::fs::write(main.join("notes.md"), "# Notes\n\nCafé crème\nline four\n").unwrap();
+    let (_, body) = send(&app, request("GET", uri, None)).await;
+    let hash = runotepad::blocks::content_hash(body["content"].as_str().unwrap());
+
+    // Byte ranges of the content as read; "é" is two bytes
+    let edits = json!({ "base_hash": hash, "edits": [
+        { "start_byte": 2, "end_byte": 7, "replacement": "Runbook" },
+        { "start_byte": 14, "end_byte": 14, "replacement": "!" }
+    ]});
+    let (status, body) = send(&app, request("PATCH", uri, Some(edits))).await;
+    assert_eq!(status, 200, "{}", body);
+    let expected = "# Runbook\n\nCafé! crème\nline four\n";
+    assert_eq!(std::fs::read_to_string(main.join("notes.md")).unwrap(), expected);
+    assert_eq!(body["file_hash"], runotepad::blocks::content_hash(expected));
+    let hash = body["file_hash"].as_str().unwrap().to_string();
+
+    let diff = "--- a/notes.md\n+++ b/notes.md\n@@ -3,2 +3,2 @@\n Café! crème\n-line four\n+line 4\n\\ No newline at end of file\n";
+    let (status, body) = send(&app, request("PATCH", uri, Some(json!({ "base_hash": hash, "diff": diff })))).await;
+    assert_eq!(status, 200, "{}", body);
+    let expected = "# Runbook\n\nCafé! crème\nline 4";
+    assert_eq!(std::fs::read_to_string(main.join("notes.md")).unwrap(), expected);
+
+    // Against an old revision: the current content comes back to rebase on
+    let (status, body) = send(&app, request("PATCH", uri, Some(json!({ "base_hash": hash, "edits": [] })))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (409, "file_changed"));
+    assert_eq!(body["error"]["details"]["content"], expected);
+    let hash = body["error"]["details"]["file_hash"].as_str().unwrap().to_string();
+
+    let invalid = [
+        (json!([{ "start_byte": 15, "end_byte": 15, "replacement": "x" }]), "invalid_edit", 0),
+        (
+            json!([
+                { "start_byte": 0, "end_byte": 4, "replacement": "" },
+                { "start_byte": 3, "end_byte": 5, "replacement": "" }
+            ]),
+            "invalid_edit",
+            1,
+        ),
+        (json!([{ "start_byte": 5, "end_byte": 99, "replacement": "" }]), "invalid_edit", 0),
+    ];
+    for (edits, code, index) in invalid {
+        let (status, body) = send(&app, request("PATCH", uri, Some(json!({ "base_hash": hash, "edits": edits })))).await;
+        assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, code), "{}", body);
+        assert_eq!(body["error"]["details"]["index"], index);
+    }
+    let stale = "@@ -1,1 +1,1 @@\n-# Runbook\n+# Notes\n@@ -3,1 +3,1 @@\n-Cafe crème\n+Café\n";
+    let (status, body) = send(&app, request("PATCH", uri, Some(json!({ "base_hash": hash, "diff": stale })))).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (400, "invalid_diff"), "{}", body);
+    assert_eq!(body["error"]["details"]["index"], 1);
+    let (status, _) = send(&app, request("PATCH", uri, Some(json!({ "base_hash": hash })))).await;
+    assert_eq!(status, 400);
+    assert_eq!(std::fs::read_to_string(main.join("notes.md")).unwrap(), expected);
+}
+
+#[actix_web::test]
+async fn git_operations_in_a_worktree_take_turns() {
+    let env = TestEnv::with_config("worktree-lock", json!({ "worktree_lock_timeout_secs": 1 }));
+    let app = env.app().await;
+    create_workspace(&env, &app, "docs").await;
+    let (status, _) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches", Some(json!({ "branch_name": "edit" }))),
+    )
+    .await;
+    assert_eq!(status, 201);
+    let edit = env.worktree("docs", "edit");
+    std::fs::write(edit.join("new.md"), "# New\n").unwrap();
+    let commit = || {
+        let body = json!({ "message": "Add new.md", "files": ["new.md"] });
+        request("POST", "/api/workspaces/docs/branches/edit/commit", Some(body))
+    };
+
+    // A pull running in the worktree
+    let held = env.state.worktree_locks.acquire(&edit, "pull", Duration::from_secs(1)).await.unwrap();
+    let (status, body) = send(&app, commit()).await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (423, "worktree_locked"), "{}", body);
+    assert_eq!(body["error"]["details"]["operation"], "pull");
+    assert_eq!(git(&["status", "--porcelain"], &edit), "?? new.md");
+    std::fs::write(edit.join("README.md"), "mangled\n").unwrap();
+    let restore = json!({ "path": "README.md", "overwrite": true });
+    let (status, body) = send(
+        &app,
+        request("POST", "/api/workspaces/docs/branches/edit/file/restore", Some(restore)),
+    )
+    .await;
+    assert_eq!((status, body["error"]["code"].as_str().unwrap()), (423, "worktree_locked"), "{}", body);
+    assert_eq!(std::fs::read_to_string(edit.join("README.md")).unwrap(), "mangled\n");
+    std::fs::write(edit.join("README.md"), "# Fixture\n").unwrap();
+
+    // Fired together, the commit and the rebase wait their turn instead of
+    // failing on git's index.lock
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        drop(held);
+    };
+    let rebase = request("POST", "/api/workspaces/docs/branches/edit/rebase?sync=true", None);
+    let ((commit_status, commit_body), (rebase_status, rebase_body), ()) =
+        futures::join!(send(&app, commit()), send(&app, rebase), release);
+    assert_eq!(commit_status, 200, "{}", commit_body);
+    assert_eq!(rebase_status, 200, "{}", rebase_body);
+    assert_eq!(git(&["log", "-1", "--format=%s"], &edit), "Add new.md");
+    assert_eq!(git(&["status", "--porcelain"], &edit), "");
+}
@@ -0,0 +1,79 @@
+//! Saves that fail partway leave the file they were replacing as it was and
+//! no temporary file behind, whoever the tests run as.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use runotepad::file_ops::{self, PendingWrite};
+
+/// Fresh directory holding `docs/runbook.md` with "original" content
+fn worktree(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "runotepad-atomic-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/runbook.md"), "original\n").unwrap();
+    dir
+}
+
+fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn write_error_partway_keeps_the_original() {
+    let dir = worktree("write-error");
+
+    // A save that runs out of disk after the first bytes, before `commit`
+    let save = || -> io::Result<()> {
+        let mut pending = PendingWrite::create(&dir, "docs/runbook.md")?;
+        pending.write(b"clobb")?;
+        Err(io::Error::other("No space left on device"))
+    };
+    assert!(save().is_err());
+
+    assert_eq!(fs::read_to_string(dir.join("docs/runbook.md")).unwrap(), "original\n");
+    assert_eq!(entries(&dir.join("docs")), ["runbook.md"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_rename_removes_the_temporary_file() {
+    let dir = worktree("rename-error");
+    // Nothing, root included, can rename a file over a directory
+    fs::create_dir(dir.join("docs/held.md")).unwrap();
+    fs::write(dir.join("docs/held.md/inside.md"), "kept\n").unwrap();
+
+    let err = file_ops::write_file(&dir, "docs/held.md", "clobbered\n").unwrap_err();
+    assert_ne!(err.kind(), io::ErrorKind::NotFound);
+
+    assert_eq!(fs::read_to_string(dir.join("docs/held.md/inside.md")).unwrap(), "kept\n");
+    assert_eq!(fs::read_to_string(dir.join("docs/runbook.md")).unwrap(), "original\n");
+    assert_eq!(entries(&dir.join("docs")), ["held.md", "runbook.md"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn successful_write_replaces_the_content_and_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = worktree("success");
+    let path = dir.join("docs/runbook.md");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+    file_ops::write_file(&dir, "docs/runbook.md", "updated\n").unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "updated\n");
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    assert_eq!(entries(&dir.join("docs")), ["runbook.md"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
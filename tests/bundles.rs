@@ -0,0 +1,188 @@
+//! Imports bundles exported from one server into a fresh config for
+//! another: secrets sealed again with the new key, workspaces cloned, and
+//! bundles from newer versions or over an existing config refused. Ends
+//! with `runotepad import` through the real binary.
+
+mod common;
+
+use common::{TestEnv, TOKEN};
+use runotepad::bundle::{self, ImportOptions};
+use runotepad::config::{ConfigManager, ConfigOverrides};
+use runotepad::secrets::SecretKey;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Empty directory for the host a bundle is imported on
+fn new_host(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("runotepad-bundle-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn overrides(host: &Path) -> ConfigOverrides {
+    ConfigOverrides {
+        config_file: Some(host.join("config.json")),
+        workspace_dir: Some(host.join("workspaces")),
+        ..Default::default()
+    }
+}
+
+fn run_import(file: &Path, host: &Path, options: ImportOptions) -> (Result<bundle::ImportReport, String>, Vec<String>) {
+    let mut lines = Vec::new();
+    let result = bundle::import(file, overrides(host), options, &mut |line| lines.push(line));
+    (result.map_err(|e| e.to_string()), lines)
+}
+
+#[test]
+fn imports_seal_secrets_again_and_clone_workspaces() {
+    let env = TestEnv::new("bundle-source");
+    let url = env.fixture_repo().to_string_lossy().to_string();
+    env.config.add_workspace("docs".to_string(), url, "main".to_string()).unwrap();
+    env.config
+        .add_workspace("gone".to_string(), "/nonexistent/repo.git".to_string(), "main".to_string())
+        .unwrap();
+    let key = SecretKey::load_or_create(env.config.secret_key_path()).unwrap();
+    let secrets = BTreeMap::from([("DB_PASSWORD".to_string(), key.seal("DB_PASSWORD", "hunter2"))]);
+    let env_vars = BTreeMap::from([("STAGE".to_string(), "prod".to_string())]);
+    env.config.set_workspace_env("docs", env_vars, secrets).unwrap();
+
+    let exported = bundle::export(env.config.snapshot(), true, Some(&key)).unwrap();
+    let file = env.dir.join("bundle.json");
+    fs::write(&file, serde_json::to_string(&exported).unwrap()).unwrap();
+
+    let host = new_host("import");
+    let options = ImportOptions { force: false, clone: true };
+    let (report, lines) = run_import(&file, &host, options);
+    let report = report.unwrap();
+    assert_eq!(report.workspaces, 2);
+    assert_eq!(report.cloned, ["docs"]);
+    assert_eq!(report.failed, ["gone"]);
+    assert!(lines.contains(&"[1/2] docs: done".to_string()), "{:?}", lines);
+    assert!(lines.iter().any(|line| line.starts_with("[2/2] gone: failed: ")), "{:?}", lines);
+
+    let imported = ConfigManager::new(overrides(&host)).unwrap();
+    assert!(imported.take_generated_token().is_none());
+    assert!(imported.verify_token(TOKEN).is_some());
+    assert!(imported.repo_path("docs").join("HEAD").exists());
+    assert!(!imported.workspace_path("gone").exists());
+    let docs = imported.get_workspace("docs").unwrap();
+    assert_eq!(docs.env["STAGE"], "prod");
+    let sealed = &docs.secret_env["DB_PASSWORD"];
+    assert_ne!(sealed, &exported.config.workspaces["docs"].secret_env["DB_PASSWORD"]);
+    let new_key = SecretKey::load(imported.secret_key_path()).unwrap();
+    assert_eq!(new_key.open("DB_PASSWORD", sealed).unwrap(), "hunter2");
+    assert!(!fs::read_to_string(host.join("config.json")).unwrap().contains("hunter2"));
+
+    // Running again clones only what is missing
+    let options = ImportOptions { force: true, clone: true };
+    let (report, lines) = run_import(&file, &host, options);
+    let report = report.unwrap();
+    assert_eq!(report.skipped, ["docs"]);
+    assert!(lines.contains(&"[1/2] docs: already cloned, skipped".to_string()), "{:?}", lines);
+    assert!(host.join("config.json.bak").exists());
+    let _ = fs::remove_dir_all(&host);
+}
+
+#[test]
+fn newer_or_foreign_bundles_and_existing_configs_are_refused() {
+    let host = new_host("refused");
+    let file = host.join("bundle.json");
+    let bundle = |bundle_version: u32, config_version: u32| {
+        json!({
+            "format": "runotepad-bundle",
+            "bundle_version": bundle_version,
+            "includes_secrets": false,
+            "redacted": [],
+            "config": { "version": config_version, "workspaces": {} }
+        })
+    };
+
+    fs::write(&file, bundle(2, 2).to_string()).unwrap();
+    let (result, _) = run_import(&file, &host, ImportOptions::default());
+    let error = result.unwrap_err();
+    assert!(error.contains("version 2 bundle, written by a newer runotepad"), "{}", error);
+    assert!(!host.join("config.json").exists());
+
+    fs::write(&file, bundle(1, 99).to_string()).unwrap();
+    let (result, _) = run_import(&file, &host, ImportOptions::default());
+    assert!(result.is_err());
+
+    fs::write(&file, json!({ "version": 2, "tokens": [] }).to_string()).unwrap();
+    let (result, _) = run_import(&file, &host, ImportOptions::default());
+    assert!(result.unwrap_err().contains("is not a runotepad bundle"));
+
+    let mut invalid = bundle(1, 2);
+    invalid["config"]["workspaces"] = json!({
+        "../etc": { "repo_url": "https://example.com/x.git", "base_branch": "main", "created_at": "2024-03-01T00:00:00Z" }
+    });
+    fs::write(&file, invalid.to_string()).unwrap();
+    let (result, _) = run_import(&file, &host, ImportOptions::default());
+    assert!(result.unwrap_err().contains("Workspace '../etc'"));
+
+    fs::write(host.join("config.json"), "{}").unwrap();
+    fs::write(&file, bundle(1, 2).to_string()).unwrap();
+    let (result, _) = run_import(&file, &host, ImportOptions::default());
+    assert!(result.unwrap_err().contains("pass --force to replace it"));
+    assert_eq!(fs::read_to_string(host.join("config.json")).unwrap(), "{}");
+    let _ = fs::remove_dir_all(&host);
+}
+
+#[test]
+fn import_reports_what_the_bundle_left_out() {
+    let host = new_host("cli");
+    let file = host.join("bundle.json");
+    let config_file = host.join("new/config.json");
+    let mut bundle = json!({
+        "format": "runotepad-bundle",
+        "bundle_version": 1,
+        "includes_secrets": false,
+        "redacted": ["tokens.default", "workspaces.docs.secret_env.DB_PASSWORD"],
+        "config": {
+            "version": 2,
+            "import_root": "/nonexistent/imports",
+            "workspaces": {
+                "docs": { "repo_url": "https://example.com/docs.git", "base_branch": "main", "created_at": "2024-03-01T00:00:00Z" }
+            }
+        }
+    });
+    let import = |bundle: &serde_json::Value, force: bool| {
+        fs::write(&file, bundle.to_string()).unwrap();
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_runotepad"));
+        cmd.arg("--config-file").arg(&config_file).arg("import").arg(&file);
+        if force {
+            cmd.arg("--force");
+        }
+        for (key, _) in std::env::vars() {
+            if key.starts_with("RUNOTEPAD_") {
+                cmd.env_remove(key);
+            }
+        }
+        cmd.output().unwrap()
+    };
+
+    let output = import(&bundle, false);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert!(output.status.success(), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("generated access token (shown only once): "), "{}", stdout);
+    assert!(stdout.contains("Not in the bundle, set it again: workspaces.docs.secret_env.DB_PASSWORD"), "{}", stdout);
+    assert!(stdout.contains("import_root /nonexistent/imports does not exist on this host"), "{}", stdout);
+    assert!(stdout.contains("Imported 1 workspace(s)"), "{}", stdout);
+
+    // A config that doesn't load leaves the one that was there
+    let before = fs::read_to_string(&config_file).unwrap();
+    bundle["config"]["tls_cert"] = json!("/nonexistent/cert.pem");
+    let output = import(&bundle, true);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("The bundle's config doesn't load"));
+    assert_eq!(fs::read_to_string(&config_file).unwrap(), before);
+
+    bundle["bundle_version"] = json!(7);
+    let output = import(&bundle, true);
+    let _ = fs::remove_dir_all(&host);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("written by a newer runotepad"));
+}
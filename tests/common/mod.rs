@@ -0,0 +1,284 @@
+//! Shared setup for the HTTP integration tests: a config, workspace
+//! directory and audit log in a throwaway directory, and the real
+//! application from `app_factory` to send requests to.
+
+#![allow(dead_code)]
+
+use actix_web::body::MessageBody;
+use actix_http::Request;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::test;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use runotepad::audit::AuditLog;
+use runotepad::config::{ConfigManager, ConfigOverrides, CONFIG_VERSION};
+use runotepad::{app_factory, AppState};
+
+pub const TOKEN: &str = "integration-test-token";
+
+pub struct TestEnv {
+    pub dir: PathBuf,
+    pub config: Arc<ConfigManager>,
+    pub state: Arc<AppState>,
+}
+
+impl TestEnv {
+    /// Fresh environment in its own directory; `name` keeps tests running in
+    /// parallel apart
+    pub fn new(name: &str) -> Self {
+        Self::with_config(name, json!({}))
+    }
+
+    /// Like `new`, starting from a config file with `settings` in it
+    pub fn with_config(name: &str, settings: Value) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "runotepad-api-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Commits made through the API need an identity whatever the
+        // machine's git config says
+        for (key, value) in [
+            ("GIT_AUTHOR_NAME", "runotepad tests"),
+            ("GIT_AUTHOR_EMAIL", "tests@example.com"),
+            ("GIT_COMMITTER_NAME", "runotepad tests"),
+            ("GIT_COMMITTER_EMAIL", "tests@example.com"),
+        ] {
+            std::env::set_var(key, value);
+        }
+
+        let mut config_file = json!({ "version": CONFIG_VERSION });
+        config_file.as_object_mut().unwrap().extend(settings.as_object().unwrap().clone());
+        fs::write(dir.join("config.json"), config_file.to_string()).unwrap();
+
+        let config = ConfigManager::new(ConfigOverrides {
+            config_file: Some(dir.join("config.json")),
+            workspace_dir: Some(dir.join("workspaces")),
+            token: Some(TOKEN.to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let audit_log = AuditLog::open(config.audit_log_path().clone()).unwrap();
+        let state = Arc::new(AppState::new(&config, Arc::new(audit_log)));
+
+        Self {
+            dir,
+            config: Arc::new(config),
+            state,
+        }
+    }
+
+    pub async fn app(
+        &self,
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>
+    {
+        test::init_service(app_factory(self.config.clone(), self.state.clone())).await
+    }
+
+    /// Bare repository with a `main` branch holding `README.md` and
+    /// `docs/alerts.md`, to create workspaces from (made on first use)
+    pub fn fixture_repo(&self) -> PathBuf {
+        let src = self.dir.join("fixture-src");
+        let bare = self.dir.join("fixture.git");
+        if bare.exists() {
+            return bare;
+        }
+        fs::create_dir_all(src.join("docs")).unwrap();
+        fs::write(src.join("README.md"), "# Fixture\n").unwrap();
+        fs::write(src.join("docs/alerts.md"), "# Alerts\n\nHighCPU fires above 90%\n").unwrap();
+        git(&["init", "-q", "-b", "main"], &src);
+        git(&["add", "-A"], &src);
+        git(&["commit", "-q", "-m", "Initial runbooks"], &src);
+        git(&["clone", "-q", "--bare", src.to_str().unwrap(), bare.to_str().unwrap()], &self.dir);
+        bare
+    }
+
+    pub fn worktree(&self, workspace: &str, branch: &str) -> PathBuf {
+        self.config
+            .get_workspace_dir()
+            .join(workspace)
+            .join("worktrees")
+            .join(branch)
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Run git in `cwd` and return its trimmed stdout, panicking on failure
+pub fn git(args: &[&str], cwd: &Path) -> String {
+    let output = Command::new("git").args(args).current_dir(cwd).output().unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Authenticated request with an optional JSON body
+pub fn request(method: &str, uri: &str, body: Option<Value>) -> test::TestRequest {
+    let req = test::TestRequest::default()
+        .method(method.parse().unwrap())
+        .uri(uri)
+        .insert_header(("Authorization", format!("Bearer {}", TOKEN)));
+    match body {
+        Some(body) => req.set_json(body),
+        None => req,
+    }
+}
+
+/// Send `req` and return the status and the JSON body (null if empty)
+pub async fn send<S, B>(app: &S, req: test::TestRequest) -> (u16, Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let resp = test::call_service(app, req.to_request()).await;
+    let status = resp.status().as_u16();
+    let bytes = test::read_body(resp).await;
+    let body = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into()))
+    };
+    (status, body)
+}
+
+/// Read the `data:` events of an event stream body (skipping heartbeat
+/// comments) up to and including the first one `last` accepts
+pub async fn read_events<B: MessageBody>(body: B, last: impl Fn(&Value) -> bool) -> Vec<Value> {
+    let mut body = Box::pin(body);
+    let mut buffer = String::new();
+    let mut events = Vec::new();
+    let read = async {
+        loop {
+            while let Some(end) = buffer.find("\n\n") {
+                let frame: String = buffer.drain(..end + 2).collect();
+                if let Some(data) = frame.trim_end().strip_prefix("data: ") {
+                    let event: Value = serde_json::from_str(data).unwrap();
+                    let done = last(&event);
+                    events.push(event);
+                    if done {
+                        return;
+                    }
+                }
+            }
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await;
+            let Some(Ok(chunk)) = chunk else {
+                panic!("event stream ended after {:?}", events);
+            };
+            buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    };
+    if actix_web::rt::time::timeout(Duration::from_secs(10), read).await.is_err() {
+        panic!("no matching event within 10s, got {:?}", events);
+    }
+    events
+}
+
+/// Poll a background job until it finishes and return its final record
+pub async fn wait_for_job<S, B>(app: &S, id: &str) -> Value
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    for _ in 0..200 {
+        let (status, job) = send(app, request("GET", &format!("/api/jobs/{}", id), None)).await;
+        assert_eq!(status, 200, "{}", job);
+        if !matches!(job["state"].as_str(), Some("queued" | "running")) {
+            return job;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("job {} did not finish", id);
+}
+
+/// A request received by `webhook_receiver`: lowercased header names and
+/// values, and the body
+pub struct ReceivedHook {
+    pub headers: std::collections::HashMap<String, String>,
+    pub body: String,
+}
+
+/// Minimal HTTP server answering every request with 200; returns its URL and
+/// the requests it receives
+pub fn webhook_receiver() -> (String, std::sync::mpsc::Receiver<ReceivedHook>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { return };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = std::collections::HashMap::new();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                let Some((name, value)) = line.trim_end().split_once(':') else { break };
+                headers.insert(name.to_lowercase(), value.trim().to_string());
+            }
+            let length = headers.get("content-length").map_or(0, |l| l.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            let body = String::from_utf8(body).unwrap();
+            if sender.send(ReceivedHook { headers, body }).is_err() {
+                return;
+            }
+        }
+    });
+    (url, receiver)
+}
+
+/// Minimal HTTP server answering every request with 401 and a Basic auth
+/// challenge, the way a private git host does; returns its base URL and
+/// the `Authorization` header of each request (None without one)
+pub fn auth_required_server() -> (String, std::sync::mpsc::Receiver<Option<String>>) {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { return };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut authorization = None;
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                let Some((name, value)) = line.trim_end().split_once(':') else { break };
+                if name.eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                }
+            }
+            let _ = stream.write_all(
+                b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"git\"\r\n\
+                  Content-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+            if sender.send(authorization).is_err() {
+                return;
+            }
+        }
+    });
+    (url, receiver)
+}
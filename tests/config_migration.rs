@@ -0,0 +1,118 @@
+//! Loads config files from each historical schema version through the real
+//! binary (`--rotate-token` loads, migrates, saves and exits) and checks the
+//! result.
+
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const CURRENT_VERSION: u64 = 3;
+
+/// Copy a fixture into a fresh directory and return the config path
+fn stage_fixture(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "runotepad-config-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/config")
+        .join(format!("{}.json", name));
+    let config_path = dir.join("config.json");
+    fs::copy(fixture, &config_path).unwrap();
+    config_path
+}
+
+fn load(config_path: &Path) -> Output {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_runotepad"));
+    cmd.arg("--config-file")
+        .arg(config_path)
+        .arg("--workspace-dir")
+        .arg(config_path.with_file_name("workspaces"))
+        .args(["--rotate-token", "--log-level", "error"]);
+    for (key, _) in std::env::vars() {
+        if key.starts_with("RUNOTEPAD_") {
+            cmd.env_remove(key);
+        }
+    }
+    cmd.output().unwrap()
+}
+
+fn load_and_read(name: &str) -> Value {
+    let config_path = stage_fixture(name);
+    let output = load(&config_path);
+    assert!(
+        output.status.success(),
+        "loading {} failed: {}",
+        name,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let saved = fs::read_to_string(&config_path).unwrap();
+    let _ = fs::remove_dir_all(config_path.parent().unwrap());
+    serde_json::from_str(&saved).unwrap()
+}
+
+fn token<'a>(config: &'a Value, name: &str) -> &'a Value {
+    config["tokens"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["name"] == name)
+        .unwrap_or_else(|| panic!("token {} missing", name))
+}
+
+#[test]
+fn v0_single_token_becomes_named_token() {
+    let config = load_and_read("v0");
+
+    assert_eq!(config["version"], CURRENT_VERSION);
+    assert!(config.get("token").is_none());
+    let default = token(&config, "default");
+    assert!(default["hash"].as_str().unwrap().starts_with("sha256$"));
+    assert_eq!(config["workspaces"]["docs"]["base_branch"], "main");
+}
+
+#[test]
+fn v1_plaintext_secrets_are_hashed() {
+    let config = load_and_read("v1");
+
+    assert_eq!(config["version"], CURRENT_VERSION);
+    let ci = token(&config, "ci");
+    assert!(ci.get("token").is_none());
+    assert!(ci["hash"].as_str().unwrap().starts_with("sha256$"));
+    assert_eq!(ci["scope"], "read");
+    // Chosen rather than the old default, so it still applies
+    assert_eq!(config["job_timeout_secs"], 600);
+}
+
+#[test]
+fn v2_round_trip_keeps_unknown_fields() {
+    let config = load_and_read("v2");
+
+    assert_eq!(config["version"], CURRENT_VERSION);
+    assert_eq!(config["future_option"]["enabled"], true);
+    assert_eq!(config["workspaces"]["docs"]["labels"][0], "team-a");
+    let ci = token(&config, "ci");
+    assert_eq!(ci["hash"], "sha256$0123$4567");
+    assert_eq!(ci["expires_at"], "2030-01-01T00:00:00Z");
+    // The old default gives way to the per-operation ones
+    assert!(config.get("job_timeout_secs").is_none());
+}
+
+#[test]
+fn newer_version_is_refused() {
+    let config_path = stage_fixture("future");
+    let before = fs::read_to_string(&config_path).unwrap();
+
+    let output = load(&config_path);
+    let after = fs::read_to_string(&config_path).unwrap();
+    let _ = fs::remove_dir_all(config_path.parent().unwrap());
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("version 99"));
+    assert_eq!(before, after);
+}
@@ -0,0 +1,109 @@
+//! Saves of the config file from many threads at once keep every change,
+//! and a config file that can't be read is replaced by its backup.
+
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use runotepad::config::{backup_path, ConfigManager, ConfigOverrides};
+
+/// Fresh directory for a config file and its workspaces
+fn config_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "runotepad-config-saves-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn load(dir: &Path) -> ConfigManager {
+    ConfigManager::new(ConfigOverrides {
+        config_file: Some(dir.join("config.json")),
+        workspace_dir: Some(dir.join("workspaces")),
+        token: Some("config-saves-test-token".to_string()),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+/// Workspace names in the config file as saved on disk
+fn saved_workspaces(path: &Path) -> BTreeSet<String> {
+    let config: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    config["workspaces"].as_object().unwrap().keys().cloned().collect()
+}
+
+#[test]
+fn concurrent_adds_and_removes_are_all_saved() {
+    let dir = config_dir("concurrent");
+    let config = Arc::new(load(&dir));
+
+    // Each thread adds its workspaces and removes every other one again
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let config = config.clone();
+            thread::spawn(move || {
+                for i in 0..10 {
+                    let name = format!("ws-{}-{}", t, i);
+                    config
+                        .add_workspace(name.clone(), "https://example.com/repo.git".into(), "main".into())
+                        .unwrap();
+                    if i % 2 == 1 {
+                        assert!(config.remove_workspace(&name).unwrap());
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let survivors: BTreeSet<String> = (0..8)
+        .flat_map(|t| (0..10).step_by(2).map(move |i| format!("ws-{}-{}", t, i)))
+        .collect();
+    assert_eq!(saved_workspaces(&dir.join("config.json")), survivors);
+    let reloaded: BTreeSet<String> = load(&dir).get_workspaces().into_keys().collect();
+    assert_eq!(reloaded, survivors);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unreadable_config_falls_back_to_the_backup() {
+    let dir = config_dir("backup");
+    let config_path = dir.join("config.json");
+    let config = load(&dir);
+    config
+        .add_workspace("docs".into(), "https://example.com/docs.git".into(), "main".into())
+        .unwrap();
+    config
+        .add_workspace("ops".into(), "https://example.com/ops.git".into(), "main".into())
+        .unwrap();
+    drop(config);
+
+    // A save cut short, with the backup holding the one before it
+    fs::write(&config_path, "{\"version\": 3, \"workspa").unwrap();
+    let config = load(&dir);
+    let names: Vec<String> = config.get_workspaces().into_keys().collect();
+    assert_eq!(names, ["docs"]);
+    // The backup is written back as the config file
+    assert_eq!(saved_workspaces(&config_path), BTreeSet::from(["docs".to_string()]));
+
+    // Without a readable backup either, loading fails rather than starting
+    // over with an empty config
+    drop(config);
+    fs::write(&config_path, "not json").unwrap();
+    fs::write(backup_path(&config_path), "not json either").unwrap();
+    assert!(ConfigManager::new(ConfigOverrides {
+        config_file: Some(config_path.clone()),
+        workspace_dir: Some(dir.join("workspaces")),
+        ..Default::default()
+    })
+    .is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
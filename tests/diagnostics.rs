@@ -0,0 +1,115 @@
+//! Runs the environment checks against throwaway setups (a leftover
+//! directory, a workspace gone from disk, missing TLS files, a port in use)
+//! and `runotepad doctor` through the real binary.
+
+mod common;
+
+use common::TestEnv;
+use runotepad::diagnostics::{self, Check, Level};
+use serde_json::json;
+use std::fs;
+use std::process::Command;
+
+fn find<'a>(checks: &'a [Check], name: &str) -> &'a Check {
+    checks
+        .iter()
+        .find(|check| check.name == name)
+        .unwrap_or_else(|| panic!("no {} check in {:?}", name, checks))
+}
+
+#[test]
+fn a_fresh_setup_passes() {
+    let env = TestEnv::new("doctor-fresh");
+    let checks = diagnostics::server_checks(&env.config);
+
+    assert_eq!(find(&checks, "git").level, Level::Pass, "{:?}", checks);
+    assert_eq!(find(&checks, "token").level, Level::Pass);
+    assert_eq!(find(&checks, "workspace_dir").level, Level::Pass);
+    assert!(checks.iter().all(|check| check.name != "tls"));
+    assert_ne!(diagnostics::verdict(&checks), Level::Fail);
+}
+
+#[test]
+fn workspaces_missing_from_disk_fail_and_leftovers_warn() {
+    let env = TestEnv::with_config(
+        "doctor-workspaces",
+        json!({
+            "workspaces": {
+                "docs": {
+                    "repo_url": "https://example.com/docs.git",
+                    "base_branch": "main",
+                    "created_at": "2024-03-01T00:00:00Z"
+                }
+            }
+        }),
+    );
+    fs::create_dir_all(env.config.get_workspace_dir().join("old-import")).unwrap();
+
+    let checks = diagnostics::server_checks(&env.config);
+    let docs = find(&checks, "workspace docs");
+    assert_eq!(docs.level, Level::Fail);
+    assert_eq!(docs.message, "workspace directory is missing on disk");
+    let dir = find(&checks, "workspace_dir");
+    assert_eq!(dir.level, Level::Warn);
+    assert!(dir.message.contains("missing from it: docs"), "{}", dir.message);
+    assert!(dir.message.contains("configured for: old-import"), "{}", dir.message);
+    assert_eq!(diagnostics::verdict(&checks), Level::Fail);
+}
+
+#[test]
+fn unreadable_tls_files_fail() {
+    let env = TestEnv::with_config(
+        "doctor-tls",
+        json!({ "tls_cert": "/nonexistent/cert.pem", "tls_key": "/nonexistent/key.pem" }),
+    );
+    let check = diagnostics::check_tls(&env.config).unwrap();
+    assert_eq!(check.level, Level::Fail);
+    assert!(check.message.contains("/nonexistent/cert.pem"), "{}", check.message);
+}
+
+#[test]
+fn a_port_in_use_fails() {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let env = TestEnv::with_config("doctor-port", json!({ "bind": "127.0.0.1", "port": port }));
+
+    let checks = diagnostics::check_listeners(&env.config);
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0].level, Level::Fail);
+    assert!(checks[0].message.contains(&format!("127.0.0.1:{}", port)), "{}", checks[0].message);
+}
+
+#[test]
+fn doctor_prints_a_table_and_exits_nonzero_on_failures() {
+    let dir = std::env::temp_dir().join(format!("runotepad-doctor-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let doctor = || {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_runotepad"));
+        cmd.arg("--config-file")
+            .arg(dir.join("config.json"))
+            .args(["--token", "doctor-token", "--port", "0", "doctor"]);
+        for (key, _) in std::env::vars() {
+            if key.starts_with("RUNOTEPAD_") {
+                cmd.env_remove(key);
+            }
+        }
+        cmd.output().unwrap()
+    };
+
+    let output = doctor();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("PASS  config"), "{}", stdout);
+    assert!(stdout.contains("0 failed"), "{}", stdout);
+
+    // A config that can't be read, with no backup to fall back to
+    fs::write(dir.join("config.json"), "{ not json").unwrap();
+    let _ = fs::remove_file(dir.join("config.json.bak"));
+    let output = doctor();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("FAIL  config"), "{}", stdout);
+    assert!(stdout.contains("PASS  git"), "{}", stdout);
+}
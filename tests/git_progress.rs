@@ -0,0 +1,39 @@
+//! Takes apart the progress lines git writes during fetches and pushes,
+//! and keeps the lines it can't make sense of as they are.
+
+use runotepad::git_ops::parse_progress;
+
+#[test]
+fn counters_give_their_phase_percentage_and_throughput() {
+    let receiving = parse_progress("Receiving objects:  45% (9/20), 1.20 MiB | 1.21 MiB/s");
+    assert_eq!(receiving.phase.as_deref(), Some("Receiving objects"));
+    assert_eq!(receiving.percent, Some(45));
+    assert_eq!((receiving.current, receiving.total), (Some(9), Some(20)));
+    assert_eq!(receiving.throughput.as_deref(), Some("1.21 MiB/s"));
+
+    let writing = parse_progress("Writing objects: 100% (3/3), 250 bytes | 250.00 KiB/s, done.");
+    assert_eq!(writing.phase.as_deref(), Some("Writing objects"));
+    assert_eq!(writing.percent, Some(100));
+    assert_eq!(writing.throughput.as_deref(), Some("250.00 KiB/s"));
+    assert_eq!(writing.line, "Writing objects: 100% (3/3), 250 bytes | 250.00 KiB/s, done.");
+
+    let remote = parse_progress("remote: Compressing objects:  50% (1/2)");
+    assert_eq!(remote.phase.as_deref(), Some("Compressing objects"));
+    assert_eq!(remote.percent, Some(50));
+    assert_eq!(remote.throughput, None);
+}
+
+#[test]
+fn other_languages_and_wordings_still_parse_or_come_through_raw() {
+    let french = parse_progress("Réception d'objets:  60% (12/20), 3,50 Mio | 1,75 Mio/s");
+    assert_eq!(french.phase.as_deref(), Some("Réception d'objets"));
+    assert_eq!(french.percent, Some(60));
+    assert_eq!((french.current, french.total), (Some(12), Some(20)));
+
+    for line in ["Enumerating objects: 5, done.", "Objects are on their way"] {
+        let raw = parse_progress(line);
+        assert_eq!(raw.line, line);
+        assert_eq!((raw.phase, raw.percent, raw.current, raw.total), (None, None, None, None));
+        assert_eq!(raw.throughput, None);
+    }
+}
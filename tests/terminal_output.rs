@@ -0,0 +1,100 @@
+//! Drives the WebSocket sender's queue with a fast producer and checks that
+//! terminal output comes out merged, in order, and that lone chunks aren't
+//! held back.
+
+use futures::FutureExt;
+use runotepad::terminal_output::{Coalescer, Outgoing, MAX_MESSAGE_BYTES};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+fn output(session_id: &str, data: &str) -> Outgoing {
+    Outgoing::Output {
+        session_id: session_id.to_string(),
+        data: data.to_string(),
+    }
+}
+
+#[actix_web::test]
+async fn bursts_of_output_are_merged_in_order() {
+    const CHUNKS: usize = 20_000;
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    // Two sessions writing at full speed, with other messages in between
+    let producer = std::thread::spawn(move || {
+        for i in 0..CHUNKS {
+            let session = if i % 1000 < 700 { "a" } else { "b" };
+            tx.send(output(session, &format!("{}:{};", session, i))).unwrap();
+            if i % 5000 == 4999 {
+                tx.send(Outgoing::Message(format!("event {}", i))).unwrap();
+            }
+        }
+    });
+
+    let mut queue = Coalescer::new(rx);
+    let mut frames = Vec::new();
+    while let Some(frame) = queue.next().await {
+        frames.push(frame);
+        // Sending a frame over the socket takes a moment
+        tokio::time::sleep(Duration::from_micros(200)).await;
+    }
+    producer.join().unwrap();
+
+    // Everything arrives exactly once and in the order it was written
+    let mut expected = String::new();
+    let mut received = String::new();
+    for i in 0..CHUNKS {
+        let session = if i % 1000 < 700 { "a" } else { "b" };
+        expected.push_str(&format!("{}:{};", session, i));
+        if i % 5000 == 4999 {
+            expected.push_str(&format!("[event {}]", i));
+        }
+    }
+    for frame in &frames {
+        match frame {
+            Outgoing::Output { session_id, data } => {
+                assert!(data.len() <= MAX_MESSAGE_BYTES);
+                for chunk in data.split_terminator(';') {
+                    assert!(chunk.starts_with(&format!("{}:", session_id)), "{} in {}", chunk, session_id);
+                }
+                received.push_str(data);
+            }
+            Outgoing::Message(msg) => received.push_str(&format!("[{}]", msg)),
+        }
+    }
+    assert_eq!(received, expected);
+
+    // ...in a small fraction of the frames
+    assert!(frames.len() < CHUNKS / 20, "{} frames for {} chunks", frames.len(), CHUNKS);
+}
+
+#[actix_web::test]
+async fn merged_output_is_capped() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let chunk = "x".repeat(4096);
+    for _ in 0..40 {
+        tx.send(output("a", &chunk)).unwrap();
+    }
+    drop(tx);
+
+    let mut queue = Coalescer::new(rx);
+    let mut sizes = Vec::new();
+    while let Some(Outgoing::Output { data, .. }) = queue.next().await {
+        sizes.push(data.len());
+    }
+    assert_eq!(sizes, vec![MAX_MESSAGE_BYTES, MAX_MESSAGE_BYTES, 8 * 4096]);
+}
+
+#[actix_web::test]
+async fn a_lone_chunk_is_sent_at_once() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut queue = Coalescer::new(rx);
+
+    // A keystroke echo with nothing else queued is ready without waiting
+    tx.send(output("a", "l")).unwrap();
+    let frame = queue.next().now_or_never().expect("echo was held back");
+    assert_eq!(frame, Some(output("a", "l")));
+
+    tx.send(Outgoing::Message("closed".into())).unwrap();
+    let frame = queue.next().now_or_never().expect("message was held back");
+    assert_eq!(frame, Some(Outgoing::Message("closed".into())));
+}
@@ -0,0 +1,136 @@
+//! Turns recorded terminal output into the transcript section attached to a
+//! runbook, and splices it onto runbooks whose end could swallow it.
+
+use chrono::{TimeZone, Utc};
+use runotepad::blocks;
+use runotepad::sessions::{
+    self, BufferSearchQuery, Scrollback, SessionInfo, Transcript, TRANSCRIPT_HEAD_BYTES, TRANSCRIPT_TAIL_BYTES,
+};
+
+fn info() -> SessionInfo {
+    SessionInfo {
+        session_id: "s1".to_string(),
+        label: Some("prod-db shell".to_string()),
+        runbook_path: Some("ops/db.md".to_string()),
+        workspace: Some("ops".to_string()),
+        branch: Some("main".to_string()),
+        opened_at: Utc.with_ymd_and_hms(2026, 10, 15, 9, 0, 0).unwrap(),
+        opened_by: None,
+        cwd: None,
+    }
+}
+
+#[test]
+fn terminal_output_becomes_plain_text() {
+    let output = "\u{1b}]0;alice@db: ~\u{7}\u{1b}[01;32m$\u{1b}[00m lss\u{8} -l\r\n\
+        total 0\r\n\
+        copying 10%\rcopying 100%\r\n\
+        \u{1b}(Bdone\u{7}\r\n";
+    assert_eq!(
+        sessions::terminal_text(output),
+        "$ ls -l\ntotal 0\ncopying 100%\ndone\n"
+    );
+}
+
+#[test]
+fn long_sessions_keep_their_start_and_end() {
+    let mut transcript = Transcript::default();
+    transcript.push(b"first line\n");
+    for _ in 0..10_000 {
+        transcript.push(b"..........\n");
+    }
+    transcript.push(b"last line\n");
+    let total = 11 + 10_000 * 11 + 10;
+    assert_eq!(transcript.omitted(), (total - TRANSCRIPT_HEAD_BYTES - TRANSCRIPT_TAIL_BYTES) as u64);
+
+    let text = transcript.text();
+    assert!(text.starts_with("first line\n"));
+    assert!(text.ends_with("last line\n"));
+    assert!(text.contains(&format!("[... {} bytes left out ...]\n", transcript.omitted())));
+}
+
+#[test]
+fn transcript_sections_carry_the_session_and_fence_the_output() {
+    let mut transcript = Transcript::default();
+    transcript.push(b"$ cat notes.md\r\n```sh\r\nexit 1\r\n```\r\n");
+    let ended_at = Utc.with_ymd_and_hms(2026, 10, 15, 9, 30, 0).unwrap();
+
+    let section = sessions::transcript_section(&info(), &transcript, ended_at, Some(0));
+    assert_eq!(
+        section,
+        "## Terminal transcript: prod-db shell\n\n\
+         - Session: s1\n\
+         - Started: 2026-10-15T09:00:00Z\n\
+         - Ended: 2026-10-15T09:30:00Z\n\
+         - Exit code: 0\n\n\
+         ````text\n$ cat notes.md\n```sh\nexit 1\n```\n````\n"
+    );
+
+    let running = sessions::transcript_section(&info(), &transcript, ended_at, None);
+    assert!(running.contains("- Exit code: none, the shell was still running\n"));
+}
+
+#[test]
+fn sections_are_appended_after_a_blank_line() {
+    let section = "## Transcript\n\n```text\nok\n```\n";
+    assert_eq!(
+        blocks::append_section("# Runbook\n\nSteps", section).unwrap(),
+        "# Runbook\n\nSteps\n\n## Transcript\n\n```text\nok\n```\n"
+    );
+    assert_eq!(blocks::append_section("", section).unwrap(), section);
+
+    let appended = blocks::append_section("# Runbook\n\n```bash\nls\n```\n", section).unwrap();
+    let runbook = blocks::parse(&appended);
+    assert_eq!(runbook.blocks.len(), 2);
+    assert_eq!(runbook.headings.last().unwrap().title, "Transcript");
+}
+
+#[test]
+fn sections_are_not_appended_where_they_would_be_swallowed() {
+    let section = "## Transcript\n\n```text\nok\n```\n";
+    assert!(blocks::append_section("# Runbook\n\n```bash\nls\n", section).is_none());
+    assert!(blocks::append_section("# Runbook\n\n<!-- draft\n", section).is_none());
+}
+
+#[test]
+fn scrollback_keeps_the_latest_output_and_finds_matches_in_it() {
+    let mut scrollback = Scrollback::new(64);
+    scrollback.push(b"$ export TOKEN=abc\r\n");
+    for _ in 0..10 {
+        scrollback.push(b"..........\r\n");
+    }
+    scrollback.push(b"\x1b[31mERROR\x1b[0m: disk full\r\n");
+    let total = 20 + 10 * 12 + 27;
+    assert_eq!(scrollback.start_offset(), total - 64);
+
+    let (offset, tail) = scrollback.tail(Some(27));
+    assert_eq!((offset, tail.as_slice()), (total - 27, b"\x1b[31mERROR\x1b[0m: disk full\r\n".as_slice()));
+    assert_eq!(scrollback.tail(Some(1000)).0, total - 64);
+
+    let (offset, output) = scrollback.tail(None);
+    let query = |q: &str, case_sensitive, strip_ansi| BufferSearchQuery {
+        q: q.to_string(),
+        case_sensitive,
+        strip_ansi,
+        limit: None,
+    };
+    // Raw offsets count everything the session wrote
+    let (matches, truncated) = sessions::search_output(&output, offset, &query("error", false, false), 10).unwrap();
+    assert!(!truncated);
+    assert_eq!(matches.len(), 1);
+    assert_eq!((matches[0].start_byte, matches[0].end_byte), (total - 22, total - 17));
+    assert_eq!(matches[0].text, "ERROR");
+    assert_eq!(matches[0].after, "\u{1b}[0m: disk full\r\n");
+    assert!(sessions::search_output(&output, offset, &query("error", true, false), 10).unwrap().0.is_empty());
+
+    // Stripped ones count the stripped text
+    let (matches, _) = sessions::search_output(&output, offset, &query("ERROR: disk", true, true), 10).unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].after, " full\n");
+    assert!(matches[0].before.ends_with("..........\n"));
+
+    let (matches, truncated) = sessions::search_output(&output, offset, &query(".....", false, false), 3).unwrap();
+    assert_eq!((matches.len(), truncated), (3, true));
+    // The export scrolled out
+    assert!(sessions::search_output(&output, offset, &query("TOKEN", false, false), 10).unwrap().0.is_empty());
+}
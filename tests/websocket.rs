@@ -0,0 +1,107 @@
+//! WebSocket handshakes authenticated with a token offered as a
+//! `runotepad-token.<token>` subprotocol, the query string, or neither, and
+//! the server log of them, which must never show the token.
+
+mod common;
+
+use actix_web::test;
+use common::{request, send, TestEnv, TOKEN};
+use serde_json::json;
+use std::sync::Once;
+
+/// The capture of the server log, with nothing printed; installed once
+/// per test binary
+fn capture_logs() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        runotepad::logging::init(Some("off"), runotepad::logging::LogFormat::Text);
+        runotepad::server_log::capture().configure(log::LevelFilter::Debug, 1000);
+    });
+}
+
+/// A WebSocket upgrade of `uri`, offering `protocols` if given
+fn handshake(uri: &str, protocols: Option<&str>) -> test::TestRequest {
+    let mut req = test::TestRequest::get()
+        .uri(uri)
+        .insert_header(("Connection", "Upgrade"))
+        .insert_header(("Upgrade", "websocket"))
+        .insert_header(("Sec-WebSocket-Version", "13"))
+        .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="));
+    if let Some(protocols) = protocols {
+        req = req.insert_header(("Sec-WebSocket-Protocol", protocols));
+    }
+    req
+}
+
+#[actix_web::test]
+async fn tokens_offered_as_a_subprotocol_open_the_socket_without_being_logged() {
+    capture_logs();
+    let env = TestEnv::new("ws-protocol");
+    let app = env.app().await;
+
+    let offered = format!("runotepad, runotepad-token.{}", TOKEN);
+    let resp = test::call_service(&app, handshake("/ws", Some(&offered)).to_request()).await;
+    assert_eq!(resp.status(), 101);
+    assert_eq!(resp.headers().get("Sec-WebSocket-Protocol").unwrap(), "runotepad");
+
+    // The subprotocol is tried before the query string
+    let resp = test::call_service(&app, handshake("/ws?token=wrong", Some(&offered)).to_request()).await;
+    assert_eq!(resp.status(), 101);
+
+    // The query string still works, and nothing is echoed without an offer
+    let uri = format!("/ws?token={}", TOKEN);
+    let resp = test::call_service(&app, handshake(&uri, None).to_request()).await;
+    assert_eq!(resp.status(), 101);
+    assert!(resp.headers().get("Sec-WebSocket-Protocol").is_none());
+
+    let (status, body) = send(&app, request("GET", "/api/v1/admin/logs?limit=1000", None)).await;
+    assert_eq!(status, 200);
+    let records = body.as_array().unwrap();
+    let headers = records
+        .iter()
+        .find(|record| record["message"].as_str().unwrap().contains("runotepad-token.<redacted>"));
+    assert!(headers.is_some(), "{}", body);
+    for record in records {
+        let message = record["message"].as_str().unwrap();
+        assert!(!message.contains(TOKEN), "{}", message);
+    }
+}
+
+#[actix_web::test]
+async fn handshakes_without_a_valid_token_are_refused() {
+    capture_logs();
+    let env = TestEnv::new("ws-refused");
+    let app = env.app().await;
+
+    let (status, body) = send(&app, handshake("/ws", Some("runotepad, runotepad-token.wrong"))).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["error"]["code"], "invalid_token");
+
+    for protocols in [None, Some("runotepad")] {
+        let (status, body) = send(&app, handshake("/ws", protocols)).await;
+        assert_eq!(status, 401);
+        assert_eq!(body["error"]["code"], "auth_required");
+        assert!(body["error"]["details"]["hint"].as_str().unwrap().contains("runotepad-token."));
+    }
+}
+
+#[actix_web::test]
+async fn query_tokens_can_be_turned_off_for_websockets() {
+    capture_logs();
+    let env = TestEnv::with_config("ws-no-query", json!({ "ws_query_token": false }));
+    let app = env.app().await;
+
+    let uri = format!("/ws?token={}", TOKEN);
+    let (status, body) = send(&app, handshake(&uri, None)).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["error"]["code"], "auth_required");
+
+    let offered = format!("runotepad, runotepad-token.{}", TOKEN);
+    let resp = test::call_service(&app, handshake("/ws", Some(&offered)).to_request()).await;
+    assert_eq!(resp.status(), 101);
+
+    // Only for /ws
+    let uri = format!("/api/v1/workspaces?token={}", TOKEN);
+    let (status, _) = send(&app, test::TestRequest::get().uri(&uri)).await;
+    assert_eq!(status, 200);
+}